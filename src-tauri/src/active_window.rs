@@ -0,0 +1,72 @@
+use std::path::Path;
+
+/// 当前焦点所在窗口的进程名称和标题
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ActiveWindow {
+    // 小写、不带 .exe 或 .app 后缀的进程名称
+    pub process_name: String,
+    // 显示给用户的应用名称，例如 Visual Studio Code，取不到时为空
+    pub app_name: String,
+    // macOS 没有屏幕录制权限时取不到标题，为空
+    pub title: String,
+}
+
+/// 获取当前焦点所在的窗口，Linux 的 Wayland 会话等不支持查询的环境下返回 None
+pub fn active_window() -> Option<ActiveWindow> {
+    match active_win_pos_rs::get_active_window() {
+        Ok(window) => Some(ActiveWindow {
+            process_name: process_name(&window.process_path, &window.app_name),
+            app_name: window.app_name.trim().to_string(),
+            title: window.title,
+        }),
+        Err(_) => {
+            println!("get active window failed");
+            None
+        }
+    }
+}
+
+/// 去掉进程名称的平台相关部分，配置中的 KeePassXC.exe、keepassxc 都能匹配到同一个进程
+pub fn normalize_process_name(name: &str) -> String {
+    let name = name.trim().to_lowercase();
+    let name = name
+        .strip_suffix(".exe")
+        .or_else(|| name.strip_suffix(".app"))
+        .unwrap_or(&name);
+    name.to_string()
+}
+
+// Windows 和 Linux 使用可执行文件的名称，和任务管理器、ps 中看到的一致
+#[cfg(not(target_os = "macos"))]
+fn process_name(process_path: &Path, app_name: &str) -> String {
+    process_path
+        .file_name()
+        .map(|name| normalize_process_name(&name.to_string_lossy()))
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| normalize_process_name(app_name))
+}
+
+// macOS 的可执行文件在 .app 包里面，名称不一定和应用名称相同，使用程序坞中显示的应用名称
+#[cfg(target_os = "macos")]
+fn process_name(process_path: &Path, app_name: &str) -> String {
+    let name = normalize_process_name(app_name);
+    if !name.is_empty() {
+        return name;
+    }
+    process_path
+        .file_name()
+        .map(|name| normalize_process_name(&name.to_string_lossy()))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_process_name() {
+        assert_eq!(normalize_process_name("KeePassXC.exe"), "keepassxc");
+        assert_eq!(normalize_process_name(" 1Password.app "), "1password");
+        assert_eq!(normalize_process_name("fcitx5"), "fcitx5");
+    }
+}
@@ -1,23 +1,80 @@
-use crate::api::assistant_api::get_assistant;
-use crate::api::llm::get_provider;
-use crate::db::assistant_db::AssistantModelConfig;
-use crate::db::conversation_db::{AttachmentType, Repository};
-use crate::db::conversation_db::{Conversation, ConversationDatabase, Message, MessageAttachment};
-use crate::db::llm_db::LLMDatabase;
+use crate::api::assistant_api::{
+    apply_active_preset, apply_skill_model, enabled_examples, fit_examples, get_assistant,
+    skill_prompt, skill_tools,
+};
+use crate::api::conversation_api::ensure_not_busy;
+use crate::api::llm::{
+    append_response_length_instruction, check_egress, configured_tools, egress_blocked_host,
+    finish_reason_event, get_model_capability, get_provider, is_local_provider,
+    is_model_unavailable, is_truncated_finish_reason, model_chain, parse_reasoning_tags,
+    prewarm_connection, provider_endpoint, replay_fixture, served_model_event, split_reasoning,
+    stream_interrupted_event, supports_assistant_prefill, supports_warm_up, tool_result_message,
+    tool_use_message, usage_event, warm_up_model, ChatMessage, ChatResponse, ContentPart,
+    ModelParams, ModelProvider, ProviderErrorEvent, ReasoningSplit, RequestContext, ResponseLength,
+    Role, StreamBatcher, TokenUsage, ToolCall, ToolProgress, ToolResult, DEFAULT_REASONING_TAGS,
+    EVENT_PREFIX, FINISH_REASON_EVENT_PREFIX, RESPONSE_LENGTH_CONFIG, SERVED_MODEL_EVENT_PREFIX,
+    STREAM_INTERRUPTED_EVENT_PREFIX, TOOLS_CONFIG, TOOL_CALLS_EVENT_PREFIX,
+    TOOL_PROGRESS_EVENT_PREFIX, USAGE_EVENT_PREFIX,
+};
+use crate::api::llm_api::{
+    generation_recording_path, is_concise_mode, is_generation_debug, is_offline_mode,
+};
+use crate::api::quick_action_api::{clipboard_config, ClipboardMode, ClipboardWriter};
+use crate::api::tool_api::call_tool;
+use crate::audit_log;
+use crate::db::assistant_db::{AssistantDatabase, AssistantModel, AssistantModelConfig};
+use crate::db::conversation_db::{
+    draft_key, AttachmentTruncation, AttachmentType, GenerationMetadata, Repository,
+    DEFAULT_CONVERSATION_NAME, DRAFT_MESSAGE_TYPE, QUOTE_MESSAGE_TYPE,
+};
+use crate::db::conversation_db::{
+    Conversation, ConversationDatabase, InterruptedMessage, Message, MessageAttachment,
+    MessageQuote,
+};
+use crate::db::llm_db::{LLMDatabase, LLMProvider, LLMProviderConfig, ModelDetail};
 use crate::db::system_db::FeatureConfig;
 use crate::errors::AppError;
+use crate::group_chat::{assistant_names, mentioned_assistant, speaker_content};
+use crate::knowledge::attachment_budget::{
+    fit_attachments, TruncateStrategy, DEFAULT_MAX_ATTACHMENT_TEXT_LENGTH,
+};
+use crate::knowledge::citation::{number_chunks, parse_citations, Citation, CITATION_INSTRUCTION};
+use crate::knowledge::image_budget::{select_images, OmittedImage};
+use crate::knowledge::indexer::retrieve;
+use crate::knowledge::injection_guard::{escape_attribute, InjectionGuard};
+use crate::knowledge::ocr::{decode_image_content, format_ocr_context, get_ocr_engine, OcrEngine};
+use crate::quote::quoted_content;
+use crate::retention::RetentionPolicy;
+use crate::selection::{references_selected_text, SelectionContextTemplate};
 use crate::state::message_token::MessageTokenManager;
+use crate::state::model_preload::ModelPreloadState;
+use crate::state::private_conversation::PrivateConversationState;
+use crate::state::request_dedup::{DedupClaim, DedupKey, RequestDedupState};
+use crate::state::response_cache::{
+    response_cache_key, CachedResponse, ResponseCacheConfig, ResponseCacheState,
+};
+use crate::state::tokenizer::TokenizerState;
+use crate::state::window_focus::WindowFocusState;
 use crate::template_engine::TemplateEngine;
+use crate::tokenizer::Tokenizer;
+use crate::topic_split::{auto_split, TopicSplitConfig};
+use crate::voice::{Sentence, SentenceSplitter};
+use crate::webhook;
+use crate::{AppHandle, Window};
 use crate::{AppState, FeatureConfigState};
 use anyhow::Context;
 use anyhow::Error;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::collections::{HashMap, HashSet};
-use std::time::Duration;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tauri::Emitter;
 use tauri::Listener;
+use tauri::Manager;
 use tauri::State;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, Mutex};
 use tokio::time::timeout;
 use tokio_util::sync::CancellationToken;
 
@@ -34,282 +91,615 @@ pub struct AiRequest {
     max_tokens: Option<u32>,
     stream: Option<bool>,
     attachment_list: Option<Vec<i64>>,
+    // 划词弹窗等快捷操作使用快速模式，见 FastModeConfig
+    fast_mode: Option<bool>,
+    // 多模型对比时设为 true，开启了回答缓存时重复运行直接使用之前的回答，见 ResponseCacheConfig
+    use_response_cache: Option<bool>,
+    // 离线模式下用户确认后仍然请求云端提供商，只对这一次提问生效，见 apply_offline_mode
+    allow_online: Option<bool>,
+    // 单独开启或关闭这次提问的先起草再改进，为空时按 draft_refine 功能配置，见 DraftRefineConfig
+    draft_refine: Option<bool>,
+    // 询问窗口中触发的快捷操作，按快捷操作的配置把回答写入剪贴板，见 QuickAction
+    quick_action_id: Option<i64>,
 }
 
-#[derive(Serialize, Deserialize)]
+impl AiRequest {
+    // 在后端直接发起一次新对话的提问，例如对比实验
+    pub fn new_conversation(assistant_id: i64, prompt: String) -> Self {
+        AiRequest {
+            conversation_id: String::new(),
+            assistant_id,
+            prompt,
+            model: None,
+            temperature: None,
+            top_p: None,
+            max_tokens: None,
+            stream: None,
+            attachment_list: None,
+            fast_mode: None,
+            use_response_cache: None,
+            allow_online: None,
+            draft_refine: None,
+            quick_action_id: None,
+        }
+    }
+
+    pub fn with_response_cache(mut self) -> Self {
+        self.use_response_cache = Some(true);
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct AiResponse {
-    conversation_id: i64,
-    add_message_id: i64,
+    pub conversation_id: i64,
+    pub add_message_id: i64,
     request_prompt_result_with_context: String,
+    // 本次提问中因为超出长度预算被截断的文本附件
+    attachment_truncation: Vec<AttachmentTruncation>,
+    // 先起草再改进时草稿消息的 id，草稿通过 message_{id} 事件流式发送
+    draft_message_id: Option<i64>,
 }
 #[tauri::command]
 pub async fn ask_ai(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
     state: State<'_, AppState>,
     feature_config_state: State<'_, FeatureConfigState>,
     message_token_manager: State<'_, MessageTokenManager>,
-    window: tauri::Window,
-    request: AiRequest,
+    request_dedup: State<'_, RequestDedupState<AiResponse>>,
+    window: Window,
+    mut request: AiRequest,
     override_model_config: Option<Vec<(String, serde_json::Value)>>,
-    override_prompt: Option<String>,
+    mut override_prompt: Option<String>,
 ) -> Result<AiResponse, AppError> {
+    // 提问内容、系统提示词和消息列表都不输出到日志，私密对话和无痕对话的内容不能出现在日志中
     println!(
-        "ask_ai: {:?}, override_model_config: {:?}, override_prompt: {:?}",
-        request, override_model_config, override_prompt
+        "ask_ai: conversation_id={}, assistant_id={}, override_model_config: {:?}",
+        request.conversation_id, request.assistant_id, override_model_config
     );
-    let template_engine = TemplateEngine::new();
+    // 群聊中提问里 @ 了哪个助手就由哪个助手回答，回答记录在消息的 assistant_id 中；
+    // 无痕助手的回答不能写入普通对话，不参与群聊
+    if let Some(assistant_id) =
+        mentioned_assistant(&request.prompt, &assistant_names(&app_handle).await)
+    {
+        if assistant_id != request.assistant_id
+            && RetentionPolicy::of_assistant(&app_handle, assistant_id).incognito
+        {
+            return Err(AppError::Anyhow(
+                "无痕助手不能在群聊中被 @ 提问".to_string(),
+            ));
+        }
+        request.assistant_id = assistant_id;
+    }
+    let injection_guard =
+        InjectionGuard::from_config_map(&*feature_config_state.config_feature_map.lock().await);
+    let template_engine = TemplateEngine::new()
+        .with_injection_guard(injection_guard.clone())
+        .with_offline_mode(is_offline_mode(&app_handle));
     let mut template_context = HashMap::new();
-    let (tx, mut rx) = mpsc::channel(100);
+    // 选中的文字只用于这一次提问，之后在同一个窗口中继续提问时不会再带上
+    let selected_text = std::mem::take(&mut *state.inner().selected_text.lock().await);
+    let selected_app = std::mem::take(&mut *state.inner().selected_app.lock().await);
+    // 选中的文字来自其他应用，和附件、网页一样检查提示注入
+    let selected_text = injection_guard.apply("选中的文字", &selected_text);
+    let selected_app = injection_guard.apply("选中文字的来源应用", &selected_app);
+    template_context.insert("selected_text".to_string(), selected_text.clone());
+    template_context.insert("app_name".to_string(), selected_app.clone());
 
-    let selected_text = state.inner().selected_text.lock().await.clone();
-    template_context.insert("selected_text".to_string(), selected_text);
-
-    let app_handle_clone = app_handle.clone();
-    let assistant_detail = get_assistant(app_handle_clone, request.assistant_id).unwrap();
+    let fast_mode = request.fast_mode.unwrap_or(false);
+    let assistant_detail = request_assistant(
+        &app_handle,
+        request.assistant_id,
+        None,
+        fast_mode,
+        request.allow_online.unwrap_or(false),
+        &*feature_config_state.config_feature_map.lock().await,
+    )?;
     let assistant_prompt_origin = &assistant_detail.prompts[0].prompt;
-    let assistant_prompt_result = template_engine
+    let mut assistant_prompt_result = template_engine
         .parse(&assistant_prompt_origin, &template_context)
         .await;
-    println!("assistant_prompt_result: {}", assistant_prompt_result);
+    // 询问窗口中按配置的模板把选中的文字和来源应用加到系统提示词中，
+    // 提示词中已经通过 !s 引用或者提问中已经包含选中的文字时不再重复添加
+    let selection_in_prompt = [
+        Some(assistant_prompt_origin),
+        Some(&request.prompt),
+        override_prompt.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    .any(|prompt| references_selected_text(prompt))
+        || (!selected_text.trim().is_empty() && request.prompt.contains(selected_text.trim()));
+    if window.label() == "ask" && !selection_in_prompt {
+        let context_template = SelectionContextTemplate::from_feature_config(
+            &*feature_config_state.config_feature_map.lock().await,
+        );
+        if let Some(selection_context) = context_template.render(&selected_text, &selected_app) {
+            assistant_prompt_result =
+                format!("{}\n\n{}", assistant_prompt_result, selection_context);
+            override_prompt =
+                override_prompt.map(|prompt| format!("{}\n\n{}", prompt, selection_context));
+        }
+    }
 
-    if assistant_detail.model.is_empty() {
-        return Err(AppError::NoModelFound);
+    let mut triggered_at = None;
+    if fast_mode {
+        // 快捷操作不处理附件
+        request.attachment_list = None;
+        triggered_at = state
+            .shortcut_pressed_at
+            .lock()
+            .await
+            .take()
+            .filter(|pressed_at| pressed_at.elapsed() < SHORTCUT_LATENCY_WINDOW);
+    }
+    // 快捷操作按自己的配置把回答写入剪贴板，只写入剪贴板时隐藏询问窗口，焦点回到原来的应用
+    let clipboard = match request.quick_action_id {
+        Some(quick_action_id) => {
+            let quick_action =
+                AssistantDatabase::new(&app_handle)?.get_quick_action(quick_action_id)?;
+            let (clipboard_mode, clipboard_only) = clipboard_config(&quick_action);
+            if clipboard_only && window.label() == "ask" {
+                window.hide()?;
+            }
+            (clipboard_mode != ClipboardMode::Off)
+                .then(|| ClipboardWriter::new(app_handle.clone(), clipboard_mode))
+        }
+        None => None,
+    };
+    let dedup_key = DedupKey::new(
+        &request.conversation_id,
+        request.assistant_id,
+        &request.prompt,
+        request
+            .model
+            .as_deref()
+            .unwrap_or(&assistant_detail.model[0].model_code),
+        request.attachment_list.as_deref(),
+    );
+    let dedup_sender = match request_dedup.claim(dedup_key).await {
+        DedupClaim::Duplicate(response) => {
+            println!(
+                "ask_ai duplicate request, reuse message {}",
+                response.add_message_id
+            );
+            return Ok(response);
+        }
+        DedupClaim::First(sender) => Some(sender),
+        DedupClaim::Retry => None,
+    };
+    if let Err(e) = AssistantDatabase::new(&app_handle)
+        .and_then(|db| db.record_assistant_usage(request.assistant_id))
+    {
+        println!("record assistant usage error: {:?}", e);
+    }
+
+    let config_feature_map = feature_config_state.config_feature_map.lock().await.clone();
+    // 开启自动拆分时，新的提问换了话题就在新对话中回答，上一个对话的简要内容放在新对话的 system 消息中
+    let mut split_from = None;
+    if !request.conversation_id.is_empty() && !fast_mode {
+        let previous_id = request.conversation_id.parse::<i64>()?;
+        let topic_split = TopicSplitConfig::from_config_map(&config_feature_map);
+        match auto_split(&app_handle, previous_id, &request.prompt, &topic_split) {
+            Ok(Some(summary)) => {
+                println!("topic changed, split conversation {}", previous_id);
+                assistant_prompt_result = format!("{}\n\n{}", assistant_prompt_result, summary);
+                override_prompt =
+                    override_prompt.map(|prompt| format!("{}\n\n{}", prompt, summary));
+                request.conversation_id = String::new();
+                split_from = Some(previous_id);
+            }
+            Ok(None) => {}
+            Err(e) => println!("detect topic shift error: {:?}", e),
+        }
+    }
+    let draft_refine = DraftRefineConfig::from_config_map(&config_feature_map);
+    let draft_assistant = if request.draft_refine.unwrap_or(draft_refine.enabled) && !fast_mode {
+        draft_refine.draft_assistant(
+            &app_handle,
+            &config_feature_map,
+            &assistant_detail,
+            request.allow_online.unwrap_or(false),
+        )
+    } else {
+        None
+    };
+    // 无痕助手的提问不进入历史记录，也不生成标题
+    let incognito = RetentionPolicy::from_model_configs(&assistant_detail.model_configs).incognito;
+    if !incognito {
+        save_prompt_history(&app_handle, &request, &config_feature_map);
     }
 
-    let need_generate_title = request.conversation_id.is_empty();
+    let need_generate_title = request.conversation_id.is_empty() && !incognito;
     let request_prompt_result = template_engine
         .parse(&request.prompt, &template_context)
         .await;
 
-    let app_handle_clone = app_handle.clone();
-    let (conversation_id, new_message_id, request_prompt_result_with_context, init_message_list) =
-        initialize_conversation(
-            &app_handle_clone,
-            &request,
-            &assistant_detail,
-            assistant_prompt_result,
-            request_prompt_result.clone(),
-            override_prompt.clone(),
-        )
-        .await?;
-
-    if new_message_id.is_some() {
-        let config_feature_map = feature_config_state.config_feature_map.lock().await.clone();
+    // 快速模式同样检索知识库，助手配置了知识库时回答需要依据知识库的内容
+    let (request_prompt_with_knowledge, citation_sources) = append_knowledge_context(
+        &app_handle,
+        &assistant_detail,
+        &config_feature_map,
+        request_prompt_result.clone(),
+    );
 
-        let request_prompt_result_clone = request_prompt_result.clone();
-        let app_handle_clone = app_handle.clone();
+    let app_handle_clone = app_handle.clone();
+    let (
+        conversation_id,
+        new_message_id,
+        request_prompt_result_with_context,
+        mut init_message_list,
+        attachment_truncation,
+    ) = initialize_conversation(
+        &app_handle_clone,
+        &request,
+        &assistant_detail,
+        assistant_prompt_result,
+        request_prompt_with_knowledge,
+        &citation_sources,
+        override_prompt.clone(),
+        &config_feature_map,
+    )
+    .await?;
+    if let Some(previous_id) = split_from {
+        let _ = window.emit("conversation_split", (previous_id, conversation_id));
+    }
+    // 提问已经保存为消息，删除输入框的草稿，自动拆分时草稿属于原来的对话
+    let draft_conversation_id = if request.conversation_id.is_empty() {
+        split_from
+    } else {
+        Some(conversation_id)
+    };
+    let draft_key = draft_key(draft_conversation_id, Some(request.assistant_id));
+    let delete_draft_result = ConversationDatabase::new(&app_handle)
+        .map_err(AppError::from)
+        .and_then(|db| db.draft_repo()?.delete(&draft_key).map_err(AppError::from));
+    if let Err(e) = delete_draft_result {
+        println!("delete draft error: {:?}", e);
+    }
+    append_assistant_prompts(
+        &app_handle,
+        assistant_detail.assistant.id,
+        &request_prompt_result,
+        &config_feature_map,
+        &mut init_message_list,
+    );
 
+    let mut draft_message_id = None;
+    if let Some(message_id) = new_message_id {
         let cancel_token = CancellationToken::new();
-        let message_id = new_message_id.unwrap();
         message_token_manager
-            .store_token(new_message_id.unwrap(), cancel_token.clone())
+            .store_token(message_id, cancel_token.clone())
             .await;
 
-        let tokens = message_token_manager.get_tokens();
-        tokio::spawn(async move {
-            let db = LLMDatabase::new(&app_handle_clone)
-                .map_err(Error::from)
-                .context("Failed to create LLMDatabase")?;
-            let conversation_db = ConversationDatabase::new(&app_handle_clone).unwrap();
-            let provider_id = &assistant_detail.model[0].provider_id;
-            let model_code = &assistant_detail.model[0].model_code;
-            let model_detail = db
-                .get_llm_model_detail(provider_id, model_code)
-                .context("Failed to get LLM model detail")?;
-            println!("model detail : {:#?}", model_detail);
-
-            let provider = get_provider(model_detail.provider, model_detail.configs);
-
-            let mut model_config_clone = assistant_detail.model_configs.clone();
-            model_config_clone.push(AssistantModelConfig {
-                id: 0,
-                assistant_id: assistant_detail.assistant.id,
-                assistant_model_id: model_detail.model.id,
-                name: "model".to_string(),
-                value: Some(model_detail.model.code),
-                value_type: "string".to_string(),
-            });
-
-            if let Some(override_configs) = override_model_config {
-                for (key, value) in override_configs {
-                    let value_type = match &value {
-                        serde_json::Value::String(_) => "string",
-                        serde_json::Value::Number(_) => "number",
-                        serde_json::Value::Bool(_) => "boolean",
-                        serde_json::Value::Array(_) => "array",
-                        serde_json::Value::Object(_) => "object",
-                        serde_json::Value::Null => "null",
-                    }
-                    .to_string();
-
-                    let value_str = value.to_string();
-
-                    if let Some(existing_config) =
-                        model_config_clone.iter_mut().find(|c| c.name == key)
-                    {
-                        existing_config.value = Some(value_str);
-                        existing_config.value_type = value_type;
-                    } else {
-                        model_config_clone.push(AssistantModelConfig {
-                            id: 0,
-                            assistant_id: assistant_detail.assistant.id,
-                            assistant_model_id: model_detail.model.id,
-                            name: key,
-                            value: Some(value_str),
-                            value_type,
-                        });
-                    }
+        let mut task = GenerationTask {
+            app_handle: app_handle.clone(),
+            assistant_detail,
+            message_id,
+            init_message_list,
+            override_model_config,
+            error_as_content: true,
+            cache_ttl: None,
+        };
+        // 回答缓存只用于结果确定的请求和多模型对比，重新生成和继续生成总是重新请求
+        let response_cache = ResponseCacheConfig::from_config_map(&config_feature_map);
+        if response_cache.enabled
+            && (request.use_response_cache.unwrap_or(false) || task.is_deterministic())
+        {
+            task.cache_ttl = Some(response_cache.ttl);
+        }
+        let watchdog = StallWatchdog::new(&config_feature_map, task.is_stream());
+        // 草稿保存为不显示的消息，和最终的回答共用取消令牌，取消任意一个都会结束整个提问
+        let draft = draft_assistant.and_then(|draft_assistant| {
+            let draft_message = add_message(
+                &app_handle,
+                None,
+                conversation_id,
+                DRAFT_MESSAGE_TYPE.to_string(),
+                String::new(),
+                None,
+                Some(draft_assistant.model[0].model_code.clone()),
+                None,
+                None,
+                0,
+                Some(draft_assistant.assistant.id),
+            );
+            let draft_task = match draft_message {
+                Ok(draft_message) => GenerationTask {
+                    assistant_detail: draft_assistant,
+                    message_id: draft_message.id,
+                    error_as_content: false,
+                    cache_ttl: None,
+                    ..task.clone()
+                },
+                Err(e) => {
+                    println!("add draft message error: {:?}", e);
+                    return None;
                 }
+            };
+            let draft_watchdog = StallWatchdog::new(&config_feature_map, draft_task.is_stream());
+            Some((draft_task, draft_watchdog))
+        });
+        let title = if need_generate_title {
+            Some((conversation_id, request_prompt_result, config_feature_map))
+        } else {
+            None
+        };
+        let options = ReceiveOptions {
+            title,
+            citation_sources,
+            fast_mode,
+            triggered_at,
+            clipboard,
+            ..Default::default()
+        };
+        let conversation_lock = message_token_manager
+            .conversation_lock(conversation_id)
+            .await;
+        match draft {
+            Some((draft_task, draft_watchdog)) => {
+                draft_message_id = Some(draft_task.message_id);
+                message_token_manager
+                    .store_token(draft_task.message_id, cancel_token.clone())
+                    .await;
+                let refine = RefineStage {
+                    task,
+                    watchdog,
+                    mode: draft_refine.mode,
+                    options: ReceiveOptions {
+                        draft_message_id,
+                        ..options
+                    },
+                };
+                spawn_generation(
+                    draft_task,
+                    window,
+                    draft_watchdog,
+                    message_token_manager.get_tokens(),
+                    conversation_lock,
+                    cancel_token,
+                    ReceiveOptions {
+                        refine: Some(Box::new(refine)),
+                        ..Default::default()
+                    },
+                );
             }
+            None => spawn_generation(
+                task,
+                window,
+                watchdog,
+                message_token_manager.get_tokens(),
+                conversation_lock,
+                cancel_token,
+                options,
+            ),
+        }
+    }
 
-            let config_map = assistant_detail
-                .model_configs
-                .iter()
-                .filter_map(|config| {
-                    config
-                        .value
-                        .as_ref()
-                        .map(|value| (config.name.clone(), value.clone()))
-                })
-                .collect::<HashMap<String, String>>();
+    let response = AiResponse {
+        conversation_id,
+        add_message_id: new_message_id.unwrap(),
+        request_prompt_result_with_context,
+        attachment_truncation,
+        draft_message_id,
+    };
+    if let Some(sender) = dedup_sender {
+        let _ = sender.send(Some(response.clone()));
+    }
+    Ok(response)
+}
 
-            let stream = config_map
-                .get("stream")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(false);
+/// 预览中的一条消息，附件只返回名称
+#[derive(Serialize, Clone, Debug)]
+pub struct PreviewMessage {
+    pub role: Role,
+    pub content: String,
+    pub attachments: Vec<String>,
+    pub tokens: usize,
+}
 
-            println!("prompt: {}", request_prompt_result_clone);
+/// 预览请求的 token 估算，不包括图片
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct TokenBreakdown {
+    pub system: usize,
+    pub history: usize,
+    // 这次的提问，没有传入提问时为 0
+    pub prompt: usize,
+    pub total: usize,
+    // 留给回答的 max_tokens
+    pub max_completion_tokens: u32,
+}
 
-            if stream {
-                let tx_clone = tx.clone();
-                if let Err(e) = provider
-                    .chat_stream(
-                        message_id,
-                        init_message_list,
-                        model_config_clone,
-                        tx,
-                        cancel_token,
-                    )
-                    .await
-                {
-                    let mut map = tokens.lock().await;
-                    map.remove(&message_id);
-                    let err_msg = format!("Chat stream error: {}", e);
-                    tx_clone.send((message_id, err_msg, true)).await.unwrap();
-                    eprintln!("Chat stream error: {}", e);
-                }
-            } else {
-                conversation_db
-                    .message_repo()
-                    .unwrap()
-                    .update_start_time(message_id)
-                    .unwrap();
-                let content = provider
-                    .chat(
-                        message_id,
-                        init_message_list,
-                        model_config_clone,
-                        cancel_token,
-                    )
-                    .await
-                    .map_err(Error::from)
-                    .context("Failed to chat")?;
+#[derive(Serialize, Clone, Debug)]
+pub struct RequestPreview {
+    pub provider: String,
+    pub model_code: String,
+    pub system_prompt: Option<String>,
+    pub messages: Vec<PreviewMessage>,
+    pub parameters: ModelParams,
+    pub stream: bool,
+    pub tokens: TokenBreakdown,
+    // 超出 context 的 max_length 没有带上的历史消息数量
+    pub dropped_messages: usize,
+    pub omitted_images: Vec<OmittedImage>,
+    pub citations: Vec<Citation>,
+}
 
-                println!("Chat content: {}", content.clone());
+/// 按提问的流程组装对话的下一次请求但不发送，返回实际会发给模型的消息、system 提示词、模型参数和 token 估算，
+/// 用于调试提示词模板、上下文裁剪和知识库检索
+///
+/// prompt 为空时预览对话现有的消息，model_code 为空时使用助手配置的模型。fast_mode、allow_online 和提问时的含义相同，
+/// 全局的简洁模式和离线模式同样生效。不会保存消息，也不会发送 context_overflow 等事件；
+/// 和提问时一样，不支持视觉的模型会先对图片做 OCR
+#[tauri::command]
+pub async fn preview_request(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    feature_config_state: State<'_, FeatureConfigState>,
+    tokenizer_state: State<'_, TokenizerState>,
+    conversation_id: i64,
+    model_code: Option<String>,
+    prompt: Option<String>,
+    fast_mode: Option<bool>,
+    allow_online: Option<bool>,
+) -> Result<RequestPreview, AppError> {
+    let db = get_conversation_db(&app_handle)?;
+    let conversation = db
+        .conversation_repo()?
+        .read(conversation_id)?
+        .ok_or(AppError::DatabaseError("未找到对话".to_string()))?;
+    let assistant_id = conversation.assistant_id.unwrap_or_default();
+    let config_feature_map = feature_config_state.config_feature_map.lock().await.clone();
+    let model = match model_code.filter(|code| !code.is_empty()) {
+        Some(model_code) => Some(find_model(&app_handle, &model_code, None)?),
+        None => None,
+    };
+    let assistant_detail = request_assistant(
+        &app_handle,
+        assistant_id,
+        model,
+        fast_mode.unwrap_or(false),
+        allow_online.unwrap_or(false),
+        &config_feature_map,
+    )?;
 
-                conversation_db
-                    .message_repo()
-                    .unwrap()
-                    .update_finish_time(message_id)
-                    .unwrap();
-                tx.send((message_id, content.clone(), true)).await.unwrap();
-                // Ensure tx is closed after sending the message
-                drop(tx);
-            }
+    let assistant_names = assistant_names(&app_handle).await;
+    let mut message_list = conversation_history(
+        db.message_repo()?
+            .list_by_conversation_id(conversation_id)?,
+        assistant_id,
+        &assistant_names,
+        &db.quote_repo()?.list_by_conversation_id(conversation_id)?,
+        &InjectionGuard::from_config_map(&config_feature_map),
+    )?;
+    let mut request_prompt_result = String::new();
+    let mut citations = vec![];
+    let has_prompt = prompt.as_ref().is_some_and(|p| !p.trim().is_empty());
+    if let Some(prompt) = prompt.filter(|_| has_prompt) {
+        let mut template_context = HashMap::new();
+        let injection_guard = InjectionGuard::from_config_map(&config_feature_map);
+        let selected_text = state.inner().selected_text.lock().await.clone();
+        template_context.insert(
+            "selected_text".to_string(),
+            injection_guard.apply("选中的文字", &selected_text),
+        );
+        request_prompt_result = TemplateEngine::new()
+            .with_injection_guard(injection_guard)
+            .with_offline_mode(is_offline_mode(&app_handle))
+            .parse(&prompt, &template_context)
+            .await;
+        let (request_prompt_with_knowledge, citation_sources) = append_knowledge_context(
+            &app_handle,
+            &assistant_detail,
+            &config_feature_map,
+            request_prompt_result.clone(),
+        );
+        let (context, _) =
+            build_attachment_context(&[], &request_prompt_with_knowledge, &config_feature_map);
+        message_list.push((
+            ChatMessage::new(
+                Role::User,
+                format!("{}\n{}", request_prompt_with_knowledge, context),
+            ),
+            false,
+        ));
+        citations = citation_sources;
+    }
+    append_response_style(&app_handle, conversation_id, &mut message_list);
+    let message_count = message_list.len();
+    let (mut init_message_list, _) =
+        fit_context_window(message_list, context_max_length(&config_feature_map));
+    let dropped_messages = message_count - init_message_list.len();
+    append_assistant_prompts(
+        &app_handle,
+        assistant_id,
+        &request_prompt_result,
+        &config_feature_map,
+        &mut init_message_list,
+    );
 
-            Ok::<(), Error>(())
-        });
+    let model = &assistant_detail.model[0];
+    let model_detail =
+        get_llm_db(&app_handle)?.get_llm_model_detail(&model.provider_id, &model.model_code)?;
+    let task = GenerationTask {
+        app_handle: app_handle.clone(),
+        assistant_detail,
+        message_id: 0,
+        init_message_list,
+        override_model_config: None,
+        error_as_content: false,
+        cache_ttl: None,
+    };
+    let (mut message_list, _, omitted_images) = task.request_messages(&model_detail).await;
+    let model_config = task.request_model_config(&model_detail);
+    append_response_length_instruction(&mut message_list, &model_config);
+    let provider = model_detail.provider.name.clone();
+    let model_code = model_detail.model.code.clone();
+    let context = RequestContext::new(model_detail.configs, &model_config, message_list);
+    let parameters = context.params();
 
-        let app_handle_clone = app_handle.clone();
-        let tokens = message_token_manager.get_tokens();
-        let window_clone = window.clone();
-        tokio::spawn(async move {
-            loop {
-                match timeout(Duration::from_secs(600), rx.recv()).await {
-                    Ok(Some((id, content, done))) => {
-                        println!("Received data: id={}, content={}", id, content);
-                        window_clone
-                            .emit(format!("message_{}", id).as_str(), content.clone())
-                            .map_err(|e| e.to_string())
-                            .unwrap();
-
-                        if done {
-                            let conversation_db = ConversationDatabase::new(&app_handle_clone)
-                                .map_err(|e: rusqlite::Error| e.to_string())
-                                .unwrap();
-
-                            let mut message = conversation_db
-                                .message_repo()
-                                .unwrap()
-                                .read(new_message_id.unwrap())
-                                .unwrap()
-                                .unwrap();
-                            message.content = content.clone().to_string();
-                            conversation_db
-                                .message_repo()
-                                .unwrap()
-                                .update(&message)
-                                .unwrap();
-
-                            println!("Message finish: id={}", id);
-                            window_clone
-                                .emit(
-                                    format!("message_{}", id).as_str(),
-                                    "Tea::Event::MessageFinish",
-                                )
-                                .map_err(|e| e.to_string())
-                                .unwrap();
-                            if need_generate_title {
-                                generate_title(
-                                    &app_handle_clone,
-                                    conversation_id,
-                                    request_prompt_result.clone(),
-                                    content.clone().to_string(),
-                                    config_feature_map.clone(),
-                                    window_clone.clone(),
-                                )
-                                .await
-                                .map_err(|e| e.to_string())
-                                .unwrap();
-                            }
-                            let mut map = tokens.lock().await;
-                            map.remove(&message_id);
-                        }
-                    }
-                    Ok(None) => {
-                        let mut map = tokens.lock().await;
-                        map.remove(&message_id);
-                        println!("Channel closed");
-                        break;
-                    }
-                    Err(err) => {
-                        let mut map = tokens.lock().await;
-                        map.remove(&message_id);
-                        println!("Timeout waiting for data from channel: {:?}", err);
-                        break;
-                    }
-                }
+    let tokenizer = tokenizer_state.get(&model_code);
+    let messages = context
+        .messages
+        .iter()
+        .map(|message| {
+            let content = message.text();
+            PreviewMessage {
+                role: message.role,
+                tokens: tokenizer.count_tokens(&content),
+                content,
+                attachments: message
+                    .attachments()
+                    .map(|a| {
+                        a.attachment_url
+                            .clone()
+                            .unwrap_or_else(|| format!("附件 {}", a.id))
+                    })
+                    .collect(),
             }
-        });
-    }
-
-    Ok(AiResponse {
-        conversation_id,
-        add_message_id: new_message_id.unwrap(),
-        request_prompt_result_with_context,
+        })
+        .collect::<Vec<_>>();
+    Ok(RequestPreview {
+        provider,
+        model_code,
+        system_prompt: context.system_prompt(),
+        tokens: token_breakdown(&messages, has_prompt, parameters.max_tokens),
+        messages,
+        parameters,
+        stream: task.is_stream(),
+        dropped_messages,
+        omitted_images,
+        citations,
     })
 }
 
+// system 消息之外，最后一条用户消息是这次的提问，其余都算历史消息
+fn token_breakdown(
+    messages: &[PreviewMessage],
+    has_prompt: bool,
+    max_completion_tokens: u32,
+) -> TokenBreakdown {
+    let prompt_index = messages
+        .iter()
+        .rposition(|message| message.role == Role::User)
+        .filter(|_| has_prompt);
+    let mut breakdown = TokenBreakdown {
+        max_completion_tokens,
+        ..Default::default()
+    };
+    for (index, message) in messages.iter().enumerate() {
+        if message.role == Role::System {
+            breakdown.system += message.tokens;
+        } else if Some(index) == prompt_index {
+            breakdown.prompt += message.tokens;
+        } else {
+            breakdown.history += message.tokens;
+        }
+        breakdown.total += message.tokens;
+    }
+    breakdown
+}
+
 #[tauri::command]
 pub async fn cancel_ai(
     message_token_manager: State<'_, MessageTokenManager>,
@@ -320,11 +710,11 @@ pub async fn cancel_ai(
 }
 
 fn init_conversation(
-    app_handle: &tauri::AppHandle,
+    app_handle: &AppHandle,
     assistant_id: i64,
     llm_model_id: i64,
     llm_model_code: String,
-    messages: &Vec<(String, String, Vec<MessageAttachment>)>,
+    messages: &[ChatMessage],
 ) -> Result<(Conversation, Vec<Message>), AppError> {
     let db = ConversationDatabase::new(app_handle).map_err(AppError::from)?;
     println!("init_conversation !{:?}", assistant_id);
@@ -333,16 +723,28 @@ fn init_conversation(
         .unwrap()
         .create(&Conversation {
             id: 0,
-            name: "新对话".to_string(),
+            name: DEFAULT_CONVERSATION_NAME.to_string(),
             assistant_id: Some(assistant_id),
             created_time: chrono::Utc::now(),
+            is_private: false,
+            summary: None,
         })
         .map_err(AppError::from)?;
     let conversation_clone = conversation.clone();
     let conversation_id = conversation_clone.id;
+    // 无痕助手的对话在保存第一条消息前标记，内容不会以可恢复的形式写入数据库，
+    // 数据库中也记录下来，异常退出后由清理任务删除
+    if RetentionPolicy::of_assistant(app_handle, assistant_id).incognito {
+        db.conversation_repo()?.set_incognito(conversation_id)?;
+        if let Some(state) = app_handle.try_state::<PrivateConversationState>() {
+            state
+                .set_incognito(conversation_id)
+                .map_err(AppError::UnknownError)?;
+        }
+    }
     let mut message_result_array = vec![];
 
-    for (message_type, content, attachment_list) in messages {
+    for chat_message in messages {
         let message = db
             .message_repo()
             .unwrap()
@@ -350,22 +752,28 @@ fn init_conversation(
                 id: 0,
                 parent_id: None,
                 conversation_id,
-                message_type: message_type.clone(),
-                content: content.clone(),
+                message_type: chat_message.role.to_string(),
+                content: chat_message.text(),
                 llm_model_id: Some(llm_model_id),
                 llm_model_name: Some(llm_model_code.clone()),
                 created_time: chrono::Utc::now(),
                 start_time: None,
-                finish_time: None,
+                // 提示词模板中预设的回答不需要生成，直接标记为完成，启动时不会被当作中断的回答
+                finish_time: (chat_message.role == Role::Assistant).then(chrono::Utc::now),
                 token_count: 0,
+                is_truncated: false,
+                attachment_truncation: None,
+                pinned: false,
+                citations: None,
+                timed_out: false,
+                generation_metadata: None,
+                reasoning: None,
+                assistant_id: None,
             })
             .map_err(AppError::from)?;
-        for attachment in attachment_list {
-            let mut updated_attachment = attachment.clone();
-            updated_attachment.message_id = message.id;
-            db.attachment_repo()
-                .unwrap()
-                .update(&updated_attachment)
+        for attachment in chat_message.attachments() {
+            db.attachment_repo()?
+                .link(attachment.id, message.id)
                 .map_err(AppError::from)?;
         }
         message_result_array.push(message.clone());
@@ -376,10 +784,84 @@ fn init_conversation(
 
 #[tauri::command]
 pub async fn regenerate_ai(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
+    feature_config_state: State<'_, FeatureConfigState>,
+    message_token_manager: State<'_, MessageTokenManager>,
+    window: Window,
+    message_id: i64,
+    allow_online: Option<bool>,
+) -> Result<AiResponse, AppError> {
+    regenerate_message(
+        app_handle,
+        feature_config_state,
+        message_token_manager,
+        window,
+        message_id,
+        None,
+        allow_online.unwrap_or(false),
+    )
+    .await
+}
+
+/// 使用另一个模型重新生成回答，结果和原来的回答一样作为同一条消息的分支保存，消息上记录使用的模型
+///
+/// 除了模型以外，助手的提示词和模型参数都保持不变。多个提供商有同名模型时通过 llm_provider_id 区分
+#[tauri::command]
+pub async fn regenerate_with_model(
+    app_handle: AppHandle,
+    feature_config_state: State<'_, FeatureConfigState>,
+    message_token_manager: State<'_, MessageTokenManager>,
+    window: Window,
+    message_id: i64,
+    model_code: String,
+    llm_provider_id: Option<i64>,
+    allow_online: Option<bool>,
+) -> Result<AiResponse, AppError> {
+    let model = find_model(&app_handle, &model_code, llm_provider_id)?;
+    regenerate_message(
+        app_handle,
+        feature_config_state,
+        message_token_manager,
+        window,
+        message_id,
+        Some(model),
+        allow_online.unwrap_or(false),
+    )
+    .await
+}
+
+// 按模型代码查找启用的模型，多个提供商有同名模型时通过 llm_provider_id 区分
+fn find_model(
+    app_handle: &AppHandle,
+    model_code: &str,
+    llm_provider_id: Option<i64>,
+) -> Result<AssistantModel, AppError> {
+    let (_, code, id, provider_id) = get_llm_db(app_handle)?
+        .get_models_for_select(false)
+        .map_err(AppError::DatabaseError)?
+        .into_iter()
+        .find(|(_, code, _, provider_id)| {
+            *code == model_code && llm_provider_id.map_or(true, |id| id == *provider_id)
+        })
+        .ok_or(AppError::NoModelFound)?;
+    Ok(AssistantModel {
+        id,
+        assistant_id: 0,
+        provider_id,
+        model_code: code.clone(),
+        alias: code,
+    })
+}
+
+// model 不为空时替换助手配置的模型
+async fn regenerate_message(
+    app_handle: AppHandle,
+    feature_config_state: State<'_, FeatureConfigState>,
     message_token_manager: State<'_, MessageTokenManager>,
-    window: tauri::Window,
+    window: Window,
     message_id: i64,
+    model: Option<AssistantModel>,
+    allow_online: bool,
 ) -> Result<AiResponse, AppError> {
     let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
     let message = db
@@ -389,6 +871,8 @@ pub async fn regenerate_ai(
         .ok_or(AppError::DatabaseError("未找到消息".to_string()))?;
 
     let conversation_id = message.conversation_id;
+    // 重新生成会切换到新的分支，正在生成的回答还在写入原来的分支
+    ensure_not_busy(&message_token_manager, conversation_id)?;
     let conversation = db
         .conversation_repo()
         .unwrap()
@@ -398,6 +882,7 @@ pub async fn regenerate_ai(
         .message_repo()
         .unwrap()
         .list_by_conversation_id(conversation_id)?;
+    let quotes = db.quote_repo()?.list_by_conversation_id(conversation_id)?;
 
     let parent_ids: HashSet<i64> = messages.iter().filter_map(|m| m.0.parent_id).collect();
     println!("parent_ids: {:?}", parent_ids);
@@ -425,13 +910,21 @@ pub async fn regenerate_ai(
 
     let max_child_ids: HashSet<i64> = parent_max_child.values().cloned().collect();
     println!("max_child_ids: {:?}", max_child_ids);
+    let citation_sources = question_citation_sources(&messages, message_id);
 
-    let assistant_id = conversation.assistant_id.unwrap();
-    let assistant_detail = get_assistant(app_handle.clone(), assistant_id).unwrap();
-
-    if assistant_detail.model.is_empty() {
-        return Err(AppError::NoModelFound);
-    }
+    // 群聊中由原来回答这条消息的助手重新生成
+    let assistant_id = message.assistant_id.or(conversation.assistant_id).unwrap();
+    let assistant_names = assistant_names(&app_handle).await;
+    let config_feature_map = feature_config_state.config_feature_map.lock().await.clone();
+    let assistant_detail = request_assistant(
+        &app_handle,
+        assistant_id,
+        model,
+        false,
+        allow_online,
+        &config_feature_map,
+    )?;
+    let injection_guard = InjectionGuard::from_config_map(&config_feature_map);
 
     let init_message_list = messages
         .into_iter()
@@ -440,34 +933,53 @@ pub async fn regenerate_ai(
                 return None;
             }
 
-            if parent_ids.contains(&m.0.id) {
-                // 这是一个父消息，保留它
-                Some((m.0.message_type, m.0.content, vec![]))
-            } else if max_child_ids.contains(&m.0.id) {
-                // 这是一个子消息，并且是最大 id 的子消息，保留它
-                Some((m.0.message_type, m.0.content, vec![]))
+            if parent_ids.contains(&m.0.id) || max_child_ids.contains(&m.0.id) {
+                // 父消息和最大 id 的子消息保留
+                Some(
+                    speaker_chat_message(
+                        &m.0,
+                        vec![],
+                        assistant_id,
+                        &assistant_names,
+                        &quotes,
+                        &injection_guard,
+                    )
+                    .map(|message| (message, m.0.pinned)),
+                )
             } else {
                 // 其他情况，过滤掉
                 None
             }
         })
-        .collect::<Vec<_>>();
-    println!("init_message_list: {:?}", init_message_list);
-
-    let (tx, mut rx) = mpsc::channel(100);
+        .collect::<Result<Vec<_>, AppError>>()?;
+    let mut init_message_list = apply_context_window(
+        &app_handle,
+        conversation_id,
+        init_message_list,
+        &config_feature_map,
+    );
+    append_assistant_prompts(
+        &app_handle,
+        assistant_detail.assistant.id,
+        &last_user_prompt(&init_message_list),
+        &config_feature_map,
+        &mut init_message_list,
+    );
+    println!("init_message_list: {} messages", init_message_list.len());
 
     let app_handle_clone = app_handle.clone();
     let new_message = add_message(
         &app_handle_clone,
         Some(message_id),
         conversation_id,
-        "assistant".to_string(),
+        Role::Assistant.to_string(),
         String::new(),
         Some(assistant_detail.model[0].id),
         Some(assistant_detail.model[0].model_code.clone()),
         None,
         None,
         0,
+        Some(assistant_id),
     )?;
     let new_message_id = new_message.id;
 
@@ -476,169 +988,2337 @@ pub async fn regenerate_ai(
         .store_token(new_message_id, cancel_token.clone())
         .await;
 
-    let tokens = message_token_manager.get_tokens();
-    tokio::spawn(async move {
-        let db = LLMDatabase::new(&app_handle_clone)
-            .map_err(Error::from)
-            .context("Failed to create LLMDatabase")?;
-        let conversation_db = ConversationDatabase::new(&app_handle_clone).unwrap();
-        let provider_id = &assistant_detail.model[0].provider_id;
-        let model_code = &assistant_detail.model[0].model_code;
-        let model_detail = db
-            .get_llm_model_detail(provider_id, model_code)
-            .context("Failed to get LLM model detail")?;
+    let task = GenerationTask {
+        app_handle: app_handle.clone(),
+        assistant_detail,
+        message_id: new_message_id,
+        init_message_list,
+        override_model_config: None,
+        error_as_content: true,
+        cache_ttl: None,
+    };
+    let watchdog = StallWatchdog::new(&config_feature_map, task.is_stream());
+    spawn_generation(
+        task,
+        window,
+        watchdog,
+        message_token_manager.get_tokens(),
+        message_token_manager
+            .conversation_lock(conversation_id)
+            .await,
+        cancel_token,
+        ReceiveOptions {
+            citation_sources,
+            ..Default::default()
+        },
+    );
 
-        let provider = get_provider(model_detail.provider, model_detail.configs);
+    Ok(AiResponse {
+        conversation_id,
+        add_message_id: new_message_id,
+        request_prompt_result_with_context: String::new(),
+        attachment_truncation: vec![],
+        draft_message_id: None,
+    })
+}
 
-        let mut model_config_clone = assistant_detail.model_configs.clone();
-        model_config_clone.push(AssistantModelConfig {
-            id: 0,
-            assistant_id: assistant_detail.assistant.id,
-            assistant_model_id: model_detail.model.id,
-            name: "model".to_string(),
-            value: Some(model_detail.model.code),
-            value_type: "string".to_string(),
-        });
+// 按下快捷键之后这么久以内发起的快速模式提问才统计从按键到第一段内容的耗时
+const SHORTCUT_LATENCY_WINDOW: Duration = Duration::from_secs(60);
 
-        let config_map = assistant_detail
-            .model_configs
-            .iter()
-            .filter_map(|config| {
-                config
-                    .value
-                    .as_ref()
-                    .map(|value| (config.name.clone(), value.clone()))
-            })
-            .collect::<HashMap<String, String>>();
+/// 快速模式的配置，保存在 fast_mode 功能配置中
+///
+/// 快速模式用于划词弹窗等快捷操作：不处理附件，可以换用更快的小模型，按下快捷键时提前和提供商建立连接
+#[derive(Debug, Clone, Default)]
+pub struct FastModeConfig {
+    // 快速模式使用的模型，model_code 为空时使用助手自己的模型
+    pub provider_id: Option<i64>,
+    pub model_code: String,
+    pub prewarm: bool,
+}
 
-        let stream = config_map
-            .get("stream")
-            .and_then(|v| v.parse().ok())
-            .unwrap_or(false);
+impl FastModeConfig {
+    pub fn from_config_map(
+        config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+    ) -> Self {
+        let config = config_feature_map.get("fast_mode");
+        let get_value = |key: &str| {
+            config
+                .and_then(|config| config.get(key))
+                .map(|config| config.value.trim().to_string())
+                .unwrap_or_default()
+        };
+        FastModeConfig {
+            provider_id: get_value("provider_id").parse().ok(),
+            model_code: get_value("model_code"),
+            prewarm: get_value("prewarm") != "false",
+        }
+    }
 
-        if stream {
-            let tx_clone = tx.clone();
-            if let Err(e) = provider
-                .chat_stream(
-                    new_message_id,
-                    init_message_list,
-                    model_config_clone,
-                    tx,
-                    cancel_token,
-                )
-                .await
-            {
-                let mut map = tokens.lock().await;
-                map.remove(&new_message_id);
-                let err_msg = format!("Chat stream error: {}", e);
-                tx_clone
-                    .send((new_message_id, err_msg, true))
-                    .await
-                    .unwrap();
-                eprintln!("Chat stream error: {}", e);
+    fn apply(&self, assistant_detail: &mut AssistantDetail) {
+        if self.model_code.is_empty() {
+            return;
+        }
+        if let Some(model) = assistant_detail.model.first_mut() {
+            if let Some(provider_id) = self.provider_id {
+                model.provider_id = provider_id;
             }
-        } else {
-            conversation_db
-                .message_repo()
-                .unwrap()
-                .update_start_time(new_message_id)
-                .unwrap();
-            let content = provider
-                .chat(
-                    new_message_id,
-                    init_message_list,
-                    model_config_clone,
-                    cancel_token,
-                )
-                .await
-                .map_err(Error::from)
-                .context("Failed to chat")?;
+            model.model_code = self.model_code.clone();
+        }
+    }
+}
 
-            conversation_db
-                .message_repo()
-                .unwrap()
-                .update_finish_time(new_message_id)
-                .unwrap();
-            tx.send((new_message_id, content.clone(), true))
-                .await
-                .unwrap();
-            // Ensure tx is closed after sending the message
-            drop(tx);
+/// 离线模式下换用的本地模型，保存在 offline_mode 功能配置中，model_code 为空时不换用
+#[derive(Debug, Clone, Default)]
+pub struct OfflineModeConfig {
+    pub provider_id: Option<i64>,
+    pub model_code: String,
+}
+
+impl OfflineModeConfig {
+    pub fn from_config_map(
+        config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+    ) -> Self {
+        let config = config_feature_map.get("offline_mode");
+        let get_value = |key: &str| {
+            config
+                .and_then(|config| config.get(key))
+                .map(|config| config.value.trim().to_string())
+                .unwrap_or_default()
+        };
+        OfflineModeConfig {
+            provider_id: get_value("provider_id").parse().ok(),
+            model_code: get_value("model_code"),
         }
+    }
+}
 
-        Ok::<(), Error>(())
-    });
+// 离线模式下助手的模型不是本地提供商时，换用 offline_mode 配置的本地模型，没有配置时返回 OfflineBlocked，
+// 前端提示用户后可以带上 allow_online 重新发送
+fn apply_offline_mode(
+    app_handle: &AppHandle,
+    config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+    assistant_detail: &mut AssistantDetail,
+    allow_online: bool,
+) -> Result<(), AppError> {
+    if allow_online || !is_offline_mode(app_handle) {
+        return Ok(());
+    }
+    let db = get_llm_db(app_handle)?;
+    let is_local = |provider_id: i64| -> Result<(bool, String), AppError> {
+        let provider = db.get_llm_provider(provider_id)?;
+        let provider_config = db.get_llm_provider_config(provider_id)?;
+        Ok((
+            is_local_provider(&provider, &provider_config),
+            provider.name,
+        ))
+    };
+    let Some(model) = assistant_detail.model.first_mut() else {
+        return Ok(());
+    };
+    let (local, provider_name) = is_local(model.provider_id)?;
+    if local {
+        return Ok(());
+    }
+    let config = OfflineModeConfig::from_config_map(config_feature_map);
+    if let Some(provider_id) = config.provider_id.filter(|_| !config.model_code.is_empty()) {
+        if is_local(provider_id).is_ok_and(|(local, _)| local) {
+            println!(
+                "offline mode, route {} to local model {}",
+                model.model_code, config.model_code
+            );
+            model.provider_id = provider_id;
+            model.model_code = config.model_code;
+            return Ok(());
+        }
+    }
+    Err(AppError::OfflineBlocked(provider_name))
+}
 
-    let app_handle_clone = app_handle.clone();
-    let tokens = message_token_manager.get_tokens();
-    let window_clone = window.clone();
-    tokio::spawn(async move {
-        loop {
-            match timeout(Duration::from_secs(600), rx.recv()).await {
-                Ok(Some((id, content, done))) => {
-                    println!("Received data: id={}, content={}", id, content);
-                    window_clone
-                        .emit(format!("message_{}", id).as_str(), content.clone())
-                        .map_err(|e| e.to_string())
-                        .unwrap();
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DraftRefineMode {
+    // 参考草稿直接给出改进后的回答
+    #[default]
+    Refine,
+    // 先在思考过程中逐条指出草稿的问题，再给出修改后的回答
+    Critique,
+}
 
-                    if done {
-                        let conversation_db = ConversationDatabase::new(&app_handle_clone)
-                            .map_err(|e: rusqlite::Error| e.to_string())
-                            .unwrap();
-
-                        let mut message = conversation_db
-                            .message_repo()
-                            .unwrap()
-                            .read(new_message_id)
-                            .unwrap()
-                            .unwrap();
-                        message.content = content.clone().to_string();
-                        conversation_db
-                            .message_repo()
-                            .unwrap()
-                            .update(&message)
-                            .unwrap();
-
-                        println!("Message finish: id={}", id);
-                        window_clone
-                            .emit(
-                                format!("message_{}", id).as_str(),
-                                "Tea::Event::MessageFinish",
-                            )
-                            .map_err(|e| e.to_string())
-                            .unwrap();
+/// 先起草再改进的配置，保存在 draft_refine 功能配置中，默认关闭
+///
+/// 开启后先由便宜的草稿模型回答，草稿保存为不显示的 draft 消息，再由助手的模型参考草稿给出最终的回答，
+/// 两个阶段都通过 message_{id} 事件流式发送；快速模式不使用
+#[derive(Debug, Clone, Default)]
+pub struct DraftRefineConfig {
+    pub enabled: bool,
+    // 草稿模型，model_code 为空时不起草
+    pub provider_id: Option<i64>,
+    pub model_code: String,
+    pub mode: DraftRefineMode,
+}
 
-                        let mut map = tokens.lock().await;
-                        map.remove(&new_message_id);
-                        break;
-                    }
-                }
-                Ok(None) => {
-                    let mut map = tokens.lock().await;
-                    map.remove(&new_message_id);
-                    println!("Channel closed");
-                    break;
+impl DraftRefineConfig {
+    pub fn from_config_map(
+        config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+    ) -> Self {
+        let config = config_feature_map.get("draft_refine");
+        let get_value = |key: &str| {
+            config
+                .and_then(|config| config.get(key))
+                .map(|config| config.value.trim().to_string())
+                .unwrap_or_default()
+        };
+        DraftRefineConfig {
+            enabled: get_value("enabled") == "true",
+            provider_id: get_value("provider_id").parse().ok(),
+            model_code: get_value("model_code"),
+            mode: match get_value("mode").as_str() {
+                "critique" => DraftRefineMode::Critique,
+                _ => DraftRefineMode::Refine,
+            },
+        }
+    }
+
+    // 起草使用的助手配置，没有配置草稿模型、草稿模型和助手的模型相同，
+    // 或者离线模式下草稿模型不可用时返回 None，只用助手的模型回答
+    fn draft_assistant(
+        &self,
+        app_handle: &AppHandle,
+        config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+        assistant_detail: &AssistantDetail,
+        allow_online: bool,
+    ) -> Option<AssistantDetail> {
+        let provider_id = self.provider_id.filter(|_| !self.model_code.is_empty())?;
+        let model = assistant_detail.model.first()?;
+        if model.provider_id == provider_id && model.model_code == self.model_code {
+            return None;
+        }
+        let mut draft_assistant = assistant_detail.clone();
+        draft_assistant.model[0].provider_id = provider_id;
+        draft_assistant.model[0].model_code = self.model_code.clone();
+        if let Err(e) = apply_offline_mode(
+            app_handle,
+            config_feature_map,
+            &mut draft_assistant,
+            allow_online,
+        ) {
+            println!("skip draft stage: {:?}", e);
+            return None;
+        }
+        Some(draft_assistant)
+    }
+}
+
+/// 按下快捷键时调用，在用户输入的同时完成 DNS 解析和 TLS 握手，之后快速模式的请求直接复用连接；
+/// 本地提供商没有建立连接的开销，改为提前把模型加载到内存中
+pub async fn prewarm_fast_mode(app_handle: AppHandle, assistant_id: i64) {
+    let Some(state) = app_handle.try_state::<FeatureConfigState>() else {
+        return;
+    };
+    let config = FastModeConfig::from_config_map(&*state.config_feature_map.lock().await);
+    if !config.prewarm {
+        return;
+    }
+    let (provider_id, model_code) =
+        match config.provider_id.filter(|_| !config.model_code.is_empty()) {
+            Some(provider_id) => (provider_id, config.model_code.clone()),
+            None => match get_assistant(app_handle.clone(), assistant_id) {
+                Ok(assistant_detail) if !assistant_detail.model.is_empty() => (
+                    assistant_detail.model[0].provider_id,
+                    assistant_detail.model[0].model_code.clone(),
+                ),
+                _ => return,
+            },
+        };
+    let result = LLMDatabase::new(&app_handle).and_then(|db| {
+        Ok((
+            db.get_llm_provider(provider_id)?,
+            db.get_llm_provider_config(provider_id)?,
+        ))
+    });
+    let (provider, provider_config) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            println!("prewarm provider {} error: {:?}", provider_id, e);
+            return;
+        }
+    };
+    if supports_warm_up(&provider, &provider_config) {
+        if let Err(e) =
+            preload_local_model(&app_handle, &provider, &provider_config, &model_code).await
+        {
+            println!("preload model {} error: {:?}", model_code, e);
+        }
+        return;
+    }
+    if is_offline_mode(&app_handle) {
+        return;
+    }
+    let started = Instant::now();
+    let connected = prewarm_connection(&provider, &provider_config).await;
+    println!(
+        "prewarm provider {} connected: {}, took {:?}",
+        provider.name,
+        connected,
+        started.elapsed()
+    );
+}
+
+/// 本地模型的预加载状态，通过 model_preload 事件发送给前端
+#[derive(Serialize, Clone, Debug)]
+pub struct ModelPreloadEvent {
+    pub provider_id: i64,
+    pub model_code: String,
+    // loading、loaded、failed
+    pub status: String,
+    pub duration_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+// 预加载本地提供商的模型，开始和结束时发送 model_preload 事件；正在加载或者刚加载过时跳过，返回是否发送了预加载请求
+async fn preload_local_model(
+    app_handle: &AppHandle,
+    provider: &LLMProvider,
+    provider_config: &[LLMProviderConfig],
+    model_code: &str,
+) -> Result<bool, AppError> {
+    let Some(state) = app_handle.try_state::<ModelPreloadState>() else {
+        return Ok(false);
+    };
+    if !state.begin(provider.id, model_code) {
+        return Ok(false);
+    }
+    let event = |status: &str, duration_ms: Option<u64>, error: Option<String>| ModelPreloadEvent {
+        provider_id: provider.id,
+        model_code: model_code.to_string(),
+        status: status.to_string(),
+        duration_ms,
+        error,
+    };
+    let _ = app_handle.emit("model_preload", event("loading", None, None));
+    let started = Instant::now();
+    let result = warm_up_model(provider, provider_config, model_code).await;
+    let duration_ms = Some(started.elapsed().as_millis() as u64);
+    state.finish(provider.id, model_code, result.is_ok());
+    match result {
+        Ok(()) => {
+            println!("preload model {} took {:?}", model_code, started.elapsed());
+            let _ = app_handle.emit("model_preload", event("loaded", duration_ms, None));
+            Ok(true)
+        }
+        Err(e) => {
+            let _ = app_handle.emit(
+                "model_preload",
+                event("failed", duration_ms, Some(e.to_string())),
+            );
+            Err(AppError::ProviderError(e.to_string()))
+        }
+    }
+}
+
+/// 打开询问窗口的快捷键按下时调用，助手使用本地提供商时提前加载模型，远程提供商不做处理
+pub async fn preload_assistant_model(app_handle: AppHandle, assistant_id: i64) {
+    let model = match get_assistant(app_handle.clone(), assistant_id) {
+        Ok(assistant_detail) if !assistant_detail.model.is_empty() => {
+            assistant_detail.model[0].clone()
+        }
+        _ => return,
+    };
+    let result = get_llm_db(&app_handle).and_then(|db| {
+        Ok((
+            db.get_llm_provider(model.provider_id)?,
+            db.get_llm_provider_config(model.provider_id)?,
+        ))
+    });
+    match result {
+        Ok((provider, provider_config)) if supports_warm_up(&provider, &provider_config) => {
+            if let Err(e) =
+                preload_local_model(&app_handle, &provider, &provider_config, &model.model_code)
+                    .await
+            {
+                println!("preload model {} error: {:?}", model.model_code, e);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => println!("preload provider {} error: {:?}", model.provider_id, e),
+    }
+}
+
+/// 让本地提供商（Ollama、LM Studio 等）提前把模型加载到内存中，第一次提问不用等待加载模型
+///
+/// 加载状态通过 model_preload 事件发送；远程提供商、正在加载或者几分钟内加载过的模型不发送请求，返回 false
+#[tauri::command]
+pub async fn preload_model(
+    app_handle: AppHandle,
+    model_code: String,
+    llm_provider_id: Option<i64>,
+) -> Result<bool, AppError> {
+    let model = find_model(&app_handle, &model_code, llm_provider_id)?;
+    let db = get_llm_db(&app_handle)?;
+    let provider = db.get_llm_provider(model.provider_id)?;
+    let provider_config = db.get_llm_provider_config(model.provider_id)?;
+    if !supports_warm_up(&provider, &provider_config) {
+        return Ok(false);
+    }
+    preload_local_model(&app_handle, &provider, &provider_config, &model.model_code).await
+}
+
+/// 按录制时的节奏把一条回答的流式内容重新发送给窗口，不请求提供商也不修改消息，用于调试前端渲染问题
+///
+/// 开启生成调试后生成的流式回答才有录制数据，speed 为回放速度倍数，默认 1。回放开始和结束时分别发送
+/// message_replay_started 和 message_replay_finished 事件，中间的内容和生成时一样通过 message_{id} 事件发送
+#[tauri::command]
+pub async fn replay_generation(
+    app_handle: AppHandle,
+    window: Window,
+    message_id: i64,
+    speed: Option<f64>,
+) -> Result<(), AppError> {
+    let speed = speed.unwrap_or(1.0);
+    if !speed.is_finite() || speed <= 0.0 {
+        return Err(AppError::UnknownError("回放速度需要大于 0".to_string()));
+    }
+    let path = generation_recording_path(&app_handle, message_id)
+        .filter(|path| path.exists())
+        .ok_or(AppError::UnknownError(
+            "这条回答没有录制数据，请开启生成调试后重新生成".to_string(),
+        ))?;
+
+    // 和生成时一样分离思考过程，按助手的 show_reasoning 决定是否发送
+    let assistant_id = ConversationDatabase::new(&app_handle)?
+        .message_repo()?
+        .read(message_id)?
+        .and_then(|message| message.assistant_id);
+    let show = match assistant_id {
+        Some(assistant_id) => AssistantDatabase::new(&app_handle)?
+            .get_assistant_model_configs(assistant_id)?
+            .into_iter()
+            .find(|config| config.name == "show_reasoning")
+            .and_then(|config| config.value)
+            .map_or(true, |value| value != "false"),
+        None => true,
+    };
+    let reasoning_filter = ReasoningFilter::load(&app_handle, show).await;
+
+    let (tx, mut rx) = mpsc::channel(100);
+    let _ = window.emit("message_replay_started", message_id);
+    let path = path.to_string_lossy().to_string();
+    let replay = replay_fixture(&path, speed, message_id, tx, CancellationToken::new());
+    let forward = async {
+        let mut last_reasoning = (String::new(), false);
+        while let Some((id, content, done)) = rx.recv().await {
+            if content.starts_with(FINISH_REASON_EVENT_PREFIX)
+                || content.starts_with(TOOL_CALLS_EVENT_PREFIX)
+            {
+                continue;
+            }
+            let split = reasoning_filter.split(&content, done);
+            let reasoning_state = (split.reasoning.clone(), split.thinking);
+            if reasoning_filter.show && reasoning_state != last_reasoning {
+                let _ = window.emit(
+                    "message_reasoning",
+                    MessageReasoningEvent {
+                        message_id: id,
+                        content: split.reasoning.clone(),
+                        thinking: split.thinking,
+                    },
+                );
+                last_reasoning = reasoning_state;
+            }
+            let _ = window.emit(format!("message_{}", id).as_str(), split.answer);
+        }
+    };
+    let (result, _) = tokio::join!(replay, forward);
+    let _ = window.emit("message_replay_finished", message_id);
+    result.map_err(|e| AppError::UnknownError(e.to_string()))
+}
+
+// 继续生成时追加给模型的提示，让模型从截断处接着输出
+const CONTINUE_GENERATION_PROMPT: &str =
+    "你的上一条回复因为长度限制被截断了，请从截断的地方继续输出，不要重复已经输出的内容，也不要添加任何解释。";
+
+#[tauri::command]
+pub async fn continue_generation(
+    app_handle: AppHandle,
+    feature_config_state: State<'_, FeatureConfigState>,
+    message_token_manager: State<'_, MessageTokenManager>,
+    window: Window,
+    message_id: i64,
+    allow_online: Option<bool>,
+) -> Result<AiResponse, AppError> {
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    let message = db
+        .message_repo()?
+        .read(message_id)?
+        .ok_or(AppError::DatabaseError("未找到消息".to_string()))?;
+    if message.role() != Some(Role::Assistant) {
+        return Err(AppError::UnknownError("只能继续生成助手的回复".to_string()));
+    }
+    if message_token_manager.exist(message_id).await {
+        return Err(AppError::UnknownError("该消息正在生成中".to_string()));
+    }
+
+    let conversation_id = message.conversation_id;
+    let conversation = db
+        .conversation_repo()?
+        .read(conversation_id)?
+        .ok_or(AppError::DatabaseError("未找到对话".to_string()))?;
+    let all_messages = db
+        .message_repo()?
+        .list_by_conversation_id(conversation_id)?;
+    let quotes = db.quote_repo()?.list_by_conversation_id(conversation_id)?;
+
+    // 群聊中由原来回答这条消息的助手继续回答
+    let assistant_id = message
+        .assistant_id
+        .or(conversation.assistant_id)
+        .ok_or(AppError::DatabaseError("对话没有关联助手".to_string()))?;
+    let assistant_names = assistant_names(&app_handle).await;
+    let config_feature_map = feature_config_state.config_feature_map.lock().await.clone();
+    let assistant_detail = request_assistant(
+        &app_handle,
+        assistant_id,
+        None,
+        false,
+        allow_online.unwrap_or(false),
+        &config_feature_map,
+    )?;
+
+    // 重新生成的消息挂在原消息下，上下文取原消息之前的内容
+    let root_message_id = message.parent_id.unwrap_or(message.id);
+    let citation_sources = question_citation_sources(&all_messages, root_message_id);
+    let mut latest_children: HashMap<i64, Message> = HashMap::new();
+    for (m, _) in all_messages.iter() {
+        if let Some(parent_id) = m.parent_id {
+            latest_children
+                .entry(parent_id)
+                .and_modify(|e| {
+                    if m.id > e.id {
+                        *e = m.clone();
+                    }
+                })
+                .or_insert(m.clone());
+        }
+    }
+    let mut attachment_map: HashMap<i64, Vec<MessageAttachment>> = HashMap::new();
+    for (m, attachment) in all_messages.iter() {
+        if let Some(attachment) = attachment {
+            attachment_map
+                .entry(m.id)
+                .or_default()
+                .push(attachment.clone());
+        }
+    }
+    let injection_guard = InjectionGuard::from_config_map(&config_feature_map);
+    let mut message_ids = HashSet::new();
+    let history_message_list = all_messages
+        .iter()
+        .filter(|(m, _)| m.parent_id.is_none() && m.id < root_message_id)
+        .filter(|(m, _)| message_ids.insert(m.id))
+        .map(|(m, _)| {
+            let final_message = latest_children.get(&m.id).unwrap_or(m);
+            let attachments = attachment_map
+                .get(&final_message.id)
+                .cloned()
+                .unwrap_or_default();
+            speaker_chat_message(
+                final_message,
+                attachments,
+                assistant_id,
+                &assistant_names,
+                &quotes,
+                &injection_guard,
+            )
+            .map(|message| (message, m.pinned || final_message.pinned))
+        })
+        .collect::<Result<Vec<_>, AppError>>()?;
+    let mut init_message_list = apply_context_window(
+        &app_handle,
+        conversation_id,
+        history_message_list,
+        &config_feature_map,
+    );
+    append_assistant_prompts(
+        &app_handle,
+        assistant_detail.assistant.id,
+        &last_user_prompt(&init_message_list),
+        &config_feature_map,
+        &mut init_message_list,
+    );
+    let partial_content = message.content.clone();
+    // 超时停止的消息也可以继续生成，继续生成时重新计时
+    if message.timed_out {
+        db.message_repo()?.update(&Message {
+            timed_out: false,
+            ..message.clone()
+        })?;
+    }
+    init_message_list.push(ChatMessage::new(Role::Assistant, partial_content.clone()));
+    init_message_list.push(ChatMessage::new(Role::User, CONTINUE_GENERATION_PROMPT));
+    println!(
+        "continue_generation init_message_list: {} messages",
+        init_message_list.len()
+    );
+
+    let cancel_token = CancellationToken::new();
+    message_token_manager
+        .store_token(message_id, cancel_token.clone())
+        .await;
+
+    let task = GenerationTask {
+        app_handle: app_handle.clone(),
+        assistant_detail,
+        message_id,
+        init_message_list,
+        override_model_config: None,
+        // 出错时保留已有内容，消息仍然是截断状态，可以再次继续生成
+        error_as_content: false,
+        cache_ttl: None,
+    };
+    let watchdog = StallWatchdog::new(&config_feature_map, task.is_stream());
+    spawn_generation(
+        task,
+        window,
+        watchdog,
+        message_token_manager.get_tokens(),
+        message_token_manager
+            .conversation_lock(conversation_id)
+            .await,
+        cancel_token,
+        ReceiveOptions {
+            content_prefix: partial_content,
+            is_truncated: true,
+            citation_sources,
+            ..Default::default()
+        },
+    );
+
+    Ok(AiResponse {
+        conversation_id,
+        add_message_id: message_id,
+        request_prompt_result_with_context: String::new(),
+        attachment_truncation: vec![],
+        draft_message_id: None,
+    })
+}
+
+/// 启动时发现的上次异常退出时没有生成完的回答，通过 get_interrupted_messages 命令返回给界面
+pub struct InterruptedMessagesState(pub Vec<InterruptedMessage>);
+
+/// 启动时在开始任何生成之前调用，把上次异常退出时正在生成的回答标记为已中断，
+/// 界面不会一直显示生成中，发送 interrupted_messages_recovered 事件
+pub fn recover_interrupted_messages(app_handle: &AppHandle) -> Vec<InterruptedMessage> {
+    let metadata = GenerationMetadata {
+        finish_reason: Some("interrupted".to_string()),
+        ..Default::default()
+    };
+    let result = ConversationDatabase::new(app_handle)
+        .map_err(AppError::from)
+        .and_then(|db| db.message_repo())
+        .and_then(|repo| {
+            let metadata = serde_json::to_string(&metadata).unwrap_or_default();
+            Ok(repo.mark_incomplete_interrupted(&metadata)?)
+        });
+    match result {
+        Ok(messages) => {
+            if !messages.is_empty() {
+                println!("recovered {} interrupted messages", messages.len());
+                let _ = app_handle.emit("interrupted_messages_recovered", &messages);
+            }
+            messages
+        }
+        Err(e) => {
+            println!("recover interrupted messages error: {:?}", e);
+            vec![]
+        }
+    }
+}
+
+#[tauri::command]
+pub fn get_interrupted_messages(
+    state: State<'_, InterruptedMessagesState>,
+) -> Vec<InterruptedMessage> {
+    state.0.clone()
+}
+
+/// 继续上次异常退出时中断的回答：已经有部分内容时接着生成，没有内容时重新生成
+#[tauri::command]
+pub async fn resume_incomplete_message(
+    app_handle: AppHandle,
+    feature_config_state: State<'_, FeatureConfigState>,
+    message_token_manager: State<'_, MessageTokenManager>,
+    window: Window,
+    message_id: i64,
+    allow_online: Option<bool>,
+) -> Result<AiResponse, AppError> {
+    let message = ConversationDatabase::new(&app_handle)?
+        .message_repo()?
+        .read(message_id)?
+        .ok_or(AppError::DatabaseError("未找到消息".to_string()))?;
+    if message.content.trim().is_empty() {
+        regenerate_message(
+            app_handle,
+            feature_config_state,
+            message_token_manager,
+            window,
+            message_id,
+            None,
+            allow_online.unwrap_or(false),
+        )
+        .await
+    } else {
+        continue_generation(
+            app_handle,
+            feature_config_state,
+            message_token_manager,
+            window,
+            message_id,
+            allow_online,
+        )
+        .await
+    }
+}
+
+// 超过 stall_timeout 秒没有收到新的数据就认为生成卡住了
+const DEFAULT_STALL_TIMEOUT_SECS: u64 = 60;
+// 非流式请求在完成之前不会返回任何数据，至少等待这么久才认为卡住
+const NON_STREAM_STALL_TIMEOUT_SECS: u64 = 600;
+
+// 流式响应中断后默认最多续写的次数
+const DEFAULT_STREAM_MAX_RESUMES: u32 = 2;
+
+// 一次回答中最多执行几轮工具调用，超过后把最后一次的回答作为结果
+const MAX_TOOL_ROUNDS: usize = 5;
+
+// resuming 为 false 时生成已经结束，界面可以提示用户通过 continue_generation 继续生成
+#[derive(Serialize, Clone, Debug)]
+pub struct StreamInterruptedEvent {
+    pub message_id: i64,
+    pub error: String,
+    pub resuming: bool,
+    pub resume_count: u32,
+}
+
+// 回答中思考过程的标签和是否在界面上显示，show_reasoning 为助手的配置，默认显示
+struct ReasoningFilter {
+    tags: Vec<String>,
+    show: bool,
+}
+
+impl ReasoningFilter {
+    async fn new(task: &GenerationTask) -> Self {
+        let show = task
+            .model_config_value("show_reasoning")
+            .map_or(true, |value| value != "false");
+        ReasoningFilter::load(&task.app_handle, show).await
+    }
+
+    async fn load(app_handle: &AppHandle, show: bool) -> Self {
+        let tags = match app_handle.try_state::<FeatureConfigState>() {
+            Some(state) => state
+                .config_feature_map
+                .lock()
+                .await
+                .get("reasoning")
+                .and_then(|config| config.get("tags"))
+                .map(|config| config.value.clone()),
+            None => None,
+        };
+        ReasoningFilter {
+            tags: parse_reasoning_tags(tags.as_deref().unwrap_or(DEFAULT_REASONING_TAGS)),
+            show,
+        }
+    }
+
+    fn split(&self, content: &str, complete: bool) -> ReasoningSplit {
+        split_reasoning(content, &self.tags, complete)
+    }
+}
+
+// thinking 为 true 表示思考还没有结束，content 是目前为止完整的思考过程
+#[derive(Serialize, Clone, Debug)]
+pub struct MessageReasoningEvent {
+    pub message_id: i64,
+    pub content: String,
+    pub thinking: bool,
+}
+
+// 朗读时按句子合成语音，index 重新从 0 开始表示内容被重新生成
+#[derive(Serialize, Clone, Debug)]
+pub struct MessageSentenceEvent {
+    pub message_id: i64,
+    pub index: usize,
+    pub text: String,
+}
+
+fn emit_sentences(window: &Window, message_id: i64, sentences: Vec<Sentence>) {
+    for sentence in sentences {
+        let _ = window.emit(
+            "message_sentence",
+            MessageSentenceEvent {
+                message_id,
+                index: sentence.index,
+                text: sentence.text,
+            },
+        );
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct StalledEvent {
+    pub message_id: i64,
+    pub retrying: bool,
+    pub retry_count: u32,
+}
+
+// 生成过程中提供商上报的 token 用量，用于实时显示
+#[derive(Serialize, Clone, Debug)]
+pub struct UsageUpdate {
+    pub message_id: i64,
+    pub prompt_tokens: Option<u32>,
+    pub completion_tokens: Option<u32>,
+    pub total_tokens: Option<u32>,
+}
+
+// 服务端工具的执行进度，前端在回答中显示“正在运行代码”等状态
+#[derive(Serialize, Clone, Debug)]
+pub struct ToolProgressUpdate {
+    pub message_id: i64,
+    pub item_id: String,
+    pub tool: String,
+    pub status: String,
+}
+
+// 生成的停滞检测配置，读取 generation 的 stall_timeout、stall_auto_retry、stall_max_retries，
+// 以及流式响应中断后最多续写的次数 stream_max_resumes
+struct StallWatchdog {
+    timeout: Duration,
+    auto_retry: bool,
+    max_retries: u32,
+    max_resumes: u32,
+}
+
+impl StallWatchdog {
+    fn new(
+        config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+        stream: bool,
+    ) -> Self {
+        let config = config_feature_map.get("generation");
+        let get_value = |key: &str| {
+            config
+                .and_then(|config| config.get(key))
+                .map(|config| config.value.clone())
+        };
+        let stall_timeout = get_value("stall_timeout")
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_STALL_TIMEOUT_SECS)
+            .max(1);
+        let timeout = if stream {
+            stall_timeout
+        } else {
+            stall_timeout.max(NON_STREAM_STALL_TIMEOUT_SECS)
+        };
+        StallWatchdog {
+            timeout: Duration::from_secs(timeout),
+            auto_retry: get_value("stall_auto_retry")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(false),
+            max_retries: get_value("stall_max_retries")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(1),
+            max_resumes: get_value("stream_max_resumes")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_STREAM_MAX_RESUMES),
+        }
+    }
+}
+
+// 一次生成请求需要的全部信息，停滞后自动重试时用它重新发起请求
+#[derive(Clone)]
+struct GenerationTask {
+    app_handle: AppHandle,
+    assistant_detail: AssistantDetail,
+    message_id: i64,
+    init_message_list: Vec<ChatMessage>,
+    override_model_config: Option<Vec<(String, serde_json::Value)>>,
+    // 出错时是否把错误信息作为消息内容
+    error_as_content: bool,
+    // 使用回答缓存时缓存的有效期
+    cache_ttl: Option<Duration>,
+}
+
+impl GenerationTask {
+    fn is_stream(&self) -> bool {
+        self.assistant_detail
+            .model_configs
+            .iter()
+            .find(|config| config.name == "stream")
+            .and_then(|config| config.value.as_ref())
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false)
+    }
+
+    // temperature 为 0 时相同的请求总是得到相同的回答
+    fn is_deterministic(&self) -> bool {
+        self.model_config_value("temperature")
+            .and_then(|v| v.trim().parse::<f64>().ok())
+            .is_some_and(|temperature| temperature == 0.0)
+    }
+
+    // 本次请求实际使用的模型参数，临时覆盖的参数优先
+    // 回答所在的对话是私密对话或者无痕对话，查询出错时按受保护处理
+    fn in_protected_conversation(&self) -> bool {
+        let (Some(conversation_id), Some(state)) = (
+            message_conversation_id(&self.app_handle, self.message_id),
+            self.app_handle.try_state::<PrivateConversationState>(),
+        ) else {
+            return true;
+        };
+        state.is_private(conversation_id) || state.is_incognito(conversation_id)
+    }
+
+    fn model_config_value(&self, name: &str) -> Option<String> {
+        self.override_model_config
+            .as_ref()
+            .and_then(|configs| configs.iter().find(|(key, _)| key == name))
+            .map(|(_, value)| match value {
+                serde_json::Value::String(value) => value.clone(),
+                value => value.to_string(),
+            })
+            .or_else(|| {
+                self.assistant_detail
+                    .model_configs
+                    .iter()
+                    .find(|config| config.name == name)
+                    .and_then(|config| config.value.clone())
+            })
+    }
+
+    // 带图片的消息中置顶的消息，图片超过模型上限时优先保留
+    fn pinned_message_ids(&self) -> HashSet<i64> {
+        let message_ids: HashSet<i64> = self
+            .init_message_list
+            .iter()
+            .flat_map(|message| message.images())
+            .filter_map(|a| a.message_id)
+            .collect();
+        if message_ids.is_empty() {
+            return message_ids;
+        }
+        let Some(message_repo) = ConversationDatabase::new(&self.app_handle)
+            .ok()
+            .and_then(|db| db.message_repo().ok())
+        else {
+            return HashSet::new();
+        };
+        message_ids
+            .into_iter()
+            .filter(|message_id| {
+                matches!(message_repo.read(*message_id), Ok(Some(message)) if message.pinned)
+            })
+            .collect()
+    }
+
+    // 生成信息的初始值，耗时和结束原因在生成过程中补充
+    fn generation_metadata(&self) -> GenerationMetadata {
+        let model = self.assistant_detail.model.first();
+        let provider = model.and_then(|model| {
+            LLMDatabase::new(&self.app_handle)
+                .ok()?
+                .get_llm_provider(model.provider_id)
+                .ok()
+        });
+        GenerationMetadata {
+            provider: provider.as_ref().map(|provider| provider.name.clone()),
+            api_type: provider.map(|provider| provider.api_type),
+            model_code: model.map(|model| model.model_code.clone()),
+            temperature: self
+                .model_config_value("temperature")
+                .and_then(|v| v.trim().parse().ok()),
+            stream: self.is_stream(),
+            ..Default::default()
+        }
+    }
+
+    // 助手配置的最长生成时间，单位秒，不配置或者为 0 时不限制
+    fn max_generation_time(&self) -> Option<Duration> {
+        self.assistant_detail
+            .model_configs
+            .iter()
+            .find(|config| config.name == "max_generation_time")
+            .and_then(|config| config.value.as_ref())
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    fn spawn(
+        &self,
+        tx: mpsc::Sender<(i64, String, bool)>,
+        cancel_token: CancellationToken,
+        tokens: Arc<Mutex<HashMap<i64, CancellationToken>>>,
+    ) {
+        let task = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = task.run(tx.clone(), cancel_token.clone()).await {
+                eprintln!("Chat error: {}", e);
+                // 前端收到 egress_blocked 事件后提示用户，可以一键把域名加入出站允许列表
+                if let Some(host) = egress_blocked_host(&e) {
+                    let _ = task.app_handle.emit("egress_blocked", host);
+                }
+                // 错误分类和处理建议，前端据此提示用户检查 API Key、充值或者修改提问
+                let _ = task.app_handle.emit(
+                    "provider_error",
+                    ProviderErrorEvent::from_error(task.message_id, &e),
+                );
+                // 被取消的请求可能已经由重试的请求接替，不能再清理状态
+                if cancel_token.is_cancelled() {
+                    return;
                 }
-                Err(err) => {
-                    let mut map = tokens.lock().await;
-                    map.remove(&new_message_id);
-                    println!("Timeout waiting for data from channel: {:?}", err);
+                // 流式请求的错误交给接收方处理，已经生成了内容时可以尝试续写
+                if task.is_stream() {
+                    let _ = tx
+                        .send((
+                            task.message_id,
+                            stream_interrupted_event(&e.to_string()),
+                            false,
+                        ))
+                        .await;
+                    return;
+                }
+                tokens.lock().await.remove(&task.message_id);
+                let content = if task.error_as_content {
+                    format!("Chat error: {}", e)
+                } else {
+                    String::new()
+                };
+                let _ = tx.send((task.message_id, content, true)).await;
+            }
+        });
+    }
+
+    // 按模型列表依次请求，模型不存在或者已经下线时换用提供商配置的下一个备用模型
+    async fn run(
+        &self,
+        tx: mpsc::Sender<(i64, String, bool)>,
+        cancel_token: CancellationToken,
+    ) -> Result<(), Error> {
+        let db = LLMDatabase::new(&self.app_handle)
+            .map_err(Error::from)
+            .context("Failed to create LLMDatabase")?;
+        let provider_id = &self.assistant_detail.model[0].provider_id;
+        let model_code = &self.assistant_detail.model[0].model_code;
+        let provider_config = db
+            .get_llm_provider_config(*provider_id)
+            .context("Failed to get LLM provider config")?;
+        let chain = model_chain(model_code, &provider_config);
+        audit_log::record(
+            &self.app_handle,
+            "prompt",
+            message_conversation_id(&self.app_handle, self.message_id),
+            Some(self.message_id),
+            json!({
+                "provider_id": provider_id,
+                "model_code": model_code,
+                "messages": self
+                    .init_message_list
+                    .iter()
+                    .map(|message| {
+                        json!({ "role": message.role.to_string(), "content": message.text() })
+                    })
+                    .collect::<Vec<_>>(),
+            }),
+        );
+
+        let mut last_error = None;
+        for (index, code) in chain.iter().enumerate() {
+            if cancel_token.is_cancelled() {
+                break;
+            }
+            let model_detail = match db.get_llm_model_detail(provider_id, code) {
+                Ok(model_detail) => model_detail,
+                Err(e) => {
+                    println!("model {} not found: {:?}", code, e);
+                    last_error = Some(Error::from(e).context("Failed to get LLM model detail"));
+                    continue;
+                }
+            };
+            // 直接使用 IP 地址的提供商不经过共用客户端的域名解析，请求前检查出站允许列表
+            if let Some(endpoint) = provider_endpoint(&model_detail.provider, &model_detail.configs)
+            {
+                check_egress(&endpoint)?;
+            }
+            if index > 0 || code != model_code {
+                println!("fallback to model {} for message {}", code, self.message_id);
+                tx.send((self.message_id, served_model_event(code), false))
+                    .await?;
+            }
+            match self
+                .run_with_model(model_detail, tx.clone(), cancel_token.clone())
+                .await
+            {
+                Err(e) if is_model_unavailable(&e) => {
+                    println!("model {} unavailable: {}", code, e);
+                    last_error = Some(e);
+                }
+                result => return result,
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No available model")))
+    }
+
+    // 按模型能力处理图片：支持视觉时控制图片数量，不支持时替换成 OCR 识别出的文字，
+    // 同时返回图片数量上限和没有发送的图片
+    async fn request_messages(
+        &self,
+        model_detail: &ModelDetail,
+    ) -> (Vec<ChatMessage>, usize, Vec<OmittedImage>) {
+        let capability =
+            get_model_capability(&model_detail.provider.api_type, &model_detail.model.code);
+        if !capability.vision_support {
+            let message_list =
+                apply_image_ocr(&self.app_handle, self.init_message_list.clone()).await;
+            return (message_list, capability.max_images, vec![]);
+        }
+        let mut message_list = self.init_message_list.clone();
+        let max_images = self
+            .model_config_value("max_images")
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(capability.max_images);
+        let omitted = select_images(&mut message_list, max_images, &self.pinned_message_ids());
+        (message_list, max_images, omitted)
+    }
+
+    // 发给提供商的模型参数：助手的参数加上实际使用的模型，再用临时覆盖的参数替换
+    fn request_model_config(&self, model_detail: &ModelDetail) -> Vec<AssistantModelConfig> {
+        let assistant_id = self.assistant_detail.assistant.id;
+        let mut model_config = self.assistant_detail.model_configs.clone();
+        model_config.push(AssistantModelConfig {
+            id: 0,
+            assistant_id,
+            assistant_model_id: model_detail.model.id,
+            name: "model".to_string(),
+            value: Some(model_detail.model.code.clone()),
+            value_type: "string".to_string(),
+        });
+
+        if let Some(override_configs) = &self.override_model_config {
+            for (key, value) in override_configs {
+                let value_type = match value {
+                    serde_json::Value::String(_) => "string",
+                    serde_json::Value::Number(_) => "number",
+                    serde_json::Value::Bool(_) => "boolean",
+                    serde_json::Value::Array(_) => "array",
+                    serde_json::Value::Object(_) => "object",
+                    serde_json::Value::Null => "null",
+                }
+                .to_string();
+
+                let value_str = value.to_string();
+
+                if let Some(existing_config) = model_config.iter_mut().find(|c| c.name == *key) {
+                    existing_config.value = Some(value_str);
+                    existing_config.value_type = value_type;
+                } else {
+                    model_config.push(AssistantModelConfig {
+                        id: 0,
+                        assistant_id,
+                        assistant_model_id: model_detail.model.id,
+                        name: key.clone(),
+                        value: Some(value_str),
+                        value_type,
+                    });
+                }
+            }
+        }
+        // 启用的技能需要的工具和助手配置的工具一起提供给模型
+        let skill_tools = skill_tools(&self.app_handle, assistant_id);
+        if !skill_tools.is_empty() {
+            let mut tools = offered_tools(&model_config);
+            for tool in skill_tools {
+                if !tools.contains(&tool) {
+                    tools.push(tool);
+                }
+            }
+            model_config.retain(|config| config.name != TOOLS_CONFIG);
+            model_config.push(AssistantModelConfig {
+                id: 0,
+                assistant_id,
+                assistant_model_id: model_detail.model.id,
+                name: TOOLS_CONFIG.to_string(),
+                value: Some(tools.join(",")),
+                value_type: "string".to_string(),
+            });
+        }
+        // 全局简洁模式下所有助手都使用简短的回答长度，优先于助手和临时覆盖的配置
+        if is_concise_mode(&self.app_handle) {
+            model_config.retain(|config| config.name != RESPONSE_LENGTH_CONFIG);
+            model_config.push(AssistantModelConfig {
+                id: 0,
+                assistant_id,
+                assistant_model_id: model_detail.model.id,
+                name: RESPONSE_LENGTH_CONFIG.to_string(),
+                value: Some(ResponseLength::Short.as_str().to_string()),
+                value_type: "string".to_string(),
+            });
+        }
+        model_config
+    }
+
+    fn cached_response(&self, cache_key: Option<&str>) -> Option<CachedResponse> {
+        let cache = self.app_handle.try_state::<ResponseCacheState>()?;
+        cache.get(cache_key?, self.cache_ttl?)
+    }
+
+    // 正常结束的回答写入缓存，被取消、因为长度限制截断或者内容为空的回答不缓存
+    fn cache_response(
+        &self,
+        cache_key: String,
+        response: CachedResponse,
+        cancel_token: &CancellationToken,
+    ) {
+        if cancel_token.is_cancelled()
+            || response.content.is_empty()
+            || response
+                .finish_reason
+                .as_deref()
+                .is_some_and(is_truncated_finish_reason)
+        {
+            return;
+        }
+        if let Some(cache) = self.app_handle.try_state::<ResponseCacheState>() {
+            cache.insert(cache_key, response);
+        }
+    }
+
+    async fn run_with_model(
+        &self,
+        mut model_detail: ModelDetail,
+        tx: mpsc::Sender<(i64, String, bool)>,
+        cancel_token: CancellationToken,
+    ) -> Result<(), Error> {
+        println!("model detail : {:#?}", model_detail);
+
+        let (mut message_list, max_images, omitted) = self.request_messages(&model_detail).await;
+        if !omitted.is_empty() {
+            println!(
+                "message {} omitted {} images, max_images {}",
+                self.message_id,
+                omitted.len(),
+                max_images
+            );
+            let _ = self.app_handle.emit(
+                "message_images_omitted",
+                ImagesOmittedEvent {
+                    message_id: self.message_id,
+                    max_images,
+                    images: omitted,
+                },
+            );
+        }
+        let model_config = self.request_model_config(&model_detail);
+        // 回答长度的要求只加在对话请求中
+        append_response_length_instruction(&mut message_list, &model_config);
+
+        let cache_key = self.cache_ttl.map(|_| {
+            response_cache_key(
+                model_detail.provider.id,
+                &model_detail.model.code,
+                &message_list,
+                &model_config,
+            )
+        });
+        if let Some(cached) = self.cached_response(cache_key.as_deref()) {
+            println!("message {} use cached response", self.message_id);
+            if let Some(finish_reason) = &cached.finish_reason {
+                tx.send((self.message_id, finish_reason_event(finish_reason), false))
+                    .await?;
+            }
+            tx.send((self.message_id, cached.content, true)).await?;
+            return Ok(());
+        }
+
+        // 开启生成调试时录制流式回答的原始数据，之后可以通过 replay_generation 回放；
+        // 录制文件是明文，私密对话和无痕对话的回答不录制
+        if self.is_stream()
+            && is_generation_debug(&self.app_handle)
+            && !self.in_protected_conversation()
+        {
+            if let Some(path) = generation_recording_path(&self.app_handle, self.message_id) {
+                model_detail.configs.push(LLMProviderConfig {
+                    id: 0,
+                    name: "record_fixture".to_string(),
+                    llm_provider_id: model_detail.provider.id,
+                    value: path.to_string_lossy().to_string(),
+                    append_location: "header".to_string(),
+                    is_addition: false,
+                });
+            }
+        }
+        let provider = get_provider(model_detail.provider, model_detail.configs)?;
+
+        if self.is_stream() {
+            let Some(cache_key) = cache_key else {
+                self.chat_stream_with_tools(
+                    &provider,
+                    message_list,
+                    model_config,
+                    tx,
+                    cancel_token,
+                )
+                .await?;
+                return Ok(());
+            };
+            // 转发提供商发送的内容，同时记下完整的回答和结束原因，正常结束后写入缓存
+            let (cache_tx, mut cache_rx) = mpsc::channel::<(i64, String, bool)>(100);
+            let forward = tokio::spawn(async move {
+                let mut content = None;
+                let mut finish_reason = None;
+                while let Some(item) = cache_rx.recv().await {
+                    if let Some(reason) = item.1.strip_prefix(FINISH_REASON_EVENT_PREFIX) {
+                        finish_reason = Some(reason.to_string());
+                    } else if item.2 {
+                        content = Some(item.1.clone());
+                    }
+                    if tx.send(item).await.is_err() {
+                        break;
+                    }
+                }
+                content.map(|content| CachedResponse {
+                    content,
+                    finish_reason,
+                })
+            });
+            let result = self
+                .chat_stream_with_tools(
+                    &provider,
+                    message_list,
+                    model_config,
+                    cache_tx,
+                    cancel_token.clone(),
+                )
+                .await;
+            let response = forward.await?;
+            result?;
+            if let Some(response) = response {
+                self.cache_response(cache_key, response, &cancel_token);
+            }
+        } else {
+            let conversation_db = ConversationDatabase::new(&self.app_handle)?;
+            conversation_db
+                .message_repo()?
+                .update_start_time(self.message_id)?;
+            let response = self
+                .chat_with_tools(&provider, message_list, model_config, &cancel_token)
+                .await?;
+
+            if let Some(cache_key) = cache_key {
+                self.cache_response(
+                    cache_key,
+                    CachedResponse {
+                        content: response.content.clone(),
+                        finish_reason: response.finish_reason.clone(),
+                    },
+                    &cancel_token,
+                );
+            }
+
+            // 和流式响应一样通过事件上报用量，保存到生成信息中用于统计费用
+            if let Some(usage) = &response.usage {
+                tx.send((self.message_id, usage_event(usage), false))
+                    .await?;
+            }
+            if let Some(finish_reason) = &response.finish_reason {
+                tx.send((self.message_id, finish_reason_event(finish_reason), false))
+                    .await?;
+            }
+            tx.send((self.message_id, response.content, true)).await?;
+        }
+        Ok(())
+    }
+
+    // 非流式请求，模型请求调用工具时执行工具，带上调用和结果重新请求，
+    // 返回的回答包含之前几轮中模型输出的文字
+    async fn chat_with_tools(
+        &self,
+        provider: &Arc<dyn ModelProvider>,
+        mut messages: Vec<ChatMessage>,
+        model_config: Vec<AssistantModelConfig>,
+        cancel_token: &CancellationToken,
+    ) -> Result<ChatResponse, Error> {
+        let offered = offered_tools(&model_config);
+        let mut previous = String::new();
+        let mut round = 0;
+        loop {
+            let mut response = provider
+                .chat(
+                    self.message_id,
+                    messages.clone(),
+                    model_config.clone(),
+                    cancel_token.clone(),
+                )
+                .await
+                .context("Failed to chat")?;
+            if response.tool_calls.is_empty()
+                || round == MAX_TOOL_ROUNDS
+                || cancel_token.is_cancelled()
+            {
+                response.content = join_tool_round_text(&previous, &response.content);
+                return Ok(response);
+            }
+            messages.push(tool_use_message(&response.content, &response.tool_calls));
+            messages.extend(self.run_tool_calls(&response.tool_calls, &offered).await);
+            previous = join_tool_round_text(&previous, &response.content);
+            round += 1;
+        }
+    }
+
+    // 流式请求，模型请求调用工具时先不转发结束标记，执行工具后带上调用和结果继续请求，
+    // 转发的内容前面加上之前几轮中模型输出的文字
+    async fn chat_stream_with_tools(
+        &self,
+        provider: &Arc<dyn ModelProvider>,
+        mut messages: Vec<ChatMessage>,
+        model_config: Vec<AssistantModelConfig>,
+        tx: mpsc::Sender<(i64, String, bool)>,
+        cancel_token: CancellationToken,
+    ) -> Result<(), Error> {
+        let offered = offered_tools(&model_config);
+        if offered.is_empty() {
+            return provider
+                .chat_stream(self.message_id, messages, model_config, tx, cancel_token)
+                .await;
+        }
+        let mut previous = String::new();
+        for round in 0..=MAX_TOOL_ROUNDS {
+            let (round_tx, mut round_rx) = mpsc::channel::<(i64, String, bool)>(100);
+            let forward_tx = tx.clone();
+            let forward_previous = previous.clone();
+            let forward = tokio::spawn(async move {
+                let mut tool_calls = Vec::new();
+                let mut done = None;
+                while let Some((message_id, content, is_done)) = round_rx.recv().await {
+                    if let Some(calls) = content.strip_prefix(TOOL_CALLS_EVENT_PREFIX) {
+                        tool_calls =
+                            serde_json::from_str::<Vec<ToolCall>>(calls).unwrap_or_default();
+                        continue;
+                    }
+                    if is_done {
+                        done = Some(content);
+                        continue;
+                    }
+                    let content = if content.starts_with(EVENT_PREFIX) {
+                        content
+                    } else {
+                        join_tool_round_text(&forward_previous, &content)
+                    };
+                    if forward_tx.send((message_id, content, false)).await.is_err() {
+                        break;
+                    }
+                }
+                (tool_calls, done)
+            });
+            let result = provider
+                .chat_stream(
+                    self.message_id,
+                    messages.clone(),
+                    model_config.clone(),
+                    round_tx,
+                    cancel_token.clone(),
+                )
+                .await;
+            let (tool_calls, done) = forward.await?;
+            result?;
+            // 没有结束标记时是连接中断，由接收方处理
+            let Some(content) = done else {
+                return Ok(());
+            };
+            if tool_calls.is_empty() || round == MAX_TOOL_ROUNDS || cancel_token.is_cancelled() {
+                tx.send((
+                    self.message_id,
+                    join_tool_round_text(&previous, &content),
+                    true,
+                ))
+                .await?;
+                return Ok(());
+            }
+            messages.push(tool_use_message(&content, &tool_calls));
+            messages.extend(self.run_tool_calls(&tool_calls, &offered).await);
+            previous = join_tool_round_text(&previous, &content);
+        }
+        Ok(())
+    }
+
+    // 依次执行模型请求的工具，调用记录和结果的提示注入检查由 call_tool 完成，
+    // 没有提供给模型的工具不执行
+    async fn run_tool_calls(
+        &self,
+        tool_calls: &[ToolCall],
+        offered: &[String],
+    ) -> Vec<ChatMessage> {
+        let conversation_id = ConversationDatabase::new(&self.app_handle)
+            .ok()
+            .and_then(|db| db.message_repo().ok())
+            .and_then(|repo| repo.read(self.message_id).ok().flatten())
+            .map(|message| message.conversation_id);
+        let mut results = Vec::new();
+        for call in tool_calls {
+            let result = if offered.contains(&call.name) {
+                call_tool(
+                    self.app_handle.clone(),
+                    call.name.clone(),
+                    call.arguments.clone(),
+                    conversation_id,
+                    Some(self.message_id),
+                )
+                .await
+            } else {
+                Err(AppError::ToolError(format!("没有提供工具 {}", call.name)))
+            };
+            results.push(tool_result_message(&match result {
+                Ok(content) => ToolResult {
+                    tool_use_id: call.id.clone(),
+                    content,
+                    is_error: None,
+                },
+                Err(e) => ToolResult {
+                    tool_use_id: call.id.clone(),
+                    content: e.to_string(),
+                    is_error: Some(true),
+                },
+            }));
+        }
+        results
+    }
+}
+
+// 本次请求提供给模型的工具名称
+fn offered_tools(model_config: &[AssistantModelConfig]) -> Vec<String> {
+    model_config
+        .iter()
+        .find(|config| config.name == TOOLS_CONFIG)
+        .and_then(|config| config.value.as_deref())
+        .map(|value| {
+            configured_tools(value)
+                .into_iter()
+                .map(|tool| tool.name)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// 多轮工具调用中模型输出的文字用空行隔开
+fn join_tool_round_text(previous: &str, content: &str) -> String {
+    match (previous.is_empty(), content.is_empty()) {
+        (true, _) => content.to_string(),
+        (false, true) => previous.to_string(),
+        (false, false) => format!("{}\n\n{}", previous, content),
+    }
+}
+
+// 模型不支持视觉时把图片附件替换成 OCR 识别出的文字，识别结果保存在附件上，之后的请求直接使用
+// 没有配置 OCR 或者识别失败的图片保持原样，由提供商按不支持图片处理
+async fn apply_image_ocr(app_handle: &AppHandle, messages: Vec<ChatMessage>) -> Vec<ChatMessage> {
+    let has_image = messages
+        .iter()
+        .any(|message| message.images().next().is_some());
+    if !has_image {
+        return messages;
+    }
+    let (engine, injection_guard) = match app_handle.try_state::<FeatureConfigState>() {
+        Some(state) => {
+            let config_feature_map = state.config_feature_map.lock().await;
+            (
+                get_ocr_engine(&config_feature_map, is_offline_mode(app_handle)),
+                InjectionGuard::from_config_map(&config_feature_map),
+            )
+        }
+        None => (None, InjectionGuard::default()),
+    };
+    let Some(engine) = engine else {
+        return messages;
+    };
+    let attachment_repo = ConversationDatabase::new(app_handle)
+        .ok()
+        .and_then(|db| db.attachment_repo().ok());
+
+    let mut result = Vec::new();
+    for mut message in messages {
+        let images = message.remove_attachments(|a| a.attachment_type == AttachmentType::Image);
+        for attachment in images {
+            let ocr_text = match &attachment.ocr_text {
+                Some(text) => text.clone(),
+                None => match recognize_image(engine.as_ref(), &attachment).await {
+                    Ok(text) => {
+                        // 只缓存已经保存到数据库中的附件
+                        if let (Some(repo), true) = (&attachment_repo, attachment.id > 0) {
+                            if let Err(e) = repo.update_ocr_text(attachment.id, &text) {
+                                println!(
+                                    "save attachment {} ocr text error: {:?}",
+                                    attachment.id, e
+                                );
+                            }
+                        }
+                        text
+                    }
+                    Err(e) => {
+                        println!("ocr attachment {} error: {:?}", attachment.id, e);
+                        String::new()
+                    }
+                },
+            };
+            if ocr_text.is_empty() {
+                message.content.push(ContentPart::Attachment(attachment));
+                continue;
+            }
+            let name = attachment
+                .attachment_url
+                .as_deref()
+                .and_then(|url| Path::new(url).file_name())
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| "未命名图片".to_string());
+            // 图片中的文字和附件一样是外部内容，检查提示注入
+            let ocr_text = injection_guard.apply(&format!("图片 {} 的 OCR 文字", name), &ocr_text);
+            message.push_text(&format!("\n{}", format_ocr_context(&name, &ocr_text)));
+        }
+        result.push(message);
+    }
+    result
+}
+
+async fn recognize_image(
+    engine: &dyn OcrEngine,
+    attachment: &MessageAttachment,
+) -> Result<String, Error> {
+    let content = attachment
+        .attachment_content
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("图片附件没有内容"))?;
+    engine.recognize(decode_image_content(content)?).await
+}
+
+// 接收生成结果时各个入口的差异
+#[derive(Default)]
+struct ReceiveOptions {
+    // 继续生成时提供商只返回续写的部分，需要拼接到原有内容之后
+    content_prefix: String,
+    is_truncated: bool,
+    // 新对话完成第一条回复后生成标题：对话 id、用户的提问、功能配置
+    title: Option<(i64, String, HashMap<String, HashMap<String, FeatureConfig>>)>,
+    // 提示词中注入的知识库分块，完成后从回答里解析引用
+    citation_sources: Vec<Citation>,
+    fast_mode: bool,
+    // 快速模式下按下快捷键的时间，用来统计从按下快捷键到收到第一段内容的耗时
+    triggered_at: Option<Instant>,
+    // 快捷操作把回答写入剪贴板
+    clipboard: Option<ClipboardWriter>,
+    // 先起草再改进时，草稿生成结束后接着进行的改进阶段
+    refine: Option<Box<RefineStage>>,
+    // 改进阶段参考的草稿消息，记录在生成信息中
+    draft_message_id: Option<i64>,
+}
+
+// 改进阶段的生成任务和接收选项，任务的消息列表在草稿生成结束后加上草稿和改进要求
+struct RefineStage {
+    task: GenerationTask,
+    watchdog: StallWatchdog,
+    mode: DraftRefineMode,
+    options: ReceiveOptions,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct RefineStartedEvent {
+    pub message_id: i64,
+    pub draft_message_id: i64,
+}
+
+// 草稿作为上一轮的回答，再追加改进的要求；批评写在思考标签中，和最终的回答分开保存
+fn refine_messages(
+    init_message_list: &[ChatMessage],
+    draft: &str,
+    mode: DraftRefineMode,
+    reasoning_tag: &str,
+) -> Vec<ChatMessage> {
+    let mut message_list = init_message_list.to_vec();
+    if draft.trim().is_empty() {
+        return message_list;
+    }
+    let instruction = match mode {
+        DraftRefineMode::Refine => {
+            "上面是一份草稿回答，请检查其中的错误和遗漏，直接输出改进后的完整回答，不要提及草稿"
+                .to_string()
+        }
+        DraftRefineMode::Critique => format!(
+            "上面是一份草稿回答，请先在 <{0}></{0}> 中逐条指出草稿的错误和不足，然后输出修改后的完整回答，不要提及草稿",
+            reasoning_tag
+        ),
+    };
+    message_list.push(ChatMessage::new(Role::Assistant, draft.trim()));
+    message_list.push(ChatMessage::new(Role::User, instruction));
+    message_list
+}
+
+// 草稿生成结束后开始改进阶段，最终的回答已经被取消时直接结束；草稿为空时不带草稿，由助手的模型直接回答
+async fn start_refine(
+    refine: RefineStage,
+    draft: &str,
+    reasoning_filter: &ReasoningFilter,
+    window: Window,
+    tokens: Arc<Mutex<HashMap<i64, CancellationToken>>>,
+    conversation_lock: Arc<Mutex<()>>,
+) {
+    let RefineStage {
+        mut task,
+        watchdog,
+        mode,
+        options,
+    } = refine;
+    let cancel_token = {
+        let mut tokens = tokens.lock().await;
+        match tokens.get(&task.message_id) {
+            Some(token) if !token.is_cancelled() => Some(token.clone()),
+            _ => {
+                tokens.remove(&task.message_id);
+                None
+            }
+        }
+    };
+    let Some(cancel_token) = cancel_token else {
+        println!("refine cancelled: id={}", task.message_id);
+        finish_message(&task.app_handle, &window, task.message_id, "", None, false);
+        release_conversation_lock(&task.app_handle, conversation_lock);
+        return;
+    };
+    let reasoning_tag = reasoning_filter
+        .tags
+        .first()
+        .map(|tag| tag.as_str())
+        .unwrap_or("think");
+    task.init_message_list = refine_messages(&task.init_message_list, draft, mode, reasoning_tag);
+    if let Some(draft_message_id) = options.draft_message_id {
+        let _ = window.emit(
+            "message_refine_started",
+            RefineStartedEvent {
+                message_id: task.message_id,
+                draft_message_id,
+            },
+        );
+    }
+    spawn_generation(
+        task,
+        window,
+        watchdog,
+        tokens,
+        conversation_lock,
+        cancel_token,
+        options,
+    );
+}
+
+// 发起生成并转发结果到窗口，超过停滞时间没有新数据时发送 message_stalled 事件，
+// 按配置自动重试；超过助手的最长生成时间时停止生成，保留已生成的内容并发送 message_timed_out 事件，
+// 无论以何种方式结束都会清理取消令牌并把消息标记为完成
+//
+// 同一个对话已经有生成在进行时先发送 message_queued 事件排队，等前一个生成结束后再开始
+fn spawn_generation(
+    task: GenerationTask,
+    window: Window,
+    watchdog: StallWatchdog,
+    tokens: Arc<Mutex<HashMap<i64, CancellationToken>>>,
+    conversation_lock: Arc<Mutex<()>>,
+    cancel_token: CancellationToken,
+    options: ReceiveOptions,
+) {
+    tokio::spawn(async move {
+        let mut task = task;
+        let message_id = task.message_id;
+        let reasoning_filter = ReasoningFilter::new(&task).await;
+        let conversation_guard = match conversation_lock.clone().try_lock_owned() {
+            Ok(guard) => guard,
+            Err(_) => {
+                let _ = window.emit("message_queued", message_id);
+                tokio::select! {
+                    guard = conversation_lock.clone().lock_owned() => guard,
+                    _ = cancel_token.cancelled() => {
+                        // 排队时被取消，保留原有内容直接结束，和正常结束一样分开思考过程和回答
+                        tokens.lock().await.remove(&message_id);
+                        let split = reasoning_filter.split(&options.content_prefix, true);
+                        let reasoning =
+                            (!split.reasoning.is_empty()).then_some(split.reasoning.as_str());
+                        finish_message(
+                            &task.app_handle,
+                            &window,
+                            message_id,
+                            &split.answer,
+                            reasoning,
+                            options.is_truncated,
+                        );
+                        if let Some(refine) = options.refine {
+                            start_refine(
+                                *refine,
+                                "",
+                                &reasoning_filter,
+                                window,
+                                tokens,
+                                conversation_lock,
+                            )
+                            .await;
+                        } else {
+                            release_conversation_lock(&task.app_handle, conversation_lock);
+                        }
+                        return;
+                    }
+                }
+            }
+        };
+        let (tx, mut rx) = mpsc::channel(100);
+        task.spawn(tx, cancel_token, tokens.clone());
+
+        let started = Instant::now();
+        let mut metadata = task.generation_metadata();
+        metadata.fast_mode = options.fast_mode;
+        metadata.draft_message_id = options.draft_message_id;
+        let mut is_truncated = options.is_truncated;
+        let mut last_content = options.content_prefix.clone();
+        // 中断后续写时，已经生成的内容也作为前缀
+        let mut content_prefix = options.content_prefix.clone();
+        let init_message_list = task.init_message_list.clone();
+        let mut stream_error: Option<String> = None;
+        let mut retry_count = 0;
+        let mut resume_count = 0;
+        let mut sentences = SentenceSplitter::default();
+        // 合并发送给前端的内容，减少事件数量和 markdown 渲染的闪烁
+        let mut batcher = StreamBatcher::new(stream_flush_interval(&window), true);
+        let mut last_reasoning = (String::new(), false);
+        let mut clipboard = options.clipboard;
+        // 最长生成时间包括停滞后自动重试的时间
+        let deadline = task
+            .max_generation_time()
+            .map(|duration| tokio::time::Instant::now() + duration);
+        loop {
+            // 提供商停顿时按时发送合并缓存着的内容，不用等到下一段内容到达
+            let flush_at = batcher.flush_deadline();
+            let receive = async {
+                match deadline {
+                    Some(deadline) => tokio::select! {
+                        received = timeout(watchdog.timeout, rx.recv()) => Some(received),
+                        _ = tokio::time::sleep_until(deadline) => None,
+                    },
+                    None => Some(timeout(watchdog.timeout, rx.recv()).await),
+                }
+            };
+            let received = tokio::select! {
+                received = receive => received,
+                _ = tokio::time::sleep_until(
+                    flush_at.map_or_else(tokio::time::Instant::now, tokio::time::Instant::from_std)
+                ), if flush_at.is_some() => {
+                    if let Some(answer) = batcher.flush_pending() {
+                        let _ = window.emit(format!("message_{}", message_id).as_str(), answer);
+                    }
+                    continue;
+                }
+            };
+            let Some(received) = received else {
+                println!("Generation timed out: id={}", message_id);
+                if let Some(token) = tokens.lock().await.remove(&message_id) {
+                    token.cancel();
+                }
+                mark_timed_out(&task.app_handle, message_id);
+                let _ = window.emit("message_timed_out", message_id);
+                metadata.finish_reason = Some("timeout".to_string());
+                save_generation_metadata(
+                    &task.app_handle,
+                    &window,
+                    message_id,
+                    &mut metadata,
+                    started,
+                );
+                finish_generated_message(
+                    &window,
+                    message_id,
+                    &last_content,
+                    is_truncated,
+                    &reasoning_filter,
+                    &mut sentences,
+                    &mut batcher,
+                );
+                break;
+            };
+            match received {
+                Ok(Some((id, content, done))) => {
+                    if let Some(finish_reason) = content.strip_prefix(FINISH_REASON_EVENT_PREFIX) {
+                        is_truncated = is_truncated_finish_reason(finish_reason);
+                        metadata.finish_reason = Some(finish_reason.to_string());
+                        continue;
+                    }
+                    if let Some(model_code) = content.strip_prefix(SERVED_MODEL_EVENT_PREFIX) {
+                        metadata.record_served_model(model_code);
+                        continue;
+                    }
+                    if let Some(error) = content.strip_prefix(STREAM_INTERRUPTED_EVENT_PREFIX) {
+                        stream_error = Some(error.to_string());
+                        continue;
+                    }
+                    if let Some(usage) = content.strip_prefix(USAGE_EVENT_PREFIX) {
+                        if let Ok(usage) = serde_json::from_str::<TokenUsage>(usage) {
+                            metadata.prompt_tokens = usage.prompt_tokens.or(metadata.prompt_tokens);
+                            metadata.completion_tokens =
+                                usage.completion_tokens.or(metadata.completion_tokens);
+                            let _ = window.emit(
+                                "message_usage",
+                                UsageUpdate {
+                                    message_id,
+                                    prompt_tokens: usage.prompt_tokens,
+                                    completion_tokens: usage.completion_tokens,
+                                    total_tokens: usage.total_tokens,
+                                },
+                            );
+                        }
+                        continue;
+                    }
+                    if let Some(progress) = content.strip_prefix(TOOL_PROGRESS_EVENT_PREFIX) {
+                        if let Ok(progress) = serde_json::from_str::<ToolProgress>(progress) {
+                            let _ = window.emit(
+                                "message_tool_progress",
+                                ToolProgressUpdate {
+                                    message_id,
+                                    item_id: progress.item_id,
+                                    tool: progress.tool,
+                                    status: progress.status,
+                                },
+                            );
+                        }
+                        continue;
+                    }
+                    if metadata.first_token_ms.is_none() && !content.is_empty() {
+                        metadata.first_token_ms = Some(started.elapsed().as_millis() as u64);
+                        metadata.shortcut_first_token_ms = options
+                            .triggered_at
+                            .map(|triggered_at| triggered_at.elapsed().as_millis() as u64);
+                    }
+                    last_content = format!("{}{}", content_prefix, content);
+                    let split = reasoning_filter.split(&last_content, done);
+                    let reasoning_state = (split.reasoning.clone(), split.thinking);
+                    if reasoning_filter.show && reasoning_state != last_reasoning {
+                        let _ = window.emit(
+                            "message_reasoning",
+                            MessageReasoningEvent {
+                                message_id,
+                                content: split.reasoning.clone(),
+                                thinking: split.thinking,
+                            },
+                        );
+                        last_reasoning = reasoning_state;
+                    }
+                    if !done {
+                        batcher.set_interval(stream_flush_interval(&window));
+                        if let Some(answer) = batcher.push(&split.answer) {
+                            let _ = window.emit(format!("message_{}", id).as_str(), answer);
+                        }
+                        emit_sentences(&window, message_id, sentences.push(&split.answer));
+                        if let Some(clipboard) = clipboard.as_mut() {
+                            clipboard.push(&split.answer);
+                        }
+                    }
+
+                    if done {
+                        if !options.citation_sources.is_empty() {
+                            save_citations(
+                                &task.app_handle,
+                                &window,
+                                message_id,
+                                &split.answer,
+                                &options.citation_sources,
+                            );
+                        }
+                        save_generation_metadata(
+                            &task.app_handle,
+                            &window,
+                            message_id,
+                            &mut metadata,
+                            started,
+                        );
+                        finish_generated_message(
+                            &window,
+                            message_id,
+                            &last_content,
+                            is_truncated,
+                            &reasoning_filter,
+                            &mut sentences,
+                            &mut batcher,
+                        );
+                        if let Some((conversation_id, user_prompt, config_feature_map)) =
+                            &options.title
+                        {
+                            if let Err(e) = generate_title(
+                                &task.app_handle,
+                                *conversation_id,
+                                user_prompt.clone(),
+                                split.answer.clone(),
+                                config_feature_map.clone(),
+                                window.clone(),
+                            )
+                            .await
+                            {
+                                println!("generate title error: {:?}", e);
+                            }
+                        }
+                        tokens.lock().await.remove(&message_id);
+                        break;
+                    }
+                }
+                Ok(None) => {
+                    println!("Channel closed");
+                    // 被取消的令牌（例如托盘菜单的取消所有生成）不再续写
+                    let current_token = tokens
+                        .lock()
+                        .await
+                        .remove(&message_id)
+                        .filter(|token| !token.is_cancelled());
+                    // 流式请求没有收到结束事件就断开了，已经生成了内容并且提供商支持时带着已生成的内容续写
+                    if current_token.is_some() && task.is_stream() {
+                        let error = stream_error
+                            .take()
+                            .unwrap_or_else(|| "连接在生成结束前断开".to_string());
+                        let generated = last_content
+                            .get(options.content_prefix.len()..)
+                            .unwrap_or_default()
+                            .trim_end()
+                            .to_string();
+                        let resuming = !generated.is_empty()
+                            && resume_count < watchdog.max_resumes
+                            && metadata
+                                .api_type
+                                .as_deref()
+                                .map_or(false, supports_assistant_prefill);
+                        if resuming {
+                            resume_count += 1;
+                        }
+                        println!(
+                            "Generation stream interrupted: id={}, resuming={}, error={}",
+                            message_id, resuming, error
+                        );
+                        let _ = window.emit(
+                            "message_stream_interrupted",
+                            StreamInterruptedEvent {
+                                message_id,
+                                error: error.clone(),
+                                resuming,
+                                resume_count,
+                            },
+                        );
+
+                        if resuming {
+                            content_prefix = format!("{}{}", options.content_prefix, generated);
+                            last_content = content_prefix.clone();
+                            task.init_message_list = init_message_list.clone();
+                            task.init_message_list
+                                .push(ChatMessage::new(Role::Assistant, generated));
+                            let (tx, new_rx) = mpsc::channel(100);
+                            rx = new_rx;
+                            let cancel_token = CancellationToken::new();
+                            tokens.lock().await.insert(message_id, cancel_token.clone());
+                            task.spawn(tx, cancel_token, tokens.clone());
+                            continue;
+                        }
+                        if generated.is_empty() && task.error_as_content {
+                            last_content = format!("Chat stream error: {}", error);
+                        }
+                        metadata.finish_reason = Some("interrupted".to_string());
+                    }
+                    save_generation_metadata(
+                        &task.app_handle,
+                        &window,
+                        message_id,
+                        &mut metadata,
+                        started,
+                    );
+                    finish_generated_message(
+                        &window,
+                        message_id,
+                        &last_content,
+                        is_truncated,
+                        &reasoning_filter,
+                        &mut sentences,
+                        &mut batcher,
+                    );
+                    break;
+                }
+                Err(_) => {
+                    println!(
+                        "Generation stalled: id={}, no data in {:?}",
+                        message_id, watchdog.timeout
+                    );
+                    // 取消卡住的请求，令牌已经不存在说明用户主动取消了，不再重试
+                    let current_token = tokens.lock().await.remove(&message_id);
+                    if let Some(token) = &current_token {
+                        token.cancel();
+                    }
+                    let retrying = current_token.is_some()
+                        && watchdog.auto_retry
+                        && retry_count < watchdog.max_retries;
+                    if retrying {
+                        retry_count += 1;
+                        metadata.retries = retry_count;
+                    }
+                    let _ = window.emit(
+                        "message_stalled",
+                        StalledEvent {
+                            message_id,
+                            retrying,
+                            retry_count,
+                        },
+                    );
+
+                    if retrying {
+                        // 旧的接收端被替换后，卡住的请求即使恢复也无法再发送数据
+                        let (tx, new_rx) = mpsc::channel(100);
+                        rx = new_rx;
+                        let cancel_token = CancellationToken::new();
+                        tokens.lock().await.insert(message_id, cancel_token.clone());
+                        task.spawn(tx, cancel_token, tokens.clone());
+                        continue;
+                    }
+                    metadata.finish_reason = Some("stalled".to_string());
+                    save_generation_metadata(
+                        &task.app_handle,
+                        &window,
+                        message_id,
+                        &mut metadata,
+                        started,
+                    );
+                    finish_generated_message(
+                        &window,
+                        message_id,
+                        &last_content,
+                        is_truncated,
+                        &reasoning_filter,
+                        &mut sentences,
+                        &mut batcher,
+                    );
                     break;
                 }
             }
         }
+        // 完成、出错、超时和停滞都会结束循环，结束时把已经生成的回答完整写入剪贴板
+        if let Some(clipboard) = clipboard.as_mut() {
+            clipboard.finish(&reasoning_filter.split(&last_content, true).answer);
+        }
+        if let Some(refine) = options.refine {
+            // 改进阶段使用同一个对话的生成锁，先释放草稿阶段持有的锁
+            drop(conversation_guard);
+            let draft = reasoning_filter.split(&last_content, true).answer;
+            start_refine(
+                *refine,
+                &draft,
+                &reasoning_filter,
+                window,
+                tokens,
+                conversation_lock,
+            )
+            .await;
+        } else {
+            drop(conversation_guard);
+            release_conversation_lock(&task.app_handle, conversation_lock);
+        }
     });
+}
 
-    Ok(AiResponse {
-        conversation_id,
-        add_message_id: new_message_id,
-        request_prompt_result_with_context: String::new(),
-    })
+// 生成结束后归还对话的生成锁，对话上没有其他生成在进行或者排队时发送 conversation_idle 事件，
+// 前端据此重新允许删除对话、编辑消息等操作
+fn release_conversation_lock(app_handle: &AppHandle, conversation_lock: Arc<Mutex<()>>) {
+    let Some(manager) = app_handle.try_state::<MessageTokenManager>() else {
+        return;
+    };
+    if let Some(conversation_id) = manager.release_conversation_lock(conversation_lock) {
+        let _ = app_handle.emit("conversation_idle", conversation_id);
+    }
+}
+
+fn mark_timed_out(app_handle: &AppHandle, message_id: i64) {
+    let result = ConversationDatabase::new(app_handle)
+        .map_err(AppError::from)
+        .and_then(|db| db.message_repo())
+        .and_then(|repo| Ok(repo.update_timed_out(message_id)?));
+    if let Err(e) = result {
+        println!("mark message {} timed out error: {:?}", message_id, e);
+    }
+}
+
+// 前台窗口的生成更频繁地发送内容，后台窗口合并更多内容再发送，减少 IPC 事件
+fn stream_flush_interval(window: &Window) -> Duration {
+    window
+        .state::<WindowFocusState>()
+        .flush_interval(window.label())
+}
+
+// 保存生成结果并标记消息完成，通知窗口结束生成
+// 生成结束时把思考过程和回答分开保存，发送还没有发送的回答，回答中剩下的内容作为最后一句发送
+fn finish_generated_message(
+    window: &Window,
+    message_id: i64,
+    content: &str,
+    is_truncated: bool,
+    reasoning_filter: &ReasoningFilter,
+    sentences: &mut SentenceSplitter,
+    batcher: &mut StreamBatcher,
+) {
+    let split = reasoning_filter.split(content, true);
+    if let Some(answer) = batcher.finish(&split.answer) {
+        let _ = window.emit(format!("message_{}", message_id).as_str(), answer);
+    }
+    emit_sentences(window, message_id, sentences.finish(&split.answer));
+    let reasoning = (!split.reasoning.is_empty()).then_some(split.reasoning.as_str());
+    finish_message(
+        window.app_handle(),
+        window,
+        message_id,
+        &split.answer,
+        reasoning,
+        is_truncated,
+    );
+}
+
+// 消息所在的对话，消息或者对话已经删除时返回 None
+fn message_conversation_id(app_handle: &AppHandle, message_id: i64) -> Option<i64> {
+    ConversationDatabase::new(app_handle)
+        .ok()?
+        .message_repo()
+        .ok()?
+        .conversation_id_of(message_id)
+        .ok()
+        .flatten()
+}
+
+// reasoning 为 None 时保留原有的思考过程
+fn finish_message(
+    app_handle: &AppHandle,
+    window: &Window,
+    message_id: i64,
+    content: &str,
+    reasoning: Option<&str>,
+    is_truncated: bool,
+) {
+    let result = (|| -> Result<(), AppError> {
+        let conversation_db = ConversationDatabase::new(app_handle)?;
+        let message_repo = conversation_db.message_repo()?;
+        if let Some(mut message) = message_repo.read(message_id)? {
+            message.content = content.to_string();
+            if let Some(reasoning) = reasoning {
+                message.reasoning = Some(reasoning.to_string());
+            }
+            message.is_truncated = is_truncated;
+            message_repo.update(&message)?;
+        }
+        message_repo.update_finish_time(message_id)?;
+        Ok(())
+    })();
+    if let Err(e) = result {
+        println!("save message {} error: {:?}", message_id, e);
+    }
+    audit_log::record(
+        app_handle,
+        "response",
+        message_conversation_id(app_handle, message_id),
+        Some(message_id),
+        json!({ "content": content, "reasoning": reasoning, "is_truncated": is_truncated }),
+    );
+
+    if is_truncated {
+        let _ = window.emit("message_truncated", message_id);
+    }
+    println!("Message finish: id={}", message_id);
+    let _ = window.emit(
+        format!("message_{}", message_id).as_str(),
+        "Tea::Event::MessageFinish",
+    );
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct MessageGenerationMetadataEvent {
+    pub message_id: i64,
+    pub metadata: GenerationMetadata,
+}
+
+// 补充总耗时后保存回答的生成信息，并通知窗口更新回答旁边的信息
+fn save_generation_metadata(
+    app_handle: &AppHandle,
+    window: &Window,
+    message_id: i64,
+    metadata: &mut GenerationMetadata,
+    started: Instant,
+) {
+    metadata.latency_ms = Some(started.elapsed().as_millis() as u64);
+    let result = (|| -> Result<(), AppError> {
+        let value =
+            serde_json::to_string(&metadata).map_err(|e| AppError::ParseError(e.to_string()))?;
+        ConversationDatabase::new(app_handle)?
+            .message_repo()?
+            .update_generation_metadata(message_id, Some(&value))?;
+        Ok(())
+    })();
+    if let Err(e) = result {
+        println!(
+            "save message {} generation metadata error: {:?}",
+            message_id, e
+        );
+        return;
+    }
+    let _ = window.emit(
+        "message_generation_metadata",
+        MessageGenerationMetadataEvent {
+            message_id,
+            metadata: metadata.clone(),
+        },
+    );
+    webhook::notify_generation_finished(
+        app_handle,
+        message_id,
+        started.elapsed().as_secs(),
+        metadata.finish_reason.as_deref(),
+    );
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct MessageCitationsEvent {
+    pub message_id: i64,
+    pub citations: Vec<Citation>,
+}
+
+// 解析回答中的 [n] 引用标记，保存实际引用到的来源并通知窗口
+fn save_citations(
+    app_handle: &AppHandle,
+    window: &Window,
+    message_id: i64,
+    content: &str,
+    citation_sources: &[Citation],
+) {
+    let citations = parse_citations(content, citation_sources);
+    if citations.is_empty() {
+        return;
+    }
+    let result = (|| -> Result<(), AppError> {
+        let value =
+            serde_json::to_string(&citations).map_err(|e| AppError::ParseError(e.to_string()))?;
+        ConversationDatabase::new(app_handle)?
+            .message_repo()?
+            .update_citations(message_id, Some(&value))?;
+        Ok(())
+    })();
+    if let Err(e) = result {
+        println!("save message {} citations error: {:?}", message_id, e);
+        return;
+    }
+    let _ = window.emit(
+        "message_citations",
+        MessageCitationsEvent {
+            message_id,
+            citations,
+        },
+    );
 }
 
 fn add_message(
-    app_handle: &tauri::AppHandle,
+    app_handle: &AppHandle,
     parent_id: Option<i64>,
     conversation_id: i64,
     message_type: String,
@@ -648,6 +3328,7 @@ fn add_message(
     start_time: Option<chrono::DateTime<chrono::Utc>>,
     finish_time: Option<chrono::DateTime<chrono::Utc>>,
     token_count: i32,
+    assistant_id: Option<i64>,
 ) -> Result<Message, AppError> {
     let db = ConversationDatabase::new(app_handle).map_err(AppError::from)?;
     let message = db
@@ -665,210 +3346,255 @@ fn add_message(
             finish_time,
             created_time: chrono::Utc::now(),
             token_count,
+            is_truncated: false,
+            attachment_truncation: None,
+            pinned: false,
+            citations: None,
+            timed_out: false,
+            generation_metadata: None,
+            reasoning: None,
+            assistant_id,
         })
         .map_err(AppError::from)?;
     Ok(message.clone())
 }
 
 async fn initialize_conversation(
-    app_handle: &tauri::AppHandle,
+    app_handle: &AppHandle,
     request: &AiRequest,
     assistant_detail: &AssistantDetail,
     assistant_prompt_result: String,
     request_prompt_result: String,
+    citation_sources: &[Citation],
     override_prompt: Option<String>,
+    config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
 ) -> Result<
     (
         i64,
         Option<i64>,
         String,
-        Vec<(String, String, Vec<MessageAttachment>)>,
+        Vec<ChatMessage>,
+        Vec<AttachmentTruncation>,
     ),
     AppError,
 > {
     let db = get_conversation_db(app_handle)?;
 
-    let (conversation_id, add_message_id, request_prompt_result_with_context, init_message_list) =
-        if request.conversation_id.is_empty() {
-            let message_attachment_list = db
-                .attachment_repo()
-                .unwrap()
-                .list_by_id(&request.attachment_list.clone().unwrap_or(vec![]))?;
-            // 新对话逻辑
-            let text_attachments: Vec<String> = message_attachment_list
-                .iter()
-                .filter(|a| matches!(a.attachment_type, AttachmentType::Text))
-                .filter_map(|a| {
-                    Some(format!(
-                        r#"<fileattachment name="{}">{}</fileattachment>"#,
-                        a.attachment_url.clone().unwrap(),
-                        a.attachment_content.clone().unwrap().as_str()
-                    ))
-                })
-                .collect();
-            let context = text_attachments.join("\n");
-            let request_prompt_result_with_context =
-                format!("{}\n{}", request_prompt_result, context);
-            let init_message_list = vec![
-                (
-                    String::from("system"),
-                    override_prompt.unwrap_or(assistant_prompt_result),
-                    vec![],
-                ),
-                (
-                    String::from("user"),
-                    request_prompt_result_with_context.clone(),
-                    message_attachment_list,
-                ),
-            ];
-            println!("initialize_conversation {:?}", request.assistant_id);
-            println!(
-                "initialize_conversation init_message_list {:?}",
-                init_message_list
-            );
-            let (conversation, _) = init_conversation(
-                app_handle,
-                request.assistant_id,
-                assistant_detail.model[0].id,
-                assistant_detail.model[0].model_code.clone(),
-                &init_message_list,
-            )?;
-            let add_message = add_message(
-                app_handle,
-                None,
-                conversation.id,
-                "assistant".to_string(),
-                String::new(),
-                Some(assistant_detail.model[0].id),
-                Some(assistant_detail.model[0].model_code.clone()),
-                None,
-                None,
-                0,
-            )?;
-            (
-                conversation.id,
-                Some(add_message.id),
-                request_prompt_result_with_context,
-                init_message_list,
-            )
-        } else {
-            // 已存在对话逻辑
-            let conversation_id = request.conversation_id.parse::<i64>()?;
-            let all_messages = db
-                .message_repo()
+    let (
+        conversation_id,
+        add_message_id,
+        request_prompt_result_with_context,
+        init_message_list,
+        attachment_truncation,
+    ) = if request.conversation_id.is_empty() {
+        let message_attachment_list = db
+            .attachment_repo()
+            .unwrap()
+            .list_by_id(&request.attachment_list.clone().unwrap_or(vec![]))?;
+        // 新对话逻辑
+        let (context, attachment_truncation) = build_attachment_context(
+            &message_attachment_list,
+            &request_prompt_result,
+            config_feature_map,
+        );
+        let request_prompt_result_with_context = format!("{}\n{}", request_prompt_result, context);
+        let init_message_list = vec![
+            ChatMessage::new(
+                Role::System,
+                override_prompt.unwrap_or(assistant_prompt_result),
+            ),
+            ChatMessage::with_attachments(
+                Role::User,
+                request_prompt_result_with_context.clone(),
+                message_attachment_list,
+            ),
+        ];
+        println!("initialize_conversation {:?}", request.assistant_id);
+        println!(
+            "initialize_conversation init_message_list: {} messages",
+            init_message_list.len()
+        );
+        let (conversation, messages) = init_conversation(
+            app_handle,
+            request.assistant_id,
+            assistant_detail.model[0].id,
+            assistant_detail.model[0].model_code.clone(),
+            &init_message_list,
+        )?;
+        if let Some(user_message) = messages.iter().find(|m| m.role() == Some(Role::User)) {
+            save_attachment_truncation(&db, user_message.id, &attachment_truncation)?;
+            save_citation_sources(&db, user_message.id, citation_sources)?;
+        }
+        let add_message = add_message(
+            app_handle,
+            None,
+            conversation.id,
+            Role::Assistant.to_string(),
+            String::new(),
+            Some(assistant_detail.model[0].id),
+            Some(assistant_detail.model[0].model_code.clone()),
+            None,
+            None,
+            0,
+            Some(assistant_detail.assistant.id),
+        )?;
+        (
+            conversation.id,
+            Some(add_message.id),
+            request_prompt_result_with_context,
+            init_message_list,
+            attachment_truncation,
+        )
+    } else {
+        // 已存在对话逻辑
+        let conversation_id = request.conversation_id.parse::<i64>()?;
+        let assistant_names = assistant_names(app_handle).await;
+        let message_list = conversation_history(
+            db.message_repo()
                 .unwrap()
-                .list_by_conversation_id(conversation_id)?;
-
-            // 创建一个 HashMap 来存储每个消息的最新子消息
-            let mut latest_children: HashMap<i64, (Message, Option<MessageAttachment>)> =
-                HashMap::new();
-            // 创建一个 HashSet 来存储所有作为子消息的消息 ID
-            let mut child_ids: HashSet<i64> = HashSet::new();
-
-            // 遍历所有消息，更新最新子消息和子消息 ID 集合
-            for (message, attachment) in all_messages.iter() {
-                if let Some(parent_id) = message.parent_id {
-                    child_ids.insert(message.id);
-                    latest_children
-                        .entry(parent_id)
-                        .and_modify(|e| *e = (message.clone(), attachment.clone()))
-                        .or_insert((message.clone(), attachment.clone()));
-                }
-            }
+                .list_by_conversation_id(conversation_id)?,
+            assistant_detail.assistant.id,
+            &assistant_names,
+            &db.quote_repo()?.list_by_conversation_id(conversation_id)?,
+            &InjectionGuard::from_config_map(config_feature_map),
+        )?;
 
-            // 构建最终的消息列表
-            let message_list: Vec<(String, String, Vec<MessageAttachment>)> = all_messages
-                .into_iter()
-                .filter(|(message, _)| !child_ids.contains(&message.id))
-                .map(|(message, attachment)| {
-                    let (final_message, final_attachment) = latest_children
-                        .get(&message.id)
-                        .map(|child| child.clone())
-                        .unwrap_or((message, attachment));
-
-                    (
-                        final_message.message_type,
-                        final_message.content, // 使用修改后的 content
-                        final_attachment.map(|a| vec![a]).unwrap_or_else(Vec::new),
-                    )
-                })
-                .collect();
+        // 获取到消息的附件列表
+        let message_attachment_list = db
+            .attachment_repo()
+            .unwrap()
+            .list_by_id(&request.attachment_list.clone().unwrap_or(vec![]))?;
+        // 文本附件拼接到提问后面
+        let (context, attachment_truncation) = build_attachment_context(
+            &message_attachment_list,
+            &request_prompt_result,
+            config_feature_map,
+        );
 
-            // 获取到消息的附件列表
-            let message_attachment_list = db
-                .attachment_repo()
-                .unwrap()
-                .list_by_id(&request.attachment_list.clone().unwrap_or(vec![]))?;
-            // 过滤出文本附件
-            let text_attachments: Vec<String> = message_attachment_list
-                .iter()
-                .filter(|a| matches!(a.attachment_type, AttachmentType::Text))
-                .filter_map(|a| {
-                    Some(format!(
-                        r#"<fileattachment name="{}">{}</fileattachment>"#,
-                        a.attachment_url.clone().unwrap(),
-                        a.attachment_content.clone().unwrap().as_str()
-                    ))
-                })
-                .collect();
-            let context = text_attachments.join("\n");
-
-            let request_prompt_result_with_context =
-                format!("{}\n{}", request_prompt_result, context);
-            // 添加用户消息
-            let _ = add_message(
-                app_handle,
-                None,
-                conversation_id,
-                "user".to_string(),
-                request_prompt_result_with_context.clone(),
-                Some(assistant_detail.model[0].id),
-                Some(assistant_detail.model[0].model_code.clone()),
-                None,
-                None,
-                0,
-            )?;
-            let mut updated_message_list = message_list;
-            updated_message_list.push((
-                String::from("user"),
+        let request_prompt_result_with_context = format!("{}\n{}", request_prompt_result, context);
+        // 添加用户消息
+        let user_message = add_message(
+            app_handle,
+            None,
+            conversation_id,
+            Role::User.to_string(),
+            request_prompt_result_with_context.clone(),
+            Some(assistant_detail.model[0].id),
+            Some(assistant_detail.model[0].model_code.clone()),
+            None,
+            None,
+            0,
+            None,
+        )?;
+        save_attachment_truncation(&db, user_message.id, &attachment_truncation)?;
+        save_citation_sources(&db, user_message.id, citation_sources)?;
+        let attachment_repo = db.attachment_repo()?;
+        for attachment in &message_attachment_list {
+            attachment_repo.link(attachment.id, user_message.id)?;
+        }
+        let mut updated_message_list = message_list;
+        updated_message_list.push((
+            ChatMessage::with_attachments(
+                Role::User,
                 request_prompt_result_with_context.clone(),
                 message_attachment_list,
-            ));
+            ),
+            false,
+        ));
+        let updated_message_list = apply_context_window(
+            app_handle,
+            conversation_id,
+            updated_message_list,
+            config_feature_map,
+        );
 
-            let add_assistant_message = add_message(
-                app_handle,
-                None,
-                conversation_id,
-                "assistant".to_string(),
-                String::new(),
-                Some(assistant_detail.model[0].id),
-                Some(assistant_detail.model[0].model_code.clone()),
-                None,
-                None,
-                0,
-            )?;
-            (
-                conversation_id,
-                Some(add_assistant_message.id),
-                request_prompt_result_with_context,
-                updated_message_list,
-            )
-        };
+        let add_assistant_message = add_message(
+            app_handle,
+            None,
+            conversation_id,
+            Role::Assistant.to_string(),
+            String::new(),
+            Some(assistant_detail.model[0].id),
+            Some(assistant_detail.model[0].model_code.clone()),
+            None,
+            None,
+            0,
+            Some(assistant_detail.assistant.id),
+        )?;
+        (
+            conversation_id,
+            Some(add_assistant_message.id),
+            request_prompt_result_with_context,
+            updated_message_list,
+            attachment_truncation,
+        )
+    };
     Ok((
         conversation_id,
         add_message_id,
         request_prompt_result_with_context,
         init_message_list,
+        attachment_truncation,
     ))
 }
 
+// 对话中已有的消息转换成发给提供商的历史消息，重新生成过的消息使用最新的版本，同时返回是否置顶
+// 群聊中其他助手的发言带上名称，从其他对话引用的消息带上来源
+fn conversation_history(
+    all_messages: Vec<(Message, Option<MessageAttachment>)>,
+    current_assistant_id: i64,
+    assistant_names: &HashMap<i64, String>,
+    quotes: &HashMap<i64, MessageQuote>,
+    injection_guard: &InjectionGuard,
+) -> Result<Vec<(ChatMessage, bool)>, AppError> {
+    // 创建一个 HashMap 来存储每个消息的最新子消息
+    let mut latest_children: HashMap<i64, (Message, Option<MessageAttachment>)> = HashMap::new();
+    // 创建一个 HashSet 来存储所有作为子消息的消息 ID
+    let mut child_ids: HashSet<i64> = HashSet::new();
+
+    // 遍历所有消息，更新最新子消息和子消息 ID 集合
+    for (message, attachment) in all_messages.iter() {
+        if let Some(parent_id) = message.parent_id {
+            child_ids.insert(message.id);
+            latest_children
+                .entry(parent_id)
+                .and_modify(|e| *e = (message.clone(), attachment.clone()))
+                .or_insert((message.clone(), attachment.clone()));
+        }
+    }
+
+    // 构建最终的消息列表
+    all_messages
+        .into_iter()
+        .filter(|(message, _)| !child_ids.contains(&message.id))
+        .map(|(message, attachment)| {
+            let pinned = message.pinned;
+            let (final_message, final_attachment) = latest_children
+                .get(&message.id)
+                .map(|child| child.clone())
+                .unwrap_or((message, attachment));
+
+            // 使用修改后的 content
+            speaker_chat_message(
+                &final_message,
+                final_attachment.map(|a| vec![a]).unwrap_or_else(Vec::new),
+                current_assistant_id,
+                assistant_names,
+                quotes,
+                injection_guard,
+            )
+            .map(|chat_message| (chat_message, pinned || final_message.pinned))
+        })
+        .collect()
+}
+
 async fn generate_title(
-    app_handle: &tauri::AppHandle,
+    app_handle: &AppHandle,
     conversation_id: i64,
     user_prompt: String,
     content: String,
     config_feature_map: HashMap<String, HashMap<String, FeatureConfig>>,
-    window: tauri::Window,
+    window: Window,
 ) -> Result<(), AppError> {
     // TODO 要检查下是否配置了对应的
     let feature_config = config_feature_map.get("conversation_summary");
@@ -927,14 +3653,20 @@ async fn generate_title(
 
         let db = get_llm_db(app_handle)?;
         let model_detail = db.get_llm_model_detail(&provider_id, &model_code).unwrap();
+        if is_offline_mode(app_handle)
+            && !is_local_provider(&model_detail.provider, &model_detail.configs)
+        {
+            println!("offline mode, skip generating title with {}", model_code);
+            return Ok(());
+        }
 
-        let provider = get_provider(model_detail.provider, model_detail.configs);
+        let provider = get_provider(model_detail.provider, model_detail.configs)?;
         let response = provider
             .chat(
                 -1,
                 vec![
-                    ("system".to_string(), prompt, vec![]),
-                    ("user".to_string(), context, vec![]),
+                    ChatMessage::new(Role::System, prompt),
+                    ChatMessage::new(Role::User, context),
                 ],
                 vec![AssistantModelConfig {
                     id: 0,
@@ -956,8 +3688,8 @@ async fn generate_title(
                     "生成对话标题失败，请检查配置",
                 );
             }
-            Ok(response_text) => {
-                println!("Chat content: {}", response_text.clone());
+            Ok(response) => {
+                let response_text = response.content;
 
                 let conversation_db = get_conversation_db(app_handle)?;
                 let _ = conversation_db
@@ -968,6 +3700,8 @@ async fn generate_title(
                         name: response_text.clone(),
                         assistant_id: None,
                         created_time: chrono::Utc::now(),
+                        is_private: false,
+                        summary: None,
                     });
                 window
                     .emit("title_change", (conversation_id, response_text.clone()))
@@ -980,10 +3714,588 @@ async fn generate_title(
     Ok(())
 }
 
-fn get_conversation_db(app_handle: &tauri::AppHandle) -> Result<ConversationDatabase, AppError> {
+// 提问使用的助手配置：应用当前的参数预设和技能指定的模型，model 不为空时替换助手的模型，
+// 快速模式换用 fast_mode 配置的模型，最后按离线模式检查，快速模式的小模型也可能是云端的。
+// 提问、预览、重新生成和继续生成都通过这里取得助手配置
+fn request_assistant(
+    app_handle: &AppHandle,
+    assistant_id: i64,
+    model: Option<AssistantModel>,
+    fast_mode: bool,
+    allow_online: bool,
+    config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+) -> Result<AssistantDetail, AppError> {
+    let mut assistant_detail =
+        get_assistant(app_handle.clone(), assistant_id).map_err(AppError::UnknownError)?;
+    apply_active_preset(app_handle, &mut assistant_detail);
+    apply_skill_model(app_handle, &mut assistant_detail);
+    if let Some(model) = model {
+        assistant_detail.model = vec![AssistantModel {
+            assistant_id,
+            ..model
+        }];
+    }
+    if assistant_detail.model.is_empty() {
+        return Err(AppError::NoModelFound);
+    }
+    if fast_mode {
+        FastModeConfig::from_config_map(config_feature_map).apply(&mut assistant_detail);
+    }
+    apply_offline_mode(
+        app_handle,
+        config_feature_map,
+        &mut assistant_detail,
+        allow_online,
+    )?;
+    Ok(assistant_detail)
+}
+
+// 裁剪上下文之后拼接助手的常驻上下文、技能和回答风格示例，只影响发给模型的内容，不写入对话记录；
+// 提问、预览、重新生成和继续生成都通过这里组装，request_prompt 用来挑选常驻上下文中与提问相关的片段
+fn append_assistant_prompts(
+    app_handle: &AppHandle,
+    assistant_id: i64,
+    request_prompt: &str,
+    config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+    message_list: &mut Vec<ChatMessage>,
+) {
+    append_assistant_context(
+        app_handle,
+        assistant_id,
+        request_prompt,
+        config_feature_map,
+        message_list,
+    );
+    append_assistant_skills(app_handle, assistant_id, message_list);
+    append_few_shot_examples(app_handle, assistant_id, config_feature_map, message_list);
+}
+
+// 重新生成和继续生成时最后一条用户消息就是这次回答的提问
+fn last_user_prompt(message_list: &[ChatMessage]) -> String {
+    message_list
+        .iter()
+        .rev()
+        .find(|message| message.role == Role::User)
+        .map(|message| message.text())
+        .unwrap_or_default()
+}
+
+// 把助手的常驻上下文拼接到本次请求的 system 消息后面，只影响发给模型的内容，不写入对话记录，
+// 修改助手的上下文后对已有对话同样生效。use_retrieval 的上下文按 attachment 的长度预算挑选与提问相关的片段
+fn append_assistant_context(
+    app_handle: &AppHandle,
+    assistant_id: i64,
+    request_prompt: &str,
+    config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+    message_list: &mut Vec<ChatMessage>,
+) {
+    let result = (|| -> Result<Vec<(String, String)>, AppError> {
+        let assistant_attachments =
+            AssistantDatabase::new(app_handle)?.get_assistant_attachments(assistant_id)?;
+        if assistant_attachments.is_empty() {
+            return Ok(vec![]);
+        }
+        let attachments = get_conversation_db(app_handle)?
+            .attachment_repo()?
+            .list_by_id(
+                &assistant_attachments
+                    .iter()
+                    .map(|a| a.attachment_id)
+                    .collect(),
+            )?;
+        let (retrieval, full): (Vec<&MessageAttachment>, Vec<&MessageAttachment>) = attachments
+            .iter()
+            .filter(|a| matches!(a.attachment_type, AttachmentType::Text))
+            .partition(|a| {
+                assistant_attachments
+                    .iter()
+                    .any(|item| item.attachment_id == a.id && item.use_retrieval)
+            });
+
+        let mut contents = full
+            .iter()
+            .map(|a| {
+                (
+                    a.attachment_url.clone().unwrap_or_default(),
+                    a.attachment_content.clone().unwrap_or_default(),
+                )
+            })
+            .collect::<Vec<(String, String)>>();
+        if !retrieval.is_empty() {
+            let max_length = config_feature_map
+                .get("attachment")
+                .and_then(|config| config.get("max_text_length"))
+                .and_then(|config| config.value.parse::<usize>().ok())
+                .unwrap_or(DEFAULT_MAX_ATTACHMENT_TEXT_LENGTH);
+            let (selected, _) = fit_attachments(
+                &retrieval,
+                request_prompt,
+                max_length,
+                TruncateStrategy::Rag,
+            );
+            contents.extend(selected);
+        }
+        Ok(contents)
+    })();
+    let contents = match result {
+        Ok(contents) if !contents.is_empty() => contents,
+        Ok(_) => return,
+        Err(e) => {
+            println!("load assistant {} context error: {:?}", assistant_id, e);
+            return;
+        }
+    };
+
+    let context = contents
+        .iter()
+        .map(|(name, content)| {
+            format!(
+                r#"<assistantcontext name="{}">{}</assistantcontext>"#,
+                name, content
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    match message_list
+        .iter_mut()
+        .find(|message| message.role == Role::System)
+    {
+        Some(message) => {
+            message.push_text(&format!("\n以下是需要始终遵循的参考资料：\n{}", context))
+        }
+        None => message_list.insert(
+            0,
+            ChatMessage::new(
+                Role::System,
+                format!("以下是需要始终遵循的参考资料：\n{}", context),
+            ),
+        ),
+    }
+}
+
+// 助手启用的技能按挂载顺序拼接到 system 消息后面，和助手的常驻上下文一样不写入对话记录
+fn append_assistant_skills(
+    app_handle: &AppHandle,
+    assistant_id: i64,
+    message_list: &mut Vec<ChatMessage>,
+) {
+    let Some(prompt) = skill_prompt(app_handle, assistant_id) else {
+        return;
+    };
+    match message_list
+        .iter_mut()
+        .find(|message| message.role == Role::System)
+    {
+        Some(message) => {
+            message.push_text(&format!("\n以下是你具备的技能，按需使用：\n{}", prompt))
+        }
+        None => message_list.insert(
+            0,
+            ChatMessage::new(
+                Role::System,
+                format!("以下是你具备的技能，按需使用：\n{}", prompt),
+            ),
+        ),
+    }
+}
+
+// 对话设置了回答风格时把对应的说明拼接到 system 消息后面，和助手的常驻上下文一样不写入对话记录；
+// 在裁剪上下文之前调用，说明的长度计入 context 的 max_length
+fn append_response_style(
+    app_handle: &AppHandle,
+    conversation_id: i64,
+    message_list: &mut Vec<(ChatMessage, bool)>,
+) {
+    let style = match get_conversation_db(app_handle).and_then(|db| {
+        db.style_repo()?
+            .get(conversation_id)
+            .map_err(AppError::from)
+    }) {
+        Ok(style) => style,
+        Err(e) => {
+            println!("load conversation {} style error: {:?}", conversation_id, e);
+            return;
+        }
+    };
+    let Some(instruction) = style.and_then(|style| style.instruction()) else {
+        return;
+    };
+    match message_list
+        .iter_mut()
+        .find(|(message, _)| message.role == Role::System)
+    {
+        Some((message, _)) => {
+            message.push_text(&format!("\n回答时遵循以下要求：\n{}", instruction))
+        }
+        None => message_list.insert(
+            0,
+            (
+                ChatMessage::new(
+                    Role::System,
+                    format!("回答时遵循以下要求：\n{}", instruction),
+                ),
+                false,
+            ),
+        ),
+    }
+}
+
+// 助手启用的回答风格示例作为一问一答插在 system 消息和对话历史之间，不写入对话记录；
+// 示例只使用 context 的 max_length 剩下的长度，放不下时丢弃排在后面的示例，不会因此丢弃历史消息
+fn append_few_shot_examples(
+    app_handle: &AppHandle,
+    assistant_id: i64,
+    config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+    message_list: &mut Vec<ChatMessage>,
+) {
+    let max_length = context_max_length(config_feature_map);
+    let used_length: usize = message_list
+        .iter()
+        .map(|message| message.text().chars().count())
+        .sum();
+    let available = (max_length > 0).then(|| max_length.saturating_sub(used_length));
+    let examples = fit_examples(enabled_examples(app_handle, assistant_id), available);
+    if examples.is_empty() {
+        return;
+    }
+    let index = message_list
+        .iter()
+        .take_while(|message| message.role == Role::System)
+        .count();
+    let example_messages = examples.into_iter().flat_map(|example| {
+        [
+            ChatMessage::new(Role::User, example.user_content),
+            ChatMessage::new(Role::Assistant, example.assistant_content),
+        ]
+    });
+    message_list.splice(index..index, example_messages);
+}
+
+// 文本附件以 fileattachment 标签拼接到提问后面，总长度超过 attachment 的 max_text_length 时
+// 按 truncate_strategy 截断（head 保留开头，rag 挑选与提问相关的片段）
+fn build_attachment_context(
+    message_attachment_list: &[MessageAttachment],
+    request_prompt: &str,
+    config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+) -> (String, Vec<AttachmentTruncation>) {
+    let config = config_feature_map.get("attachment");
+    let max_length = config
+        .and_then(|config| config.get("max_text_length"))
+        .and_then(|config| config.value.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_MAX_ATTACHMENT_TEXT_LENGTH);
+    let strategy = TruncateStrategy::from_config(
+        config
+            .and_then(|config| config.get("truncate_strategy"))
+            .map(|config| config.value.as_str())
+            .unwrap_or("head"),
+    );
+
+    let text_attachments = message_attachment_list
+        .iter()
+        .filter(|a| matches!(a.attachment_type, AttachmentType::Text))
+        .collect::<Vec<&MessageAttachment>>();
+    let (contents, truncations) =
+        fit_attachments(&text_attachments, request_prompt, max_length, strategy);
+    let injection_guard = InjectionGuard::from_config_map(config_feature_map);
+    let context = contents
+        .iter()
+        .map(|(name, content)| {
+            format!(
+                r#"<fileattachment name="{}">{}</fileattachment>"#,
+                name,
+                injection_guard.apply(&format!("附件 {}", name), content)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    (context, truncations)
+}
+
+// 图片超过模型单次请求上限时没有发送的图片，前端据此提示用户
+#[derive(Serialize, Clone, Debug)]
+pub struct ImagesOmittedEvent {
+    pub message_id: i64,
+    pub max_images: usize,
+    pub images: Vec<OmittedImage>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ContextOverflowEvent {
+    pub conversation_id: i64,
+    pub pinned_length: usize,
+    pub max_length: usize,
+}
+
+// 拼接对话的回答风格之后，按 context 的 max_length（字符数，0 表示不限制）从最早的历史消息开始丢弃，
+// system 消息、最后一条消息和置顶的消息总是保留；仅保留的内容就超出预算时发送 context_overflow 事件提醒用户
+fn apply_context_window(
+    app_handle: &AppHandle,
+    conversation_id: i64,
+    mut message_list: Vec<(ChatMessage, bool)>,
+    config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+) -> Vec<ChatMessage> {
+    append_response_style(app_handle, conversation_id, &mut message_list);
+    let max_length = context_max_length(config_feature_map);
+    let (message_list, pinned_length) = fit_context_window(message_list, max_length);
+    if let Some(pinned_length) = pinned_length {
+        println!(
+            "conversation {} pinned messages length {} exceeds context max_length {}",
+            conversation_id, pinned_length, max_length
+        );
+        let _ = app_handle.emit(
+            "context_overflow",
+            ContextOverflowEvent {
+                conversation_id,
+                pinned_length,
+                max_length,
+            },
+        );
+    }
+    message_list
+}
+
+fn context_max_length(
+    config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+) -> usize {
+    config_feature_map
+        .get("context")
+        .and_then(|config| config.get("max_length"))
+        .and_then(|config| config.value.parse::<usize>().ok())
+        .unwrap_or(0)
+}
+
+// 返回裁剪后的消息列表，必须保留的内容超出 max_length 时同时返回这部分内容的长度
+fn fit_context_window(
+    message_list: Vec<(ChatMessage, bool)>,
+    max_length: usize,
+) -> (Vec<ChatMessage>, Option<usize>) {
+    let last_index = message_list.len().saturating_sub(1);
+    let required = |index: usize, role: Role, pinned: bool| {
+        pinned || index == last_index || role == Role::System
+    };
+    let mut total_length: usize = message_list
+        .iter()
+        .map(|(message, _)| message.text().chars().count())
+        .sum();
+    let mut keep = vec![true; message_list.len()];
+    if max_length > 0 {
+        for (index, (message, pinned)) in message_list.iter().enumerate() {
+            if total_length <= max_length {
+                break;
+            }
+            if !required(index, message.role, *pinned) {
+                keep[index] = false;
+                total_length -= message.text().chars().count();
+            }
+        }
+    }
+
+    let overflow = if max_length > 0 && total_length > max_length {
+        Some(total_length)
+    } else {
+        None
+    };
+    let message_list = message_list
+        .into_iter()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|((message, _), _)| message)
+        .collect();
+    (message_list, overflow)
+}
+
+fn save_attachment_truncation(
+    db: &ConversationDatabase,
+    message_id: i64,
+    attachment_truncation: &Vec<AttachmentTruncation>,
+) -> Result<(), AppError> {
+    if attachment_truncation.is_empty() {
+        return Ok(());
+    }
+    let value = serde_json::to_string(attachment_truncation)
+        .map_err(|e| AppError::ParseError(e.to_string()))?;
+    db.message_repo()?
+        .update_attachment_truncation(message_id, Some(&value))?;
+    Ok(())
+}
+
+// 提问时检索到的全部来源保存在用户消息上，重新生成和继续生成时用来解析新回答中的引用
+fn save_citation_sources(
+    db: &ConversationDatabase,
+    message_id: i64,
+    citation_sources: &[Citation],
+) -> Result<(), AppError> {
+    if citation_sources.is_empty() {
+        return Ok(());
+    }
+    let value =
+        serde_json::to_string(citation_sources).map_err(|e| AppError::ParseError(e.to_string()))?;
+    db.message_repo()?
+        .update_citations(message_id, Some(&value))?;
+    Ok(())
+}
+
+// before_id 之前最近一条用户消息上保存的来源
+fn question_citation_sources(
+    messages: &[(Message, Option<MessageAttachment>)],
+    before_id: i64,
+) -> Vec<Citation> {
+    messages
+        .iter()
+        .map(|(message, _)| message)
+        .filter(|message| message.id < before_id && message.role() == Some(Role::User))
+        .max_by_key(|message| message.id)
+        .and_then(|message| message.citations.as_deref())
+        .and_then(|value| serde_json::from_str(value).ok())
+        .unwrap_or_default()
+}
+
+// 助手在 context_folder_ids 中配置了上下文文件夹时，检索相关的分块附加到用户的提问后面，
+// 分块按顺序编号并要求模型用 [n] 标注引用，同时返回编号对应的来源
+fn append_knowledge_context(
+    app_handle: &AppHandle,
+    assistant_detail: &AssistantDetail,
+    config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+    request_prompt: String,
+) -> (String, Vec<Citation>) {
+    let folder_ids = assistant_detail
+        .model_configs
+        .iter()
+        .find(|config| config.name == "context_folder_ids")
+        .and_then(|config| config.value.as_ref())
+        .map(|value| {
+            value
+                .split(',')
+                .filter_map(|id| id.trim().parse::<i64>().ok())
+                .collect::<Vec<i64>>()
+        })
+        .unwrap_or_default();
+    if folder_ids.is_empty() {
+        return (request_prompt, vec![]);
+    }
+    let top_k = config_feature_map
+        .get("knowledge")
+        .and_then(|config| config.get("top_k"))
+        .and_then(|config| config.value.parse::<usize>().ok())
+        .unwrap_or(5);
+
+    match retrieve(app_handle, &folder_ids, &request_prompt, top_k) {
+        Ok(chunks) if !chunks.is_empty() => {
+            let citation_sources = number_chunks(&chunks);
+            let injection_guard = InjectionGuard::from_config_map(config_feature_map);
+            let context = chunks
+                .iter()
+                .zip(citation_sources.iter())
+                .map(|(retrieved, source)| {
+                    format!(
+                        r#"<knowledge id="{}" source="{}">{}</knowledge>"#,
+                        source.index,
+                        escape_attribute(&retrieved.chunk.file_path),
+                        injection_guard.apply(
+                            &format!("知识库文件 {}", retrieved.chunk.file_path),
+                            &retrieved.chunk.content
+                        )
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("\n");
+            let prompt = format!(
+                "{}\n以下是从知识库中检索到的可能相关的内容，请参考这些内容回答。{}\n{}",
+                request_prompt, CITATION_INSTRUCTION, context
+            );
+            (prompt, citation_sources)
+        }
+        Ok(_) => (request_prompt, vec![]),
+        Err(e) => {
+            println!("retrieve knowledge error: {:?}", e);
+            (request_prompt, vec![])
+        }
+    }
+}
+
+// 记录用户提交的提示词，并按照 prompt_history 的 retention_days 配置清理过期的记录，失败不影响对话
+fn save_prompt_history(
+    app_handle: &AppHandle,
+    request: &AiRequest,
+    config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+) {
+    if request.prompt.trim().is_empty() {
+        return;
+    }
+    // 私密对话的提示词不进入历史记录，避免通过搜索看到私密内容
+    if let Ok(conversation_id) = request.conversation_id.parse::<i64>() {
+        if app_handle
+            .try_state::<PrivateConversationState>()
+            .is_some_and(|state| state.is_private(conversation_id))
+        {
+            return;
+        }
+    }
+    let retention_days = config_feature_map
+        .get("prompt_history")
+        .and_then(|config| config.get("retention_days"))
+        .and_then(|config| config.value.parse::<i64>().ok())
+        .unwrap_or(90);
+
+    let result = get_conversation_db(app_handle)
+        .and_then(|db| db.prompt_history_repo())
+        .and_then(|repo| {
+            repo.add(Some(request.assistant_id), &request.prompt)?;
+            // 0 表示永久保留
+            if retention_days > 0 {
+                repo.delete_before(chrono::Utc::now() - chrono::Duration::days(retention_days))?;
+            }
+            Ok(())
+        });
+    if let Err(e) = result {
+        println!("save prompt history error: {:?}", e);
+    }
+}
+
+// 数据库中的消息转换成发给提供商的消息，消息类型不是已知的角色时返回错误
+fn chat_message(
+    message: &Message,
+    attachments: Vec<MessageAttachment>,
+) -> Result<ChatMessage, AppError> {
+    ChatMessage::from_message_type(&message.message_type, message.content.clone(), attachments)
+        .map_err(AppError::ParseError)
+}
+
+fn speaker_chat_message(
+    message: &Message,
+    attachments: Vec<MessageAttachment>,
+    current_assistant_id: i64,
+    assistant_names: &HashMap<i64, String>,
+    quotes: &HashMap<i64, MessageQuote>,
+    injection_guard: &InjectionGuard,
+) -> Result<ChatMessage, AppError> {
+    // 引用的消息作为用户提供的内容发给模型，不管原来是提问还是回答
+    if message.message_type == QUOTE_MESSAGE_TYPE {
+        return Ok(ChatMessage::with_attachments(
+            Role::User,
+            quoted_content(
+                &injection_guard.apply("引用的消息", &message.content),
+                quotes.get(&message.id),
+            ),
+            attachments,
+        ));
+    }
+    match speaker_content(message, current_assistant_id, assistant_names) {
+        Some(content) => chat_message(
+            &Message {
+                content,
+                ..message.clone()
+            },
+            attachments,
+        ),
+        None => chat_message(message, attachments),
+    }
+}
+
+fn get_conversation_db(app_handle: &AppHandle) -> Result<ConversationDatabase, AppError> {
     ConversationDatabase::new(app_handle).map_err(AppError::from)
 }
 
-fn get_llm_db(app_handle: &tauri::AppHandle) -> Result<LLMDatabase, AppError> {
+fn get_llm_db(app_handle: &AppHandle) -> Result<LLMDatabase, AppError> {
     LLMDatabase::new(app_handle).map_err(AppError::from)
 }
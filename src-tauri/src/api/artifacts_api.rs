@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 
+use crate::AppHandle;
 use crate::FeatureConfigState;
 
 use crate::{
@@ -9,7 +10,7 @@ use crate::{
 };
 #[tauri::command]
 pub async fn run_artifacts(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
     state: tauri::State<'_, FeatureConfigState>,
     lang: &str,
     input_str: &str,
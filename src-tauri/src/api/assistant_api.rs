@@ -1,11 +1,22 @@
+use std::collections::HashMap;
+
+use crate::AppHandle;
 use crate::{
     db::{
+        assistant_config_schema::{
+            assistant_config_model, assistant_config_schema, validate_model_configs,
+            validate_preset_param, AssistantConfigSchema,
+        },
         assistant_db::{
-            Assistant, AssistantDatabase, AssistantModel, AssistantModelConfig, AssistantPrompt,
-            AssistantPromptParam,
+            Assistant, AssistantAttachment, AssistantDatabase, AssistantExample, AssistantFilter,
+            AssistantModel, AssistantModelConfig, AssistantPreset, AssistantPrompt,
+            AssistantPromptParam, AssistantSkill, Skill, BUILTIN_PRESETS,
         },
-        conversation_db::ConversationDatabase,
+        conversation_db::{ConversationDatabase, MessageAttachmentRepository},
+        llm_db::LLMDatabase,
     },
+    errors::AppError,
+    tools::find_tool,
     NameCacheState,
 };
 
@@ -19,7 +30,7 @@ pub struct AssistantDetail {
 }
 
 #[tauri::command]
-pub fn get_assistants(app_handle: tauri::AppHandle) -> Result<Vec<Assistant>, String> {
+pub fn get_assistants(app_handle: AppHandle) -> Result<Vec<Assistant>, String> {
     let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
     assistant_db
         .get_assistants()
@@ -27,11 +38,49 @@ pub fn get_assistants(app_handle: tauri::AppHandle) -> Result<Vec<Assistant>, St
         .map_err(|e| e.to_string())
 }
 
+/// 按分类、收藏和关键字筛选助手，默认收藏的在前，然后是使用次数多的
 #[tauri::command]
-pub fn get_assistant(
-    app_handle: tauri::AppHandle,
+pub fn list_assistants(
+    app_handle: AppHandle,
+    filter: Option<AssistantFilter>,
+) -> Result<Vec<Assistant>, String> {
+    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    assistant_db
+        .list_assistants(&filter.unwrap_or_default())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn favorite_assistant(
+    app_handle: AppHandle,
     assistant_id: i64,
-) -> Result<AssistantDetail, String> {
+    favorite: bool,
+) -> Result<(), String> {
+    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    assistant_db
+        .update_assistant_favorite(assistant_id, favorite)
+        .map_err(|e| e.to_string())
+}
+
+/// 设置助手的分类，传空字符串或者不传表示取消分类
+#[tauri::command]
+pub fn set_assistant_category(
+    app_handle: AppHandle,
+    assistant_id: i64,
+    category: Option<String>,
+) -> Result<(), String> {
+    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    let category = category
+        .as_deref()
+        .map(|category| category.trim())
+        .filter(|category| !category.is_empty());
+    assistant_db
+        .update_assistant_category(assistant_id, category)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_assistant(app_handle: AppHandle, assistant_id: i64) -> Result<AssistantDetail, String> {
     let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
 
     // 获取 Assistant 基本信息
@@ -78,11 +127,20 @@ pub fn get_assistant(
 
 #[tauri::command]
 pub async fn save_assistant(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
     name_cache_state: tauri::State<'_, NameCacheState>,
-    assistant_detail: AssistantDetail,
-) -> Result<(), String> {
-    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    mut assistant_detail: AssistantDetail,
+) -> Result<(), AppError> {
+    // 先检查参数，有不符合规则的参数时什么都不保存，返回所有错误给设置界面
+    let config_model =
+        assistant_config_model(&LLMDatabase::new(&app_handle)?, &assistant_detail.model);
+    validate_model_configs(
+        &mut assistant_detail.model_configs,
+        config_model
+            .as_ref()
+            .map(|(api_type, model_code)| (api_type.as_str(), model_code.as_str())),
+    )?;
+    let assistant_db = AssistantDatabase::new(&app_handle)?;
 
     println!(
         "save_assistant assistant_detail: {:?}",
@@ -91,30 +149,26 @@ pub async fn save_assistant(
 
     // Save or update the Assistant
     if assistant_detail.assistant.id == 0 {
-        assistant_db
-            .add_assistant(
-                &assistant_detail.assistant.name,
-                assistant_detail
-                    .assistant
-                    .description
-                    .as_deref()
-                    .unwrap_or(""),
-                assistant_detail.assistant.assistant_type,
-                true,
-            )
-            .map_err(|e| e.to_string())?;
+        assistant_db.add_assistant(
+            &assistant_detail.assistant.name,
+            assistant_detail
+                .assistant
+                .description
+                .as_deref()
+                .unwrap_or(""),
+            assistant_detail.assistant.assistant_type,
+            true,
+        )?;
     } else {
-        assistant_db
-            .update_assistant(
-                assistant_detail.assistant.id,
-                &assistant_detail.assistant.name,
-                assistant_detail
-                    .assistant
-                    .description
-                    .as_deref()
-                    .unwrap_or(""),
-            )
-            .map_err(|e| e.to_string())?;
+        assistant_db.update_assistant(
+            assistant_detail.assistant.id,
+            &assistant_detail.assistant.name,
+            assistant_detail
+                .assistant
+                .description
+                .as_deref()
+                .unwrap_or(""),
+        )?;
     }
 
     // Update the name_cache_state
@@ -127,94 +181,87 @@ pub async fn save_assistant(
     // Save or update the AssistantPrompts
     for prompt in assistant_detail.prompts {
         if prompt.id == 0 {
-            assistant_db
-                .add_assistant_prompt(prompt.assistant_id, &prompt.prompt)
-                .map_err(|e| e.to_string())?;
+            assistant_db.add_assistant_prompt(prompt.assistant_id, &prompt.prompt)?;
         } else {
-            assistant_db
-                .update_assistant_prompt(prompt.id, &prompt.prompt)
-                .map_err(|e| e.to_string())?;
+            assistant_db.update_assistant_prompt(prompt.id, &prompt.prompt)?;
         }
     }
 
     // Save or update the AssistantModels
     for mut model in assistant_detail.model {
         if model.id == 0 {
-            let result_id = assistant_db
-                .add_assistant_model(
-                    model.assistant_id,
-                    model.provider_id,
-                    &model.model_code,
-                    &model.alias,
-                )
-                .map_err(|e| e.to_string())?;
+            let result_id = assistant_db.add_assistant_model(
+                model.assistant_id,
+                model.provider_id,
+                &model.model_code,
+                &model.alias,
+            )?;
             model.id = result_id;
         } else {
-            assistant_db
-                .update_assistant_model(
-                    model.id,
-                    model.provider_id,
-                    &model.model_code,
-                    &model.alias,
-                )
-                .map_err(|e| e.to_string())?;
+            assistant_db.update_assistant_model(
+                model.id,
+                model.provider_id,
+                &model.model_code,
+                &model.alias,
+            )?;
         }
     }
 
     // Save or update the AssistantModelConfigs
     for mut config in assistant_detail.model_configs {
         if config.id == 0 {
-            let result_id = assistant_db
-                .add_assistant_model_config(
-                    config.assistant_id,
-                    config.id,
-                    &config.name,
-                    config.value.as_deref().unwrap_or(""),
-                    &config.value_type,
-                )
-                .map_err(|e| e.to_string())?;
+            let result_id = assistant_db.add_assistant_model_config(
+                config.assistant_id,
+                config.id,
+                &config.name,
+                config.value.as_deref().unwrap_or(""),
+                &config.value_type,
+            )?;
             config.id = result_id;
         } else {
-            assistant_db
-                .update_assistant_model_config(
-                    config.id,
-                    &config.name,
-                    config.value.as_deref().unwrap_or(""),
-                )
-                .map_err(|e| e.to_string())?;
+            assistant_db.update_assistant_model_config(
+                config.id,
+                &config.name,
+                config.value.as_deref().unwrap_or(""),
+            )?;
         }
     }
 
     // Save or update the AssistantPromptParams
     for param in assistant_detail.prompt_params {
         if param.id == 0 {
-            assistant_db
-                .add_assistant_prompt_param(
-                    param.assistant_id,
-                    param.assistant_prompt_id,
-                    &param.param_name,
-                    param.param_type.as_deref().unwrap_or(""),
-                    param.param_value.as_deref().unwrap_or(""),
-                )
-                .map_err(|e| e.to_string())?;
+            assistant_db.add_assistant_prompt_param(
+                param.assistant_id,
+                param.assistant_prompt_id,
+                &param.param_name,
+                param.param_type.as_deref().unwrap_or(""),
+                param.param_value.as_deref().unwrap_or(""),
+            )?;
         } else {
-            assistant_db
-                .update_assistant_prompt_param(
-                    param.id,
-                    &param.param_name,
-                    param.param_type.as_deref().unwrap_or(""),
-                    param.param_value.as_deref().unwrap_or(""),
-                )
-                .map_err(|e| e.to_string())?;
+            assistant_db.update_assistant_prompt_param(
+                param.id,
+                &param.param_name,
+                param.param_type.as_deref().unwrap_or(""),
+                param.param_value.as_deref().unwrap_or(""),
+            )?;
         }
     }
 
     Ok(())
 }
 
+/// 助手模型参数的规则，设置界面按规则显示输入框和取值范围，数值参数的范围按选中的模型返回
+#[tauri::command]
+pub fn get_assistant_config_schema(
+    api_type: Option<String>,
+    model_code: Option<String>,
+) -> AssistantConfigSchema {
+    assistant_config_schema(api_type.as_deref().zip(model_code.as_deref()))
+}
+
 #[tauri::command]
 pub fn add_assistant(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
     name: String,
     description: String,
     assistant_type: i64,
@@ -329,52 +376,47 @@ pub fn add_assistant(
 }
 
 #[tauri::command]
-pub fn copy_assistant(
-    app_handle: tauri::AppHandle,
+pub fn copy_assistant(app_handle: AppHandle, assistant_id: i64) -> Result<AssistantDetail, String> {
+    // 和克隆助手相同，完整复制提示词参数、附件、技能和回答风格示例
+    clone_assistant(app_handle, assistant_id, None)
+}
+
+/// 完整复制一个助手，包括提示词参数和助手级别的模型配置，用于基于现有助手做变体或者对比实验
+#[tauri::command]
+pub fn clone_assistant(
+    app_handle: AppHandle,
     assistant_id: i64,
+    name: Option<String>,
 ) -> Result<AssistantDetail, String> {
-    println!("Start copying assistant with ID: {}", assistant_id);
+    let original = get_assistant(app_handle.clone(), assistant_id)?;
     let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
 
-    // Get the original assistant
-    let original_assistant = assistant_db
-        .get_assistant(assistant_id)
-        .map_err(|e| e.to_string())?;
-
-    // Create a new assistant based on the original
+    let name = name
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or_else(|| format!("副本 {}", original.assistant.name));
     let new_assistant_id = assistant_db
         .add_assistant(
-            &format!("副本 {}", original_assistant.name),
-            &original_assistant.description.unwrap(),
-            original_assistant.assistant_type,
-            original_assistant.is_addition,
+            &name,
+            original.assistant.description.as_deref().unwrap_or(""),
+            original.assistant.assistant_type,
+            original.assistant.is_addition,
         )
         .map_err(|e| e.to_string())?;
-
-    // Copy prompts
-    let original_prompts = assistant_db
-        .get_assistant_prompt(assistant_id)
+    assistant_db
+        .update_assistant_category(new_assistant_id, original.assistant.category.as_deref())
         .map_err(|e| e.to_string())?;
-    let mut new_prompts = Vec::new();
-    for prompt in original_prompts {
+
+    let mut prompt_id_map = HashMap::new();
+    for prompt in original.prompts.iter() {
         let new_prompt_id = assistant_db
             .add_assistant_prompt(new_assistant_id, &prompt.prompt)
             .map_err(|e| e.to_string())?;
-        new_prompts.push(AssistantPrompt {
-            id: new_prompt_id,
-            assistant_id: new_assistant_id,
-            prompt: prompt.prompt,
-            created_time: None,
-        });
+        prompt_id_map.insert(prompt.id, new_prompt_id);
     }
 
-    // Copy models and their configs
-    let original_models = assistant_db
-        .get_assistant_model(assistant_id)
-        .map_err(|e| e.to_string())?;
-    let mut new_models = Vec::new();
-    let mut new_model_configs = Vec::new();
-    for model in original_models {
+    // assistant_model_id 为 -1 的配置属于助手本身
+    let mut model_id_map = HashMap::from([(-1, -1)]);
+    for model in original.model.iter() {
         let new_model_id = assistant_db
             .add_assistant_model(
                 new_assistant_id,
@@ -383,62 +425,89 @@ pub fn copy_assistant(
                 &model.alias,
             )
             .map_err(|e| e.to_string())?;
-        new_models.push(AssistantModel {
-            id: new_model_id,
-            assistant_id: new_assistant_id,
-            provider_id: model.provider_id,
-            model_code: model.model_code,
-            alias: model.alias,
-        });
+        model_id_map.insert(model.id, new_model_id);
+    }
+
+    for config in original.model_configs.iter() {
+        let Some(new_model_id) = model_id_map.get(&config.assistant_model_id) else {
+            continue;
+        };
+        assistant_db
+            .add_assistant_model_config(
+                new_assistant_id,
+                *new_model_id,
+                &config.name,
+                config.value.as_deref().unwrap_or(""),
+                &config.value_type,
+            )
+            .map_err(|e| e.to_string())?;
+    }
+
+    for param in original.prompt_params.iter() {
+        let Some(new_prompt_id) = prompt_id_map.get(&param.assistant_prompt_id) else {
+            continue;
+        };
+        assistant_db
+            .add_assistant_prompt_param(
+                new_assistant_id,
+                *new_prompt_id,
+                &param.param_name,
+                param.param_type.as_deref().unwrap_or(""),
+                param.param_value.as_deref().unwrap_or(""),
+            )
+            .map_err(|e| e.to_string())?;
+    }
 
-        // Copy model configs
-        let original_configs = assistant_db
-            .get_assistant_model_configs_with_model_id(assistant_id, model.id)
+    let attachment_repo = assistant_attachment_repo(&app_handle)?;
+    for attachment in assistant_db
+        .get_assistant_attachments(assistant_id)
+        .map_err(|e| e.to_string())?
+    {
+        assistant_db
+            .add_assistant_attachment(
+                new_assistant_id,
+                attachment.attachment_id,
+                attachment.use_retrieval,
+            )
             .map_err(|e| e.to_string())?;
-        for config in original_configs {
-            let new_config_id = assistant_db
-                .add_assistant_model_config(
+        attachment_repo
+            .add_assistant_reference(new_assistant_id, attachment.attachment_id)
+            .map_err(|e| e.to_string())?;
+    }
+
+    for assistant_skill in assistant_db
+        .get_assistant_skills(assistant_id)
+        .map_err(|e| e.to_string())?
+    {
+        assistant_db
+            .add_assistant_skill(new_assistant_id, assistant_skill.skill.id)
+            .and_then(|_| {
+                assistant_db.update_assistant_skill_enabled(
                     new_assistant_id,
-                    new_model_id,
-                    &config.name,
-                    config.value.as_deref().unwrap_or(""),
-                    &config.value_type,
+                    assistant_skill.skill.id,
+                    assistant_skill.is_enabled,
                 )
-                .map_err(|e| e.to_string())?;
-            new_model_configs.push(AssistantModelConfig {
-                id: new_config_id,
-                assistant_id: new_assistant_id,
-                assistant_model_id: new_model_id,
-                name: config.name,
-                value: config.value,
-                value_type: config.value_type,
-            });
-        }
+            })
+            .map_err(|e| e.to_string())?;
     }
 
-    // Get the newly created assistant
-    let new_assistant = assistant_db
-        .get_assistant(new_assistant_id)
-        .map_err(|e| e.to_string())?;
-
-    // Build AssistantDetail object
-    let assistant_detail = AssistantDetail {
-        assistant: new_assistant,
-        prompts: new_prompts,
-        model: new_models,
-        model_configs: new_model_configs,
-        prompt_params: Vec::new(), // Assuming prompt_params are not copied
-    };
+    for example in assistant_db
+        .get_assistant_examples(assistant_id)
+        .map_err(|e| e.to_string())?
+    {
+        assistant_db
+            .add_assistant_example(&AssistantExample {
+                assistant_id: new_assistant_id,
+                ..example
+            })
+            .map_err(|e| e.to_string())?;
+    }
 
-    println!(
-        "Successfully copied assistant. New assistant ID: {}",
-        new_assistant_id
-    );
-    Ok(assistant_detail)
+    get_assistant(app_handle, new_assistant_id)
 }
 
 #[tauri::command]
-pub fn delete_assistant(app_handle: tauri::AppHandle, assistant_id: i64) -> Result<(), String> {
+pub fn delete_assistant(app_handle: AppHandle, assistant_id: i64) -> Result<(), String> {
     let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
     let _ = assistant_db
         .delete_assistant_model_config_by_assistant_id(assistant_id)
@@ -449,6 +518,21 @@ pub fn delete_assistant(app_handle: tauri::AppHandle, assistant_id: i64) -> Resu
     let _ = assistant_db
         .delete_assistant_prompt_param_by_assistant_id(assistant_id)
         .map_err(|e| e.to_string());
+    let _ = assistant_db
+        .delete_assistant_attachment_by_assistant_id(assistant_id)
+        .map_err(|e| e.to_string());
+    let _ = assistant_db
+        .delete_assistant_preset_by_assistant_id(assistant_id)
+        .map_err(|e| e.to_string());
+    let _ = assistant_db
+        .delete_assistant_skill_by_assistant_id(assistant_id)
+        .map_err(|e| e.to_string());
+    let _ = assistant_db
+        .delete_assistant_example_by_assistant_id(assistant_id)
+        .map_err(|e| e.to_string());
+    let _ = assistant_db
+        .delete_quick_action_by_assistant_id(assistant_id)
+        .map_err(|e| e.to_string());
 
     let conversation_db = ConversationDatabase::new(&app_handle).map_err(|e| e.to_string())?;
     let _ = conversation_db
@@ -456,15 +540,516 @@ pub fn delete_assistant(app_handle: tauri::AppHandle, assistant_id: i64) -> Resu
         .unwrap()
         .update_assistant_id(assistant_id, Some(1))
         .map_err(|e| e.to_string())?;
+    // 助手的常驻上下文不再引用的附件随助手一起删除
+    conversation_db
+        .attachment_repo()
+        .map_err(|e| e.to_string())?
+        .remove_assistant_reference(assistant_id, None)
+        .map_err(|e| e.to_string())?;
 
     assistant_db
         .delete_assistant(assistant_id)
         .map_err(|e| e.to_string())
 }
 
+/// 助手可以选择的参数预设，包括内置的 precise、balanced、creative 和自定义的预设
+#[tauri::command]
+pub fn list_assistant_presets(
+    app_handle: AppHandle,
+    assistant_id: i64,
+) -> Result<Vec<AssistantPreset>, String> {
+    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    assistant_db
+        .get_assistant_presets(assistant_id)
+        .map_err(|e| e.to_string())
+}
+
+/// 保存自定义预设，和内置预设同名时覆盖内置预设的参数，参数需要是可调的模型参数，
+/// 数值按助手当前模型的范围检查
+#[tauri::command]
+pub fn save_assistant_preset(
+    app_handle: AppHandle,
+    assistant_id: i64,
+    name: String,
+    params: HashMap<String, String>,
+) -> Result<(), String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("预设名称不能为空".to_string());
+    }
+    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    let llm_db = LLMDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    let models = assistant_db
+        .get_assistant_model(assistant_id)
+        .map_err(|e| e.to_string())?;
+    let config_model = assistant_config_model(&llm_db, &models);
+    for (param_name, value) in &params {
+        validate_preset_param(
+            param_name,
+            value,
+            config_model
+                .as_ref()
+                .map(|(api_type, model_code)| (api_type.as_str(), model_code.as_str())),
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    assistant_db
+        .save_assistant_preset(assistant_id, name, &params)
+        .map_err(|e| e.to_string())
+}
+
+/// 删除自定义预设，内置预设只会恢复默认参数，正在使用的预设被删除时取消选中
+#[tauri::command]
+pub fn delete_assistant_preset(
+    app_handle: AppHandle,
+    assistant_id: i64,
+    name: String,
+) -> Result<(), String> {
+    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    assistant_db
+        .delete_assistant_preset(assistant_id, &name)
+        .map_err(|e| e.to_string())?;
+    let assistant = assistant_db
+        .get_assistant(assistant_id)
+        .map_err(|e| e.to_string())?;
+    let is_builtin = BUILTIN_PRESETS.iter().any(|(preset, _, _)| *preset == name);
+    if !is_builtin && assistant.active_preset.as_deref() == Some(name.as_str()) {
+        assistant_db
+            .update_assistant_active_preset(assistant_id, None)
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// 设置助手当前使用的预设，不传表示不使用预设，直接使用助手配置的模型参数
+#[tauri::command]
+pub fn set_active_preset(
+    app_handle: AppHandle,
+    assistant_id: i64,
+    preset: Option<String>,
+) -> Result<(), String> {
+    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    let preset = preset.filter(|preset| !preset.is_empty());
+    if let Some(preset) = preset.as_ref() {
+        let presets = assistant_db
+            .get_assistant_presets(assistant_id)
+            .map_err(|e| e.to_string())?;
+        if !presets.iter().any(|p| &p.name == preset) {
+            return Err(format!("预设 {} 不存在", preset));
+        }
+    }
+    assistant_db
+        .update_assistant_active_preset(assistant_id, preset.as_deref())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn list_skills(app_handle: AppHandle) -> Result<Vec<Skill>, String> {
+    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    assistant_db.get_skills().map_err(|e| e.to_string())
+}
+
+/// 新增或修改技能，id 为 0 时新增，返回技能的 id
+#[tauri::command]
+pub fn save_skill(app_handle: AppHandle, mut skill: Skill) -> Result<i64, String> {
+    skill.name = skill.name.trim().to_string();
+    if skill.name.is_empty() {
+        return Err("技能名称不能为空".to_string());
+    }
+    if skill.prompt.trim().is_empty() {
+        return Err("技能提示词不能为空".to_string());
+    }
+    if let Some(tool) = skill
+        .required_tools
+        .iter()
+        .find(|tool| find_tool(tool).is_none())
+    {
+        return Err(format!("未知的工具 {}", tool));
+    }
+    skill.preferred_model_code = skill
+        .preferred_model_code
+        .filter(|code| !code.trim().is_empty());
+    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    if skill.id == 0 {
+        assistant_db.add_skill(&skill).map_err(|e| e.to_string())
+    } else {
+        assistant_db
+            .update_skill(&skill)
+            .map_err(|e| e.to_string())?;
+        Ok(skill.id)
+    }
+}
+
+/// 删除技能，同时从所有助手上移除
+#[tauri::command]
+pub fn delete_skill(app_handle: AppHandle, skill_id: i64) -> Result<(), String> {
+    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    assistant_db
+        .delete_skill(skill_id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn list_assistant_skills(
+    app_handle: AppHandle,
+    assistant_id: i64,
+) -> Result<Vec<AssistantSkill>, String> {
+    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    assistant_db
+        .get_assistant_skills(assistant_id)
+        .map_err(|e| e.to_string())
+}
+
+/// 给助手挂载技能，挂载后默认启用
+#[tauri::command]
+pub fn add_assistant_skill(
+    app_handle: AppHandle,
+    assistant_id: i64,
+    skill_id: i64,
+) -> Result<Vec<AssistantSkill>, String> {
+    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    assistant_db
+        .add_assistant_skill(assistant_id, skill_id)
+        .map_err(|e| e.to_string())?;
+    list_assistant_skills(app_handle, assistant_id)
+}
+
+#[tauri::command]
+pub fn remove_assistant_skill(
+    app_handle: AppHandle,
+    assistant_id: i64,
+    skill_id: i64,
+) -> Result<(), String> {
+    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    assistant_db
+        .delete_assistant_skill(assistant_id, skill_id)
+        .map_err(|e| e.to_string())
+}
+
+/// 启用或停用助手上的技能，停用的技能保留挂载关系，但提问时不会使用
+#[tauri::command]
+pub fn set_assistant_skill_enabled(
+    app_handle: AppHandle,
+    assistant_id: i64,
+    skill_id: i64,
+    enabled: bool,
+) -> Result<(), String> {
+    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    assistant_db
+        .update_assistant_skill_enabled(assistant_id, skill_id, enabled)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn list_assistant_examples(
+    app_handle: AppHandle,
+    assistant_id: i64,
+) -> Result<Vec<AssistantExample>, String> {
+    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    assistant_db
+        .get_assistant_examples(assistant_id)
+        .map_err(|e| e.to_string())
+}
+
+/// 新增或修改回答风格示例，id 为 0 时新增到最后，返回助手的全部示例
+#[tauri::command]
+pub fn save_assistant_example(
+    app_handle: AppHandle,
+    example: AssistantExample,
+) -> Result<Vec<AssistantExample>, String> {
+    if example.user_content.trim().is_empty() || example.assistant_content.trim().is_empty() {
+        return Err("示例的提问和回答不能为空".to_string());
+    }
+    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    if example.id == 0 {
+        assistant_db.add_assistant_example(&example)
+    } else {
+        assistant_db
+            .update_assistant_example(&example)
+            .map(|_| example.id)
+    }
+    .map_err(|e| e.to_string())?;
+    list_assistant_examples(app_handle, example.assistant_id)
+}
+
+#[tauri::command]
+pub fn delete_assistant_example(app_handle: AppHandle, example_id: i64) -> Result<(), String> {
+    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    assistant_db
+        .delete_assistant_example(example_id)
+        .map_err(|e| e.to_string())
+}
+
+/// 按 example_ids 的顺序排列示例，提问时按这个顺序放在对话历史前面
+#[tauri::command]
+pub fn reorder_assistant_examples(
+    app_handle: AppHandle,
+    assistant_id: i64,
+    example_ids: Vec<i64>,
+) -> Result<Vec<AssistantExample>, String> {
+    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    assistant_db
+        .reorder_assistant_examples(assistant_id, &example_ids)
+        .map_err(|e| e.to_string())?;
+    list_assistant_examples(app_handle, assistant_id)
+}
+
+/// 助手启用的回答风格示例，按排序返回，读取失败时当作没有示例
+pub fn enabled_examples(app_handle: &AppHandle, assistant_id: i64) -> Vec<AssistantExample> {
+    match AssistantDatabase::new(app_handle).and_then(|db| db.get_assistant_examples(assistant_id))
+    {
+        Ok(examples) => examples
+            .into_iter()
+            .filter(|example| example.is_enabled)
+            .collect(),
+        Err(e) => {
+            println!("get_assistant_examples error: {:?}", e);
+            vec![]
+        }
+    }
+}
+
+/// 按顺序保留放得下的示例，available 为剩余可用的字符数，None 表示不限制；
+/// 一组示例放不下时丢弃它和排在后面的所有示例，排在前面的示例优先保留
+pub fn fit_examples(
+    examples: Vec<AssistantExample>,
+    available: Option<usize>,
+) -> Vec<AssistantExample> {
+    let Some(mut available) = available else {
+        return examples;
+    };
+    examples
+        .into_iter()
+        .take_while(|example| {
+            let length =
+                example.user_content.chars().count() + example.assistant_content.chars().count();
+            if length > available {
+                return false;
+            }
+            available -= length;
+            true
+        })
+        .collect()
+}
+
+// 助手启用的技能，读取失败时当作没有技能
+fn enabled_skills(app_handle: &AppHandle, assistant_id: i64) -> Vec<Skill> {
+    match AssistantDatabase::new(app_handle).and_then(|db| db.get_assistant_skills(assistant_id)) {
+        Ok(skills) => skills
+            .into_iter()
+            .filter(|assistant_skill| assistant_skill.is_enabled)
+            .map(|assistant_skill| assistant_skill.skill)
+            .collect(),
+        Err(e) => {
+            println!("get_assistant_skills error: {:?}", e);
+            vec![]
+        }
+    }
+}
+
+/// 启用的技能推荐了模型时，改用排在最前面的技能推荐的模型
+pub fn apply_skill_model(app_handle: &AppHandle, assistant_detail: &mut AssistantDetail) {
+    let skills = enabled_skills(app_handle, assistant_detail.assistant.id);
+    let preferred = skills.iter().find_map(|skill| {
+        let provider_id = skill.preferred_provider_id?;
+        let model_code = skill.preferred_model_code.as_deref()?;
+        Some((provider_id, model_code))
+    });
+    let Some((provider_id, model_code)) = preferred else {
+        return;
+    };
+    if let Some(model) = assistant_detail.model.first_mut() {
+        model.provider_id = provider_id;
+        model.model_code = model_code.to_string();
+    }
+}
+
+/// 把助手启用的技能组合成一段 system 提示词，没有启用的技能时返回 None
+pub fn skill_prompt(app_handle: &AppHandle, assistant_id: i64) -> Option<String> {
+    compose_skill_prompt(&enabled_skills(app_handle, assistant_id))
+}
+
+/// 助手启用的技能需要的工具，按挂载顺序去重，和助手配置的工具一起提供给模型
+pub fn skill_tools(app_handle: &AppHandle, assistant_id: i64) -> Vec<String> {
+    let mut tools: Vec<String> = Vec::new();
+    for tool in enabled_skills(app_handle, assistant_id)
+        .into_iter()
+        .flat_map(|skill| skill.required_tools)
+    {
+        if !tools.contains(&tool) {
+            tools.push(tool);
+        }
+    }
+    tools
+}
+
+// 按挂载的顺序拼接，同样的技能组合每次得到的提示词相同
+fn compose_skill_prompt(skills: &[Skill]) -> Option<String> {
+    if skills.is_empty() {
+        return None;
+    }
+    let sections = skills
+        .iter()
+        .map(|skill| {
+            let mut section = format!("<skill name=\"{}\">\n{}", skill.name, skill.prompt.trim());
+            if !skill.required_tools.is_empty() {
+                section.push_str(&format!(
+                    "\n需要使用的工具：{}",
+                    skill.required_tools.join(", ")
+                ));
+            }
+            section.push_str("\n</skill>");
+            section
+        })
+        .collect::<Vec<String>>();
+    Some(sections.join("\n"))
+}
+
+/// 用助手当前预设中的参数覆盖模型参数，助手没有这个参数时按数值类型新增
+pub fn apply_active_preset(app_handle: &AppHandle, assistant_detail: &mut AssistantDetail) {
+    let Some(preset_name) = assistant_detail.assistant.active_preset.clone() else {
+        return;
+    };
+    let presets = AssistantDatabase::new(app_handle)
+        .and_then(|db| db.get_assistant_presets(assistant_detail.assistant.id));
+    let preset = match presets {
+        Ok(presets) => presets.into_iter().find(|p| p.name == preset_name),
+        Err(e) => {
+            println!("get_assistant_presets error: {:?}", e);
+            None
+        }
+    };
+    let Some(preset) = preset else {
+        return;
+    };
+    for (name, value) in preset.params {
+        match assistant_detail
+            .model_configs
+            .iter_mut()
+            .find(|config| config.name == name)
+        {
+            Some(config) => config.value = Some(value),
+            None => {
+                let value_type = if value.parse::<i64>().is_ok() {
+                    "number"
+                } else if value.parse::<f64>().is_ok() {
+                    "float"
+                } else {
+                    "string"
+                };
+                assistant_detail.model_configs.push(AssistantModelConfig {
+                    id: 0,
+                    assistant_id: assistant_detail.assistant.id,
+                    assistant_model_id: -1,
+                    name,
+                    value: Some(value),
+                    value_type: value_type.to_string(),
+                });
+            }
+        }
+    }
+}
+
+/// 助手的常驻上下文，附件需要先通过 add_attachment 添加，每次使用这个助手提问时都会自动注入
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+pub struct AssistantContextItem {
+    pub id: i64,
+    pub attachment_id: i64,
+    pub name: String,
+    pub use_retrieval: bool,
+    pub created_time: String,
+}
+
+#[tauri::command]
+pub fn get_assistant_context_items(
+    app_handle: AppHandle,
+    assistant_id: i64,
+) -> Result<Vec<AssistantContextItem>, String> {
+    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    let attachments = assistant_db
+        .get_assistant_attachments(assistant_id)
+        .map_err(|e| e.to_string())?;
+    let conversation_db = ConversationDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    let attachment_repo = conversation_db
+        .attachment_repo()
+        .map_err(|e| e.to_string())?;
+    let message_attachments = attachment_repo
+        .list_by_id(&attachments.iter().map(|a| a.attachment_id).collect())
+        .map_err(|e| e.to_string())?;
+
+    Ok(attachments
+        .into_iter()
+        .map(|attachment: AssistantAttachment| {
+            let name = message_attachments
+                .iter()
+                .find(|a| a.id == attachment.attachment_id)
+                .and_then(|a| a.attachment_url.clone())
+                .unwrap_or_else(|| "未知附件".to_string());
+            AssistantContextItem {
+                id: attachment.id,
+                attachment_id: attachment.attachment_id,
+                name,
+                use_retrieval: attachment.use_retrieval,
+                created_time: attachment.created_time,
+            }
+        })
+        .collect())
+}
+
+#[tauri::command]
+pub fn add_assistant_context_item(
+    app_handle: AppHandle,
+    assistant_id: i64,
+    attachment_id: i64,
+    use_retrieval: bool,
+) -> Result<Vec<AssistantContextItem>, String> {
+    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    let exists = assistant_db
+        .get_assistant_attachments(assistant_id)
+        .map_err(|e| e.to_string())?
+        .iter()
+        .any(|a| a.attachment_id == attachment_id);
+    if !exists {
+        assistant_db
+            .add_assistant_attachment(assistant_id, attachment_id, use_retrieval)
+            .map_err(|e| e.to_string())?;
+        assistant_attachment_repo(&app_handle)?
+            .add_assistant_reference(assistant_id, attachment_id)
+            .map_err(|e| e.to_string())?;
+    }
+    get_assistant_context_items(app_handle, assistant_id)
+}
+
+#[tauri::command]
+pub fn remove_assistant_context_item(app_handle: AppHandle, id: i64) -> Result<(), String> {
+    let assistant_db = AssistantDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    let Some(attachment) = assistant_db
+        .get_assistant_attachment(id)
+        .map_err(|e| e.to_string())?
+    else {
+        return Ok(());
+    };
+    assistant_db
+        .delete_assistant_attachment(id)
+        .map_err(|e| e.to_string())?;
+    assistant_attachment_repo(&app_handle)?
+        .remove_assistant_reference(attachment.assistant_id, Some(attachment.attachment_id))
+        .map_err(|e| e.to_string())
+}
+
+// 常驻上下文的附件保存在对话数据库中，助手对附件的引用也记录在那里
+fn assistant_attachment_repo(
+    app_handle: &AppHandle,
+) -> Result<MessageAttachmentRepository, String> {
+    ConversationDatabase::new(app_handle)
+        .map_err(|e| e.to_string())?
+        .attachment_repo()
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub fn get_assistant_field_value(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
     assistant_id: i64,
     field_name: &str,
 ) -> Result<String, String> {
@@ -499,3 +1084,60 @@ pub fn get_assistant_field_value(
         .and_then(|config| config.value.clone())
         .ok_or_else(|| format!("Field '{}' not found", field_name))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn skill(name: &str, prompt: &str, required_tools: &[&str]) -> Skill {
+        Skill {
+            id: 0,
+            name: name.to_string(),
+            description: None,
+            prompt: prompt.to_string(),
+            required_tools: required_tools.iter().map(|t| t.to_string()).collect(),
+            preferred_provider_id: None,
+            preferred_model_code: None,
+            created_time: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_fit_examples() {
+        let example = |id: i64, user_content: &str, assistant_content: &str| AssistantExample {
+            id,
+            assistant_id: 1,
+            user_content: user_content.to_string(),
+            assistant_content: assistant_content.to_string(),
+            is_enabled: true,
+            sort_order: id,
+            created_time: String::new(),
+        };
+        let examples = vec![
+            example(1, "你好", "你好！"),
+            example(2, "很长的提问", "很长很长的回答"),
+            example(3, "短", "短"),
+        ];
+        let ids =
+            |examples: Vec<AssistantExample>| examples.iter().map(|e| e.id).collect::<Vec<_>>();
+        assert_eq!(ids(fit_examples(examples.clone(), None)), vec![1, 2, 3]);
+        assert_eq!(ids(fit_examples(examples.clone(), Some(17))), vec![1, 2]);
+        // 第二组放不下时后面的示例也不再使用，保持示例的顺序
+        assert_eq!(ids(fit_examples(examples.clone(), Some(10))), vec![1]);
+        assert!(fit_examples(examples, Some(0)).is_empty());
+    }
+
+    #[test]
+    fn test_compose_skill_prompt() {
+        assert_eq!(compose_skill_prompt(&[]), None);
+        let skills = vec![
+            skill("代码审查", "指出代码中的问题\n", &["read_file"]),
+            skill("简洁", "回答尽量简短", &[]),
+        ];
+        assert_eq!(
+            compose_skill_prompt(&skills).unwrap(),
+            "<skill name=\"代码审查\">\n指出代码中的问题\n需要使用的工具：read_file\n</skill>\n\
+             <skill name=\"简洁\">\n回答尽量简短\n</skill>"
+        );
+    }
+}
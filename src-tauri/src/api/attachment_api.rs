@@ -1,14 +1,23 @@
 use crate::db::conversation_db::{AttachmentType, Repository};
 use anyhow::{anyhow, Result};
+use arboard::Clipboard;
 use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Local, Utc};
+use image::{ImageFormat, RgbaImage};
 use mime_guess::from_path;
 use serde::Serialize;
 use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Cursor, Read};
 use std::path::Path;
+use tauri::Manager;
 use tauri_plugin_opener::OpenerExt;
 
+use crate::knowledge::attachment_policy::AttachmentPolicy;
+use crate::knowledge::ocr::decode_image_content;
+use crate::knowledge::text_encoding::decode_text;
+use crate::{AppHandle, FeatureConfigState};
 use crate::{
     db::conversation_db::{ConversationDatabase, MessageAttachment},
     errors::AppError,
@@ -21,7 +30,7 @@ pub struct AttachmentResult {
 
 #[tauri::command]
 pub async fn add_attachment(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
     file_url: Option<String>,
     file_content: Option<String>,
     file_name: Option<String>,
@@ -46,7 +55,7 @@ pub async fn add_attachment(
 }
 
 pub async fn add_attachment_by_url(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
     file_url: String,
 ) -> Result<AttachmentResult, AppError> {
     // 1. 解析文件路径
@@ -70,9 +79,17 @@ pub async fn add_attachment_by_url(
     }
     println!("文件类型大类: {}", file_type_classify);
 
+    // 读取文件之前先检查扩展名和大小，配置了扫描程序时扫描原始文件
+    let policy = attachment_policy(&app_handle).await;
+    policy.check_file(&file_url, file_path.metadata()?.len())?;
+    if policy.has_scanner() {
+        policy.scan(&file_url, std::fs::read(&file_path)?).await?;
+    }
+
     let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
 
     // 4. 使用不同类型的文件读取方式来进行读取
+    let mut encoding = None;
     let reader = match file_type_classify.as_str() {
         "image" => {
             // 使用 BufReader 读取图片文件
@@ -91,10 +108,10 @@ pub async fn add_attachment_by_url(
             }
         }
         "text" => {
-            // 读取文本文件
-            let mut file = File::open(file_path)?;
-            let mut content = String::new();
-            file.read_to_string(&mut content)?;
+            // 读取文本文件，GBK、Shift_JIS 等非 UTF-8 编码的文件先转换成 UTF-8
+            let (content, detected) = decode_text(&std::fs::read(&file_path)?);
+            println!("检测到的文本编码: {}", detected);
+            encoding = Some(detected.to_string());
             content
         }
         _ => {
@@ -131,13 +148,15 @@ pub async fn add_attachment_by_url(
                     let message_attachment =
                         db.attachment_repo().unwrap().create(&MessageAttachment {
                             id: 0,
-                            message_id: -1,
+                            message_id: None,
                             attachment_type: AttachmentType::Image,
                             attachment_url: Some(file_url),
                             attachment_content: Some(reader),
                             attachment_hash: Some(hash_str),
                             use_vector: false,
                             token_count: Some(0),
+                            ocr_text: None,
+                            encoding: None,
                         })?;
                     message_attachment.id
                 }
@@ -146,13 +165,15 @@ pub async fn add_attachment_by_url(
                     let message_attachment =
                         db.attachment_repo().unwrap().create(&MessageAttachment {
                             id: 0,
-                            message_id: -1,
+                            message_id: None,
                             attachment_type: AttachmentType::Text,
                             attachment_url: Some(file_url),
                             attachment_content: Some(reader),
                             attachment_hash: Some(hash_str),
                             use_vector: false,
                             token_count: Some(0),
+                            ocr_text: None,
+                            encoding,
                         })?;
                     message_attachment.id
                 }
@@ -170,12 +191,23 @@ pub async fn add_attachment_by_url(
 }
 
 pub async fn add_attachment_content(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
     file_content: String,
     file_name: String,
     attachment_type: i64,
 ) -> Result<AttachmentResult, AppError> {
     println!("add_attachment_content file_name: {}", file_name);
+    // 图片内容是 data URL，按解码后的原始字节检查
+    let raw_content = if attachment_type == AttachmentType::Image as i64 {
+        decode_image_content(&file_content)?
+    } else {
+        file_content.as_bytes().to_vec()
+    };
+    attachment_policy(&app_handle)
+        .await
+        .check(&file_name, raw_content)
+        .await?;
+
     let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
 
     let mut hasher = Sha256::new();
@@ -200,13 +232,15 @@ pub async fn add_attachment_content(
         None => {
             let message_attachment = db.attachment_repo().unwrap().create(&MessageAttachment {
                 id: 0,
-                message_id: -1,
+                message_id: None,
                 attachment_type: AttachmentType::try_from(attachment_type).unwrap(),
                 attachment_url: Some(file_name),
                 attachment_content: Some(file_content),
                 attachment_hash: Some(hash_str),
                 use_vector: false,
                 token_count: Some(0),
+                ocr_text: None,
+                encoding: None,
             });
             let attachment_id = match message_attachment {
                 Ok(t) => t.id,
@@ -217,8 +251,106 @@ pub async fn add_attachment_content(
     }
 }
 
+// 添加附件前的检查策略，见 AttachmentPolicy
+async fn attachment_policy(app_handle: &AppHandle) -> AttachmentPolicy {
+    match app_handle.try_state::<FeatureConfigState>() {
+        Some(state) => AttachmentPolicy::from_config_map(&*state.config_feature_map.lock().await),
+        None => AttachmentPolicy::from_config_map(&HashMap::new()),
+    }
+}
+
+/// 读取系统剪贴板中的图片（例如截图）作为图片附件，编码成 PNG 后走和 add_attachment 相同的保存流程
+#[tauri::command]
+pub async fn add_attachment_from_clipboard(
+    app_handle: AppHandle,
+) -> Result<AttachmentResult, AppError> {
+    let png = tauri::async_runtime::spawn_blocking(read_clipboard_image_as_png)
+        .await
+        .map_err(|e| AppError::UnknownError(e.to_string()))?
+        .map_err(|e| AppError::UnknownError(e.to_string()))?;
+    let content = "data:image/png;base64,".to_string() + &STANDARD.encode(&png);
+    let file_name = format!("clipboard_{}.png", Local::now().format("%Y%m%d%H%M%S"));
+    add_attachment_content(app_handle, content, file_name, AttachmentType::Image as i64).await
+}
+
+// 图库中缩略图的最大边长，文字附件截取开头作为预览
+const THUMBNAIL_SIZE: u32 = 256;
+const TEXT_PREVIEW_CHARS: usize = 200;
+
+#[derive(Serialize)]
+pub struct ConversationAttachment {
+    attachment_id: i64,
+    message_id: Option<i64>,
+    attachment_type: AttachmentType,
+    name: Option<String>,
+    // 图片为 PNG 格式的缩略图 data URL，其他附件为 None
+    thumbnail: Option<String>,
+    // 文字附件开头的一段内容
+    preview: Option<String>,
+    token_count: Option<i32>,
+    created_time: DateTime<Utc>,
+}
+
+/// 列出对话中所有消息的附件，用于对话的图库和文件页，不需要加载完整的消息列表
+///
+/// type_filter 为附件类型的数值，不传时返回所有类型
 #[tauri::command]
-pub async fn open_attachment_with_default_app(id: i64, app_handle: tauri::AppHandle) -> Result<(), AppError> {
+pub async fn list_conversation_attachments(
+    app_handle: AppHandle,
+    conversation_id: i64,
+    type_filter: Option<i64>,
+) -> Result<Vec<ConversationAttachment>, AppError> {
+    let attachment_type = type_filter.map(AttachmentType::try_from).transpose()?;
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    let attachments = db
+        .attachment_repo()?
+        .list_by_conversation_and_type(conversation_id, attachment_type)?;
+
+    // 生成缩略图需要解码整张图片，放到阻塞线程中处理
+    tauri::async_runtime::spawn_blocking(move || {
+        attachments
+            .into_iter()
+            .map(|(attachment, created_time)| {
+                let content = attachment.attachment_content.as_deref().unwrap_or_default();
+                let (thumbnail, preview) = match attachment.attachment_type {
+                    AttachmentType::Image => (
+                        create_thumbnail(content)
+                            .map_err(|e| {
+                                println!("create thumbnail {} error: {:?}", attachment.id, e)
+                            })
+                            .ok(),
+                        None,
+                    ),
+                    AttachmentType::Text => (
+                        None,
+                        Some(content.chars().take(TEXT_PREVIEW_CHARS).collect()),
+                    ),
+                    _ => (None, None),
+                };
+                ConversationAttachment {
+                    attachment_id: attachment.id,
+                    message_id: attachment.message_id,
+                    attachment_type: attachment.attachment_type,
+                    name: attachment.attachment_url.as_deref().map(|url| {
+                        Path::new(url)
+                            .file_name()
+                            .map(|name| name.to_string_lossy().to_string())
+                            .unwrap_or_else(|| url.to_string())
+                    }),
+                    thumbnail,
+                    preview,
+                    token_count: attachment.token_count,
+                    created_time,
+                }
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| AppError::UnknownError(e.to_string()))
+}
+
+#[tauri::command]
+pub async fn open_attachment_with_default_app(id: i64, app_handle: AppHandle) -> Result<(), AppError> {
     let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
     let attachment = db.attachment_repo().unwrap().read(id)?;
 
@@ -241,3 +373,55 @@ fn read_image_as_base64(file_path: &str) -> Result<String> {
     let base64_string = STANDARD.encode(&buffer);
     Ok(base64_string)
 }
+
+// 图片附件保存的是 data URL，缩小后重新编码为 PNG
+fn create_thumbnail(data_url: &str) -> Result<String> {
+    let encoded = data_url
+        .split_once("base64,")
+        .map(|(_, encoded)| encoded)
+        .ok_or_else(|| anyhow!("图片内容不是 base64 格式"))?;
+    let image = image::load_from_memory(&STANDARD.decode(encoded.trim())?)?;
+    let thumbnail = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+    let mut png = Vec::new();
+    thumbnail.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok("data:image/png;base64,".to_string() + &STANDARD.encode(&png))
+}
+
+// 剪贴板中的图片是 RGBA 的原始像素，需要编码之后才能作为附件发送给模型
+fn read_clipboard_image_as_png() -> Result<Vec<u8>> {
+    let mut clipboard = Clipboard::new()?;
+    let image = clipboard
+        .get_image()
+        .map_err(|e| anyhow!("剪贴板中没有图片: {}", e))?;
+    let buffer = RgbaImage::from_raw(
+        image.width as u32,
+        image.height as u32,
+        image.bytes.into_owned(),
+    )
+    .ok_or_else(|| anyhow!("剪贴板中的图片数据不完整"))?;
+    let mut png = Vec::new();
+    buffer.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_thumbnail() {
+        let image = RgbaImage::from_pixel(600, 300, image::Rgba([255, 0, 0, 255]));
+        let mut png = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let data_url = format!("data:image/png;base64,{}", STANDARD.encode(&png));
+
+        let thumbnail = create_thumbnail(&data_url).unwrap();
+        let encoded = thumbnail.strip_prefix("data:image/png;base64,").unwrap();
+        let decoded = image::load_from_memory(&STANDARD.decode(encoded).unwrap()).unwrap();
+        // 按比例缩小到最长边不超过 THUMBNAIL_SIZE
+        assert_eq!((decoded.width(), decoded.height()), (256, 128));
+        assert!(create_thumbnail("not an image").is_err());
+    }
+}
@@ -0,0 +1,554 @@
+use std::collections::HashMap;
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::db::assistant_config_schema::{validate_model_configs, ConfigValidationErrors};
+use crate::db::assistant_db::{
+    Assistant, AssistantDatabase, AssistantModel, AssistantModelConfig, AssistantPrompt,
+    AssistantPromptParam, QuickAction,
+};
+use crate::db::llm_db::LLMDatabase;
+use crate::db::system_db::{FeatureConfig, SystemDatabase};
+use crate::errors::AppError;
+use crate::shortcut::{
+    ask_window_shortcut, parse_shortcut, selection_shortcut, ASK_WINDOW_SHORTCUT_KEY,
+    SELECTION_SHORTCUT_KEY,
+};
+use crate::AppHandle;
+use crate::FeatureConfigState;
+
+// 导出文件的格式版本，结构发生不兼容变化时需要递增
+const APP_CONFIG_EXPORT_VERSION: u32 = 1;
+
+// 允许导出的 system_config 偏好项，system_version 等内部状态不能导出
+const PREFERENCE_KEYS: &[&str] = &["theme"];
+
+// 导入时不接受的功能配置：会执行本机程序、把内容发往其他地址、放开工具权限或者关闭安全防护，
+// 分享的配置文件不能修改这些配置，key 为 * 表示这个功能的所有配置
+const PROTECTED_FEATURE_CONFIGS: &[(&str, &str)] = &[
+    ("tools", "*"),
+    ("attachment_policy", "*"),
+    ("injection_guard", "*"),
+    ("audit_log", "*"),
+    ("webhook", "*"),
+    ("ocr", "tesseract_path"),
+    ("ocr", "endpoint"),
+    ("voice", "command"),
+    ("voice", "endpoint"),
+    ("quick_action", "allow_type_output"),
+    ("selection", "blocked_apps"),
+    ("selection", "blocked_action"),
+];
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProviderConfigExport {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ModelExport {
+    pub name: String,
+    pub code: String,
+    pub description: String,
+    pub vision_support: bool,
+    pub audio_support: bool,
+    pub video_support: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProviderExport {
+    pub id: i64,
+    pub name: String,
+    pub api_type: String,
+    pub description: String,
+    pub is_official: bool,
+    pub is_enabled: bool,
+    pub configs: Vec<ProviderConfigExport>,
+    pub models: Vec<ModelExport>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AssistantExport {
+    pub assistant: Assistant,
+    pub prompts: Vec<AssistantPrompt>,
+    pub models: Vec<AssistantModel>,
+    pub model_configs: Vec<AssistantModelConfig>,
+    pub prompt_params: Vec<AssistantPromptParam>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AppConfigExport {
+    pub version: u32,
+    pub exported_time: String,
+    #[serde(default)]
+    pub providers: Vec<ProviderExport>,
+    #[serde(default)]
+    pub assistants: Vec<AssistantExport>,
+    #[serde(default)]
+    pub feature_configs: Vec<FeatureConfig>,
+    #[serde(default)]
+    pub preferences: HashMap<String, String>,
+    // 全局快捷键，键为 system_config 中的 selection_shortcut 和 ask_window_shortcut
+    #[serde(default)]
+    pub shortcuts: HashMap<String, String>,
+    // 快捷操作的 assistant_id 是导出时的助手 id，导入时按导入的助手映射
+    #[serde(default)]
+    pub quick_actions: Vec<QuickAction>,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct ImportConfigResult {
+    pub providers: usize,
+    pub models: usize,
+    pub assistants: usize,
+    pub feature_configs: usize,
+    pub preferences: usize,
+    // 快捷键在重启应用后生效
+    pub shortcuts: usize,
+    pub quick_actions: usize,
+    // 因为已经存在同名记录而跳过的助手
+    pub skipped_assistants: Vec<String>,
+    // 出于安全考虑没有导入的功能配置，格式为 feature_code.key
+    pub skipped_feature_configs: Vec<String>,
+}
+
+// 密钥类的配置不会写入导出文件，custom_headers 中也可能带有鉴权信息
+fn is_secret_config(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name == "custom_headers"
+        || ["key", "secret", "token", "password"]
+            .iter()
+            .any(|word| name.contains(word))
+}
+
+// 功能配置中的密钥，以及 webhook 地址这类可能带有令牌的地址
+fn is_secret_feature_config(feature_code: &str, key: &str) -> bool {
+    is_secret_config(key) || (feature_code == "webhook" && key == "url")
+}
+
+fn is_protected_feature_config(feature_code: &str, key: &str) -> bool {
+    is_secret_feature_config(feature_code, key)
+        || PROTECTED_FEATURE_CONFIGS
+            .iter()
+            .any(|(code, protected_key)| {
+                *code == feature_code && (*protected_key == "*" || *protected_key == key)
+            })
+}
+
+/// 导出提供商、模型、助手、快捷操作、功能配置、快捷键和偏好设置到一个 JSON 文件，不含密钥
+#[tauri::command]
+pub async fn export_app_config(app_handle: AppHandle, path: String) -> Result<(), AppError> {
+    let llm_db = LLMDatabase::new(&app_handle)?;
+    let mut providers = Vec::new();
+    for (id, name, api_type, description, is_official, is_enabled) in llm_db.get_llm_providers()? {
+        let configs = llm_db
+            .get_llm_provider_config(id)?
+            .into_iter()
+            .filter(|config| !is_secret_config(&config.name))
+            .map(|config| ProviderConfigExport {
+                name: config.name,
+                value: config.value,
+            })
+            .collect();
+        let models = llm_db
+            .get_llm_models(id.to_string())?
+            .into_iter()
+            .map(|model| ModelExport {
+                name: model.name,
+                code: model.code,
+                description: model.description,
+                vision_support: model.vision_support,
+                audio_support: model.audio_support,
+                video_support: model.video_support,
+            })
+            .collect();
+        providers.push(ProviderExport {
+            id,
+            name,
+            api_type,
+            description,
+            is_official,
+            is_enabled,
+            configs,
+            models,
+        });
+    }
+
+    let assistant_db = AssistantDatabase::new(&app_handle)?;
+    let mut assistants = Vec::new();
+    for assistant in assistant_db.get_assistants()? {
+        assistants.push(AssistantExport {
+            prompts: assistant_db.get_assistant_prompt(assistant.id)?,
+            models: assistant_db.get_assistant_model(assistant.id)?,
+            model_configs: assistant_db.get_assistant_model_configs(assistant.id)?,
+            prompt_params: assistant_db.get_assistant_prompt_params(assistant.id)?,
+            assistant,
+        });
+    }
+    let quick_actions = assistant_db.get_quick_actions()?;
+
+    let system_db = SystemDatabase::new(&app_handle)?;
+    let feature_configs = system_db
+        .get_all_feature_config()?
+        .into_iter()
+        .filter(|config| !is_secret_feature_config(&config.feature_code, &config.key))
+        .collect();
+    let mut preferences = HashMap::new();
+    for key in PREFERENCE_KEYS {
+        let value = system_db.get_config(key)?;
+        if !value.is_empty() {
+            preferences.insert(key.to_string(), value);
+        }
+    }
+    let shortcuts = HashMap::from([
+        (
+            SELECTION_SHORTCUT_KEY.to_string(),
+            selection_shortcut(&app_handle).into_string(),
+        ),
+        (
+            ASK_WINDOW_SHORTCUT_KEY.to_string(),
+            ask_window_shortcut(&app_handle).into_string(),
+        ),
+    ]);
+
+    let export = AppConfigExport {
+        version: APP_CONFIG_EXPORT_VERSION,
+        exported_time: chrono::Utc::now().to_rfc3339(),
+        providers,
+        assistants,
+        feature_configs,
+        preferences,
+        shortcuts,
+        quick_actions,
+    };
+    let content =
+        serde_json::to_string_pretty(&export).map_err(|e| AppError::ParseError(e.to_string()))?;
+    fs::write(&path, content)?;
+    Ok(())
+}
+
+/// 从导出文件导入配置
+///
+/// 提供商按名称和类型合并，已存在时只补充配置和缺少的模型；同名助手会被跳过；
+/// 功能配置按配置项覆盖，密钥和 PROTECTED_FEATURE_CONFIGS 中的配置不会导入，需要用户在设置中自己修改
+#[tauri::command]
+pub async fn import_app_config(
+    app_handle: AppHandle,
+    state: State<'_, FeatureConfigState>,
+    path: String,
+) -> Result<ImportConfigResult, AppError> {
+    let content = fs::read_to_string(&path)?;
+    let mut config: AppConfigExport =
+        serde_json::from_str(&content).map_err(|e| AppError::ParseError(e.to_string()))?;
+    if config.version > APP_CONFIG_EXPORT_VERSION {
+        return Err(AppError::ParseError(format!(
+            "不支持的配置文件版本: {}",
+            config.version
+        )));
+    }
+    // 和保存助手一样先检查所有参数，有不符合规则的参数时什么都不导入
+    validate_imported_configs(&mut config)?;
+
+    let mut result = ImportConfigResult::default();
+
+    // 导出文件中的提供商 id 映射到本机的提供商 id，助手模型需要用到
+    let mut provider_id_map: HashMap<i64, i64> = HashMap::new();
+    let llm_db = LLMDatabase::new(&app_handle)?;
+    let existing_providers = llm_db.get_llm_providers()?;
+    for provider in config.providers.iter() {
+        let provider_id = match existing_providers
+            .iter()
+            .find(|(_, name, api_type, _, _, _)| {
+                *name == provider.name && *api_type == provider.api_type
+            }) {
+            Some((id, ..)) => *id,
+            None => {
+                llm_db.add_llm_provider(
+                    &provider.name,
+                    &provider.api_type,
+                    &provider.description,
+                    false,
+                    provider.is_enabled,
+                )?;
+                result.providers += 1;
+                llm_db.conn.last_insert_rowid()
+            }
+        };
+        provider_id_map.insert(provider.id, provider_id);
+
+        for provider_config in provider.configs.iter() {
+            if is_secret_config(&provider_config.name) {
+                continue;
+            }
+            llm_db.update_llm_provider_config(
+                provider_id,
+                &provider_config.name,
+                &provider_config.value,
+            )?;
+        }
+
+        let existing_codes: Vec<String> = llm_db
+            .get_llm_models(provider_id.to_string())?
+            .into_iter()
+            .map(|model| model.code)
+            .collect();
+        for model in provider.models.iter() {
+            if existing_codes.contains(&model.code) {
+                continue;
+            }
+            llm_db.add_llm_model(
+                &model.name,
+                provider_id,
+                &model.code,
+                &model.description,
+                model.vision_support,
+                model.audio_support,
+                model.video_support,
+            )?;
+            result.models += 1;
+        }
+    }
+
+    let assistant_db = AssistantDatabase::new(&app_handle)?;
+    let existing_assistants = assistant_db.get_assistants()?;
+    // 导出文件中的助手 id 映射到本机的助手 id，快捷操作需要用到；跳过的同名助手映射到已有的助手
+    let mut assistant_id_map: HashMap<i64, i64> = HashMap::new();
+    for item in config.assistants.iter() {
+        if let Some(existing) = existing_assistants
+            .iter()
+            .find(|assistant| assistant.name == item.assistant.name)
+        {
+            assistant_id_map.insert(item.assistant.id, existing.id);
+            result.skipped_assistants.push(item.assistant.name.clone());
+            continue;
+        }
+        let assistant_id = assistant_db.add_assistant(
+            &item.assistant.name,
+            item.assistant.description.as_deref().unwrap_or(""),
+            item.assistant.assistant_type,
+            item.assistant.is_addition,
+        )?;
+        assistant_id_map.insert(item.assistant.id, assistant_id);
+
+        let mut prompt_id_map = HashMap::new();
+        for prompt in item.prompts.iter() {
+            let prompt_id = assistant_db.add_assistant_prompt(assistant_id, &prompt.prompt)?;
+            prompt_id_map.insert(prompt.id, prompt_id);
+        }
+
+        // assistant_model_id 为 -1 的配置属于助手本身，不需要映射
+        let mut model_id_map = HashMap::from([(-1, -1)]);
+        for model in item.models.iter() {
+            let provider_id = provider_id_map
+                .get(&model.provider_id)
+                .copied()
+                .unwrap_or(model.provider_id);
+            let model_id = assistant_db.add_assistant_model(
+                assistant_id,
+                provider_id,
+                &model.model_code,
+                &model.alias,
+            )?;
+            model_id_map.insert(model.id, model_id);
+        }
+
+        for model_config in item.model_configs.iter() {
+            let Some(model_id) = model_id_map.get(&model_config.assistant_model_id) else {
+                continue;
+            };
+            assistant_db.add_assistant_model_config(
+                assistant_id,
+                *model_id,
+                &model_config.name,
+                model_config.value.as_deref().unwrap_or(""),
+                &model_config.value_type,
+            )?;
+        }
+
+        for param in item.prompt_params.iter() {
+            let Some(prompt_id) = prompt_id_map.get(&param.assistant_prompt_id) else {
+                continue;
+            };
+            assistant_db.add_assistant_prompt_param(
+                assistant_id,
+                *prompt_id,
+                &param.param_name,
+                param.param_type.as_deref().unwrap_or(""),
+                param.param_value.as_deref().unwrap_or(""),
+            )?;
+        }
+        result.assistants += 1;
+    }
+
+    // 已经存在同名快捷操作，或者对应的助手不在导出文件中时跳过
+    let existing_quick_actions = assistant_db.get_quick_actions()?;
+    for quick_action in config.quick_actions.iter() {
+        let Some(assistant_id) = assistant_id_map.get(&quick_action.assistant_id) else {
+            continue;
+        };
+        if existing_quick_actions
+            .iter()
+            .any(|existing| existing.name == quick_action.name)
+        {
+            continue;
+        }
+        assistant_db.add_quick_action(&QuickAction {
+            assistant_id: *assistant_id,
+            ..quick_action.clone()
+        })?;
+        result.quick_actions += 1;
+    }
+
+    let system_db = SystemDatabase::new(&app_handle)?;
+    for feature_config in config.feature_configs.iter() {
+        if is_protected_feature_config(&feature_config.feature_code, &feature_config.key) {
+            result.skipped_feature_configs.push(format!(
+                "{}.{}",
+                feature_config.feature_code, feature_config.key
+            ));
+            continue;
+        }
+        system_db.upsert_feature_config(&FeatureConfig {
+            id: None,
+            ..feature_config.clone()
+        })?;
+        result.feature_configs += 1;
+    }
+
+    for (key, value) in config.preferences.iter() {
+        if !PREFERENCE_KEYS.contains(&key.as_str()) {
+            continue;
+        }
+        if system_db.get_config(key)?.is_empty() {
+            system_db.add_system_config(key, value)?;
+        } else {
+            system_db.update_system_config(key, value)?;
+        }
+        result.preferences += 1;
+    }
+
+    for (key, value) in config.shortcuts.iter() {
+        if ![SELECTION_SHORTCUT_KEY, ASK_WINDOW_SHORTCUT_KEY].contains(&key.as_str()) {
+            continue;
+        }
+        parse_shortcut(value).map_err(AppError::ParseError)?;
+        if system_db.get_config(key)?.is_empty() {
+            system_db.add_system_config(key, value)?;
+        } else {
+            system_db.update_system_config(key, value)?;
+        }
+        result.shortcuts += 1;
+    }
+
+    // 重新加载内存中的功能配置
+    let configs = system_db.get_all_feature_config()?;
+    let mut config_feature_map: HashMap<String, HashMap<String, FeatureConfig>> = HashMap::new();
+    for feature_config in configs.iter() {
+        config_feature_map
+            .entry(feature_config.feature_code.clone())
+            .or_default()
+            .insert(feature_config.key.clone(), feature_config.clone());
+    }
+    *state.configs.lock().await = configs;
+    *state.config_feature_map.lock().await = config_feature_map;
+
+    Ok(result)
+}
+
+// 按导出文件中助手第一个模型的提供商类型检查并整理助手的参数，返回所有助手的错误
+fn validate_imported_configs(config: &mut AppConfigExport) -> Result<(), ConfigValidationErrors> {
+    let mut errors = Vec::new();
+    for item in config.assistants.iter_mut() {
+        let config_model = item.models.first().and_then(|model| {
+            config
+                .providers
+                .iter()
+                .find(|provider| provider.id == model.provider_id)
+                .map(|provider| (provider.api_type.as_str(), model.model_code.as_str()))
+        });
+        if let Err(e) = validate_model_configs(&mut item.model_configs, config_model) {
+            errors.extend(e.0);
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ConfigValidationErrors(errors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_feature_config() {
+        assert!(is_secret_feature_config("ocr", "api_key"));
+        assert!(is_secret_feature_config("voice", "api_key"));
+        assert!(is_secret_feature_config("webhook", "url"));
+        assert!(!is_secret_feature_config("ocr", "language"));
+    }
+
+    #[test]
+    fn test_protected_feature_config() {
+        assert!(is_protected_feature_config("tools", "run_shell_enabled"));
+        assert!(is_protected_feature_config("tools", "run_shell_allowed"));
+        assert!(is_protected_feature_config("ocr", "tesseract_path"));
+        assert!(is_protected_feature_config("voice", "command"));
+        assert!(is_protected_feature_config(
+            "attachment_policy",
+            "scanner_path"
+        ));
+        assert!(is_protected_feature_config("injection_guard", "policy"));
+        assert!(is_protected_feature_config("audit_log", "enabled"));
+        assert!(!is_protected_feature_config("voice", "language"));
+        assert!(!is_protected_feature_config(
+            "conversation_summary",
+            "prompt"
+        ));
+    }
+    #[test]
+    fn test_validate_imported_configs() {
+        let mut config: AppConfigExport = serde_json::from_value(serde_json::json!({
+            "version": APP_CONFIG_EXPORT_VERSION,
+            "exported_time": "",
+            "providers": [{
+                "id": 3, "name": "Anthropic", "api_type": "anthropic", "description": "",
+                "is_official": false, "is_enabled": true, "configs": [], "models": []
+            }],
+            "assistants": [{
+                "assistant": {
+                    "id": 1, "name": "a", "description": null, "assistant_type": 0,
+                    "is_addition": false, "created_time": ""
+                },
+                "prompts": [],
+                "models": [{
+                    "id": 5, "assistant_id": 1, "provider_id": 3,
+                    "model_code": "claude-3-5-sonnet-latest", "alias": ""
+                }],
+                "model_configs": [
+                    { "id": 1, "assistant_id": 1, "assistant_model_id": -1,
+                      "name": "max_tokens", "value": "2000.0", "value_type": "string" },
+                    { "id": 2, "assistant_id": 1, "assistant_model_id": -1,
+                      "name": "temperature", "value": "0.8", "value_type": "float" }
+                ],
+                "prompt_params": []
+            }]
+        }))
+        .unwrap();
+        assert!(validate_imported_configs(&mut config).is_ok());
+        assert_eq!(
+            config.assistants[0].model_configs[0].value.as_deref(),
+            Some("2000")
+        );
+
+        // Anthropic 的 temperature 不能超过 1
+        config.assistants[0].model_configs[1].value = Some("1.5".to_string());
+        let errors = validate_imported_configs(&mut config).unwrap_err();
+        assert_eq!(errors.0.len(), 1);
+        assert_eq!(errors.0[0].name, "temperature");
+    }
+}
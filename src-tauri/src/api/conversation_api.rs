@@ -1,15 +1,28 @@
 use std::collections::HashMap;
+use std::path::Path;
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use tauri::Emitter;
+use tauri::{Emitter, State};
 
+use crate::AppHandle;
 use crate::{
+    api::{llm::Role, llm_api::remove_orphan_recordings},
+    conversation_cost::{self, ConversationCost, ModelPrice, ModelPricing, MODEL_PRICE_FEATURE},
+    conversation_export::{self, ConversationExportResult},
     db::conversation_db::{
-        ConversationDatabase, Message, MessageAttachment, MessageDetail, Repository,
+        ActivityDay, AttachmentType, Conversation, ConversationDatabase, GlossaryTerm, Message,
+        MessageAttachment, MessageDetail, MessageOperation, MessageQuote, MessageTranslation,
+        PromptHistory, RecentConversation, Repository, ResponseStyle, QUOTE_MESSAGE_TYPE,
     },
+    db::system_db::{model_price_config, SystemDatabase},
     errors::AppError,
-    NameCacheState,
+    knowledge::citation::Citation,
+    state::{message_token::MessageTokenManager, private_conversation::PrivateConversationState},
+    title_backfill::{cancel_title_backfill, start_title_backfill},
+    topic_split::{self, SplitSuggestion, TopicSplitConfig},
+    translation::{self, TranslationConfig},
+    FeatureConfigState, NameCacheState,
 };
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -19,12 +32,18 @@ pub struct ConversationResult {
     pub assistant_id: i64,
     pub assistant_name: String,
     pub created_time: DateTime<Utc>,
+    pub is_private: bool,
+    // 私密对话未解锁时为 true，这时不能查看消息
+    pub locked: bool,
+    // 一句话总结，补全标题之前的对话和新对话没有总结
+    pub summary: Option<String>,
 }
 
 #[tauri::command]
 pub async fn list_conversations(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
     name_cache_state: tauri::State<'_, NameCacheState>,
+    private_state: State<'_, PrivateConversationState>,
     page: u32,
     page_size: u32,
 ) -> Result<Vec<ConversationResult>, AppError> {
@@ -47,16 +66,142 @@ pub async fn list_conversations(
                 assistant_id: conversation.assistant_id.unwrap_or(0),
                 assistant_name: assistant_name.unwrap_or(&"未知".to_string()).clone(),
                 created_time: conversation.created_time,
+                is_private: conversation.is_private,
+                locked: private_state.is_locked(conversation.id),
+                summary: conversation.summary.clone(),
             });
         }
     }
     Ok(conversation_results)
 }
 
+// 快速切换列表中最后一条消息预览的最大字符数
+const MESSAGE_PREVIEW_CHARS: usize = 80;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecentConversationResult {
+    pub id: i64,
+    pub name: String,
+    pub assistant_id: i64,
+    pub assistant_name: String,
+    pub summary: Option<String>,
+    // 最后一条消息合并空白后的开头部分，没有消息或者私密对话未解锁时为 None
+    pub last_message_preview: Option<String>,
+    pub last_message_type: Option<String>,
+    pub last_active_time: DateTime<Utc>,
+    pub unread: bool,
+    pub is_private: bool,
+    pub locked: bool,
+}
+
+/// 询问窗口快速切换对话使用的最近对话列表，按最后活动时间倒序
+///
+/// 对话、最后一条消息和未读状态在一条查询中取出，助手名称来自 NameCacheState，不会逐个对话查询
+#[tauri::command]
+pub async fn recent_conversations(
+    app_handle: AppHandle,
+    name_cache_state: tauri::State<'_, NameCacheState>,
+    private_state: State<'_, PrivateConversationState>,
+    limit: u32,
+) -> Result<Vec<RecentConversationResult>, AppError> {
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    let conversations = db.conversation_repo()?.list_recent(limit)?;
+    let assistant_name_cache = name_cache_state.assistant_names.lock().await.clone();
+    Ok(conversations
+        .into_iter()
+        .map(|conversation| {
+            let locked = private_state.is_locked(conversation.id);
+            let assistant_id = conversation.assistant_id.unwrap_or(0);
+            RecentConversationResult {
+                id: conversation.id,
+                name: conversation.name.clone(),
+                assistant_id,
+                assistant_name: assistant_name_cache
+                    .get(&assistant_id)
+                    .cloned()
+                    .unwrap_or("未知".to_string()),
+                summary: conversation.summary.clone(),
+                last_message_preview: message_preview(&private_state, &conversation, locked),
+                last_message_type: conversation.last_message_type,
+                last_active_time: conversation.last_active_time,
+                unread: conversation.unread,
+                is_private: conversation.is_private,
+                locked,
+            }
+        })
+        .collect())
+}
+
+fn message_preview(
+    private_state: &PrivateConversationState,
+    conversation: &RecentConversation,
+    locked: bool,
+) -> Option<String> {
+    if locked {
+        return None;
+    }
+    let content = private_state
+        .decrypt(conversation.id, conversation.last_message.as_deref()?)
+        .map_err(|e| {
+            println!(
+                "decrypt conversation {} preview error: {}",
+                conversation.id, e
+            )
+        })
+        .ok()?;
+    Some(
+        content
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .chars()
+            .take(MESSAGE_PREVIEW_CHARS)
+            .collect(),
+    )
+}
+
+/// 标记对话已读，打开对话或者在窗口中看完回答后调用
+#[tauri::command]
+pub fn mark_conversation_read(app_handle: AppHandle, conversation_id: i64) -> Result<(), AppError> {
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    db.conversation_repo()?
+        .update_last_read_time(conversation_id)?;
+    Ok(())
+}
+
+/// 在后台为还是默认名称的历史对话补全标题和一句话总结，返回需要处理的对话数量，
+/// 进度通过 title_backfill_progress 事件发送
+#[tauri::command]
+pub async fn backfill_conversation_titles(
+    app_handle: AppHandle,
+    batch_size: Option<usize>,
+) -> Result<usize, AppError> {
+    start_title_backfill(app_handle, batch_size).await
+}
+
+#[tauri::command]
+pub fn cancel_conversation_title_backfill() {
+    cancel_title_backfill();
+}
+
+/// 检查对话的最后一次提问是否换了话题，建议开始新对话时同时返回可以带过去的简要内容；
+/// topic_split 功能配置的 mode 为 off 时总是返回不需要拆分
+#[tauri::command]
+pub async fn suggest_split(
+    app_handle: AppHandle,
+    feature_config_state: State<'_, FeatureConfigState>,
+    conversation_id: i64,
+) -> Result<SplitSuggestion, AppError> {
+    let config =
+        TopicSplitConfig::from_config_map(&*feature_config_state.config_feature_map.lock().await);
+    topic_split::suggest_split(&app_handle, conversation_id, &config)
+}
+
 #[tauri::command]
 pub async fn get_conversation_with_messages(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
     name_cache_state: tauri::State<'_, NameCacheState>,
+    private_state: State<'_, PrivateConversationState>,
     conversation_id: i64,
 ) -> Result<(ConversationResult, Vec<MessageDetail>), String> {
     let db = ConversationDatabase::new(&app_handle).map_err(|e| e.to_string())?;
@@ -66,12 +211,22 @@ pub async fn get_conversation_with_messages(
         .read(conversation_id)
         .map_err(|e| e.to_string())?
         .ok_or_else(|| "Conversation not found".to_string())?;
+    if private_state.is_locked(conversation_id) {
+        return Err("对话已锁定，请先解锁".to_string());
+    }
 
     let messages = db
         .message_repo()
         .unwrap()
         .list_by_conversation_id(conversation_id)
         .map_err(|e| e.to_string())?;
+    let mut quotes = db
+        .quote_repo()
+        .and_then(|repo| {
+            repo.list_by_conversation_id(conversation_id)
+                .map_err(AppError::from)
+        })
+        .map_err(|e| e.to_string())?;
 
     let mut message_details: Vec<MessageDetail> = Vec::new();
     let mut attachment_map: HashMap<i64, Vec<MessageAttachment>> = HashMap::new();
@@ -103,9 +258,29 @@ pub async fn get_conversation_with_messages(
             llm_model_id: message.llm_model_id,
             created_time: message.created_time,
             token_count: message.token_count,
+            is_truncated: message.is_truncated,
+            attachment_truncation: message
+                .attachment_truncation
+                .as_deref()
+                .and_then(|value| serde_json::from_str(value).ok())
+                .unwrap_or_default(),
+            pinned: message.pinned,
+            citations: message
+                .citations
+                .as_deref()
+                .and_then(|value| serde_json::from_str(value).ok())
+                .unwrap_or_default(),
+            timed_out: message.timed_out,
+            generation_metadata: message
+                .generation_metadata
+                .as_deref()
+                .and_then(|value| serde_json::from_str(value).ok()),
+            reasoning: message.reasoning.clone(),
+            assistant_id: message.assistant_id,
             attachment_list,
             regenerate: Vec::new(),
             parent_id: message.parent_id,
+            quote: quotes.remove(&message_id),
         });
     }
 
@@ -145,26 +320,120 @@ pub async fn get_conversation_with_messages(
             assistant_id: conversation.assistant_id.unwrap_or(0),
             assistant_name,
             created_time: conversation.created_time,
+            is_private: conversation.is_private,
+            locked: false,
+            summary: conversation.summary,
         },
         message_details,
     ))
 }
 
+/// 只读查看窗口读取对话，只保留提问、回答和引用的消息，去掉模型、生成参数和本机文件路径等信息，
+/// 演示或者共享屏幕时不会显示提供商和本机相关的内容
+#[tauri::command]
+pub async fn get_viewer_conversation(
+    app_handle: AppHandle,
+    name_cache_state: tauri::State<'_, NameCacheState>,
+    private_state: State<'_, PrivateConversationState>,
+    conversation_id: i64,
+) -> Result<(ConversationResult, Vec<MessageDetail>), String> {
+    let (conversation, messages) = get_conversation_with_messages(
+        app_handle,
+        name_cache_state,
+        private_state,
+        conversation_id,
+    )
+    .await?;
+    let messages = messages.into_iter().filter_map(viewer_message).collect();
+    Ok((conversation, messages))
+}
+
+// system 消息中是助手的提示词，工具消息中是工具的参数和结果，都不在查看窗口中显示
+fn viewer_message(message: MessageDetail) -> Option<MessageDetail> {
+    let visible = matches!(
+        message.message_type.parse::<Role>(),
+        Ok(Role::User | Role::Assistant)
+    ) || message.message_type == QUOTE_MESSAGE_TYPE;
+    if !visible {
+        return None;
+    }
+    Some(MessageDetail {
+        llm_model_id: None,
+        generation_metadata: None,
+        assistant_id: None,
+        token_count: 0,
+        attachment_truncation: vec![],
+        citations: message
+            .citations
+            .into_iter()
+            .map(|citation| Citation {
+                file_path: file_name(&citation.file_path),
+                ..citation
+            })
+            .collect(),
+        attachment_list: message
+            .attachment_list
+            .into_iter()
+            .map(viewer_attachment)
+            .collect(),
+        regenerate: message
+            .regenerate
+            .into_iter()
+            .filter_map(viewer_message)
+            .collect(),
+        ..message
+    })
+}
+
+// 图片保留内容用于显示，其他附件只显示文件名
+fn viewer_attachment(attachment: MessageAttachment) -> MessageAttachment {
+    let is_image = attachment.attachment_type == AttachmentType::Image;
+    MessageAttachment {
+        attachment_url: attachment.attachment_url.as_deref().map(file_name),
+        attachment_content: attachment.attachment_content.filter(|_| is_image),
+        attachment_hash: None,
+        ocr_text: None,
+        encoding: None,
+        ..attachment
+    }
+}
+
+fn file_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
 #[tauri::command]
 pub fn delete_conversation(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
+    token_manager: State<'_, MessageTokenManager>,
     conversation_id: i64,
-) -> Result<(), String> {
-    let db = ConversationDatabase::new(&app_handle).map_err(|e| e.to_string())?;
-    db.conversation_repo()
-        .unwrap()
-        .delete(conversation_id)
-        .map_err(|e| e.to_string())
+) -> Result<(), AppError> {
+    ensure_not_busy(&token_manager, conversation_id)?;
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    db.conversation_repo()?.delete(conversation_id)?;
+    // 录制文件是明文，随对话一起删除
+    remove_orphan_recordings(&app_handle)?;
+    Ok(())
+}
+
+/// 对话正在生成回答时不能删除对话、编辑消息、合并对话或者重新生成出新的分支，
+/// 生成结束后会发送 conversation_idle 事件
+pub(crate) fn ensure_not_busy(
+    token_manager: &MessageTokenManager,
+    conversation_id: i64,
+) -> Result<(), AppError> {
+    if token_manager.is_conversation_busy(conversation_id) {
+        return Err(AppError::ConversationBusy(conversation_id));
+    }
+    Ok(())
 }
 
 #[tauri::command]
 pub fn update_conversation(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
     conversation_id: i64,
     name: String,
 ) -> Result<(), String> {
@@ -184,3 +453,866 @@ pub fn update_conversation(
     let _ = app_handle.emit("title_change", [conversation_id.to_string(), name]);
     Ok(())
 }
+
+/// 把多个对话合并成一个新的对话，原对话保持不变
+///
+/// strategy 为 interleave 时按消息时间交错排列，concatenate 时按传入的顺序依次拼接；
+/// 只保留第一个对话的 system 消息，消息的时间、附件和重新生成的关系都会保留。
+/// 正在生成回答的对话不能合并，否则会带上还没有生成完的回答
+#[tauri::command]
+pub async fn merge_conversations(
+    app_handle: AppHandle,
+    name_cache_state: tauri::State<'_, NameCacheState>,
+    private_state: State<'_, PrivateConversationState>,
+    token_manager: State<'_, MessageTokenManager>,
+    conversation_ids: Vec<i64>,
+    strategy: String,
+) -> Result<ConversationResult, AppError> {
+    if conversation_ids.len() < 2 {
+        return Err(AppError::UnknownError("至少需要选择两个对话".to_string()));
+    }
+    if strategy != "interleave" && strategy != "concatenate" {
+        return Err(AppError::UnknownError(format!(
+            "不支持的合并方式: {}",
+            strategy
+        )));
+    }
+    for conversation_id in &conversation_ids {
+        ensure_not_busy(&token_manager, *conversation_id)?;
+    }
+
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    let conversation_repo = db.conversation_repo()?;
+    let message_repo = db.message_repo()?;
+    let quote_repo = db.quote_repo()?;
+
+    let mut conversations = Vec::new();
+    // (对话在列表中的顺序, 消息, 附件)
+    let mut merged_messages: Vec<(usize, Message, Vec<MessageAttachment>)> = Vec::new();
+    let mut quotes: HashMap<i64, MessageQuote> = HashMap::new();
+    for (order, conversation_id) in conversation_ids.iter().enumerate() {
+        let conversation = conversation_repo
+            .read(*conversation_id)?
+            .ok_or(AppError::DatabaseError("未找到对话".to_string()))?;
+        if private_state.is_locked(*conversation_id) {
+            return Err(AppError::UnknownError(format!(
+                "对话 {} 已锁定，请先解锁",
+                conversation.name
+            )));
+        }
+        conversations.push(conversation);
+        quotes.extend(quote_repo.list_by_conversation_id(*conversation_id)?);
+
+        // 一条消息有多个附件时会查询出多行
+        let mut messages: Vec<(Message, Vec<MessageAttachment>)> = Vec::new();
+        for (message, attachment) in message_repo.list_by_conversation_id(*conversation_id)? {
+            match messages.iter_mut().find(|(m, _)| m.id == message.id) {
+                Some((_, attachments)) => attachments.extend(attachment),
+                None => messages.push((message, attachment.into_iter().collect())),
+            }
+        }
+        merged_messages.extend(
+            messages
+                .into_iter()
+                .filter(|(message, _)| order == 0 || message.role() != Some(Role::System))
+                .map(|(message, attachments)| (order, message, attachments)),
+        );
+    }
+
+    if strategy == "interleave" {
+        merged_messages.sort_by(|a, b| {
+            let system_first =
+                (a.1.role() != Some(Role::System)).cmp(&(b.1.role() != Some(Role::System)));
+            system_first
+                .then(a.1.created_time.cmp(&b.1.created_time))
+                .then(a.1.id.cmp(&b.1.id))
+        });
+    } else {
+        merged_messages.sort_by_key(|(order, message, _)| (*order, message.id));
+    }
+
+    let first = &conversations[0];
+    // 合并了私密对话时新对话也是私密的，读取原对话时已经确认过都已解锁
+    let is_private = conversations.iter().any(|c| c.is_private);
+    let new_conversation = Conversation {
+        id: 0,
+        name: format!("合并：{}", first.name),
+        assistant_id: first.assistant_id,
+        created_time: Utc::now(),
+        is_private,
+        summary: None,
+    };
+    // 新对话和所有消息在同一个事务中写入，中途失败时不会留下只有一部分消息的对话
+    let mut created_id = None;
+    let result = db.transaction(|tx| {
+        let conversation = tx.conversation_repo().create(&new_conversation)?;
+        created_id = Some(conversation.id);
+        if is_private {
+            // 写入消息时需要用新对话的密钥加密
+            private_state
+                .unlock(&app_handle, conversation.id)
+                .map_err(AppError::UnknownError)?;
+            private_state.set_private(conversation.id, true);
+        }
+
+        let message_repo = tx.message_repo();
+        let attachment_repo = tx.attachment_repo();
+        let quote_repo = tx.quote_repo();
+        let mut message_id_map: HashMap<i64, i64> = HashMap::new();
+        for (_, message, attachments) in merged_messages {
+            let new_message = message_repo.create(&Message {
+                id: 0,
+                conversation_id: conversation.id,
+                parent_id: message
+                    .parent_id
+                    .and_then(|parent_id| message_id_map.get(&parent_id).copied()),
+                ..message.clone()
+            })?;
+            message_id_map.insert(message.id, new_message.id);
+            if let Some(quote) = quotes.get(&message.id) {
+                quote_repo.save(
+                    conversation.id,
+                    &MessageQuote {
+                        message_id: new_message.id,
+                        ..quote.clone()
+                    },
+                )?;
+            }
+            for attachment in attachments {
+                attachment_repo.create(&MessageAttachment {
+                    id: 0,
+                    message_id: Some(new_message.id),
+                    ..attachment
+                })?;
+            }
+        }
+        Ok(conversation)
+    });
+    let conversation = match result {
+        Ok(conversation) => conversation,
+        Err(e) => {
+            // 事务回滚后这个 id 不存在，撤销为它记录的私密状态
+            if let Some(conversation_id) = created_id.filter(|_| is_private) {
+                private_state.set_private(conversation_id, false);
+            }
+            return Err(e);
+        }
+    };
+
+    let assistant_names = name_cache_state.assistant_names.lock().await;
+    let assistant_id = conversation.assistant_id.unwrap_or(0);
+    Ok(ConversationResult {
+        id: conversation.id,
+        name: conversation.name,
+        assistant_id,
+        assistant_name: assistant_names
+            .get(&assistant_id)
+            .cloned()
+            .unwrap_or("未知".to_string()),
+        created_time: conversation.created_time,
+        is_private: conversation.is_private,
+        locked: false,
+        summary: conversation.summary,
+    })
+}
+
+/// 把其他对话中的一条提问或者回答引用到对话中，来源记录在 message_quote 中，之后提问时作为带来源说明的引用块发给模型
+///
+/// include_attachments 为 true 时同时引用消息的附件。私密对话中的消息只能引用到私密对话，
+/// 无痕对话中的消息不能引用
+#[tauri::command]
+pub fn quote_message(
+    app_handle: AppHandle,
+    private_state: State<'_, PrivateConversationState>,
+    token_manager: State<'_, MessageTokenManager>,
+    into_conversation_id: i64,
+    from_message_id: i64,
+    include_attachments: Option<bool>,
+) -> Result<Message, AppError> {
+    ensure_not_busy(&token_manager, into_conversation_id)?;
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    let conversation_repo = db.conversation_repo()?;
+    let message_repo = db.message_repo()?;
+    let source = message_repo
+        .read(from_message_id)?
+        .ok_or(AppError::DatabaseError("未找到消息".to_string()))?;
+    let quotable = matches!(source.role(), Some(Role::User | Role::Assistant))
+        || source.message_type == QUOTE_MESSAGE_TYPE;
+    if !quotable {
+        return Err(AppError::UnknownError("只能引用提问和回答".to_string()));
+    }
+    if source.conversation_id == into_conversation_id {
+        return Err(AppError::UnknownError(
+            "不能引用同一个对话中的消息".to_string(),
+        ));
+    }
+    let target = conversation_repo
+        .read(into_conversation_id)?
+        .ok_or(AppError::DatabaseError("未找到对话".to_string()))?;
+    let source_conversation = conversation_repo
+        .read(source.conversation_id)?
+        .ok_or(AppError::DatabaseError("未找到对话".to_string()))?;
+    if private_state.is_locked(into_conversation_id)
+        || private_state.is_locked(source.conversation_id)
+    {
+        return Err(AppError::UnknownError("对话已锁定，请先解锁".to_string()));
+    }
+    if source_conversation.is_private && !target.is_private {
+        return Err(AppError::UnknownError(
+            "私密对话中的消息只能引用到私密对话".to_string(),
+        ));
+    }
+    // 无痕对话退出后删除，引用到其他对话会把内容保留下来
+    if private_state.is_incognito(source.conversation_id) {
+        return Err(AppError::UnknownError(
+            "无痕对话中的消息不能引用到其他对话".to_string(),
+        ));
+    }
+
+    let quote_repo = db.quote_repo()?;
+    // 引用的消息本身是引用时沿用最初的来源
+    let source_quote = if source.message_type == QUOTE_MESSAGE_TYPE {
+        quote_repo
+            .list_by_conversation_id(source.conversation_id)?
+            .remove(&source.id)
+    } else {
+        None
+    };
+    let now = Utc::now();
+    let message = message_repo.create(&Message {
+        id: 0,
+        parent_id: None,
+        conversation_id: into_conversation_id,
+        message_type: QUOTE_MESSAGE_TYPE.to_string(),
+        content: source.content.clone(),
+        llm_model_id: None,
+        llm_model_name: None,
+        created_time: now,
+        start_time: None,
+        finish_time: Some(now),
+        token_count: 0,
+        is_truncated: false,
+        attachment_truncation: None,
+        pinned: false,
+        citations: None,
+        timed_out: false,
+        generation_metadata: None,
+        reasoning: None,
+        assistant_id: None,
+    })?;
+    quote_repo.save(
+        into_conversation_id,
+        &MessageQuote {
+            message_id: message.id,
+            ..source_quote.unwrap_or(MessageQuote {
+                message_id: 0,
+                source_conversation_id: source_conversation.id,
+                source_conversation_name: source_conversation.name,
+                source_message_id: source.id,
+                source_message_type: source.message_type.clone(),
+                source_created_time: source.created_time,
+            })
+        },
+    )?;
+    if include_attachments.unwrap_or(false) {
+        let attachment_repo = db.attachment_repo()?;
+        for attachment in attachment_repo.list_by_message_id(source.conversation_id, source.id)? {
+            attachment_repo.link(attachment.id, message.id)?;
+        }
+    }
+    Ok(message)
+}
+
+/// 把对话设为私密或者取消私密，已有的消息和附件内容会按新的状态重新保存
+///
+/// 设为私密时从系统钥匙串读取密钥（第一次使用时生成），之后对话保持解锁直到调用 lock_conversation；
+/// 取消私密需要对话已经解锁
+#[tauri::command]
+pub fn set_conversation_private(
+    app_handle: AppHandle,
+    private_state: State<'_, PrivateConversationState>,
+    token_manager: State<'_, MessageTokenManager>,
+    conversation_id: i64,
+    private: bool,
+) -> Result<(), AppError> {
+    // 切换私密状态会重新保存所有消息，和正在写入的回答冲突
+    ensure_not_busy(&token_manager, conversation_id)?;
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    let conversation_repo = db.conversation_repo()?;
+    let conversation = conversation_repo
+        .read(conversation_id)?
+        .ok_or(AppError::DatabaseError("未找到对话".to_string()))?;
+    if conversation.is_private == private {
+        return Ok(());
+    }
+    if private {
+        private_state
+            .unlock(&app_handle, conversation_id)
+            .map_err(AppError::UnknownError)?;
+    } else if private_state.is_locked(conversation_id) {
+        return Err(AppError::UnknownError("对话已锁定，请先解锁".to_string()));
+    }
+
+    // 先按旧的状态读出明文，切换状态后再写回
+    let message_repo = db.message_repo()?;
+    let attachment_repo = db.attachment_repo()?;
+    let mut messages: Vec<Message> = Vec::new();
+    for (message, _) in message_repo.list_by_conversation_id(conversation_id)? {
+        if !messages.iter().any(|m| m.id == message.id) {
+            messages.push(message);
+        }
+    }
+    // 删除后还可以撤销的消息和编辑前的内容也要按新的状态保存
+    messages.extend(message_repo.list_deleted_by_conversation_id(conversation_id)?);
+    let operations = db
+        .operation_repo()?
+        .list_by_conversation_id(conversation_id)?;
+    let attachments = attachment_repo.list_by_conversation_id(conversation_id)?;
+    let draft = db.draft_repo()?.get(Some(conversation_id), None)?;
+    let quotes = db.quote_repo()?.list_by_conversation_id(conversation_id)?;
+
+    // 所有内容在同一个事务中重新保存，中途失败时不会留下一部分加密、一部分明文的对话
+    private_state.set_private(conversation_id, private);
+    let result = db.transaction(|tx| {
+        let attachment_repo = tx.attachment_repo();
+        if private {
+            attachment_repo.unshare_conversation(conversation_id)?;
+        }
+        let message_repo = tx.message_repo();
+        for message in &messages {
+            message_repo.update(message)?;
+        }
+        for attachment in &attachments {
+            attachment_repo.update_content(attachment)?;
+        }
+        let operation_repo = tx.operation_repo();
+        for operation in &operations {
+            operation_repo.update(operation)?;
+        }
+        if let Some(draft) = &draft {
+            tx.draft_repo().save(Some(conversation_id), None, draft)?;
+        }
+        let quote_repo = tx.quote_repo();
+        for quote in quotes.values() {
+            quote_repo.save(conversation_id, quote)?;
+        }
+        tx.conversation_repo()
+            .update_private(conversation_id, private)?;
+        Ok(())
+    });
+    if result.is_err() {
+        private_state.set_private(conversation_id, !private);
+        if !private {
+            private_state
+                .unlock(&app_handle, conversation_id)
+                .map_err(AppError::UnknownError)?;
+        }
+    }
+    result
+}
+
+/// 锁定私密对话，锁定后不能查看和继续对话，直到再次解锁
+#[tauri::command]
+pub fn lock_conversation(
+    private_state: State<'_, PrivateConversationState>,
+    conversation_id: i64,
+) -> Result<(), AppError> {
+    private_state.lock(conversation_id);
+    Ok(())
+}
+
+/// 从系统钥匙串读取密钥解锁私密对话，并确认密钥可以解密对话内容
+#[tauri::command]
+pub fn unlock_conversation(
+    app_handle: AppHandle,
+    private_state: State<'_, PrivateConversationState>,
+    conversation_id: i64,
+) -> Result<(), AppError> {
+    if !private_state.is_private(conversation_id) {
+        return Ok(());
+    }
+    private_state
+        .unlock(&app_handle, conversation_id)
+        .map_err(AppError::UnknownError)?;
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    if let Err(e) = db.message_repo()?.list_by_conversation_id(conversation_id) {
+        private_state.lock(conversation_id);
+        return Err(AppError::from(e));
+    }
+    Ok(())
+}
+
+/// 置顶或取消置顶消息，置顶的消息在后续提问时总是会带入上下文
+#[tauri::command]
+pub fn pin_message(app_handle: AppHandle, message_id: i64, pinned: bool) -> Result<(), AppError> {
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    let message_repo = db.message_repo()?;
+    if message_repo.read(message_id)?.is_none() {
+        return Err(AppError::DatabaseError("未找到消息".to_string()));
+    }
+    message_repo.update_pinned(message_id, pinned)?;
+    Ok(())
+}
+
+/// 删除消息以及它重新生成的版本，返回删除的消息 id
+///
+/// 删除的消息会保留一段时间，在这之前可以通过 undo_last_operation 撤销
+#[tauri::command]
+pub fn delete_message(
+    app_handle: AppHandle,
+    token_manager: State<'_, MessageTokenManager>,
+    message_id: i64,
+) -> Result<Vec<i64>, AppError> {
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    let message_repo = db.message_repo()?;
+    let message = message_repo
+        .read(message_id)?
+        .ok_or(AppError::DatabaseError("未找到消息".to_string()))?;
+    ensure_not_busy(&token_manager, message.conversation_id)?;
+    let ids = message_repo.soft_delete(message_id)?;
+    if !ids.is_empty() {
+        db.operation_repo()?
+            .add(message.conversation_id, "delete", &ids, None)?;
+    }
+    Ok(ids)
+}
+
+/// 修改消息内容，修改前的内容会记录下来，可以通过 undo_last_operation 撤销
+#[tauri::command]
+pub fn update_message_content(
+    app_handle: AppHandle,
+    token_manager: State<'_, MessageTokenManager>,
+    message_id: i64,
+    content: String,
+) -> Result<(), AppError> {
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    let message_repo = db.message_repo()?;
+    let mut message = message_repo
+        .read(message_id)?
+        .ok_or(AppError::DatabaseError("未找到消息".to_string()))?;
+    ensure_not_busy(&token_manager, message.conversation_id)?;
+    if message.content == content {
+        return Ok(());
+    }
+    db.operation_repo()?.add(
+        message.conversation_id,
+        "edit",
+        &[message_id],
+        Some(&message.content),
+    )?;
+    message.content = content;
+    message_repo.update(&message)?;
+    Ok(())
+}
+
+/// 撤销对话中最近一次删除或者编辑消息的操作，返回撤销的操作，没有可以撤销的操作时返回 None
+#[tauri::command]
+pub fn undo_last_operation(
+    app_handle: AppHandle,
+    private_state: State<'_, PrivateConversationState>,
+    token_manager: State<'_, MessageTokenManager>,
+    conversation_id: i64,
+) -> Result<Option<MessageOperation>, AppError> {
+    if private_state.is_locked(conversation_id) {
+        return Err(AppError::UnknownError("对话已锁定，请先解锁".to_string()));
+    }
+    ensure_not_busy(&token_manager, conversation_id)?;
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    match db.operation_repo()?.last_done(conversation_id)? {
+        Some(operation) => Ok(Some(toggle_operation(&db, operation, true)?)),
+        None => Ok(None),
+    }
+}
+
+/// 重做最近一次撤销的操作，没有可以重做的操作时返回 None
+#[tauri::command]
+pub fn redo_last_operation(
+    app_handle: AppHandle,
+    private_state: State<'_, PrivateConversationState>,
+    token_manager: State<'_, MessageTokenManager>,
+    conversation_id: i64,
+) -> Result<Option<MessageOperation>, AppError> {
+    if private_state.is_locked(conversation_id) {
+        return Err(AppError::UnknownError("对话已锁定，请先解锁".to_string()));
+    }
+    ensure_not_busy(&token_manager, conversation_id)?;
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    match db.operation_repo()?.first_undone(conversation_id)? {
+        Some(operation) => Ok(Some(toggle_operation(&db, operation, false)?)),
+        None => Ok(None),
+    }
+}
+
+// 删除操作恢复或者重新删除消息，编辑操作交换消息的内容和记录的内容
+fn toggle_operation(
+    db: &ConversationDatabase,
+    mut operation: MessageOperation,
+    undo: bool,
+) -> Result<MessageOperation, AppError> {
+    let message_repo = db.message_repo()?;
+    match operation.operation_type.as_str() {
+        "delete" => message_repo.update_deleted(&operation.message_ids, !undo)?,
+        "edit" => {
+            let message_id = operation.message_ids.first().copied().unwrap_or_default();
+            if let Some(mut message) = message_repo.read(message_id)? {
+                let content = operation.content.take().unwrap_or_default();
+                operation.content = Some(std::mem::replace(&mut message.content, content));
+                message_repo.update(&message)?;
+            }
+        }
+        _ => {}
+    }
+    operation.undone = undo;
+    db.operation_repo()?.update(&operation)?;
+    Ok(operation)
+}
+
+/// 活跃度日历，返回 year 年每天的消息数量和 token 数量，只包含有消息的日期
+#[tauri::command]
+pub fn get_activity_calendar(
+    app_handle: AppHandle,
+    year: i32,
+) -> Result<Vec<ActivityDay>, AppError> {
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    Ok(db.message_repo()?.activity_calendar(year)?)
+}
+
+/// 读取对话的回答风格，没有设置时返回空的风格
+#[tauri::command]
+pub fn get_conversation_style(
+    app_handle: AppHandle,
+    conversation_id: i64,
+) -> Result<ResponseStyle, AppError> {
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    Ok(db.style_repo()?.get(conversation_id)?.unwrap_or_default())
+}
+
+/// 设置对话的回答风格，从下一次提问开始生效，所有偏好都为空时恢复助手原本的风格
+#[tauri::command]
+pub fn set_conversation_style(
+    app_handle: AppHandle,
+    conversation_id: i64,
+    style: ResponseStyle,
+) -> Result<(), AppError> {
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    if db.conversation_repo()?.read(conversation_id)?.is_none() {
+        return Err(AppError::DatabaseError("未找到对话".to_string()));
+    }
+    db.style_repo()?.save(conversation_id, &style)?;
+    Ok(())
+}
+
+/// 把对话导出为 markdown 文件，导出过程中通过 conversation_export_progress 事件发送进度
+#[tauri::command]
+pub async fn export_conversation(
+    app_handle: AppHandle,
+    feature_config_state: State<'_, FeatureConfigState>,
+    private_state: State<'_, PrivateConversationState>,
+    conversation_id: i64,
+    path: String,
+) -> Result<ConversationExportResult, AppError> {
+    if private_state.is_locked(conversation_id) {
+        return Err(AppError::UnknownError("对话已锁定，请先解锁".to_string()));
+    }
+    let pricing =
+        ModelPricing::from_config_map(&*feature_config_state.config_feature_map.lock().await);
+    // 写大量消息和图片比较耗时，放到阻塞线程中进行
+    tauri::async_runtime::spawn_blocking(move || {
+        conversation_export::export_conversation(
+            &app_handle,
+            conversation_id,
+            Path::new(&path),
+            &pricing,
+        )
+    })
+    .await
+    .map_err(|e| AppError::UnknownError(e.to_string()))?
+}
+
+/// 对话中每条回答的 token 用量和按 model_price 功能配置计算的费用，以及合计，用于对话中的费用标记
+#[tauri::command]
+pub async fn get_conversation_cost(
+    app_handle: AppHandle,
+    feature_config_state: State<'_, FeatureConfigState>,
+    conversation_id: i64,
+) -> Result<ConversationCost, AppError> {
+    let pricing =
+        ModelPricing::from_config_map(&*feature_config_state.config_feature_map.lock().await);
+    conversation_cost::conversation_cost(&app_handle, conversation_id, &pricing)
+}
+
+/// 设置模型每百万 token 的价格，输入和输出价格都为空时删除这个模型的价格
+#[tauri::command]
+pub async fn set_model_price(
+    app_handle: AppHandle,
+    feature_config_state: State<'_, FeatureConfigState>,
+    model_code: String,
+    input_price: Option<f64>,
+    output_price: Option<f64>,
+) -> Result<(), AppError> {
+    let model_code = model_code.trim().to_string();
+    if model_code.is_empty() {
+        return Err(AppError::UnknownError("模型代码不能为空".to_string()));
+    }
+    let db = SystemDatabase::new(&app_handle)?;
+    let mut configs = feature_config_state.configs.lock().await;
+    let mut config_feature_map = feature_config_state.config_feature_map.lock().await;
+    configs.retain(|c| !(c.feature_code == MODEL_PRICE_FEATURE && c.key == model_code));
+    match (input_price, output_price) {
+        (None, None) => {
+            db.delete_feature_config(MODEL_PRICE_FEATURE, &model_code)?;
+            if let Some(prices) = config_feature_map.get_mut(MODEL_PRICE_FEATURE) {
+                prices.remove(&model_code);
+            }
+        }
+        (input, output) => {
+            let price = ModelPrice::new(input.unwrap_or_default(), output.unwrap_or_default())
+                .map_err(AppError::UnknownError)?;
+            let config = model_price_config(&model_code, &price);
+            db.upsert_feature_config(&config)?;
+            configs.push(config.clone());
+            config_feature_map
+                .entry(MODEL_PRICE_FEATURE.to_string())
+                .or_default()
+                .insert(model_code, config);
+        }
+    }
+    Ok(())
+}
+
+/// 使用 translation 功能配置的助手把消息翻译成 target_lang，同一条消息的译文会保存下来，原文没有变化时不再请求模型
+#[tauri::command]
+pub async fn translate_message(
+    app_handle: AppHandle,
+    feature_config_state: State<'_, FeatureConfigState>,
+    private_state: State<'_, PrivateConversationState>,
+    message_id: i64,
+    target_lang: String,
+) -> Result<MessageTranslation, AppError> {
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    let message = db
+        .message_repo()?
+        .read(message_id)?
+        .ok_or(AppError::DatabaseError("未找到消息".to_string()))?;
+    if private_state.is_locked(message.conversation_id) {
+        return Err(AppError::UnknownError("对话已锁定，请先解锁".to_string()));
+    }
+    let config =
+        TranslationConfig::from_config_map(&*feature_config_state.config_feature_map.lock().await);
+    translation::translate_message(&app_handle, &config, &message, &target_lang).await
+}
+
+/// 术语表，target_lang 不为空时只返回这种语言和所有语言通用的术语
+#[tauri::command]
+pub fn list_glossary_terms(
+    app_handle: AppHandle,
+    target_lang: Option<String>,
+) -> Result<Vec<GlossaryTerm>, AppError> {
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    Ok(db.glossary_repo()?.list(target_lang.as_deref())?)
+}
+
+/// 新增或者修改术语，id 为 0 时新增
+#[tauri::command]
+pub fn save_glossary_term(
+    app_handle: AppHandle,
+    term: GlossaryTerm,
+) -> Result<GlossaryTerm, AppError> {
+    if term.source_term.trim().is_empty() || term.target_term.trim().is_empty() {
+        return Err(AppError::UnknownError("术语和译法不能为空".to_string()));
+    }
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    Ok(db.glossary_repo()?.save(&term)?)
+}
+
+#[tauri::command]
+pub fn delete_glossary_term(app_handle: AppHandle, id: i64) -> Result<(), AppError> {
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    db.glossary_repo()?.delete(id)?;
+    Ok(())
+}
+
+/// 保存输入框中还没有发送的内容，内容为空时删除草稿；新对话的草稿按助手保存，无痕对话不保存
+#[tauri::command]
+pub fn save_draft(
+    app_handle: AppHandle,
+    private_state: State<'_, PrivateConversationState>,
+    conversation_id: Option<i64>,
+    assistant_id: Option<i64>,
+    content: String,
+) -> Result<(), AppError> {
+    if let Some(conversation_id) = conversation_id {
+        if private_state.is_incognito(conversation_id) || private_state.is_locked(conversation_id) {
+            return Ok(());
+        }
+    }
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    db.draft_repo()?
+        .save(conversation_id, assistant_id, &content)?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_draft(
+    app_handle: AppHandle,
+    private_state: State<'_, PrivateConversationState>,
+    conversation_id: Option<i64>,
+    assistant_id: Option<i64>,
+) -> Result<Option<String>, AppError> {
+    if let Some(conversation_id) = conversation_id {
+        if private_state.is_locked(conversation_id) {
+            return Ok(None);
+        }
+    }
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    Ok(db.draft_repo()?.get(conversation_id, assistant_id)?)
+}
+
+// 参与模糊匹配的历史提示词数量上限
+const PROMPT_HISTORY_SEARCH_SCOPE: u32 = 2000;
+
+#[tauri::command]
+pub async fn search_prompt_history(
+    app_handle: AppHandle,
+    query: String,
+    limit: Option<u32>,
+) -> Result<Vec<PromptHistory>, AppError> {
+    let limit = limit.unwrap_or(20);
+    let db = ConversationDatabase::new(&app_handle).map_err(AppError::from)?;
+    let query = query.trim();
+    if query.is_empty() {
+        // 没有输入时按时间倒序返回，供输入框上下键翻阅历史
+        return Ok(db.prompt_history_repo()?.list_recent(limit)?);
+    }
+
+    let history_list = db
+        .prompt_history_repo()?
+        .list_recent(PROMPT_HISTORY_SEARCH_SCOPE)?;
+    Ok(rank_prompt_history(query, history_list, limit as usize))
+}
+
+// 按模糊匹配的分数从高到低排列，去掉不匹配的，history_list 需要已经按时间倒序
+fn rank_prompt_history(
+    query: &str,
+    history_list: Vec<PromptHistory>,
+    limit: usize,
+) -> Vec<PromptHistory> {
+    let mut scored_list: Vec<(i64, PromptHistory)> = history_list
+        .into_iter()
+        .filter_map(|history| fuzzy_match_score(query, &history.prompt).map(|s| (s, history)))
+        .collect();
+    // 分数相同的情况下越新的越靠前，sort_by_key 是稳定排序，原列表已经按时间倒序
+    scored_list.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+
+    scored_list
+        .into_iter()
+        .take(limit)
+        .map(|(_, history)| history)
+        .collect()
+}
+
+// 忽略大小写的子序列匹配，连续命中的字符、从开头命中以及整段包含都会获得更高的分数，不匹配返回 None
+fn fuzzy_match_score(query: &str, text: &str) -> Option<i64> {
+    let query_chars: Vec<char> = query.to_lowercase().chars().collect();
+    let text_lower = text.to_lowercase();
+    let text_chars: Vec<char> = text_lower.chars().collect();
+
+    let mut score: i64 = 0;
+    let mut query_index = 0;
+    let mut last_match: Option<usize> = None;
+    for (text_index, c) in text_chars.iter().enumerate() {
+        if query_index >= query_chars.len() {
+            break;
+        }
+        if *c == query_chars[query_index] {
+            score += 1;
+            if let Some(last) = last_match {
+                if last + 1 == text_index {
+                    score += 5;
+                }
+            } else if text_index == 0 {
+                score += 10;
+            }
+            last_match = Some(text_index);
+            query_index += 1;
+        }
+    }
+    if query_index < query_chars.len() {
+        return None;
+    }
+
+    let query_lower: String = query_chars.iter().collect();
+    if text_lower.contains(&query_lower) {
+        score += 50;
+    }
+    // 越短的文本与查询越接近
+    score -= (text_chars.len() as i64 - query_chars.len() as i64) / 10;
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_match_score() {
+        // 完全相同：开头命中 10，连续命中 5，整段包含 50
+        assert_eq!(fuzzy_match_score("abc", "abc"), Some(73));
+        // 不连续的子序列也能匹配，只有开头的加分
+        assert_eq!(fuzzy_match_score("abc", "axbxc"), Some(13));
+        // 忽略大小写
+        assert_eq!(fuzzy_match_score("ABC", "xAbC"), Some(63));
+        // 顺序不对或者缺少字符时不匹配
+        assert_eq!(fuzzy_match_score("ba", "ab"), None);
+        assert_eq!(fuzzy_match_score("abd", "abc"), None);
+        assert_eq!(fuzzy_match_score("abc", ""), None);
+    }
+
+    #[test]
+    fn test_fuzzy_match_bonus() {
+        // 连续命中比分散命中分数高
+        assert!(fuzzy_match_score("ab", "xab") > fuzzy_match_score("ab", "xaxb"));
+        // 从开头命中比从中间命中分数高
+        assert!(fuzzy_match_score("ab", "abx") > fuzzy_match_score("ab", "xab"));
+        // 每多 10 个字符扣 1 分
+        assert_eq!(
+            fuzzy_match_score("abc", &format!("abc{}", "x".repeat(20))),
+            Some(71)
+        );
+        assert!(
+            fuzzy_match_score("abc", "abcx")
+                > fuzzy_match_score("abc", &format!("abc{}", "x".repeat(40)))
+        );
+    }
+
+    #[test]
+    fn test_fuzzy_match_cjk() {
+        assert_eq!(fuzzy_match_score("周报", "帮我写一份周报"), Some(57));
+        assert_eq!(fuzzy_match_score("写周报", "帮我写一份周报"), Some(8));
+        assert_eq!(fuzzy_match_score("月报", "帮我写一份周报"), None);
+    }
+
+    #[test]
+    fn test_rank_prompt_history() {
+        let history = |id: i64, prompt: &str| PromptHistory {
+            id,
+            assistant_id: None,
+            prompt: prompt.to_string(),
+            created_time: Utc::now(),
+        };
+        // 按时间倒序排列，id 越小越新
+        let history_list = vec![
+            history(1, "xaxb"),
+            history(2, "ab"),
+            history(3, "xab"),
+            history(4, "ab"),
+            history(5, "cd"),
+        ];
+        let ids = |list: Vec<PromptHistory>| list.iter().map(|h| h.id).collect::<Vec<_>>();
+        assert_eq!(
+            ids(rank_prompt_history("ab", history_list.clone(), 10)),
+            vec![2, 4, 3, 1]
+        );
+        assert_eq!(ids(rank_prompt_history("ab", history_list, 2)), vec![2, 4]);
+    }
+}
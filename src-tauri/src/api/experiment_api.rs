@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::api::ai_api::{ask_ai, AiRequest, AiResponse};
+use crate::db::assistant_db::AssistantDatabase;
+use crate::errors::AppError;
+use crate::state::message_token::MessageTokenManager;
+use crate::state::request_dedup::RequestDedupState;
+use crate::{AppHandle, Window};
+use crate::{AppState, FeatureConfigState};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExperimentResponse {
+    pub conversation_id: i64,
+    pub message_id: i64,
+}
+
+// 返回给前端的结果只区分左右两侧，投票之前不暴露哪一侧是哪个助手
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExperimentRun {
+    pub experiment_id: i64,
+    pub left: ExperimentResponse,
+    pub right: ExperimentResponse,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExperimentStats {
+    pub assistant_a_id: i64,
+    pub assistant_b_id: i64,
+    pub total_votes: i64,
+    pub a_wins: i64,
+    pub b_wins: i64,
+    pub ties: i64,
+    pub a_win_rate: f64,
+    pub b_win_rate: f64,
+}
+
+/// 用同一个提示词分别向两个助手提问，两个回复随机排列在左右两侧用于盲测
+#[tauri::command]
+pub async fn run_experiment(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    feature_config_state: State<'_, FeatureConfigState>,
+    message_token_manager: State<'_, MessageTokenManager>,
+    request_dedup: State<'_, RequestDedupState<AiResponse>>,
+    window: Window,
+    assistant_a_id: i64,
+    assistant_b_id: i64,
+    prompt: String,
+) -> Result<ExperimentRun, AppError> {
+    if assistant_a_id == assistant_b_id {
+        return Err(AppError::UnknownError("请选择两个不同的助手".to_string()));
+    }
+
+    let mut responses = Vec::new();
+    for assistant_id in [assistant_a_id, assistant_b_id] {
+        let response = ask_ai(
+            app_handle.clone(),
+            state.clone(),
+            feature_config_state.clone(),
+            message_token_manager.clone(),
+            request_dedup.clone(),
+            window.clone(),
+            AiRequest::new_conversation(assistant_id, prompt.clone()).with_response_cache(),
+            None,
+            None,
+        )
+        .await?;
+        responses.push(ExperimentResponse {
+            conversation_id: response.conversation_id,
+            message_id: response.add_message_id,
+        });
+    }
+    let response_b = responses.pop().unwrap();
+    let response_a = responses.pop().unwrap();
+
+    let a_on_left = chrono::Utc::now().timestamp_subsec_nanos() % 2 == 0;
+    let assistant_db = AssistantDatabase::new(&app_handle)?;
+    let experiment_id = assistant_db.add_experiment(
+        assistant_a_id,
+        assistant_b_id,
+        &prompt,
+        response_a.conversation_id,
+        response_b.conversation_id,
+        a_on_left,
+    )?;
+
+    let (left, right) = if a_on_left {
+        (response_a, response_b)
+    } else {
+        (response_b, response_a)
+    };
+    Ok(ExperimentRun {
+        experiment_id,
+        left,
+        right,
+    })
+}
+
+/// 记录用户的偏好，choice 为 left、right 或 tie，返回这组助手最新的胜率
+#[tauri::command]
+pub fn vote_experiment(
+    app_handle: AppHandle,
+    experiment_id: i64,
+    choice: String,
+) -> Result<ExperimentStats, AppError> {
+    let assistant_db = AssistantDatabase::new(&app_handle)?;
+    let experiment = assistant_db.get_experiment(experiment_id)?;
+    let vote = match (choice.as_str(), experiment.a_on_left) {
+        ("left", true) | ("right", false) => "a",
+        ("left", false) | ("right", true) => "b",
+        ("tie", _) => "tie",
+        _ => {
+            return Err(AppError::UnknownError(format!(
+                "无效的投票选项: {}",
+                choice
+            )))
+        }
+    };
+    assistant_db.update_experiment_vote(experiment_id, vote)?;
+    experiment_stats(
+        &assistant_db,
+        experiment.assistant_a_id,
+        experiment.assistant_b_id,
+    )
+}
+
+#[tauri::command]
+pub fn get_experiment_stats(
+    app_handle: AppHandle,
+    assistant_a_id: i64,
+    assistant_b_id: i64,
+) -> Result<ExperimentStats, AppError> {
+    let assistant_db = AssistantDatabase::new(&app_handle)?;
+    experiment_stats(&assistant_db, assistant_a_id, assistant_b_id)
+}
+
+fn experiment_stats(
+    assistant_db: &AssistantDatabase,
+    assistant_a_id: i64,
+    assistant_b_id: i64,
+) -> Result<ExperimentStats, AppError> {
+    let (a_wins, b_wins, ties) =
+        assistant_db.get_experiment_votes(assistant_a_id, assistant_b_id)?;
+    let total_votes = a_wins + b_wins + ties;
+    let win_rate = |wins: i64| {
+        if total_votes == 0 {
+            0.0
+        } else {
+            wins as f64 / total_votes as f64
+        }
+    };
+    Ok(ExperimentStats {
+        assistant_a_id,
+        assistant_b_id,
+        total_votes,
+        a_wins,
+        b_wins,
+        ties,
+        a_win_rate: win_rate(a_wins),
+        b_win_rate: win_rate(b_wins),
+    })
+}
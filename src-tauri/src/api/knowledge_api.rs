@@ -0,0 +1,80 @@
+use std::path::Path;
+use std::time::Instant;
+
+use crate::AppHandle;
+use crate::{
+    db::knowledge_db::{ContextFolder, KnowledgeDatabase},
+    errors::AppError,
+    knowledge::indexer::index_folder,
+    webhook::{self, WebhookEvent},
+};
+
+#[tauri::command]
+pub async fn add_context_folder(
+    app_handle: AppHandle,
+    path: String,
+) -> Result<ContextFolder, AppError> {
+    if !Path::new(&path).is_dir() {
+        return Err(AppError::IoError(format!("{} 不是一个文件夹", path)));
+    }
+
+    let db = KnowledgeDatabase::new(&app_handle)?;
+    let folder_id = db.add_context_folder(&path)?;
+    let folder = db
+        .get_context_folder(folder_id)?
+        .ok_or(AppError::DatabaseError("未找到上下文文件夹".to_string()))?;
+
+    spawn_index(app_handle, folder_id, true);
+    Ok(folder)
+}
+
+#[tauri::command]
+pub async fn list_context_folders(app_handle: AppHandle) -> Result<Vec<ContextFolder>, AppError> {
+    let db = KnowledgeDatabase::new(&app_handle)?;
+    Ok(db.get_context_folders()?)
+}
+
+#[tauri::command]
+pub async fn remove_context_folder(app_handle: AppHandle, folder_id: i64) -> Result<(), AppError> {
+    let db = KnowledgeDatabase::new(&app_handle)?;
+    db.delete_context_folder(folder_id)?;
+    Ok(())
+}
+
+// 重新索引文件夹中的所有文件，进度通过 context_folder_index_status 事件通知
+#[tauri::command]
+pub async fn reindex_folder(app_handle: AppHandle, folder_id: i64) -> Result<(), AppError> {
+    let db = KnowledgeDatabase::new(&app_handle)?;
+    db.get_context_folder(folder_id)?
+        .ok_or(AppError::DatabaseError("未找到上下文文件夹".to_string()))?;
+
+    spawn_index(app_handle, folder_id, true);
+    Ok(())
+}
+
+fn spawn_index(app_handle: AppHandle, folder_id: i64, full: bool) {
+    tauri::async_runtime::spawn_blocking(move || {
+        let started = Instant::now();
+        let message = match index_folder(&app_handle, folder_id, full) {
+            Ok(_) => "上下文文件夹索引完成".to_string(),
+            Err(e) => {
+                println!("index context folder {} error: {:?}", folder_id, e);
+                format!("上下文文件夹索引失败：{}", e)
+            }
+        };
+        webhook::notify(
+            &app_handle,
+            WebhookEvent {
+                event: "index_finished".to_string(),
+                title: KnowledgeDatabase::new(&app_handle)
+                    .ok()
+                    .and_then(|db| db.get_context_folder(folder_id).ok().flatten())
+                    .map(|folder| folder.path)
+                    .unwrap_or_else(|| format!("上下文文件夹 {}", folder_id)),
+                message,
+                conversation_id: None,
+                duration_secs: started.elapsed().as_secs(),
+            },
+        );
+    });
+}
@@ -1,18 +1,21 @@
-use super::ModelProvider;
-use crate::{
-    api::llm_api::LlmModel,
-    db::{
-        conversation_db::{AttachmentType, MessageAttachment},
-        llm_db::LLMProviderConfig,
-    },
+use super::{
+    capability::set_clamped_param,
+    chat_message::{ChatMessage, Role},
+    check_response_status, finish_reason_event,
+    mock::FixtureRecorder,
+    request_builder::{apply_request_headers, AuthScheme},
+    request_context::RequestContext,
+    send_request, shared_client,
+    sse::SseDecoder,
+    tool_call::{parse_tool_result, send_tool_calls, ToolCall, ToolCallAccumulator},
+    usage_event, ChatResponse, ModelProvider, ProviderClient, ProviderError, TokenUsage,
 };
+use crate::{api::llm_api::LlmModel, db::llm_db::LLMProviderConfig};
 use anyhow::{anyhow, Result};
 use futures::StreamExt;
 use regex::Regex;
-use reqwest::Client;
 use serde::{Deserialize, Serialize, Serializer};
 use serde_json::{json, Value};
-use std::collections::HashMap;
 use tokio_util::sync::CancellationToken;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -71,6 +74,8 @@ pub struct AnthropicChatCompletionChunk {
     pub index: Option<usize>,
     pub delta: Option<AnthropicTextDelta>,
     pub message: Option<AnthropicMessage>,
+    // message_delta 事件的累计输出用量在顶层，不在 delta 中
+    pub usage: Option<AnthropicUsage>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -115,9 +120,40 @@ impl Serialize for ToolChoice {
     }
 }
 
+/// 请求中使用的内容块，一条消息可以由多个内容块组成
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnthropicRequestBlock {
+    Text {
+        text: String,
+    },
+    Image {
+        source: AnthropicImageSource,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        content: Vec<AnthropicRequestBlock>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        is_error: Option<bool>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AnthropicImageSource {
+    #[serde(rename = "type")]
+    pub source_type: String,
+    pub media_type: String,
+    pub data: String,
+}
+
 pub struct AnthropicProvider {
     llm_provider_config: Vec<LLMProviderConfig>,
-    client: Client,
+    client: ProviderClient,
 }
 
 impl ModelProvider for AnthropicProvider {
@@ -127,128 +163,39 @@ impl ModelProvider for AnthropicProvider {
     {
         AnthropicProvider {
             llm_provider_config,
-            client: Client::new(),
+            client: shared_client(),
         }
     }
 
     fn chat(
         &self,
         _message_id: i64,
-        messages: Vec<(String, String, Vec<MessageAttachment>)>,
+        messages: Vec<ChatMessage>,
         model_config: Vec<crate::db::assistant_db::AssistantModelConfig>,
         cancel_token: CancellationToken,
-    ) -> futures::future::BoxFuture<'static, Result<String>> {
+    ) -> futures::future::BoxFuture<'static, Result<ChatResponse>> {
         let config = self.llm_provider_config.clone();
         let client = self.client.clone();
 
         Box::pin(async move {
-            let config_map: HashMap<String, String> =
-                config.into_iter().map(|c| (c.name, c.value)).collect();
-
-            let default_endpoint = &"https://api.anthropic.com".to_string();
-            let endpoint = config_map
-                .get("endpoint")
-                .unwrap_or(default_endpoint)
-                .trim_end_matches('/');
-            let url = format!("{}/v1/messages", endpoint);
-            let api_key = config_map.get("api_key").unwrap().clone();
-
-            let json_messages = messages
-                .iter()
-                .filter(|(message_type, _, _)| message_type != "system")
-                .map(|(message_type, content, attachment_list)| {
-                    if attachment_list.len() > 0 {
-                        let content_array = vec![json!({
-                            "type": "text",
-                            "text": content
-                        })];
-
-                        let mut images = attachment_list
-                            .iter()
-                            .filter(|a| a.attachment_type == AttachmentType::Image)
-                            .map(|a| {
-                                let attachment_content = a.attachment_content.clone().unwrap();
-                                let re =
-                                    Regex::new(r"data:(?P<media_type>[^;]+);base64,(?P<data>.+)")
-                                        .unwrap();
-                                let caps = re.captures(&attachment_content).unwrap();
-                                let media_type = caps.name("media_type").unwrap().as_str();
-                                let data = caps.name("data").unwrap().as_str();
-
-                                json!({
-                                    "type": "image",
-                                    "source": {
-                                        "type": "base64",
-                                        "media_type": media_type,
-                                        "data": data,
-                                    },
-                                })
-                            })
-                            .collect::<Vec<Value>>();
-                        images.extend(content_array);
-
-                        json!({
-                            "role": message_type,
-                            "content": images,
-                        })
-                    } else {
-                        json!({
-                            "role": message_type,
-                            "content": content
-                        })
-                    }
-                })
-                .collect::<Vec<serde_json::Value>>();
-            let system_message = messages
-                .iter()
-                .find(|(message_type, _, _)| message_type == "system");
-
-            let model_config_map = model_config
-                .iter()
-                .filter_map(|config| {
-                    config
-                        .value
-                        .as_ref()
-                        .map(|value| (config.name.clone(), value.clone()))
-                })
-                .collect::<HashMap<String, String>>();
-            let temperature = model_config_map
-                .get("temperature")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(0.75);
-            let top_p = model_config_map
-                .get("top_p")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(1.0);
-            let max_tokens = model_config_map
-                .get("max_tokens")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(2000);
-
-            let model = model_config_map.get("model"); // Assuming the first model config is the one to use
-
-            let body = json!({
-                "model": model,
-                "temperature": temperature,
-                "top_p": top_p,
-                "system": system_message.map(|(_, content, _)| content),
-                "max_tokens": max_tokens,
-                "messages": json_messages,
-                "stream": false
-            });
+            let client = client.get()?;
+            let context = RequestContext::new(config, &model_config, messages);
+            let url = context.url("https://api.anthropic.com", "/v1/messages");
+            let body = build_body(&context, false);
             println!("anthropic chat: {:?}", body);
 
-            let request = client
-                .post(&url)
-                .header("X-API-Key", api_key)
-                .header("anthropic-version", "2023-06-01")
-                .json(&body);
+            let request =
+                apply_request_headers(client.post(&url), &context.config_map, AuthScheme::XApiKey)?
+                    .header("anthropic-version", "2023-06-01")
+                    .json(&body);
 
             let response = tokio::select! {
-                response = request.send() => response?,
+                response = send_request(request) => response?,
                 _ = cancel_token.cancelled() => return Err(anyhow!("Request cancelled")),
             };
 
+            let response = check_response_status(response).await?;
+
             let json_response = tokio::select! {
                 json = response.json::<serde_json::Value>() => json?,
                 _ = cancel_token.cancelled() => return Err(anyhow!("Request cancelled")),
@@ -256,8 +203,42 @@ impl ModelProvider for AnthropicProvider {
 
             println!("anthropic chat response: {:?}", json_response.clone());
 
-            if let Some(content) = json_response["content"][0]["text"].as_str() {
-                Ok(content.to_string())
+            // 回复可能由多个内容块组成（例如文本和 tool_use），文本块拼接成回答
+            if let Some(blocks) = json_response["content"].as_array() {
+                let content = blocks
+                    .iter()
+                    .filter(|block| block["type"] == "text")
+                    .filter_map(|block| block["text"].as_str())
+                    .collect::<Vec<&str>>()
+                    .join("");
+                let tool_calls = blocks
+                    .iter()
+                    .filter(|block| block["type"] == "tool_use")
+                    .map(|block| ToolCall {
+                        id: block["id"].as_str().unwrap_or_default().to_string(),
+                        name: block["name"].as_str().unwrap_or_default().to_string(),
+                        arguments: block["input"].clone(),
+                    })
+                    .collect();
+                Ok(ChatResponse {
+                    content,
+                    finish_reason: json_response["stop_reason"].as_str().map(|s| s.to_string()),
+                    tool_calls,
+                    usage: json_response
+                        .get("usage")
+                        .filter(|usage| usage.is_object())
+                        .map(|usage| {
+                            let mut token_usage = TokenUsage::default();
+                            token_usage.merge(&TokenUsage {
+                                prompt_tokens: usage["input_tokens"].as_u64().map(|v| v as u32),
+                                completion_tokens: usage["output_tokens"]
+                                    .as_u64()
+                                    .map(|v| v as u32),
+                                total_tokens: None,
+                            });
+                            token_usage
+                        }),
+                })
             } else {
                 Err(anyhow!("Failed to get content from response"))
             }
@@ -267,7 +248,7 @@ impl ModelProvider for AnthropicProvider {
     fn chat_stream(
         &self,
         message_id: i64,
-        messages: Vec<(String, String, Vec<MessageAttachment>)>,
+        messages: Vec<ChatMessage>,
         model_config: Vec<crate::db::assistant_db::AssistantModelConfig>,
         tx: tokio::sync::mpsc::Sender<(i64, String, bool)>,
         cancel_token: CancellationToken,
@@ -276,192 +257,97 @@ impl ModelProvider for AnthropicProvider {
         let client = self.client.clone();
 
         Box::pin(async move {
-            let config_map: HashMap<String, String> =
-                config.into_iter().map(|c| (c.name, c.value)).collect();
-
-            let default_endpoint = &"https://api.anthropic.com".to_string();
-            let endpoint = config_map
-                .get("endpoint")
-                .unwrap_or(default_endpoint)
-                .trim_end_matches('/');
-            let url = format!("{}/v1/messages", endpoint);
-            let api_key = config_map.get("api_key").unwrap().clone();
-
-            let json_messages = messages
-                .iter()
-                .filter(|(message_type, _, _)| message_type != "system")
-                .map(|(message_type, content, attachment_list)| {
-                    if attachment_list.len() > 0 {
-                        let content_array = vec![json!({
-                            "type": "text",
-                            "text": content
-                        })];
-
-                        let mut images = attachment_list
-                            .iter()
-                            .filter(|a| a.attachment_type == AttachmentType::Image)
-                            .map(|a| {
-                                let attachment_content = a.attachment_content.clone().unwrap();
-                                let re =
-                                    Regex::new(r"data:(?P<media_type>[^;]+);base64,(?P<data>.+)")
-                                        .unwrap();
-                                let caps = re.captures(&attachment_content).unwrap();
-                                let media_type = caps.name("media_type").unwrap().as_str();
-                                let data = caps.name("data").unwrap().as_str();
-
-                                json!({
-                                    "type": "image",
-                                    "source": {
-                                        "type": "base64",
-                                        "media_type": media_type,
-                                        "data": data,
-                                    },
-                                })
-                            })
-                            .collect::<Vec<Value>>();
-                        images.extend(content_array);
-
-                        json!({
-                            "role": message_type,
-                            "content": images,
-                        })
-                    } else {
-                        json!({
-                            "role": message_type,
-                            "content": content
-                        })
-                    }
-                })
-                .collect::<Vec<Value>>();
-            let system_message = messages
-                .iter()
-                .find(|(message_type, _, _)| message_type == "system");
-
-            let model_config_map = model_config
-                .iter()
-                .filter_map(|config| {
-                    config
-                        .value
-                        .as_ref()
-                        .map(|value| (config.name.clone(), value.clone()))
-                })
-                .collect::<HashMap<String, String>>();
-            let temperature = model_config_map
-                .get("temperature")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(0.75);
-            let top_p = model_config_map
-                .get("top_p")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(1.0);
-            let max_tokens = model_config_map
-                .get("max_tokens")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(2000);
-
-            let model = model_config_map.get("model"); // Assuming the first model config is the one to use
-
-            let body = json!({
-                "model": model,
-                "temperature": temperature,
-                "top_p": top_p,
-                "system": system_message.map(|(_, content, _)| content),
-                "max_tokens": max_tokens,
-                "messages": json_messages,
-                "stream": true
-            });
+            let client = client.get()?;
+            let context = RequestContext::new(config, &model_config, messages);
+            let url = context.url("https://api.anthropic.com", "/v1/messages");
+            let body = build_body(&context, true);
             println!("anthropic chat stream url: {} body: {:?}", url, body);
 
-            let request = client
-                .post(&url)
-                .header("X-API-Key", api_key)
-                .header("anthropic-version", "2023-06-01")
-                .json(&body);
+            let request =
+                apply_request_headers(client.post(&url), &context.config_map, AuthScheme::XApiKey)?
+                    .header("anthropic-version", "2023-06-01")
+                    .json(&body);
 
             let response = tokio::select! {
-                response = request.send() => response?,
+                response = send_request(request) => response?,
                 _ = cancel_token.cancelled() => return Err(anyhow!("Request cancelled")),
             };
 
+            let response = check_response_status(response).await?;
+
             let mut stream = response.bytes_stream();
             let mut full_text = String::new();
-            let mut buffer = String::new();
+            let mut decoder = SseDecoder::new();
+            let mut recorder = FixtureRecorder::from_config(&context.config_map);
+            // message_start 中带有输入用量，message_delta 中是累计的输出用量
+            let mut usage = TokenUsage::default();
+            let mut tool_calls = ToolCallAccumulator::default();
 
             loop {
                 tokio::select! {
                     chunk = stream.next() => {
                         match chunk {
                             Some(Ok(chunk)) => {
-                                let s = std::str::from_utf8(&chunk)
-                                    .map_err(|e| anyhow!("Invalid UTF-8 sequence: {}", e))?;
-                                buffer.push_str(s);
-                                println!("anthropic chat stream text: {}", s);
-
-                                loop {
-                                    if let Some(index) = buffer.find("\n\n") {
-                                        let chunk = buffer[..index].to_string();
-                                        buffer.drain(..=index + 1);
-
-                                        let processed_chunk = chunk
-                                            .trim_start_matches("event: message_start")
-                                            .trim_start_matches("event: content_block_start")
-                                            .trim_start_matches("event: ping")
-                                            .trim_start_matches("event: content_block_delta")
-                                            .trim_start_matches("event: content_block_stop")
-                                            .trim_start_matches("event: message_delta")
-                                            .trim_start_matches("event: message_stop")
-                                            .to_string();
-
-                                        let cleaned_string = processed_chunk
-                                            .trim_start()
-                                            .strip_prefix("data: ")
-                                            .unwrap_or(&processed_chunk);
-                                        print!("clean string: {}", cleaned_string);
-
-                                        match serde_json::from_str::<AnthropicChatCompletionChunk>(cleaned_string) {
-                                            Ok(d) => {
-                                                if let Some(delta) = d.delta {
-                                                    println!("anthropic chat stream delta: {:?}", delta);
-
-                                                    if let Some(content) = delta.text {
-                                                        full_text.push_str(&content);
-                                                        tx.send((message_id, full_text.clone(), false)).await?;
-                                                    }
-                                                } else if d.event_type == "message_stop" {
-                                                    tx.send((message_id, full_text.clone(), true)).await?;
-                                                    break;
-                                                } else {
-                                                    eprintln!("Unknown AnthropicChatCompletionChunk: {:?}", d);
-                                                }
+                                if let Some(recorder) = recorder.as_mut() {
+                                    recorder.record(&chunk);
+                                }
+                                println!("anthropic chat stream text: {}", String::from_utf8_lossy(&chunk));
+
+                                for event in decoder.push(&chunk) {
+                                    if let Some(value) = event.json() {
+                                        tool_calls.push_anthropic_event(&value);
+                                    }
+                                    match serde_json::from_str::<AnthropicChatCompletionChunk>(&event.data) {
+                                        Ok(d) => {
+                                            let chunk_usage = d
+                                                .usage
+                                                .as_ref()
+                                                .or(d.message.as_ref().and_then(|m| m.usage.as_ref()));
+                                            if let Some(chunk_usage) = chunk_usage {
+                                                usage.merge(&TokenUsage {
+                                                    prompt_tokens: chunk_usage.input_tokens,
+                                                    completion_tokens: chunk_usage.output_tokens,
+                                                    total_tokens: None,
+                                                });
+                                                tx.send((message_id, usage_event(&usage), false)).await?;
                                             }
-                                            Err(_) => {
-                                                let processed_chunk = cleaned_string
-                                                    .trim_start_matches("event: error")
-                                                    .to_string();
-                                                let cleaned_string = &processed_chunk
-                                                    .trim_start()
-                                                    .strip_prefix("data: ")
-                                                    .unwrap_or(&processed_chunk);
-                                                match serde_json::from_str::<AnthropicErrorMessage>(&cleaned_string)
-                                                {
-                                                    Ok(error_message) => {
-                                                        eprintln!(
-                                                            "{}: {}",
-                                                            error_message.error.error_type,
-                                                            error_message.error.message
-                                                        );
-                                                    }
-                                                    Err(_) => {
-                                                        eprintln!(
-                                                                "Couldn't parse AnthropicChatCompletionChunk or AnthropicErrorMessage: {}",
-                                                                &cleaned_string
-                                                            );
-                                                    }
+                                            if let Some(delta) = d.delta {
+                                                println!("anthropic chat stream delta: {:?}", delta);
+
+                                                if let Some(content) = delta.text {
+                                                    full_text.push_str(&content);
+                                                    tx.send((message_id, full_text.clone(), false)).await?;
+                                                }
+                                                if let Some(stop_reason) = delta.stop_reason {
+                                                    tx.send((message_id, finish_reason_event(&stop_reason), false)).await?;
                                                 }
+                                            } else if d.event_type == "message_stop" {
+                                                send_tool_calls(&mut tool_calls, message_id, &tx).await?;
+                                                tx.send((message_id, full_text.clone(), true)).await?;
+                                                return Ok(());
                                             }
                                         }
-                                    } else {
-                                        break;
+                                        Err(_) => match serde_json::from_str::<AnthropicErrorMessage>(&event.data) {
+                                            // 流式响应中途的错误（例如 overloaded_error）结束请求，交给调用方按错误分类处理
+                                            Ok(error_message) => {
+                                                return Err(ProviderError::from_body(
+                                                    None,
+                                                    &event.data,
+                                                    format!(
+                                                        "{}: {}",
+                                                        error_message.error.error_type,
+                                                        error_message.error.message
+                                                    ),
+                                                )
+                                                .into());
+                                            }
+                                            Err(_) => {
+                                                eprintln!(
+                                                    "Couldn't parse AnthropicChatCompletionChunk or AnthropicErrorMessage: {}",
+                                                    &event.data
+                                                );
+                                            }
+                                        },
                                     }
                                 }
                             }
@@ -516,6 +402,7 @@ impl ModelProvider for AnthropicProvider {
                 vision_support: true, // Set this according to your needs
                 audio_support: false, // Set this according to your needs
                 video_support: false, // Set this according to your needs
+                is_enabled: true,
             };
             result.push(llm_model);
         }
@@ -523,3 +410,171 @@ impl ModelProvider for AnthropicProvider {
         Box::pin(async move { Ok(result) })
     }
 }
+
+// chat 和 chat_stream 共用的请求体
+fn build_body(context: &RequestContext, stream: bool) -> Value {
+    let params = context.params();
+    let model_code = params.model.as_deref().unwrap_or("");
+    let mut body = json!({
+        "model": params.model,
+        "system": context.system_prompt(),
+        "max_tokens": params.max_tokens,
+        "messages": build_messages(&context.messages),
+        "stream": stream
+    });
+    // 预设中的 temperature 可能超过 Anthropic 接受的上限
+    set_clamped_param(
+        &mut body,
+        "temperature",
+        "anthropic",
+        model_code,
+        "temperature",
+        params.temperature,
+    );
+    set_clamped_param(
+        &mut body,
+        "top_p",
+        "anthropic",
+        model_code,
+        "top_p",
+        params.top_p,
+    );
+    if let Some(stop) = params.stop {
+        body["stop_sequences"] = json!(stop);
+    }
+    let tools = context.tools();
+    if !tools.is_empty() {
+        body["tools"] = json!(tools
+            .iter()
+            .map(|tool| {
+                json!({
+                    "name": tool.name,
+                    "description": tool.description,
+                    "input_schema": tool.parameters,
+                })
+            })
+            .collect::<Vec<Value>>());
+    }
+    body
+}
+
+/// 构建 Anthropic 格式的消息列表
+///
+/// - 内部构建的内容块（例如包含 tool_use 的助手消息）会按原样作为多个内容块发送，
+///   消息文字即使是 JSON 数组也只作为文本块发送
+/// - role 为 tool 的消息会转换成 user 消息中的 tool_result 块
+/// - 相邻的同角色消息会合并成一条，满足 Anthropic 要求 user 和 assistant 交替出现的限制
+fn build_messages(messages: &[ChatMessage]) -> Vec<Value> {
+    let mut result: Vec<(Role, Vec<AnthropicRequestBlock>)> = Vec::new();
+
+    for message in messages {
+        if message.role == Role::System {
+            continue;
+        }
+        let content = message.text();
+        let images = message
+            .images()
+            .filter_map(|a| a.attachment_content.as_deref().and_then(parse_image_block))
+            .collect::<Vec<AnthropicRequestBlock>>();
+
+        let (role, blocks) = if message.role == Role::Tool {
+            let blocks = match parse_tool_result(message) {
+                Some(tool_result) => {
+                    let mut tool_result_content = text_blocks(&tool_result.content);
+                    tool_result_content.extend(images);
+                    vec![AnthropicRequestBlock::ToolResult {
+                        tool_use_id: tool_result.tool_use_id,
+                        content: tool_result_content,
+                        is_error: tool_result.is_error,
+                    }]
+                }
+                None => {
+                    eprintln!("Invalid tool result message: {}", content);
+                    text_blocks(&content)
+                }
+            };
+            (Role::User, blocks)
+        } else {
+            let mut blocks = images;
+            blocks.extend(text_blocks(&content));
+            blocks.extend(message.blocks().flat_map(parse_content_blocks));
+            (message.role, blocks)
+        };
+
+        if blocks.is_empty() {
+            continue;
+        }
+        match result.last_mut() {
+            Some((last_role, last_blocks)) if *last_role == role => last_blocks.extend(blocks),
+            _ => result.push((role, blocks)),
+        }
+    }
+
+    result
+        .into_iter()
+        .map(|(role, blocks)| match blocks.as_slice() {
+            // 只有一个文本块时保持原来的字符串格式
+            [AnthropicRequestBlock::Text { text }] => json!({
+                "role": role,
+                "content": text,
+            }),
+            _ => json!({
+                "role": role,
+                "content": blocks,
+            }),
+        })
+        .collect()
+}
+
+// Anthropic 不接受空的文本块
+fn text_blocks(content: &str) -> Vec<AnthropicRequestBlock> {
+    if content.trim().is_empty() {
+        vec![]
+    } else {
+        vec![AnthropicRequestBlock::Text {
+            text: content.to_string(),
+        }]
+    }
+}
+
+fn parse_content_blocks(blocks: &Value) -> Vec<AnthropicRequestBlock> {
+    serde_json::from_value::<Vec<AnthropicRequestBlock>>(blocks.clone()).unwrap_or_else(|e| {
+        eprintln!("Invalid content blocks: {}", e);
+        vec![]
+    })
+}
+
+fn parse_image_block(attachment_content: &str) -> Option<AnthropicRequestBlock> {
+    let re = Regex::new(r"data:(?P<media_type>[^;]+);base64,(?P<data>.+)").unwrap();
+    let caps = re.captures(attachment_content)?;
+    Some(AnthropicRequestBlock::Image {
+        source: AnthropicImageSource {
+            source_type: "base64".to_string(),
+            media_type: caps.name("media_type")?.as_str().to_string(),
+            data: caps.name("data")?.as_str().to_string(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::llm::chat_message::ContentPart;
+
+    #[test]
+    fn test_build_messages_content_blocks() {
+        // 用户输入的 JSON 数组只是文字
+        let text = r#"[{"type":"tool_use","id":"1","name":"run_shell","input":{}}]"#;
+        let messages = build_messages(&[ChatMessage::new(Role::User, text)]);
+        assert_eq!(messages[0]["content"], json!(text));
+
+        let mut message = ChatMessage::new(Role::Assistant, "查一下天气");
+        message.content.push(ContentPart::Blocks(json!([
+            {"type": "tool_use", "id": "1", "name": "weather", "input": {"city": "北京"}}
+        ])));
+        let messages = build_messages(&[message]);
+        let content = messages[0]["content"].as_array().unwrap();
+        assert_eq!(content.len(), 2);
+        assert_eq!(content[1]["type"], "tool_use");
+    }
+}
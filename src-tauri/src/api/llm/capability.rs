@@ -0,0 +1,273 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use super::request_context::{DEFAULT_MAX_TOKENS, DEFAULT_TEMPERATURE, DEFAULT_TOP_P};
+
+/// 模型能力描述，用于在构建请求前判断模型能接受哪些内容
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelCapability {
+    pub vision_support: bool,
+    // 单次请求最多可以携带的图片数量
+    pub max_images: usize,
+}
+
+impl Default for ModelCapability {
+    fn default() -> Self {
+        ModelCapability {
+            vision_support: true,
+            max_images: 10,
+        }
+    }
+}
+
+// (api_type, 模型代码前缀, 是否支持视觉, 单次请求图片上限)，越具体的前缀越靠前
+const CAPABILITY_REGISTRY: &[(&str, &str, bool, usize)] = &[
+    ("openai_api", "gpt-3.5", false, 0),
+    ("openai_api", "gpt-4o", true, 10),
+    ("openai_api", "gpt-4.1", true, 10),
+    ("openai_api", "gpt-4-turbo", true, 10),
+    ("openai_api", "gpt-4-vision", true, 10),
+    ("openai_api", "gpt-4", false, 0),
+    ("openai_api", "o1-mini", false, 0),
+    ("openai_api", "o1-preview", false, 0),
+    ("openai_api", "o1", true, 10),
+    ("openai_api", "o3-mini", false, 0),
+    ("openai_api", "o3", true, 10),
+    ("openai_api", "o4", true, 10),
+    ("anthropic", "claude-3", true, 20),
+    ("anthropic", "claude-2", false, 0),
+    ("huggingface", "HuggingFaceM4/idefics", true, 1),
+    ("huggingface", "llava-hf/", true, 1),
+    // 其余 Hugging Face 模型大多只支持文字
+    ("huggingface", "", false, 0),
+    ("dashscope", "qwen-vl", true, 10),
+    ("dashscope", "qwen2-vl", true, 10),
+    ("dashscope", "qwen2.5-vl", true, 10),
+    ("dashscope", "qvq", true, 10),
+    ("dashscope", "", false, 0),
+    // GLM-4V 单次请求只支持一张图片
+    ("zhipu", "glm-4v", true, 1),
+    ("zhipu", "", false, 0),
+];
+
+/// 根据提供商类型和模型代码查询模型能力，未登记的模型按默认能力处理
+pub fn get_model_capability(api_type: &str, model_code: &str) -> ModelCapability {
+    CAPABILITY_REGISTRY
+        .iter()
+        .find(|(registry_api_type, prefix, _, _)| {
+            *registry_api_type == api_type && model_code.starts_with(prefix)
+        })
+        .map(|(_, _, vision_support, max_images)| ModelCapability {
+            vision_support: *vision_support,
+            max_images: *max_images,
+        })
+        .unwrap_or_default()
+}
+
+/// 提供商是否支持以助手消息结尾的请求，模型会接着这条助手消息继续输出，用于流式响应中断后续写
+pub fn supports_assistant_prefill(api_type: &str) -> bool {
+    matches!(api_type, "anthropic" | "ollama")
+}
+
+/// 模型的一个可调参数，前端按这里的范围显示滑块
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModelParamSpec {
+    // 和助手模型配置中的名称一致，例如 temperature
+    pub name: &'static str,
+    // 和助手模型配置的 value_type 一致，float 或 number
+    pub value_type: &'static str,
+    pub min: f64,
+    pub max: f64,
+    pub step: f64,
+    pub default: f64,
+}
+
+// (参数名称, value_type, 最小值, 最大值, 步长)，提供商没有特别限制时的范围
+const BASE_PARAMS: &[(&str, &str, f64, f64, f64)] = &[
+    ("temperature", "float", 0.0, 2.0, 0.05),
+    ("top_p", "float", 0.0, 1.0, 0.01),
+    ("max_tokens", "number", 1.0, 8192.0, 1.0),
+];
+
+// (api_type, 模型代码前缀, 参数名称, 取值范围)，取值范围为 None 表示模型不接受这个参数，
+// 未登记的使用 BASE_PARAMS 中的范围
+const PARAM_RANGE_REGISTRY: &[(&str, &str, &str, Option<(f64, f64)>)] = &[
+    // 推理模型只接受默认的 temperature 和 top_p
+    ("openai_api", "o1", "temperature", None),
+    ("openai_api", "o3", "temperature", None),
+    ("openai_api", "o4", "temperature", None),
+    ("openai_api", "o1", "top_p", None),
+    ("openai_api", "o3", "top_p", None),
+    ("openai_api", "o4", "top_p", None),
+    ("anthropic", "", "temperature", Some((0.0, 1.0))),
+    // Cohere 的 p 和智谱的 top_p 都不能等于 1
+    ("cohere", "", "top_p", Some((0.0, 0.99))),
+    ("zhipu", "", "temperature", Some((0.0, 1.0))),
+    ("zhipu", "", "top_p", Some((0.0, 0.99))),
+];
+
+// (api_type, 模型代码前缀, 单次回答的最大 token 数)，越具体的前缀越靠前
+const MAX_TOKENS_REGISTRY: &[(&str, &str, u32)] = &[
+    ("openai_api", "gpt-3.5", 4096),
+    ("openai_api", "gpt-4o", 16384),
+    ("openai_api", "gpt-4.1", 32768),
+    ("openai_api", "gpt-4-turbo", 4096),
+    ("openai_api", "o1", 100000),
+    ("openai_api", "o3", 100000),
+    ("openai_api", "o4", 100000),
+    ("anthropic", "claude-3-7", 64000),
+    ("anthropic", "claude-3-5", 8192),
+    ("anthropic", "claude-3", 4096),
+    ("anthropic", "claude-sonnet-4", 64000),
+    ("anthropic", "claude-opus-4", 32000),
+];
+
+/// 根据提供商类型和模型代码查询可调参数及其范围，模型不接受的参数不会返回
+pub fn get_model_param_schema(api_type: &str, model_code: &str) -> Vec<ModelParamSpec> {
+    BASE_PARAMS
+        .iter()
+        .filter_map(|(name, value_type, min, max, step)| {
+            let (min, max) =
+                param_range(api_type, model_code, name).unwrap_or(Some((*min, *max)))?;
+            Some(ModelParamSpec {
+                name,
+                value_type,
+                min,
+                max,
+                step: *step,
+                default: param_default(name).clamp(min, max),
+            })
+        })
+        .collect()
+}
+
+// 登记过的范围，外层的 None 表示没有登记
+fn param_range(api_type: &str, model_code: &str, name: &str) -> Option<Option<(f64, f64)>> {
+    if name == "max_tokens" {
+        return MAX_TOKENS_REGISTRY
+            .iter()
+            .find(|(registry_api_type, prefix, _)| {
+                *registry_api_type == api_type && model_code.starts_with(prefix)
+            })
+            .map(|(_, _, max_tokens)| Some((1.0, *max_tokens as f64)));
+    }
+    PARAM_RANGE_REGISTRY
+        .iter()
+        .find(|(registry_api_type, prefix, registry_name, _)| {
+            *registry_api_type == api_type
+                && *registry_name == name
+                && model_code.starts_with(prefix)
+        })
+        .map(|(_, _, _, range)| *range)
+}
+
+/// 把参数值限制在模型接受的范围内，模型不接受这个参数时返回 None，请求中不应该发送
+pub fn clamp_param(api_type: &str, model_code: &str, name: &str, value: f64) -> Option<f64> {
+    let base = BASE_PARAMS
+        .iter()
+        .find(|(base_name, _, _, _, _)| *base_name == name)
+        .map(|(_, _, min, max, _)| (*min, *max));
+    let (min, max) = param_range(api_type, model_code, name).unwrap_or(base)?;
+    Some(value.clamp(min, max))
+}
+
+/// 把 clamp_param 限制后的参数写入请求体的 field 字段，模型不接受这个参数时不写入，
+/// 推理模型收到 temperature 为 null 同样会返回参数错误
+pub fn set_clamped_param(
+    body: &mut Value,
+    field: &str,
+    api_type: &str,
+    model_code: &str,
+    name: &str,
+    value: f64,
+) {
+    if let Some(value) = clamp_param(api_type, model_code, name, value) {
+        body[field] = json!(value);
+    }
+}
+
+fn param_default(name: &str) -> f64 {
+    match name {
+        "temperature" => DEFAULT_TEMPERATURE,
+        "top_p" => DEFAULT_TOP_P,
+        _ => DEFAULT_MAX_TOKENS as f64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param<'a>(schema: &'a [ModelParamSpec], name: &str) -> Option<&'a ModelParamSpec> {
+        schema.iter().find(|spec| spec.name == name)
+    }
+
+    #[test]
+    fn test_get_model_capability() {
+        assert!(get_model_capability("openai_api", "o1").vision_support);
+        assert!(get_model_capability("openai_api", "o1-2024-12-17").vision_support);
+        assert!(!get_model_capability("openai_api", "o1-preview").vision_support);
+        assert!(!get_model_capability("openai_api", "o1-mini").vision_support);
+        assert_eq!(get_model_capability("zhipu", "glm-4v-plus").max_images, 1);
+        // 未登记的提供商按默认能力处理
+        assert_eq!(
+            get_model_capability("ollama", "llava"),
+            ModelCapability::default()
+        );
+    }
+
+    #[test]
+    fn test_get_model_param_schema() {
+        let schema = get_model_param_schema("ollama", "llama3");
+        assert_eq!(schema.len(), 3);
+        let temperature = param(&schema, "temperature").unwrap();
+        assert_eq!((temperature.min, temperature.max), (0.0, 2.0));
+        assert_eq!(temperature.default, DEFAULT_TEMPERATURE);
+
+        let schema = get_model_param_schema("anthropic", "claude-3-5-sonnet-latest");
+        assert_eq!(param(&schema, "temperature").unwrap().max, 1.0);
+        assert_eq!(param(&schema, "max_tokens").unwrap().max, 8192.0);
+
+        let schema = get_model_param_schema("openai_api", "o3-mini");
+        assert!(param(&schema, "temperature").is_none());
+        assert!(param(&schema, "top_p").is_none());
+        assert_eq!(param(&schema, "max_tokens").unwrap().max, 100000.0);
+
+        // 默认值超出范围时取边界值
+        let schema = get_model_param_schema("zhipu", "glm-4");
+        assert_eq!(param(&schema, "top_p").unwrap().default, 0.99);
+    }
+
+    #[test]
+    fn test_clamp_param() {
+        // creative 预设的 temperature 超出 Anthropic 的范围
+        assert_eq!(
+            clamp_param("anthropic", "claude-3-5-sonnet-latest", "temperature", 1.1),
+            Some(1.0)
+        );
+        assert_eq!(
+            clamp_param("openai_api", "gpt-4o", "temperature", 1.1),
+            Some(1.1)
+        );
+        assert_eq!(clamp_param("cohere", "command-r", "top_p", 1.0), Some(0.99));
+        assert_eq!(
+            clamp_param("openai_api", "o3-mini", "temperature", 1.1),
+            None
+        );
+    }
+
+    #[test]
+    fn test_set_clamped_param() {
+        let mut body = json!({ "model": "o3-mini" });
+        set_clamped_param(
+            &mut body,
+            "temperature",
+            "openai_api",
+            "o3-mini",
+            "temperature",
+            0.7,
+        );
+        set_clamped_param(&mut body, "p", "cohere", "command-r", "top_p", 1.0);
+        assert_eq!(body, json!({ "model": "o3-mini", "p": 0.99 }));
+    }
+}
@@ -0,0 +1,215 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::db::conversation_db::{AttachmentType, MessageAttachment};
+
+/// 消息的角色，和数据库中 message_type 的取值一致
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+    // 工具调用的结果
+    Tool,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool => "tool",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "system" => Ok(Role::System),
+            "user" => Ok(Role::User),
+            "assistant" => Ok(Role::Assistant),
+            "tool" => Ok(Role::Tool),
+            _ => Err(format!("未知的消息角色: {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// 消息内容的一部分，文字、附件或者提供商格式的内容块
+#[derive(Debug, Clone)]
+pub enum ContentPart {
+    Text(String),
+    Attachment(MessageAttachment),
+    // 内容块的 JSON 数组（例如包含 tool_use 的助手消息），只能由内部代码构建，
+    // 不会从消息文字中解析，避免用户输入的 JSON 被当成工具调用发送
+    Blocks(Value),
+}
+
+/// 发送给提供商的一条消息，各个提供商把它转换成自己的请求格式
+#[derive(Debug, Clone)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: Vec<ContentPart>,
+}
+
+impl ChatMessage {
+    pub fn new(role: Role, text: impl Into<String>) -> Self {
+        ChatMessage {
+            role,
+            content: vec![ContentPart::Text(text.into())],
+        }
+    }
+
+    pub fn with_attachments(
+        role: Role,
+        text: impl Into<String>,
+        attachments: Vec<MessageAttachment>,
+    ) -> Self {
+        let mut message = ChatMessage::new(role, text);
+        message
+            .content
+            .extend(attachments.into_iter().map(ContentPart::Attachment));
+        message
+    }
+
+    /// 从数据库中的消息类型构建，消息类型不是已知的角色时返回错误
+    pub fn from_message_type(
+        message_type: &str,
+        text: impl Into<String>,
+        attachments: Vec<MessageAttachment>,
+    ) -> Result<Self, String> {
+        Ok(ChatMessage::with_attachments(
+            message_type.parse()?,
+            text,
+            attachments,
+        ))
+    }
+
+    /// 所有文字部分按顺序拼接后的内容
+    pub fn text(&self) -> String {
+        self.content
+            .iter()
+            .filter_map(|part| match part {
+                ContentPart::Text(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// 在最后一段文字后面追加内容，没有文字部分时新增一段
+    pub fn push_text(&mut self, text: &str) {
+        let last_text = self.content.iter_mut().rev().find_map(|part| match part {
+            ContentPart::Text(content) => Some(content),
+            _ => None,
+        });
+        match last_text {
+            Some(content) => content.push_str(text),
+            None => self.content.push(ContentPart::Text(text.to_string())),
+        }
+    }
+
+    pub fn attachments(&self) -> impl Iterator<Item = &MessageAttachment> {
+        self.content.iter().filter_map(|part| match part {
+            ContentPart::Attachment(attachment) => Some(attachment),
+            _ => None,
+        })
+    }
+
+    /// 内部代码构建的内容块
+    pub fn blocks(&self) -> impl Iterator<Item = &Value> {
+        self.content.iter().filter_map(|part| match part {
+            ContentPart::Blocks(blocks) => Some(blocks),
+            _ => None,
+        })
+    }
+
+    pub fn images(&self) -> impl Iterator<Item = &MessageAttachment> {
+        self.attachments()
+            .filter(|attachment| attachment.attachment_type == AttachmentType::Image)
+    }
+
+    pub fn has_attachments(&self) -> bool {
+        self.attachments().next().is_some()
+    }
+
+    /// 按条件移除附件，返回被移除的附件
+    pub fn remove_attachments(
+        &mut self,
+        mut predicate: impl FnMut(&MessageAttachment) -> bool,
+    ) -> Vec<MessageAttachment> {
+        let mut removed = Vec::new();
+        let mut content = Vec::with_capacity(self.content.len());
+        for part in self.content.drain(..) {
+            match part {
+                ContentPart::Attachment(attachment) if predicate(&attachment) => {
+                    removed.push(attachment)
+                }
+                part => content.push(part),
+            }
+        }
+        self.content = content;
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attachment(id: i64, attachment_type: AttachmentType) -> MessageAttachment {
+        MessageAttachment {
+            id,
+            message_id: Some(1),
+            attachment_type,
+            attachment_url: None,
+            attachment_content: None,
+            attachment_hash: None,
+            use_vector: false,
+            token_count: None,
+            ocr_text: None,
+            encoding: None,
+        }
+    }
+
+    #[test]
+    fn test_role_from_message_type() {
+        assert_eq!("assistant".parse::<Role>(), Ok(Role::Assistant));
+        assert_eq!(Role::Tool.as_str(), "tool");
+        assert!(ChatMessage::from_message_type("bot", "hi", vec![]).is_err());
+    }
+
+    #[test]
+    fn test_text_and_attachments() {
+        let mut message = ChatMessage::with_attachments(
+            Role::User,
+            "看看这张图",
+            vec![
+                attachment(1, AttachmentType::Image),
+                attachment(2, AttachmentType::Text),
+            ],
+        );
+        message.push_text("\n还有这个文件");
+        assert_eq!(message.text(), "看看这张图\n还有这个文件");
+        assert_eq!(message.images().map(|a| a.id).collect::<Vec<_>>(), vec![1]);
+
+        let removed = message.remove_attachments(|a| a.attachment_type == AttachmentType::Image);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(
+            message.attachments().map(|a| a.id).collect::<Vec<_>>(),
+            vec![2]
+        );
+    }
+}
@@ -1,20 +1,22 @@
 use std::collections::HashMap;
 
 use anyhow::{anyhow, bail, Result};
-use reqwest::{
-    header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION},
-    Client,
-};
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use tokio_util::sync::CancellationToken;
 
-use crate::{
-    api::llm_api::LlmModel,
-    db::{conversation_db::MessageAttachment, llm_db::LLMProviderConfig},
-};
+use crate::{api::llm_api::LlmModel, db::llm_db::LLMProviderConfig};
 
-use super::ModelProvider;
+use super::{
+    capability::set_clamped_param,
+    chat_message::{ChatMessage, Role},
+    check_response_status, client_builder, finish_reason_event,
+    mock::FixtureRecorder,
+    request_builder::{apply_request_headers, AuthScheme},
+    request_context::RequestContext,
+    send_request, ChatResponse, ModelProvider, ProviderClient, TokenUsage,
+};
 use futures::StreamExt;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -34,7 +36,7 @@ struct Model {
 
 pub struct CohereProvider {
     llm_provider_config: Vec<LLMProviderConfig>,
-    client: Client,
+    client: ProviderClient,
 }
 
 impl ModelProvider for CohereProvider {
@@ -44,95 +46,38 @@ impl ModelProvider for CohereProvider {
     {
         CohereProvider {
             llm_provider_config,
-            client: Client::new(),
+            client: ProviderClient::new(client_builder()),
         }
     }
 
     fn chat(
         &self,
         _message_id: i64,
-        mut messages: Vec<(String, String, Vec<MessageAttachment>)>,
+        messages: Vec<ChatMessage>,
         model_config: Vec<crate::db::assistant_db::AssistantModelConfig>,
         cancel_token: CancellationToken,
-    ) -> futures::future::BoxFuture<'static, Result<String>> {
+    ) -> futures::future::BoxFuture<'static, Result<ChatResponse>> {
         let config = self.llm_provider_config.clone();
         let client = self.client.clone();
 
         Box::pin(async move {
-            let config_map: HashMap<String, String> =
-                config.into_iter().map(|c| (c.name, c.value)).collect();
-
-            let default_endpoint = &"https://api.cohere.ai/v1".to_string();
-            let endpoint = config_map
-                .get("endpoint")
-                .unwrap_or(default_endpoint)
-                .trim_end_matches('/');
-            let url = format!("{}/chat", endpoint);
-            let api_key = config_map.get("api_key").unwrap().clone();
-            let message = messages.pop().ok_or_else(|| anyhow!("No message found"))?;
-            if message.0 != "user" {
-                return Err(anyhow!("First message must be from user"));
-            }
-
-            let json_messages = messages
-                .iter()
-                .map(|(message_type, content, _)| {
-                    let role = match message_type.as_str() {
-                        "assistant" => "chatbot",
-                        _ => message_type,
-                    };
-                    json!({
-                        "role": role.to_uppercase(),
-                        "message": content
-                    })
-                })
-                .collect::<Vec<serde_json::Value>>();
-
-            let model_config_map = model_config
-                .iter()
-                .filter_map(|config| {
-                    config
-                        .value
-                        .as_ref()
-                        .map(|value| (config.name.clone(), value.clone()))
-                })
-                .collect::<HashMap<String, String>>();
-            let temperature = model_config_map
-                .get("temperature")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(0.75);
-            let top_p = model_config_map
-                .get("top_p")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(1.0);
-            let max_tokens = model_config_map
-                .get("max_tokens")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(2000);
-
-            let model = model_config_map.get("model"); // Assuming the first model config is the one to use
-
-            let body = json!({
-                "model": model,
-                "temperature": temperature,
-                "p": top_p,
-                "max_tokens": max_tokens,
-                "message": message.1,
-                "chat_history": json_messages,
-                "stream": false
-            });
+            let client = client.get()?;
+            let context = RequestContext::new(config, &model_config, messages);
+            let url = context.url("https://api.cohere.ai/v1", "/chat");
+            let body = build_body(&context, false)?;
             println!("cohere chat: {:?}", body);
 
-            let request = client
-                .post(&url)
-                .header(AUTHORIZATION, &format!("bearer {}", api_key))
-                .json(&body);
+            let request =
+                apply_request_headers(client.post(&url), &context.config_map, AuthScheme::Bearer)?
+                    .json(&body);
 
             let response = tokio::select! {
-                response = request.send() => response?,
+                response = send_request(request) => response?,
                 _ = cancel_token.cancelled() => bail!("Request cancelled"),
             };
 
+            let response = check_response_status(response).await?;
+
             let json_response = tokio::select! {
                 json = response.json::<serde_json::Value>() => json?,
                 _ = cancel_token.cancelled() => bail!("Request cancelled"),
@@ -141,7 +86,14 @@ impl ModelProvider for CohereProvider {
             println!("cohere chat response: {:?}", json_response.clone());
 
             if let Some(content) = json_response["text"].as_str() {
-                Ok(content.to_string())
+                Ok(ChatResponse {
+                    content: content.to_string(),
+                    finish_reason: json_response["finish_reason"]
+                        .as_str()
+                        .map(|s| s.to_string()),
+                    usage: token_usage(&json_response),
+                    ..Default::default()
+                })
             } else {
                 bail!("Failed to get content from response");
             }
@@ -151,7 +103,7 @@ impl ModelProvider for CohereProvider {
     fn chat_stream(
         &self,
         message_id: i64,
-        mut messages: Vec<(String, String, Vec<MessageAttachment>)>,
+        messages: Vec<ChatMessage>,
         model_config: Vec<crate::db::assistant_db::AssistantModelConfig>,
         tx: tokio::sync::mpsc::Sender<(i64, String, bool)>,
         cancel_token: CancellationToken,
@@ -160,89 +112,36 @@ impl ModelProvider for CohereProvider {
         let client = self.client.clone();
 
         Box::pin(async move {
-            let config_map: HashMap<String, String> =
-                config.into_iter().map(|c| (c.name, c.value)).collect();
-
-            let default_endpoint = &"https://api.cohere.ai/v1".to_string();
-            let endpoint = config_map
-                .get("endpoint")
-                .unwrap_or(default_endpoint)
-                .trim_end_matches('/');
-            let url = format!("{}/chat", endpoint);
-            let api_key = config_map.get("api_key").unwrap().clone();
-
-            let message = messages.pop().ok_or_else(|| anyhow!("No message found"))?;
-            if message.0 != "user" {
-                return Err(anyhow!("First message must be from user"));
-            }
-            let json_messages = messages
-                .iter()
-                .map(|(message_type, content, _)| {
-                    let role = match message_type.as_str() {
-                        "assistant" => "chatbot",
-                        _ => message_type,
-                    };
-                    json!({
-                        "role": role.to_uppercase(),
-                        "message": content
-                    })
-                })
-                .collect::<Vec<serde_json::Value>>();
-
-            let model_config_map = model_config
-                .iter()
-                .filter_map(|config| {
-                    config
-                        .value
-                        .as_ref()
-                        .map(|value| (config.name.clone(), value.clone()))
-                })
-                .collect::<HashMap<String, String>>();
-            let temperature = model_config_map
-                .get("temperature")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(0.75);
-            let top_p = model_config_map
-                .get("top_p")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(1.0);
-            let max_tokens = model_config_map
-                .get("max_tokens")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(2000);
-
-            let model = model_config_map.get("model"); // Assuming the first model config is the one to use
-
-            let body = json!({
-                "model": model,
-                "temperature": temperature,
-                "p": top_p,
-                "max_tokens": max_tokens,
-                "message": message.1,
-                "chat_history": json_messages,
-                "stream": true
-            });
+            let client = client.get()?;
+            let context = RequestContext::new(config, &model_config, messages);
+            let url = context.url("https://api.cohere.ai/v1", "/chat");
+            let body = build_body(&context, true)?;
             println!("cohere chat stream url: {} body: {:?}", url, body);
 
-            let request = client
-                .post(&url)
-                .header(AUTHORIZATION, &format!("bearer {}", api_key))
-                .json(&body);
+            let request =
+                apply_request_headers(client.post(&url), &context.config_map, AuthScheme::Bearer)?
+                    .json(&body);
 
             let response = tokio::select! {
-                response = request.send() => response?,
+                response = send_request(request) => response?,
                 _ = cancel_token.cancelled() => bail!("Request cancelled"),
             };
 
+            let response = check_response_status(response).await?;
+
             let mut stream = response.bytes_stream();
             let mut full_text = String::new();
             let mut buffer = Vec::new();
+            let mut recorder = FixtureRecorder::from_config(&context.config_map);
 
             loop {
                 tokio::select! {
                     chunk = stream.next() => {
                         match chunk {
                             Some(Ok(chunk)) => {
+                                if let Some(recorder) = recorder.as_mut() {
+                                    recorder.record(&chunk);
+                                }
                                 let text = String::from_utf8_lossy(&chunk);
                                 println!("cohere chat stream text: {}", text);
                                 buffer.extend_from_slice(&chunk);
@@ -266,6 +165,9 @@ impl ModelProvider for CohereProvider {
                                                         full_text = text.to_string();
                                                     }
                                                 }
+                                                if let Some(finish_reason) = chunk_response["finish_reason"].as_str() {
+                                                    tx.send((message_id, finish_reason_event(finish_reason), false)).await?;
+                                                }
                                                 tx.send((message_id, full_text.clone(), true)).await?;
                                             },
                                             _ => {}
@@ -294,6 +196,7 @@ impl ModelProvider for CohereProvider {
         let client = self.client.clone();
 
         Box::pin(async move {
+            let client = client.get()?;
             let mut result = Vec::new();
 
             let config_map: HashMap<String, String> =
@@ -306,24 +209,20 @@ impl ModelProvider for CohereProvider {
                 .unwrap_or(default_endpoint)
                 .trim_end_matches('/');
             let url = format!("{}/models", endpoint);
-            let api_key = config_map.get("api_key").unwrap().clone();
             println!("Cohere models endpoint : {}", url);
 
             let mut headers = HeaderMap::new();
             headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
-            headers.insert(
-                AUTHORIZATION,
-                HeaderValue::from_str(&format!("bearer {}", api_key)).unwrap(),
-            );
-
-            let req = client
-                .request("GET".parse().unwrap(), url)
-                .headers(headers)
-                .build();
-            println!("req: {:?}", req);
-
-            let response = client.execute(req.unwrap());
-            let res2 = response.await?;
+
+            let request = apply_request_headers(
+                client.request("GET".parse().unwrap(), url),
+                &config_map,
+                AuthScheme::Bearer,
+            )?
+            .headers(headers);
+            println!("request: {:?}", request);
+
+            let res2 = send_request(request).await?;
             // println!("response: {:?}", res2.unwrap().text().await.unwrap());
 
             // 读取响应体为字符串
@@ -348,6 +247,7 @@ impl ModelProvider for CohereProvider {
                     vision_support: false, // Set this according to your needs
                     audio_support: false,  // Set this according to your needs
                     video_support: false,  // Set this according to your needs
+                    is_enabled: true,
                 };
                 result.push(llm_model);
             }
@@ -357,6 +257,26 @@ impl ModelProvider for CohereProvider {
     }
 }
 
+// 非流式响应中按计费的 token 数量返回用量
+fn token_usage(response: &serde_json::Value) -> Option<TokenUsage> {
+    let billed_units = response["meta"]["billed_units"]
+        .as_object()
+        .filter(|units| !units.is_empty())?;
+    let mut usage = TokenUsage::default();
+    usage.merge(&TokenUsage {
+        prompt_tokens: billed_units
+            .get("input_tokens")
+            .and_then(|v| v.as_u64())
+            .map(|v| v as u32),
+        completion_tokens: billed_units
+            .get("output_tokens")
+            .and_then(|v| v.as_u64())
+            .map(|v| v as u32),
+        total_tokens: None,
+    });
+    Some(usage)
+}
+
 fn find_json_end(buffer: &[u8]) -> Option<usize> {
     let mut depth = 0;
     for (i, &byte) in buffer.iter().enumerate() {
@@ -373,3 +293,50 @@ fn find_json_end(buffer: &[u8]) -> Option<usize> {
     }
     None
 }
+
+// chat 和 chat_stream 共用的请求体，最后一条用户消息单独作为 message，其余作为 chat_history
+fn build_body(context: &RequestContext, stream: bool) -> Result<serde_json::Value> {
+    let params = context.params();
+    let model_code = params.model.as_deref().unwrap_or("");
+    let (message, history) = context
+        .messages
+        .split_last()
+        .ok_or_else(|| anyhow!("No message found"))?;
+    if message.role != Role::User {
+        return Err(anyhow!("First message must be from user"));
+    }
+    let json_messages = history
+        .iter()
+        .map(|message| {
+            let role = match message.role {
+                Role::Assistant => "chatbot",
+                role => role.as_str(),
+            };
+            json!({
+                "role": role.to_uppercase(),
+                "message": message.text()
+            })
+        })
+        .collect::<Vec<serde_json::Value>>();
+
+    let mut body = json!({
+        "model": params.model,
+        "max_tokens": params.max_tokens,
+        "message": message.text(),
+        "chat_history": json_messages,
+        "stream": stream
+    });
+    set_clamped_param(
+        &mut body,
+        "temperature",
+        "cohere",
+        model_code,
+        "temperature",
+        params.temperature,
+    );
+    set_clamped_param(&mut body, "p", "cohere", model_code, "top_p", params.top_p);
+    if let Some(stop) = params.stop {
+        body["stop_sequences"] = json!(stop);
+    }
+    Ok(body)
+}
@@ -0,0 +1,378 @@
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use crate::{api::llm_api::LlmModel, db::llm_db::LLMProviderConfig};
+
+use super::{
+    capability::get_model_capability,
+    chat_message::ChatMessage,
+    check_response_status, finish_reason_event,
+    mock::FixtureRecorder,
+    request_builder::{apply_request_headers, AuthScheme},
+    request_context::RequestContext,
+    send_request, shared_client,
+    sse::{SseDecoder, SseEvent},
+    usage_event, ChatResponse, ModelProvider, ProviderClient, TokenUsage,
+};
+use futures::StreamExt;
+
+const DEFAULT_ENDPOINT: &str = "https://dashscope.aliyuncs.com/api/v1";
+const TEXT_GENERATION_PATH: &str = "/services/aigc/text-generation/generation";
+// 通义千问 VL 等视觉模型使用多模态接口，消息内容是文字和图片组成的数组
+const MULTIMODAL_GENERATION_PATH: &str = "/services/aigc/multimodal-generation/generation";
+
+// DashScope 没有列出模型的接口，使用常用模型作为模型列表，(模型代码, 是否支持视觉)
+const DEFAULT_MODELS: &[(&str, bool)] = &[
+    ("qwen-max", false),
+    ("qwen-plus", false),
+    ("qwen-turbo", false),
+    ("qwen-long", false),
+    ("qwen-vl-max", true),
+    ("qwen-vl-plus", true),
+];
+
+/// 阿里云百炼 DashScope 原生接口，用于通义千问系列模型
+pub struct DashScopeProvider {
+    llm_provider_config: Vec<LLMProviderConfig>,
+    client: ProviderClient,
+}
+
+impl ModelProvider for DashScopeProvider {
+    fn new(llm_provider_config: Vec<crate::db::llm_db::LLMProviderConfig>) -> Self
+    where
+        Self: Sized,
+    {
+        DashScopeProvider {
+            llm_provider_config,
+            client: shared_client(),
+        }
+    }
+
+    fn chat(
+        &self,
+        _message_id: i64,
+        messages: Vec<ChatMessage>,
+        model_config: Vec<crate::db::assistant_db::AssistantModelConfig>,
+        cancel_token: CancellationToken,
+    ) -> futures::future::BoxFuture<'static, Result<ChatResponse>> {
+        let config = self.llm_provider_config.clone();
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            let client = client.get()?;
+            let context = RequestContext::new(config, &model_config, messages);
+            let multimodal = is_multimodal(&context);
+            let url = context.url(DEFAULT_ENDPOINT, generation_path(multimodal));
+            let body = build_body(&context, multimodal, false);
+            println!("dashscope chat: {:?}", body);
+
+            let request =
+                apply_request_headers(client.post(&url), &context.config_map, AuthScheme::Bearer)?
+                    .json(&body);
+
+            let response = tokio::select! {
+                response = send_request(request) => response?,
+                _ = cancel_token.cancelled() => bail!("Request cancelled"),
+            };
+
+            let response = check_response_status(response).await?;
+
+            let json_response = tokio::select! {
+                json = response.json::<Value>() => json?,
+                _ = cancel_token.cancelled() => bail!("Request cancelled"),
+            };
+
+            println!("dashscope chat response: {:?}", json_response.clone());
+
+            let choice = &json_response["output"]["choices"][0];
+            match message_content(&choice["message"]["content"]) {
+                Some(content) => Ok(ChatResponse {
+                    content,
+                    finish_reason: finish_reason(choice),
+                    usage: token_usage(&json_response),
+                    ..Default::default()
+                }),
+                None => bail!("Failed to get content from response"),
+            }
+        })
+    }
+
+    fn chat_stream(
+        &self,
+        message_id: i64,
+        messages: Vec<ChatMessage>,
+        model_config: Vec<crate::db::assistant_db::AssistantModelConfig>,
+        tx: tokio::sync::mpsc::Sender<(i64, String, bool)>,
+        cancel_token: CancellationToken,
+    ) -> futures::future::BoxFuture<'static, Result<()>> {
+        let config = self.llm_provider_config.clone();
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            let client = client.get()?;
+            let context = RequestContext::new(config, &model_config, messages);
+            let multimodal = is_multimodal(&context);
+            let url = context.url(DEFAULT_ENDPOINT, generation_path(multimodal));
+            let body = build_body(&context, multimodal, true);
+            println!("dashscope chat stream url: {} body: {:?}", url, body);
+
+            let request =
+                apply_request_headers(client.post(&url), &context.config_map, AuthScheme::Bearer)?
+                    .header("X-DashScope-SSE", "enable")
+                    .json(&body);
+
+            let response = tokio::select! {
+                response = send_request(request) => response?,
+                _ = cancel_token.cancelled() => bail!("Request cancelled"),
+            };
+
+            let response = check_response_status(response).await?;
+
+            let mut stream = response.bytes_stream();
+            let mut full_text = String::new();
+            let mut decoder = SseDecoder::new();
+            let mut recorder = FixtureRecorder::from_config(&context.config_map);
+            // 收到 finish_reason 才算正常结束，否则是连接中断
+            let mut finished = false;
+
+            loop {
+                tokio::select! {
+                    chunk = stream.next() => {
+                        match chunk {
+                            Some(Ok(chunk)) => {
+                                if let Some(recorder) = recorder.as_mut() {
+                                    recorder.record(&chunk);
+                                }
+                                let text = String::from_utf8_lossy(&chunk);
+                                println!("dashscope chat stream text: {}", text);
+
+                                for event in decoder.push(&chunk) {
+                                    finished |= send_stream_event(&event, message_id, &mut full_text, &tx).await?;
+                                }
+                            }
+                            Some(Err(e)) => bail!(e),
+                            None => {
+                                println!("dashscope chat stream end");
+                                for event in decoder.finish() {
+                                    finished |= send_stream_event(&event, message_id, &mut full_text, &tx).await?;
+                                }
+                                if !finished {
+                                    // 不发送结束标记，由接收方按连接中断处理
+                                    println!("dashscope chat stream interrupted");
+                                    return Ok(());
+                                }
+                                tx.send((message_id, full_text.clone(), true)).await?;
+                                return Ok(());
+                            },
+                        }
+                    }
+                    _ = cancel_token.cancelled() => {
+                        tx.send((message_id, full_text.clone(), true)).await?;
+                        return Ok(());
+                    }
+                }
+            }
+        })
+    }
+
+    fn models(&self) -> futures::future::BoxFuture<'static, Result<Vec<LlmModel>>> {
+        Box::pin(async move {
+            Ok(DEFAULT_MODELS
+                .iter()
+                .map(|(code, vision_support)| LlmModel {
+                    id: 0,
+                    name: code.to_string(),
+                    llm_provider_id: 0,
+                    code: code.to_string(),
+                    description: format!("通义千问 {}", code),
+                    vision_support: *vision_support,
+                    audio_support: false,
+                    video_support: false,
+                    is_enabled: true,
+                })
+                .collect())
+        })
+    }
+}
+
+fn is_multimodal(context: &RequestContext) -> bool {
+    let model = context.params().model.unwrap_or_default();
+    get_model_capability("dashscope", &model).vision_support
+}
+
+fn generation_path(multimodal: bool) -> &'static str {
+    if multimodal {
+        MULTIMODAL_GENERATION_PATH
+    } else {
+        TEXT_GENERATION_PATH
+    }
+}
+
+// 处理一条流式事件，返回是否收到了 finish_reason
+async fn send_stream_event(
+    event: &SseEvent,
+    message_id: i64,
+    full_text: &mut String,
+    tx: &tokio::sync::mpsc::Sender<(i64, String, bool)>,
+) -> Result<bool> {
+    let Some(chunk_response) = event.json() else {
+        return Ok(false);
+    };
+    // 请求过程中出错时发送 event:error，data 中包含 code 和 message
+    if event.event.as_deref() == Some("error") {
+        return Err(anyhow!(
+            "{}: {}",
+            chunk_response["code"].as_str().unwrap_or_default(),
+            chunk_response["message"].as_str().unwrap_or_default()
+        ));
+    }
+    let choice = &chunk_response["output"]["choices"][0];
+    // 开启 incremental_output 后每个数据块只包含新增的内容
+    if let Some(delta) = message_content(&choice["message"]["content"]) {
+        if !delta.is_empty() {
+            full_text.push_str(&delta);
+            tx.send((message_id, full_text.clone(), false)).await?;
+        }
+    }
+    if let Some(usage) = token_usage(&chunk_response) {
+        tx.send((message_id, usage_event(&usage), false)).await?;
+    }
+    match finish_reason(choice) {
+        Some(finish_reason) => {
+            tx.send((message_id, finish_reason_event(&finish_reason), false))
+                .await?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+fn token_usage(response: &Value) -> Option<TokenUsage> {
+    let usage = response.get("usage").filter(|usage| usage.is_object())?;
+    Some(TokenUsage {
+        prompt_tokens: usage["input_tokens"].as_u64().map(|v| v as u32),
+        completion_tokens: usage["output_tokens"].as_u64().map(|v| v as u32),
+        total_tokens: usage["total_tokens"].as_u64().map(|v| v as u32),
+    })
+}
+
+// 生成过程中 finish_reason 是字符串 "null"，结束时才是 stop、length 等
+fn finish_reason(choice: &Value) -> Option<String> {
+    choice["finish_reason"]
+        .as_str()
+        .filter(|reason| !reason.is_empty() && *reason != "null")
+        .map(|reason| reason.to_string())
+}
+
+// 文字模型返回字符串，多模态模型返回 [{"text": "..."}] 数组
+fn message_content(content: &Value) -> Option<String> {
+    match content {
+        Value::String(text) => Some(text.clone()),
+        Value::Array(parts) => Some(
+            parts
+                .iter()
+                .filter_map(|part| part["text"].as_str())
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+// chat 和 chat_stream 共用的请求体
+fn build_body(context: &RequestContext, multimodal: bool, stream: bool) -> Value {
+    let params = context.params();
+    let mut parameters = json!({
+        "result_format": "message",
+        "temperature": params.temperature,
+        "top_p": params.top_p,
+        "max_tokens": params.max_tokens,
+        "incremental_output": stream
+    });
+    if let Some(stop) = params.stop {
+        parameters["stop"] = json!(stop);
+    }
+    json!({
+        "model": params.model,
+        "input": {
+            "messages": build_messages(&context.messages, multimodal)
+        },
+        "parameters": parameters
+    })
+}
+
+fn build_messages(messages: &[ChatMessage], multimodal: bool) -> Vec<Value> {
+    messages
+        .iter()
+        .map(|message| {
+            if multimodal {
+                let mut content = message
+                    .images()
+                    .filter_map(|attachment| attachment.attachment_content.clone())
+                    .map(|image| json!({ "image": image }))
+                    .collect::<Vec<Value>>();
+                content.push(json!({ "text": message.text() }));
+                json!({
+                    "role": message.role,
+                    "content": content
+                })
+            } else {
+                json!({
+                    "role": message.role,
+                    "content": message.text()
+                })
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::llm::chat_message::Role;
+    use crate::db::conversation_db::{AttachmentType, MessageAttachment};
+
+    #[test]
+    fn test_build_multimodal_messages() {
+        let image = MessageAttachment {
+            id: 1,
+            message_id: Some(1),
+            attachment_type: AttachmentType::Image,
+            attachment_url: None,
+            attachment_content: Some("data:image/png;base64,AAAA".to_string()),
+            attachment_hash: None,
+            use_vector: false,
+            token_count: None,
+            ocr_text: None,
+            encoding: None,
+        };
+        let messages = vec![
+            ChatMessage::new(Role::System, "你是一个助手"),
+            ChatMessage::with_attachments(Role::User, "这是什么", vec![image]),
+        ];
+        let json_messages = build_messages(&messages, true);
+        assert_eq!(json_messages[0]["content"][0]["text"], "你是一个助手");
+        assert_eq!(
+            json_messages[1]["content"][0]["image"],
+            "data:image/png;base64,AAAA"
+        );
+        assert_eq!(json_messages[1]["content"][1]["text"], "这是什么");
+
+        let json_messages = build_messages(&messages, false);
+        assert_eq!(json_messages[1]["content"], "这是什么");
+    }
+
+    #[test]
+    fn test_stream_content_and_finish_reason() {
+        assert_eq!(message_content(&json!("你好")), Some("你好".to_string()));
+        assert_eq!(
+            message_content(&json!([{"text": "你"}, {"text": "好"}])),
+            Some("你好".to_string())
+        );
+        assert_eq!(finish_reason(&json!({"finish_reason": "null"})), None);
+        assert_eq!(
+            finish_reason(&json!({"finish_reason": "stop"})),
+            Some("stop".to_string())
+        );
+    }
+}
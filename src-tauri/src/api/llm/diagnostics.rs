@@ -0,0 +1,907 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use reqwest::{RequestBuilder, Url};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::db::llm_db::{LLMProvider, LLMProviderConfig};
+
+use super::capability::get_model_capability;
+use super::egress::{client_builder, send_request, EgressBlockedError, SendError};
+use super::request_builder::{apply_openai_request_headers, apply_request_headers, AuthScheme};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(20);
+// 1x1 像素的 PNG，用于探测模型是否接受图片
+const PROBE_IMAGE_PNG: &str = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mP8z8BQDwAEhQGAhKmMIQAAAABJRU5ErkJggg==";
+const PROBE_PROMPT: &str = "hi";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Ok,
+    Warning,
+    Error,
+    Skipped,
+}
+
+/// 检查失败的具体原因，设置界面根据它显示对应的修改建议
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticKind {
+    Ok,
+    InvalidUrl,
+    DnsFailure,
+    ConnectionFailed,
+    Timeout,
+    TlsError,
+    Unauthorized,
+    Forbidden,
+    WrongEndpoint,
+    RateLimited,
+    ServerError,
+    InvalidResponse,
+    ModelNotFound,
+    VisionUnsupported,
+    EgressBlocked,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosticCheck {
+    // endpoint / connection / api_key / model / vision
+    pub name: String,
+    pub status: CheckStatus,
+    pub kind: DiagnosticKind,
+    pub message: String,
+    // 状态码、服务端返回的错误信息等原始内容
+    pub detail: Option<String>,
+    pub elapsed_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderDiagnostics {
+    pub llm_provider_id: i64,
+    pub provider_name: String,
+    pub api_type: String,
+    pub endpoint: String,
+    pub model: Option<String>,
+    // 没有 error 级别的检查项时为 true
+    pub success: bool,
+    pub checks: Vec<DiagnosticCheck>,
+}
+
+impl DiagnosticCheck {
+    fn new(
+        name: &str,
+        status: CheckStatus,
+        kind: DiagnosticKind,
+        message: impl Into<String>,
+    ) -> Self {
+        DiagnosticCheck {
+            name: name.to_string(),
+            status,
+            kind,
+            message: message.into(),
+            detail: None,
+            elapsed_ms: None,
+        }
+    }
+
+    fn ok(name: &str, message: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Ok, DiagnosticKind::Ok, message)
+    }
+
+    fn skipped(name: &str, message: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Skipped, DiagnosticKind::Ok, message)
+    }
+
+    fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    fn with_elapsed(mut self, start: Instant) -> Self {
+        self.elapsed_ms = Some(start.elapsed().as_millis() as u64);
+        self
+    }
+}
+
+// 各提供商的默认地址、鉴权方式和探测请求的格式
+struct ProbeSpec {
+    default_endpoint: &'static str,
+    auth_scheme: AuthScheme,
+    // 没有模型列表接口的提供商为 None，连通性和 API Key 由对话请求判断
+    models_path: Option<&'static str>,
+    chat_path: &'static str,
+    // 模型列表在返回结果中的位置和模型代码的字段名
+    models_field: &'static str,
+    model_code_field: &'static str,
+}
+
+fn probe_spec(api_type: &str) -> Option<ProbeSpec> {
+    match api_type {
+        "openai_api" => Some(ProbeSpec {
+            default_endpoint: "https://api.openai.com/v1",
+            auth_scheme: AuthScheme::Bearer,
+            models_path: Some("/models"),
+            chat_path: "/chat/completions",
+            models_field: "data",
+            model_code_field: "id",
+        }),
+        "anthropic" => Some(ProbeSpec {
+            default_endpoint: "https://api.anthropic.com",
+            auth_scheme: AuthScheme::XApiKey,
+            models_path: Some("/v1/models"),
+            chat_path: "/v1/messages",
+            models_field: "data",
+            model_code_field: "id",
+        }),
+        "ollama" => Some(ProbeSpec {
+            default_endpoint: "http://localhost:11434",
+            auth_scheme: AuthScheme::Bearer,
+            models_path: Some("/api/tags"),
+            chat_path: "/api/chat",
+            models_field: "models",
+            model_code_field: "name",
+        }),
+        "cohere" => Some(ProbeSpec {
+            default_endpoint: "https://api.cohere.ai/v1",
+            auth_scheme: AuthScheme::Bearer,
+            models_path: Some("/models"),
+            chat_path: "/chat",
+            models_field: "models",
+            model_code_field: "name",
+        }),
+        // 对话地址由 chat_url 按是否配置了 TGI 地址拼接
+        "huggingface" => Some(ProbeSpec {
+            default_endpoint: "https://api-inference.huggingface.co/models",
+            auth_scheme: AuthScheme::Bearer,
+            models_path: None,
+            chat_path: "/generate",
+            models_field: "",
+            model_code_field: "",
+        }),
+        // 多模态模型使用另一个对话地址，见 chat_url
+        "dashscope" => Some(ProbeSpec {
+            default_endpoint: "https://dashscope.aliyuncs.com/api/v1",
+            auth_scheme: AuthScheme::Bearer,
+            models_path: None,
+            chat_path: "/services/aigc/text-generation/generation",
+            models_field: "",
+            model_code_field: "",
+        }),
+        "zhipu" => Some(ProbeSpec {
+            default_endpoint: "https://open.bigmodel.cn/api/paas/v4",
+            auth_scheme: AuthScheme::Bearer,
+            models_path: None,
+            chat_path: "/chat/completions",
+            models_field: "",
+            model_code_field: "",
+        }),
+        _ => None,
+    }
+}
+
+// 探测对话请求的地址，和各提供商实际请求的地址一致
+fn chat_url(
+    api_type: &str,
+    spec: &ProbeSpec,
+    endpoint: &str,
+    endpoint_configured: bool,
+    model: &str,
+    multimodal: bool,
+) -> String {
+    match api_type {
+        // Inference API 把模型代码拼接在地址后面，配置了地址时是自己部署的 TGI
+        "huggingface" if !endpoint_configured => format!("{}/{}", endpoint, model),
+        "dashscope" if multimodal || get_model_capability(api_type, model).vision_support => {
+            format!(
+                "{}/services/aigc/multimodal-generation/generation",
+                endpoint
+            )
+        }
+        _ => format!("{}{}", endpoint, spec.chat_path),
+    }
+}
+
+/// 构造最小的对话请求，只生成 1 个 token，with_image 时附带一张 1x1 的图片
+fn probe_body(api_type: &str, model: &str, with_image: bool) -> Value {
+    match api_type {
+        "anthropic" => {
+            let content = if with_image {
+                json!([
+                    {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": PROBE_IMAGE_PNG}},
+                    {"type": "text", "text": PROBE_PROMPT}
+                ])
+            } else {
+                json!(PROBE_PROMPT)
+            };
+            json!({"model": model, "max_tokens": 1, "messages": [{"role": "user", "content": content}]})
+        }
+        "ollama" => {
+            let mut message = json!({"role": "user", "content": PROBE_PROMPT});
+            if with_image {
+                message["images"] = json!([PROBE_IMAGE_PNG]);
+            }
+            json!({"model": model, "stream": false, "options": {"num_predict": 1}, "messages": [message]})
+        }
+        "cohere" => json!({"model": model, "message": PROBE_PROMPT, "max_tokens": 1}),
+        "huggingface" => json!({"inputs": PROBE_PROMPT, "parameters": {"max_new_tokens": 1}}),
+        "dashscope" => {
+            // 多模态模型的内容是数组，和 dashscope 提供商的请求格式一致
+            let mut content = vec![json!({"text": PROBE_PROMPT})];
+            if with_image {
+                content.insert(
+                    0,
+                    json!({"image": format!("data:image/png;base64,{}", PROBE_IMAGE_PNG)}),
+                );
+            }
+            let content = if with_image || get_model_capability(api_type, model).vision_support {
+                json!(content)
+            } else {
+                json!(PROBE_PROMPT)
+            };
+            json!({
+                "model": model,
+                "input": {"messages": [{"role": "user", "content": content}]},
+                "parameters": {"result_format": "message", "max_tokens": 1}
+            })
+        }
+        // GLM-4V 的图片只接受不带 data: 前缀的 base64
+        "zhipu" if with_image => {
+            json!({"model": model, "max_tokens": 1, "messages": [{"role": "user", "content": [
+                {"type": "text", "text": PROBE_PROMPT},
+                {"type": "image_url", "image_url": {"url": PROBE_IMAGE_PNG}}
+            ]}]})
+        }
+        _ => {
+            let content = if with_image {
+                json!([
+                    {"type": "text", "text": PROBE_PROMPT},
+                    {"type": "image_url", "image_url": {"url": format!("data:image/png;base64,{}", PROBE_IMAGE_PNG)}}
+                ])
+            } else {
+                json!(PROBE_PROMPT)
+            };
+            json!({"model": model, "max_tokens": 1, "messages": [{"role": "user", "content": content}]})
+        }
+    }
+}
+
+/// 根据 HTTP 状态码和返回内容判断失败原因
+pub fn classify_status(status: u16, body: &str) -> (CheckStatus, DiagnosticKind) {
+    let model_unavailable = is_model_unavailable_body(status, body);
+    match status {
+        200..=299 => (CheckStatus::Ok, DiagnosticKind::Ok),
+        401 => (CheckStatus::Error, DiagnosticKind::Unauthorized),
+        403 => (CheckStatus::Error, DiagnosticKind::Forbidden),
+        404 if model_unavailable => (CheckStatus::Error, DiagnosticKind::ModelNotFound),
+        404 | 405 => (CheckStatus::Error, DiagnosticKind::WrongEndpoint),
+        410 => (CheckStatus::Error, DiagnosticKind::ModelNotFound),
+        400 | 422 if model_unavailable => (CheckStatus::Error, DiagnosticKind::ModelNotFound),
+        429 => (CheckStatus::Warning, DiagnosticKind::RateLimited),
+        500..=599 => (CheckStatus::Error, DiagnosticKind::ServerError),
+        _ => (CheckStatus::Error, DiagnosticKind::Unknown),
+    }
+}
+
+// 提供商表示模型不存在或者已经下线的错误码，不区分大小写，1211 为智谱的“模型不存在”
+const MODEL_UNAVAILABLE_CODES: &[&str] = &[
+    "model_not_found",
+    "model_decommissioned",
+    "model_not_available",
+    "modelnotfound",
+    "1211",
+];
+
+// 按错误内容中的错误码判断模型是否不可用，不按错误信息中的文字判断，
+// 避免“参数不支持”等其他错误被当成模型不存在而换用备用模型
+fn is_model_unavailable_body(status: u16, body: &str) -> bool {
+    let value = serde_json::from_str::<Value>(body).unwrap_or_default();
+    let error = value.get("error").unwrap_or(&value);
+    let field = |value: &Value, name: &str| match &value[name] {
+        Value::String(s) => s.to_lowercase(),
+        Value::Number(n) => n.to_string(),
+        _ => String::new(),
+    };
+    let codes = [
+        field(error, "code"),
+        field(error, "type"),
+        field(&value, "code"),
+    ];
+    if codes
+        .iter()
+        .any(|code| MODEL_UNAVAILABLE_CODES.contains(&code.as_str()))
+    {
+        return true;
+    }
+    if status != 404 {
+        return false;
+    }
+    // Anthropic 的对话接口只在模型不存在时返回 not_found_error，
+    // Ollama 的错误只有一句话没有错误码，模型没有下载时返回 model "x" not found
+    let message = error.as_str().unwrap_or_default().to_lowercase();
+    codes.iter().any(|code| code == "not_found_error")
+        || (message.starts_with("model") && message.contains("not found"))
+}
+
+fn kind_message(kind: DiagnosticKind) -> &'static str {
+    match kind {
+        DiagnosticKind::Ok => "正常",
+        DiagnosticKind::InvalidUrl => "接口地址格式不正确",
+        DiagnosticKind::DnsFailure => "无法解析接口地址的域名，请检查地址拼写或网络、代理设置",
+        DiagnosticKind::ConnectionFailed => "无法连接到接口地址，请确认服务已经启动并且端口正确",
+        DiagnosticKind::Timeout => "连接超时，请检查网络或代理设置",
+        DiagnosticKind::TlsError => "TLS 握手失败，请确认地址使用了正确的 http 或 https",
+        DiagnosticKind::Unauthorized => "API Key 无效或者没有填写",
+        DiagnosticKind::Forbidden => "API Key 没有访问权限，请检查账户状态或额度",
+        DiagnosticKind::WrongEndpoint => "接口路径不存在，请检查接口地址是否缺少或多了 /v1 等路径",
+        DiagnosticKind::RateLimited => "请求过于频繁或者额度不足",
+        DiagnosticKind::ServerError => "服务端返回错误，请稍后重试",
+        DiagnosticKind::InvalidResponse => "返回的内容不是预期的格式，接口地址可能指向了其他服务",
+        DiagnosticKind::ModelNotFound => "模型不存在或者当前账户不可用",
+        DiagnosticKind::VisionUnsupported => "模型不支持图片输入",
+        DiagnosticKind::EgressBlocked => "接口地址不在出站允许列表中，可以在安全设置中添加",
+        DiagnosticKind::Unknown => "请求失败",
+    }
+}
+
+fn classify_request_error(e: &SendError) -> DiagnosticKind {
+    let e = match e {
+        SendError::Blocked(_) => return DiagnosticKind::EgressBlocked,
+        SendError::Request(e) => e,
+    };
+    // 域名解析时被出站允许列表阻止的错误包在连接错误里
+    let mut source = std::error::Error::source(e);
+    while let Some(inner) = source {
+        if inner.is::<EgressBlockedError>() {
+            return DiagnosticKind::EgressBlocked;
+        }
+        source = inner.source();
+    }
+    if e.is_timeout() {
+        return DiagnosticKind::Timeout;
+    }
+    let text = format!("{:?}", e).to_lowercase();
+    if text.contains("dns") || text.contains("failed to lookup address") {
+        DiagnosticKind::DnsFailure
+    } else if text.contains("certificate") || text.contains("tls") || text.contains("ssl") {
+        DiagnosticKind::TlsError
+    } else if e.is_connect() {
+        DiagnosticKind::ConnectionFailed
+    } else {
+        DiagnosticKind::Unknown
+    }
+}
+
+// 服务端错误信息通常在 error.message 或 message 字段中，找不到时截取原始内容
+pub(super) fn error_detail(status: u16, body: &str) -> String {
+    let message = serde_json::from_str::<Value>(body).ok().and_then(|value| {
+        value["error"]["message"]
+            .as_str()
+            .or_else(|| value["error"].as_str())
+            .or_else(|| value["message"].as_str())
+            .map(|s| s.to_string())
+    });
+    let message = message.unwrap_or_else(|| body.chars().take(300).collect());
+    format!("HTTP {}: {}", status, message)
+}
+
+struct ProbeResponse {
+    status: u16,
+    body: String,
+}
+
+async fn send_probe(request: RequestBuilder) -> Result<ProbeResponse, SendError> {
+    let response = send_request(request).await?;
+    let status = response.status().as_u16();
+    let body = response.text().await?;
+    Ok(ProbeResponse { status, body })
+}
+
+fn request_error_check(name: &str, e: &SendError, start: Instant) -> DiagnosticCheck {
+    let kind = classify_request_error(e);
+    DiagnosticCheck::new(name, CheckStatus::Error, kind, kind_message(kind))
+        .with_detail(e.to_string())
+        .with_elapsed(start)
+}
+
+fn status_check(name: &str, response: &ProbeResponse, start: Instant) -> DiagnosticCheck {
+    let (status, kind) = classify_status(response.status, &response.body);
+    DiagnosticCheck::new(name, status, kind, kind_message(kind))
+        .with_detail(error_detail(response.status, &response.body))
+        .with_elapsed(start)
+}
+
+/// 逐项检查提供商配置：地址格式和域名解析、连通性和 API Key、模型是否可用、是否支持图片
+///
+/// 模型检查会发送只生成 1 个 token 的对话请求，model_code 为空时使用该提供商的第一个模型，
+/// models 为数据库中保存的 (模型代码, 是否支持视觉)
+pub async fn diagnose_provider(
+    provider: LLMProvider,
+    llm_provider_config: Vec<LLMProviderConfig>,
+    models: Vec<(String, bool)>,
+    model_code: Option<String>,
+) -> ProviderDiagnostics {
+    let config_map: HashMap<String, String> = llm_provider_config
+        .into_iter()
+        .map(|c| (c.name, c.value))
+        .collect();
+    let model = model_code
+        .filter(|code| !code.trim().is_empty())
+        .or_else(|| models.first().map(|(code, _)| code.clone()));
+    let mut report = ProviderDiagnostics {
+        llm_provider_id: provider.id,
+        provider_name: provider.name.clone(),
+        api_type: provider.api_type.clone(),
+        endpoint: String::new(),
+        model: model.clone(),
+        success: false,
+        checks: Vec::new(),
+    };
+
+    if provider.api_type == "mock" {
+        report
+            .checks
+            .push(DiagnosticCheck::ok("endpoint", "模拟提供商不需要网络连接"));
+        report.success = true;
+        return report;
+    }
+    let Some(spec) = probe_spec(&provider.api_type) else {
+        report.checks.push(DiagnosticCheck::new(
+            "endpoint",
+            CheckStatus::Error,
+            DiagnosticKind::Unknown,
+            format!("不支持的提供商类型: {}", provider.api_type),
+        ));
+        return report;
+    };
+    let configured_endpoint = config_map
+        .get("endpoint")
+        .filter(|endpoint| !endpoint.trim().is_empty())
+        .map(|endpoint| endpoint.trim().trim_end_matches('/').to_string());
+    let endpoint_configured = configured_endpoint.is_some();
+    let endpoint = configured_endpoint.unwrap_or_else(|| spec.default_endpoint.to_string());
+    report.endpoint = endpoint.clone();
+
+    // 地址格式和域名解析
+    let url = match Url::parse(&endpoint) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => url,
+        Ok(url) => {
+            report.checks.push(
+                DiagnosticCheck::new(
+                    "endpoint",
+                    CheckStatus::Error,
+                    DiagnosticKind::InvalidUrl,
+                    kind_message(DiagnosticKind::InvalidUrl),
+                )
+                .with_detail(format!("不支持的协议: {}", url.scheme())),
+            );
+            return finish(report);
+        }
+        Err(e) => {
+            report.checks.push(
+                DiagnosticCheck::new(
+                    "endpoint",
+                    CheckStatus::Error,
+                    DiagnosticKind::InvalidUrl,
+                    kind_message(DiagnosticKind::InvalidUrl),
+                )
+                .with_detail(e.to_string()),
+            );
+            return finish(report);
+        }
+    };
+    let host = url.host_str().unwrap_or_default().to_string();
+    let port = url.port_or_known_default().unwrap_or(443);
+    let start = Instant::now();
+    match tokio::time::timeout(
+        PROBE_TIMEOUT,
+        tokio::net::lookup_host((host.as_str(), port)),
+    )
+    .await
+    .map(|result| result.map(|addrs| addrs.count()))
+    {
+        Ok(Ok(count)) if count > 0 => report.checks.push(
+            DiagnosticCheck::ok("endpoint", format!("{} 解析成功", host)).with_elapsed(start),
+        ),
+        Ok(Ok(_)) => {
+            report.checks.push(
+                DiagnosticCheck::new(
+                    "endpoint",
+                    CheckStatus::Error,
+                    DiagnosticKind::DnsFailure,
+                    kind_message(DiagnosticKind::DnsFailure),
+                )
+                .with_detail(format!("{} 没有解析到任何地址", host)),
+            );
+            return finish(report);
+        }
+        Ok(Err(e)) => {
+            report.checks.push(
+                DiagnosticCheck::new(
+                    "endpoint",
+                    CheckStatus::Error,
+                    DiagnosticKind::DnsFailure,
+                    kind_message(DiagnosticKind::DnsFailure),
+                )
+                .with_detail(e.to_string())
+                .with_elapsed(start),
+            );
+            return finish(report);
+        }
+        Err(_) => {
+            report.checks.push(
+                DiagnosticCheck::new(
+                    "endpoint",
+                    CheckStatus::Error,
+                    DiagnosticKind::Timeout,
+                    kind_message(DiagnosticKind::Timeout),
+                )
+                .with_elapsed(start),
+            );
+            return finish(report);
+        }
+    }
+
+    let client = match client_builder().timeout(PROBE_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            report.checks.push(
+                DiagnosticCheck::new(
+                    "connection",
+                    CheckStatus::Error,
+                    DiagnosticKind::Unknown,
+                    "创建请求失败",
+                )
+                .with_detail(e.to_string()),
+            );
+            return finish(report);
+        }
+    };
+    let build_request = |request: RequestBuilder| {
+        let request = if provider.api_type == "openai_api" {
+            apply_openai_request_headers(request, &config_map)?
+        } else {
+            apply_request_headers(request, &config_map, spec.auth_scheme.clone())?
+        };
+        Ok::<_, anyhow::Error>(if provider.api_type == "anthropic" {
+            request.header("anthropic-version", "2023-06-01")
+        } else {
+            request
+        })
+    };
+    let request_config_error = |name: &str, e: anyhow::Error| {
+        DiagnosticCheck::new(
+            name,
+            CheckStatus::Error,
+            DiagnosticKind::Unknown,
+            "请求头配置有误",
+        )
+        .with_detail(e.to_string())
+    };
+
+    // 通过模型列表接口检查连通性和 API Key，部分兼容服务没有这个接口，此时交给对话请求判断
+    let start = Instant::now();
+    let mut remote_models: Option<Vec<String>> = None;
+    let mut key_verified = false;
+    let list_request = match spec
+        .models_path
+        .map(|path| build_request(client.get(format!("{}{}", endpoint, path))))
+    {
+        Some(Ok(request)) => Some(request),
+        Some(Err(e)) => {
+            report.checks.push(request_config_error("connection", e));
+            return finish(report);
+        }
+        None => None,
+    };
+    let connection_checked = list_request.is_some();
+    if let Some(list_request) = list_request {
+        match send_probe(list_request).await {
+            Err(e) => {
+                report
+                    .checks
+                    .push(request_error_check("connection", &e, start));
+                return finish(report);
+            }
+            Ok(response) => {
+                report.checks.push(
+                    DiagnosticCheck::ok("connection", format!("HTTP {}", response.status))
+                        .with_elapsed(start),
+                );
+                let (status, kind) = classify_status(response.status, &response.body);
+                match kind {
+                    DiagnosticKind::Ok => match serde_json::from_str::<Value>(&response.body) {
+                        Ok(value) => {
+                            key_verified = true;
+                            remote_models = value[spec.models_field].as_array().map(|list| {
+                                list.iter()
+                                    .filter_map(|m| m[spec.model_code_field].as_str())
+                                    .map(|code| code.to_string())
+                                    .collect()
+                            });
+                            report
+                                .checks
+                                .push(DiagnosticCheck::ok("api_key", "API Key 可用"));
+                        }
+                        Err(_) => report.checks.push(
+                            DiagnosticCheck::new(
+                                "api_key",
+                                CheckStatus::Warning,
+                                DiagnosticKind::InvalidResponse,
+                                kind_message(DiagnosticKind::InvalidResponse),
+                            )
+                            .with_detail(error_detail(response.status, &response.body)),
+                        ),
+                    },
+                    DiagnosticKind::Unauthorized | DiagnosticKind::Forbidden => {
+                        report
+                            .checks
+                            .push(status_check("api_key", &response, start));
+                        return finish(report);
+                    }
+                    _ => report.checks.push(
+                        DiagnosticCheck::new(
+                            "api_key",
+                            if status == CheckStatus::Error {
+                                CheckStatus::Warning
+                            } else {
+                                status
+                            },
+                            kind,
+                            format!("模型列表接口不可用: {}", kind_message(kind)),
+                        )
+                        .with_detail(error_detail(response.status, &response.body)),
+                    ),
+                }
+            }
+        }
+    }
+
+    // 模型是否可用
+    let Some(model) = model else {
+        report.checks.push(DiagnosticCheck::skipped(
+            "model",
+            "还没有添加模型，跳过模型检查",
+        ));
+        report
+            .checks
+            .push(DiagnosticCheck::skipped("vision", "没有可以检查的模型"));
+        return finish(report);
+    };
+    let start = Instant::now();
+    let chat_request = match build_request(client.post(chat_url(
+        &provider.api_type,
+        &spec,
+        &endpoint,
+        endpoint_configured,
+        &model,
+        false,
+    ))) {
+        Ok(request) => request.json(&probe_body(&provider.api_type, &model, false)),
+        Err(e) => {
+            report.checks.push(request_config_error("model", e));
+            return finish(report);
+        }
+    };
+    let listed = remote_models
+        .as_ref()
+        .map(|codes| codes.iter().any(|code| code == &model));
+    match send_probe(chat_request).await {
+        Err(e) => {
+            let name = if connection_checked {
+                "model"
+            } else {
+                "connection"
+            };
+            report.checks.push(request_error_check(name, &e, start));
+            return finish(report);
+        }
+        Ok(response) => {
+            if !connection_checked {
+                report.checks.push(
+                    DiagnosticCheck::ok("connection", format!("HTTP {}", response.status))
+                        .with_elapsed(start),
+                );
+            }
+            let check = status_check("model", &response, start);
+            if check.kind == DiagnosticKind::Unauthorized || check.kind == DiagnosticKind::Forbidden
+            {
+                let mut check = check;
+                check.name = "api_key".to_string();
+                report.checks.push(check);
+                return finish(report);
+            }
+            if check.kind == DiagnosticKind::Ok {
+                report.checks.push(
+                    DiagnosticCheck::ok("model", format!("{} 可以正常对话", model))
+                        .with_elapsed(start),
+                );
+                if !key_verified {
+                    report
+                        .checks
+                        .push(DiagnosticCheck::ok("api_key", "API Key 可用"));
+                }
+            } else if check.kind == DiagnosticKind::WrongEndpoint && listed == Some(true) {
+                // 模型列表里有这个模型，对话接口却是 404，基本是接口地址的路径不对
+                report.checks.push(check);
+                return finish(report);
+            } else {
+                let check = match listed {
+                    Some(false) => DiagnosticCheck {
+                        kind: DiagnosticKind::ModelNotFound,
+                        message: format!("模型列表中没有 {}", model),
+                        ..check
+                    },
+                    _ => check,
+                };
+                report.checks.push(check);
+                return finish(report);
+            }
+        }
+    }
+
+    // 是否支持图片：登记的能力和数据库中的配置都不支持时不再发送图片
+    let capability = get_model_capability(&provider.api_type, &model);
+    let configured_vision = models
+        .iter()
+        .find(|(code, _)| code == &model)
+        .map(|(_, vision)| *vision)
+        .unwrap_or(false);
+    // Cohere 和 Hugging Face 的请求格式不支持图片
+    if provider.api_type == "cohere"
+        || provider.api_type == "huggingface"
+        || (!capability.vision_support && !configured_vision)
+    {
+        report.checks.push(DiagnosticCheck::skipped(
+            "vision",
+            "模型不支持图片输入，跳过检查",
+        ));
+        return finish(report);
+    }
+    let start = Instant::now();
+    let vision_url = chat_url(
+        &provider.api_type,
+        &spec,
+        &endpoint,
+        endpoint_configured,
+        &model,
+        true,
+    );
+    let vision_request = match build_request(client.post(&vision_url)) {
+        Ok(request) => request.json(&probe_body(&provider.api_type, &model, true)),
+        Err(e) => {
+            report.checks.push(request_config_error("vision", e));
+            return finish(report);
+        }
+    };
+    let vision_check = match send_probe(vision_request).await {
+        Err(e) => {
+            let mut check = request_error_check("vision", &e, start);
+            check.status = CheckStatus::Warning;
+            check
+        }
+        Ok(response) if (200..300).contains(&response.status) => {
+            DiagnosticCheck::ok("vision", "模型可以接受图片输入").with_elapsed(start)
+        }
+        Ok(response) => DiagnosticCheck::new(
+            "vision",
+            CheckStatus::Warning,
+            DiagnosticKind::VisionUnsupported,
+            kind_message(DiagnosticKind::VisionUnsupported),
+        )
+        .with_detail(error_detail(response.status, &response.body))
+        .with_elapsed(start),
+    };
+    report.checks.push(vision_check);
+    finish(report)
+}
+
+fn finish(mut report: ProviderDiagnostics) -> ProviderDiagnostics {
+    report.success = !report
+        .checks
+        .iter()
+        .any(|check| check.status == CheckStatus::Error);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_status() {
+        assert_eq!(
+            classify_status(401, "{\"error\":{\"message\":\"Incorrect API key\"}}").1,
+            DiagnosticKind::Unauthorized
+        );
+        assert_eq!(
+            classify_status(404, "<html>Not Found</html>").1,
+            DiagnosticKind::WrongEndpoint
+        );
+        assert_eq!(
+            classify_status(
+                404,
+                "{\"error\":{\"message\":\"The model `gpt-5x` does not exist\",\"code\":\"model_not_found\"}}"
+            )
+            .1,
+            DiagnosticKind::ModelNotFound
+        );
+        assert_eq!(
+            classify_status(
+                404,
+                "{\"error\":\"model \\\"llama9\\\" not found, try pulling it first\"}"
+            )
+            .1,
+            DiagnosticKind::ModelNotFound
+        );
+        assert_eq!(
+            classify_status(
+                400,
+                "{\"error\":{\"code\":\"1211\",\"message\":\"模型不存在\"}}"
+            )
+            .1,
+            DiagnosticKind::ModelNotFound
+        );
+        // 只是错误信息中提到了模型的参数错误不换用备用模型
+        assert_eq!(
+            classify_status(
+                400,
+                "{\"error\":{\"message\":\"temperature is not supported with this model\",\"code\":\"unsupported_parameter\"}}"
+            )
+            .1,
+            DiagnosticKind::Unknown
+        );
+        assert_eq!(classify_status(429, "").0, CheckStatus::Warning);
+        assert_eq!(classify_status(200, "{}").0, CheckStatus::Ok);
+    }
+
+    #[test]
+    fn test_error_detail() {
+        assert_eq!(
+            error_detail(401, "{\"error\":{\"message\":\"invalid key\"}}"),
+            "HTTP 401: invalid key"
+        );
+        assert_eq!(error_detail(502, "bad gateway"), "HTTP 502: bad gateway");
+    }
+
+    #[test]
+    fn test_chat_url() {
+        let spec = probe_spec("huggingface").unwrap();
+        assert_eq!(
+            chat_url(
+                "huggingface",
+                &spec,
+                spec.default_endpoint,
+                false,
+                "gpt2",
+                false
+            ),
+            "https://api-inference.huggingface.co/models/gpt2"
+        );
+        assert_eq!(
+            chat_url(
+                "huggingface",
+                &spec,
+                "http://localhost:8080",
+                true,
+                "gpt2",
+                false
+            ),
+            "http://localhost:8080/generate"
+        );
+        let spec = probe_spec("dashscope").unwrap();
+        assert!(chat_url(
+            "dashscope",
+            &spec,
+            spec.default_endpoint,
+            false,
+            "qwen-max",
+            true
+        )
+        .ends_with("/multimodal-generation/generation"));
+        assert!(probe_spec("zhipu").unwrap().models_path.is_none());
+    }
+}
@@ -0,0 +1,245 @@
+use std::error::Error as StdError;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::RwLock;
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::{Attempt, Policy};
+use reqwest::Url;
+
+// 和 reqwest 默认的重定向策略一致，最多跟随 10 次
+const MAX_REDIRECTS: usize = 10;
+
+// 出站允许列表，None 表示没有开启限制，由 set_egress_allowlist 在启动和修改设置时更新
+static EGRESS_ALLOWLIST: RwLock<Option<Vec<String>>> = RwLock::new(None);
+
+/// 请求的域名不在出站允许列表中
+#[derive(Debug)]
+pub struct EgressBlockedError {
+    pub host: String,
+}
+
+impl std::fmt::Display for EgressBlockedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "出站请求被阻止，{} 不在允许列表中，可以在安全设置中添加",
+            self.host
+        )
+    }
+}
+
+impl StdError for EgressBlockedError {}
+
+/// 被阻止的域名，错误不是因为出站允许列表时返回 None
+pub fn egress_blocked_host(error: &anyhow::Error) -> Option<String> {
+    error
+        .chain()
+        .find_map(|e| e.downcast_ref::<EgressBlockedError>())
+        .map(|e| e.host.clone())
+}
+
+/// 更新出站允许列表，enabled 为 false 时不限制请求的域名
+pub fn set_egress_allowlist(enabled: bool, hosts: &[String]) {
+    let allowlist = enabled.then(|| {
+        hosts
+            .iter()
+            .map(|host| normalize_host(host))
+            .filter(|host| !host.is_empty())
+            .collect()
+    });
+    match EGRESS_ALLOWLIST.write() {
+        Ok(mut guard) => *guard = allowlist,
+        Err(poisoned) => *poisoned.into_inner() = allowlist,
+    }
+}
+
+/// 把用户输入的地址整理成域名：去掉协议、路径、端口和用户信息，转为小写，保留 *. 通配符
+pub fn normalize_host(value: &str) -> String {
+    let value = value.trim().to_lowercase();
+    let value = match value.split_once("://") {
+        Some((_, rest)) => rest,
+        None => &value,
+    };
+    let host = value.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = host.rsplit_once('@').map(|(_, host)| host).unwrap_or(host);
+    let host = match host.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        // 没有方括号的 IPv6 地址中有多个冒号，不是端口
+        None if host.matches(':').count() == 1 => host.split(':').next().unwrap_or_default(),
+        None => host,
+    };
+    host.trim_end_matches('.').to_string()
+}
+
+/// 域名是否允许请求：没有开启限制、指向本机或者匹配允许列表中的一项，*.example.com 同时匹配 example.com 和它的子域名
+pub fn is_host_allowed(host: &str) -> bool {
+    let host = normalize_host(host);
+    if is_local_host(&host) {
+        return true;
+    }
+    let guard = match EGRESS_ALLOWLIST.read() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    let Some(allowlist) = guard.as_ref() else {
+        return true;
+    };
+    allowlist
+        .iter()
+        .any(|entry| match entry.strip_prefix("*.") {
+            Some(domain) => host == domain || host.ends_with(&format!(".{}", domain)),
+            None => host == *entry,
+        })
+}
+
+/// 检查请求的地址，域名不在允许列表中时返回 EgressBlockedError
+pub fn check_egress(url: &str) -> Result<(), EgressBlockedError> {
+    let host = Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| host.to_string()))
+        .unwrap_or_else(|| normalize_host(url));
+    if is_host_allowed(&host) {
+        Ok(())
+    } else {
+        Err(EgressBlockedError {
+            host: normalize_host(&host),
+        })
+    }
+}
+
+fn is_local_host(host: &str) -> bool {
+    match host.parse::<IpAddr>() {
+        Ok(ip) => ip.is_loopback() || ip.is_unspecified(),
+        Err(_) => host == "localhost" || host.ends_with(".localhost"),
+    }
+}
+
+/// 共用 HTTP 客户端使用的域名解析，解析前检查出站允许列表，重定向到的域名同样会经过这里
+struct EgressResolver;
+
+impl Resolve for EgressResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            if !is_host_allowed(&host) {
+                let error: Box<dyn StdError + Send + Sync> = Box::new(EgressBlockedError { host });
+                return Err(error);
+            }
+            let addrs = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .collect::<Vec<SocketAddr>>();
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+// 直接使用 IP 地址的重定向不经过域名解析，在这里检查
+fn redirect_policy() -> Policy {
+    Policy::custom(|attempt: Attempt| {
+        if let Err(e) = check_egress(attempt.url().as_str()) {
+            return attempt.error(e);
+        }
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            return attempt.error("too many redirects");
+        }
+        attempt.follow()
+    })
+}
+
+/// 所有提供商请求使用的 HTTP 客户端构建器，域名解析和重定向都会检查出站允许列表，发送请求时使用 send_request
+pub fn client_builder() -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        .dns_resolver(std::sync::Arc::new(EgressResolver))
+        .redirect(redirect_policy())
+}
+
+/// send_request 的错误：请求地址被出站允许列表阻止，或者请求本身失败
+#[derive(Debug)]
+pub enum SendError {
+    Blocked(EgressBlockedError),
+    Request(reqwest::Error),
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::Blocked(e) => e.fmt(f),
+            SendError::Request(e) => e.fmt(f),
+        }
+    }
+}
+
+impl StdError for SendError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            SendError::Blocked(e) => Some(e),
+            SendError::Request(e) => Some(e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for SendError {
+    fn from(e: reqwest::Error) -> Self {
+        SendError::Request(e)
+    }
+}
+
+/// 发送请求前检查请求地址，直接使用 IP 地址的请求不经过域名解析，只能在这里拦截
+pub async fn send_request(
+    request: reqwest::RequestBuilder,
+) -> Result<reqwest::Response, SendError> {
+    let (client, request) = request.build_split();
+    let request = request?;
+    check_egress(request.url().as_str()).map_err(SendError::Blocked)?;
+    Ok(client.execute(request).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_host() {
+        assert_eq!(
+            normalize_host("https://API.OpenAI.com/v1"),
+            "api.openai.com"
+        );
+        assert_eq!(normalize_host("example.com:8443"), "example.com");
+        assert_eq!(normalize_host("http://user@[::1]:11434"), "::1");
+        assert_eq!(normalize_host(" *.example.com "), "*.example.com");
+    }
+
+    #[tokio::test]
+    async fn test_egress_allowlist() {
+        set_egress_allowlist(false, &[]);
+        assert!(is_host_allowed("evil.example"));
+
+        set_egress_allowlist(
+            true,
+            &["api.openai.com".to_string(), "*.example.com".to_string()],
+        );
+        assert!(is_host_allowed("api.openai.com"));
+        assert!(is_host_allowed("example.com"));
+        assert!(is_host_allowed("a.b.example.com"));
+        assert!(!is_host_allowed("badexample.com"));
+        assert!(!is_host_allowed("api.openai.com.evil.example"));
+        // 本机地址不算出站请求
+        assert!(is_host_allowed("localhost"));
+        assert!(is_host_allowed("127.0.0.1"));
+        assert_eq!(
+            check_egress("https://8.8.8.8/v1").unwrap_err().host,
+            "8.8.8.8"
+        );
+
+        // 直接使用 IP 地址的请求不经过域名解析，发送前被拦截
+        let client = client_builder().build().unwrap();
+        let error = send_request(client.get("http://8.8.8.8/v1/models"))
+            .await
+            .unwrap_err();
+        assert!(matches!(&error, SendError::Blocked(e) if e.host == "8.8.8.8"));
+        let error = anyhow::Error::new(error);
+        assert_eq!(egress_blocked_host(&error), Some("8.8.8.8".to_string()));
+        set_egress_allowlist(false, &[]);
+    }
+}
@@ -0,0 +1,405 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use futures::StreamExt;
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use crate::{api::llm_api::LlmModel, db::llm_db::LLMProviderConfig};
+
+use super::{
+    capability::get_model_capability,
+    chat_message::{ChatMessage, Role},
+    check_response_status, client_builder, finish_reason_event,
+    mock::FixtureRecorder,
+    request_builder::{apply_request_headers, AuthScheme},
+    request_context::RequestContext,
+    send_request,
+    sse::SseDecoder,
+    sse::SseEvent,
+    usage_event, ChatResponse, ModelProvider, ProviderClient, TokenUsage,
+};
+
+// 没有配置 endpoint 时使用 Hugging Face 的 Serverless Inference API，模型代码拼接在地址后面
+const HF_INFERENCE_ENDPOINT: &str = "https://api-inference.huggingface.co/models";
+
+/// Hugging Face Inference API 和自己部署的 Text Generation Inference (TGI) 服务
+///
+/// TGI 接收拼接好的提示词，不接收消息列表，提示词的格式由提供商配置的 chat_template 决定：
+/// chatml（默认）、llama3 或 plain。配置了 endpoint 时按 TGI 的 /generate 和 /generate_stream 接口请求
+pub struct HuggingFaceProvider {
+    llm_provider_config: Vec<LLMProviderConfig>,
+    client: ProviderClient,
+}
+
+impl ModelProvider for HuggingFaceProvider {
+    fn new(llm_provider_config: Vec<LLMProviderConfig>) -> Self
+    where
+        Self: Sized,
+    {
+        HuggingFaceProvider {
+            llm_provider_config,
+            client: ProviderClient::new(client_builder().timeout(Duration::from_secs(300))),
+        }
+    }
+
+    fn chat(
+        &self,
+        _message_id: i64,
+        messages: Vec<ChatMessage>,
+        model_config: Vec<crate::db::assistant_db::AssistantModelConfig>,
+        cancel_token: CancellationToken,
+    ) -> futures::future::BoxFuture<'static, Result<ChatResponse>> {
+        let config = self.llm_provider_config.clone();
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            let client = client.get()?;
+            let context = RequestContext::new(config, &model_config, messages);
+            let url = generate_url(&context, false);
+            let body = build_body(&context, false);
+            println!("huggingface chat url: {} body: {:?}", url, body);
+
+            let request =
+                apply_request_headers(client.post(&url), &context.config_map, AuthScheme::Bearer)?
+                    .json(&body);
+
+            let response = tokio::select! {
+                response = send_request(request) => response?,
+                _ = cancel_token.cancelled() => bail!("Request cancelled"),
+            };
+
+            let response = check_response_status(response).await?;
+
+            let json_response = tokio::select! {
+                json = response.json::<Value>() => json?,
+                _ = cancel_token.cancelled() => bail!("Request cancelled"),
+            };
+            println!("huggingface chat response: {:?}", json_response);
+
+            if let Some(error) = json_response["error"].as_str() {
+                bail!("Hugging Face error: {}", error);
+            }
+            // Inference API 返回数组，TGI 的 /generate 返回对象
+            let result = if json_response.is_array() {
+                &json_response[0]
+            } else {
+                &json_response
+            };
+            match result["generated_text"].as_str() {
+                Some(content) => Ok(ChatResponse {
+                    content: content.to_string(),
+                    finish_reason: result["details"]["finish_reason"]
+                        .as_str()
+                        .map(|s| s.to_string()),
+                    // TGI 只返回生成的 token 数量
+                    usage: result["details"]["generated_tokens"]
+                        .as_u64()
+                        .map(|tokens| TokenUsage {
+                            completion_tokens: Some(tokens as u32),
+                            ..Default::default()
+                        }),
+                    ..Default::default()
+                }),
+                None => bail!("Failed to get content from response"),
+            }
+        })
+    }
+
+    fn chat_stream(
+        &self,
+        message_id: i64,
+        messages: Vec<ChatMessage>,
+        model_config: Vec<crate::db::assistant_db::AssistantModelConfig>,
+        tx: tokio::sync::mpsc::Sender<(i64, String, bool)>,
+        cancel_token: CancellationToken,
+    ) -> futures::future::BoxFuture<'static, Result<()>> {
+        let config = self.llm_provider_config.clone();
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            let client = client.get()?;
+            let context = RequestContext::new(config, &model_config, messages);
+            let url = generate_url(&context, true);
+            let body = build_body(&context, true);
+            println!("huggingface chat stream url: {} body: {:?}", url, body);
+
+            let request =
+                apply_request_headers(client.post(&url), &context.config_map, AuthScheme::Bearer)?
+                    .json(&body);
+
+            let response = tokio::select! {
+                response = send_request(request) => response?,
+                _ = cancel_token.cancelled() => bail!("Request cancelled"),
+            };
+
+            let response = check_response_status(response).await?;
+
+            let mut stream = response.bytes_stream();
+            let mut full_text = String::new();
+            let mut decoder = SseDecoder::new();
+            let mut recorder = FixtureRecorder::from_config(&context.config_map);
+
+            loop {
+                tokio::select! {
+                    chunk = stream.next() => {
+                        match chunk {
+                            Some(Ok(chunk)) => {
+                                if let Some(recorder) = recorder.as_mut() {
+                                    recorder.record(&chunk);
+                                }
+                                for event in decoder.push(&chunk) {
+                                    if send_stream_event(&event, message_id, &mut full_text, &tx).await? {
+                                        tx.send((message_id, full_text.clone(), true)).await?;
+                                        return Ok(());
+                                    }
+                                }
+                            }
+                            Some(Err(e)) => bail!(e),
+                            None => {
+                                println!("huggingface chat stream end");
+                                for event in decoder.finish() {
+                                    if send_stream_event(&event, message_id, &mut full_text, &tx).await? {
+                                        tx.send((message_id, full_text.clone(), true)).await?;
+                                        return Ok(());
+                                    }
+                                }
+                                // 没有收到带 details 的最后一条事件，不发送结束标记，由接收方按连接中断处理
+                                println!("huggingface chat stream interrupted");
+                                return Ok(());
+                            }
+                        }
+                    }
+                    _ = cancel_token.cancelled() => {
+                        tx.send((message_id, full_text.clone(), true)).await?;
+                        return Ok(());
+                    }
+                }
+            }
+        })
+    }
+
+    /// TGI 的 /info 接口返回当前部署的模型，Inference API 没有模型列表接口，需要手动添加模型
+    fn models(&self) -> futures::future::BoxFuture<'static, Result<Vec<LlmModel>>> {
+        let config = self.llm_provider_config.clone();
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            let client = client.get()?;
+            let config_map: HashMap<String, String> =
+                config.into_iter().map(|c| (c.name, c.value)).collect();
+            let Some(endpoint) = configured_endpoint(&config_map) else {
+                bail!("Hugging Face Inference API 不支持获取模型列表，请手动添加模型");
+            };
+            let url = format!("{}/info", endpoint);
+            println!("TGI info endpoint : {}", url);
+
+            let request = apply_request_headers(client.get(&url), &config_map, AuthScheme::Bearer)?;
+            let response = send_request(request).await?.error_for_status()?;
+            let info: Value = response.json().await?;
+            println!("TGI info: {:?}", info);
+
+            let Some(model_id) = info["model_id"].as_str() else {
+                bail!("TGI 返回的信息中没有 model_id");
+            };
+            let capability = get_model_capability("huggingface", model_id);
+            Ok(vec![LlmModel {
+                id: 0,
+                name: model_id.to_string(),
+                llm_provider_id: 1,
+                code: model_id.to_string(),
+                description: format!(
+                    "Model id: {}, max input tokens: {}, max total tokens: {}",
+                    model_id,
+                    info["max_input_tokens"]
+                        .as_u64()
+                        .or(info["max_input_length"].as_u64())
+                        .unwrap_or_default(),
+                    info["max_total_tokens"].as_u64().unwrap_or_default(),
+                ),
+                vision_support: capability.vision_support,
+                audio_support: false,
+                video_support: false,
+                is_enabled: true,
+            }])
+        })
+    }
+}
+
+fn configured_endpoint(config_map: &HashMap<String, String>) -> Option<String> {
+    config_map
+        .get("endpoint")
+        .map(|endpoint| endpoint.trim().trim_end_matches('/'))
+        .filter(|endpoint| !endpoint.is_empty())
+        .map(|endpoint| endpoint.to_string())
+}
+
+fn generate_url(context: &RequestContext, stream: bool) -> String {
+    match configured_endpoint(&context.config_map) {
+        Some(endpoint) if stream => format!("{}/generate_stream", endpoint),
+        Some(endpoint) => format!("{}/generate", endpoint),
+        None => format!(
+            "{}/{}",
+            HF_INFERENCE_ENDPOINT,
+            context.params().model.unwrap_or_default()
+        ),
+    }
+}
+
+// 处理一条 generate_stream 事件，返回 true 表示生成结束
+async fn send_stream_event(
+    event: &SseEvent,
+    message_id: i64,
+    full_text: &mut String,
+    tx: &tokio::sync::mpsc::Sender<(i64, String, bool)>,
+) -> Result<bool> {
+    let Some(chunk) = event.json() else {
+        return Ok(false);
+    };
+    if let Some(error) = chunk["error"].as_str() {
+        bail!("Hugging Face error: {}", error);
+    }
+    // 特殊 token（例如 </s>、<|im_end|>）不显示
+    if chunk["token"]["special"].as_bool() != Some(true) {
+        if let Some(text) = chunk["token"]["text"].as_str() {
+            full_text.push_str(text);
+            tx.send((message_id, full_text.clone(), false)).await?;
+        }
+    }
+    let details = &chunk["details"];
+    if !details.is_object() {
+        return Ok(false);
+    }
+    if let Some(finish_reason) = details["finish_reason"].as_str() {
+        tx.send((message_id, finish_reason_event(finish_reason), false))
+            .await?;
+    }
+    if let Some(completion_tokens) = details["generated_tokens"].as_u64() {
+        let usage = TokenUsage {
+            completion_tokens: Some(completion_tokens as u32),
+            ..Default::default()
+        };
+        tx.send((message_id, usage_event(&usage), false)).await?;
+    }
+    Ok(true)
+}
+
+// chat 和 chat_stream 共用的请求体
+fn build_body(context: &RequestContext, stream: bool) -> Value {
+    let params = context.params();
+    let model = params.model.clone().unwrap_or_default();
+    let template = context
+        .config_map
+        .get("chat_template")
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .unwrap_or("chatml");
+    let vision_support = get_model_capability("huggingface", &model).vision_support;
+
+    let mut stop = params.stop.unwrap_or_default();
+    match template {
+        "chatml" => stop.push("<|im_end|>".to_string()),
+        "llama3" => stop.push("<|eot_id|>".to_string()),
+        _ => {}
+    }
+    let mut parameters = json!({
+        "max_new_tokens": params.max_tokens,
+        "return_full_text": false,
+        "details": true,
+        "stop": stop,
+    });
+    // TGI 要求 temperature 大于 0、top_p 在 0 和 1 之间（不含），否则直接报错
+    if params.temperature > 0.0 {
+        parameters["do_sample"] = json!(true);
+        parameters["temperature"] = json!(params.temperature);
+    } else {
+        parameters["do_sample"] = json!(false);
+    }
+    if params.top_p > 0.0 && params.top_p < 1.0 {
+        parameters["top_p"] = json!(params.top_p);
+    }
+
+    let mut body = json!({
+        "inputs": build_prompt(&context.messages, template, vision_support),
+        "parameters": parameters,
+    });
+    // Inference API 通过 stream 字段区分是否流式返回，TGI 通过不同的接口区分
+    if configured_endpoint(&context.config_map).is_none() {
+        body["stream"] = json!(stream);
+    }
+    body
+}
+
+/// 按对话模板把消息列表拼接成提示词，最后留出助手回答的开头
+///
+/// 支持视觉的模型（例如 idefics、llava）使用 TGI 的 `![](data:...)` 语法在提示词中携带图片
+pub fn build_prompt(messages: &[ChatMessage], template: &str, vision_support: bool) -> String {
+    let mut prompt = String::new();
+    if template == "llama3" {
+        prompt.push_str("<|begin_of_text|>");
+    }
+    for message in messages {
+        let message_type = message.role.as_str();
+        let mut content = message.text();
+        if vision_support {
+            for attachment in message.images() {
+                if let Some(data) = &attachment.attachment_content {
+                    content = format!("![]({}){}", data, content);
+                }
+            }
+        }
+        match template {
+            "llama3" => prompt.push_str(&format!(
+                "<|start_header_id|>{}<|end_header_id|>\n\n{}<|eot_id|>",
+                message_type, content
+            )),
+            "plain" => {
+                let role = match message.role {
+                    Role::System => "System",
+                    Role::Assistant => "Assistant",
+                    _ => "User",
+                };
+                prompt.push_str(&format!("{}: {}\n\n", role, content));
+            }
+            _ => prompt.push_str(&format!(
+                "<|im_start|>{}\n{}<|im_end|>\n",
+                message_type, content
+            )),
+        }
+    }
+    match template {
+        "llama3" => prompt.push_str("<|start_header_id|>assistant<|end_header_id|>\n\n"),
+        "plain" => prompt.push_str("Assistant:"),
+        _ => prompt.push_str("<|im_start|>assistant\n"),
+    }
+    prompt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages() -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::new(Role::System, "You are helpful."),
+            ChatMessage::new(Role::User, "你好"),
+        ]
+    }
+
+    #[test]
+    fn test_build_prompt_chatml() {
+        assert_eq!(
+            build_prompt(&messages(), "chatml", false),
+            "<|im_start|>system\nYou are helpful.<|im_end|>\n<|im_start|>user\n你好<|im_end|>\n<|im_start|>assistant\n"
+        );
+    }
+
+    #[test]
+    fn test_build_prompt_plain() {
+        assert_eq!(
+            build_prompt(&messages(), "plain", false),
+            "System: You are helpful.\n\nUser: 你好\n\nAssistant:"
+        );
+    }
+}
@@ -0,0 +1,662 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    api::llm_api::LlmModel,
+    db::{assistant_db::AssistantModelConfig, llm_db::LLMProviderConfig},
+};
+
+use super::{
+    chat_message::{ChatMessage, Role},
+    finish_reason_event,
+    sse::{JsonLinesDecoder, SseDecoder},
+    tool_call::ToolCallAccumulator,
+    tool_calls_event, ChatResponse, ModelProvider, ModelUnavailableError,
+};
+
+/// 录制文件中的一个网络数据块，每行一个 JSON
+///
+/// delay_ms 为距离上一个数据块的时间，不是合法 UTF-8 的数据块用 data_base64 保存
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FixtureChunk {
+    pub delay_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_base64: Option<String>,
+}
+
+impl FixtureChunk {
+    fn bytes(&self) -> Result<Vec<u8>> {
+        match (&self.data, &self.data_base64) {
+            (Some(data), _) => Ok(data.as_bytes().to_vec()),
+            (None, Some(data)) => Ok(STANDARD.decode(data)?),
+            (None, None) => Ok(Vec::new()),
+        }
+    }
+}
+
+pub fn load_fixture(path: &str) -> Result<Vec<FixtureChunk>> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("读取录制文件 {} 失败", path))?;
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str::<FixtureChunk>(line)?))
+        .collect()
+}
+
+// 录制文件只能写在这个目录中，由 generation_recording_path 在第一次录制时设置
+static RECORDING_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+pub fn set_recording_dir(dir: &Path) {
+    let _ = RECORDING_DIR.set(dir.to_path_buf());
+}
+
+// 只接受录制目录中的 .jsonl 文件，提供商配置中手动填写的其他路径不会被写入
+fn is_recording_path(dir: &Path, path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "jsonl")
+        && path.parent() == Some(dir)
+        && path.file_name().is_some()
+}
+
+/// 把真实提供商返回的流式数据原样录制到文件，开启生成调试时由 ai_api 在提供商配置中设置
+/// record_fixture 为录制目录中的文件路径，录制的文件可以直接作为 mock 提供商的 fixture_path 回放
+pub struct FixtureRecorder {
+    file: File,
+    last: Instant,
+}
+
+impl FixtureRecorder {
+    pub fn from_config(config_map: &HashMap<String, String>) -> Option<Self> {
+        let path = config_map
+            .get("record_fixture")
+            .map(|path| path.trim())
+            .filter(|path| !path.is_empty())?;
+        if !RECORDING_DIR
+            .get()
+            .is_some_and(|dir| is_recording_path(dir, Path::new(path)))
+        {
+            println!("ignore fixture path outside recording dir: {}", path);
+            return None;
+        }
+        match OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)
+        {
+            Ok(file) => Some(FixtureRecorder {
+                file,
+                last: Instant::now(),
+            }),
+            Err(e) => {
+                println!("open fixture file {} error: {:?}", path, e);
+                None
+            }
+        }
+    }
+
+    pub fn record(&mut self, chunk: &[u8]) {
+        let now = Instant::now();
+        let delay_ms = now.duration_since(self.last).as_millis() as u64;
+        self.last = now;
+        let fixture_chunk = match std::str::from_utf8(chunk) {
+            Ok(data) => FixtureChunk {
+                delay_ms,
+                data: Some(data.to_string()),
+                data_base64: None,
+            },
+            Err(_) => FixtureChunk {
+                delay_ms,
+                data: None,
+                data_base64: Some(STANDARD.encode(chunk)),
+            },
+        };
+        if let Ok(line) = serde_json::to_string(&fixture_chunk) {
+            if let Err(e) = writeln!(self.file, "{}", line) {
+                println!("record fixture error: {:?}", e);
+            }
+        }
+    }
+}
+
+/// 回放录制文件的模拟提供商，不发起任何网络请求，用于测试流式处理流程和复现问题
+///
+/// 提供商配置：
+/// - fixture_path: 录制文件路径
+/// - latency_ms: 每个数据块之间的固定延迟，不配置时使用录制时的间隔
+/// - error_after: 回放这么多个数据块之后返回错误，用于测试出错处理
+/// - error_message: 注入的错误信息
+/// - speed: 回放速度倍数，大于 1 时加快，默认 1
+/// - unavailable_models: 逗号分隔的模型代码，请求这些模型时返回模型不存在，用于测试备用模型
+/// - tool_call_fixture_path: 最后一条消息不是工具结果时回放的录制文件，用于测试工具调用
+pub struct MockProvider {
+    llm_provider_config: Vec<LLMProviderConfig>,
+}
+
+struct MockOptions {
+    fixture_path: String,
+    latency: Option<Duration>,
+    error_after: Option<usize>,
+    error_message: String,
+    speed: f64,
+    unavailable_models: Vec<String>,
+    tool_call_fixture_path: Option<String>,
+}
+
+impl MockOptions {
+    fn from_config(llm_provider_config: &[LLMProviderConfig]) -> Result<Self> {
+        let config_map: HashMap<&str, &str> = llm_provider_config
+            .iter()
+            .map(|c| (c.name.as_str(), c.value.trim()))
+            .collect();
+        let fixture_path = config_map
+            .get("fixture_path")
+            .filter(|path| !path.is_empty())
+            .context("mock 提供商没有配置 fixture_path")?;
+        Ok(MockOptions {
+            fixture_path: fixture_path.to_string(),
+            latency: config_map
+                .get("latency_ms")
+                .and_then(|v| v.parse::<u64>().ok())
+                .map(Duration::from_millis),
+            error_after: config_map
+                .get("error_after")
+                .and_then(|v| v.parse::<usize>().ok()),
+            error_message: config_map
+                .get("error_message")
+                .filter(|v| !v.is_empty())
+                .unwrap_or(&"mock provider injected error")
+                .to_string(),
+            speed: config_map
+                .get("speed")
+                .and_then(|v| v.parse::<f64>().ok())
+                .filter(|speed| speed.is_finite() && *speed > 0.0)
+                .unwrap_or(1.0),
+            unavailable_models: config_map
+                .get("unavailable_models")
+                .map(|v| {
+                    v.split(',')
+                        .map(|code| code.trim().to_string())
+                        .filter(|code| !code.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            tool_call_fixture_path: config_map
+                .get("tool_call_fixture_path")
+                .filter(|path| !path.is_empty())
+                .map(|path| path.to_string()),
+        })
+    }
+
+    // 模型请求调用工具之后，带着工具结果的下一次请求回放 fixture_path
+    fn select_fixture(&mut self, messages: &[ChatMessage]) {
+        let after_tool_result = messages
+            .last()
+            .is_some_and(|message| message.role == Role::Tool);
+        if let Some(path) = self.tool_call_fixture_path.take() {
+            if !after_tool_result {
+                self.fixture_path = path;
+            }
+        }
+    }
+
+    // 请求的模型配置为不可用时返回和真实提供商一样的 ModelUnavailableError
+    fn check_model(&self, model_config: &[AssistantModelConfig]) -> Result<()> {
+        let model = model_config
+            .iter()
+            .find(|config| config.name == "model")
+            .and_then(|config| config.value.as_deref())
+            .unwrap_or_default();
+        if self.unavailable_models.iter().any(|code| code == model) {
+            return Err(ModelUnavailableError {
+                detail: format!("HTTP 404: model {} not found", model),
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+// 提供商单独返回的思考过程放在这个标签中，和 DEFAULT_REASONING_TAGS 的第一个一致，
+// 生成时由 ReasoningFilter 分离出来
+const REASONING_TAG: &str = "think";
+
+/// 一条流式事件中的增量内容、思考过程和结束原因
+#[derive(Debug, Default, PartialEq)]
+struct EventDelta<'a> {
+    content: Option<&'a str>,
+    reasoning: Option<&'a str>,
+    finish_reason: Option<&'a str>,
+}
+
+// 兼容各个提供商的流式事件：OpenAI（包括智谱和 Responses API）、Anthropic、DashScope、
+// HuggingFace 的 SSE 事件，以及 Ollama、Cohere 的 JSON Lines
+fn parse_event(value: &Value) -> EventDelta<'_> {
+    let choice = &value["choices"][0];
+    let dashscope = &value["output"]["choices"][0];
+    let content = choice["delta"]["content"]
+        .as_str()
+        .or(value["delta"]["text"].as_str())
+        .or(dashscope["message"]["content"].as_str())
+        .or(value["message"]["content"].as_str())
+        .or_else(|| match value["type"].as_str() {
+            Some("response.output_text.delta") => value["delta"].as_str(),
+            _ => None,
+        })
+        .or_else(|| match value["event_type"].as_str() {
+            Some("text-generation") => value["text"].as_str(),
+            _ => None,
+        })
+        .or_else(|| {
+            (value["token"]["special"].as_bool() != Some(true))
+                .then(|| value["token"]["text"].as_str())
+                .flatten()
+        });
+    let reasoning = choice["delta"]["reasoning_content"]
+        .as_str()
+        .or(value["delta"]["thinking"].as_str())
+        .or(dashscope["message"]["reasoning_content"].as_str())
+        .or(value["message"]["thinking"].as_str())
+        .or_else(|| match value["type"].as_str() {
+            Some("response.reasoning_summary_text.delta") => value["delta"].as_str(),
+            _ => None,
+        });
+    let finish_reason = choice["finish_reason"]
+        .as_str()
+        .or(value["delta"]["stop_reason"].as_str())
+        // DashScope 生成过程中的 finish_reason 是字符串 "null"
+        .or(dashscope["finish_reason"]
+            .as_str()
+            .filter(|reason| *reason != "null"))
+        .or(value["done_reason"].as_str())
+        .or(value["details"]["finish_reason"].as_str())
+        .or_else(|| match value["type"].as_str() {
+            Some("response.completed") => Some("stop"),
+            Some("response.incomplete") => Some("length"),
+            _ => None,
+        })
+        .or_else(|| match value["event_type"].as_str() {
+            Some("stream-end") => value["finish_reason"].as_str(),
+            _ => None,
+        });
+    EventDelta {
+        content: content.filter(|content| !content.is_empty()),
+        reasoning: reasoning.filter(|reasoning| !reasoning.is_empty()),
+        finish_reason,
+    }
+}
+
+// 单独返回的思考过程包在标签中和回答拼接，回答开始时关闭标签
+fn push_delta(response: &mut ChatResponse, thinking: &mut bool, delta: &EventDelta) -> bool {
+    let mut changed = false;
+    if let Some(reasoning) = delta.reasoning {
+        if !*thinking {
+            response.content.push_str(&format!("<{}>", REASONING_TAG));
+            *thinking = true;
+        }
+        response.content.push_str(reasoning);
+        changed = true;
+    }
+    if let Some(content) = delta.content {
+        close_reasoning(response, thinking);
+        response.content.push_str(content);
+        changed = true;
+    }
+    changed
+}
+
+fn close_reasoning(response: &mut ChatResponse, thinking: &mut bool) {
+    if *thinking {
+        response.content.push_str(&format!("</{}>", REASONING_TAG));
+        *thinking = false;
+    }
+}
+
+async fn replay(
+    options: MockOptions,
+    message_id: i64,
+    tx: Option<&mpsc::Sender<(i64, String, bool)>>,
+    cancel_token: CancellationToken,
+) -> Result<ChatResponse> {
+    let chunks = load_fixture(&options.fixture_path)?;
+    let mut decoder = SseDecoder::new();
+    // Ollama 和 Cohere 的录制是 JSON Lines，SSE 的 data 行不是 JSON，两种解码器不会重复解析
+    let mut lines_decoder = JsonLinesDecoder::new();
+    let mut response = ChatResponse::default();
+    let mut tool_calls = ToolCallAccumulator::default();
+    let mut thinking = false;
+
+    for (index, chunk) in chunks.iter().enumerate() {
+        if options.error_after == Some(index) {
+            bail!("{}", options.error_message);
+        }
+        let delay = options
+            .latency
+            .unwrap_or(Duration::from_millis(chunk.delay_ms))
+            .div_f64(options.speed);
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = cancel_token.cancelled() => break,
+        }
+
+        let bytes = chunk.bytes()?;
+        let values = decoder
+            .push(&bytes)
+            .into_iter()
+            .filter(|event| !event.is_done())
+            .filter_map(|event| event.json())
+            .chain(lines_decoder.push(&bytes));
+        for value in values.collect::<Vec<Value>>() {
+            tool_calls.push_openai_event(&value);
+            tool_calls.push_anthropic_event(&value);
+            let delta = parse_event(&value);
+            if push_delta(&mut response, &mut thinking, &delta) {
+                if let Some(tx) = tx {
+                    tx.send((message_id, response.content.clone(), false))
+                        .await?;
+                }
+            }
+            if let Some(finish_reason) = delta.finish_reason {
+                response.finish_reason = Some(finish_reason.to_string());
+                if let Some(tx) = tx {
+                    tx.send((message_id, finish_reason_event(finish_reason), false))
+                        .await?;
+                }
+            }
+        }
+    }
+    close_reasoning(&mut response, &mut thinking);
+    response.tool_calls = tool_calls.finish();
+    if let Some(tx) = tx {
+        if !response.tool_calls.is_empty() {
+            tx.send((message_id, tool_calls_event(&response.tool_calls), false))
+                .await?;
+        }
+        tx.send((message_id, response.content.clone(), true))
+            .await?;
+    }
+    Ok(response)
+}
+
+/// 按录制时的节奏回放录制文件，speed 为速度倍数，内容和结束原因按提供商的格式发送到 tx
+pub async fn replay_fixture(
+    path: &str,
+    speed: f64,
+    message_id: i64,
+    tx: mpsc::Sender<(i64, String, bool)>,
+    cancel_token: CancellationToken,
+) -> Result<()> {
+    let options = MockOptions {
+        fixture_path: path.to_string(),
+        latency: None,
+        error_after: None,
+        error_message: String::new(),
+        speed,
+        unavailable_models: Vec::new(),
+        tool_call_fixture_path: None,
+    };
+    replay(options, message_id, Some(&tx), cancel_token).await?;
+    Ok(())
+}
+
+impl ModelProvider for MockProvider {
+    fn new(llm_provider_config: Vec<LLMProviderConfig>) -> Self {
+        MockProvider {
+            llm_provider_config,
+        }
+    }
+
+    fn chat(
+        &self,
+        message_id: i64,
+        messages: Vec<ChatMessage>,
+        model_config: Vec<AssistantModelConfig>,
+        cancel_token: CancellationToken,
+    ) -> BoxFuture<'static, Result<ChatResponse>> {
+        let options = MockOptions::from_config(&self.llm_provider_config);
+        Box::pin(async move {
+            let mut options = options?;
+            options.check_model(&model_config)?;
+            options.select_fixture(&messages);
+            replay(options, message_id, None, cancel_token).await
+        })
+    }
+
+    fn chat_stream(
+        &self,
+        message_id: i64,
+        messages: Vec<ChatMessage>,
+        model_config: Vec<AssistantModelConfig>,
+        tx: mpsc::Sender<(i64, String, bool)>,
+        cancel_token: CancellationToken,
+    ) -> BoxFuture<'static, Result<()>> {
+        let options = MockOptions::from_config(&self.llm_provider_config);
+        Box::pin(async move {
+            let mut options = options?;
+            options.check_model(&model_config)?;
+            options.select_fixture(&messages);
+            replay(options, message_id, Some(&tx), cancel_token).await?;
+            Ok(())
+        })
+    }
+
+    fn models(&self) -> BoxFuture<'static, Result<Vec<LlmModel>>> {
+        Box::pin(async move {
+            Ok(vec![LlmModel {
+                id: 0,
+                name: "mock".to_string(),
+                llm_provider_id: 0,
+                code: "mock".to_string(),
+                description: "回放录制文件的模拟模型".to_string(),
+                vision_support: false,
+                audio_support: false,
+                video_support: false,
+                is_enabled: true,
+            }])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider_config(name: &str, value: &str) -> LLMProviderConfig {
+        LLMProviderConfig {
+            id: 0,
+            name: name.to_string(),
+            llm_provider_id: 1,
+            value: value.to_string(),
+            append_location: "header".to_string(),
+            is_addition: false,
+        }
+    }
+
+    // 把一段 OpenAI 格式的流拆成若干个数据块写入临时录制文件，JSON 故意从中间拆开
+    fn write_fixture(name: &str) -> String {
+        let stream = "data: {\"choices\":[{\"delta\":{\"content\":\"你好\"}}]}\n\n\
+            data: {\"choices\":[{\"delta\":{\"content\":\"，世界\"},\"finish_reason\":\"stop\"}]}\n\n\
+            data: [DONE]\n\n";
+        let bytes = stream.as_bytes();
+        let path =
+            std::env::temp_dir().join(format!("aipp_mock_{}_{}.jsonl", name, std::process::id()));
+        let mut recorder = FixtureRecorder {
+            file: File::create(&path).unwrap(),
+            last: Instant::now(),
+        };
+        for chunk in bytes.chunks(7) {
+            recorder.record(chunk);
+        }
+        path.to_string_lossy().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_replay_stream() {
+        let path = write_fixture("replay");
+        let provider = MockProvider::new(vec![
+            provider_config("fixture_path", &path),
+            provider_config("latency_ms", "0"),
+        ]);
+        let (tx, mut rx) = mpsc::channel(100);
+        provider
+            .chat_stream(1, vec![], vec![], tx, CancellationToken::new())
+            .await
+            .unwrap();
+
+        let mut received = Vec::new();
+        while let Some(item) = rx.recv().await {
+            received.push(item);
+        }
+        assert_eq!(received.first().unwrap().1, "你好");
+        assert!(received.contains(&(1, finish_reason_event("stop"), false)));
+        assert_eq!(
+            received.last().unwrap(),
+            &(1, "你好，世界".to_string(), true)
+        );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_replay_fixture_speed() {
+        let path = write_fixture("speed");
+        let (tx, mut rx) = mpsc::channel(100);
+        // 录制时的间隔很短，加快一千倍后几乎没有等待
+        replay_fixture(&path, 1000.0, 2, tx, CancellationToken::new())
+            .await
+            .unwrap();
+        let mut last = None;
+        while let Some(item) = rx.recv().await {
+            last = Some(item);
+        }
+        assert_eq!(last, Some((2, "你好，世界".to_string(), true)));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_error_injection() {
+        let path = write_fixture("error");
+        let provider = MockProvider::new(vec![
+            provider_config("fixture_path", &path),
+            provider_config("latency_ms", "0"),
+            provider_config("error_after", "3"),
+            provider_config("error_message", "connection reset"),
+        ]);
+        let result = provider
+            .chat(1, vec![], vec![], CancellationToken::new())
+            .await;
+        assert_eq!(result.unwrap_err().to_string(), "connection reset");
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_is_recording_path() {
+        let dir = Path::new("/data/generation_recordings");
+        assert!(is_recording_path(dir, &dir.join("12.jsonl")));
+        assert!(!is_recording_path(dir, Path::new("/etc/passwd")));
+        assert!(!is_recording_path(dir, &dir.join("12.txt")));
+        assert!(!is_recording_path(dir, &dir.join("sub").join("12.jsonl")));
+    }
+
+    #[test]
+    fn test_parse_provider_events() {
+        use serde_json::json;
+
+        let cases = [
+            (
+                json!({"delta": {"type": "thinking_delta", "thinking": "想"}}),
+                None,
+                Some("想"),
+                None,
+            ),
+            (
+                json!({"output": {"choices": [{"message": {"content": "好"}, "finish_reason": "null"}]}}),
+                Some("好"),
+                None,
+                None,
+            ),
+            (
+                json!({"token": {"text": "</s>", "special": true}, "details": {"finish_reason": "eos_token"}}),
+                None,
+                None,
+                Some("eos_token"),
+            ),
+            (
+                json!({"message": {"content": "嗯"}, "done": true, "done_reason": "stop"}),
+                Some("嗯"),
+                None,
+                Some("stop"),
+            ),
+            (
+                json!({"event_type": "text-generation", "text": "是"}),
+                Some("是"),
+                None,
+                None,
+            ),
+            (
+                json!({"type": "response.output_text.delta", "delta": "对"}),
+                Some("对"),
+                None,
+                None,
+            ),
+        ];
+        for (value, content, reasoning, finish_reason) in cases {
+            assert_eq!(
+                parse_event(&value),
+                EventDelta {
+                    content,
+                    reasoning,
+                    finish_reason,
+                },
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_reasoning_and_json_lines() {
+        let path =
+            std::env::temp_dir().join(format!("aipp_mock_reasoning_{}.jsonl", std::process::id()));
+        let mut recorder = FixtureRecorder {
+            file: File::create(&path).unwrap(),
+            last: Instant::now(),
+        };
+        // DeepSeek 格式的 reasoning_content 和 Ollama 的 JSON Lines
+        recorder.record(
+            "data: {\"choices\":[{\"delta\":{\"reasoning_content\":\"1+1\"}}]}\n\n".as_bytes(),
+        );
+        recorder.record(
+            "{\"message\":{\"content\":\"等于 2\"},\"done\":true,\"done_reason\":\"stop\"}\n"
+                .as_bytes(),
+        );
+        let path = path.to_string_lossy().to_string();
+        let provider = MockProvider::new(vec![
+            provider_config("fixture_path", &path),
+            provider_config("latency_ms", "0"),
+        ]);
+        let response = provider
+            .chat(1, vec![], vec![], CancellationToken::new())
+            .await
+            .unwrap();
+        assert_eq!(response.content, "<think>1+1</think>等于 2");
+        assert_eq!(response.finish_reason.as_deref(), Some("stop"));
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -1,27 +1,304 @@
 use anyhow::Result;
-use std::sync::Arc;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 
 use anthropic::AnthropicProvider;
 use cohere::CohereProvider;
+use dashscope::DashScopeProvider;
 use futures::future::BoxFuture;
+use huggingface::HuggingFaceProvider;
+use mock::MockProvider;
 use ollama::OllamaProvider;
 use openai::OpenAIProvider;
+use zhipu::ZhipuProvider;
 
 use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
 
 use crate::db::{
     assistant_db::AssistantModelConfig,
-    conversation_db::MessageAttachment,
     llm_db::{LLMProvider, LLMProviderConfig},
 };
 
 use super::llm_api::LlmModel;
 
 mod anthropic;
+mod capability;
+mod chat_message;
 mod cohere;
+mod dashscope;
+mod diagnostics;
+mod egress;
+mod huggingface;
+mod mock;
+mod offline;
 mod ollama;
 mod openai;
+mod openai_responses;
+mod preload;
+mod provider_error;
+mod reasoning;
+mod request_builder;
+mod request_context;
+mod sse;
+mod stream_batcher;
+mod tool_call;
+mod zhipu;
+
+pub use capability::{
+    get_model_capability, get_model_param_schema, supports_assistant_prefill, ModelParamSpec,
+};
+pub use chat_message::{ChatMessage, ContentPart, Role};
+use diagnostics::{classify_status, error_detail, DiagnosticKind};
+pub use diagnostics::{diagnose_provider, ProviderDiagnostics};
+pub use egress::{
+    check_egress, client_builder, egress_blocked_host, normalize_host, send_request,
+    set_egress_allowlist, SendError,
+};
+pub use mock::{replay_fixture, set_recording_dir};
+pub use offline::{is_local_endpoint, is_local_provider, provider_endpoint};
+pub use preload::{supports_warm_up, warm_up_model};
+pub use provider_error::{ProviderError, ProviderErrorEvent};
+pub use reasoning::{
+    parse_reasoning_tags, split_reasoning, ReasoningSplit, DEFAULT_REASONING_TAGS,
+};
+pub use request_context::{
+    append_response_length_instruction, ModelParams, RequestContext, ResponseLength,
+    RESPONSE_LENGTH_CONFIG,
+};
+pub use stream_batcher::{StreamBatcher, DEFAULT_FLUSH_INTERVAL};
+pub use tool_call::{
+    configured_tools, tool_result_message, tool_use_message, ToolCall, ToolResult, TOOLS_CONFIG,
+};
+
+// 提供商通过 tx 发送的事件都以这个前缀开头，和回答内容区分
+pub const EVENT_PREFIX: &str = "Tea::Event::";
+// 流式对话中提供商通过 tx 发送的结束原因事件前缀，后面跟提供商返回的原始结束原因，不属于消息正文
+pub const FINISH_REASON_EVENT_PREFIX: &str = "Tea::Event::FinishReason::";
+// 流式对话中提供商上报 token 用量的事件前缀，后面跟 TokenUsage 的 JSON
+pub const USAGE_EVENT_PREFIX: &str = "Tea::Event::Usage::";
+// 流式请求在收到结束事件之前出错时发送的事件前缀，后面跟错误信息
+pub const STREAM_INTERRUPTED_EVENT_PREFIX: &str = "Tea::Event::StreamInterrupted::";
+// 请求的模型不可用、换用备用模型时发送的事件前缀，后面跟实际使用的模型代码
+pub const SERVED_MODEL_EVENT_PREFIX: &str = "Tea::Event::ServedModel::";
+// 服务端工具（代码解释器、文件检索等）执行进度的事件前缀，后面跟 ToolProgress 的 JSON
+pub const TOOL_PROGRESS_EVENT_PREFIX: &str = "Tea::Event::ToolProgress::";
+// 模型请求调用内置工具的事件前缀，后面跟 ToolCall 数组的 JSON，在结束标记之前发送
+pub const TOOL_CALLS_EVENT_PREFIX: &str = "Tea::Event::ToolCalls::";
+
+/// 非流式对话的返回结果
+#[derive(Debug, Clone, Default)]
+pub struct ChatResponse {
+    pub content: String,
+    // 提供商返回的原始结束原因，例如 stop、length、max_tokens
+    pub finish_reason: Option<String>,
+    // 模型请求调用的工具，调用方执行后带上结果重新请求
+    pub tool_calls: Vec<ToolCall>,
+    // 提供商返回的 token 用量，没有返回时为空
+    pub usage: Option<TokenUsage>,
+}
+
+/// 提供商返回的 token 用量，流式响应中可能分多次上报，后到的字段覆盖先到的
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: Option<u32>,
+    pub completion_tokens: Option<u32>,
+    pub total_tokens: Option<u32>,
+}
+
+impl TokenUsage {
+    pub fn merge(&mut self, other: &TokenUsage) {
+        self.prompt_tokens = other.prompt_tokens.or(self.prompt_tokens);
+        self.completion_tokens = other.completion_tokens.or(self.completion_tokens);
+        self.total_tokens = other.total_tokens.or(self.total_tokens);
+        if other.total_tokens.is_none() {
+            if let (Some(prompt), Some(completion)) = (self.prompt_tokens, self.completion_tokens) {
+                self.total_tokens = Some(prompt + completion);
+            }
+        }
+    }
+}
+
+/// 提供商在服务端执行的工具的进度，例如代码解释器正在运行代码
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ToolProgress {
+    // 同一次工具调用的多个进度事件 item_id 相同
+    pub item_id: String,
+    pub tool: String,
+    // in_progress、interpreting、searching、completed 等，直接使用提供商返回的状态
+    pub status: String,
+}
+
+pub fn finish_reason_event(finish_reason: &str) -> String {
+    format!("{}{}", FINISH_REASON_EVENT_PREFIX, finish_reason)
+}
+
+pub fn stream_interrupted_event(error: &str) -> String {
+    format!("{}{}", STREAM_INTERRUPTED_EVENT_PREFIX, error)
+}
+
+pub fn served_model_event(model_code: &str) -> String {
+    format!("{}{}", SERVED_MODEL_EVENT_PREFIX, model_code)
+}
+
+pub fn tool_progress_event(progress: &ToolProgress) -> String {
+    format!(
+        "{}{}",
+        TOOL_PROGRESS_EVENT_PREFIX,
+        serde_json::to_string(progress).unwrap_or_default()
+    )
+}
+
+pub fn tool_calls_event(tool_calls: &[ToolCall]) -> String {
+    format!(
+        "{}{}",
+        TOOL_CALLS_EVENT_PREFIX,
+        serde_json::to_string(tool_calls).unwrap_or_default()
+    )
+}
+
+pub fn usage_event(usage: &TokenUsage) -> String {
+    format!(
+        "{}{}",
+        USAGE_EVENT_PREFIX,
+        serde_json::to_string(usage).unwrap_or_default()
+    )
+}
+
+/// 判断结束原因是否表示输出因为达到 max_tokens 而被截断
+pub fn is_truncated_finish_reason(finish_reason: &str) -> bool {
+    matches!(
+        finish_reason.to_lowercase().as_str(),
+        "length" | "max_tokens"
+    )
+}
+
+/// 按顺序尝试的模型列表，第一个是请求的模型，没有指定模型时使用提供商配置的 default_model，
+/// 后面是提供商配置的 fallback_models，逗号分隔
+pub fn model_chain(model_code: &str, llm_provider_config: &[LLMProviderConfig]) -> Vec<String> {
+    let get_value = |name: &str| {
+        llm_provider_config
+            .iter()
+            .find(|config| config.name == name)
+            .map(|config| config.value.trim())
+            .unwrap_or_default()
+    };
+    let requested = match model_code.trim() {
+        "" => get_value("default_model"),
+        code => code,
+    };
+    let mut chain: Vec<String> = Vec::new();
+    for code in std::iter::once(requested).chain(get_value("fallback_models").split(',')) {
+        let code = code.trim();
+        if !code.is_empty() && !chain.iter().any(|c| c == code) {
+            chain.push(code.to_string());
+        }
+    }
+    chain
+}
+
+/// 提供商返回模型不存在或者已经下线，调度时可以换用提供商配置的备用模型
+#[derive(Debug)]
+pub struct ModelUnavailableError {
+    pub detail: String,
+}
+
+impl std::fmt::Display for ModelUnavailableError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "模型不可用，{}", self.detail)
+    }
+}
+
+impl std::error::Error for ModelUnavailableError {}
+
+pub fn is_model_unavailable(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<ModelUnavailableError>().is_some()
+}
+
+/// 请求失败时读出错误信息返回，模型不存在时返回 ModelUnavailableError，其他错误返回分类后的 ProviderError
+pub async fn check_response_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    let detail = error_detail(status.as_u16(), &body);
+    match classify_status(status.as_u16(), &body) {
+        (_, DiagnosticKind::ModelNotFound) => Err(ModelUnavailableError { detail }.into()),
+        _ => Err(ProviderError::from_body(Some(status.as_u16()), &body, detail).into()),
+    }
+}
+
+// 建立连接的超时时间
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// 两次读到数据之间最长的等待时间，不限制总时长，长时间的流式回答只要持续有数据就不会超时
+const READ_TIMEOUT: Duration = Duration::from_secs(300);
+// 预热连接时最多等待的时间，只为建立连接，不关心返回的内容
+const PREWARM_TIMEOUT: Duration = Duration::from_secs(5);
+
+static SHARED_CLIENT: OnceLock<ProviderClient> = OnceLock::new();
+
+/// 提供商使用的 HTTP 客户端，构建失败时保存错误信息，发送请求时返回，
+/// 不会退回到不检查出站允许列表的默认客户端
+#[derive(Clone)]
+pub struct ProviderClient(std::result::Result<Client, String>);
+
+impl ProviderClient {
+    pub fn new(builder: reqwest::ClientBuilder) -> Self {
+        ProviderClient(
+            builder
+                .build()
+                .map_err(|e| format!("创建 HTTP 客户端失败: {}", e)),
+        )
+    }
+
+    pub fn get(&self) -> Result<Client> {
+        self.0.clone().map_err(anyhow::Error::msg)
+    }
+}
+
+/// 提供商共用的 HTTP 客户端，连接池在请求之间复用，预热时建立的连接可以直接用于之后的请求
+fn shared_client() -> ProviderClient {
+    SHARED_CLIENT
+        .get_or_init(|| {
+            ProviderClient::new(
+                client_builder()
+                    .connect_timeout(CONNECT_TIMEOUT)
+                    .read_timeout(READ_TIMEOUT),
+            )
+        })
+        .clone()
+}
+
+/// 提前完成 DNS 解析和 TLS 握手，之后的对话请求复用连接池中的连接，返回是否连接成功
+pub async fn prewarm_connection(
+    provider: &LLMProvider,
+    llm_provider_config: &[LLMProviderConfig],
+) -> bool {
+    let default_endpoint = match provider.api_type.as_str() {
+        "openai_api" => "https://api.openai.com/v1",
+        "anthropic" => "https://api.anthropic.com",
+        "dashscope" => "https://dashscope.aliyuncs.com/api/v1",
+        "zhipu" => "https://open.bigmodel.cn/api/paas/v4",
+        // 本地服务和没有使用共用客户端的提供商不需要预热
+        _ => return false,
+    };
+    let endpoint = llm_provider_config
+        .iter()
+        .find(|config| config.name == "endpoint")
+        .map(|config| config.value.trim())
+        .filter(|endpoint| !endpoint.is_empty())
+        .unwrap_or(default_endpoint);
+    // 只要收到响应就说明连接已经建立，返回的状态码不重要
+    let Ok(client) = shared_client().get() else {
+        return false;
+    };
+    send_request(client.head(endpoint).timeout(PREWARM_TIMEOUT))
+        .await
+        .is_ok()
+}
 
 pub trait ModelProvider: Send + Sync {
     fn new(llm_provider_config: Vec<LLMProviderConfig>) -> Self
@@ -31,15 +308,15 @@ pub trait ModelProvider: Send + Sync {
     fn chat(
         &self,
         message_id: i64,
-        messages: Vec<(String, String, Vec<MessageAttachment>)>,
+        messages: Vec<ChatMessage>,
         model_config: Vec<AssistantModelConfig>,
         cancel_token: CancellationToken,
-    ) -> BoxFuture<'static, Result<String>>;
+    ) -> BoxFuture<'static, Result<ChatResponse>>;
 
     fn chat_stream(
         &self,
         message_id: i64,
-        messages: Vec<(String, String, Vec<MessageAttachment>)>,
+        messages: Vec<ChatMessage>,
         model_config: Vec<AssistantModelConfig>,
         tx: mpsc::Sender<(i64, String, bool)>,
         cancel_token: CancellationToken,
@@ -48,19 +325,25 @@ pub trait ModelProvider: Send + Sync {
     fn models(&self) -> BoxFuture<'static, Result<Vec<LlmModel>>>;
 }
 
+/// 按提供商的 api_type 创建提供商，数据库中的类型不是已知的类型时返回错误
 pub fn get_provider(
     provider: LLMProvider,
     llm_provider_config: Vec<LLMProviderConfig>,
-) -> Arc<dyn ModelProvider> {
-    match provider.api_type.as_str() {
+) -> Result<Arc<dyn ModelProvider>> {
+    Ok(match provider.api_type.as_str() {
         "ollama" => Arc::new(OllamaProvider::new(llm_provider_config)), // 传入适当的配置
         "openai_api" => Arc::new(OpenAIProvider::new(llm_provider_config)), // 传入适当的配置
         "anthropic" => Arc::new(AnthropicProvider::new(llm_provider_config)), // 传入适当的配置
         "cohere" => Arc::new(CohereProvider::new(llm_provider_config)), // 传入适当的配置
+        "huggingface" => Arc::new(HuggingFaceProvider::new(llm_provider_config)),
+        "dashscope" => Arc::new(DashScopeProvider::new(llm_provider_config)),
+        "zhipu" => Arc::new(ZhipuProvider::new(llm_provider_config)),
+        "mock" => Arc::new(MockProvider::new(llm_provider_config)),
         // 其他提供商...
-        _ => panic!(
-            "Unknown provider: {} and type: {}",
-            provider.name, provider.api_type
+        _ => anyhow::bail!(
+            "未知的提供商类型: {}（{}）",
+            provider.api_type,
+            provider.name
         ),
-    }
+    })
 }
@@ -0,0 +1,120 @@
+use std::net::IpAddr;
+
+use reqwest::Url;
+
+use crate::db::llm_db::{LLMProvider, LLMProviderConfig};
+
+// 各提供商没有配置 endpoint 时请求的地址
+fn default_endpoint(api_type: &str) -> Option<&'static str> {
+    match api_type {
+        "openai_api" => Some("https://api.openai.com/v1"),
+        "anthropic" => Some("https://api.anthropic.com"),
+        "ollama" => Some("http://localhost:11434"),
+        "cohere" => Some("https://api.cohere.ai/v1"),
+        "huggingface" => Some("https://api-inference.huggingface.co/models"),
+        "dashscope" => Some("https://dashscope.aliyuncs.com/api/v1"),
+        "zhipu" => Some("https://open.bigmodel.cn/api/paas/v4"),
+        _ => None,
+    }
+}
+
+/// 提供商实际请求的地址，优先使用配置中的 endpoint
+pub fn provider_endpoint(
+    provider: &LLMProvider,
+    llm_provider_config: &[LLMProviderConfig],
+) -> Option<String> {
+    llm_provider_config
+        .iter()
+        .find(|config| config.name == "endpoint")
+        .map(|config| config.value.trim())
+        .filter(|endpoint| !endpoint.is_empty())
+        .or_else(|| default_endpoint(&provider.api_type))
+        .map(|endpoint| endpoint.to_string())
+}
+
+/// 地址是否指向本机：localhost、*.localhost、回环地址和 0.0.0.0，局域网地址不算本机
+pub fn is_local_endpoint(endpoint: &str) -> bool {
+    let Ok(url) = Url::parse(endpoint) else {
+        return false;
+    };
+    match url.host_str() {
+        Some(host) => {
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            match host.parse::<IpAddr>() {
+                Ok(ip) => ip.is_loopback() || ip.is_unspecified(),
+                Err(_) => {
+                    let host = host.to_lowercase();
+                    host == "localhost" || host.ends_with(".localhost")
+                }
+            }
+        }
+        None => false,
+    }
+}
+
+/// 离线模式下允许请求的提供商：模拟提供商和地址指向本机的提供商
+pub fn is_local_provider(
+    provider: &LLMProvider,
+    llm_provider_config: &[LLMProviderConfig],
+) -> bool {
+    provider.api_type == "mock"
+        || provider_endpoint(provider, llm_provider_config)
+            .is_some_and(|endpoint| is_local_endpoint(&endpoint))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider(api_type: &str) -> LLMProvider {
+        LLMProvider {
+            id: 1,
+            name: api_type.to_string(),
+            api_type: api_type.to_string(),
+            description: String::new(),
+            is_official: false,
+            is_enabled: true,
+        }
+    }
+
+    fn endpoint(value: &str) -> Vec<LLMProviderConfig> {
+        vec![LLMProviderConfig {
+            id: 1,
+            name: "endpoint".to_string(),
+            llm_provider_id: 1,
+            value: value.to_string(),
+            append_location: "header".to_string(),
+            is_addition: false,
+        }]
+    }
+
+    #[test]
+    fn test_is_local_endpoint() {
+        assert!(is_local_endpoint("http://localhost:11434"));
+        assert!(is_local_endpoint("http://LOCALHOST/v1"));
+        assert!(is_local_endpoint("http://ollama.localhost:8080"));
+        assert!(is_local_endpoint("http://127.0.0.1:1234/v1"));
+        assert!(is_local_endpoint("http://[::1]:8000"));
+        assert!(is_local_endpoint("http://0.0.0.0:8000"));
+        assert!(!is_local_endpoint("http://192.168.1.10:11434"));
+        assert!(!is_local_endpoint("https://api.openai.com/v1"));
+        assert!(!is_local_endpoint("https://localhost.example.com"));
+        assert!(!is_local_endpoint("not a url"));
+    }
+
+    #[test]
+    fn test_is_local_provider() {
+        assert!(is_local_provider(&provider("ollama"), &[]));
+        assert!(is_local_provider(&provider("mock"), &[]));
+        assert!(!is_local_provider(&provider("openai_api"), &[]));
+        assert!(is_local_provider(
+            &provider("openai_api"),
+            &endpoint("http://127.0.0.1:1234/v1")
+        ));
+        assert!(!is_local_provider(
+            &provider("ollama"),
+            &endpoint("http://gpu-server:11434")
+        ));
+        assert!(!is_local_provider(&provider("unknown"), &[]));
+    }
+}
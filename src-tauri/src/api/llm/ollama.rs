@@ -1,22 +1,26 @@
 use crate::{
     api::llm_api::LlmModel,
-    db::{
-        assistant_db::AssistantModelConfig,
-        conversation_db::{AttachmentType, MessageAttachment},
-        llm_db::LLMProviderConfig,
-    },
+    db::{assistant_db::AssistantModelConfig, llm_db::LLMProviderConfig},
 };
 use anyhow::{anyhow, Result};
 use futures::{future::BoxFuture, StreamExt};
 use regex::Regex;
-use reqwest::{header::AUTHORIZATION, Client};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::collections::HashMap;
 use tokio::{select, sync::mpsc};
 use tokio_util::sync::CancellationToken;
 
-use super::ModelProvider;
+use super::{
+    chat_message::ChatMessage,
+    check_response_status, client_builder, finish_reason_event,
+    mock::FixtureRecorder,
+    request_builder::{apply_request_headers, AuthScheme},
+    request_context::RequestContext,
+    send_request,
+    sse::JsonLinesDecoder,
+    ChatResponse, ModelProvider, ProviderClient, TokenUsage,
+};
 
 #[derive(Serialize, Deserialize, Debug)]
 struct ModelsResponse {
@@ -45,122 +49,57 @@ struct Details {
 
 pub struct OllamaProvider {
     llm_provider_config: Vec<LLMProviderConfig>,
-    client: Client,
+    client: ProviderClient,
 }
 
 impl ModelProvider for OllamaProvider {
     fn new(llm_provider_config: Vec<LLMProviderConfig>) -> Self {
         OllamaProvider {
             llm_provider_config,
-            client: Client::new(),
+            client: ProviderClient::new(client_builder()),
         }
     }
 
     fn chat(
         &self,
         _message_id: i64,
-        messages: Vec<(String, String, Vec<MessageAttachment>)>,
+        messages: Vec<ChatMessage>,
         model_config: Vec<AssistantModelConfig>,
         cancel_token: CancellationToken,
-    ) -> BoxFuture<'static, Result<String>> {
+    ) -> BoxFuture<'static, Result<ChatResponse>> {
         let config = self.llm_provider_config.clone();
         let client = self.client.clone();
 
         Box::pin(async move {
-            let config_map: HashMap<String, String> =
-                config.into_iter().map(|c| (c.name, c.value)).collect();
-
-            let default_endpoint = &"http://localhost:11434".to_string();
-            let endpoint = config_map
-                .get("endpoint")
-                .unwrap_or(default_endpoint)
-                .trim_end_matches('/');
-            let url = format!("{}/api/chat", endpoint);
-            let api_key = config_map.get("api_key").unwrap_or(&"".to_string()).clone();
-
-            let json_messages = messages
-                .iter()
-                .map(|(message_type, content, attachment_list)| {
-                    if attachment_list.len() > 0 {
-                        let images = attachment_list
-                            .iter()
-                            .filter(|a| a.attachment_type == AttachmentType::Image)
-                            .map(|a| {
-                                let attachment_content = a.attachment_content.clone().unwrap();
-                                let re =
-                                    Regex::new(r"data:(?P<media_type>[^;]+);base64,(?P<data>.+)")
-                                        .unwrap();
-                                let caps = re.captures(&attachment_content).unwrap();
-                                let data = caps.name("data").unwrap().as_str();
-
-                                data.to_string()
-                            })
-                            .collect::<Vec<String>>();
-                        json!({
-                            "role": message_type,
-                            "content": content,
-                            "images": images,
-                        })
-                    } else {
-                        json!({
-                            "role": message_type,
-                            "content": content
-                        })
-                    }
-                })
-                .collect::<Vec<serde_json::Value>>();
-
-            let model_config_map = model_config
-                .iter()
-                .filter_map(|config| {
-                    config
-                        .value
-                        .as_ref()
-                        .map(|value| (config.name.clone(), value.clone()))
-                })
-                .collect::<HashMap<String, String>>();
-            let temperature = model_config_map
-                .get("temperature")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(0.75);
-            let top_p = model_config_map
-                .get("top_p")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(1.0);
-            let max_tokens = model_config_map
-                .get("max_tokens")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(2000);
-
-            let model = model_config_map.get("model"); // Assuming the first model config is the one to use
-
-            let body = json!({
-                "model": model,
-                "temperature": temperature,
-                "top_p": top_p,
-                "max_tokens": max_tokens,
-                "messages": json_messages,
-                "stream": false
-            });
+            let client = client.get()?;
+            let context = RequestContext::new(config, &model_config, messages);
+            let url = context.url("http://localhost:11434", "/api/chat");
+            let body = build_body(&context, false);
             println!("ollama chat: {:?}", body);
 
-            let request = client
-                .post(&url)
-                .header(AUTHORIZATION, &format!("Bearer {}", api_key))
-                .json(&body);
+            let request =
+                apply_request_headers(client.post(&url), &context.config_map, AuthScheme::Bearer)?
+                    .json(&body);
 
             let response = tokio::select! {
-                response = request.send() => response?,
+                response = send_request(request) => response?,
                 _ = cancel_token.cancelled() => return Err(anyhow!("Request cancelled")),
             };
 
+            let response = check_response_status(response).await?;
+
             let json_response = tokio::select! {
                 json = response.json::<serde_json::Value>() => json?,
                 _ = cancel_token.cancelled() => return Err(anyhow!("Request cancelled")),
             };
 
             if let Some(content) = json_response["message"]["content"].as_str() {
-                Ok(content.to_string())
+                Ok(ChatResponse {
+                    content: content.to_string(),
+                    finish_reason: json_response["done_reason"].as_str().map(|s| s.to_string()),
+                    usage: token_usage(&json_response),
+                    ..Default::default()
+                })
             } else {
                 Err(anyhow!("Failed to get content from response"))
             }
@@ -170,7 +109,7 @@ impl ModelProvider for OllamaProvider {
     fn chat_stream(
         &self,
         message_id: i64,
-        messages: Vec<(String, String, Vec<MessageAttachment>)>,
+        messages: Vec<ChatMessage>,
         model_config: Vec<AssistantModelConfig>,
         tx: mpsc::Sender<(i64, String, bool)>,
         cancel_token: CancellationToken,
@@ -179,120 +118,56 @@ impl ModelProvider for OllamaProvider {
         let client = self.client.clone();
 
         Box::pin(async move {
-            let config_map: HashMap<String, String> =
-                config.into_iter().map(|c| (c.name, c.value)).collect();
-
-            let default_endpoint = &"http://localhost:11434".to_string();
-            let endpoint = config_map
-                .get("endpoint")
-                .unwrap_or(default_endpoint)
-                .trim_end_matches('/');
-            let url = format!("{}/api/chat", endpoint);
-            println!("url: {}", url);
-            let api_key = config_map.get("api_key").unwrap_or(&"".to_string()).clone();
-
-            let json_messages = messages
-                .iter()
-                .map(|(message_type, content, attachment_list)| {
-                    if attachment_list.len() > 0 {
-                        let images = attachment_list
-                            .iter()
-                            .filter(|a| a.attachment_type == AttachmentType::Image)
-                            .map(|a| {
-                                let attachment_content = a.attachment_content.clone().unwrap();
-                                let re =
-                                    Regex::new(r"data:(?P<media_type>[^;]+);base64,(?P<data>.+)")
-                                        .unwrap();
-                                let caps = re.captures(&attachment_content).unwrap();
-                                let data = caps.name("data").unwrap().as_str();
-
-                                data.to_string()
-                            })
-                            .collect::<Vec<String>>();
-                        json!({
-                            "role": message_type,
-                            "content": content,
-                            "images": images,
-                        })
-                    } else {
-                        json!({
-                            "role": message_type,
-                            "content": content
-                        })
-                    }
-                })
-                .collect::<Vec<serde_json::Value>>();
-
-            let model_config_map = model_config
-                .iter()
-                .filter_map(|config| {
-                    config
-                        .value
-                        .as_ref()
-                        .map(|value| (config.name.clone(), value.clone()))
-                })
-                .collect::<HashMap<String, String>>();
-            let temperature = model_config_map
-                .get("temperature")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(0.75);
-            let top_p = model_config_map
-                .get("top_p")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(1.0);
-            let max_tokens = model_config_map
-                .get("max_tokens")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(2000);
-
-            let model = model_config_map.get("model"); // Assuming the first model config is the one to use
-
-            let body = json!({
-                "model": model,
-                "temperature": temperature,
-                "top_p": top_p,
-                "max_tokens": max_tokens,
-                "messages": json_messages,
-                "stream": true
-            });
+            let client = client.get()?;
+            let context = RequestContext::new(config, &model_config, messages);
+            let url = context.url("http://localhost:11434", "/api/chat");
+            let body = build_body(&context, true);
 
             println!("ollama chat stream: {:?}", body);
 
-            let request = client
-                .post(&url)
-                .header(AUTHORIZATION, &format!("Bearer {}", api_key))
-                .json(&body);
+            let request =
+                apply_request_headers(client.post(&url), &context.config_map, AuthScheme::Bearer)?
+                    .json(&body);
 
             println!("request: {:?}", request);
 
             let response = tokio::select! {
-                response = request.send() => response?,
+                response = send_request(request) => response?,
                 _ = cancel_token.cancelled() => return Err(anyhow!("Request cancelled")),
             };
 
+            let response = check_response_status(response).await?;
+
             let mut stream = response.bytes_stream();
             let mut full_text = String::new();
+            let mut decoder = JsonLinesDecoder::new();
+            let mut recorder = FixtureRecorder::from_config(&context.config_map);
 
             loop {
                 select! {
                     chunk = stream.next() => {
                         match chunk {
                             Some(Ok(chunk)) => {
+                                if let Some(recorder) = recorder.as_mut() {
+                                    recorder.record(&chunk);
+                                }
                                 let text = String::from_utf8_lossy(&chunk);
                                 println!("text: {}", text.clone());
 
-                                if let Ok(response) = serde_json::from_str::<serde_json::Value>(text.to_string().as_str()) {
-                                    if let Some(delta) = response["message"]["content"].as_str() {
-                                        full_text.push_str(delta);
-                                        tx.send((message_id, full_text.clone(), response["done"].as_bool().unwrap())).await?;
-                                    }
-                                    if response["done"].as_bool().unwrap_or(false) {
-                                        break;
+                                // 一个网络包里可能有多行，也可能只有半行
+                                for response in decoder.push(&chunk) {
+                                    if send_stream_value(&response, message_id, &mut full_text, &tx).await? {
+                                        return Ok(());
                                     }
                                 }
                             },
                             Some(Err(e)) => return Err(anyhow!(e)),
-                            None => break,
+                            None => {
+                                for response in decoder.finish() {
+                                    send_stream_value(&response, message_id, &mut full_text, &tx).await?;
+                                }
+                                break;
+                            },
                         }
                     },
                     _ = cancel_token.cancelled() => {
@@ -311,6 +186,7 @@ impl ModelProvider for OllamaProvider {
         let client = self.client.clone();
 
         Box::pin(async move {
+            let client = client.get()?;
             let mut result = Vec::new();
 
             let config_map: HashMap<String, String> =
@@ -322,13 +198,9 @@ impl ModelProvider for OllamaProvider {
                 .unwrap_or(default_endpoint)
                 .trim_end_matches('/');
             let url = format!("{}/api/tags", endpoint);
-            let api_key = config_map.get("api_key").unwrap_or(&"".to_string()).clone();
 
-            let response = client
-                .get(&url)
-                .header(AUTHORIZATION, &format!("Bearer {}", api_key))
-                .send()
-                .await?;
+            let request = apply_request_headers(client.get(&url), &config_map, AuthScheme::Bearer)?;
+            let response = send_request(request).await?;
 
             let models_response: ModelsResponse = response.json().await?;
 
@@ -347,6 +219,7 @@ impl ModelProvider for OllamaProvider {
                     vision_support: false, // Set this according to your needs
                     audio_support: false,  // Set this according to your needs
                     video_support: false,  // Set this according to your needs
+                    is_enabled: true,
                 };
                 result.push(llm_model);
             }
@@ -355,3 +228,84 @@ impl ModelProvider for OllamaProvider {
         })
     }
 }
+
+// 处理一行流式响应，返回是否已经结束
+async fn send_stream_value(
+    response: &serde_json::Value,
+    message_id: i64,
+    full_text: &mut String,
+    tx: &mpsc::Sender<(i64, String, bool)>,
+) -> Result<bool> {
+    let done = response["done"].as_bool().unwrap_or(false);
+    if let Some(done_reason) = response["done_reason"].as_str() {
+        tx.send((message_id, finish_reason_event(done_reason), false))
+            .await?;
+    }
+    if let Some(delta) = response["message"]["content"].as_str() {
+        full_text.push_str(delta);
+        tx.send((message_id, full_text.clone(), done)).await?;
+    }
+    Ok(done)
+}
+
+// 结束时的响应中 prompt_eval_count 是输入的 token 数量，eval_count 是生成的 token 数量
+fn token_usage(response: &serde_json::Value) -> Option<TokenUsage> {
+    if response["prompt_eval_count"].is_null() && response["eval_count"].is_null() {
+        return None;
+    }
+    let mut usage = TokenUsage::default();
+    usage.merge(&TokenUsage {
+        prompt_tokens: response["prompt_eval_count"].as_u64().map(|v| v as u32),
+        completion_tokens: response["eval_count"].as_u64().map(|v| v as u32),
+        total_tokens: None,
+    });
+    Some(usage)
+}
+
+// chat 和 chat_stream 共用的请求体
+fn build_body(context: &RequestContext, stream: bool) -> serde_json::Value {
+    let params = context.params();
+    let json_messages = context
+        .messages
+        .iter()
+        .map(|message| {
+            if message.has_attachments() {
+                let images = message
+                    .images()
+                    .map(|a| {
+                        let attachment_content = a.attachment_content.clone().unwrap();
+                        let re =
+                            Regex::new(r"data:(?P<media_type>[^;]+);base64,(?P<data>.+)").unwrap();
+                        let caps = re.captures(&attachment_content).unwrap();
+                        let data = caps.name("data").unwrap().as_str();
+
+                        data.to_string()
+                    })
+                    .collect::<Vec<String>>();
+                json!({
+                    "role": message.role,
+                    "content": message.text(),
+                    "images": images,
+                })
+            } else {
+                json!({
+                    "role": message.role,
+                    "content": message.text()
+                })
+            }
+        })
+        .collect::<Vec<serde_json::Value>>();
+
+    let mut body = json!({
+        "model": params.model,
+        "temperature": params.temperature,
+        "top_p": params.top_p,
+        "max_tokens": params.max_tokens,
+        "messages": json_messages,
+        "stream": stream
+    });
+    if let Some(stop) = params.stop {
+        body["options"] = json!({ "stop": stop });
+    }
+    body
+}
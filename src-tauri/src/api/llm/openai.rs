@@ -1,23 +1,29 @@
-use std::{collections::HashMap, time::Duration};
+use std::{collections::HashMap, path::Path};
 
 use anyhow::{bail, Result};
-use reqwest::{
-    header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION},
-    Client,
-};
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use tokio_util::sync::CancellationToken;
 
-use crate::{
-    api::llm_api::LlmModel,
-    db::{
-        conversation_db::{AttachmentType, MessageAttachment},
-        llm_db::LLMProviderConfig,
+use crate::{api::llm_api::LlmModel, db::llm_db::LLMProviderConfig};
+
+use super::{
+    capability::{get_model_capability, set_clamped_param},
+    chat_message::{ChatMessage, Role},
+    check_response_status, finish_reason_event,
+    mock::FixtureRecorder,
+    openai_responses::{self, is_responses_mode},
+    request_builder::apply_openai_request_headers,
+    request_context::RequestContext,
+    send_request, shared_client,
+    sse::{SseDecoder, SseEvent},
+    tool_call::{
+        message_tool_calls, parse_openai_tool_calls, parse_tool_result, send_tool_calls,
+        ToolCallAccumulator,
     },
+    usage_event, ChatResponse, ModelProvider, ProviderClient, TokenUsage,
 };
-
-use super::ModelProvider;
 use futures::StreamExt;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -37,7 +43,7 @@ struct Model {
 
 pub struct OpenAIProvider {
     llm_provider_config: Vec<LLMProviderConfig>,
-    client: Client,
+    client: ProviderClient,
 }
 
 impl ModelProvider for OpenAIProvider {
@@ -47,114 +53,47 @@ impl ModelProvider for OpenAIProvider {
     {
         OpenAIProvider {
             llm_provider_config,
-            client: Client::builder()
-                .timeout(Duration::from_secs(300))
-                .build()
-                .unwrap(),
+            client: shared_client(),
         }
     }
 
     fn chat(
         &self,
         _message_id: i64,
-        messages: Vec<(String, String, Vec<MessageAttachment>)>,
+        messages: Vec<ChatMessage>,
         model_config: Vec<crate::db::assistant_db::AssistantModelConfig>,
         cancel_token: CancellationToken,
-    ) -> futures::future::BoxFuture<'static, Result<String>> {
+    ) -> futures::future::BoxFuture<'static, Result<ChatResponse>> {
         let config = self.llm_provider_config.clone();
         let client = self.client.clone();
 
         Box::pin(async move {
-            let config_map: HashMap<String, String> =
-                config.into_iter().map(|c| (c.name, c.value)).collect();
-
-            let default_endpoint = &"https://api.openai.com/v1".to_string();
-            let endpoint = config_map
-                .get("endpoint")
-                .unwrap_or(default_endpoint)
-                .trim_end_matches('/');
-            let url = format!("{}/chat/completions", endpoint);
-            let api_key = config_map.get("api_key").unwrap().clone();
-
-            let json_messages = messages
-                .iter()
-                .map(|(message_type, content, attachment_list)| {
-                    if attachment_list.len() > 0 {
-                        let mut content_array = vec![json!({
-                            "type": "text",
-                            "text": content
-                        })];
-                        let images = attachment_list
-                            .iter()
-                            .filter(|a| a.attachment_type == AttachmentType::Image)
-                            .map(|a| {
-                                json!({
-                                    "type": "image_url",
-                                    "image_url": {
-                                        "url": a.attachment_content.clone().unwrap()
-                                    }
-                                })
-                            })
-                            .collect::<Vec<Value>>();
-                        content_array.extend(images);
-
-                        json!({
-                            "role": message_type,
-                            "content": content_array,
-                        })
-                    } else {
-                        json!({
-                            "role": message_type,
-                            "content": content
-                        })
-                    }
-                })
-                .collect::<Vec<Value>>();
-
-            let model_config_map = model_config
-                .iter()
-                .filter_map(|config| {
-                    config
-                        .value
-                        .as_ref()
-                        .map(|value| (config.name.clone(), value.clone()))
-                })
-                .collect::<HashMap<String, String>>();
-            let temperature = model_config_map
-                .get("temperature")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(0.75);
-            let top_p = model_config_map
-                .get("top_p")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(1.0);
-            let max_tokens = model_config_map
-                .get("max_tokens")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(2000);
-
-            let model = model_config_map.get("model"); // Assuming the first model config is the one to use
-
-            let body = json!({
-                "model": model,
-                "temperature": temperature,
-                "top_p": top_p,
-                "max_tokens": max_tokens,
-                "messages": json_messages,
-                "stream": false
-            });
+            let client = client.get()?;
+            let context = RequestContext::new(config, &model_config, messages);
+            let responses_mode = is_responses_mode(&context);
+            let (url, body) = if responses_mode {
+                (
+                    context.url("https://api.openai.com/v1", "/responses"),
+                    openai_responses::build_body(&context, false),
+                )
+            } else {
+                (
+                    context.url("https://api.openai.com/v1", "/chat/completions"),
+                    build_body(&context, false),
+                )
+            };
             println!("openai chat: {:?}", body);
 
-            let request = client
-                .post(&url)
-                .header(AUTHORIZATION, &format!("Bearer {}", api_key))
-                .json(&body);
+            let request =
+                apply_openai_request_headers(client.post(&url), &context.config_map)?.json(&body);
 
             let response = tokio::select! {
-                response = request.send() => response?,
+                response = send_request(request) => response?,
                 _ = cancel_token.cancelled() => bail!("Request cancelled"),
             };
 
+            let response = check_response_status(response).await?;
+
             let json_response = tokio::select! {
                 json = response.json::<serde_json::Value>() => json?,
                 _ = cancel_token.cancelled() => bail!("Request cancelled"),
@@ -162,18 +101,30 @@ impl ModelProvider for OpenAIProvider {
 
             println!("openai chat response: {:?}", json_response.clone());
 
-            if let Some(content) = json_response["choices"][0]["message"]["content"].as_str() {
-                Ok(content.to_string())
-            } else {
-                bail!("Failed to get content from response")
+            if responses_mode {
+                return openai_responses::parse_response(&json_response);
             }
+            let message = &json_response["choices"][0]["message"];
+            // 请求调用工具时 content 可能为 null
+            let tool_calls = parse_openai_tool_calls(message);
+            if !message["content"].is_string() && tool_calls.is_empty() {
+                bail!("Failed to get content from response");
+            }
+            Ok(ChatResponse {
+                content: message["content"].as_str().unwrap_or_default().to_string(),
+                finish_reason: json_response["choices"][0]["finish_reason"]
+                    .as_str()
+                    .map(|s| s.to_string()),
+                tool_calls,
+                usage: token_usage(&json_response),
+            })
         })
     }
 
     fn chat_stream(
         &self,
         message_id: i64,
-        messages: Vec<(String, String, Vec<MessageAttachment>)>,
+        messages: Vec<ChatMessage>,
         model_config: Vec<crate::db::assistant_db::AssistantModelConfig>,
         tx: tokio::sync::mpsc::Sender<(i64, String, bool)>,
         cancel_token: CancellationToken,
@@ -182,137 +133,78 @@ impl ModelProvider for OpenAIProvider {
         let client = self.client.clone();
 
         Box::pin(async move {
-            let config_map: HashMap<String, String> =
-                config.into_iter().map(|c| (c.name, c.value)).collect();
-
-            let default_endpoint = &"https://api.openai.com/v1".to_string();
-            let endpoint = config_map
-                .get("endpoint")
-                .unwrap_or(default_endpoint)
-                .trim_end_matches('/');
-            let url = format!("{}/chat/completions", endpoint);
-            let api_key = config_map.get("api_key").unwrap().clone();
-
-            let json_messages = messages
-                .iter()
-                .map(|(message_type, content, attachment_list)| {
-                    if attachment_list.len() > 0 {
-                        let mut content_array = vec![json!({
-                            "type": "text",
-                            "text": content
-                        })];
-                        let images = attachment_list
-                            .iter()
-                            .filter(|a| a.attachment_type == AttachmentType::Image)
-                            .map(|a| {
-                                json!({
-                                    "type": "image_url",
-                                    "image_url": {
-                                        "url": a.attachment_content.clone().unwrap()
-                                    }
-                                })
-                            })
-                            .collect::<Vec<Value>>();
-                        content_array.extend(images);
-
-                        json!({
-                            "role": message_type,
-                            "content": content_array,
-                        })
-                    } else {
-                        json!({
-                            "role": message_type,
-                            "content": content
-                        })
-                    }
-                })
-                .collect::<Vec<serde_json::Value>>();
-
-            let model_config_map = model_config
-                .iter()
-                .filter_map(|config| {
-                    config
-                        .value
-                        .as_ref()
-                        .map(|value| (config.name.clone(), value.clone()))
-                })
-                .collect::<HashMap<String, String>>();
-            let temperature = model_config_map
-                .get("temperature")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(0.75);
-            let top_p = model_config_map
-                .get("top_p")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(1.0);
-            let max_tokens = model_config_map
-                .get("max_tokens")
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(2000);
-
-            let model = model_config_map.get("model"); // Assuming the first model config is the one to use
-
-            let body = json!({
-                "model": model,
-                "temperature": temperature,
-                "top_p": top_p,
-                "max_tokens": max_tokens,
-                "messages": json_messages,
-                "stream": true
-            });
+            let client = client.get()?;
+            let context = RequestContext::new(config, &model_config, messages);
+            let responses_mode = is_responses_mode(&context);
+            let (url, body) = if responses_mode {
+                (
+                    context.url("https://api.openai.com/v1", "/responses"),
+                    openai_responses::build_body(&context, true),
+                )
+            } else {
+                (
+                    context.url("https://api.openai.com/v1", "/chat/completions"),
+                    build_body(&context, true),
+                )
+            };
             println!("openai chat stream url: {} body: {:?}", url, body);
 
-            let request = client
-                .post(&url)
-                .header(AUTHORIZATION, &format!("Bearer {}", api_key))
-                .json(&body);
+            let request =
+                apply_openai_request_headers(client.post(&url), &context.config_map)?.json(&body);
 
             let response = tokio::select! {
-                response = request.send() => response?,
+                response = send_request(request) => response?,
                 _ = cancel_token.cancelled() => bail!("Request cancelled"),
             };
 
+            let response = check_response_status(response).await?;
+
             let mut stream = response.bytes_stream();
             let mut full_text = String::new();
-            let mut buffer = Vec::new();
+            let mut decoder = SseDecoder::new();
+            let mut recorder = FixtureRecorder::from_config(&context.config_map);
+            let mut tool_calls = ToolCallAccumulator::default();
+            // 收到 finish_reason 或 [DONE] 才算正常结束，否则是连接中断
+            let mut finished = false;
 
             loop {
                 tokio::select! {
                     chunk = stream.next() => {
                         match chunk {
                             Some(Ok(chunk)) => {
+                                if let Some(recorder) = recorder.as_mut() {
+                                    recorder.record(&chunk);
+                                }
                                 let text = String::from_utf8_lossy(&chunk);
                                 println!("openai chat stream text: {}", text);
-                                buffer.extend_from_slice(&chunk);
-
-                                // 处理粘包和拆包
-                                while let Some(pos) = buffer.windows(2).position(|w| w == b"\n\n") {
-                                    let chunk_data = buffer.drain(..=pos + 1).collect::<Vec<_>>();
-                                    let chunk_str = String::from_utf8_lossy(&chunk_data);
-
-                                    if chunk_str.starts_with("data: ") {
-                                        let json_str = &chunk_str["data: ".len()..];
-                                        if json_str.trim() == "[DONE]" {
-                                            tx.send((message_id, full_text.clone(), true)).await?;
-                                            return Ok(());
-                                        }
-
-                                        if let Ok(chunk_response) =
-                                            serde_json::from_str::<serde_json::Value>(json_str)
-                                        {
-                                            if let Some(delta) =
-                                                chunk_response["choices"][0]["delta"]["content"].as_str()
-                                            {
-                                                full_text.push_str(delta);
-                                                tx.send((message_id, full_text.clone(), false)).await?;
-                                            }
-                                        }
+
+                                for event in decoder.push(&chunk) {
+                                    if event.is_done() {
+                                        send_tool_calls(&mut tool_calls, message_id, &tx).await?;
+                                        tx.send((message_id, full_text.clone(), true)).await?;
+                                        return Ok(());
                                     }
+                                    push_tool_call_event(&mut tool_calls, &event, responses_mode);
+                                    finished |= send_event(&event, responses_mode, message_id, &mut full_text, &tx).await?;
                                 }
                             }
                             Some(Err(e)) => bail!(e),
                             None => {
                                 println!("openai chat stream end");
+                                for event in decoder.finish() {
+                                    if event.is_done() {
+                                        finished = true;
+                                    } else {
+                                        push_tool_call_event(&mut tool_calls, &event, responses_mode);
+                                        finished |= send_event(&event, responses_mode, message_id, &mut full_text, &tx).await?;
+                                    }
+                                }
+                                if !finished {
+                                    // 不发送结束标记，由接收方按连接中断处理
+                                    println!("openai chat stream interrupted");
+                                    return Ok(());
+                                }
+                                send_tool_calls(&mut tool_calls, message_id, &tx).await?;
                                 tx.send((message_id, full_text.clone(), true)).await?;
                                 return Ok(());
                             },
@@ -334,6 +226,7 @@ impl ModelProvider for OpenAIProvider {
         let client = self.client.clone();
 
         Box::pin(async move {
+            let client = client.get()?;
             let mut result = Vec::new();
 
             let config_map: HashMap<String, String> =
@@ -346,24 +239,19 @@ impl ModelProvider for OpenAIProvider {
                 .unwrap_or(default_endpoint)
                 .trim_end_matches('/');
             let url = format!("{}/models", endpoint);
-            let api_key = config_map.get("api_key").unwrap().clone();
             println!("OpenAI models endpoint : {}", url);
 
             let mut headers = HeaderMap::new();
             headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
-            headers.insert(
-                AUTHORIZATION,
-                HeaderValue::from_str(&format!("Bearer {}", api_key)).unwrap(),
-            );
-
-            let req = client
-                .request("GET".parse().unwrap(), url)
-                .headers(headers)
-                .build();
-            println!("req: {:?}", req);
-
-            let response = client.execute(req.unwrap());
-            let res2 = response.await;
+
+            let request = apply_openai_request_headers(
+                client.request("GET".parse().unwrap(), url),
+                &config_map,
+            )?
+            .headers(headers);
+            println!("request: {:?}", request);
+
+            let res2 = send_request(request).await;
             // println!("response: {:?}", res2.unwrap().text().await.unwrap());
 
             let models_response: ModelsResponse = res2?.json().await?;
@@ -384,6 +272,7 @@ impl ModelProvider for OpenAIProvider {
                     vision_support: false, // Set this according to your needs
                     audio_support: false,  // Set this according to your needs
                     video_support: false,  // Set this according to your needs
+                    is_enabled: true,
                 };
                 result.push(llm_model);
             }
@@ -392,3 +281,350 @@ impl ModelProvider for OpenAIProvider {
         })
     }
 }
+
+// Responses API 的服务端工具由提供商执行，只有 Chat Completions 的工具调用需要在本地执行
+fn push_tool_call_event(
+    tool_calls: &mut ToolCallAccumulator,
+    event: &SseEvent,
+    responses_mode: bool,
+) {
+    if responses_mode {
+        return;
+    }
+    if let Some(value) = event.json() {
+        tool_calls.push_openai_event(&value);
+    }
+}
+
+// Responses API 的事件格式不同，按模式分别处理
+async fn send_event(
+    event: &SseEvent,
+    responses_mode: bool,
+    message_id: i64,
+    full_text: &mut String,
+    tx: &tokio::sync::mpsc::Sender<(i64, String, bool)>,
+) -> Result<bool> {
+    if responses_mode {
+        openai_responses::send_stream_event(event, message_id, full_text, tx).await
+    } else {
+        send_stream_event(event, message_id, full_text, tx).await
+    }
+}
+
+// 把一条流式事件中的增量内容和结束原因发送给接收端，返回是否收到了 finish_reason
+pub(super) async fn send_stream_event(
+    event: &SseEvent,
+    message_id: i64,
+    full_text: &mut String,
+    tx: &tokio::sync::mpsc::Sender<(i64, String, bool)>,
+) -> Result<bool> {
+    let Some(chunk_response) = event.json() else {
+        return Ok(false);
+    };
+    let mut finished = false;
+    if let Some(delta) = chunk_response["choices"][0]["delta"]["content"].as_str() {
+        full_text.push_str(delta);
+        tx.send((message_id, full_text.clone(), false)).await?;
+    }
+    if let Some(finish_reason) = chunk_response["choices"][0]["finish_reason"].as_str() {
+        tx.send((message_id, finish_reason_event(finish_reason), false))
+            .await?;
+        finished = true;
+    }
+    // 开启 include_usage 后，最后一个数据块的 choices 为空，只包含整次请求的用量
+    if let Some(usage) = token_usage(&chunk_response) {
+        tx.send((message_id, usage_event(&usage), false)).await?;
+    }
+    Ok(finished)
+}
+
+/// OpenAI 格式的响应中的用量，智谱等兼容 OpenAI 格式的提供商共用
+pub(super) fn token_usage(response: &Value) -> Option<TokenUsage> {
+    let usage = response.get("usage").filter(|usage| usage.is_object())?;
+    Some(TokenUsage {
+        prompt_tokens: usage["prompt_tokens"].as_u64().map(|v| v as u32),
+        completion_tokens: usage["completion_tokens"].as_u64().map(|v| v as u32),
+        total_tokens: usage["total_tokens"].as_u64().map(|v| v as u32),
+    })
+}
+
+// 构建 OpenAI 格式的消息列表，图片超过单次请求上限时保留最近的图片，其余的用文字说明代替
+// chat 和 chat_stream 共用的请求体
+fn build_body(context: &RequestContext, stream: bool) -> Value {
+    let params = context.params();
+    let image_detail = context
+        .model_config_map
+        .get("image_detail")
+        .map(|v| v.as_str())
+        .unwrap_or("auto");
+    let max_images = context.model_config("max_images").unwrap_or_else(|| {
+        let capability = get_model_capability("openai_api", params.model.as_deref().unwrap_or(""));
+        if capability.vision_support {
+            capability.max_images
+        } else {
+            0
+        }
+    });
+    let mut body = json!({
+        "model": params.model,
+        "max_tokens": params.max_tokens,
+        "messages": build_messages(&context.messages, image_detail, max_images),
+        "stream": stream
+    });
+    // o 系列推理模型不接受 temperature 和 top_p，请求中不能带上
+    let model_code = params.model.as_deref().unwrap_or("");
+    set_clamped_param(
+        &mut body,
+        "temperature",
+        "openai_api",
+        model_code,
+        "temperature",
+        params.temperature,
+    );
+    set_clamped_param(
+        &mut body,
+        "top_p",
+        "openai_api",
+        model_code,
+        "top_p",
+        params.top_p,
+    );
+    if let Some(stop) = params.stop {
+        body["stop"] = json!(stop);
+    }
+    let tools = context.tools();
+    if !tools.is_empty() {
+        body["tools"] = json!(tools
+            .iter()
+            .map(|tool| {
+                json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool.name,
+                        "description": tool.description,
+                        "parameters": tool.parameters,
+                    }
+                })
+            })
+            .collect::<Vec<Value>>());
+    }
+    if stream && stream_usage_enabled(context) {
+        body["stream_options"] = json!({ "include_usage": true });
+    }
+    body
+}
+
+// 已知支持 stream_options 的服务，其他兼容 OpenAI 的服务收到未知字段时可能直接返回 400
+const STREAM_USAGE_HOSTS: &[&str] = &[
+    "api.openai.com",
+    "api.deepseek.com",
+    "dashscope.aliyuncs.com",
+    "openrouter.ai",
+];
+
+// 提供商配置中的 stream_usage 优先，没有配置时只对已知支持的服务开启
+fn stream_usage_enabled(context: &RequestContext) -> bool {
+    if let Some(value) = context.config_map.get("stream_usage") {
+        return value == "true";
+    }
+    let url = context.url("https://api.openai.com/v1", "");
+    let host = url
+        .split("://")
+        .nth(1)
+        .unwrap_or(&url)
+        .split(['/', ':'])
+        .next()
+        .unwrap_or("");
+    STREAM_USAGE_HOSTS.contains(&host)
+}
+
+fn build_messages(messages: &[ChatMessage], image_detail: &str, max_images: usize) -> Vec<Value> {
+    let mut remain_images = max_images;
+    let mut json_messages = messages
+        .iter()
+        .rev()
+        .map(|message| {
+            if message.has_attachments() {
+                let mut content_array = vec![json!({
+                    "type": "text",
+                    "text": message.text()
+                })];
+                let mut images = Vec::new();
+                let mut omitted_images = Vec::new();
+                let message_images = message.images().collect::<Vec<_>>();
+                for attachment in message_images.into_iter().rev() {
+                    if remain_images > 0 {
+                        remain_images -= 1;
+                        images.push(json!({
+                            "type": "image_url",
+                            "image_url": {
+                                "url": attachment.attachment_content.clone().unwrap(),
+                                "detail": image_detail
+                            }
+                        }));
+                    } else {
+                        omitted_images.push(
+                            attachment
+                                .attachment_url
+                                .as_deref()
+                                .and_then(|url| Path::new(url).file_name())
+                                .map(|name| name.to_string_lossy().to_string())
+                                .unwrap_or_else(|| "未命名图片".to_string()),
+                        );
+                    }
+                }
+                images.reverse();
+                content_array.extend(images);
+                if !omitted_images.is_empty() {
+                    omitted_images.reverse();
+                    content_array.push(json!({
+                        "type": "text",
+                        "text": format!(
+                            "[用户附带了 {} 张图片（{}），但由于当前模型单次请求最多支持 {} 张图片，这些图片没有发送]",
+                            omitted_images.len(),
+                            omitted_images.join(", "),
+                            max_images
+                        )
+                    }));
+                }
+
+                json!({
+                    "role": message.role,
+                    "content": content_array,
+                })
+            } else if let Some(tool_result) = parse_tool_result(message) {
+                json!({
+                    "role": Role::Tool,
+                    "tool_call_id": tool_result.tool_use_id,
+                    "content": tool_result.content,
+                })
+            } else {
+                let tool_calls = message_tool_calls(message);
+                if tool_calls.is_empty() {
+                    json!({
+                        "role": message.role,
+                        "content": message.text()
+                    })
+                } else {
+                    json!({
+                        "role": message.role,
+                        "content": message.text(),
+                        "tool_calls": tool_calls
+                            .iter()
+                            .map(|call| {
+                                json!({
+                                    "id": call.id,
+                                    "type": "function",
+                                    "function": {
+                                        "name": call.name,
+                                        "arguments": call.arguments.to_string(),
+                                    }
+                                })
+                            })
+                            .collect::<Vec<Value>>(),
+                    })
+                }
+            }
+        })
+        .collect::<Vec<Value>>();
+    json_messages.reverse();
+    json_messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::llm::tool_call::{tool_result_message, tool_use_message, ToolCall, ToolResult};
+
+    fn context(configs: &[(&str, &str)]) -> RequestContext {
+        let config = configs
+            .iter()
+            .map(|(name, value)| LLMProviderConfig {
+                id: 0,
+                name: name.to_string(),
+                llm_provider_id: 0,
+                value: value.to_string(),
+                append_location: String::new(),
+                is_addition: false,
+            })
+            .collect();
+        RequestContext::new(config, &[], vec![])
+    }
+
+    #[test]
+    fn test_build_body_tools() {
+        let mut context = context(&[]);
+        assert!(build_body(&context, false).get("tools").is_none());
+
+        context
+            .model_config_map
+            .insert("tools".to_string(), "calculate".to_string());
+        context.messages = vec![
+            ChatMessage::new(Role::User, "1+1 等于几"),
+            tool_use_message(
+                "",
+                &[ToolCall {
+                    id: "call_1".to_string(),
+                    name: "calculate".to_string(),
+                    arguments: json!({ "expression": "1+1" }),
+                }],
+            ),
+            tool_result_message(&ToolResult {
+                tool_use_id: "call_1".to_string(),
+                content: "2".to_string(),
+                is_error: None,
+            }),
+        ];
+        let body = build_body(&context, false);
+        assert_eq!(body["tools"][0]["function"]["name"], "calculate");
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages[1]["tool_calls"][0]["id"], "call_1");
+        assert_eq!(
+            messages[1]["tool_calls"][0]["function"]["arguments"],
+            r#"{"expression":"1+1"}"#
+        );
+        assert_eq!(messages[2]["role"], "tool");
+        assert_eq!(messages[2]["tool_call_id"], "call_1");
+        assert_eq!(messages[2]["content"], "2");
+    }
+
+    #[test]
+    fn test_build_body_reasoning_model_params() {
+        let mut context = context(&[]);
+        context
+            .model_config_map
+            .insert("model".to_string(), "gpt-4o".to_string());
+        let body = build_body(&context, false);
+        assert!(body["temperature"].is_number());
+        assert!(body["top_p"].is_number());
+
+        // o 系列推理模型不接受 temperature 和 top_p，请求体中没有这两个字段
+        context
+            .model_config_map
+            .insert("model".to_string(), "o3-mini".to_string());
+        let body = build_body(&context, false);
+        assert!(body.get("temperature").is_none());
+        assert!(body.get("top_p").is_none());
+    }
+
+    #[test]
+    fn test_stream_usage_enabled() {
+        assert!(stream_usage_enabled(&context(&[])));
+        assert!(stream_usage_enabled(&context(&[(
+            "endpoint",
+            "https://api.deepseek.com/v1"
+        )])));
+        assert!(!stream_usage_enabled(&context(&[(
+            "endpoint",
+            "http://localhost:8000/v1"
+        )])));
+        assert!(stream_usage_enabled(&context(&[
+            ("endpoint", "http://localhost:8000/v1"),
+            ("stream_usage", "true"),
+        ])));
+        assert!(!stream_usage_enabled(&context(&[(
+            "stream_usage",
+            "false"
+        )])));
+    }
+}
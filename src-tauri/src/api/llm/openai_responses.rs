@@ -0,0 +1,350 @@
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+
+use super::{
+    capability::set_clamped_param,
+    chat_message::{ChatMessage, Role},
+    finish_reason_event,
+    request_context::RequestContext,
+    sse::SseEvent,
+    tool_progress_event, usage_event, ChatResponse, TokenUsage, ToolProgress,
+};
+
+// 服务端执行的内置工具，对应助手模型参数或者提供商配置中的 builtin_tools，逗号分隔
+const CODE_INTERPRETER: &str = "code_interpreter";
+const FILE_SEARCH: &str = "file_search";
+const WEB_SEARCH: &str = "web_search";
+
+/// 提供商配置的 api_mode 为 responses 时使用 Responses API 代替 Chat Completions，可以使用代码解释器、文件检索等服务端工具
+pub(super) fn is_responses_mode(context: &RequestContext) -> bool {
+    context
+        .config_map
+        .get("api_mode")
+        .is_some_and(|mode| mode.trim() == "responses")
+}
+
+// 助手的模型参数优先，没有时使用提供商配置
+fn config_value<'a>(context: &'a RequestContext, key: &str) -> Option<&'a str> {
+    context
+        .model_config_map
+        .get(key)
+        .or_else(|| context.config_map.get(key))
+        .map(|value| value.trim())
+        .filter(|value| !value.is_empty())
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split([',', '\n'])
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn build_tools(context: &RequestContext) -> Vec<Value> {
+    let Some(tools) = config_value(context, "builtin_tools") else {
+        return vec![];
+    };
+    split_list(tools)
+        .into_iter()
+        .filter_map(|tool| match tool.as_str() {
+            CODE_INTERPRETER => Some(json!({
+                "type": CODE_INTERPRETER,
+                "container": { "type": "auto" }
+            })),
+            FILE_SEARCH => {
+                // 文件检索需要指定在哪些向量库中检索
+                let vector_store_ids = config_value(context, "vector_store_ids")
+                    .map(split_list)
+                    .unwrap_or_default();
+                if vector_store_ids.is_empty() {
+                    println!("file_search requires vector_store_ids, skipped");
+                    return None;
+                }
+                Some(json!({
+                    "type": FILE_SEARCH,
+                    "vector_store_ids": vector_store_ids
+                }))
+            }
+            WEB_SEARCH => Some(json!({ "type": "web_search_preview" })),
+            _ => {
+                println!("unknown builtin tool: {}", tool);
+                None
+            }
+        })
+        .collect()
+}
+
+// 系统提示词放在 instructions 中，其余消息转换成 input；工具结果没有对应的角色，作为用户消息发送
+fn build_input(messages: &[ChatMessage], image_detail: &str) -> Vec<Value> {
+    messages
+        .iter()
+        .filter(|message| message.role != Role::System)
+        .map(|message| {
+            let (role, text_type) = match message.role {
+                Role::Assistant => ("assistant", "output_text"),
+                _ => ("user", "input_text"),
+            };
+            let mut content = vec![json!({ "type": text_type, "text": message.text() })];
+            if message.role != Role::Assistant {
+                content.extend(message.images().filter_map(|image| {
+                    image.attachment_content.as_ref().map(|url| {
+                        json!({
+                            "type": "input_image",
+                            "image_url": url,
+                            "detail": image_detail
+                        })
+                    })
+                }));
+            }
+            json!({ "role": role, "content": content })
+        })
+        .collect()
+}
+
+/// Responses API 的请求体，chat 和 chat_stream 共用
+pub(super) fn build_body(context: &RequestContext, stream: bool) -> Value {
+    let params = context.params();
+    let image_detail = config_value(context, "image_detail").unwrap_or("auto");
+    let mut body = json!({
+        "model": params.model,
+        "max_output_tokens": params.max_tokens,
+        "input": build_input(&context.messages, image_detail),
+        "stream": stream
+    });
+    let model_code = params.model.as_deref().unwrap_or("");
+    set_clamped_param(
+        &mut body,
+        "temperature",
+        "openai_api",
+        model_code,
+        "temperature",
+        params.temperature,
+    );
+    set_clamped_param(
+        &mut body,
+        "top_p",
+        "openai_api",
+        model_code,
+        "top_p",
+        params.top_p,
+    );
+    if let Some(instructions) = context.system_prompt() {
+        body["instructions"] = json!(instructions);
+    }
+    let tools = build_tools(context);
+    if !tools.is_empty() {
+        body["tools"] = json!(tools);
+    }
+    body
+}
+
+// completed 对应 stop，因为 max_output_tokens 没有完成时对应 length，和 Chat Completions 的结束原因保持一致
+fn finish_reason(response: &Value) -> Option<String> {
+    match response["status"].as_str()? {
+        "completed" => Some("stop".to_string()),
+        "incomplete" => Some(
+            match response["incomplete_details"]["reason"].as_str() {
+                Some("max_output_tokens") | None => "length",
+                Some(reason) => reason,
+            }
+            .to_string(),
+        ),
+        status => Some(status.to_string()),
+    }
+}
+
+fn token_usage(response: &Value) -> Option<TokenUsage> {
+    let usage = response.get("usage").filter(|usage| usage.is_object())?;
+    Some(TokenUsage {
+        prompt_tokens: usage["input_tokens"].as_u64().map(|v| v as u32),
+        completion_tokens: usage["output_tokens"].as_u64().map(|v| v as u32),
+        total_tokens: usage["total_tokens"].as_u64().map(|v| v as u32),
+    })
+}
+
+fn error_message(value: &Value) -> String {
+    value["response"]["error"]["message"]
+        .as_str()
+        .or_else(|| value["error"]["message"].as_str())
+        .or_else(|| value["message"].as_str())
+        .unwrap_or("Responses API 请求失败")
+        .to_string()
+}
+
+/// 非流式响应中所有 message 类型输出的文字
+pub(super) fn parse_response(response: &Value) -> Result<ChatResponse> {
+    if response["status"].as_str() == Some("failed") {
+        bail!(error_message(&json!({ "response": response })));
+    }
+    let Some(output) = response["output"].as_array() else {
+        bail!("Failed to get output from response")
+    };
+    let content = output
+        .iter()
+        .filter(|item| item["type"] == "message")
+        .flat_map(|item| item["content"].as_array().into_iter().flatten())
+        .filter(|part| part["type"] == "output_text")
+        .filter_map(|part| part["text"].as_str())
+        .collect::<String>();
+    Ok(ChatResponse {
+        content,
+        finish_reason: finish_reason(response),
+        usage: token_usage(response),
+        ..Default::default()
+    })
+}
+
+// response.code_interpreter_call.interpreting 这样的事件表示服务端工具的执行进度
+fn tool_progress(value: &Value) -> Option<ToolProgress> {
+    let event_type = value["type"].as_str()?;
+    let (kind, status) = event_type.strip_prefix("response.")?.split_once('.')?;
+    let tool = kind.strip_suffix("_call")?;
+    Some(ToolProgress {
+        item_id: value["item_id"].as_str().unwrap_or_default().to_string(),
+        tool: tool.to_string(),
+        status: status.to_string(),
+    })
+}
+
+/// 处理一条 Responses API 的流式事件，返回是否已经结束；文字增量、结束原因、用量和工具进度都转换成 Chat Completions 相同的事件
+pub(super) async fn send_stream_event(
+    event: &SseEvent,
+    message_id: i64,
+    full_text: &mut String,
+    tx: &tokio::sync::mpsc::Sender<(i64, String, bool)>,
+) -> Result<bool> {
+    let Some(value) = event.json() else {
+        return Ok(false);
+    };
+    match value["type"].as_str().unwrap_or_default() {
+        "response.output_text.delta" => {
+            if let Some(delta) = value["delta"].as_str() {
+                full_text.push_str(delta);
+                tx.send((message_id, full_text.clone(), false)).await?;
+            }
+            Ok(false)
+        }
+        "response.completed" | "response.incomplete" => {
+            let response = &value["response"];
+            if let Some(reason) = finish_reason(response) {
+                tx.send((message_id, finish_reason_event(&reason), false))
+                    .await?;
+            }
+            if let Some(usage) = token_usage(response) {
+                tx.send((message_id, usage_event(&usage), false)).await?;
+            }
+            Ok(true)
+        }
+        "response.failed" | "error" => bail!(error_message(&value)),
+        _ => {
+            if let Some(progress) = tool_progress(&value) {
+                tx.send((message_id, tool_progress_event(&progress), false))
+                    .await?;
+            }
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::assistant_db::AssistantModelConfig;
+    use crate::db::llm_db::LLMProviderConfig;
+
+    fn context(builtin_tools: &str) -> RequestContext {
+        let provider_config = |name: &str, value: &str| LLMProviderConfig {
+            id: 0,
+            name: name.to_string(),
+            llm_provider_id: 1,
+            value: value.to_string(),
+            append_location: "header".to_string(),
+            is_addition: false,
+        };
+        RequestContext::new(
+            vec![
+                provider_config("api_mode", "responses"),
+                provider_config("vector_store_ids", "vs_1, vs_2"),
+            ],
+            &[
+                AssistantModelConfig {
+                    id: 0,
+                    assistant_id: 1,
+                    assistant_model_id: 1,
+                    name: "model".to_string(),
+                    value: Some("gpt-4.1".to_string()),
+                    value_type: "string".to_string(),
+                },
+                AssistantModelConfig {
+                    id: 0,
+                    assistant_id: 1,
+                    assistant_model_id: 1,
+                    name: "builtin_tools".to_string(),
+                    value: Some(builtin_tools.to_string()),
+                    value_type: "string".to_string(),
+                },
+            ],
+            vec![
+                ChatMessage::new(Role::System, "You are a data analyst."),
+                ChatMessage::new(Role::User, "Plot the data"),
+                ChatMessage::new(Role::Assistant, "Sure"),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_build_body() {
+        let context = context("code_interpreter, file_search");
+        assert!(is_responses_mode(&context));
+        let body = build_body(&context, true);
+        assert_eq!(body["instructions"], "You are a data analyst.");
+        assert_eq!(body["input"].as_array().unwrap().len(), 2);
+        assert_eq!(body["input"][0]["content"][0]["type"], "input_text");
+        assert_eq!(body["input"][1]["content"][0]["type"], "output_text");
+        assert_eq!(body["tools"][0]["type"], "code_interpreter");
+        assert_eq!(
+            body["tools"][1]["vector_store_ids"],
+            json!(["vs_1", "vs_2"])
+        );
+
+        assert!(build_body(&context(""), false).get("tools").is_none());
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let response = json!({
+            "status": "incomplete",
+            "incomplete_details": { "reason": "max_output_tokens" },
+            "output": [
+                { "type": "code_interpreter_call", "id": "ci_1" },
+                { "type": "message", "content": [
+                    { "type": "output_text", "text": "The mean " },
+                    { "type": "output_text", "text": "is 4.2" }
+                ]}
+            ]
+        });
+        let response = parse_response(&response).unwrap();
+        assert_eq!(response.content, "The mean is 4.2");
+        assert_eq!(response.finish_reason.as_deref(), Some("length"));
+    }
+
+    #[test]
+    fn test_tool_progress() {
+        assert_eq!(
+            tool_progress(&json!({
+                "type": "response.code_interpreter_call.interpreting",
+                "item_id": "ci_1"
+            })),
+            Some(ToolProgress {
+                item_id: "ci_1".to_string(),
+                tool: "code_interpreter".to_string(),
+                status: "interpreting".to_string(),
+            })
+        );
+        assert_eq!(
+            tool_progress(&json!({ "type": "response.output_text.delta" })),
+            None
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use serde_json::json;
+
+use crate::db::llm_db::{LLMProvider, LLMProviderConfig};
+
+use super::offline::{is_local_provider, provider_endpoint};
+use super::request_builder::{apply_request_headers, AuthScheme};
+use super::{check_response_status, client_builder, send_request};
+
+// 第一次加载大模型可能需要几十秒，等待的时间比普通请求长
+const PRELOAD_TIMEOUT: Duration = Duration::from_secs(180);
+// 没有配置 keep_alive 时让 Ollama 在内存中保留模型的时间，Ollama 默认只保留 5 分钟
+const DEFAULT_KEEP_ALIVE: &str = "30m";
+
+/// 预加载请求的地址和请求体，不是本地提供商或者不支持预加载时返回 None
+fn preload_request(
+    provider: &LLMProvider,
+    llm_provider_config: &[LLMProviderConfig],
+    model_code: &str,
+) -> Option<(String, serde_json::Value)> {
+    if !is_local_provider(provider, llm_provider_config) {
+        return None;
+    }
+    let endpoint = provider_endpoint(provider, llm_provider_config)?;
+    let endpoint = endpoint.trim_end_matches('/');
+    match provider.api_type.as_str() {
+        // 不带 prompt 的 generate 请求只把模型加载到内存中，不生成内容
+        "ollama" => {
+            let keep_alive = llm_provider_config
+                .iter()
+                .find(|config| config.name == "keep_alive")
+                .map(|config| config.value.trim())
+                .filter(|value| !value.is_empty())
+                .unwrap_or(DEFAULT_KEEP_ALIVE);
+            Some((
+                format!("{}/api/generate", endpoint),
+                json!({ "model": model_code, "keep_alive": keep_alive }),
+            ))
+        }
+        // LM Studio、vLLM 等 OpenAI 兼容的本地服务在收到第一个请求时才加载模型，只生成一个 token
+        "openai_api" => Some((
+            format!("{}/chat/completions", endpoint),
+            json!({
+                "model": model_code,
+                "messages": [{ "role": "user", "content": "hi" }],
+                "max_tokens": 1,
+                "stream": false,
+            }),
+        )),
+        _ => None,
+    }
+}
+
+/// 提供商是否支持预加载模型：地址指向本机的 Ollama 和 OpenAI 兼容服务
+pub fn supports_warm_up(provider: &LLMProvider, llm_provider_config: &[LLMProviderConfig]) -> bool {
+    preload_request(provider, llm_provider_config, "").is_some()
+}
+
+/// 让本地提供商提前把模型加载到内存中，第一次提问不用等待加载模型
+pub async fn warm_up_model(
+    provider: &LLMProvider,
+    llm_provider_config: &[LLMProviderConfig],
+    model_code: &str,
+) -> Result<()> {
+    let (url, body) = preload_request(provider, llm_provider_config, model_code)
+        .ok_or_else(|| anyhow!("提供商 {} 不支持预加载模型", provider.name))?;
+    let config_map = llm_provider_config
+        .iter()
+        .map(|config| (config.name.clone(), config.value.clone()))
+        .collect();
+    let client = client_builder().timeout(PRELOAD_TIMEOUT).build()?;
+    let request = apply_request_headers(client.post(&url), &config_map, AuthScheme::Bearer)?;
+    check_response_status(send_request(request.json(&body)).await?).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider(api_type: &str) -> LLMProvider {
+        LLMProvider {
+            id: 1,
+            name: api_type.to_string(),
+            api_type: api_type.to_string(),
+            description: String::new(),
+            is_official: false,
+            is_enabled: true,
+        }
+    }
+
+    fn config(name: &str, value: &str) -> LLMProviderConfig {
+        LLMProviderConfig {
+            id: 0,
+            name: name.to_string(),
+            llm_provider_id: 1,
+            value: value.to_string(),
+            append_location: "header".to_string(),
+            is_addition: false,
+        }
+    }
+
+    #[test]
+    fn test_preload_request() {
+        let (url, body) = preload_request(&provider("ollama"), &[], "qwen2.5:7b").unwrap();
+        assert_eq!(url, "http://localhost:11434/api/generate");
+        assert_eq!(body, json!({ "model": "qwen2.5:7b", "keep_alive": "30m" }));
+
+        let (_, body) =
+            preload_request(&provider("ollama"), &[config("keep_alive", "-1")], "llama3").unwrap();
+        assert_eq!(body["keep_alive"], "-1");
+
+        let (url, body) = preload_request(
+            &provider("openai_api"),
+            &[config("endpoint", "http://127.0.0.1:1234/v1/")],
+            "local-model",
+        )
+        .unwrap();
+        assert_eq!(url, "http://127.0.0.1:1234/v1/chat/completions");
+        assert_eq!(body["max_tokens"], 1);
+
+        // 远程提供商不需要预加载
+        assert!(preload_request(&provider("openai_api"), &[], "gpt-4o").is_none());
+        assert!(preload_request(
+            &provider("ollama"),
+            &[config("endpoint", "http://192.168.1.10:11434")],
+            "llama3"
+        )
+        .is_none());
+        assert!(preload_request(&provider("mock"), &[], "mock").is_none());
+    }
+}
@@ -0,0 +1,342 @@
+use serde::Serialize;
+use serde_json::Value;
+
+use super::diagnostics::{classify_status, DiagnosticKind};
+use super::ModelUnavailableError;
+
+/// 提供商错误的分类，前端根据 code 显示对应的处理建议
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderErrorCode {
+    InvalidApiKey,
+    InsufficientQuota,
+    ModelNotFound,
+    ContentPolicy,
+    ContextLengthExceeded,
+    RateLimited,
+    Overloaded,
+    Unknown,
+}
+
+impl ProviderErrorCode {
+    /// 给用户的处理建议
+    pub fn suggestion(&self) -> &'static str {
+        match self {
+            ProviderErrorCode::InvalidApiKey => {
+                "API Key 无效或者已经过期，请在模型设置中检查提供商的 API Key"
+            }
+            ProviderErrorCode::InsufficientQuota => {
+                "账户余额或额度不足，请到提供商的控制台充值或者更换 API Key"
+            }
+            ProviderErrorCode::ModelNotFound => {
+                "模型不存在或者当前账户没有权限，请重新获取模型列表或者在提供商配置中设置备用模型"
+            }
+            ProviderErrorCode::ContentPolicy => "内容被提供商的安全策略拦截，请修改提问内容后重试",
+            ProviderErrorCode::ContextLengthExceeded => {
+                "对话内容超过了模型的上下文长度，请减少附件、开启上下文压缩或者新建对话"
+            }
+            ProviderErrorCode::RateLimited => "请求过于频繁，请稍后重试",
+            ProviderErrorCode::Overloaded => "提供商的服务繁忙，请稍后重试或者换用其他模型",
+            ProviderErrorCode::Unknown => "请求失败，请检查网络和提供商配置后重试",
+        }
+    }
+}
+
+// 提供商错误内容中 type 或者 code 字段的取值，按完整的值比较，不区分大小写。
+// 1113、1301 为智谱的“余额不足”和“内容不安全”
+const ERROR_CODES: &[(ProviderErrorCode, &[&str])] = &[
+    (
+        ProviderErrorCode::InsufficientQuota,
+        &[
+            "insufficient_quota",
+            "insufficient_balance",
+            "billing_hard_limit_reached",
+            "arrearage",
+            "1113",
+        ],
+    ),
+    (
+        ProviderErrorCode::InvalidApiKey,
+        &[
+            "invalid_api_key",
+            "incorrect_api_key",
+            "authentication_error",
+            "invalidapikey",
+        ],
+    ),
+    (
+        ProviderErrorCode::ContentPolicy,
+        &[
+            "content_policy_violation",
+            "content_filter",
+            "contentfilter",
+            "data_inspection_failed",
+            "1301",
+        ],
+    ),
+    (
+        ProviderErrorCode::ContextLengthExceeded,
+        &["context_length_exceeded"],
+    ),
+    (
+        ProviderErrorCode::ModelNotFound,
+        &["model_not_found", "not_found_error"],
+    ),
+    (
+        ProviderErrorCode::RateLimited,
+        &["rate_limit_exceeded", "rate_limit_error", "throttling"],
+    ),
+    (ProviderErrorCode::Overloaded, &["overloaded_error"]),
+];
+
+// 没有错误码和状态码时才按错误信息判断，只匹配错误码和提供商固定的说法，
+// 不匹配 safety、billing 之类的单词，避免无关的错误信息中出现这些词时分类错误
+const MESSAGE_PHRASES: &[(ProviderErrorCode, &[&str])] = &[
+    (
+        ProviderErrorCode::InsufficientQuota,
+        &[
+            "insufficient_quota",
+            "exceeded your current quota",
+            "credit balance is too low",
+            "余额不足",
+        ],
+    ),
+    (
+        ProviderErrorCode::InvalidApiKey,
+        &[
+            "invalid_api_key",
+            "incorrect api key",
+            "invalid api key",
+            "invalid x-api-key",
+            "api key not valid",
+        ],
+    ),
+    (
+        ProviderErrorCode::ContentPolicy,
+        &["content_policy_violation", "content management policy"],
+    ),
+    (
+        ProviderErrorCode::ContextLengthExceeded,
+        &[
+            "context_length_exceeded",
+            "maximum context length",
+            "prompt is too long",
+        ],
+    ),
+    (ProviderErrorCode::ModelNotFound, &["model_not_found"]),
+    (
+        ProviderErrorCode::RateLimited,
+        &[
+            "rate_limit_exceeded",
+            "rate_limit_error",
+            "too many requests",
+        ],
+    ),
+    (ProviderErrorCode::Overloaded, &["overloaded_error"]),
+];
+
+fn classify_error_code(code: &str) -> Option<ProviderErrorCode> {
+    let code = code.trim().to_lowercase();
+    ERROR_CODES
+        .iter()
+        .find(|(_, codes)| codes.contains(&code.as_str()))
+        .map(|(error_code, _)| *error_code)
+}
+
+fn classify_status_code(status: u16, message: &str) -> Option<ProviderErrorCode> {
+    match classify_status(status, message).1 {
+        DiagnosticKind::Unauthorized => Some(ProviderErrorCode::InvalidApiKey),
+        DiagnosticKind::ModelNotFound => Some(ProviderErrorCode::ModelNotFound),
+        DiagnosticKind::RateLimited => Some(ProviderErrorCode::RateLimited),
+        _ if status == 402 => Some(ProviderErrorCode::InsufficientQuota),
+        _ if status == 529 => Some(ProviderErrorCode::Overloaded),
+        _ => None,
+    }
+}
+
+fn classify_message(message: &str) -> Option<ProviderErrorCode> {
+    let lower_message = message.to_lowercase();
+    MESSAGE_PHRASES
+        .iter()
+        .find(|(_, phrases)| phrases.iter().any(|phrase| lower_message.contains(phrase)))
+        .map(|(code, _)| *code)
+}
+
+/// 先按错误内容中的错误码，再按 HTTP 状态码（流式事件中的错误没有状态码），
+/// 都判断不出来时才按错误信息中固定的说法判断
+pub fn classify_provider_error(
+    status: Option<u16>,
+    error_codes: &[String],
+    message: &str,
+) -> ProviderErrorCode {
+    error_codes
+        .iter()
+        .find_map(|code| classify_error_code(code))
+        .or_else(|| status.and_then(|status| classify_status_code(status, message)))
+        .or_else(|| classify_message(message))
+        .unwrap_or(ProviderErrorCode::Unknown)
+}
+
+/// 提供商返回的错误，message 为原始的错误信息
+#[derive(Debug)]
+pub struct ProviderError {
+    pub code: ProviderErrorCode,
+    pub message: String,
+}
+
+impl ProviderError {
+    /// 错误内容中的 type 和 code 字段比 message 更准确，优先按它们分类
+    pub fn from_body(status: Option<u16>, body: &str, message: String) -> Self {
+        let value = serde_json::from_str::<Value>(body).unwrap_or_default();
+        let error = value.get("error").unwrap_or(&value);
+        let field = |value: &Value, name: &str| match &value[name] {
+            Value::String(s) => s.clone(),
+            Value::Number(n) => n.to_string(),
+            _ => String::new(),
+        };
+        let error_codes = [
+            field(error, "type"),
+            field(error, "code"),
+            field(&value, "code"),
+        ];
+        ProviderError {
+            code: classify_provider_error(status, &error_codes, &message),
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+/// 对话请求失败时通过 provider_error 事件发送给前端
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ProviderErrorEvent {
+    pub message_id: i64,
+    pub code: ProviderErrorCode,
+    pub message: String,
+    pub suggestion: String,
+}
+
+impl ProviderErrorEvent {
+    /// 提供商返回的错误直接使用它的分类和原始的错误信息，其他错误按完整的错误链判断
+    pub fn from_error(message_id: i64, error: &anyhow::Error) -> Self {
+        let (code, message) = error
+            .chain()
+            .find_map(|e| {
+                if let Some(e) = e.downcast_ref::<ProviderError>() {
+                    Some((e.code, e.message.clone()))
+                } else {
+                    e.downcast_ref::<ModelUnavailableError>()
+                        .map(|e| (ProviderErrorCode::ModelNotFound, e.to_string()))
+                }
+            })
+            .unwrap_or_else(|| {
+                let message = format!("{:#}", error);
+                (classify_provider_error(None, &[], &message), message)
+            });
+        ProviderErrorEvent {
+            message_id,
+            code,
+            message,
+            suggestion: code.suggestion().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_provider_error() {
+        let error = ProviderError::from_body(
+            Some(429),
+            "{\"error\":{\"type\":\"insufficient_quota\",\"message\":\"You exceeded your current quota\"}}",
+            "HTTP 429: You exceeded your current quota".to_string(),
+        );
+        assert_eq!(error.code, ProviderErrorCode::InsufficientQuota);
+        assert_eq!(
+            error.to_string(),
+            "HTTP 429: You exceeded your current quota"
+        );
+
+        assert_eq!(
+            classify_provider_error(Some(401), &[], "{}"),
+            ProviderErrorCode::InvalidApiKey
+        );
+        assert_eq!(
+            classify_provider_error(None, &[], "overloaded_error: Overloaded"),
+            ProviderErrorCode::Overloaded
+        );
+        assert_eq!(
+            classify_provider_error(Some(429), &[], ""),
+            ProviderErrorCode::RateLimited
+        );
+        assert_eq!(
+            classify_provider_error(None, &[], "connection reset"),
+            ProviderErrorCode::Unknown
+        );
+
+        // 错误码优先于错误信息，智谱的错误码是数字
+        let error = ProviderError::from_body(
+            Some(400),
+            "{\"error\":{\"code\":\"1301\",\"message\":\"系统检测到输入或生成内容可能包含不安全或敏感内容\"}}",
+            "HTTP 400".to_string(),
+        );
+        assert_eq!(error.code, ProviderErrorCode::ContentPolicy);
+    }
+
+    #[test]
+    fn test_classify_ignores_bare_words() {
+        // 错误信息中出现 safety、billing、敏感 等单词不代表内容被拦截或者额度不足
+        for message in [
+            "invalid parameter: safety_settings",
+            "billing address is required",
+            "参数 敏感度 超出范围",
+        ] {
+            assert_eq!(
+                classify_provider_error(Some(400), &[], message),
+                ProviderErrorCode::Unknown
+            );
+            assert_eq!(
+                classify_provider_error(None, &[], message),
+                ProviderErrorCode::Unknown
+            );
+        }
+        let error = ProviderError::from_body(
+            Some(400),
+            "{\"error\":{\"type\":\"invalid_request_error\",\"message\":\"safety_settings is not supported\"}}",
+            "HTTP 400: safety_settings is not supported".to_string(),
+        );
+        assert_eq!(error.code, ProviderErrorCode::Unknown);
+    }
+
+    #[test]
+    fn test_provider_error_event() {
+        let error = anyhow::Error::from(ProviderError {
+            code: ProviderErrorCode::ContentPolicy,
+            message: "blocked".to_string(),
+        })
+        .context("Chat failed");
+        let event = ProviderErrorEvent::from_error(1, &error);
+        assert_eq!(event.code, ProviderErrorCode::ContentPolicy);
+        // 事件中是提供商原始的错误信息，不是最外层的上下文
+        assert_eq!(event.message, "blocked");
+        assert_eq!(
+            event.suggestion,
+            ProviderErrorCode::ContentPolicy.suggestion()
+        );
+
+        // 其他错误按完整的错误链判断，事件中也是完整的错误链
+        let error = anyhow::anyhow!("Error: context_length_exceeded").context("Chat failed");
+        let event = ProviderErrorEvent::from_error(1, &error);
+        assert_eq!(event.code, ProviderErrorCode::ContextLengthExceeded);
+        assert_eq!(event.message, "Chat failed: Error: context_length_exceeded");
+    }
+}
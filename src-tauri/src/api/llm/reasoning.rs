@@ -0,0 +1,137 @@
+// 推理模型在回答正文中输出的思考过程标签，可以通过 reasoning 的 tags 配置修改，逗号分隔
+pub const DEFAULT_REASONING_TAGS: &str = "think,thinking";
+
+/// 从回答中分离出来的思考过程和正式回答
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ReasoningSplit {
+    pub reasoning: String,
+    pub answer: String,
+    // 思考过程的结束标签还没有出现
+    pub thinking: bool,
+}
+
+pub fn parse_reasoning_tags(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches('<').trim_end_matches('>'))
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| tag.to_string())
+        .collect()
+}
+
+/// 把 <think>...</think> 之类标签内的内容和正文分开，content 为目前为止的完整内容
+///
+/// 生成过程中末尾可能是不完整的标签，complete 为 false 时先不输出这部分，等后续内容确定后再处理
+pub fn split_reasoning(content: &str, tags: &[String], complete: bool) -> ReasoningSplit {
+    let mut result = ReasoningSplit::default();
+    let mut rest = content;
+    loop {
+        let open = tags
+            .iter()
+            .filter_map(|tag| {
+                let open_tag = format!("<{}>", tag);
+                rest.find(&open_tag)
+                    .map(|index| (index, tag, open_tag.len()))
+            })
+            .min_by_key(|(index, _, _)| *index);
+        let Some((index, tag, open_len)) = open else {
+            let keep = if complete {
+                rest.len()
+            } else {
+                rest.len() - partial_tag_len(rest, tags, false)
+            };
+            result.answer.push_str(&rest[..keep]);
+            break;
+        };
+        result.answer.push_str(&rest[..index]);
+        let inner = &rest[index + open_len..];
+        let close_tag = format!("</{}>", tag);
+        match inner.find(&close_tag) {
+            Some(end) => {
+                push_reasoning(&mut result.reasoning, &inner[..end]);
+                rest = &inner[end + close_tag.len()..];
+            }
+            None => {
+                let keep = if complete {
+                    inner.len()
+                } else {
+                    inner.len() - partial_tag_len(inner, std::slice::from_ref(tag), true)
+                };
+                push_reasoning(&mut result.reasoning, &inner[..keep]);
+                result.thinking = !complete;
+                break;
+            }
+        }
+    }
+    result.answer = result.answer.trim_start().to_string();
+    result
+}
+
+fn push_reasoning(reasoning: &mut String, text: &str) {
+    let text = text.trim();
+    if text.is_empty() {
+        return;
+    }
+    if !reasoning.is_empty() {
+        reasoning.push_str("\n\n");
+    }
+    reasoning.push_str(text);
+}
+
+// 末尾和某个标签开头相同的部分的长度
+fn partial_tag_len(text: &str, tags: &[String], close: bool) -> usize {
+    tags.iter()
+        .map(|tag| {
+            let full = if close {
+                format!("</{}>", tag)
+            } else {
+                format!("<{}>", tag)
+            };
+            (1..full.len())
+                .rev()
+                .find(|len| text.ends_with(&full[..*len]))
+                .unwrap_or(0)
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags() -> Vec<String> {
+        parse_reasoning_tags(DEFAULT_REASONING_TAGS)
+    }
+
+    #[test]
+    fn test_split_reasoning_stream() {
+        let split = split_reasoning("<think>先算一下", &tags(), false);
+        assert_eq!(split.reasoning, "先算一下");
+        assert_eq!(split.answer, "");
+        assert!(split.thinking);
+
+        let split = split_reasoning("<think>先算一下</th", &tags(), false);
+        assert_eq!(split.reasoning, "先算一下");
+        assert!(split.thinking);
+
+        let split = split_reasoning("<think>先算一下</think>\n\n答案是 2 <", &tags(), false);
+        assert_eq!(split.reasoning, "先算一下");
+        assert_eq!(split.answer, "答案是 2 ");
+        assert!(!split.thinking);
+
+        let split = split_reasoning("<think>先算一下</think>\n\n答案是 2 <", &tags(), true);
+        assert_eq!(split.answer, "答案是 2 <");
+    }
+
+    #[test]
+    fn test_split_reasoning_without_tags() {
+        let split = split_reasoning("a < b，<thinking>x</thinking>结论", &tags(), true);
+        assert_eq!(split.reasoning, "x");
+        assert_eq!(split.answer, "a < b，结论");
+        assert_eq!(
+            parse_reasoning_tags(" <think>, ,reason "),
+            vec!["think", "reason"]
+        );
+    }
+}
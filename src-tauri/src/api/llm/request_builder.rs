@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION},
+    RequestBuilder,
+};
+
+/// 鉴权方式，对应提供商配置中的 auth_scheme，未配置时使用各提供商自己的默认方式
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthScheme {
+    // Authorization: Bearer <api_key>
+    Bearer,
+    // api-key: <api_key>，Azure OpenAI 等网关使用
+    ApiKey,
+    // x-api-key: <api_key>，Anthropic 使用
+    XApiKey,
+    // 使用 auth_header 配置的请求头名称直接携带 api_key
+    Custom(String),
+    // 不携带任何鉴权信息
+    None,
+}
+
+impl AuthScheme {
+    pub fn from_config(config_map: &HashMap<String, String>, default: AuthScheme) -> AuthScheme {
+        match config_map.get("auth_scheme").map(|s| s.trim()) {
+            Some("bearer") => AuthScheme::Bearer,
+            Some("api_key") => AuthScheme::ApiKey,
+            Some("x_api_key") => AuthScheme::XApiKey,
+            Some("custom") => AuthScheme::Custom(
+                config_map
+                    .get("auth_header")
+                    .filter(|h| !h.trim().is_empty())
+                    .map(|h| h.trim().to_string())
+                    .unwrap_or_else(|| AUTHORIZATION.to_string()),
+            ),
+            Some("none") => AuthScheme::None,
+            _ => default,
+        }
+    }
+}
+
+/// 为请求添加鉴权请求头以及用户在 custom_headers 中配置的自定义请求头
+///
+/// custom_headers 是一个 JSON 对象，例如 `{"cf-aig-authorization": "Bearer xxx"}`，
+/// 同名请求头会覆盖鉴权请求头，方便适配 Cloudflare AI Gateway、LiteLLM 等网关
+pub fn apply_request_headers(
+    request: RequestBuilder,
+    config_map: &HashMap<String, String>,
+    default_auth_scheme: AuthScheme,
+) -> Result<RequestBuilder> {
+    build_headers(request, config_map, default_auth_scheme, false)
+}
+
+/// OpenAI 类型的提供商使用，在 apply_request_headers 的基础上添加组织和项目请求头。
+/// Ollama、通义千问等其他使用 Bearer 鉴权的提供商不认识这两个请求头，不添加
+pub fn apply_openai_request_headers(
+    request: RequestBuilder,
+    config_map: &HashMap<String, String>,
+) -> Result<RequestBuilder> {
+    build_headers(request, config_map, AuthScheme::Bearer, true)
+}
+
+fn build_headers(
+    request: RequestBuilder,
+    config_map: &HashMap<String, String>,
+    default_auth_scheme: AuthScheme,
+    with_organization: bool,
+) -> Result<RequestBuilder> {
+    let api_key = config_map.get("api_key").cloned().unwrap_or_default();
+    let mut headers = HeaderMap::new();
+
+    let auth_header = match AuthScheme::from_config(config_map, default_auth_scheme) {
+        AuthScheme::Bearer => Some((AUTHORIZATION.to_string(), format!("Bearer {}", api_key))),
+        AuthScheme::ApiKey => Some(("api-key".to_string(), api_key)),
+        AuthScheme::XApiKey => Some(("x-api-key".to_string(), api_key)),
+        AuthScheme::Custom(name) => Some((name, api_key)),
+        AuthScheme::None => None,
+    };
+    if let Some((name, value)) = auth_header {
+        insert_header(&mut headers, &name, &value)?;
+    }
+    if with_organization {
+        for (name, value) in organization_headers(config_map) {
+            insert_header(&mut headers, name, &value)?;
+        }
+    }
+
+    for (name, value) in parse_custom_headers(config_map)? {
+        insert_header(&mut headers, &name, &value)?;
+    }
+
+    Ok(request.headers(headers))
+}
+
+/// 提供商配置中的 organization 和 project 对应的 OpenAI-Organization、OpenAI-Project 请求头，
+/// 企业账号据此把用量记到指定的组织和项目上，没有配置时不添加
+fn organization_headers(config_map: &HashMap<String, String>) -> Vec<(&'static str, String)> {
+    [
+        ("organization", "OpenAI-Organization"),
+        ("project", "OpenAI-Project"),
+    ]
+    .into_iter()
+    .filter_map(|(key, header)| {
+        config_map
+            .get(key)
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+            .map(|value| (header, value.to_string()))
+    })
+    .collect()
+}
+
+pub fn parse_custom_headers(config_map: &HashMap<String, String>) -> Result<Vec<(String, String)>> {
+    let custom_headers = match config_map.get("custom_headers") {
+        Some(value) if !value.trim().is_empty() => value,
+        _ => return Ok(vec![]),
+    };
+
+    let value: serde_json::Value = serde_json::from_str(custom_headers)
+        .map_err(|e| anyhow!("custom_headers 不是合法的 JSON: {}", e))?;
+    let object = value
+        .as_object()
+        .ok_or_else(|| anyhow!("custom_headers 必须是 JSON 对象"))?;
+
+    Ok(object
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .as_str()
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| value.to_string());
+            (name.clone(), value)
+        })
+        .collect())
+}
+
+fn insert_header(headers: &mut HeaderMap, name: &str, value: &str) -> Result<()> {
+    let header_name = HeaderName::from_bytes(name.as_bytes())
+        .map_err(|e| anyhow!("无效的请求头名称 {}: {}", name, e))?;
+    let header_value =
+        HeaderValue::from_str(value).map_err(|e| anyhow!("无效的请求头 {} 的值: {}", name, e))?;
+    headers.insert(header_name, header_value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_map(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn build(config: &HashMap<String, String>, default: AuthScheme) -> reqwest::Request {
+        apply_request_headers(
+            reqwest::Client::new().post("https://api.openai.com/v1/chat/completions"),
+            config,
+            default,
+        )
+        .unwrap()
+        .build()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_auth_scheme_from_config() {
+        let cases = [
+            ("bearer", AuthScheme::Bearer),
+            ("api_key", AuthScheme::ApiKey),
+            (" x_api_key ", AuthScheme::XApiKey),
+            ("none", AuthScheme::None),
+            ("", AuthScheme::XApiKey),
+            ("unknown", AuthScheme::XApiKey),
+        ];
+        for (value, expected) in cases {
+            let config = config_map(&[("auth_scheme", value)]);
+            assert_eq!(
+                AuthScheme::from_config(&config, AuthScheme::XApiKey),
+                expected,
+                "{}",
+                value
+            );
+        }
+        assert_eq!(
+            AuthScheme::from_config(&config_map(&[]), AuthScheme::Bearer),
+            AuthScheme::Bearer
+        );
+        assert_eq!(
+            AuthScheme::from_config(
+                &config_map(&[("auth_scheme", "custom"), ("auth_header", " X-Token ")]),
+                AuthScheme::Bearer
+            ),
+            AuthScheme::Custom("X-Token".to_string())
+        );
+        // 没有填写请求头名称时使用 Authorization
+        assert_eq!(
+            AuthScheme::from_config(
+                &config_map(&[("auth_scheme", "custom")]),
+                AuthScheme::Bearer
+            ),
+            AuthScheme::Custom("authorization".to_string())
+        );
+    }
+
+    #[test]
+    fn test_auth_headers() {
+        let request = build(&config_map(&[("api_key", "sk-test")]), AuthScheme::Bearer);
+        assert_eq!(request.headers()[AUTHORIZATION], "Bearer sk-test");
+
+        let request = build(
+            &config_map(&[("api_key", "sk-test"), ("auth_scheme", "api_key")]),
+            AuthScheme::Bearer,
+        );
+        assert_eq!(request.headers()["api-key"], "sk-test");
+        assert!(request.headers().get(AUTHORIZATION).is_none());
+
+        let request = build(
+            &config_map(&[
+                ("api_key", "sk-test"),
+                ("auth_scheme", "custom"),
+                ("auth_header", "X-Token"),
+            ]),
+            AuthScheme::Bearer,
+        );
+        assert_eq!(request.headers()["x-token"], "sk-test");
+
+        let request = build(
+            &config_map(&[("api_key", "sk-test"), ("auth_scheme", "none")]),
+            AuthScheme::Bearer,
+        );
+        assert!(request.headers().is_empty());
+    }
+
+    #[test]
+    fn test_custom_headers_override_auth() {
+        let request = build(
+            &config_map(&[
+                ("api_key", "sk-test"),
+                (
+                    "custom_headers",
+                    r#"{"Authorization": "Bearer gateway", "cf-aig-cache-ttl": 3600}"#,
+                ),
+            ]),
+            AuthScheme::Bearer,
+        );
+        assert_eq!(request.headers()[AUTHORIZATION], "Bearer gateway");
+        assert_eq!(request.headers()["cf-aig-cache-ttl"], "3600");
+    }
+
+    #[test]
+    fn test_invalid_custom_headers() {
+        for value in [r#"{"a": "#, r#"["a", "b"]"#, r#"{"bad header": "x"}"#] {
+            let config = config_map(&[("custom_headers", value)]);
+            assert!(
+                apply_request_headers(
+                    reqwest::Client::new().get("https://example.com"),
+                    &config,
+                    AuthScheme::Bearer,
+                )
+                .is_err(),
+                "{}",
+                value
+            );
+        }
+        assert!(
+            parse_custom_headers(&config_map(&[("custom_headers", " ")]))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_organization_headers() {
+        let config = config_map(&[
+            ("api_key", "sk-test"),
+            ("organization", " org-123 "),
+            ("project", ""),
+        ]);
+        let request = apply_openai_request_headers(
+            reqwest::Client::new().post("https://api.openai.com/v1/chat/completions"),
+            &config,
+        )
+        .unwrap()
+        .build()
+        .unwrap();
+        assert_eq!(request.headers()["OpenAI-Organization"], "org-123");
+        assert!(request.headers().get("OpenAI-Project").is_none());
+
+        // 其他提供商即使使用 Bearer 鉴权也不添加
+        let request = build(&config, AuthScheme::Bearer);
+        assert!(request.headers().get("OpenAI-Organization").is_none());
+        assert_eq!(request.headers()[AUTHORIZATION], "Bearer sk-test");
+    }
+}
@@ -0,0 +1,401 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::db::{assistant_db::AssistantModelConfig, llm_db::LLMProviderConfig};
+use crate::tools::ToolDefinition;
+
+use super::chat_message::{ChatMessage, Role};
+use super::tool_call::{configured_tools, TOOLS_CONFIG};
+
+// 助手没有配置时使用的参数，也是 get_model_param_schema 返回的默认值
+pub const DEFAULT_TEMPERATURE: f64 = 0.75;
+pub const DEFAULT_TOP_P: f64 = 1.0;
+pub const DEFAULT_MAX_TOKENS: u32 = 2000;
+// 回答长度预设对应的助手模型参数名称
+pub const RESPONSE_LENGTH_CONFIG: &str = "response_length";
+
+/// 一次对话请求的公共上下文，chat 和 chat_stream 共用，各个提供商只需要把它转换成自己的请求体
+pub struct RequestContext {
+    // 提供商配置，例如 endpoint、api_key
+    pub config_map: HashMap<String, String>,
+    // 助手的模型参数，例如 model、temperature
+    pub model_config_map: HashMap<String, String>,
+    pub messages: Vec<ChatMessage>,
+}
+
+/// 助手的回答长度预设，保存在模型参数 response_length 中，
+/// 同时决定 max_tokens 的上限和 system 消息中对回答长度的要求
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResponseLength {
+    Short,
+    Medium,
+    Long,
+}
+
+impl ResponseLength {
+    pub fn from_config(value: &str) -> Option<Self> {
+        // 临时覆盖的参数按 JSON 保存，字符串带有引号
+        match value.trim().trim_matches('"') {
+            "short" => Some(ResponseLength::Short),
+            "medium" => Some(ResponseLength::Medium),
+            "long" => Some(ResponseLength::Long),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResponseLength::Short => "short",
+            ResponseLength::Medium => "medium",
+            ResponseLength::Long => "long",
+        }
+    }
+
+    /// 没有配置 max_tokens 时使用的值，配置了 max_tokens 时取两者中较小的
+    pub fn max_tokens(&self) -> u32 {
+        match self {
+            ResponseLength::Short => 512,
+            ResponseLength::Medium => DEFAULT_MAX_TOKENS,
+            ResponseLength::Long => 8192,
+        }
+    }
+
+    fn instruction(&self) -> &'static str {
+        match self {
+            ResponseLength::Short => {
+                "回答尽量简短，直接给出结论，不要铺垫和重复，一般不超过三句话。"
+            }
+            ResponseLength::Medium => "回答长度适中，说清楚要点即可，不要展开无关的内容。",
+            ResponseLength::Long => "回答详细完整，充分展开说明，必要时给出示例和步骤。",
+        }
+    }
+}
+
+/// 各个提供商通用的模型参数
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModelParams {
+    pub model: Option<String>,
+    pub temperature: f64,
+    pub top_p: f64,
+    pub max_tokens: u32,
+    pub stop: Option<Vec<String>>,
+}
+
+impl RequestContext {
+    pub fn new(
+        llm_provider_config: Vec<LLMProviderConfig>,
+        model_config: &[AssistantModelConfig],
+        messages: Vec<ChatMessage>,
+    ) -> Self {
+        let config_map = llm_provider_config
+            .into_iter()
+            .map(|c| (c.name, c.value))
+            .collect();
+        let model_config_map = model_config
+            .iter()
+            .filter_map(|config| {
+                config
+                    .value
+                    .as_ref()
+                    .map(|value| (config.name.clone(), value.clone()))
+            })
+            .collect();
+        RequestContext {
+            config_map,
+            model_config_map,
+            messages,
+        }
+    }
+
+    pub fn response_length(&self) -> Option<ResponseLength> {
+        self.model_config_map
+            .get(RESPONSE_LENGTH_CONFIG)
+            .and_then(|value| ResponseLength::from_config(value))
+    }
+
+    /// 拼接请求地址，没有配置 endpoint 时使用提供商的默认地址
+    pub fn url(&self, default_endpoint: &str, path: &str) -> String {
+        let endpoint = self
+            .config_map
+            .get("endpoint")
+            .filter(|endpoint| !endpoint.trim().is_empty())
+            .map(|endpoint| endpoint.as_str())
+            .unwrap_or(default_endpoint)
+            .trim_end_matches('/');
+        format!("{}{}", endpoint, path)
+    }
+
+    pub fn model_config<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.model_config_map
+            .get(key)
+            .and_then(|value| value.parse().ok())
+    }
+
+    pub fn params(&self) -> ModelParams {
+        ModelParams {
+            model: self.model_config_map.get("model").cloned(),
+            temperature: self
+                .model_config("temperature")
+                .unwrap_or(DEFAULT_TEMPERATURE),
+            top_p: self.model_config("top_p").unwrap_or(DEFAULT_TOP_P),
+            max_tokens: match (
+                self.model_config::<u32>("max_tokens"),
+                self.response_length(),
+            ) {
+                (Some(max_tokens), Some(length)) => max_tokens.min(length.max_tokens()),
+                (Some(max_tokens), None) => max_tokens,
+                (None, Some(length)) => length.max_tokens(),
+                (None, None) => DEFAULT_MAX_TOKENS,
+            },
+            stop: self.stop_sequences(),
+        }
+    }
+
+    /// 助手模型参数 tools 中提供给模型调用的内置工具
+    pub fn tools(&self) -> Vec<ToolDefinition> {
+        self.model_config_map
+            .get(TOOLS_CONFIG)
+            .map(|value| configured_tools(value))
+            .unwrap_or_default()
+    }
+
+    pub fn system_prompt(&self) -> Option<String> {
+        self.messages
+            .iter()
+            .find(|message| message.role == Role::System)
+            .map(|message| message.text())
+    }
+
+    // stop 支持 JSON 数组，或者每行一个停止序列
+    fn stop_sequences(&self) -> Option<Vec<String>> {
+        let value = self.model_config_map.get("stop")?;
+        let stop = match serde_json::from_str::<Vec<String>>(value) {
+            Ok(stop) => stop,
+            Err(_) => value.lines().map(|line| line.to_string()).collect(),
+        };
+        let stop = stop
+            .into_iter()
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<String>>();
+        if stop.is_empty() {
+            None
+        } else {
+            Some(stop)
+        }
+    }
+}
+
+/// 按模型参数中的回答长度预设在 system 消息后面加上长度要求，没有 system 消息时新增一条。
+/// 只用于对话生成和请求预览，翻译、标题等内部请求不加
+pub fn append_response_length_instruction(
+    messages: &mut Vec<ChatMessage>,
+    model_config: &[AssistantModelConfig],
+) {
+    let Some(response_length) = model_config
+        .iter()
+        .find(|config| config.name == RESPONSE_LENGTH_CONFIG)
+        .and_then(|config| config.value.as_deref())
+        .and_then(ResponseLength::from_config)
+    else {
+        return;
+    };
+    let instruction = response_length.instruction();
+    match messages
+        .iter_mut()
+        .find(|message| message.role == Role::System)
+    {
+        Some(message) => message.push_text(&format!("\n\n{}", instruction)),
+        None => messages.insert(0, ChatMessage::new(Role::System, instruction)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model_config(name: &str, value: &str) -> AssistantModelConfig {
+        AssistantModelConfig {
+            id: 0,
+            assistant_id: 1,
+            assistant_model_id: 1,
+            name: name.to_string(),
+            value: Some(value.to_string()),
+            value_type: "string".to_string(),
+        }
+    }
+
+    fn provider_config(name: &str, value: &str) -> LLMProviderConfig {
+        LLMProviderConfig {
+            id: 0,
+            name: name.to_string(),
+            llm_provider_id: 1,
+            value: value.to_string(),
+            append_location: "header".to_string(),
+            is_addition: false,
+        }
+    }
+
+    #[test]
+    fn test_default_params() {
+        let context = RequestContext::new(vec![], &[model_config("model", "gpt-4o")], vec![]);
+        assert_eq!(
+            context.params(),
+            ModelParams {
+                model: Some("gpt-4o".to_string()),
+                temperature: DEFAULT_TEMPERATURE,
+                top_p: DEFAULT_TOP_P,
+                max_tokens: DEFAULT_MAX_TOKENS,
+                stop: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_params_from_model_config() {
+        let context = RequestContext::new(
+            vec![],
+            &[
+                model_config("temperature", "0.2"),
+                model_config("top_p", "0.9"),
+                model_config("max_tokens", "512"),
+            ],
+            vec![],
+        );
+        let params = context.params();
+        assert_eq!(params.temperature, 0.2);
+        assert_eq!(params.top_p, 0.9);
+        assert_eq!(params.max_tokens, 512);
+    }
+
+    #[test]
+    fn test_invalid_value_uses_default() {
+        let context = RequestContext::new(vec![], &[model_config("max_tokens", "abc")], vec![]);
+        assert_eq!(context.params().max_tokens, DEFAULT_MAX_TOKENS);
+    }
+
+    #[test]
+    fn test_config_without_value_is_ignored() {
+        let mut config = model_config("temperature", "0.1");
+        config.value = None;
+        let context = RequestContext::new(vec![], &[config], vec![]);
+        assert!(context.model_config_map.is_empty());
+    }
+
+    #[test]
+    fn test_url() {
+        let context = RequestContext::new(vec![], &[], vec![]);
+        assert_eq!(
+            context.url("https://api.openai.com/v1", "/chat/completions"),
+            "https://api.openai.com/v1/chat/completions"
+        );
+
+        let context = RequestContext::new(
+            vec![provider_config("endpoint", "http://localhost:8080/v1/")],
+            &[],
+            vec![],
+        );
+        assert_eq!(
+            context.url("https://api.openai.com/v1", "/chat/completions"),
+            "http://localhost:8080/v1/chat/completions"
+        );
+    }
+
+    #[test]
+    fn test_empty_endpoint_uses_default() {
+        let context = RequestContext::new(vec![provider_config("endpoint", " ")], &[], vec![]);
+        assert_eq!(
+            context.url("http://localhost:11434", "/api/chat"),
+            "http://localhost:11434/api/chat"
+        );
+    }
+
+    #[test]
+    fn test_stop_sequences() {
+        let context = RequestContext::new(
+            vec![],
+            &[model_config("stop", "[\"###\", \"END\"]")],
+            vec![],
+        );
+        assert_eq!(
+            context.params().stop,
+            Some(vec!["###".to_string(), "END".to_string()])
+        );
+
+        let context = RequestContext::new(vec![], &[model_config("stop", "###\n\nEND")], vec![]);
+        assert_eq!(
+            context.params().stop,
+            Some(vec!["###".to_string(), "END".to_string()])
+        );
+
+        let context = RequestContext::new(vec![], &[model_config("stop", "")], vec![]);
+        assert_eq!(context.params().stop, None);
+    }
+
+    #[test]
+    fn test_response_length() {
+        let configs = [model_config("response_length", "short")];
+        let mut messages = vec![
+            ChatMessage::new(Role::System, "You are a helpful assistant."),
+            ChatMessage::new(Role::User, "Hello"),
+        ];
+        // 不加长度要求时只限制 max_tokens，system 消息保持不变
+        let context = RequestContext::new(vec![], &configs, messages.clone());
+        assert_eq!(context.params().max_tokens, 512);
+        assert_eq!(
+            context.system_prompt().as_deref(),
+            Some("You are a helpful assistant.")
+        );
+        append_response_length_instruction(&mut messages, &configs);
+        let context = RequestContext::new(vec![], &configs, messages);
+        assert_eq!(
+            context.system_prompt().unwrap(),
+            format!(
+                "You are a helpful assistant.\n\n{}",
+                ResponseLength::Short.instruction()
+            )
+        );
+
+        // 助手配置的 max_tokens 更小时保持不变，没有 system 消息时新增一条
+        let configs = [
+            model_config("response_length", "\"long\""),
+            model_config("max_tokens", "1000"),
+        ];
+        let mut messages = vec![ChatMessage::new(Role::User, "Hello")];
+        append_response_length_instruction(&mut messages, &configs);
+        let context = RequestContext::new(vec![], &configs, messages);
+        assert_eq!(context.params().max_tokens, 1000);
+        assert_eq!(context.messages.len(), 2);
+        assert_eq!(
+            context.system_prompt().as_deref(),
+            Some(ResponseLength::Long.instruction())
+        );
+
+        let configs = [model_config("response_length", "")];
+        let mut messages = vec![];
+        append_response_length_instruction(&mut messages, &configs);
+        let context = RequestContext::new(vec![], &configs, messages);
+        assert_eq!(context.response_length(), None);
+        assert!(context.messages.is_empty());
+    }
+
+    #[test]
+    fn test_system_prompt() {
+        let context = RequestContext::new(
+            vec![],
+            &[],
+            vec![
+                ChatMessage::new(Role::System, "You are a helpful assistant."),
+                ChatMessage::new(Role::User, "Hello"),
+            ],
+        );
+        assert_eq!(
+            context.system_prompt().as_deref(),
+            Some("You are a helpful assistant.")
+        );
+
+        let context = RequestContext::new(vec![], &[], vec![ChatMessage::new(Role::User, "Hello")]);
+        assert_eq!(context.system_prompt(), None);
+    }
+}
@@ -0,0 +1,255 @@
+use serde_json::Value;
+
+const DONE_SENTINEL: &str = "[DONE]";
+
+/// 一条 SSE 事件，event 为 `event:` 字段，data 为所有 `data:` 行用换行拼接后的内容
+#[derive(Debug, Clone, PartialEq)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub data: String,
+}
+
+impl SseEvent {
+    /// OpenAI 风格的流在结束时发送 `data: [DONE]`
+    pub fn is_done(&self) -> bool {
+        self.data.trim() == DONE_SENTINEL
+    }
+
+    pub fn json(&self) -> Option<Value> {
+        serde_json::from_str(&self.data).ok()
+    }
+}
+
+/// 各个提供商共用的 SSE 解码器，按字节缓存网络数据，只处理完整的行
+///
+/// - 一个 JSON 被拆到多个网络包时，等到后续数据到达后再解析
+/// - 一个网络包里有多个 `data:` 行时，每个完整的 JSON 单独作为一个事件，不要求事件之间有空行
+/// - 以冒号开头的注释行（keep-alive）会被忽略
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data: Vec<String>,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        SseDecoder::default()
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        // 换行符是单字节，在换行处切分不会截断多字节的 UTF-8 字符
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line = self.buffer.drain(..=pos).collect::<Vec<u8>>();
+            let line = String::from_utf8_lossy(&line[..line.len() - 1]);
+            self.process_line(line.trim_end_matches('\r'), &mut events);
+        }
+        events
+    }
+
+    /// 流结束时调用，处理最后一行没有换行符的数据和还没有分发的事件
+    pub fn finish(&mut self) -> Vec<SseEvent> {
+        let mut events = Vec::new();
+        if !self.buffer.is_empty() {
+            let line = std::mem::take(&mut self.buffer);
+            let line = String::from_utf8_lossy(&line);
+            self.process_line(line.trim_end_matches('\r'), &mut events);
+        }
+        if !self.data.is_empty() {
+            events.push(self.dispatch());
+        }
+        events
+    }
+
+    fn process_line(&mut self, line: &str, events: &mut Vec<SseEvent>) {
+        if line.is_empty() {
+            if !self.data.is_empty() {
+                events.push(self.dispatch());
+            }
+            return;
+        }
+        if line.starts_with(':') {
+            return;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = Some(value.to_string()),
+            "data" => {
+                self.data.push(value.to_string());
+                // 数据已经是完整的 JSON 时马上分发，兼容不用空行分隔事件的提供商
+                let data = self.data.join("\n");
+                if data.trim() == DONE_SENTINEL || serde_json::from_str::<Value>(&data).is_ok() {
+                    events.push(self.dispatch());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn dispatch(&mut self) -> SseEvent {
+        SseEvent {
+            event: self.event.take(),
+            data: std::mem::take(&mut self.data).join("\n"),
+        }
+    }
+}
+
+/// 每行一个 JSON 的流（Ollama），和 SseDecoder 一样按字节缓存，只解析完整的行，
+/// 一个 JSON 被拆到多个网络包或者多字节字符被截断时等到后续数据到达后再解析
+#[derive(Debug, Default)]
+pub struct JsonLinesDecoder {
+    buffer: Vec<u8>,
+}
+
+impl JsonLinesDecoder {
+    pub fn new() -> Self {
+        JsonLinesDecoder::default()
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<Value> {
+        self.buffer.extend_from_slice(chunk);
+        let mut values = Vec::new();
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line = self.buffer.drain(..=pos).collect::<Vec<u8>>();
+            values.extend(parse_json_line(&line));
+        }
+        values
+    }
+
+    /// 流结束时调用，处理最后一行没有换行符的数据
+    pub fn finish(&mut self) -> Vec<Value> {
+        let line = std::mem::take(&mut self.buffer);
+        parse_json_line(&line).into_iter().collect()
+    }
+}
+
+fn parse_json_line(line: &[u8]) -> Option<Value> {
+    let line = String::from_utf8_lossy(line);
+    let line = line.trim();
+    if line.is_empty() {
+        return None;
+    }
+    serde_json::from_str(line).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_harness::Lcg;
+
+    const STREAM: &str = ": keep-alive\n\n\
+        data: {\"choices\":[{\"delta\":{\"content\":\"你好\"}}]}\n\n\
+        data: {\"choices\":[{\"delta\":{\"content\":\"，世界\"}}]}\r\n\r\n\
+        event: message_delta\n\
+        data: {\"delta\":{\"stop_reason\":\"end_turn\"}}\n\n\
+        data: {\"a\":1}\ndata: {\"b\":2}\n\
+        data: [DONE]\n\n";
+
+    fn decode_all(chunks: &[&[u8]]) -> Vec<SseEvent> {
+        let mut decoder = SseDecoder::new();
+        let mut events = Vec::new();
+        for chunk in chunks {
+            events.extend(decoder.push(chunk));
+        }
+        events.extend(decoder.finish());
+        events
+    }
+
+    fn expected() -> Vec<SseEvent> {
+        decode_all(&[STREAM.as_bytes()])
+    }
+
+    #[test]
+    fn test_decode_events() {
+        let events = expected();
+        assert_eq!(events.len(), 6);
+        assert_eq!(
+            events[0].json().unwrap()["choices"][0]["delta"]["content"],
+            "你好"
+        );
+        assert_eq!(
+            events[1].json().unwrap()["choices"][0]["delta"]["content"],
+            "，世界"
+        );
+        assert_eq!(events[2].event.as_deref(), Some("message_delta"));
+        assert_eq!(events[3].data, "{\"a\":1}");
+        assert_eq!(events[4].data, "{\"b\":2}");
+        assert!(events[5].is_done());
+        assert!(events[..5].iter().all(|event| !event.is_done()));
+    }
+
+    #[test]
+    fn test_split_at_every_byte() {
+        let bytes = STREAM.as_bytes();
+        for pos in 0..=bytes.len() {
+            assert_eq!(
+                decode_all(&[&bytes[..pos], &bytes[pos..]]),
+                expected(),
+                "split at {}",
+                pos
+            );
+        }
+    }
+
+    #[test]
+    fn test_fuzz_random_chunks() {
+        let bytes = STREAM.as_bytes();
+        let mut rng = Lcg(42);
+        for _ in 0..500 {
+            let mut chunks = Vec::new();
+            let mut start = 0;
+            while start < bytes.len() {
+                let end = (start + 1 + rng.next(16)).min(bytes.len());
+                chunks.push(&bytes[start..end]);
+                start = end;
+            }
+            assert_eq!(decode_all(&chunks), expected());
+        }
+    }
+
+    #[test]
+    fn test_multiline_json_data() {
+        let events = decode_all(&[b"data: {\"a\":\ndata: 1}\n\n"]);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].json().unwrap()["a"], 1);
+    }
+
+    #[test]
+    fn test_finish_without_trailing_newline() {
+        let events = decode_all(&[b"data: {\"a\":1}\n\ndata: [DONE]"]);
+        assert_eq!(events.len(), 2);
+        assert!(events[1].is_done());
+    }
+
+    #[test]
+    fn test_json_lines_random_chunks() {
+        let stream = "{\"message\":{\"content\":\"你好😀\"},\"done\":false}\n\
+            {\"message\":{\"content\":\"，世界\"},\"done\":false}\r\n\
+            {\"message\":{\"content\":\"\"},\"done\":true}";
+        let bytes = stream.as_bytes();
+        let mut rng = Lcg(7);
+        for _ in 0..500 {
+            let mut decoder = JsonLinesDecoder::new();
+            let mut values = Vec::new();
+            let mut start = 0;
+            while start < bytes.len() {
+                let end = (start + 1 + rng.next(16)).min(bytes.len());
+                values.extend(decoder.push(&bytes[start..end]));
+                start = end;
+            }
+            values.extend(decoder.finish());
+            let content = values
+                .iter()
+                .filter_map(|value| value["message"]["content"].as_str())
+                .collect::<String>();
+            assert_eq!(content, "你好😀，世界");
+            assert_eq!(values[2]["done"], true);
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use std::time::{Duration, Instant};
+
+// 两次发送之间的最短间隔
+pub const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_millis(50);
+// 对齐到空白时最多缓存的字符数，超过后不再等待，避免很长的链接、代码等一直不显示
+const MAX_PENDING_CHARS: usize = 64;
+const ZERO_WIDTH_JOINER: char = '\u{200D}';
+
+/// 合并流式生成的内容再发送给前端，减少事件数量和前端增量渲染 markdown 时的闪烁
+///
+/// 输入和输出都是目前为止的完整内容。切分位置总是在字符边界上，不会截断多字节的 UTF-8 字符，
+/// 前端按 UTF-16 处理时也不会拆开代理对；零宽连接符、变体选择符、组合附加符号和前一个字符一起发送，
+/// 避免 emoji 和带声调的字母显示一半
+pub struct StreamBatcher {
+    // 上次发送的内容
+    flushed: String,
+    last_flush: Option<Instant>,
+    // 最近一次 push 的内容，生成停顿时由 flush_pending 发送还缓存着的部分
+    pending: String,
+    last_push: Option<Instant>,
+    interval: Duration,
+    // 只在空白或中日韩字符之后切分，避免把 `**`、链接等 markdown 标记拆开
+    align_whitespace: bool,
+}
+
+impl StreamBatcher {
+    pub fn new(interval: Duration, align_whitespace: bool) -> Self {
+        StreamBatcher {
+            flushed: String::new(),
+            last_flush: None,
+            pending: String::new(),
+            last_push: None,
+            interval,
+            align_whitespace,
+        }
+    }
+
+    /// 修改发送间隔，下一次 push 时生效
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// content 为目前为止生成的完整内容，返回这次需要发送的内容，None 表示先缓存
+    pub fn push(&mut self, content: &str) -> Option<String> {
+        self.push_at(content, Instant::now())
+    }
+
+    /// 还有缓存的内容时，生成停顿多久之后需要调用 flush_pending，没有可以发送的内容时为 None
+    ///
+    /// 提供商停顿时不会再有新的 push，缓存着的内容要靠定时发送，否则要等到下一段内容到达才显示
+    pub fn flush_deadline(&self) -> Option<Instant> {
+        let last_push = self.last_push?;
+        flush_end(&self.pending, self.flushed.len(), false)?;
+        let deadline = last_push + self.interval;
+        Some(match self.last_flush {
+            Some(last_flush) => deadline.max(last_flush + self.interval),
+            None => deadline,
+        })
+    }
+
+    /// 到达 flush_deadline 时调用，发送缓存的内容，不再等待对齐到空白
+    pub fn flush_pending(&mut self) -> Option<String> {
+        self.flush_pending_at(Instant::now())
+    }
+
+    /// 生成结束时发送剩下的内容，返回完整的内容，已经全部发送过时返回 None
+    pub fn finish(&mut self, content: &str) -> Option<String> {
+        if self.last_flush.is_some() && self.flushed == content {
+            return None;
+        }
+        Some(self.flush(content, Instant::now()))
+    }
+
+    fn push_at(&mut self, content: &str, now: Instant) -> Option<String> {
+        self.pending = content.to_string();
+        self.last_push = Some(now);
+        // 重试时内容会重新生成，和已经发送的内容不一致时马上发送，让前端尽快替换
+        if !content.starts_with(self.flushed.as_str()) {
+            return Some(self.flush(content, now));
+        }
+        if self
+            .last_flush
+            .is_some_and(|last| now.duration_since(last) < self.interval)
+        {
+            return None;
+        }
+        let end = flush_end(content, self.flushed.len(), self.align_whitespace)?;
+        Some(self.flush(&content[..end], now))
+    }
+
+    fn flush_pending_at(&mut self, now: Instant) -> Option<String> {
+        let end = flush_end(&self.pending, self.flushed.len(), false)?;
+        let content = self.pending[..end].to_string();
+        Some(self.flush(&content, now))
+    }
+
+    fn flush(&mut self, content: &str, now: Instant) -> String {
+        self.flushed = content.to_string();
+        self.last_flush = Some(now);
+        content.to_string()
+    }
+}
+
+// 返回 start 之后最后一个可以切分的位置，没有新的可切分位置时返回 None
+fn flush_end(content: &str, start: usize, align_whitespace: bool) -> Option<usize> {
+    let pending = &content[start..];
+    let force = pending.chars().count() > MAX_PENDING_CHARS;
+    let mut chars = pending.char_indices().peekable();
+    let mut end = None;
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|(_, next)| *next);
+        if c == ZERO_WIDTH_JOINER || next.is_some_and(is_extending) {
+            continue;
+        }
+        if !align_whitespace || force || is_break_char(c) {
+            end = Some(start + i + c.len_utf8());
+        }
+    }
+    end
+}
+
+// 需要和前一个字符组合显示的字符
+fn is_extending(c: char) -> bool {
+    matches!(c,
+        '\u{0300}'..='\u{036F}'
+        | '\u{200D}'
+        | '\u{20E3}'
+        | '\u{FE00}'..='\u{FE0F}'
+        | '\u{1F3FB}'..='\u{1F3FF}'
+        | '\u{E0020}'..='\u{E007F}')
+}
+
+// 中日韩文字没有空格分词，每个字符之后都可以切分
+fn is_break_char(c: char) -> bool {
+    c.is_whitespace()
+        || matches!(c,
+            '\u{3000}'..='\u{303F}'
+            | '\u{3040}'..='\u{30FF}'
+            | '\u{4E00}'..='\u{9FFF}'
+            | '\u{AC00}'..='\u{D7AF}'
+            | '\u{FF00}'..='\u{FFEF}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_harness::Lcg;
+
+    // 覆盖 1 到 4 字节的 UTF-8 字符、代理对、组合符号和 emoji 序列
+    const PIECES: &[&str] = &[
+        "a",
+        "Z",
+        " ",
+        "\n",
+        "**",
+        "`",
+        "[链接](",
+        "é",
+        "e\u{0301}",
+        "中",
+        "文",
+        "，",
+        "。",
+        "한",
+        "カ",
+        "😀",
+        "👍🏽",
+        "👨\u{200D}👩\u{200D}👧",
+        "❤\u{FE0F}",
+        "1\u{FE0F}\u{20E3}",
+        "𝒳",
+        "🇨🇳",
+    ];
+
+    fn random_content(rng: &mut Lcg) -> String {
+        (0..1 + rng.next(80))
+            .map(|_| PIECES[rng.next(PIECES.len())])
+            .collect()
+    }
+
+    fn assert_valid_flush(flushed: &str, content: &str) {
+        assert!(content.starts_with(flushed));
+        assert!(!flushed.ends_with(ZERO_WIDTH_JOINER));
+        if let Some(next) = content[flushed.len()..].chars().next() {
+            assert!(!is_extending(next), "split before {:?}", next);
+        }
+        let utf16 = flushed.encode_utf16().collect::<Vec<u16>>();
+        assert_eq!(String::from_utf16(&utf16).unwrap(), flushed);
+    }
+
+    #[test]
+    fn test_random_unicode_streams() {
+        let mut rng = Lcg(7);
+        for round in 0..500 {
+            let content = random_content(&mut rng);
+            let mut batcher = StreamBatcher::new(DEFAULT_FLUSH_INTERVAL, round % 2 == 0);
+            let mut now = Instant::now();
+            let mut end = 0;
+            let mut last = String::new();
+            while end < content.len() {
+                // 每次到达随机数量的字符，模拟提供商返回的增量
+                end = content[end..]
+                    .char_indices()
+                    .nth(1 + rng.next(4))
+                    .map(|(i, _)| end + i)
+                    .unwrap_or(content.len());
+                now += Duration::from_millis(rng.next(40) as u64);
+                if let Some(flushed) = batcher.push_at(&content[..end], now) {
+                    assert_valid_flush(&flushed, &content[..end]);
+                    assert!(flushed.starts_with(&last));
+                    last = flushed;
+                }
+            }
+            assert_eq!(batcher.finish(&content).unwrap_or(last), content);
+        }
+    }
+
+    #[test]
+    fn test_align_to_whitespace() {
+        let mut batcher = StreamBatcher::new(Duration::ZERO, true);
+        assert_eq!(batcher.push("Hello"), None);
+        assert_eq!(batcher.push("Hello **wor").as_deref(), Some("Hello "));
+        assert_eq!(
+            batcher.push("Hello **world** ").as_deref(),
+            Some("Hello **world** ")
+        );
+        assert_eq!(
+            batcher.push("Hello **world** 你好").as_deref(),
+            Some("Hello **world** 你好")
+        );
+    }
+
+    #[test]
+    fn test_interval_and_retry() {
+        let mut batcher = StreamBatcher::new(Duration::from_secs(60), false);
+        assert_eq!(batcher.push("第一").as_deref(), Some("第一"));
+        assert_eq!(batcher.push("第一次"), None);
+        assert_eq!(batcher.finish("第一次").as_deref(), Some("第一次"));
+        assert_eq!(batcher.finish("第一次"), None);
+        // 重试后内容和之前的不一致，不等间隔马上发送
+        assert_eq!(batcher.push("重试").as_deref(), Some("重试"));
+    }
+
+    #[test]
+    fn test_flush_pending_after_pause() {
+        let mut batcher = StreamBatcher::new(Duration::from_millis(50), true);
+        let start = Instant::now();
+        assert_eq!(batcher.flush_deadline(), None);
+        assert_eq!(
+            batcher.push_at("Hello wor", start).as_deref(),
+            Some("Hello ")
+        );
+        assert_eq!(batcher.push_at("Hello world", start), None);
+        // 之后没有新的内容，到时间后不等空白直接发送
+        let deadline = batcher.flush_deadline().unwrap();
+        assert_eq!(deadline, start + Duration::from_millis(50));
+        assert_eq!(
+            batcher.flush_pending_at(deadline).as_deref(),
+            Some("Hello world")
+        );
+        assert_eq!(batcher.flush_deadline(), None);
+        assert_eq!(batcher.flush_pending_at(deadline), None);
+
+        // 只剩下还不能切分的零宽连接符时不需要定时发送
+        batcher.push_at("Hello world 👨\u{200D}", deadline);
+        batcher.flush_pending_at(deadline + Duration::from_millis(50));
+        assert_eq!(batcher.flush_deadline(), None);
+    }
+
+    #[test]
+    fn test_keep_emoji_sequence_together() {
+        let mut batcher = StreamBatcher::new(Duration::ZERO, false);
+        assert_eq!(batcher.push("hi 👨\u{200D}").as_deref(), Some("hi "));
+        assert_eq!(
+            batcher.push("hi 👨\u{200D}👩").as_deref(),
+            Some("hi 👨\u{200D}👩")
+        );
+    }
+}
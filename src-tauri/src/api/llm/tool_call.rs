@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use anyhow::Result;
+use tokio::sync::mpsc;
+
+use crate::tools::{find_tool, ToolDefinition};
+
+use super::chat_message::{ChatMessage, ContentPart, Role};
+use super::tool_calls_event;
+
+// 助手模型参数中提供给模型的内置工具，逗号分隔的工具名称
+pub const TOOLS_CONFIG: &str = "tools";
+
+/// 模型请求调用的一个工具，arguments 为模型生成的参数
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: Value,
+}
+
+/// 工具调用的结果，保存在 role 为 tool 的消息中，各个提供商转换成自己的格式
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolResult {
+    pub tool_use_id: String,
+    pub content: String,
+    pub is_error: Option<bool>,
+}
+
+/// 逗号分隔的工具名称对应的工具定义，未知的工具和重复的名称忽略
+pub fn configured_tools(value: &str) -> Vec<ToolDefinition> {
+    let mut definitions: Vec<ToolDefinition> = Vec::new();
+    for name in value.trim().trim_matches('"').split(',') {
+        let name = name.trim();
+        if name.is_empty() || definitions.iter().any(|d| d.name == name) {
+            continue;
+        }
+        match find_tool(name) {
+            Some(tool) => definitions.push(tool.definition()),
+            None => println!("ignore unknown tool {}", name),
+        }
+    }
+    definitions
+}
+
+/// 模型请求调用工具的助手消息，工具调用按 Anthropic 的 tool_use 内容块保存
+pub fn tool_use_message(text: &str, tool_calls: &[ToolCall]) -> ChatMessage {
+    let blocks = tool_calls
+        .iter()
+        .map(|call| {
+            json!({
+                "type": "tool_use",
+                "id": call.id,
+                "name": call.name,
+                "input": call.arguments,
+            })
+        })
+        .collect::<Vec<Value>>();
+    let mut message = ChatMessage::new(Role::Assistant, text);
+    message.content.push(ContentPart::Blocks(json!(blocks)));
+    message
+}
+
+pub fn tool_result_message(result: &ToolResult) -> ChatMessage {
+    ChatMessage::new(
+        Role::Tool,
+        serde_json::to_string(result).unwrap_or_default(),
+    )
+}
+
+/// role 为 tool 的消息中保存的工具结果
+pub fn parse_tool_result(message: &ChatMessage) -> Option<ToolResult> {
+    if message.role != Role::Tool {
+        return None;
+    }
+    serde_json::from_str(&message.text()).ok()
+}
+
+/// 消息中由 tool_use_message 保存的工具调用
+pub fn message_tool_calls(message: &ChatMessage) -> Vec<ToolCall> {
+    message
+        .blocks()
+        .filter_map(|blocks| blocks.as_array())
+        .flatten()
+        .filter(|block| block["type"] == "tool_use")
+        .map(|block| ToolCall {
+            id: block["id"].as_str().unwrap_or_default().to_string(),
+            name: block["name"].as_str().unwrap_or_default().to_string(),
+            arguments: block["input"].clone(),
+        })
+        .collect()
+}
+
+// 模型生成的参数是 JSON 字符串，解析失败时原样交给工具，由工具返回参数错误
+fn parse_arguments(arguments: &str) -> Value {
+    if arguments.trim().is_empty() {
+        return json!({});
+    }
+    serde_json::from_str(arguments).unwrap_or_else(|_| Value::String(arguments.to_string()))
+}
+
+/// OpenAI 格式中非流式回答的 tool_calls
+pub fn parse_openai_tool_calls(message: &Value) -> Vec<ToolCall> {
+    message["tool_calls"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|call| ToolCall {
+            id: call["id"].as_str().unwrap_or_default().to_string(),
+            name: call["function"]["name"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            arguments: parse_arguments(call["function"]["arguments"].as_str().unwrap_or("")),
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct PartialToolCall {
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+/// 流式回答中的工具调用分散在多个事件中，按序号拼接 id、名称和参数
+#[derive(Default)]
+pub struct ToolCallAccumulator {
+    calls: BTreeMap<u64, PartialToolCall>,
+}
+
+impl ToolCallAccumulator {
+    /// OpenAI 格式的 delta.tool_calls，同一个调用的后续事件只带有 index 和参数片段
+    pub fn push_openai_event(&mut self, value: &Value) {
+        for call in value["choices"][0]["delta"]["tool_calls"]
+            .as_array()
+            .into_iter()
+            .flatten()
+        {
+            let index = call["index"].as_u64().unwrap_or(0);
+            let partial = self.calls.entry(index).or_default();
+            if let Some(id) = call["id"].as_str() {
+                partial.id = id.to_string();
+            }
+            if let Some(name) = call["function"]["name"].as_str() {
+                partial.name.push_str(name);
+            }
+            if let Some(arguments) = call["function"]["arguments"].as_str() {
+                partial.arguments.push_str(arguments);
+            }
+        }
+    }
+
+    /// Anthropic 的 content_block_start 带有 tool_use 的 id 和名称，
+    /// 之后的 content_block_delta 中 input_json_delta 是参数片段
+    pub fn push_anthropic_event(&mut self, value: &Value) {
+        let index = value["index"].as_u64().unwrap_or(0);
+        match value["type"].as_str() {
+            Some("content_block_start") if value["content_block"]["type"] == "tool_use" => {
+                let block = &value["content_block"];
+                self.calls.insert(
+                    index,
+                    PartialToolCall {
+                        id: block["id"].as_str().unwrap_or_default().to_string(),
+                        name: block["name"].as_str().unwrap_or_default().to_string(),
+                        arguments: String::new(),
+                    },
+                );
+            }
+            Some("content_block_delta") if value["delta"]["type"] == "input_json_delta" => {
+                if let Some(partial) = self.calls.get_mut(&index) {
+                    partial
+                        .arguments
+                        .push_str(value["delta"]["partial_json"].as_str().unwrap_or(""));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn finish(self) -> Vec<ToolCall> {
+        self.calls
+            .into_values()
+            .filter(|partial| !partial.name.is_empty())
+            .map(|partial| ToolCall {
+                id: partial.id,
+                name: partial.name,
+                arguments: parse_arguments(&partial.arguments),
+            })
+            .collect()
+    }
+}
+
+/// 在结束标记之前发送拼接好的工具调用，没有工具调用时不发送
+pub(super) async fn send_tool_calls(
+    accumulator: &mut ToolCallAccumulator,
+    message_id: i64,
+    tx: &mpsc::Sender<(i64, String, bool)>,
+) -> Result<()> {
+    let tool_calls = std::mem::take(accumulator).finish();
+    if !tool_calls.is_empty() {
+        tx.send((message_id, tool_calls_event(&tool_calls), false))
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::llm::sse::SseDecoder;
+
+    #[test]
+    fn test_configured_tools() {
+        let names = configured_tools("calculate, read_file,calculate,not_exist")
+            .into_iter()
+            .map(|definition| definition.name)
+            .collect::<Vec<String>>();
+        assert_eq!(names, vec!["calculate", "read_file"]);
+        assert!(configured_tools("").is_empty());
+    }
+
+    #[test]
+    fn test_accumulate_openai_stream() {
+        let stream = "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"function\":{\"name\":\"calculate\",\"arguments\":\"\"}}]}}]}\n\n\
+            data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"expression\\\":\"}}]}}]}\n\n\
+            data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\"1+1\\\"}\"}}]}}]}\n\n";
+        let mut decoder = SseDecoder::new();
+        let mut accumulator = ToolCallAccumulator::default();
+        for event in decoder.push(stream.as_bytes()) {
+            accumulator.push_openai_event(&event.json().unwrap());
+        }
+        assert_eq!(
+            accumulator.finish(),
+            vec![ToolCall {
+                id: "call_1".to_string(),
+                name: "calculate".to_string(),
+                arguments: json!({ "expression": "1+1" }),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_accumulate_anthropic_stream() {
+        let mut accumulator = ToolCallAccumulator::default();
+        for event in [
+            json!({"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": ""}}),
+            json!({"type": "content_block_start", "index": 1, "content_block": {"type": "tool_use", "id": "toolu_1", "name": "read_file", "input": {}}}),
+            json!({"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": "{\"path\": \"/tmp"}}),
+            json!({"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": "\"}"}}),
+        ] {
+            accumulator.push_anthropic_event(&event);
+        }
+        let calls = accumulator.finish();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].id, "toolu_1");
+        assert_eq!(calls[0].arguments, json!({ "path": "/tmp" }));
+    }
+
+    #[test]
+    fn test_tool_messages_round_trip() {
+        let calls = vec![ToolCall {
+            id: "call_1".to_string(),
+            name: "calculate".to_string(),
+            arguments: json!({ "expression": "1+1" }),
+        }];
+        assert_eq!(message_tool_calls(&tool_use_message("", &calls)), calls);
+        let result = ToolResult {
+            tool_use_id: "call_1".to_string(),
+            content: "2".to_string(),
+            is_error: None,
+        };
+        assert_eq!(
+            parse_tool_result(&tool_result_message(&result)),
+            Some(result)
+        );
+        // 用户消息中的同样内容不是工具结果
+        assert_eq!(
+            parse_tool_result(&ChatMessage::new(
+                Role::User,
+                r#"{"tool_use_id":"1","content":"x"}"#
+            )),
+            None
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use crate::{api::llm_api::LlmModel, db::llm_db::LLMProviderConfig};
+
+use super::{
+    capability::{get_model_capability, set_clamped_param},
+    chat_message::ChatMessage,
+    check_response_status,
+    mock::FixtureRecorder,
+    openai::{send_stream_event, token_usage},
+    request_builder::{apply_request_headers, AuthScheme},
+    request_context::RequestContext,
+    send_request, shared_client,
+    sse::SseDecoder,
+    ChatResponse, ModelProvider, ProviderClient,
+};
+use futures::StreamExt;
+
+const DEFAULT_ENDPOINT: &str = "https://open.bigmodel.cn/api/paas/v4";
+
+// 智谱没有列出模型的接口，使用常用模型作为模型列表，(模型代码, 是否支持视觉)
+const DEFAULT_MODELS: &[(&str, bool)] = &[
+    ("glm-4-plus", false),
+    ("glm-4-air", false),
+    ("glm-4-flash", false),
+    ("glm-4-long", false),
+    ("glm-4v-plus", true),
+    ("glm-4v-flash", true),
+];
+
+/// 智谱 AI 开放平台，用于 GLM 系列模型，接口格式和 OpenAI 基本一致
+pub struct ZhipuProvider {
+    llm_provider_config: Vec<LLMProviderConfig>,
+    client: ProviderClient,
+}
+
+impl ModelProvider for ZhipuProvider {
+    fn new(llm_provider_config: Vec<crate::db::llm_db::LLMProviderConfig>) -> Self
+    where
+        Self: Sized,
+    {
+        ZhipuProvider {
+            llm_provider_config,
+            client: shared_client(),
+        }
+    }
+
+    fn chat(
+        &self,
+        _message_id: i64,
+        messages: Vec<ChatMessage>,
+        model_config: Vec<crate::db::assistant_db::AssistantModelConfig>,
+        cancel_token: CancellationToken,
+    ) -> futures::future::BoxFuture<'static, Result<ChatResponse>> {
+        let config = self.llm_provider_config.clone();
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            let client = client.get()?;
+            let context = RequestContext::new(config, &model_config, messages);
+            let url = context.url(DEFAULT_ENDPOINT, "/chat/completions");
+            let body = build_body(&context, false);
+            println!("zhipu chat: {:?}", body);
+
+            let request =
+                apply_request_headers(client.post(&url), &context.config_map, AuthScheme::Bearer)?
+                    .json(&body);
+
+            let response = tokio::select! {
+                response = send_request(request) => response?,
+                _ = cancel_token.cancelled() => bail!("Request cancelled"),
+            };
+
+            let response = check_response_status(response).await?;
+
+            let json_response = tokio::select! {
+                json = response.json::<Value>() => json?,
+                _ = cancel_token.cancelled() => bail!("Request cancelled"),
+            };
+
+            println!("zhipu chat response: {:?}", json_response.clone());
+
+            if let Some(content) = json_response["choices"][0]["message"]["content"].as_str() {
+                Ok(ChatResponse {
+                    content: content.to_string(),
+                    finish_reason: json_response["choices"][0]["finish_reason"]
+                        .as_str()
+                        .map(|s| s.to_string()),
+                    usage: token_usage(&json_response),
+                    ..Default::default()
+                })
+            } else {
+                bail!("Failed to get content from response")
+            }
+        })
+    }
+
+    fn chat_stream(
+        &self,
+        message_id: i64,
+        messages: Vec<ChatMessage>,
+        model_config: Vec<crate::db::assistant_db::AssistantModelConfig>,
+        tx: tokio::sync::mpsc::Sender<(i64, String, bool)>,
+        cancel_token: CancellationToken,
+    ) -> futures::future::BoxFuture<'static, Result<()>> {
+        let config = self.llm_provider_config.clone();
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            let client = client.get()?;
+            let context = RequestContext::new(config, &model_config, messages);
+            let url = context.url(DEFAULT_ENDPOINT, "/chat/completions");
+            let body = build_body(&context, true);
+            println!("zhipu chat stream url: {} body: {:?}", url, body);
+
+            let request =
+                apply_request_headers(client.post(&url), &context.config_map, AuthScheme::Bearer)?
+                    .json(&body);
+
+            let response = tokio::select! {
+                response = send_request(request) => response?,
+                _ = cancel_token.cancelled() => bail!("Request cancelled"),
+            };
+
+            let response = check_response_status(response).await?;
+
+            let mut stream = response.bytes_stream();
+            let mut full_text = String::new();
+            let mut decoder = SseDecoder::new();
+            let mut recorder = FixtureRecorder::from_config(&context.config_map);
+            // 收到 finish_reason 或 [DONE] 才算正常结束，否则是连接中断
+            let mut finished = false;
+
+            loop {
+                tokio::select! {
+                    chunk = stream.next() => {
+                        match chunk {
+                            Some(Ok(chunk)) => {
+                                if let Some(recorder) = recorder.as_mut() {
+                                    recorder.record(&chunk);
+                                }
+                                let text = String::from_utf8_lossy(&chunk);
+                                println!("zhipu chat stream text: {}", text);
+
+                                // 数据块格式和 OpenAI 相同，最后一个数据块同时带有 finish_reason 和 usage
+                                for event in decoder.push(&chunk) {
+                                    if event.is_done() {
+                                        tx.send((message_id, full_text.clone(), true)).await?;
+                                        return Ok(());
+                                    }
+                                    finished |= send_stream_event(&event, message_id, &mut full_text, &tx).await?;
+                                }
+                            }
+                            Some(Err(e)) => bail!(e),
+                            None => {
+                                println!("zhipu chat stream end");
+                                for event in decoder.finish() {
+                                    if event.is_done() {
+                                        finished = true;
+                                    } else {
+                                        finished |= send_stream_event(&event, message_id, &mut full_text, &tx).await?;
+                                    }
+                                }
+                                if !finished {
+                                    // 不发送结束标记，由接收方按连接中断处理
+                                    println!("zhipu chat stream interrupted");
+                                    return Ok(());
+                                }
+                                tx.send((message_id, full_text.clone(), true)).await?;
+                                return Ok(());
+                            },
+                        }
+                    }
+                    _ = cancel_token.cancelled() => {
+                        tx.send((message_id, full_text.clone(), true)).await?;
+                        return Ok(());
+                    }
+                }
+            }
+        })
+    }
+
+    fn models(&self) -> futures::future::BoxFuture<'static, Result<Vec<LlmModel>>> {
+        Box::pin(async move {
+            Ok(DEFAULT_MODELS
+                .iter()
+                .map(|(code, vision_support)| LlmModel {
+                    id: 0,
+                    name: code.to_string(),
+                    llm_provider_id: 0,
+                    code: code.to_string(),
+                    description: format!("智谱 {}", code),
+                    vision_support: *vision_support,
+                    audio_support: false,
+                    video_support: false,
+                    is_enabled: true,
+                })
+                .collect())
+        })
+    }
+}
+
+// chat 和 chat_stream 共用的请求体
+fn build_body(context: &RequestContext, stream: bool) -> Value {
+    let params = context.params();
+    let model_code = params.model.as_deref().unwrap_or("");
+    let vision_support = get_model_capability("zhipu", model_code).vision_support;
+    let mut body = json!({
+        "model": params.model,
+        "max_tokens": params.max_tokens,
+        "messages": build_messages(&context.messages, vision_support),
+        "stream": stream
+    });
+    // 智谱要求 top_p 在 (0, 1) 之间，传 1 会返回参数错误
+    set_clamped_param(
+        &mut body,
+        "temperature",
+        "zhipu",
+        model_code,
+        "temperature",
+        params.temperature,
+    );
+    set_clamped_param(
+        &mut body,
+        "top_p",
+        "zhipu",
+        model_code,
+        "top_p",
+        params.top_p,
+    );
+    if let Some(stop) = params.stop {
+        body["stop"] = json!(stop);
+    }
+    body
+}
+
+fn build_messages(messages: &[ChatMessage], vision_support: bool) -> Vec<Value> {
+    messages
+        .iter()
+        .map(|message| {
+            let images = message
+                .images()
+                .filter_map(|attachment| attachment.attachment_content.as_deref())
+                .collect::<Vec<_>>();
+            if !vision_support || images.is_empty() {
+                return json!({
+                    "role": message.role,
+                    "content": message.text()
+                });
+            }
+            // GLM-4V 的图片只接受 URL 或者不带 data: 前缀的 base64
+            let mut content = images
+                .into_iter()
+                .map(|image| {
+                    let url = image
+                        .split_once(";base64,")
+                        .map(|(_, data)| data)
+                        .unwrap_or(image);
+                    json!({ "type": "image_url", "image_url": { "url": url } })
+                })
+                .collect::<Vec<Value>>();
+            content.push(json!({ "type": "text", "text": message.text() }));
+            json!({
+                "role": message.role,
+                "content": content
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::llm::chat_message::Role;
+    use crate::db::conversation_db::{AttachmentType, MessageAttachment};
+
+    #[test]
+    fn test_build_vision_messages() {
+        let image = MessageAttachment {
+            id: 1,
+            message_id: Some(1),
+            attachment_type: AttachmentType::Image,
+            attachment_url: None,
+            attachment_content: Some("data:image/png;base64,AAAA".to_string()),
+            attachment_hash: None,
+            use_vector: false,
+            token_count: None,
+            ocr_text: None,
+            encoding: None,
+        };
+        let messages = vec![ChatMessage::with_attachments(
+            Role::User,
+            "这是什么",
+            vec![image],
+        )];
+        let json_messages = build_messages(&messages, true);
+        assert_eq!(json_messages[0]["content"][0]["image_url"]["url"], "AAAA");
+        assert_eq!(json_messages[0]["content"][1]["text"], "这是什么");
+
+        // 不支持视觉的模型只发送文字
+        let json_messages = build_messages(&messages, false);
+        assert_eq!(json_messages[0]["content"], "这是什么");
+    }
+}
@@ -1,5 +1,20 @@
-use crate::{api::llm::get_provider, db::llm_db::LLMDatabase};
+use crate::AppHandle;
+use crate::{
+    api::llm::{
+        self, diagnose_provider, get_provider, is_local_endpoint, is_local_provider,
+        normalize_host, set_egress_allowlist as apply_egress_allowlist, ModelParamSpec,
+        ProviderDiagnostics,
+    },
+    db::{conversation_db::ConversationDatabase, llm_db::LLMDatabase, system_db::SystemDatabase},
+    errors::AppError,
+    state::response_cache::{ResponseCacheState, ResponseCacheStats},
+    state::tokenizer::TokenizerState,
+    tokenizer::count_words,
+    window::set_system_config,
+};
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tauri::{Emitter, Manager};
 
 #[derive(Serialize, Deserialize)]
 pub struct LlmProvider {
@@ -21,6 +36,13 @@ pub struct LlmModel {
     pub vision_support: bool,
     pub audio_support: bool,
     pub video_support: bool,
+    // 隐藏的模型不出现在模型选择列表中
+    #[serde(default = "default_true")]
+    pub is_enabled: bool,
+}
+
+fn default_true() -> bool {
+    true
 }
 
 #[derive(Serialize, Deserialize)]
@@ -34,7 +56,7 @@ pub struct LlmProviderConfig {
 }
 
 #[tauri::command]
-pub async fn get_llm_providers(app_handle: tauri::AppHandle) -> Result<Vec<LlmProvider>, String> {
+pub async fn get_llm_providers(app_handle: AppHandle) -> Result<Vec<LlmProvider>, String> {
     let db = LLMDatabase::new(&app_handle).map_err(|e: rusqlite::Error| e.to_string())?;
     let providers = db.get_llm_providers().map_err(|e| e.to_string())?;
     let mut result = Vec::new();
@@ -53,7 +75,7 @@ pub async fn get_llm_providers(app_handle: tauri::AppHandle) -> Result<Vec<LlmPr
 
 #[tauri::command]
 pub async fn add_llm_provider(
-    app: tauri::AppHandle,
+    app: AppHandle,
     name: String,
     api_type: String,
 ) -> Result<(), String> {
@@ -65,7 +87,7 @@ pub async fn add_llm_provider(
 
 #[tauri::command]
 pub async fn update_llm_provider(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
     id: i64,
     name: String,
     api_type: String,
@@ -80,7 +102,7 @@ pub async fn update_llm_provider(
 
 #[tauri::command]
 pub async fn delete_llm_provider(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
     llm_provider_id: i64,
 ) -> Result<(), String> {
     let db = LLMDatabase::new(&app_handle).map_err(|e| e.to_string())?;
@@ -91,7 +113,7 @@ pub async fn delete_llm_provider(
 
 #[tauri::command]
 pub async fn get_llm_provider_config(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
     id: i64,
 ) -> Result<Vec<LlmProviderConfig>, String> {
     let db = LLMDatabase::new(&app_handle).map_err(|e| e.to_string())?;
@@ -112,7 +134,7 @@ pub async fn get_llm_provider_config(
 
 #[tauri::command]
 pub async fn update_llm_provider_config(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
     llm_provider_id: i64,
     name: String,
     value: String,
@@ -125,42 +147,33 @@ pub async fn update_llm_provider_config(
 
 #[tauri::command]
 pub async fn get_llm_models(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
     llm_provider_id: String,
 ) -> Result<Vec<LlmModel>, String> {
     let db = LLMDatabase::new(&app_handle).map_err(|e| e.to_string())?;
     let models = db
         .get_llm_models(llm_provider_id)
         .map_err(|e| e.to_string())?;
-    let mut result = Vec::new();
-    for (
-        id,
-        name,
-        llm_provider_id,
-        code,
-        description,
-        vision_support,
-        audio_support,
-        video_support,
-    ) in models
-    {
-        result.push(LlmModel {
-            id,
-            name,
-            llm_provider_id,
-            code,
-            description,
-            vision_support,
-            audio_support,
-            video_support,
-        });
-    }
+    let result = models
+        .into_iter()
+        .map(|model| LlmModel {
+            id: model.id,
+            name: model.name,
+            llm_provider_id: model.llm_provider_id,
+            code: model.code,
+            description: model.description,
+            vision_support: model.vision_support,
+            audio_support: model.audio_support,
+            video_support: model.video_support,
+            is_enabled: model.is_enabled,
+        })
+        .collect();
     Ok(result)
 }
 
 #[tauri::command]
 pub async fn fetch_model_list(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
     llm_provider_id: i64,
 ) -> Result<Vec<LlmModel>, String> {
     let db = LLMDatabase::new(&app_handle).map_err(|e| e.to_string())?;
@@ -171,14 +184,29 @@ pub async fn fetch_model_list(
         .get_llm_provider_config(llm_provider_id)
         .map_err(|e| e.to_string())?;
 
-    let provider = get_provider(llm_provider, llm_provider_config);
+    if is_offline_mode(&app_handle) && !is_local_provider(&llm_provider, &llm_provider_config) {
+        return Err(format!(
+            "当前处于离线模式，不能从 {} 获取模型列表",
+            llm_provider.name
+        ));
+    }
+
+    let provider = get_provider(llm_provider, llm_provider_config).map_err(|e| e.to_string())?;
 
     let models_future = provider.models();
     match models_future.await {
-        Ok(models) => {
+        Ok(mut models) => {
+            // 重新获取模型列表后保留用户隐藏的模型
+            let disabled_codes: Vec<String> = db
+                .get_llm_models(llm_provider_id.to_string())
+                .map_err(|e| e.to_string())?
+                .into_iter()
+                .filter(|model| !model.is_enabled)
+                .map(|model| model.code)
+                .collect();
             db.delete_llm_model_by_provider(llm_provider_id)
                 .map_err(|e| e.to_string())?;
-            for model in &models {
+            for model in models.iter_mut() {
                 println!("Model: {:?}", model);
                 db.add_llm_model(
                     &model.name,
@@ -190,6 +218,11 @@ pub async fn fetch_model_list(
                     model.video_support,
                 )
                 .map_err(|e| e.to_string())?;
+                if disabled_codes.contains(&model.code) {
+                    db.update_llm_model_enabled_by_code(llm_provider_id, &model.code, false)
+                        .map_err(|e| e.to_string())?;
+                    model.is_enabled = false;
+                }
             }
 
             Ok(models)
@@ -201,9 +234,37 @@ pub async fn fetch_model_list(
     }
 }
 
+/// 检查提供商的配置能否正常使用，返回每一项检查的结果和失败原因，供设置界面展示
+///
+/// model_code 为空时检查该提供商的第一个模型
+#[tauri::command]
+pub async fn validate_provider_config(
+    app_handle: AppHandle,
+    llm_provider_id: i64,
+    model_code: Option<String>,
+) -> Result<ProviderDiagnostics, String> {
+    let (llm_provider, llm_provider_config, models) = {
+        let db = LLMDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+        let llm_provider = db
+            .get_llm_provider(llm_provider_id)
+            .map_err(|e| e.to_string())?;
+        let llm_provider_config = db
+            .get_llm_provider_config(llm_provider_id)
+            .map_err(|e| e.to_string())?;
+        let models = db
+            .get_llm_models(llm_provider_id.to_string())
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .map(|model| (model.code, model.vision_support))
+            .collect::<Vec<_>>();
+        (llm_provider, llm_provider_config, models)
+    };
+    Ok(diagnose_provider(llm_provider, llm_provider_config, models, model_code).await)
+}
+
 #[tauri::command]
 pub async fn add_llm_model(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
     llm_provider_id: i64,
     code: String,
 ) -> Result<(), String> {
@@ -224,7 +285,7 @@ pub async fn add_llm_model(
 
 #[tauri::command]
 pub async fn delete_llm_model(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
     llm_provider_id: i64,
     code: String,
 ) -> Result<(), String> {
@@ -233,6 +294,18 @@ pub async fn delete_llm_model(
     Ok(())
 }
 
+/// 批量启用或隐藏模型，提供商返回几百个模型时只保留常用的出现在选择列表中
+#[tauri::command]
+pub fn set_models_enabled(
+    app_handle: AppHandle,
+    ids: Vec<i64>,
+    enabled: bool,
+) -> Result<(), String> {
+    let db = LLMDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    db.set_llm_models_enabled(&ids, enabled)
+        .map_err(|e| e.to_string())
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ModelForSelect {
     name: String,
@@ -242,9 +315,9 @@ pub struct ModelForSelect {
 }
 
 #[tauri::command]
-pub fn get_models_for_select(app_handle: tauri::AppHandle) -> Result<Vec<ModelForSelect>, String> {
+pub fn get_models_for_select(app_handle: AppHandle) -> Result<Vec<ModelForSelect>, String> {
     let db = LLMDatabase::new(&app_handle).map_err(|e| e.to_string())?;
-    let result = db.get_models_for_select().unwrap();
+    let result = db.get_models_for_select(false).unwrap();
     let models = result
         .iter()
         .map(|(name, code, id, llm_provider_id)| ModelForSelect {
@@ -256,3 +329,291 @@ pub fn get_models_for_select(app_handle: tauri::AppHandle) -> Result<Vec<ModelFo
         .collect();
     Ok(models)
 }
+
+/// 模型可调的参数及其范围和默认值，设置界面按提供商显示对应的滑块；
+/// 没有传 llm_provider_id 时使用第一个包含这个模型的提供商，找不到时返回通用的范围
+#[tauri::command]
+pub fn get_model_param_schema(
+    app_handle: AppHandle,
+    model_code: String,
+    llm_provider_id: Option<i64>,
+) -> Result<Vec<ModelParamSpec>, String> {
+    let db = LLMDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    let provider_id = match llm_provider_id {
+        Some(id) => Some(id),
+        None => db
+            .get_all_llm_models()
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .find(|(_, _, _, code, ..)| *code == model_code)
+            .map(|(_, _, provider_id, ..)| provider_id),
+    };
+    let api_type = match provider_id {
+        Some(id) => db.get_llm_provider(id).map_err(|e| e.to_string())?.api_type,
+        None => String::new(),
+    };
+    Ok(llm::get_model_param_schema(&api_type, &model_code))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TokenEstimate {
+    pub characters: usize,
+    pub words: usize,
+    pub tokens: usize,
+    // 传入每百万 token 的价格时按估算的 token 数计算费用
+    pub estimated_cost: Option<f64>,
+}
+
+/// 估算输入框内容的字数和 token 数量，前端每次输入都会调用，分词器实例缓存在 TokenizerState 中
+#[tauri::command]
+pub fn estimate_tokens(
+    tokenizer_state: tauri::State<'_, TokenizerState>,
+    text: String,
+    model_code: Option<String>,
+    price_per_million_tokens: Option<f64>,
+) -> TokenEstimate {
+    let tokenizer = tokenizer_state.get(model_code.as_deref().unwrap_or_default());
+    let tokens = tokenizer.count_tokens(&text);
+    TokenEstimate {
+        characters: text.chars().count(),
+        words: count_words(&text),
+        tokens,
+        estimated_cost: price_per_million_tokens.map(|price| tokens as f64 * price / 1_000_000.0),
+    }
+}
+
+/// 清空回答缓存和命中统计，返回清除的回答数量
+#[tauri::command]
+pub fn clear_response_cache(response_cache: tauri::State<'_, ResponseCacheState>) -> usize {
+    response_cache.clear()
+}
+
+/// 回答缓存的条目数量和命中统计
+#[tauri::command]
+pub fn get_response_cache_stats(
+    response_cache: tauri::State<'_, ResponseCacheState>,
+) -> ResponseCacheStats {
+    response_cache.stats()
+}
+
+const OFFLINE_MODE_CONFIG_KEY: &str = "offline_mode";
+
+/// 读取 system_config 中保存的离线模式开关，默认关闭
+///
+/// 离线模式下只请求地址指向本机的提供商，例如 Ollama、LM Studio，提问时可以按 offline_mode 功能配置换用本地模型
+pub fn is_offline_mode(app_handle: &AppHandle) -> bool {
+    SystemDatabase::new(app_handle)
+        .and_then(|db| db.get_config(OFFLINE_MODE_CONFIG_KEY))
+        .is_ok_and(|value| value == "true")
+}
+
+/// 离线模式下提供商以外的请求（OCR、语音识别、webhook、网页命令）同样只允许发往本机的地址
+pub fn check_offline_endpoint(app_handle: &AppHandle, endpoint: &str) -> Result<(), String> {
+    if is_offline_mode(app_handle) && !is_local_endpoint(endpoint) {
+        return Err(format!("离线模式下不能请求 {}", endpoint));
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_offline_mode(app_handle: AppHandle) -> bool {
+    is_offline_mode(&app_handle)
+}
+
+#[tauri::command]
+pub fn set_offline_mode(app_handle: AppHandle, enabled: bool) -> Result<(), String> {
+    let db = SystemDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    set_system_config(&db, OFFLINE_MODE_CONFIG_KEY, &enabled.to_string())
+        .map_err(|e| e.to_string())?;
+    app_handle
+        .emit("offline_mode_changed", enabled)
+        .map_err(|e| e.to_string())
+}
+
+const CONCISE_MODE_CONFIG_KEY: &str = "concise_mode";
+
+/// 简洁模式下所有助手的回答都使用 short 长度预设，不用逐个修改助手
+pub fn is_concise_mode(app_handle: &AppHandle) -> bool {
+    SystemDatabase::new(app_handle)
+        .and_then(|db| db.get_config(CONCISE_MODE_CONFIG_KEY))
+        .is_ok_and(|value| value == "true")
+}
+
+#[tauri::command]
+pub fn get_concise_mode(app_handle: AppHandle) -> bool {
+    is_concise_mode(&app_handle)
+}
+
+#[tauri::command]
+pub fn set_concise_mode(app_handle: AppHandle, enabled: bool) -> Result<(), String> {
+    let db = SystemDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    set_system_config(&db, CONCISE_MODE_CONFIG_KEY, &enabled.to_string())
+        .map_err(|e| e.to_string())?;
+    app_handle
+        .emit("concise_mode_changed", enabled)
+        .map_err(|e| e.to_string())
+}
+
+const GENERATION_DEBUG_CONFIG_KEY: &str = "generation_debug";
+
+/// 生成调试开关，开启后录制流式回答时提供商返回的原始数据，可以通过 replay_generation 回放，默认关闭
+pub fn is_generation_debug(app_handle: &AppHandle) -> bool {
+    SystemDatabase::new(app_handle)
+        .and_then(|db| db.get_config(GENERATION_DEBUG_CONFIG_KEY))
+        .is_ok_and(|value| value == "true")
+}
+
+// 录制文件的目录，测试应用使用临时目录
+fn generation_recording_dir(app_handle: &AppHandle) -> Option<PathBuf> {
+    #[cfg(test)]
+    if let Some(databases) = app_handle.try_state::<crate::test_harness::MemoryDatabases>() {
+        return Some(databases.temp_dir("generation_recordings"));
+    }
+    Some(
+        app_handle
+            .path()
+            .app_data_dir()
+            .ok()?
+            .join("generation_recordings"),
+    )
+}
+
+/// 一条回答的录制文件，每次请求时覆盖，只保留最近一次请求的数据
+pub fn generation_recording_path(app_handle: &AppHandle, message_id: i64) -> Option<PathBuf> {
+    let dir = generation_recording_dir(app_handle)?;
+    std::fs::create_dir_all(&dir).ok()?;
+    llm::set_recording_dir(&dir);
+    Some(dir.join(format!("{}.jsonl", message_id)))
+}
+
+/// 删除消息已经不存在的录制文件，删除对话和清理已删除的消息之后调用，返回删除的文件数量
+///
+/// 撤销删除之前消息还在数据库中，录制文件保留到消息被彻底清理
+pub fn remove_orphan_recordings(app_handle: &AppHandle) -> Result<usize, AppError> {
+    let Some(dir) = generation_recording_dir(app_handle) else {
+        return Ok(0);
+    };
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return Ok(0);
+    };
+    let message_repo = ConversationDatabase::new(app_handle)?.message_repo()?;
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let message_id = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<i64>().ok());
+        let Some(message_id) = message_id else {
+            continue;
+        };
+        if message_repo.conversation_id_of(message_id)?.is_none() {
+            std::fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[tauri::command]
+pub fn get_generation_debug(app_handle: AppHandle) -> bool {
+    is_generation_debug(&app_handle)
+}
+
+#[tauri::command]
+pub fn set_generation_debug(app_handle: AppHandle, enabled: bool) -> Result<(), String> {
+    let db = SystemDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    set_system_config(&db, GENERATION_DEBUG_CONFIG_KEY, &enabled.to_string())
+        .map_err(|e| e.to_string())?;
+    app_handle
+        .emit("generation_debug_changed", enabled)
+        .map_err(|e| e.to_string())
+}
+
+const EGRESS_ALLOWLIST_ENABLED_CONFIG_KEY: &str = "egress_allowlist_enabled";
+const EGRESS_ALLOWLIST_CONFIG_KEY: &str = "egress_allowlist";
+
+/// 出站允许列表，开启后提供商的请求只能发往列表中的域名，防止自定义提供商的配置把对话和密钥发到其他地方
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct EgressAllowlist {
+    pub enabled: bool,
+    // 域名，*.example.com 匹配 example.com 和它的所有子域名
+    pub hosts: Vec<String>,
+}
+
+fn read_egress_allowlist(db: &SystemDatabase) -> rusqlite::Result<EgressAllowlist> {
+    let hosts = db
+        .get_config(EGRESS_ALLOWLIST_CONFIG_KEY)?
+        .lines()
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .collect();
+    Ok(EgressAllowlist {
+        enabled: db.get_config(EGRESS_ALLOWLIST_ENABLED_CONFIG_KEY)? == "true",
+        hosts,
+    })
+}
+
+fn save_egress_allowlist(
+    db: &SystemDatabase,
+    enabled: bool,
+    hosts: Vec<String>,
+) -> rusqlite::Result<EgressAllowlist> {
+    let mut normalized: Vec<String> = Vec::new();
+    for host in hosts.iter().map(|host| normalize_host(host)) {
+        if !host.is_empty() && !normalized.contains(&host) {
+            normalized.push(host);
+        }
+    }
+    set_system_config(db, EGRESS_ALLOWLIST_CONFIG_KEY, &normalized.join("\n"))?;
+    set_system_config(
+        db,
+        EGRESS_ALLOWLIST_ENABLED_CONFIG_KEY,
+        &enabled.to_string(),
+    )?;
+    apply_egress_allowlist(enabled, &normalized);
+    Ok(EgressAllowlist {
+        enabled,
+        hosts: normalized,
+    })
+}
+
+/// 启动时读取保存的出站允许列表，之后所有提供商的请求都会按它检查
+pub fn load_egress_allowlist(app_handle: &AppHandle) {
+    match SystemDatabase::new(app_handle).and_then(|db| read_egress_allowlist(&db)) {
+        Ok(allowlist) => apply_egress_allowlist(allowlist.enabled, &allowlist.hosts),
+        Err(e) => {
+            // 读不到设置时不知道用户是否开启了限制，按开启处理，只允许请求本机地址
+            println!("load egress allowlist error: {:?}", e);
+            apply_egress_allowlist(true, &[]);
+        }
+    }
+}
+
+#[tauri::command]
+pub fn get_egress_allowlist(app_handle: AppHandle) -> Result<EgressAllowlist, String> {
+    let db = SystemDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    read_egress_allowlist(&db).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn set_egress_allowlist(
+    app_handle: AppHandle,
+    enabled: bool,
+    hosts: Vec<String>,
+) -> Result<EgressAllowlist, String> {
+    let db = SystemDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    save_egress_allowlist(&db, enabled, hosts).map_err(|e| e.to_string())
+}
+
+/// 请求被阻止后前端一键把域名加入允许列表，不改变是否开启限制
+#[tauri::command]
+pub fn add_egress_host(app_handle: AppHandle, host: String) -> Result<EgressAllowlist, String> {
+    if normalize_host(&host).is_empty() {
+        return Err("域名不能为空".to_string());
+    }
+    let db = SystemDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    let mut allowlist = read_egress_allowlist(&db).map_err(|e| e.to_string())?;
+    allowlist.hosts.push(host);
+    save_egress_allowlist(&db, allowlist.enabled, allowlist.hosts).map_err(|e| e.to_string())
+}
@@ -2,7 +2,13 @@ pub mod ai_api;
 pub mod artifacts_api;
 pub mod assistant_api;
 pub mod attachment_api;
+pub mod config_api;
 pub mod conversation_api;
-mod llm;
+pub mod experiment_api;
+pub mod knowledge_api;
+pub mod llm;
 pub mod llm_api;
+pub mod quick_action_api;
 pub mod system_api;
+pub mod tool_api;
+pub mod voice_api;
@@ -0,0 +1,246 @@
+use std::time::{Duration, Instant};
+
+use tauri::{Manager, State};
+use tauri_plugin_clipboard_manager::ClipboardExt;
+
+use crate::api::assistant_api::get_assistant;
+use crate::db::assistant_db::{AssistantDatabase, QuickAction};
+use crate::errors::AppError;
+use crate::selection::type_text;
+use crate::{AppHandle, FeatureConfigState};
+
+// 隐藏询问窗口后等待焦点回到原来的应用
+const FOCUS_RESTORE_DELAY: Duration = Duration::from_millis(200);
+const DEFAULT_TYPE_INTERVAL_MS: u64 = 10;
+// 流式写入剪贴板时两次写入之间的最短间隔，避免每收到一段内容都写一次剪贴板
+const CLIPBOARD_STREAM_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuickActionOutput {
+    // 在询问窗口中显示，默认方式
+    Window,
+    // 复制到剪贴板
+    Copy,
+    // 模拟键盘输入到当前焦点所在的应用，替换选中的文字
+    Type,
+}
+
+impl QuickActionOutput {
+    pub fn from_config(value: &str) -> Self {
+        match value {
+            "copy" => QuickActionOutput::Copy,
+            "type" => QuickActionOutput::Type,
+            _ => QuickActionOutput::Window,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            QuickActionOutput::Window => "window",
+            QuickActionOutput::Copy => "copy",
+            QuickActionOutput::Type => "type",
+        }
+    }
+}
+
+/// 快捷操作生成完成后按助手的 quick_action_output 配置输出结果，返回实际使用的输出方式
+///
+/// 模拟键盘输入会向其他应用发送按键，需要先在 quick_action 的 allow_type_output 中开启
+#[tauri::command]
+pub async fn apply_quick_action_output(
+    app_handle: AppHandle,
+    feature_config_state: State<'_, FeatureConfigState>,
+    assistant_id: i64,
+    content: String,
+) -> Result<String, AppError> {
+    let assistant_detail =
+        get_assistant(app_handle.clone(), assistant_id).map_err(AppError::DatabaseError)?;
+    let output = assistant_detail
+        .model_configs
+        .iter()
+        .find(|config| config.name == "quick_action_output")
+        .and_then(|config| config.value.as_deref())
+        .map(QuickActionOutput::from_config)
+        .unwrap_or(QuickActionOutput::Window);
+
+    match output {
+        QuickActionOutput::Window => {}
+        QuickActionOutput::Copy => app_handle
+            .clipboard()
+            .write_text(content)
+            .map_err(|e| AppError::UnknownError(e.to_string()))?,
+        QuickActionOutput::Type => {
+            let (allow_type_output, interval) = {
+                let config_feature_map = feature_config_state.config_feature_map.lock().await;
+                let config = config_feature_map.get("quick_action");
+                let get_value = |key: &str| {
+                    config
+                        .and_then(|config| config.get(key))
+                        .map(|config| config.value.clone())
+                };
+                (
+                    get_value("allow_type_output")
+                        .and_then(|v| v.parse::<bool>().ok())
+                        .unwrap_or(false),
+                    get_value("type_interval")
+                        .and_then(|v| v.parse::<u64>().ok())
+                        .unwrap_or(DEFAULT_TYPE_INTERVAL_MS),
+                )
+            };
+            if !allow_type_output {
+                return Err(AppError::UnknownError(
+                    "请先在设置中开启快捷操作的键盘输入".to_string(),
+                ));
+            }
+
+            if let Some(window) = app_handle.get_webview_window("ask") {
+                window.hide()?;
+            }
+            tokio::time::sleep(FOCUS_RESTORE_DELAY).await;
+            tauri::async_runtime::spawn_blocking(move || {
+                type_text(&content, Duration::from_millis(interval))
+            })
+            .await
+            .map_err(|e| AppError::UnknownError(e.to_string()))?
+            .map_err(AppError::UnknownError)?;
+        }
+    }
+    Ok(output.as_str().to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ClipboardMode {
+    // 不写入剪贴板，默认方式
+    #[default]
+    Off,
+    // 生成结束后写入完整的回答
+    Final,
+    // 生成过程中持续更新剪贴板，结束后写入完整的回答
+    Stream,
+}
+
+impl ClipboardMode {
+    pub fn from_config(value: &str) -> Self {
+        match value {
+            "final" => ClipboardMode::Final,
+            "stream" => ClipboardMode::Stream,
+            _ => ClipboardMode::Off,
+        }
+    }
+}
+
+/// 快捷操作的回答写入剪贴板的方式，clipboard_only 只在写入剪贴板时生效
+pub fn clipboard_config(quick_action: &QuickAction) -> (ClipboardMode, bool) {
+    let mode = ClipboardMode::from_config(quick_action.clipboard_mode.trim());
+    (
+        mode,
+        mode != ClipboardMode::Off && quick_action.clipboard_only,
+    )
+}
+
+#[tauri::command]
+pub fn list_quick_actions(app_handle: AppHandle) -> Result<Vec<QuickAction>, AppError> {
+    Ok(AssistantDatabase::new(&app_handle)?.get_quick_actions()?)
+}
+
+/// 保存快捷操作，id 为 0 时新增，返回快捷操作的 id
+#[tauri::command]
+pub fn save_quick_action(
+    app_handle: AppHandle,
+    quick_action: QuickAction,
+) -> Result<i64, AppError> {
+    if quick_action.name.trim().is_empty() {
+        return Err(AppError::ParseError("快捷操作的名称不能为空".to_string()));
+    }
+    let assistant_db = AssistantDatabase::new(&app_handle)?;
+    // 助手不存在时 get_assistant 返回错误
+    assistant_db.get_assistant(quick_action.assistant_id)?;
+    if quick_action.id == 0 {
+        Ok(assistant_db.add_quick_action(&quick_action)?)
+    } else {
+        assistant_db.update_quick_action(&quick_action)?;
+        Ok(quick_action.id)
+    }
+}
+
+#[tauri::command]
+pub fn delete_quick_action(app_handle: AppHandle, id: i64) -> Result<(), AppError> {
+    Ok(AssistantDatabase::new(&app_handle)?.delete_quick_action(id)?)
+}
+
+/// 生成过程中把回答写入剪贴板
+pub struct ClipboardWriter {
+    app_handle: AppHandle,
+    mode: ClipboardMode,
+    last_write: Option<Instant>,
+}
+
+impl ClipboardWriter {
+    pub fn new(app_handle: AppHandle, mode: ClipboardMode) -> Self {
+        ClipboardWriter {
+            app_handle,
+            mode,
+            last_write: None,
+        }
+    }
+
+    /// 收到新的内容时调用，流式模式下按间隔更新剪贴板
+    pub fn push(&mut self, content: &str) {
+        if self.mode != ClipboardMode::Stream || content.is_empty() {
+            return;
+        }
+        if self.last_write.map_or(false, |last_write| {
+            last_write.elapsed() < CLIPBOARD_STREAM_INTERVAL
+        }) {
+            return;
+        }
+        self.write(content);
+    }
+
+    /// 生成结束时调用，写入完整的回答
+    pub fn finish(&mut self, content: &str) {
+        if self.mode == ClipboardMode::Off || content.is_empty() {
+            return;
+        }
+        self.write(content);
+    }
+
+    fn write(&mut self, content: &str) {
+        self.last_write = Some(Instant::now());
+        if let Err(e) = self.app_handle.clipboard().write_text(content.to_string()) {
+            println!("write clipboard error: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clipboard_config() {
+        let quick_action = QuickAction {
+            id: 1,
+            name: "修正语法并复制".to_string(),
+            assistant_id: 1,
+            clipboard_mode: "final".to_string(),
+            clipboard_only: true,
+            created_time: String::new(),
+        };
+        assert_eq!(
+            clipboard_config(&quick_action),
+            (ClipboardMode::Final, true)
+        );
+        // 不写入剪贴板时忽略 clipboard_only，回答仍然显示在询问窗口中
+        let off = QuickAction {
+            clipboard_mode: "off".to_string(),
+            ..quick_action.clone()
+        };
+        assert_eq!(clipboard_config(&off), (ClipboardMode::Off, false));
+        let unknown = QuickAction {
+            clipboard_mode: "unknown".to_string(),
+            ..quick_action
+        };
+        assert_eq!(clipboard_config(&unknown).0, ClipboardMode::Off);
+    }
+}
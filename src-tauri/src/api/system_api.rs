@@ -1,11 +1,20 @@
 use std::cmp::Ord;
 use std::collections::HashMap;
-use tauri::{Manager, State};
+use tauri::{Emitter, Manager, State, Theme};
 
+use crate::api::llm_api::check_offline_endpoint;
+use crate::knowledge::injection_guard::InjectionGuard;
+use crate::shortcut;
+use crate::shutdown::request_shutdown;
+use crate::state::message_token::MessageTokenManager;
 use crate::template_engine::{BangType, TemplateEngine};
+use crate::webhook::{send_webhook, WebhookConfig, WebhookEvent};
+use crate::window::{get_theme_preference, preferred_theme, theme_name, ThemeChangedPayload};
+use crate::AppHandle;
 use crate::AppState;
 use crate::FeatureConfigState;
 
+use crate::db::health::{StartupDiagnostics, StartupDiagnosticsState};
 use crate::db::system_db::{FeatureConfig, SystemDatabase};
 
 #[tauri::command]
@@ -18,7 +27,7 @@ pub async fn get_all_feature_config(
 
 #[tauri::command]
 pub async fn save_feature_config(
-    app_handle: tauri::AppHandle,
+    app_handle: AppHandle,
     state: State<'_, FeatureConfigState>,
     feature_code: String,
     config: HashMap<String, String>,
@@ -65,7 +74,7 @@ pub async fn save_feature_config(
 }
 
 #[tauri::command]
-pub async fn open_data_folder(app: tauri::AppHandle) -> Result<(), String> {
+pub async fn open_data_folder(app: AppHandle) -> Result<(), String> {
     let app_dir = app.path().app_data_dir().unwrap();
     let db_path = app_dir.join("db");
     if let Err(e) = open::that(db_path) {
@@ -75,8 +84,13 @@ pub async fn open_data_folder(app: tauri::AppHandle) -> Result<(), String> {
 }
 
 #[tauri::command]
-pub async fn get_bang_list() -> Result<Vec<(String, String, String, BangType)>, String> {
-    let engine = TemplateEngine::new();
+pub async fn get_bang_list(
+    feature_config_state: State<'_, FeatureConfigState>,
+) -> Result<Vec<(String, String, String, BangType)>, String> {
+    // 和提问时使用同样的配置创建，命令列表和实际可用的命令一致
+    let engine = TemplateEngine::new().with_injection_guard(InjectionGuard::from_config_map(
+        &*feature_config_state.config_feature_map.lock().await,
+    ));
     let mut list = vec![];
     for bang in engine.get_commands().iter() {
         list.push((
@@ -94,3 +108,124 @@ pub async fn get_selected_text_api(state: tauri::State<'_, AppState>) -> Result<
     let selected_text = state.selected_text.lock().await;
     Ok(selected_text.clone())
 }
+
+// 全局快捷键失效时手动重新注册
+#[tauri::command]
+pub fn reregister_shortcuts(app_handle: AppHandle) -> Result<(), String> {
+    shortcut::reregister_shortcuts(&app_handle)
+}
+
+// 返回启动时数据库检查、修复和恢复的结果
+#[tauri::command]
+pub async fn get_startup_diagnostics(
+    state: State<'_, StartupDiagnosticsState>,
+) -> Result<StartupDiagnostics, String> {
+    Ok(state.0.clone())
+}
+
+// 返回用户的主题偏好和当前实际生效的主题
+#[tauri::command]
+pub async fn get_theme(app_handle: AppHandle) -> Result<ThemeChangedPayload, String> {
+    let preference = get_theme_preference(&app_handle);
+    let theme = match preferred_theme(&app_handle) {
+        Some(theme) => theme,
+        None => app_handle
+            .webview_windows()
+            .values()
+            .next()
+            .and_then(|window| window.theme().ok())
+            .unwrap_or(Theme::Light),
+    };
+    Ok(ThemeChangedPayload {
+        preference,
+        theme: theme_name(theme),
+    })
+}
+
+#[tauri::command]
+pub async fn set_theme(app_handle: AppHandle, theme: String) -> Result<(), String> {
+    if !matches!(theme.as_str(), "system" | "light" | "dark") {
+        return Err(format!("不支持的主题: {}", theme));
+    }
+    let db = SystemDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    if db
+        .get_config("theme")
+        .map_err(|e| e.to_string())?
+        .is_empty()
+    {
+        db.add_system_config("theme", &theme)
+            .map_err(|e| e.to_string())?;
+    } else {
+        db.update_system_config("theme", &theme)
+            .map_err(|e| e.to_string())?;
+    }
+
+    let override_theme = preferred_theme(&app_handle);
+    let mut effective_theme = override_theme.unwrap_or(Theme::Light);
+    for window in app_handle.webview_windows().values() {
+        let _ = window.set_theme(override_theme);
+        if override_theme.is_none() {
+            if let Ok(system_theme) = window.theme() {
+                effective_theme = system_theme;
+            }
+        }
+    }
+
+    app_handle
+        .emit(
+            "theme_changed",
+            ThemeChangedPayload {
+                preference: theme,
+                theme: theme_name(effective_theme),
+            },
+        )
+        .map_err(|e| e.to_string())
+}
+
+/// 是否还有正在进行的生成，前端在退出前用来提醒用户
+#[tauri::command]
+pub async fn is_busy(
+    message_token_manager: State<'_, MessageTokenManager>,
+) -> Result<bool, String> {
+    Ok(message_token_manager.running_count().await > 0)
+}
+
+/// 退出应用，force 为 false 且有生成正在进行时不退出并返回 false
+#[tauri::command]
+pub async fn quit_app(app_handle: AppHandle, force: bool) -> Result<bool, String> {
+    Ok(request_shutdown(app_handle, force).await)
+}
+
+/// 按当前的 webhook 配置发送一条测试通知，url 和 payload_template 不为空时使用传入的值，方便保存配置前先测试
+#[tauri::command]
+pub async fn test_webhook(
+    app_handle: AppHandle,
+    state: State<'_, FeatureConfigState>,
+    url: Option<String>,
+    payload_template: Option<String>,
+) -> Result<u16, String> {
+    let mut config = WebhookConfig::from_config_map(&*state.config_feature_map.lock().await);
+    if let Some(url) = url.filter(|url| !url.trim().is_empty()) {
+        config.url = url.trim().to_string();
+    }
+    if let Some(payload_template) = payload_template {
+        config.payload_template = payload_template;
+    }
+    if !config.is_enabled() {
+        return Err("未配置 webhook 地址".to_string());
+    }
+    check_offline_endpoint(&app_handle, &config.url)?;
+    // 测试时不重试，直接返回结果
+    config.max_retries = 0;
+    send_webhook(
+        &config,
+        &WebhookEvent {
+            event: "test".to_string(),
+            title: "Aipp".to_string(),
+            message: "这是一条测试通知".to_string(),
+            conversation_id: None,
+            duration_secs: 0,
+        },
+    )
+    .await
+}
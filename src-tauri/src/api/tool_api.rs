@@ -0,0 +1,88 @@
+use std::time::Instant;
+
+use serde_json::{json, Value};
+use tauri::{Emitter, Manager};
+
+use crate::audit_log;
+use crate::db::conversation_db::{ConversationDatabase, ToolInvocation};
+use crate::errors::AppError;
+use crate::knowledge::injection_guard::InjectionGuard;
+use crate::tools::{builtin_tools, find_tool, ToolDefinition};
+use crate::{AppHandle, FeatureConfigState};
+
+#[tauri::command]
+pub fn list_tools() -> Vec<ToolDefinition> {
+    builtin_tools()
+        .iter()
+        .map(|tool| tool.definition())
+        .collect()
+}
+
+/// 调用内置工具，arguments 为工具参数的 JSON，返回交给模型的文本结果
+///
+/// 每次调用都会保存到 tool_invocation 表，并发送 tool_invocation 事件，对话中按消息折叠显示工具的输出；
+/// 保存的是工具的原始输出，交给模型的结果会先经过提示注入检查
+#[tauri::command]
+pub async fn call_tool(
+    app_handle: AppHandle,
+    name: String,
+    arguments: Value,
+    conversation_id: Option<i64>,
+    message_id: Option<i64>,
+) -> Result<String, AppError> {
+    let tool = find_tool(&name).ok_or(AppError::ToolError(format!("未知的工具 {}", name)))?;
+    println!("call tool {}: {}", name, arguments);
+    let started = Instant::now();
+    let result = tool.call(app_handle.clone(), arguments.clone()).await;
+
+    let invocation = ToolInvocation {
+        id: 0,
+        conversation_id,
+        message_id,
+        tool_name: name,
+        arguments: arguments.to_string(),
+        result: match &result {
+            Ok(output) => output.clone(),
+            Err(e) => e.to_string(),
+        },
+        status: if result.is_ok() { "success" } else { "error" }.to_string(),
+        duration_ms: started.elapsed().as_millis() as i64,
+        created_time: chrono::Utc::now(),
+    };
+    match ConversationDatabase::new(&app_handle)
+        .map_err(AppError::from)
+        .and_then(|db| db.tool_invocation_repo())
+        .and_then(|repo| repo.add(&invocation).map_err(AppError::from))
+    {
+        Ok(invocation) => {
+            let _ = app_handle.emit("tool_invocation", invocation);
+        }
+        Err(e) => println!("save tool invocation error: {:?}", e),
+    }
+    audit_log::record(
+        &app_handle,
+        "tool",
+        conversation_id,
+        message_id,
+        json!({
+            "tool_name": invocation.tool_name,
+            "arguments": invocation.arguments,
+            "result": invocation.result,
+            "status": invocation.status,
+        }),
+    );
+    let injection_guard = match app_handle.try_state::<FeatureConfigState>() {
+        Some(state) => InjectionGuard::from_config_map(&*state.config_feature_map.lock().await),
+        None => InjectionGuard::default(),
+    };
+    result.map(|output| injection_guard.apply(&format!("工具 {}", invocation.tool_name), &output))
+}
+
+#[tauri::command]
+pub async fn list_tool_invocations(
+    app_handle: AppHandle,
+    conversation_id: i64,
+) -> Result<Vec<ToolInvocation>, AppError> {
+    let repo = ConversationDatabase::new(&app_handle)?.tool_invocation_repo()?;
+    Ok(repo.list_by_conversation_id(conversation_id)?)
+}
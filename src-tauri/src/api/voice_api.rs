@@ -0,0 +1,136 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tauri::{Emitter, Manager, State};
+use tokio_util::sync::CancellationToken;
+
+use crate::api::llm_api::is_offline_mode;
+use crate::errors::AppError;
+use crate::voice::{get_speech_engine, Recording};
+use crate::FeatureConfigState;
+use crate::{AppHandle, Window};
+
+const DEFAULT_PARTIAL_INTERVAL_SECS: u64 = 3;
+const DEFAULT_MAX_DURATION_SECS: u64 = 120;
+// 录音太短时识别结果没有意义，不识别中间结果
+const MIN_PARTIAL_DURATION: Duration = Duration::from_secs(1);
+
+struct VoiceSession {
+    recording: Arc<Mutex<Option<Recording>>>,
+    partial_cancel: CancellationToken,
+}
+
+#[derive(Default)]
+pub struct VoiceInputState {
+    session: Mutex<Option<VoiceSession>>,
+}
+
+/// 开始录制麦克风的声音，录音过程中按 voice 的 partial_interval 配置定时识别已经录到的内容，
+/// 通过 voice_partial_transcript 事件发送中间结果
+#[tauri::command]
+pub async fn start_voice_input(
+    window: Window,
+    feature_config_state: State<'_, FeatureConfigState>,
+    voice_state: State<'_, VoiceInputState>,
+) -> Result<(), AppError> {
+    if voice_state.session.lock().unwrap().is_some() {
+        return Err(AppError::UnknownError("正在录音中".to_string()));
+    }
+    let (partial_engine, partial_interval, max_duration) = {
+        let config_feature_map = feature_config_state.config_feature_map.lock().await;
+        let get_value = |key: &str| {
+            config_feature_map
+                .get("voice")
+                .and_then(|config| config.get(key))
+                .and_then(|config| config.value.trim().parse::<u64>().ok())
+        };
+        (
+            get_speech_engine(&config_feature_map, is_offline_mode(window.app_handle()))?,
+            get_value("partial_interval").unwrap_or(DEFAULT_PARTIAL_INTERVAL_SECS),
+            get_value("max_duration")
+                .unwrap_or(DEFAULT_MAX_DURATION_SECS)
+                .max(1),
+        )
+    };
+
+    let recording = tauri::async_runtime::spawn_blocking(move || {
+        Recording::start(Duration::from_secs(max_duration))
+    })
+    .await
+    .map_err(|e| AppError::UnknownError(e.to_string()))?
+    .map_err(AppError::UnknownError)?;
+    let recording = Arc::new(Mutex::new(Some(recording)));
+    let partial_cancel = CancellationToken::new();
+    {
+        let mut session = voice_state.session.lock().unwrap();
+        if session.is_some() {
+            return Err(AppError::UnknownError("正在录音中".to_string()));
+        }
+        *session = Some(VoiceSession {
+            recording: recording.clone(),
+            partial_cancel: partial_cancel.clone(),
+        });
+    }
+
+    // 0 表示不识别中间结果，只在停止时识别一次
+    if partial_interval > 0 {
+        tokio::spawn(async move {
+            let mut last_text = String::new();
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(partial_interval)) => {}
+                    _ = partial_cancel.cancelled() => break,
+                }
+                let audio = match recording.lock().unwrap().as_ref() {
+                    Some(recording) if recording.duration() >= MIN_PARTIAL_DURATION => {
+                        recording.snapshot_wav()
+                    }
+                    Some(_) => continue,
+                    None => break,
+                };
+                let result = tokio::select! {
+                    result = partial_engine.transcribe(audio) => result,
+                    _ = partial_cancel.cancelled() => break,
+                };
+                match result {
+                    Ok(text) if text != last_text => {
+                        let _ = window.emit("voice_partial_transcript", &text);
+                        last_text = text;
+                    }
+                    Ok(_) => {}
+                    Err(e) => println!("voice partial transcribe error: {:?}", e),
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+/// 停止录音并识别完整的录音，返回的文字用于填入询问窗口的输入框
+#[tauri::command]
+pub async fn stop_voice_input(
+    app_handle: AppHandle,
+    feature_config_state: State<'_, FeatureConfigState>,
+    voice_state: State<'_, VoiceInputState>,
+) -> Result<String, AppError> {
+    let session = voice_state
+        .session
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or_else(|| AppError::UnknownError("没有正在进行的录音".to_string()))?;
+    session.partial_cancel.cancel();
+    let recording = session.recording.lock().unwrap().take();
+    let Some(recording) = recording else {
+        return Ok(String::new());
+    };
+    let audio = tauri::async_runtime::spawn_blocking(move || recording.stop())
+        .await
+        .map_err(|e| AppError::UnknownError(e.to_string()))?;
+
+    let engine = {
+        let config_feature_map = feature_config_state.config_feature_map.lock().await;
+        get_speech_engine(&config_feature_map, is_offline_mode(&app_handle))?
+    };
+    Ok(engine.transcribe(audio).await?)
+}
@@ -0,0 +1,546 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tauri::{Emitter, Manager};
+use tokio::sync::mpsc;
+
+use crate::db::system_db::FeatureConfig;
+use crate::state::audit_log::{AuditLogState, AuditRecord};
+use crate::AppHandle;
+use crate::FeatureConfigState;
+
+const AUDIT_LOG_FILE: &str = "audit_log.jsonl";
+// 第一条记录的 prev_hash
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+const REDACTED: &str = "[REDACTED]";
+
+/// 审计日志的配置，保存在 audit_log 功能配置中，默认不记录
+#[derive(Debug, Clone, Default)]
+pub struct AuditLogConfig {
+    pub enabled: bool,
+}
+
+impl AuditLogConfig {
+    pub fn from_config_map(
+        config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+    ) -> Self {
+        let enabled = config_feature_map
+            .get("audit_log")
+            .and_then(|config| config.get("enabled"))
+            .is_some_and(|config| config.value.trim() == "true");
+        AuditLogConfig { enabled }
+    }
+}
+
+/// 审计日志中的一条记录，每条记录的 hash 包含上一条记录的 hash，修改或删除任意一条记录都会使后面的链断开
+///
+/// hash 只覆盖内容的摘要 content_hash 和 redacted 标记，导出时隐去内容（redacted 为 true）仍然可以按导出文件校验整条链
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub timestamp: String,
+    // prompt、response、tool
+    pub kind: String,
+    pub conversation_id: Option<i64>,
+    pub message_id: Option<i64>,
+    pub content: Value,
+    pub content_hash: String,
+    #[serde(default)]
+    pub redacted: bool,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+fn sha256_hex(data: &str) -> String {
+    hex::encode(Sha256::digest(data.as_bytes()))
+}
+
+fn content_hash(content: &Value) -> String {
+    sha256_hex(&content.to_string())
+}
+
+impl AuditEntry {
+    pub fn new(
+        seq: u64,
+        prev_hash: &str,
+        kind: &str,
+        conversation_id: Option<i64>,
+        message_id: Option<i64>,
+        content: Value,
+    ) -> Self {
+        let mut entry = AuditEntry {
+            seq,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            kind: kind.to_string(),
+            conversation_id,
+            message_id,
+            content_hash: content_hash(&content),
+            content,
+            redacted: false,
+            prev_hash: prev_hash.to_string(),
+            hash: String::new(),
+        };
+        entry.hash = entry.compute_hash(false);
+        entry
+    }
+
+    // redacted 为写入时的标记，审计日志中的记录写入时都没有隐去内容
+    fn compute_hash(&self, redacted: bool) -> String {
+        let id = |id: Option<i64>| id.map(|id| id.to_string()).unwrap_or_default();
+        sha256_hex(&format!(
+            "{}|{}|{}|{}|{}|{}|{}|{}",
+            self.seq,
+            self.timestamp,
+            self.kind,
+            id(self.conversation_id),
+            id(self.message_id),
+            self.content_hash,
+            redacted,
+            self.prev_hash
+        ))
+    }
+}
+
+/// 校验的对象：审计日志本身不允许有隐去内容的记录，导出的文件中隐去内容的记录只校验链
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VerifyMode {
+    Live,
+    Export,
+}
+
+/// 校验结果，链断开时 broken_seq 为第一条校验失败的记录序号
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AuditVerifyResult {
+    pub valid: bool,
+    pub entries: u64,
+    pub redacted_entries: u64,
+    pub broken_seq: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// 逐行校验序号、hash 链和内容摘要
+pub fn verify_entries(reader: impl BufRead, mode: VerifyMode) -> AuditVerifyResult {
+    let mut result = AuditVerifyResult {
+        valid: true,
+        entries: 0,
+        redacted_entries: 0,
+        broken_seq: None,
+        error: None,
+    };
+    let mut prev_hash = GENESIS_HASH.to_string();
+    for line in reader.lines() {
+        let seq = result.entries;
+        let line = match line {
+            Ok(line) if line.trim().is_empty() => continue,
+            Ok(line) => line,
+            Err(e) => {
+                result.valid = false;
+                result.error = Some(e.to_string());
+                return result;
+            }
+        };
+        let error = match serde_json::from_str::<AuditEntry>(&line) {
+            Err(e) => Some(format!("记录 {} 无法解析: {}", seq, e)),
+            Ok(entry) if entry.seq != seq => {
+                Some(format!("序号应为 {}，实际为 {}", seq, entry.seq))
+            }
+            Ok(entry) if entry.prev_hash != prev_hash => {
+                Some(format!("记录 {} 的 prev_hash 与上一条记录不一致", seq))
+            }
+            Ok(entry) if entry.redacted && mode == VerifyMode::Live => Some(format!(
+                "记录 {} 被标记为隐去内容，审计日志中不应该有这样的记录",
+                seq
+            )),
+            // 导出时隐去内容的记录保留写入时的 hash
+            Ok(entry) if entry.hash != entry.compute_hash(false) => {
+                Some(format!("记录 {} 的 hash 不正确", seq))
+            }
+            Ok(entry) if !entry.redacted && entry.content_hash != content_hash(&entry.content) => {
+                Some(format!("记录 {} 的内容被修改", seq))
+            }
+            Ok(entry) => {
+                if entry.redacted {
+                    result.redacted_entries += 1;
+                }
+                prev_hash = entry.hash;
+                None
+            }
+        };
+        if let Some(error) = error {
+            result.valid = false;
+            result.broken_seq = Some(seq);
+            result.error = Some(error);
+            return result;
+        }
+        result.entries += 1;
+    }
+    result
+}
+
+// API Key、Bearer 令牌和邮箱地址，导出时替换为 [REDACTED]
+fn secret_patterns() -> Vec<Regex> {
+    [
+        r"sk-[A-Za-z0-9_\-]{16,}",
+        r"(?i)bearer\s+[A-Za-z0-9._\-]{16,}",
+        r"[A-Za-z0-9._%+\-]+@[A-Za-z0-9\-]+(\.[A-Za-z0-9\-]+)+",
+    ]
+    .iter()
+    .map(|pattern| Regex::new(pattern).unwrap())
+    .collect()
+}
+
+fn redact_value(value: &Value, patterns: &[Regex]) -> Value {
+    match value {
+        Value::String(text) => {
+            Value::String(patterns.iter().fold(text.clone(), |text, pattern| {
+                pattern.replace_all(&text, REDACTED).into_owned()
+            }))
+        }
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| redact_value(item, patterns))
+                .collect(),
+        ),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, item)| (key.clone(), redact_value(item, patterns)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+/// 导出时隐去记录的内容：redact_kinds 中类型的记录整条内容替换为 [REDACTED]，其他记录隐去其中的密钥和邮箱
+///
+/// 内容有改动的记录标记为 redacted，hash 和 content_hash 保持原样，导出的文件可以用 verify_exported_audit_log 校验
+pub fn redact_entry(mut entry: AuditEntry, redact_kinds: &[String]) -> AuditEntry {
+    let content = if redact_kinds.contains(&entry.kind) {
+        Value::String(REDACTED.to_string())
+    } else {
+        redact_value(&entry.content, &secret_patterns())
+    };
+    if content != entry.content {
+        entry.content = content;
+        entry.redacted = true;
+    }
+    entry
+}
+
+fn audit_log_path(app_handle: &AppHandle) -> Result<PathBuf, String> {
+    let app_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?;
+    Ok(app_dir.join(AUDIT_LOG_FILE))
+}
+
+// 文件中的链尾，truncated 为修复时从文件末尾删除的不完整记录
+struct LogTail {
+    seq: u64,
+    prev_hash: String,
+    truncated: Option<String>,
+}
+
+// 读取最后一条记录的序号和 hash，文件不存在或者为空时返回创世 hash；
+// 写入时程序退出会在文件末尾留下没有换行的不完整记录，删除这一行后继续写入，中间的记录损坏时返回错误
+fn read_tail(path: &Path) -> Result<LogTail, String> {
+    let mut tail = LogTail {
+        seq: 0,
+        prev_hash: GENESIS_HASH.to_string(),
+        truncated: None,
+    };
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(tail),
+        Err(e) => return Err(e.to_string()),
+    };
+    let mut offset = 0;
+    let mut lines = data.split_inclusive(|byte| *byte == b'\n').peekable();
+    while let Some(line) = lines.next() {
+        let start = offset;
+        offset += line.len();
+        let text = String::from_utf8_lossy(line);
+        if text.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<AuditEntry>(&text) {
+            Ok(entry) => {
+                tail.seq = entry.seq + 1;
+                tail.prev_hash = entry.hash;
+            }
+            Err(_) if lines.peek().is_none() && !line.ends_with(b"\n") => {
+                OpenOptions::new()
+                    .write(true)
+                    .open(path)
+                    .and_then(|file| file.set_len(start as u64))
+                    .map_err(|e| e.to_string())?;
+                tail.truncated = Some(text.into_owned());
+            }
+            Err(e) => return Err(format!("审计日志的记录 {} 无法解析: {}", tail.seq, e)),
+        }
+    }
+    Ok(tail)
+}
+
+/// 追加一条记录，调用方需要保证同一时间只有一个写入，记录之间的链不会交错
+fn append(
+    app_handle: &AppHandle,
+    state: &AuditLogState,
+    path: &Path,
+    record: AuditRecord,
+) -> Result<AuditEntry, String> {
+    let mut tail = state.tail.lock().unwrap();
+    let (seq, prev_hash) = match tail.take() {
+        Some(tail) => tail,
+        None => {
+            let tail = read_tail(path)?;
+            if let Some(truncated) = tail.truncated {
+                println!("audit log truncated incomplete entry: {}", truncated);
+                let _ = app_handle.emit("audit_log_repaired", truncated);
+            }
+            (tail.seq, tail.prev_hash)
+        }
+    };
+    let entry = AuditEntry::new(
+        seq,
+        &prev_hash,
+        &record.kind,
+        record.conversation_id,
+        record.message_id,
+        record.content,
+    );
+    let line = serde_json::to_string(&entry).map_err(|e| e.to_string())?;
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{}", line));
+    match result {
+        Ok(()) => {
+            *tail = Some((seq + 1, entry.hash.clone()));
+            Ok(entry)
+        }
+        // 写入失败时保留原来的链尾，下次写入重新读取文件
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+async fn write_record(app_handle: &AppHandle, record: AuditRecord) {
+    let Some(config_state) = app_handle.try_state::<FeatureConfigState>() else {
+        return;
+    };
+    let config = AuditLogConfig::from_config_map(&*config_state.config_feature_map.lock().await);
+    if !config.enabled {
+        return;
+    }
+    let Some(state) = app_handle.try_state::<AuditLogState>() else {
+        return;
+    };
+    let kind = record.kind.clone();
+    let result =
+        audit_log_path(app_handle).and_then(|path| append(app_handle, &state, &path, record));
+    if let Err(e) = result {
+        println!("append audit log {} error: {}", kind, e);
+    }
+}
+
+// 按收到的顺序逐条写入记录的后台任务
+fn spawn_writer(app_handle: AppHandle) -> mpsc::UnboundedSender<AuditRecord> {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<AuditRecord>();
+    tauri::async_runtime::spawn(async move {
+        while let Some(record) = receiver.recv().await {
+            write_record(&app_handle, record).await;
+        }
+    });
+    sender
+}
+
+/// 开启了审计日志时在后台追加一条记录，不影响调用方；记录按调用的顺序写入。
+/// 私密和无痕对话同样记录，合规场景需要完整的记录
+pub fn record(
+    app_handle: &AppHandle,
+    kind: &str,
+    conversation_id: Option<i64>,
+    message_id: Option<i64>,
+    content: Value,
+) {
+    let Some(state) = app_handle.try_state::<AuditLogState>() else {
+        return;
+    };
+    let record = AuditRecord {
+        kind: kind.to_string(),
+        conversation_id,
+        message_id,
+        content,
+    };
+    let mut sender = state.sender.lock().unwrap();
+    let sender = sender.get_or_insert_with(|| spawn_writer(app_handle.clone()));
+    if sender.send(record).is_err() {
+        println!("audit log writer stopped, drop {} entry", kind);
+    }
+}
+
+#[tauri::command]
+pub fn verify_audit_log(app_handle: AppHandle) -> Result<AuditVerifyResult, String> {
+    let path = audit_log_path(&app_handle)?;
+    match File::open(&path) {
+        Ok(file) => Ok(verify_entries(BufReader::new(file), VerifyMode::Live)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Ok(verify_entries(std::io::empty(), VerifyMode::Live))
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// 校验 export_audit_log 导出的文件，隐去内容的记录只校验链
+#[tauri::command]
+pub fn verify_exported_audit_log(path: String) -> Result<AuditVerifyResult, String> {
+    let file = File::open(&path).map_err(|e| e.to_string())?;
+    Ok(verify_entries(BufReader::new(file), VerifyMode::Export))
+}
+
+/// 把审计日志导出到 path，按 redact_kinds 和内置规则隐去敏感内容，返回导出的记录数
+#[tauri::command]
+pub fn export_audit_log(
+    app_handle: AppHandle,
+    path: String,
+    redact_kinds: Vec<String>,
+) -> Result<u64, String> {
+    let file = File::open(audit_log_path(&app_handle)?).map_err(|e| e.to_string())?;
+    let mut output = File::create(&path).map_err(|e| e.to_string())?;
+    let mut count = 0;
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: AuditEntry = serde_json::from_str(&line).map_err(|e| e.to_string())?;
+        let entry = redact_entry(entry, &redact_kinds);
+        let line = serde_json::to_string(&entry).map_err(|e| e.to_string())?;
+        writeln!(output, "{}", line).map_err(|e| e.to_string())?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn chain() -> Vec<AuditEntry> {
+        let contents = [
+            (
+                "prompt",
+                json!({ "messages": [{ "role": "user", "content": "我的邮箱是 a@example.com" }] }),
+            ),
+            (
+                "tool",
+                json!({ "tool_name": "read_file", "arguments": "{}" }),
+            ),
+            ("response", json!({ "content": "好的" })),
+        ];
+        let mut prev_hash = GENESIS_HASH.to_string();
+        contents
+            .into_iter()
+            .enumerate()
+            .map(|(seq, (kind, content))| {
+                let entry =
+                    AuditEntry::new(seq as u64, &prev_hash, kind, Some(1), Some(2), content);
+                prev_hash = entry.hash.clone();
+                entry
+            })
+            .collect()
+    }
+
+    fn to_jsonl(entries: &[AuditEntry]) -> String {
+        entries
+            .iter()
+            .map(|entry| serde_json::to_string(entry).unwrap())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_verify_entries() {
+        let entries = chain();
+        let result = verify_entries(to_jsonl(&entries).as_bytes(), VerifyMode::Live);
+        assert!(result.valid);
+        assert_eq!(result.entries, 3);
+
+        // 修改内容
+        let mut tampered = entries.clone();
+        tampered[1].content = json!({ "tool_name": "run_shell" });
+        let result = verify_entries(to_jsonl(&tampered).as_bytes(), VerifyMode::Live);
+        assert!(!result.valid);
+        assert_eq!(result.broken_seq, Some(1));
+
+        // 删除中间的记录
+        let removed = vec![entries[0].clone(), entries[2].clone()];
+        assert_eq!(
+            verify_entries(to_jsonl(&removed).as_bytes(), VerifyMode::Live).broken_seq,
+            Some(1)
+        );
+
+        // 同时伪造内容摘要也会破坏 hash
+        let mut forged = entries.clone();
+        forged[2].content = json!({ "content": "不好" });
+        forged[2].content_hash = content_hash(&forged[2].content);
+        assert_eq!(
+            verify_entries(to_jsonl(&forged).as_bytes(), VerifyMode::Live).broken_seq,
+            Some(2)
+        );
+
+        assert!(verify_entries(std::io::empty(), VerifyMode::Live).valid);
+    }
+
+    #[test]
+    fn test_redact_entry() {
+        let entries: Vec<AuditEntry> = chain()
+            .into_iter()
+            .map(|entry| redact_entry(entry, &["tool".to_string()]))
+            .collect();
+        assert_eq!(
+            entries[0].content["messages"][0]["content"],
+            "我的邮箱是 [REDACTED]"
+        );
+        assert!(entries[0].redacted);
+        assert_eq!(entries[1].content, json!(REDACTED));
+        assert!(!entries[2].redacted);
+
+        // 隐去内容后仍然可以按导出文件校验整条链
+        let result = verify_entries(to_jsonl(&entries).as_bytes(), VerifyMode::Export);
+        assert!(result.valid);
+        assert_eq!(result.redacted_entries, 2);
+        // 审计日志本身不能有隐去内容的记录，否则修改内容后标记为隐去就能绕过内容摘要的校验
+        let result = verify_entries(to_jsonl(&entries).as_bytes(), VerifyMode::Live);
+        assert_eq!(result.broken_seq, Some(0));
+    }
+
+    #[test]
+    fn test_read_tail_repairs_truncated_line() {
+        let path =
+            std::env::temp_dir().join(format!("aipp_audit_log_{}.jsonl", std::process::id()));
+        let entries = chain();
+        let complete = format!("{}\n", to_jsonl(&entries[..2]));
+        std::fs::write(&path, format!("{}{{\"seq\":2,\"times", complete)).unwrap();
+
+        let tail = read_tail(&path).unwrap();
+        assert_eq!(tail.seq, 2);
+        assert_eq!(tail.prev_hash, entries[1].hash);
+        assert_eq!(tail.truncated.as_deref(), Some("{\"seq\":2,\"times"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), complete);
+
+        // 中间的记录损坏时不修复
+        std::fs::write(&path, format!("{{\"seq\":0}}\n{}", complete)).unwrap();
+        assert!(read_tail(&path).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::api::llm::Role;
+use crate::db::conversation_db::{ConversationDatabase, GenerationMetadata};
+use crate::db::system_db::FeatureConfig;
+use crate::errors::AppError;
+use crate::AppHandle;
+
+pub const MODEL_PRICE_FEATURE: &str = "model_price";
+
+// 初始化和升级时添加的常用模型价格，单位是美元每百万 token，用户修改过的价格保持不变
+pub const DEFAULT_MODEL_PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-4o", 2.5, 10.0),
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("claude-3-5-sonnet-20241022", 3.0, 15.0),
+    ("claude-3-5-haiku-20241022", 0.8, 4.0),
+    ("deepseek-chat", 0.27, 1.1),
+];
+
+/// 模型每百万 token 的价格
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64,
+}
+
+impl ModelPrice {
+    /// 价格不能是负数或者非有限的数字
+    pub fn new(input: f64, output: f64) -> Result<Self, String> {
+        if !input.is_finite() || !output.is_finite() || input < 0.0 || output < 0.0 {
+            return Err("模型价格必须是不小于 0 的数字".to_string());
+        }
+        Ok(ModelPrice { input, output })
+    }
+
+    /// 解析 "输入价格,输出价格" 格式的配置值
+    pub fn parse(value: &str) -> Option<Self> {
+        let (input, output) = value.split_once(',')?;
+        ModelPrice::new(input.trim().parse().ok()?, output.trim().parse().ok()?).ok()
+    }
+
+    /// 保存到 model_price 功能配置中的值
+    pub fn to_config_value(&self) -> String {
+        format!("{},{}", self.input, self.output)
+    }
+}
+
+/// model_price 功能配置中的模型价格，key 为模型代码，value 为 "输入价格,输出价格"，单位是每百万 token
+#[derive(Debug, Clone, Default)]
+pub struct ModelPricing {
+    prices: HashMap<String, ModelPrice>,
+}
+
+impl ModelPricing {
+    pub fn from_config_map(
+        config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+    ) -> Self {
+        let prices = config_feature_map
+            .get(MODEL_PRICE_FEATURE)
+            .map(|config| {
+                config
+                    .iter()
+                    .filter_map(|(model_code, config)| {
+                        Some((model_code.clone(), ModelPrice::parse(&config.value)?))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        ModelPricing { prices }
+    }
+
+    /// 没有配置这个模型的价格时返回 None
+    pub fn cost(
+        &self,
+        model_code: &str,
+        prompt_tokens: u32,
+        completion_tokens: u32,
+    ) -> Option<f64> {
+        self.prices.get(model_code).map(|price| {
+            (prompt_tokens as f64 * price.input + completion_tokens as f64 * price.output)
+                / 1_000_000.0
+        })
+    }
+}
+
+/// 一条回答的 token 用量和费用，提供商没有返回用量或者没有配置模型价格时费用为空
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct MessageCost {
+    pub message_id: i64,
+    pub model_code: Option<String>,
+    pub prompt_tokens: Option<u32>,
+    pub completion_tokens: Option<u32>,
+    pub cost: Option<f64>,
+}
+
+impl MessageCost {
+    /// 按消息保存的生成信息计算，没有生成信息的消息（例如用户的提问）返回 None
+    pub fn from_generation_metadata(
+        message_id: i64,
+        generation_metadata: Option<&str>,
+        pricing: &ModelPricing,
+    ) -> Option<Self> {
+        let metadata: GenerationMetadata = serde_json::from_str(generation_metadata?).ok()?;
+        let cost = match (
+            metadata.model_code.as_deref(),
+            metadata.prompt_tokens,
+            metadata.completion_tokens,
+        ) {
+            (Some(model_code), prompt_tokens, completion_tokens)
+                if prompt_tokens.is_some() || completion_tokens.is_some() =>
+            {
+                pricing.cost(
+                    model_code,
+                    prompt_tokens.unwrap_or_default(),
+                    completion_tokens.unwrap_or_default(),
+                )
+            }
+            _ => None,
+        };
+        Some(MessageCost {
+            message_id,
+            model_code: metadata.model_code,
+            prompt_tokens: metadata.prompt_tokens,
+            completion_tokens: metadata.completion_tokens,
+            cost,
+        })
+    }
+}
+
+/// 对话的 token 用量和费用合计，对话中显示费用标记和导出时使用
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct ConversationCost {
+    pub conversation_id: i64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cost: f64,
+    // 没有用量或者没有配置价格、没有计入费用的回答数量，大于 0 时费用只是已知部分的合计
+    pub unpriced_messages: usize,
+    pub messages: Vec<MessageCost>,
+}
+
+impl ConversationCost {
+    pub fn new(conversation_id: i64) -> Self {
+        ConversationCost {
+            conversation_id,
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, message: MessageCost) {
+        self.prompt_tokens += message.prompt_tokens.unwrap_or_default() as u64;
+        self.completion_tokens += message.completion_tokens.unwrap_or_default() as u64;
+        match message.cost {
+            Some(cost) => self.cost += cost,
+            None => self.unpriced_messages += 1,
+        }
+        self.messages.push(message);
+    }
+}
+
+/// 统计对话中所有回答的 token 用量和费用，不包含删除的消息和草稿
+pub fn conversation_cost(
+    app_handle: &AppHandle,
+    conversation_id: i64,
+    pricing: &ModelPricing,
+) -> Result<ConversationCost, AppError> {
+    let rows = ConversationDatabase::new(app_handle)?
+        .message_repo()?
+        .list_generation_metadata_by_conversation_id(conversation_id)?;
+    let mut cost = ConversationCost::new(conversation_id);
+    for (message_id, message_type, generation_metadata) in rows {
+        if message_type.parse::<Role>() == Ok(Role::User) {
+            continue;
+        }
+        if let Some(message) = MessageCost::from_generation_metadata(
+            message_id,
+            generation_metadata.as_deref(),
+            pricing,
+        ) {
+            cost.add(message);
+        }
+    }
+    Ok(cost)
+}
+
+/// 费用保留 4 位小数，没有费用时显示 -
+pub fn format_cost(cost: Option<f64>) -> String {
+    cost.map(|cost| format!("{:.4}", cost))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pricing() -> ModelPricing {
+        let config = |key: &str, value: &str| {
+            (
+                key.to_string(),
+                FeatureConfig {
+                    id: None,
+                    feature_code: "model_price".to_string(),
+                    key: key.to_string(),
+                    value: value.to_string(),
+                    data_type: "string".to_string(),
+                    description: None,
+                },
+            )
+        };
+        ModelPricing::from_config_map(&HashMap::from([(
+            "model_price".to_string(),
+            HashMap::from([
+                config("gpt-4o", "2.5, 10"),
+                config("broken", "abc"),
+                config("negative", "-1,2"),
+            ]),
+        )]))
+    }
+
+    #[test]
+    fn test_model_price() {
+        let price = ModelPrice::new(0.15, 0.6).unwrap();
+        assert_eq!(ModelPrice::parse(&price.to_config_value()), Some(price));
+        assert!(ModelPrice::new(-1.0, 1.0).is_err());
+        assert!(ModelPrice::new(f64::NAN, 1.0).is_err());
+        for (_, input, output) in DEFAULT_MODEL_PRICES {
+            assert!(ModelPrice::new(*input, *output).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_model_pricing() {
+        let pricing = pricing();
+        assert_eq!(pricing.cost("gpt-4o", 1_000_000, 100_000), Some(3.5));
+        assert_eq!(pricing.cost("broken", 10, 10), None);
+        assert_eq!(pricing.cost("negative", 10, 10), None);
+        assert_eq!(pricing.cost("unknown", 10, 10), None);
+    }
+
+    #[test]
+    fn test_conversation_cost() {
+        let pricing = pricing();
+        let mut cost = ConversationCost::new(1);
+        let metadata = r#"{"model_code":"gpt-4o","stream":true,"retries":0,"prompt_tokens":2000,"completion_tokens":500}"#;
+        cost.add(MessageCost::from_generation_metadata(2, Some(metadata), &pricing).unwrap());
+        // 旧版本保存的生成信息没有用量
+        let metadata = r#"{"model_code":"gpt-4o","stream":true,"retries":0}"#;
+        let message = MessageCost::from_generation_metadata(4, Some(metadata), &pricing).unwrap();
+        assert_eq!(message.cost, None);
+        cost.add(message);
+        assert!(MessageCost::from_generation_metadata(5, None, &pricing).is_none());
+
+        assert_eq!(cost.prompt_tokens, 2000);
+        assert_eq!(cost.completion_tokens, 500);
+        assert_eq!(format_cost(Some(cost.cost)), "0.0100");
+        assert_eq!(cost.unpriced_messages, 1);
+        assert_eq!(cost.messages.len(), 2);
+        assert_eq!(format_cost(None), "-");
+    }
+}
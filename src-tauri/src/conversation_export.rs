@@ -0,0 +1,322 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::Serialize;
+use tauri::Emitter;
+
+use crate::api::llm::Role;
+use crate::conversation_cost::{format_cost, ConversationCost, MessageCost, ModelPricing};
+use crate::db::conversation_db::{
+    AttachmentType, ConversationDatabase, Message, MessageAttachment, Repository,
+};
+use crate::errors::AppError;
+use crate::AppHandle;
+
+// 写文件的缓冲区大小，攒够后一次写入磁盘
+const WRITE_BUFFER_SIZE: usize = 64 * 1024;
+// 每写完这么多条消息发送一次进度
+const PROGRESS_INTERVAL: usize = 50;
+
+/// 导出对话的进度，通过 conversation_export_progress 事件发送
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct ConversationExportProgress {
+    pub conversation_id: i64,
+    pub total: usize,
+    pub written: usize,
+    pub done: bool,
+}
+
+/// 导出的结果
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct ConversationExportResult {
+    pub path: String,
+    pub messages: usize,
+    // 图片保存在导出文件旁边的 <文件名>_files 目录中，没有图片时不创建目录
+    pub images: usize,
+    pub cost: ConversationCost,
+}
+
+/// 把对话导出为 markdown 文件，逐条读取消息并分块写入磁盘，图片写成单独的文件并以相对路径引用，
+/// 包含几千条消息和大量图片的对话也不会一次加载到内存中；每条回答后面写上 token 用量和费用，文件末尾写上合计
+pub fn export_conversation(
+    app_handle: &AppHandle,
+    conversation_id: i64,
+    path: &Path,
+    pricing: &ModelPricing,
+) -> Result<ConversationExportResult, AppError> {
+    let db = ConversationDatabase::new(app_handle)?;
+    let conversation = db
+        .conversation_repo()?
+        .read(conversation_id)?
+        .ok_or(AppError::DatabaseError("未找到对话".to_string()))?;
+    let message_repo = db.message_repo()?;
+    let attachment_repo = db.attachment_repo()?;
+    let ids = message_repo.list_ids_by_conversation_id(conversation_id)?;
+
+    let mut progress = ConversationExportProgress {
+        conversation_id,
+        total: ids.len(),
+        ..Default::default()
+    };
+    let mut writer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, File::create(path)?);
+    let mut images = ImageWriter::new(path);
+    let mut cost = ConversationCost::new(conversation_id);
+    writeln!(writer, "# {}\n", conversation.name.trim())?;
+    for id in ids {
+        // 导出过程中被删除的消息跳过
+        if let Some(message) = message_repo.read(id)? {
+            let attachments = attachment_repo.list_by_message_id(conversation_id, id)?;
+            let message_cost = if message.role() == Some(Role::User) {
+                None
+            } else {
+                MessageCost::from_generation_metadata(
+                    id,
+                    message.generation_metadata.as_deref(),
+                    pricing,
+                )
+            };
+            write_message(
+                &mut writer,
+                &message,
+                &attachments,
+                message_cost.as_ref(),
+                &mut images,
+            )?;
+            if let Some(message_cost) = message_cost {
+                cost.add(message_cost);
+            }
+        }
+        progress.written += 1;
+        if progress.written % PROGRESS_INTERVAL == 0 {
+            let _ = app_handle.emit("conversation_export_progress", &progress);
+        }
+    }
+    write_cost_footer(&mut writer, &cost)?;
+    writer.flush()?;
+    progress.done = true;
+    let _ = app_handle.emit("conversation_export_progress", &progress);
+
+    Ok(ConversationExportResult {
+        path: path.to_string_lossy().to_string(),
+        messages: progress.written,
+        images: images.count,
+        cost,
+    })
+}
+
+// 图片保存的目录，第一次写图片时才创建
+struct ImageWriter {
+    dir: PathBuf,
+    // markdown 中引用图片使用的相对路径前缀
+    dir_name: String,
+    count: usize,
+}
+
+impl ImageWriter {
+    fn new(path: &Path) -> Self {
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| "conversation".to_string());
+        let dir_name = format!("{}_files", stem);
+        ImageWriter {
+            dir: path.with_file_name(&dir_name),
+            dir_name,
+            count: 0,
+        }
+    }
+
+    // 写入图片，返回相对导出文件的路径；不是 base64 内容时返回 None
+    fn write(&mut self, attachment: &MessageAttachment) -> std::io::Result<Option<String>> {
+        let Some((extension, data)) = attachment
+            .attachment_content
+            .as_deref()
+            .and_then(decode_image)
+        else {
+            return Ok(None);
+        };
+        if self.count == 0 {
+            fs::create_dir_all(&self.dir)?;
+        }
+        let file_name = format!(
+            "{}_{}.{}",
+            attachment.message_id.unwrap_or_default(),
+            attachment.id,
+            extension
+        );
+        fs::write(self.dir.join(&file_name), data)?;
+        self.count += 1;
+        Ok(Some(format!("{}/{}", self.dir_name, file_name)))
+    }
+}
+
+// data:image/png;base64,... 形式的图片内容，返回扩展名和解码后的数据
+fn decode_image(content: &str) -> Option<(&'static str, Vec<u8>)> {
+    let (header, data) = content.split_once("base64,")?;
+    let extension = match header.trim_end_matches(';') {
+        "data:image/jpeg" | "data:image/jpg" => "jpg",
+        "data:image/gif" => "gif",
+        "data:image/webp" => "webp",
+        _ => "png",
+    };
+    STANDARD
+        .decode(data.trim())
+        .ok()
+        .map(|data| (extension, data))
+}
+
+fn role_title(message: &Message) -> String {
+    match message.role() {
+        Some(Role::User) => "用户".to_string(),
+        Some(Role::System) => "系统".to_string(),
+        _ => message
+            .llm_model_name
+            .as_deref()
+            .filter(|name| !name.is_empty())
+            .unwrap_or("助手")
+            .to_string(),
+    }
+}
+
+fn write_message<W: Write>(
+    writer: &mut W,
+    message: &Message,
+    attachments: &[MessageAttachment],
+    cost: Option<&MessageCost>,
+    images: &mut ImageWriter,
+) -> std::io::Result<()> {
+    writeln!(
+        writer,
+        "## {} · {}\n",
+        role_title(message),
+        message
+            .created_time
+            .with_timezone(&chrono::Local)
+            .format("%Y-%m-%d %H:%M:%S")
+    )?;
+    if let Some(reasoning) = message
+        .reasoning
+        .as_deref()
+        .filter(|reasoning| !reasoning.trim().is_empty())
+    {
+        writeln!(
+            writer,
+            "<details>\n<summary>思考过程</summary>\n\n{}\n\n</details>\n",
+            reasoning.trim()
+        )?;
+    }
+    writeln!(writer, "{}\n", message.content.trim())?;
+    for attachment in attachments {
+        let name = attachment.attachment_url.as_deref().unwrap_or_default();
+        match attachment.attachment_type {
+            AttachmentType::Image => match images.write(attachment)? {
+                Some(relative_path) => writeln!(writer, "![{}](<{}>)\n", name, relative_path)?,
+                None => writeln!(writer, "> 图片：{}\n", name)?,
+            },
+            _ => writeln!(writer, "> 附件：{}\n", name)?,
+        }
+    }
+    if let Some(cost) = cost {
+        writeln!(
+            writer,
+            "| 输入 token | 输出 token | 费用 |\n| --- | --- | --- |\n| {} | {} | {} |\n",
+            format_tokens(cost.prompt_tokens.map(u64::from)),
+            format_tokens(cost.completion_tokens.map(u64::from)),
+            format_cost(cost.cost)
+        )?;
+    }
+    Ok(())
+}
+
+fn format_tokens(tokens: Option<u64>) -> String {
+    tokens
+        .map(|tokens| tokens.to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+// 对话的 token 用量和费用合计，没有回答时不写
+fn write_cost_footer<W: Write>(writer: &mut W, cost: &ConversationCost) -> std::io::Result<()> {
+    if cost.messages.is_empty() {
+        return Ok(());
+    }
+    writeln!(
+        writer,
+        "---\n\n**合计**\n\n| 回答数 | 输入 token | 输出 token | 费用 |\n| --- | --- | --- | --- |\n| {} | {} | {} | {} |",
+        cost.messages.len(),
+        cost.prompt_tokens,
+        cost.completion_tokens,
+        format_cost(Some(cost.cost))
+    )?;
+    if cost.unpriced_messages > 0 {
+        writeln!(
+            writer,
+            "\n> {} 条回答没有 token 用量或者没有配置模型价格，未计入费用",
+            cost.unpriced_messages
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_image() {
+        let (extension, data) = decode_image("data:image/jpeg;base64,aGVsbG8=").unwrap();
+        assert_eq!(extension, "jpg");
+        assert_eq!(data, b"hello");
+        assert_eq!(decode_image("/tmp/a.png"), None);
+    }
+
+    #[test]
+    fn test_write_message() {
+        let dir = std::env::temp_dir().join(format!("aipp_export_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut images = ImageWriter::new(&dir.join("对话.md"));
+        let message = Message {
+            id: 3,
+            parent_id: None,
+            conversation_id: 1,
+            message_type: "user".to_string(),
+            content: "看看这张图".to_string(),
+            llm_model_id: None,
+            llm_model_name: None,
+            created_time: chrono::Utc::now(),
+            start_time: None,
+            finish_time: None,
+            token_count: 0,
+            is_truncated: false,
+            attachment_truncation: None,
+            pinned: false,
+            citations: None,
+            timed_out: false,
+            generation_metadata: None,
+            reasoning: None,
+            assistant_id: None,
+        };
+        let attachment = MessageAttachment {
+            id: 7,
+            message_id: Some(3),
+            attachment_type: AttachmentType::Image,
+            attachment_url: Some("cat.png".to_string()),
+            attachment_content: Some("data:image/png;base64,aGVsbG8=".to_string()),
+            attachment_hash: None,
+            use_vector: false,
+            token_count: None,
+            ocr_text: None,
+            encoding: None,
+        };
+        let mut output = Vec::new();
+        write_message(&mut output, &message, &[attachment], None, &mut images).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("## 用户 · "));
+        assert!(output.contains("看看这张图\n"));
+        assert!(output.contains("![cat.png](<对话_files/3_7.png>)"));
+        assert_eq!(fs::read(dir.join("对话_files/3_7.png")).unwrap(), b"hello");
+        assert_eq!(images.count, 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
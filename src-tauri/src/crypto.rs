@@ -0,0 +1,322 @@
+use std::process::{Command, Stdio};
+
+use crate::AppHandle;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+
+// 加密后的内容以这个前缀开头，后面是 base64 编码的 nonce + 密文
+const ENCRYPTED_PREFIX: &str = "aipp:enc:v1:";
+const KEYCHAIN_SERVICE: &str = "aipp";
+pub const KEY_LEN: usize = 32;
+
+pub fn is_encrypted(text: &str) -> bool {
+    text.starts_with(ENCRYPTED_PREFIX)
+}
+
+pub fn generate_key() -> Result<[u8; KEY_LEN], String> {
+    let mut key = [0u8; KEY_LEN];
+    SystemRandom::new()
+        .fill(&mut key)
+        .map_err(|_| "生成密钥失败".to_string())?;
+    Ok(key)
+}
+
+/// 使用 AES-256-GCM 加密文字，每次加密使用随机的 nonce
+pub fn encrypt_text(key: &[u8; KEY_LEN], text: &str) -> Result<String, String> {
+    let sealing_key =
+        LessSafeKey::new(UnboundKey::new(&AES_256_GCM, key).map_err(|_| "无效的密钥".to_string())?);
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| "生成随机数失败".to_string())?;
+
+    let mut in_out = text.as_bytes().to_vec();
+    sealing_key
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::empty(),
+            &mut in_out,
+        )
+        .map_err(|_| "加密失败".to_string())?;
+
+    let mut data = nonce.to_vec();
+    data.extend(in_out);
+    Ok(format!("{}{}", ENCRYPTED_PREFIX, STANDARD.encode(data)))
+}
+
+/// 解密 encrypt_text 的结果，没有加密前缀的内容原样返回
+pub fn decrypt_text(key: &[u8; KEY_LEN], text: &str) -> Result<String, String> {
+    let Some(encoded) = text.strip_prefix(ENCRYPTED_PREFIX) else {
+        return Ok(text.to_string());
+    };
+    let mut data = STANDARD.decode(encoded).map_err(|e| e.to_string())?;
+    if data.len() < NONCE_LEN {
+        return Err("密文格式错误".to_string());
+    }
+    let opening_key =
+        LessSafeKey::new(UnboundKey::new(&AES_256_GCM, key).map_err(|_| "无效的密钥".to_string())?);
+    let (nonce, ciphertext) = data.split_at_mut(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| "密文格式错误".to_string())?;
+    let plaintext = opening_key
+        .open_in_place(nonce, Aad::empty(), ciphertext)
+        .map_err(|_| "解密失败，密钥不匹配或者内容已损坏".to_string())?;
+    String::from_utf8(plaintext.to_vec()).map_err(|e| e.to_string())
+}
+
+/// 从系统钥匙串读取密钥，确认不存在并且 can_create 为 true 时生成新的密钥并保存。
+/// 已经有内容用旧密钥加密时 can_create 必须为 false，新密钥无法解密这些内容
+pub fn load_or_create_key(
+    app_handle: &AppHandle,
+    account: &str,
+    can_create: bool,
+) -> Result<[u8; KEY_LEN], String> {
+    if let Some(secret) = keychain::get_secret(app_handle, KEYCHAIN_SERVICE, account)? {
+        let bytes = STANDARD.decode(secret.trim()).map_err(|e| e.to_string())?;
+        return bytes
+            .try_into()
+            .map_err(|_| "钥匙串中保存的密钥长度不正确".to_string());
+    }
+    if !can_create {
+        return Err("系统钥匙串中找不到私密对话的密钥，无法解密已有的私密对话".to_string());
+    }
+    let key = generate_key()?;
+    keychain::set_secret(app_handle, KEYCHAIN_SERVICE, account, &STANDARD.encode(key))?;
+    Ok(key)
+}
+
+// 通过系统自带的命令行工具访问钥匙串：macOS 使用 security，Linux 使用 libsecret 的 secret-tool，
+// Windows 没有可以直接读取凭据的命令，使用 DPAPI 加密后保存在应用数据目录
+mod keychain {
+    use super::*;
+
+    struct CommandOutput {
+        code: Option<i32>,
+        stdout: String,
+        stderr: String,
+    }
+
+    impl CommandOutput {
+        fn success(&self) -> bool {
+            self.code == Some(0)
+        }
+
+        fn error(&self, message: &str) -> String {
+            if self.stderr.is_empty() {
+                format!("{}，退出码 {:?}", message, self.code)
+            } else {
+                format!("{}: {}", message, self.stderr)
+            }
+        }
+    }
+
+    fn run(command: &mut Command, stdin: Option<&str>) -> Result<CommandOutput, String> {
+        let mut child = command
+            .stdin(if stdin.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("无法访问系统钥匙串: {}", e))?;
+        if let (Some(input), Some(mut child_stdin)) = (stdin, child.stdin.take()) {
+            use std::io::Write;
+            child_stdin
+                .write_all(input.as_bytes())
+                .map_err(|e| e.to_string())?;
+        }
+        let output = child.wait_with_output().map_err(|e| e.to_string())?;
+        Ok(CommandOutput {
+            code: output.status.code(),
+            stdout: String::from_utf8_lossy(&output.stdout).trim().to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        })
+    }
+
+    // security 找不到钥匙串项目时的退出码（errSecItemNotFound）
+    #[cfg(target_os = "macos")]
+    const SECURITY_ITEM_NOT_FOUND: i32 = 44;
+
+    #[cfg(target_os = "macos")]
+    pub fn get_secret(
+        _app_handle: &AppHandle,
+        service: &str,
+        account: &str,
+    ) -> Result<Option<String>, String> {
+        let output = run(
+            Command::new("security").args([
+                "find-generic-password",
+                "-s",
+                service,
+                "-a",
+                account,
+                "-w",
+            ]),
+            None,
+        )?;
+        // 拒绝授权、钥匙串被锁定等错误不能当作没有密钥，否则会生成新的密钥
+        match output.code {
+            _ if output.success() => Ok(Some(output.stdout)),
+            Some(SECURITY_ITEM_NOT_FOUND) => Ok(None),
+            _ => Err(output.error("读取钥匙串失败")),
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    pub fn set_secret(
+        _app_handle: &AppHandle,
+        service: &str,
+        account: &str,
+        secret: &str,
+    ) -> Result<(), String> {
+        // 密钥不能放在命令行参数中，其他进程可以通过 ps 看到，使用交互模式从标准输入读取命令。
+        // 不使用 -U，已经存在同名项目时添加失败，不会覆盖原来的密钥
+        let command = format!(
+            "add-generic-password -s \"{}\" -a \"{}\" -w \"{}\"\n",
+            service, account, secret
+        );
+        run(Command::new("security").arg("-i"), Some(&command))?;
+        // 交互模式中命令失败时 security 仍然正常退出，读回来确认保存的是这个密钥
+        match get_secret(_app_handle, service, account)? {
+            Some(saved) if saved == secret => Ok(()),
+            _ => Err("保存密钥到钥匙串失败".to_string()),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn get_secret(
+        _app_handle: &AppHandle,
+        service: &str,
+        account: &str,
+    ) -> Result<Option<String>, String> {
+        let output = run(
+            Command::new("secret-tool").args(["lookup", "service", service, "account", account]),
+            None,
+        )?;
+        // 找不到时 secret-tool 不输出内容直接返回 1，D-Bus 不可用、钥匙串被锁定等错误会输出到 stderr
+        if output.success() {
+            Ok(Some(output.stdout).filter(|secret| !secret.is_empty()))
+        } else if output.stderr.is_empty() {
+            Ok(None)
+        } else {
+            Err(output.error("读取钥匙串失败"))
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn set_secret(
+        _app_handle: &AppHandle,
+        service: &str,
+        account: &str,
+        secret: &str,
+    ) -> Result<(), String> {
+        let output = run(
+            Command::new("secret-tool").args([
+                "store", "--label", "Aipp", "service", service, "account", account,
+            ]),
+            Some(secret),
+        )?;
+        if output.success() {
+            Ok(())
+        } else {
+            Err(output.error("保存密钥到钥匙串失败，请确认已经安装 secret-tool"))
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    fn secret_path(
+        app_handle: &AppHandle,
+        service: &str,
+        account: &str,
+    ) -> Result<std::path::PathBuf, String> {
+        use tauri::Manager;
+        let dir = app_handle
+            .path()
+            .app_data_dir()
+            .map_err(|e| e.to_string())?
+            .join("keychain");
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        Ok(dir.join(format!("{}_{}.dpapi", service, account)))
+    }
+
+    #[cfg(target_os = "windows")]
+    fn dpapi(action: &str, input: &str) -> Result<String, String> {
+        let script = format!(
+            "Add-Type -AssemblyName System.Security; $data = [Convert]::FromBase64String([Console]::In.ReadToEnd().Trim()); [Convert]::ToBase64String([Security.Cryptography.ProtectedData]::{}($data, $null, 'CurrentUser'))",
+            action
+        );
+        let output = run(
+            Command::new("powershell").args(["-NoProfile", "-NonInteractive", "-Command", &script]),
+            Some(input),
+        )?;
+        if output.success() {
+            Ok(output.stdout)
+        } else {
+            Err(output.error(&format!("DPAPI {} 失败", action)))
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    pub fn get_secret(
+        app_handle: &AppHandle,
+        service: &str,
+        account: &str,
+    ) -> Result<Option<String>, String> {
+        let path = secret_path(app_handle, service, account)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let protected = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let secret = dpapi("Unprotect", &protected)?;
+        let bytes = STANDARD.decode(secret).map_err(|e| e.to_string())?;
+        Ok(Some(String::from_utf8_lossy(&bytes).to_string()))
+    }
+
+    #[cfg(target_os = "windows")]
+    pub fn set_secret(
+        app_handle: &AppHandle,
+        service: &str,
+        account: &str,
+        secret: &str,
+    ) -> Result<(), String> {
+        use std::io::Write;
+        let protected = dpapi("Protect", &STANDARD.encode(secret))?;
+        // 文件已经存在时不覆盖原来的密钥
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(secret_path(app_handle, service, account)?)
+            .and_then(|mut file| file.write_all(protected.as_bytes()))
+            .map_err(|e| format!("保存密钥失败: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_roundtrip() {
+        let key = generate_key().unwrap();
+        let text = "私密对话内容 with emoji 🔒";
+        let encrypted = encrypt_text(&key, text).unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.contains("私密"));
+        assert_ne!(encrypted, encrypt_text(&key, text).unwrap());
+        assert_eq!(decrypt_text(&key, &encrypted).unwrap(), text);
+    }
+
+    #[test]
+    fn test_decrypt_with_wrong_key() {
+        let encrypted = encrypt_text(&generate_key().unwrap(), "secret").unwrap();
+        assert!(decrypt_text(&generate_key().unwrap(), &encrypted).is_err());
+    }
+
+    #[test]
+    fn test_plain_text_passthrough() {
+        let key = generate_key().unwrap();
+        assert_eq!(decrypt_text(&key, "普通内容").unwrap(), "普通内容");
+    }
+}
@@ -0,0 +1,469 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use super::assistant_db::{AssistantModel, AssistantModelConfig};
+use super::llm_db::LLMDatabase;
+use crate::api::llm::{get_model_param_schema, ModelParamSpec};
+
+/// 助手模型参数规则的版本，新增或者修改参数规则时加一
+///
+/// 升级时按当前版本的规则整理已有的参数，数据库中记录的版本比当前版本新时说明数据由新版本写入，不做整理
+pub const ASSISTANT_CONFIG_SCHEMA_VERSION: u32 = 1;
+// system_config 中记录已经按哪个版本的规则整理过参数
+pub const SCHEMA_VERSION_CONFIG_KEY: &str = "assistant_config_schema_version";
+
+/// 参数规则对应的模型，(api_type, 模型代码)。temperature、top_p、max_tokens 的范围按模型
+/// 从 capability 的登记中查询，没有模型时使用提供商没有特别限制时的范围
+pub type ConfigModel<'a> = Option<(&'a str, &'a str)>;
+
+/// 助手第一个模型的提供商类型和模型代码，助手的参数按这个模型的范围检查，
+/// 没有模型或者提供商已经删除时返回 None
+pub fn assistant_config_model(
+    llm_db: &LLMDatabase,
+    models: &[AssistantModel],
+) -> Option<(String, String)> {
+    let model = models.first()?;
+    let provider = llm_db.get_llm_provider(model.provider_id).ok()?;
+    Some((provider.api_type, model.model_code.clone()))
+}
+
+/// 参数值的类型和取值范围
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ConfigValueKind {
+    Integer { min: i64, max: i64 },
+    Float { min: f64, max: f64 },
+    Boolean,
+    Enum { values: &'static [&'static str] },
+    Text,
+}
+
+impl ConfigValueKind {
+    /// 保存到 assistant_model_config 的 value_type，和新建助手时的默认参数一致
+    pub fn value_type(&self) -> &'static str {
+        match self {
+            ConfigValueKind::Integer { .. } => "number",
+            ConfigValueKind::Float { .. } => "float",
+            ConfigValueKind::Boolean => "boolean",
+            ConfigValueKind::Enum { .. } | ConfigValueKind::Text => "string",
+        }
+    }
+
+    // 返回整理后的值，空值表示没有设置，使用默认值
+    fn normalize(&self, value: &str) -> Result<String, ConfigErrorReason> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(String::new());
+        }
+        match self {
+            ConfigValueKind::Integer { min, max } => {
+                let number = value
+                    .parse::<i64>()
+                    .ok()
+                    .or_else(|| {
+                        // 2000.0 这样没有小数部分的值也接受
+                        value
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|v| v.is_finite() && v.fract() == 0.0)
+                            .map(|v| v as i64)
+                    })
+                    .ok_or(ConfigErrorReason::InvalidInteger)?;
+                if number < *min || number > *max {
+                    return Err(ConfigErrorReason::OutOfRange {
+                        min: *min as f64,
+                        max: *max as f64,
+                    });
+                }
+                Ok(number.to_string())
+            }
+            ConfigValueKind::Float { min, max } => {
+                let number = value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|v| v.is_finite())
+                    .ok_or(ConfigErrorReason::InvalidFloat)?;
+                if number < *min || number > *max {
+                    return Err(ConfigErrorReason::OutOfRange {
+                        min: *min,
+                        max: *max,
+                    });
+                }
+                Ok(value.to_string())
+            }
+            ConfigValueKind::Boolean => match value.to_lowercase().as_str() {
+                "true" | "1" | "yes" | "on" => Ok("true".to_string()),
+                "false" | "0" | "no" | "off" => Ok("false".to_string()),
+                _ => Err(ConfigErrorReason::InvalidBoolean),
+            },
+            ConfigValueKind::Enum { values } => {
+                let value = value.trim_matches('"').trim().to_lowercase();
+                if values.contains(&value.as_str()) {
+                    Ok(value)
+                } else {
+                    Err(ConfigErrorReason::InvalidEnum {
+                        allowed: values.iter().map(|v| v.to_string()).collect(),
+                    })
+                }
+            }
+            ConfigValueKind::Text => Ok(value.to_string()),
+        }
+    }
+}
+
+/// 一个参数的规则，通过 get_assistant_config_schema 返回给设置界面
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ConfigFieldSchema {
+    pub name: &'static str,
+    #[serde(flatten)]
+    pub kind: ConfigValueKind,
+}
+
+/// 设置界面使用的参数规则
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AssistantConfigSchema {
+    pub version: u32,
+    pub fields: Vec<ConfigFieldSchema>,
+}
+
+// 和模型无关的参数，模型相关的数值参数见 capability 中的 get_model_param_schema
+const CONFIG_FIELDS: &[ConfigFieldSchema] = &[
+    ConfigFieldSchema {
+        name: "stream",
+        kind: ConfigValueKind::Boolean,
+    },
+    ConfigFieldSchema {
+        name: "show_reasoning",
+        kind: ConfigValueKind::Boolean,
+    },
+    // 单位秒，0 表示不限制
+    ConfigFieldSchema {
+        name: "max_generation_time",
+        kind: ConfigValueKind::Integer {
+            min: 0,
+            max: 86_400,
+        },
+    },
+    ConfigFieldSchema {
+        name: "max_images",
+        kind: ConfigValueKind::Integer { min: 0, max: 100 },
+    },
+    ConfigFieldSchema {
+        name: "response_length",
+        kind: ConfigValueKind::Enum {
+            values: &["short", "medium", "long"],
+        },
+    },
+    ConfigFieldSchema {
+        name: "image_detail",
+        kind: ConfigValueKind::Enum {
+            values: &["auto", "low", "high"],
+        },
+    },
+    ConfigFieldSchema {
+        name: "stop",
+        kind: ConfigValueKind::Text,
+    },
+];
+
+/// 模型接受的数值参数加上和模型无关的参数，模型不接受的参数不返回
+pub fn assistant_config_schema(model: ConfigModel) -> AssistantConfigSchema {
+    let (api_type, model_code) = model.unwrap_or_default();
+    let mut fields = get_model_param_schema(api_type, model_code)
+        .iter()
+        .map(|spec| ConfigFieldSchema {
+            name: spec.name,
+            kind: param_kind(spec),
+        })
+        .collect::<Vec<_>>();
+    fields.extend(CONFIG_FIELDS.iter().cloned());
+    AssistantConfigSchema {
+        version: ASSISTANT_CONFIG_SCHEMA_VERSION,
+        fields,
+    }
+}
+
+fn param_kind(spec: &ModelParamSpec) -> ConfigValueKind {
+    match spec.value_type {
+        "number" => ConfigValueKind::Integer {
+            min: spec.min as i64,
+            max: spec.max as i64,
+        },
+        _ => ConfigValueKind::Float {
+            min: spec.min,
+            max: spec.max,
+        },
+    }
+}
+
+/// 参数值不符合规则的原因
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum ConfigErrorReason {
+    InvalidInteger,
+    InvalidFloat,
+    InvalidBoolean,
+    OutOfRange { min: f64, max: f64 },
+    InvalidEnum { allowed: Vec<String> },
+    Unsupported,
+}
+
+/// 保存助手时没有通过检查的参数，作为 AppError::InvalidAssistantConfig 返回给设置界面
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ConfigValidationError {
+    pub name: String,
+    pub value: String,
+    #[serde(flatten)]
+    pub reason: ConfigErrorReason,
+}
+
+impl fmt::Display for ConfigValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.reason {
+            ConfigErrorReason::InvalidInteger => write!(f, "{} 需要是整数", self.name),
+            ConfigErrorReason::InvalidFloat => write!(f, "{} 需要是数字", self.name),
+            ConfigErrorReason::InvalidBoolean => write!(f, "{} 需要是 true 或 false", self.name),
+            ConfigErrorReason::OutOfRange { min, max } => {
+                write!(f, "{} 需要在 {} 到 {} 之间", self.name, min, max)
+            }
+            ConfigErrorReason::InvalidEnum { allowed } => {
+                write!(f, "{} 只能是 {} 中的一个", self.name, allowed.join("、"))
+            }
+            ConfigErrorReason::Unsupported => write!(f, "不支持的参数: {}", self.name),
+        }
+    }
+}
+
+/// 多个参数的检查结果
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ConfigValidationErrors(pub Vec<ConfigValidationError>);
+
+impl fmt::Display for ConfigValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let messages: Vec<String> = self.0.iter().map(|e| e.to_string()).collect();
+        write!(f, "{}", messages.join("；"))
+    }
+}
+
+// 模型不接受的数值参数没有规则，发送请求时不会带上
+fn field_kind(name: &str, model: ConfigModel) -> Option<ConfigValueKind> {
+    if let Some(field) = CONFIG_FIELDS.iter().find(|field| field.name == name) {
+        return Some(field.kind.clone());
+    }
+    let (api_type, model_code) = model.unwrap_or_default();
+    get_model_param_schema(api_type, model_code)
+        .iter()
+        .find(|spec| spec.name == name)
+        .map(param_kind)
+}
+
+/// 按规则整理参数值，返回整理后的值和 value_type；没有规则的参数原样保留，新版本增加的参数不会被拒绝
+pub fn normalize_config_value(
+    name: &str,
+    value: &str,
+    value_type: &str,
+    model: ConfigModel,
+) -> Result<(String, String), ConfigValidationError> {
+    let Some(kind) = field_kind(name, model) else {
+        return Ok((value.to_string(), value_type.to_string()));
+    };
+    kind.normalize(value)
+        .map(|value| (value, kind.value_type().to_string()))
+        .map_err(|reason| ConfigValidationError {
+            name: name.to_string(),
+            value: value.to_string(),
+            reason,
+        })
+}
+
+/// 检查并整理保存的参数，有参数不符合规则时返回所有的错误
+pub fn validate_model_configs(
+    configs: &mut [AssistantModelConfig],
+    model: ConfigModel,
+) -> Result<(), ConfigValidationErrors> {
+    let mut errors = Vec::new();
+    for config in configs.iter_mut() {
+        let value = config.value.as_deref().unwrap_or_default();
+        match normalize_config_value(&config.name, value, &config.value_type, model) {
+            Ok((value, value_type)) => {
+                config.value = Some(value);
+                config.value_type = value_type;
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ConfigValidationErrors(errors))
+    }
+}
+
+/// 预设中只能保存模型的数值参数，数值按模型的范围检查
+pub fn validate_preset_param(
+    name: &str,
+    value: &str,
+    model: ConfigModel,
+) -> Result<(), ConfigValidationError> {
+    let is_model_param = get_model_param_schema("", "")
+        .iter()
+        .any(|spec| spec.name == name);
+    if !is_model_param {
+        return Err(ConfigValidationError {
+            name: name.to_string(),
+            value: value.to_string(),
+            reason: ConfigErrorReason::Unsupported,
+        });
+    }
+    // 空值表示使用默认值，预设中不接受
+    match normalize_config_value(name, value, "", model)? {
+        (normalized, _) if normalized.is_empty() => Err(ConfigValidationError {
+            name: name.to_string(),
+            value: value.to_string(),
+            reason: ConfigErrorReason::InvalidFloat,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// 升级时整理已有的参数：超出范围的值改为最近的边界值，无法识别的值清空后使用默认值
+pub fn migrate_config_value(
+    name: &str,
+    value: &str,
+    value_type: &str,
+    model: ConfigModel,
+) -> (String, String) {
+    match normalize_config_value(name, value, value_type, model) {
+        Ok(normalized) => normalized,
+        Err(e) => {
+            let kind = field_kind(name, model)
+                .map(|kind| kind.value_type())
+                .unwrap_or(value_type);
+            let value = match e.reason {
+                ConfigErrorReason::OutOfRange { min, max } => {
+                    let number = value.trim().parse::<f64>().unwrap_or(min).clamp(min, max);
+                    if kind == "number" {
+                        (number.round() as i64).to_string()
+                    } else {
+                        number.to_string()
+                    }
+                }
+                _ => String::new(),
+            };
+            (value, kind.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_config_value() {
+        assert_eq!(
+            normalize_config_value("max_tokens", " 2000.0 ", "string", None).unwrap(),
+            ("2000".to_string(), "number".to_string())
+        );
+        assert_eq!(
+            normalize_config_value("stream", "True", "string", None).unwrap(),
+            ("true".to_string(), "boolean".to_string())
+        );
+        assert_eq!(
+            normalize_config_value("response_length", "\"Short\"", "string", None)
+                .unwrap()
+                .0,
+            "short"
+        );
+        assert_eq!(
+            normalize_config_value("temperature", "3", "float", None)
+                .unwrap_err()
+                .reason,
+            ConfigErrorReason::OutOfRange { min: 0.0, max: 2.0 }
+        );
+        assert_eq!(
+            normalize_config_value("top_p", "abc", "float", None)
+                .unwrap_err()
+                .reason,
+            ConfigErrorReason::InvalidFloat
+        );
+        // 没有规则的参数和空值原样保留
+        assert_eq!(
+            normalize_config_value("builtin_tools", "code_interpreter", "string", None).unwrap(),
+            ("code_interpreter".to_string(), "string".to_string())
+        );
+        assert_eq!(
+            normalize_config_value("max_tokens", "", "number", None)
+                .unwrap()
+                .0,
+            ""
+        );
+    }
+
+    #[test]
+    fn test_migrate_config_value() {
+        assert_eq!(
+            migrate_config_value("temperature", "5", "float", None),
+            ("2".to_string(), "float".to_string())
+        );
+        assert_eq!(
+            migrate_config_value("max_tokens", "0", "number", None),
+            ("1".to_string(), "number".to_string())
+        );
+        assert_eq!(
+            migrate_config_value("stream", "maybe", "boolean", None),
+            (String::new(), "boolean".to_string())
+        );
+    }
+
+    #[test]
+    fn test_model_param_ranges() {
+        // 数值参数的范围和 capability 中登记的一致
+        let claude = Some(("anthropic", "claude-3-5-sonnet-latest"));
+        assert_eq!(
+            normalize_config_value("temperature", "1.5", "float", claude)
+                .unwrap_err()
+                .reason,
+            ConfigErrorReason::OutOfRange { min: 0.0, max: 1.0 }
+        );
+        assert!(normalize_config_value("temperature", "1.5", "float", None).is_ok());
+        assert!(normalize_config_value("max_tokens", "8192", "number", claude).is_ok());
+        assert!(normalize_config_value("max_tokens", "8193", "number", claude).is_err());
+        assert!(normalize_config_value(
+            "max_tokens",
+            "50000",
+            "number",
+            Some(("openai_api", "o3"))
+        )
+        .is_ok());
+        // 模型不接受的参数没有规则，发送请求时不会带上
+        assert!(
+            normalize_config_value("temperature", "5", "float", Some(("openai_api", "o3"))).is_ok()
+        );
+
+        let schema = assistant_config_schema(Some(("openai_api", "o3-mini")));
+        assert!(schema
+            .fields
+            .iter()
+            .all(|field| field.name != "temperature"));
+        assert!(schema
+            .fields
+            .iter()
+            .any(|field| field.name == "response_length"));
+    }
+
+    #[test]
+    fn test_validate_preset_param() {
+        assert!(validate_preset_param("temperature", "1.1", None).is_ok());
+        assert!(validate_preset_param("top_p", "1.5", None).is_err());
+        assert!(validate_preset_param("temperature", "hot", None).is_err());
+        assert!(validate_preset_param("temperature", "", None).is_err());
+        assert_eq!(
+            validate_preset_param("seed", "1", None).unwrap_err().reason,
+            ConfigErrorReason::Unsupported
+        );
+        assert!(validate_preset_param("top_p", "0.99", Some(("zhipu", "glm-4"))).is_ok());
+        assert!(validate_preset_param("top_p", "1", Some(("zhipu", "glm-4"))).is_err());
+    }
+}
@@ -1,6 +1,8 @@
 use super::get_db_path;
-use rusqlite::{params, Connection, Result};
+use crate::AppHandle;
+use rusqlite::{params, Connection, OptionalExtension, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Assistant {
@@ -10,6 +12,39 @@ pub struct Assistant {
     pub assistant_type: Option<i64>, // 0: 普通对话助手, 1: 多模型对比助手，2: 工作流助手，3: 展示助手
     pub is_addition: bool,
     pub created_time: String,
+    // 以下字段在旧版本导出的配置中不存在
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub is_favorite: bool,
+    // 使用次数和最后使用时间，每次用这个助手提问时自动更新
+    #[serde(default)]
+    pub use_count: i64,
+    #[serde(default)]
+    pub last_used_time: Option<String>,
+    // 当前使用的参数预设名称，为空表示直接使用助手的模型参数
+    #[serde(default)]
+    pub active_preset: Option<String>,
+}
+
+// 转义 LIKE 中的通配符，关键字中的 % 和 _ 按普通字符匹配
+fn escape_like(keyword: &str) -> String {
+    keyword
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// 助手列表的筛选和排序条件，所有字段都可以不传
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AssistantFilter {
+    pub category: Option<String>,
+    pub favorite_only: Option<bool>,
+    // 按名称和描述模糊匹配
+    pub keyword: Option<String>,
+    // most_used: 使用次数, recent: 最近使用, name: 名称, created: 创建时间，
+    // 默认收藏的在前，然后按使用次数排序
+    pub sort: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -49,12 +84,116 @@ pub struct AssistantPromptParam {
     pub param_value: Option<String>,
 }
 
+// 两个助手变体的一次对比实验，a_on_left 记录盲测时 A 显示在哪一侧
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Experiment {
+    pub id: i64,
+    pub assistant_a_id: i64,
+    pub assistant_b_id: i64,
+    pub prompt: String,
+    pub conversation_a_id: i64,
+    pub conversation_b_id: i64,
+    pub a_on_left: bool,
+    // a、b 或 tie，还没有投票时为空
+    pub vote: Option<String>,
+    pub created_time: String,
+    pub voted_time: Option<String>,
+}
+
+// 助手的常驻上下文，关联到 message_attachment 中的文本附件，例如风格指南、代码规范
+// use_retrieval 为 true 时只注入与提问相关的片段，否则注入全文
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AssistantAttachment {
+    pub id: i64,
+    pub assistant_id: i64,
+    pub attachment_id: i64,
+    pub use_retrieval: bool,
+    pub created_time: String,
+}
+
+/// 助手的参数预设，选中后提问时用预设中的参数覆盖助手的模型参数
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AssistantPreset {
+    pub name: String,
+    // 参数名到参数值，例如 temperature -> 0.2
+    pub params: HashMap<String, String>,
+    // 内置预设不能删除，保存同名的预设会覆盖内置的参数
+    #[serde(default)]
+    pub builtin: bool,
+}
+
+/// 技能：一段提示词、需要的工具和推荐的模型，可以挂到任意助手上，多个助手共用同一个技能
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Skill {
+    pub id: i64,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub prompt: String,
+    // 需要的内置工具名称，例如 read_file、run_shell
+    #[serde(default)]
+    pub required_tools: Vec<String>,
+    // 推荐的模型，助手启用了多个技能时使用排在最前面的技能推荐的模型
+    #[serde(default)]
+    pub preferred_provider_id: Option<i64>,
+    #[serde(default)]
+    pub preferred_model_code: Option<String>,
+    #[serde(default)]
+    pub created_time: String,
+}
+
+/// 助手挂载的技能，按 sort_order 和技能 id 排序，组合提示词时使用同样的顺序
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AssistantSkill {
+    pub assistant_id: i64,
+    pub skill: Skill,
+    pub is_enabled: bool,
+    pub sort_order: i64,
+}
+
+/// 助手的回答风格示例，一问一答为一组，提问时按 sort_order 放在对话历史前面作为 few-shot 示例
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AssistantExample {
+    pub id: i64,
+    pub assistant_id: i64,
+    pub user_content: String,
+    pub assistant_content: String,
+    pub is_enabled: bool,
+    #[serde(default)]
+    pub sort_order: i64,
+    #[serde(default)]
+    pub created_time: String,
+}
+
+/// 快捷操作：在询问窗口中用某个助手处理选中文字，每个快捷操作单独配置回答写入剪贴板的方式
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QuickAction {
+    pub id: i64,
+    pub name: String,
+    pub assistant_id: i64,
+    // 回答写入剪贴板的方式：off、final（生成结束后写入）、stream（生成过程中持续更新）
+    #[serde(default)]
+    pub clipboard_mode: String,
+    // 只写入剪贴板，不在询问窗口中显示
+    #[serde(default)]
+    pub clipboard_only: bool,
+    #[serde(default)]
+    pub created_time: String,
+}
+
+/// 内置的精确、平衡、创意三个预设
+pub const BUILTIN_PRESETS: [(&str, &str, &str); 3] = [
+    ("precise", "0.2", "0.8"),
+    ("balanced", "0.7", "0.95"),
+    ("creative", "1.1", "1.0"),
+];
+
 pub struct AssistantDatabase {
     pub conn: Connection,
 }
 
 impl AssistantDatabase {
-    pub fn new(app_handle: &tauri::AppHandle) -> rusqlite::Result<Self> {
+    pub fn new(app_handle: &AppHandle) -> rusqlite::Result<Self> {
         let db_path = get_db_path(app_handle, "assistant.db");
         let conn = Connection::open(db_path.unwrap())?;
         Ok(AssistantDatabase { conn })
@@ -68,7 +207,12 @@ impl AssistantDatabase {
                 description TEXT,
                 assistant_type INTEGER NOT NULL DEFAULT 0,
                 is_addition BOOLEAN NOT NULL DEFAULT 0,
-                created_time DATETIME DEFAULT CURRENT_TIMESTAMP
+                created_time DATETIME DEFAULT CURRENT_TIMESTAMP,
+                category TEXT,
+                is_favorite BOOLEAN NOT NULL DEFAULT 0,
+                use_count INTEGER NOT NULL DEFAULT 0,
+                last_used_time DATETIME,
+                active_preset TEXT
             );",
             [],
         )?;
@@ -117,6 +261,94 @@ impl AssistantDatabase {
             );",
             [],
         )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS experiment (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                assistant_a_id INTEGER NOT NULL,
+                assistant_b_id INTEGER NOT NULL,
+                prompt TEXT NOT NULL,
+                conversation_a_id INTEGER NOT NULL,
+                conversation_b_id INTEGER NOT NULL,
+                a_on_left BOOLEAN NOT NULL DEFAULT 1,
+                vote TEXT,
+                created_time DATETIME DEFAULT CURRENT_TIMESTAMP,
+                voted_time DATETIME
+            );",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS assistant_attachment (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                assistant_id INTEGER NOT NULL,
+                attachment_id INTEGER NOT NULL,
+                use_retrieval BOOLEAN NOT NULL DEFAULT 0,
+                created_time DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (assistant_id) REFERENCES assistant(id)
+            );",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS assistant_preset (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                assistant_id INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                params TEXT NOT NULL,
+                created_time DATETIME DEFAULT CURRENT_TIMESTAMP,
+                UNIQUE (assistant_id, name),
+                FOREIGN KEY (assistant_id) REFERENCES assistant(id)
+            );",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS skill (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                description TEXT,
+                prompt TEXT NOT NULL,
+                required_tools TEXT NOT NULL DEFAULT '[]',
+                preferred_provider_id INTEGER,
+                preferred_model_code TEXT,
+                created_time DATETIME DEFAULT CURRENT_TIMESTAMP
+            );",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS assistant_skill (
+                assistant_id INTEGER NOT NULL,
+                skill_id INTEGER NOT NULL,
+                is_enabled BOOLEAN NOT NULL DEFAULT 1,
+                sort_order INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (assistant_id, skill_id),
+                FOREIGN KEY (assistant_id) REFERENCES assistant(id),
+                FOREIGN KEY (skill_id) REFERENCES skill(id)
+            );",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS assistant_example (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                assistant_id INTEGER NOT NULL,
+                user_content TEXT NOT NULL,
+                assistant_content TEXT NOT NULL,
+                is_enabled BOOLEAN NOT NULL DEFAULT 1,
+                sort_order INTEGER NOT NULL DEFAULT 0,
+                created_time DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (assistant_id) REFERENCES assistant(id)
+            );",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS quick_action (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                assistant_id INTEGER NOT NULL,
+                clipboard_mode TEXT NOT NULL DEFAULT 'off',
+                clipboard_only BOOLEAN NOT NULL DEFAULT 0,
+                created_time DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (assistant_id) REFERENCES assistant(id)
+            );",
+            [],
+        )?;
 
         if let Err(err) = self.init_assistant() {
             println!("init_assistant error: {:?}", err);
@@ -231,6 +463,37 @@ impl AssistantDatabase {
         Ok(())
     }
 
+    /// 升级时按参数规则整理已有的参数，同时更新 value_type
+    pub fn update_assistant_model_config_value(
+        &self,
+        id: i64,
+        value: &str,
+        value_type: &str,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE assistant_model_config SET value = ?, value_type = ? WHERE id = ?",
+            params![value, value_type, id],
+        )?;
+        Ok(())
+    }
+
+    pub fn list_all_assistant_model_configs(&self) -> Result<Vec<AssistantModelConfig>> {
+        let mut stmt = self.conn.prepare("SELECT id, assistant_id, assistant_model_id, name, value, value_type FROM assistant_model_config")?;
+        let configs = stmt
+            .query_map([], |row| {
+                Ok(AssistantModelConfig {
+                    id: row.get(0)?,
+                    assistant_id: row.get(1)?,
+                    assistant_model_id: row.get(2)?,
+                    name: row.get(3)?,
+                    value: row.get(4)?,
+                    value_type: row.get(5)?,
+                })
+            })?
+            .collect::<Result<Vec<AssistantModelConfig>>>()?;
+        Ok(configs)
+    }
+
     pub fn delete_assistant_model_config_by_assistant_id(&self, assistant_id: i64) -> Result<()> {
         self.conn.execute(
             "DELETE FROM assistant_model_config WHERE assistant_id = ?",
@@ -277,17 +540,8 @@ impl AssistantDatabase {
     }
 
     pub fn get_assistants(&self) -> Result<Vec<Assistant>> {
-        let mut stmt = self.conn.prepare("SELECT id, name, description, assistant_type, is_addition, created_time FROM assistant")?;
-        let assistant_iter = stmt.query_map(params![], |row| {
-            Ok(Assistant {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                description: row.get(2)?,
-                assistant_type: row.get(3)?,
-                is_addition: row.get(4)?,
-                created_time: row.get(5)?,
-            })
-        })?;
+        let mut stmt = self.conn.prepare("SELECT id, name, description, assistant_type, is_addition, created_time, category, is_favorite, use_count, last_used_time, active_preset FROM assistant")?;
+        let assistant_iter = stmt.query_map(params![], Self::map_assistant)?;
 
         let mut assistants = Vec::new();
         for assistant in assistant_iter {
@@ -297,17 +551,8 @@ impl AssistantDatabase {
     }
 
     pub fn get_assistant(&self, assistant_id: i64) -> Result<Assistant> {
-        let mut stmt = self.conn.prepare("SELECT id, name, description, assistant_type, is_addition, created_time FROM assistant WHERE id = ?")?;
-        let mut assistant_iter = stmt.query_map(params![assistant_id], |row| {
-            Ok(Assistant {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                description: row.get(2)?,
-                assistant_type: row.get(3)?,
-                is_addition: row.get(4)?,
-                created_time: row.get(5)?,
-            })
-        })?;
+        let mut stmt = self.conn.prepare("SELECT id, name, description, assistant_type, is_addition, created_time, category, is_favorite, use_count, last_used_time, active_preset FROM assistant WHERE id = ?")?;
+        let mut assistant_iter = stmt.query_map(params![assistant_id], Self::map_assistant)?;
 
         if let Some(assistant) = assistant_iter.next() {
             return Ok(assistant?);
@@ -316,6 +561,91 @@ impl AssistantDatabase {
         Err(rusqlite::Error::QueryReturnedNoRows)
     }
 
+    fn map_assistant(row: &rusqlite::Row) -> Result<Assistant> {
+        Ok(Assistant {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            description: row.get(2)?,
+            assistant_type: row.get(3)?,
+            is_addition: row.get(4)?,
+            created_time: row.get(5)?,
+            category: row.get(6)?,
+            is_favorite: row.get(7)?,
+            use_count: row.get(8)?,
+            last_used_time: row.get(9)?,
+            active_preset: row.get(10)?,
+        })
+    }
+
+    pub fn list_assistants(&self, filter: &AssistantFilter) -> Result<Vec<Assistant>> {
+        let mut conditions = Vec::new();
+        let mut values: Vec<String> = Vec::new();
+        if let Some(category) = filter.category.as_ref().filter(|c| !c.is_empty()) {
+            conditions.push("category = ?");
+            values.push(category.clone());
+        }
+        if filter.favorite_only == Some(true) {
+            conditions.push("is_favorite = 1");
+        }
+        if let Some(keyword) = filter
+            .keyword
+            .as_ref()
+            .map(|k| k.trim())
+            .filter(|k| !k.is_empty())
+        {
+            conditions.push("(name LIKE ? ESCAPE '\\' OR description LIKE ? ESCAPE '\\')");
+            let pattern = format!("%{}%", escape_like(keyword));
+            values.push(pattern.clone());
+            values.push(pattern);
+        }
+        let where_clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+        // 排序字段只能从固定的几种中选择，不拼接外部传入的内容
+        let order_by = match filter.sort.as_deref() {
+            Some("most_used") => "use_count DESC, last_used_time DESC, id",
+            Some("recent") => "last_used_time IS NULL, last_used_time DESC, id",
+            Some("name") => "name COLLATE NOCASE, id",
+            Some("created") => "created_time DESC, id DESC",
+            _ => "is_favorite DESC, use_count DESC, last_used_time DESC, id",
+        };
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT id, name, description, assistant_type, is_addition, created_time, category, is_favorite, use_count, last_used_time, active_preset FROM assistant {} ORDER BY {}",
+            where_clause, order_by
+        ))?;
+        let rows = stmt.query_map(
+            rusqlite::params_from_iter(values.iter()),
+            Self::map_assistant,
+        )?;
+        rows.collect()
+    }
+
+    pub fn update_assistant_favorite(&self, id: i64, is_favorite: bool) -> Result<()> {
+        self.conn.execute(
+            "UPDATE assistant SET is_favorite = ? WHERE id = ?",
+            params![is_favorite, id],
+        )?;
+        Ok(())
+    }
+
+    pub fn update_assistant_category(&self, id: i64, category: Option<&str>) -> Result<()> {
+        self.conn.execute(
+            "UPDATE assistant SET category = ? WHERE id = ?",
+            params![category, id],
+        )?;
+        Ok(())
+    }
+
+    pub fn record_assistant_usage(&self, id: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE assistant SET use_count = use_count + 1, last_used_time = CURRENT_TIMESTAMP WHERE id = ?",
+            params![id],
+        )?;
+        Ok(())
+    }
+
     pub fn get_assistant_model(&self, assistant_id: i64) -> Result<Vec<AssistantModel>> {
         let mut stmt = self.conn.prepare("SELECT id, assistant_id, provider_id, model_code, alias FROM assistant_model WHERE assistant_id = ?")?;
         let assistant_model_iter = stmt.query_map(params![assistant_id], |row| {
@@ -425,6 +755,483 @@ impl AssistantDatabase {
         Ok(assistant_prompt_params)
     }
 
+    pub fn add_experiment(
+        &self,
+        assistant_a_id: i64,
+        assistant_b_id: i64,
+        prompt: &str,
+        conversation_a_id: i64,
+        conversation_b_id: i64,
+        a_on_left: bool,
+    ) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO experiment (assistant_a_id, assistant_b_id, prompt, conversation_a_id, conversation_b_id, a_on_left) VALUES (?, ?, ?, ?, ?, ?)",
+            params![
+                assistant_a_id,
+                assistant_b_id,
+                prompt,
+                conversation_a_id,
+                conversation_b_id,
+                a_on_left
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn get_experiment(&self, id: i64) -> Result<Experiment> {
+        self.conn.query_row(
+            "SELECT id, assistant_a_id, assistant_b_id, prompt, conversation_a_id, conversation_b_id, a_on_left, vote, created_time, voted_time FROM experiment WHERE id = ?",
+            params![id],
+            |row| {
+                Ok(Experiment {
+                    id: row.get(0)?,
+                    assistant_a_id: row.get(1)?,
+                    assistant_b_id: row.get(2)?,
+                    prompt: row.get(3)?,
+                    conversation_a_id: row.get(4)?,
+                    conversation_b_id: row.get(5)?,
+                    a_on_left: row.get(6)?,
+                    vote: row.get(7)?,
+                    created_time: row.get(8)?,
+                    voted_time: row.get(9)?,
+                })
+            },
+        )
+    }
+
+    pub fn update_experiment_vote(&self, id: i64, vote: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE experiment SET vote = ?, voted_time = CURRENT_TIMESTAMP WHERE id = ?",
+            params![vote, id],
+        )?;
+        Ok(())
+    }
+
+    /// 统计一组助手之间已投票的结果，返回每个助手的获胜次数和平局次数，助手的先后顺序不影响结果
+    pub fn get_experiment_votes(
+        &self,
+        assistant_id: i64,
+        other_assistant_id: i64,
+    ) -> Result<(i64, i64, i64)> {
+        let mut stmt = self.conn.prepare(
+            "SELECT assistant_a_id, vote FROM experiment
+             WHERE vote IS NOT NULL
+               AND ((assistant_a_id = ?1 AND assistant_b_id = ?2) OR (assistant_a_id = ?2 AND assistant_b_id = ?1))",
+        )?;
+        let rows = stmt.query_map(params![assistant_id, other_assistant_id], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let (mut wins, mut losses, mut ties) = (0, 0, 0);
+        for row in rows {
+            let (assistant_a_id, vote) = row?;
+            let winner = match vote.as_str() {
+                "a" => assistant_a_id,
+                "b" if assistant_a_id == assistant_id => other_assistant_id,
+                "b" => assistant_id,
+                _ => {
+                    ties += 1;
+                    continue;
+                }
+            };
+            if winner == assistant_id {
+                wins += 1;
+            } else {
+                losses += 1;
+            }
+        }
+        Ok((wins, losses, ties))
+    }
+
+    pub fn add_assistant_attachment(
+        &self,
+        assistant_id: i64,
+        attachment_id: i64,
+        use_retrieval: bool,
+    ) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO assistant_attachment (assistant_id, attachment_id, use_retrieval) VALUES (?, ?, ?)",
+            params![assistant_id, attachment_id, use_retrieval],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn get_assistant_attachments(&self, assistant_id: i64) -> Result<Vec<AssistantAttachment>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, assistant_id, attachment_id, use_retrieval, created_time FROM assistant_attachment WHERE assistant_id = ? ORDER BY id",
+        )?;
+        let rows = stmt.query_map(params![assistant_id], |row| {
+            Ok(AssistantAttachment {
+                id: row.get(0)?,
+                assistant_id: row.get(1)?,
+                attachment_id: row.get(2)?,
+                use_retrieval: row.get(3)?,
+                created_time: row.get(4)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    pub fn get_assistant_attachment(&self, id: i64) -> Result<Option<AssistantAttachment>> {
+        self.conn
+            .query_row(
+                "SELECT id, assistant_id, attachment_id, use_retrieval, created_time FROM assistant_attachment WHERE id = ?",
+                params![id],
+                |row| {
+                    Ok(AssistantAttachment {
+                        id: row.get(0)?,
+                        assistant_id: row.get(1)?,
+                        attachment_id: row.get(2)?,
+                        use_retrieval: row.get(3)?,
+                        created_time: row.get(4)?,
+                    })
+                },
+            )
+            .optional()
+    }
+
+    /// 所有助手的常驻上下文附件，用于在对话数据库中补齐附件的引用
+    pub fn list_all_assistant_attachments(&self) -> Result<Vec<(i64, i64)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT assistant_id, attachment_id FROM assistant_attachment")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+
+    pub fn delete_assistant_attachment(&self, id: i64) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM assistant_attachment WHERE id = ?", params![id])?;
+        Ok(())
+    }
+
+    pub fn delete_assistant_attachment_by_assistant_id(&self, assistant_id: i64) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM assistant_attachment WHERE assistant_id = ?",
+            params![assistant_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn update_assistant_active_preset(&self, id: i64, preset: Option<&str>) -> Result<()> {
+        self.conn.execute(
+            "UPDATE assistant SET active_preset = ? WHERE id = ?",
+            params![preset, id],
+        )?;
+        Ok(())
+    }
+
+    /// 内置预设和助手自定义的预设，自定义的同名预设覆盖内置的
+    pub fn get_assistant_presets(&self, assistant_id: i64) -> Result<Vec<AssistantPreset>> {
+        let mut presets: Vec<AssistantPreset> = BUILTIN_PRESETS
+            .iter()
+            .map(|(name, temperature, top_p)| AssistantPreset {
+                name: name.to_string(),
+                params: HashMap::from([
+                    ("temperature".to_string(), temperature.to_string()),
+                    ("top_p".to_string(), top_p.to_string()),
+                ]),
+                builtin: true,
+            })
+            .collect();
+
+        let mut stmt = self.conn.prepare(
+            "SELECT name, params FROM assistant_preset WHERE assistant_id = ? ORDER BY id",
+        )?;
+        let rows = stmt.query_map(params![assistant_id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (name, params) = row?;
+            let params: HashMap<String, String> = serde_json::from_str(&params).unwrap_or_default();
+            match presets.iter_mut().find(|preset| preset.name == name) {
+                Some(preset) => preset.params = params,
+                None => presets.push(AssistantPreset {
+                    name,
+                    params,
+                    builtin: false,
+                }),
+            }
+        }
+        Ok(presets)
+    }
+
+    pub fn save_assistant_preset(
+        &self,
+        assistant_id: i64,
+        name: &str,
+        params: &HashMap<String, String>,
+    ) -> Result<()> {
+        let params = serde_json::to_string(params).unwrap_or("{}".to_string());
+        self.conn.execute(
+            "INSERT INTO assistant_preset (assistant_id, name, params) VALUES (?, ?, ?)
+             ON CONFLICT(assistant_id, name) DO UPDATE SET params = excluded.params",
+            params![assistant_id, name, params],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_assistant_preset(&self, assistant_id: i64, name: &str) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM assistant_preset WHERE assistant_id = ? AND name = ?",
+            params![assistant_id, name],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_assistant_preset_by_assistant_id(&self, assistant_id: i64) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM assistant_preset WHERE assistant_id = ?",
+            params![assistant_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn add_skill(&self, skill: &Skill) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO skill (name, description, prompt, required_tools, preferred_provider_id, preferred_model_code)
+             VALUES (?, ?, ?, ?, ?, ?)",
+            params![
+                skill.name,
+                skill.description,
+                skill.prompt,
+                serde_json::to_string(&skill.required_tools).unwrap_or("[]".to_string()),
+                skill.preferred_provider_id,
+                skill.preferred_model_code
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn update_skill(&self, skill: &Skill) -> Result<()> {
+        self.conn.execute(
+            "UPDATE skill SET name = ?, description = ?, prompt = ?, required_tools = ?,
+             preferred_provider_id = ?, preferred_model_code = ? WHERE id = ?",
+            params![
+                skill.name,
+                skill.description,
+                skill.prompt,
+                serde_json::to_string(&skill.required_tools).unwrap_or("[]".to_string()),
+                skill.preferred_provider_id,
+                skill.preferred_model_code,
+                skill.id
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// 删除技能，同时从所有助手上移除
+    pub fn delete_skill(&self, id: i64) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM assistant_skill WHERE skill_id = ?",
+            params![id],
+        )?;
+        self.conn
+            .execute("DELETE FROM skill WHERE id = ?", params![id])?;
+        Ok(())
+    }
+
+    pub fn get_skills(&self) -> Result<Vec<Skill>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, name, description, prompt, required_tools, preferred_provider_id, preferred_model_code, created_time
+             FROM skill ORDER BY id",
+        )?;
+        let rows = stmt.query_map([], skill_from_row)?;
+        rows.collect()
+    }
+
+    /// 挂载技能，排在已有技能的后面，已经挂载过时不做修改
+    pub fn add_assistant_skill(&self, assistant_id: i64, skill_id: i64) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO assistant_skill (assistant_id, skill_id, sort_order)
+             SELECT ?1, ?2, COALESCE(MAX(sort_order), -1) + 1 FROM assistant_skill WHERE assistant_id = ?1",
+            params![assistant_id, skill_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_assistant_skill(&self, assistant_id: i64, skill_id: i64) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM assistant_skill WHERE assistant_id = ? AND skill_id = ?",
+            params![assistant_id, skill_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn update_assistant_skill_enabled(
+        &self,
+        assistant_id: i64,
+        skill_id: i64,
+        is_enabled: bool,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE assistant_skill SET is_enabled = ? WHERE assistant_id = ? AND skill_id = ?",
+            params![is_enabled, assistant_id, skill_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_assistant_skills(&self, assistant_id: i64) -> Result<Vec<AssistantSkill>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT s.id, s.name, s.description, s.prompt, s.required_tools, s.preferred_provider_id,
+                    s.preferred_model_code, s.created_time, a.assistant_id, a.is_enabled, a.sort_order
+             FROM assistant_skill a JOIN skill s ON s.id = a.skill_id
+             WHERE a.assistant_id = ? ORDER BY a.sort_order, s.id",
+        )?;
+        let rows = stmt.query_map(params![assistant_id], |row| {
+            Ok(AssistantSkill {
+                skill: skill_from_row(row)?,
+                assistant_id: row.get(8)?,
+                is_enabled: row.get(9)?,
+                sort_order: row.get(10)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    pub fn delete_assistant_skill_by_assistant_id(&self, assistant_id: i64) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM assistant_skill WHERE assistant_id = ?",
+            params![assistant_id],
+        )?;
+        Ok(())
+    }
+
+    /// 新增示例，排在助手已有示例的后面
+    pub fn add_assistant_example(&self, example: &AssistantExample) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO assistant_example (assistant_id, user_content, assistant_content, is_enabled, sort_order)
+             SELECT ?1, ?2, ?3, ?4, COALESCE(MAX(sort_order), -1) + 1 FROM assistant_example WHERE assistant_id = ?1",
+            params![
+                example.assistant_id,
+                example.user_content,
+                example.assistant_content,
+                example.is_enabled
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn update_assistant_example(&self, example: &AssistantExample) -> Result<()> {
+        self.conn.execute(
+            "UPDATE assistant_example SET user_content = ?, assistant_content = ?, is_enabled = ?
+             WHERE id = ? AND assistant_id = ?",
+            params![
+                example.user_content,
+                example.assistant_content,
+                example.is_enabled,
+                example.id,
+                example.assistant_id
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// 按 example_ids 的顺序重新排列助手的示例，不在列表中的示例排序不变
+    pub fn reorder_assistant_examples(&self, assistant_id: i64, example_ids: &[i64]) -> Result<()> {
+        for (sort_order, id) in example_ids.iter().enumerate() {
+            self.conn.execute(
+                "UPDATE assistant_example SET sort_order = ? WHERE id = ? AND assistant_id = ?",
+                params![sort_order as i64, id, assistant_id],
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn delete_assistant_example(&self, id: i64) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM assistant_example WHERE id = ?", params![id])?;
+        Ok(())
+    }
+
+    pub fn get_assistant_examples(&self, assistant_id: i64) -> Result<Vec<AssistantExample>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, assistant_id, user_content, assistant_content, is_enabled, sort_order, created_time
+             FROM assistant_example WHERE assistant_id = ? ORDER BY sort_order, id",
+        )?;
+        let rows = stmt.query_map(params![assistant_id], |row| {
+            Ok(AssistantExample {
+                id: row.get(0)?,
+                assistant_id: row.get(1)?,
+                user_content: row.get(2)?,
+                assistant_content: row.get(3)?,
+                is_enabled: row.get(4)?,
+                sort_order: row.get(5)?,
+                created_time: row.get(6)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    pub fn delete_assistant_example_by_assistant_id(&self, assistant_id: i64) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM assistant_example WHERE assistant_id = ?",
+            params![assistant_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn add_quick_action(&self, quick_action: &QuickAction) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO quick_action (name, assistant_id, clipboard_mode, clipboard_only)
+             VALUES (?, ?, ?, ?)",
+            params![
+                quick_action.name,
+                quick_action.assistant_id,
+                quick_action.clipboard_mode,
+                quick_action.clipboard_only
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn update_quick_action(&self, quick_action: &QuickAction) -> Result<()> {
+        self.conn.execute(
+            "UPDATE quick_action SET name = ?, assistant_id = ?, clipboard_mode = ?, clipboard_only = ?
+             WHERE id = ?",
+            params![
+                quick_action.name,
+                quick_action.assistant_id,
+                quick_action.clipboard_mode,
+                quick_action.clipboard_only,
+                quick_action.id
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_quick_action(&self, id: i64) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM quick_action WHERE id = ?", params![id])?;
+        Ok(())
+    }
+
+    pub fn delete_quick_action_by_assistant_id(&self, assistant_id: i64) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM quick_action WHERE assistant_id = ?",
+            params![assistant_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_quick_action(&self, id: i64) -> Result<QuickAction> {
+        self.conn.query_row(
+            "SELECT id, name, assistant_id, clipboard_mode, clipboard_only, created_time
+             FROM quick_action WHERE id = ?",
+            params![id],
+            quick_action_from_row,
+        )
+    }
+
+    pub fn get_quick_actions(&self) -> Result<Vec<QuickAction>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, name, assistant_id, clipboard_mode, clipboard_only, created_time
+             FROM quick_action ORDER BY id",
+        )?;
+        let rows = stmt.query_map([], quick_action_from_row)?;
+        rows.collect()
+    }
+
     pub fn init_assistant(&self) -> Result<()> {
         self.conn.execute(
             "INSERT INTO assistant (id, name, description, is_addition) VALUES (1, '快速使用助手', '快捷键呼出的快速使用助手', 0)",
@@ -438,3 +1245,200 @@ impl AssistantDatabase {
         Ok(())
     }
 }
+
+fn skill_from_row(row: &rusqlite::Row) -> Result<Skill> {
+    let required_tools: String = row.get(4)?;
+    Ok(Skill {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        description: row.get(2)?,
+        prompt: row.get(3)?,
+        required_tools: serde_json::from_str(&required_tools).unwrap_or_default(),
+        preferred_provider_id: row.get(5)?,
+        preferred_model_code: row.get(6)?,
+        created_time: row.get(7)?,
+    })
+}
+
+fn quick_action_from_row(row: &rusqlite::Row) -> Result<QuickAction> {
+    Ok(QuickAction {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        assistant_id: row.get(2)?,
+        clipboard_mode: row.get(3)?,
+        clipboard_only: row.get(4)?,
+        created_time: row.get(5)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn skill(name: &str) -> Skill {
+        Skill {
+            id: 0,
+            name: name.to_string(),
+            description: None,
+            prompt: format!("{} 的提示词", name),
+            required_tools: vec!["read_file".to_string()],
+            preferred_provider_id: None,
+            preferred_model_code: None,
+            created_time: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_assistant_skills() {
+        let db = AssistantDatabase {
+            conn: Connection::open_in_memory().unwrap(),
+        };
+        db.create_tables().unwrap();
+        let review = db.add_skill(&skill("代码审查")).unwrap();
+        let translate = db.add_skill(&skill("翻译")).unwrap();
+
+        // 按挂载的顺序排列，重复挂载不改变顺序
+        db.add_assistant_skill(1, translate).unwrap();
+        db.add_assistant_skill(1, review).unwrap();
+        db.add_assistant_skill(1, translate).unwrap();
+        let skills = db.get_assistant_skills(1).unwrap();
+        assert_eq!(
+            skills.iter().map(|s| s.skill.id).collect::<Vec<_>>(),
+            vec![translate, review]
+        );
+        assert!(skills.iter().all(|s| s.is_enabled));
+        assert_eq!(
+            skills[0].skill.required_tools,
+            vec!["read_file".to_string()]
+        );
+
+        db.update_assistant_skill_enabled(1, translate, false)
+            .unwrap();
+        assert!(!db.get_assistant_skills(1).unwrap()[0].is_enabled);
+
+        db.delete_skill(translate).unwrap();
+        let skills = db.get_assistant_skills(1).unwrap();
+        assert_eq!(skills.len(), 1);
+        assert_eq!(skills[0].skill.name, "代码审查");
+    }
+
+    fn example(assistant_id: i64, user_content: &str) -> AssistantExample {
+        AssistantExample {
+            id: 0,
+            assistant_id,
+            user_content: user_content.to_string(),
+            assistant_content: format!("{} 的回答", user_content),
+            is_enabled: true,
+            sort_order: 0,
+            created_time: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_assistant_examples() {
+        let db = AssistantDatabase {
+            conn: Connection::open_in_memory().unwrap(),
+        };
+        db.create_tables().unwrap();
+        let first = db.add_assistant_example(&example(1, "第一个")).unwrap();
+        let second = db.add_assistant_example(&example(1, "第二个")).unwrap();
+        db.add_assistant_example(&example(2, "其他助手")).unwrap();
+
+        let examples = db.get_assistant_examples(1).unwrap();
+        assert_eq!(
+            examples.iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![first, second]
+        );
+
+        db.reorder_assistant_examples(1, &[second, first]).unwrap();
+        db.update_assistant_example(&AssistantExample {
+            id: first,
+            is_enabled: false,
+            ..example(1, "修改后")
+        })
+        .unwrap();
+        let examples = db.get_assistant_examples(1).unwrap();
+        assert_eq!(examples[0].id, second);
+        assert_eq!(examples[1].user_content, "修改后");
+        assert!(!examples[1].is_enabled);
+
+        db.delete_assistant_example_by_assistant_id(1).unwrap();
+        assert!(db.get_assistant_examples(1).unwrap().is_empty());
+        assert_eq!(db.get_assistant_examples(2).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_quick_actions() {
+        let db = AssistantDatabase {
+            conn: Connection::open_in_memory().unwrap(),
+        };
+        db.create_tables().unwrap();
+        let quick_action = QuickAction {
+            id: 0,
+            name: "修正语法并复制".to_string(),
+            assistant_id: 1,
+            clipboard_mode: "final".to_string(),
+            clipboard_only: true,
+            created_time: String::new(),
+        };
+        let id = db.add_quick_action(&quick_action).unwrap();
+        let translate = db
+            .add_quick_action(&QuickAction {
+                name: "翻译".to_string(),
+                assistant_id: 2,
+                clipboard_mode: "off".to_string(),
+                clipboard_only: false,
+                ..quick_action.clone()
+            })
+            .unwrap();
+
+        let saved = db.get_quick_action(id).unwrap();
+        assert_eq!(saved.clipboard_mode, "final");
+        assert!(saved.clipboard_only);
+        db.update_quick_action(&QuickAction {
+            id,
+            clipboard_mode: "stream".to_string(),
+            ..quick_action
+        })
+        .unwrap();
+        assert_eq!(db.get_quick_action(id).unwrap().clipboard_mode, "stream");
+
+        // 删除助手时一起删除它的快捷操作
+        db.delete_quick_action_by_assistant_id(2).unwrap();
+        let ids = db
+            .get_quick_actions()
+            .unwrap()
+            .into_iter()
+            .map(|quick_action| quick_action.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![id]);
+        assert!(db.get_quick_action(translate).is_err());
+        db.delete_quick_action(id).unwrap();
+        assert!(db.get_quick_actions().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_list_assistants_keyword_escape() {
+        let db = AssistantDatabase {
+            conn: Connection::open_in_memory().unwrap(),
+        };
+        db.create_tables().unwrap();
+        db.add_assistant("100%准确", "", None, false).unwrap();
+        db.add_assistant("1000准确", "", None, false).unwrap();
+        db.add_assistant("a_b", "", None, false).unwrap();
+        db.add_assistant("axb", "", None, false).unwrap();
+
+        let names = |keyword: &str| {
+            db.list_assistants(&AssistantFilter {
+                keyword: Some(keyword.to_string()),
+                ..Default::default()
+            })
+            .unwrap()
+            .into_iter()
+            .map(|assistant| assistant.name)
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(names("0%"), vec!["100%准确"]);
+        assert_eq!(names("a_b"), vec!["a_b"]);
+    }
+}
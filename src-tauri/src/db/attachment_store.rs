@@ -0,0 +1,155 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use sha2::{Digest, Sha256};
+
+// 超过这个字节数的附件内容保存到文件中，数据库里只保存引用
+pub const EXTERNAL_THRESHOLD: usize = 256 * 1024;
+// 数据库中保存的引用，后面是内容的 sha256
+pub const REFERENCE_PREFIX: &str = "aipp:file:";
+// 私密对话加密后的内容在引用中带上 enc:，按哈希复用附件时在 SQL 中排除
+const ENCRYPTED_REFERENCE_PREFIX: &str = "aipp:file:enc:";
+const ENCRYPTED_PREFIX: &str = "aipp:enc:";
+// 清理没有引用的文件时跳过最近写入的，它们可能还没有写入数据库
+const ORPHAN_MIN_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// 按内容寻址的附件文件存储，大附件的 base64 内容保存在 app 数据目录的 attachments 中，
+/// 相同内容只保存一份；dir 为 None 时不启用，内容全部保存在数据库中
+#[derive(Debug, Clone, Default)]
+pub struct AttachmentStore {
+    dir: Option<PathBuf>,
+}
+
+impl AttachmentStore {
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        AttachmentStore { dir }
+    }
+
+    /// 内容超过 EXTERNAL_THRESHOLD 时写入文件并返回引用，否则原样返回，返回值保存到数据库中
+    pub fn externalize(&self, content: &str) -> io::Result<String> {
+        let Some(dir) = &self.dir else {
+            return Ok(content.to_string());
+        };
+        if content.len() <= EXTERNAL_THRESHOLD || reference_hash(content).is_some() {
+            return Ok(content.to_string());
+        }
+        let hash = format!("{:x}", Sha256::digest(content.as_bytes()));
+        let path = file_path(dir, &hash);
+        if !path.exists() {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            // 先写临时文件再改名，中途退出时不会留下不完整的内容
+            let temp_path = path.with_extension("tmp");
+            fs::write(&temp_path, content)?;
+            fs::rename(&temp_path, &path)?;
+        }
+        let prefix = if content.starts_with(ENCRYPTED_PREFIX) {
+            ENCRYPTED_REFERENCE_PREFIX
+        } else {
+            REFERENCE_PREFIX
+        };
+        Ok(format!("{}{}", prefix, hash))
+    }
+
+    /// 数据库中的值是引用时读取文件内容，否则原样返回
+    pub fn resolve(&self, value: String) -> io::Result<String> {
+        let Some(hash) = reference_hash(&value) else {
+            return Ok(value);
+        };
+        let Some(dir) = &self.dir else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("附件内容保存在文件中，但没有配置附件目录: {}", hash),
+            ));
+        };
+        fs::read_to_string(file_path(dir, hash))
+    }
+
+    /// 删除数据库中已经没有引用的文件，referenced 为数据库中所有引用的哈希，返回删除的文件数量
+    pub fn remove_unreferenced(&self, referenced: &HashSet<String>) -> io::Result<usize> {
+        let Some(dir) = &self.dir else {
+            return Ok(0);
+        };
+        if !dir.exists() {
+            return Ok(0);
+        }
+        let now = SystemTime::now();
+        let mut removed = 0;
+        for sub_dir in fs::read_dir(dir)? {
+            let sub_dir = sub_dir?.path();
+            if !sub_dir.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(&sub_dir)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                if referenced.contains(&name) {
+                    continue;
+                }
+                let age = entry
+                    .metadata()?
+                    .modified()
+                    .ok()
+                    .and_then(|modified| now.duration_since(modified).ok())
+                    .unwrap_or_default();
+                if age >= ORPHAN_MIN_AGE {
+                    fs::remove_file(entry.path())?;
+                    removed += 1;
+                }
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// 引用中的哈希，不是引用或者哈希格式不对时返回 None
+pub fn reference_hash(value: &str) -> Option<&str> {
+    let hash = value
+        .strip_prefix(ENCRYPTED_REFERENCE_PREFIX)
+        .or_else(|| value.strip_prefix(REFERENCE_PREFIX))?;
+    // 哈希会拼进文件路径，只接受 sha256 的十六进制形式
+    (hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit())).then_some(hash)
+}
+
+// 按哈希的前两位分目录，避免一个目录下文件过多
+fn file_path(dir: &std::path::Path, hash: &str) -> PathBuf {
+    dir.join(&hash[..2]).join(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attachment_store() {
+        let dir = std::env::temp_dir().join(format!("aipp_attachments_{}", std::process::id()));
+        let store = AttachmentStore::new(Some(dir.clone()));
+        assert_eq!(store.externalize("small").unwrap(), "small");
+
+        let content = format!("data:image/png;base64,{}", "A".repeat(EXTERNAL_THRESHOLD));
+        let reference = store.externalize(&content).unwrap();
+        assert!(reference.starts_with(REFERENCE_PREFIX));
+        // 相同内容得到相同的引用，只保存一份
+        assert_eq!(store.externalize(&content).unwrap(), reference);
+        assert_eq!(store.resolve(reference.clone()).unwrap(), content);
+        assert_eq!(store.resolve("small".to_string()).unwrap(), "small");
+
+        let encrypted = format!("{}{}", ENCRYPTED_PREFIX, "B".repeat(EXTERNAL_THRESHOLD));
+        assert!(store
+            .externalize(&encrypted)
+            .unwrap()
+            .starts_with(ENCRYPTED_REFERENCE_PREFIX));
+
+        // 不启用时内容保持原样
+        assert_eq!(
+            AttachmentStore::default().externalize(&content).unwrap(),
+            content
+        );
+        assert_eq!(reference_hash("aipp:file:../../etc/passwd"), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
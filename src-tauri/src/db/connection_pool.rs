@@ -0,0 +1,146 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use rusqlite::Connection;
+
+// 每个数据库文件最多保留的空闲连接数，超出的连接用完后直接关闭
+const MAX_IDLE_CONNECTIONS: usize = 8;
+
+// 按数据库文件路径保存的空闲连接，连接上 prepare_cached 缓存的语句随连接一起复用
+static IDLE_CONNECTIONS: OnceLock<Mutex<HashMap<PathBuf, Vec<Connection>>>> = OnceLock::new();
+
+fn idle_connections() -> &'static Mutex<HashMap<PathBuf, Vec<Connection>>> {
+    IDLE_CONNECTIONS.get_or_init(Default::default)
+}
+
+/// 从连接池中取出的连接，drop 时放回池中，下次打开同一个数据库时复用
+pub struct PooledConnection {
+    conn: Option<Connection>,
+    path: PathBuf,
+}
+
+impl PooledConnection {
+    /// 优先取出池中的空闲连接，没有时打开新连接，新连接设置 statement_cache_capacity 个缓存语句
+    pub fn open(path: &Path, statement_cache_capacity: usize) -> rusqlite::Result<Self> {
+        let idle = idle_connections()
+            .lock()
+            .ok()
+            .and_then(|mut pool| pool.get_mut(path).and_then(|conns| conns.pop()));
+        let conn = match idle {
+            Some(conn) => conn,
+            None => {
+                let conn = Connection::open(path)?;
+                conn.set_prepared_statement_cache_capacity(statement_cache_capacity);
+                conn
+            }
+        };
+        Ok(PooledConnection {
+            conn: Some(conn),
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Deref for PooledConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().expect("连接已经放回连接池")
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.conn.as_mut().expect("连接已经放回连接池")
+    }
+}
+
+impl Borrow<Connection> for PooledConnection {
+    fn borrow(&self) -> &Connection {
+        self
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        let Some(conn) = self.conn.take() else {
+            return;
+        };
+        // 还在事务中的连接说明事务没有正常结束，直接关闭，不能让下一个使用者接着这个事务读写
+        if !conn.is_autocommit() {
+            return;
+        }
+        if let Ok(mut pool) = idle_connections().lock() {
+            let conns = pool.entry(self.path.clone()).or_default();
+            if conns.len() < MAX_IDLE_CONNECTIONS {
+                conns.push(conn);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_db_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "aipp_pool_{}_{}_{}.db",
+            name,
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or_default()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_connection_reused_after_drop() {
+        let path = temp_db_path("reuse");
+        {
+            let conn = PooledConnection::open(&path, 4).unwrap();
+            // 临时表只在创建它的连接上可见，可以用来判断拿到的是不是同一个连接
+            conn.execute_batch("CREATE TEMP TABLE marker (id INTEGER)")
+                .unwrap();
+        }
+        let conn = PooledConnection::open(&path, 4).unwrap();
+        let count: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM temp.sqlite_master WHERE name = 'marker'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(count, 1);
+        drop(conn);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_connection_in_transaction_not_reused() {
+        let path = temp_db_path("transaction");
+        {
+            let conn = PooledConnection::open(&path, 4).unwrap();
+            conn.execute_batch("CREATE TEMP TABLE marker (id INTEGER); BEGIN;")
+                .unwrap();
+        }
+        let conn = PooledConnection::open(&path, 4).unwrap();
+        assert!(conn.is_autocommit());
+        let count: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM temp.sqlite_master WHERE name = 'marker'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(count, 0);
+        drop(conn);
+        let _ = std::fs::remove_file(&path);
+    }
+}
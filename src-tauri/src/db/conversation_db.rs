@@ -1,12 +1,24 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 use chrono::prelude::*;
 use rusqlite::{Connection, OptionalExtension, Result};
 use serde::{Deserialize, Serialize};
+use tauri::Manager;
 
+use crate::api::llm::chat_message::Role;
 use crate::errors::AppError;
+use crate::knowledge::citation::Citation;
+use crate::state::private_conversation::PrivateConversationState;
+use crate::AppHandle;
 
-use super::get_db_path;
+use super::attachment_store::{reference_hash, AttachmentStore, EXTERNAL_THRESHOLD};
+use super::connection_pool::PooledConnection;
+use super::{get_attachment_dir, get_db_path};
+
+// 每个连接缓存的预编译语句数量，需要大于仓库中常用查询的数量
+const STATEMENT_CACHE_CAPACITY: usize = 32;
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
 pub enum AttachmentType {
@@ -40,12 +52,58 @@ impl TryFrom<i64> for AttachmentType {
         }
     }
 }
+// 新建对话的名称，生成标题之前一直是这个名称
+pub const DEFAULT_CONVERSATION_NAME: &str = "新对话";
+// 先起草再改进时草稿模型生成的中间结果，不出现在消息列表和发给模型的历史中，只能按 id 读取
+pub const DRAFT_MESSAGE_TYPE: &str = "draft";
+// 从其他对话引用过来的消息，来源记录在 message_quote 中，发给模型时作为带来源说明的引用块
+pub const QUOTE_MESSAGE_TYPE: &str = "quote";
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Conversation {
     pub id: i64,
     pub name: String,
     pub assistant_id: Option<i64>,
     pub created_time: DateTime<Utc>,
+    // 私密对话的消息和附件内容加密保存
+    pub is_private: bool,
+    // 一句话总结，显示在对话列表中，由补全标题的任务生成
+    pub summary: Option<String>,
+}
+
+/// 最近对话列表中的一项，用于快速切换对话
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecentConversation {
+    pub id: i64,
+    pub name: String,
+    pub assistant_id: Option<i64>,
+    pub is_private: bool,
+    pub summary: Option<String>,
+    // 最后一条消息的原始内容，私密对话中是密文，由调用方解密
+    pub last_message: Option<String>,
+    pub last_message_type: Option<String>,
+    // 最后一条消息的时间，没有消息时为对话的创建时间
+    pub last_active_time: DateTime<Utc>,
+    // 上次查看之后有新的回答
+    pub unread: bool,
+}
+
+/// 活跃度日历中的一天，按本地时间统计消息数量和 token 数量
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ActivityDay {
+    // YYYY-MM-DD
+    pub date: String,
+    pub message_count: i64,
+    pub token_count: i64,
+}
+
+/// 上次异常退出时没有生成完的回答，启动时标记为已中断
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct InterruptedMessage {
+    pub message_id: i64,
+    pub conversation_id: i64,
+    // 已经生成的部分内容，为空时只能重新生成
+    pub has_content: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -61,6 +119,30 @@ pub struct Message {
     pub start_time: Option<DateTime<Utc>>,
     pub finish_time: Option<DateTime<Utc>>,
     pub token_count: i32,
+    // 是否因为达到 max_tokens 被截断，可以通过 continue_generation 继续生成
+    pub is_truncated: bool,
+    // 文本附件超出长度预算时的截断信息，JSON 格式的 AttachmentTruncation 列表
+    pub attachment_truncation: Option<String>,
+    // 置顶的消息总是包含在上下文中，不会因为超出上下文长度被丢弃
+    pub pinned: bool,
+    // 知识库来源，JSON 格式的 Citation 列表。用户消息上是提问时检索到的全部来源，
+    // 回答上是实际引用到的来源
+    pub citations: Option<String>,
+    // 是否因为超过助手配置的最长生成时间被停止，已生成的内容会保留
+    pub timed_out: bool,
+    // 生成这条回答时的模型、参数、耗时和结束原因，JSON 格式的 GenerationMetadata
+    pub generation_metadata: Option<String>,
+    // 推理模型输出的思考过程，和回答分开保存
+    pub reasoning: Option<String>,
+    // 回答这条消息的助手，群聊中一个对话里可以有多个助手回答，提问和旧版本的消息为空
+    pub assistant_id: Option<i64>,
+}
+
+impl Message {
+    /// message_type 对应的角色，草稿、引用等不是角色的消息类型返回 None
+    pub fn role(&self) -> Option<Role> {
+        self.message_type.parse().ok()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -73,20 +155,132 @@ pub struct MessageDetail {
     pub llm_model_id: Option<i64>,
     pub created_time: DateTime<Utc>,
     pub token_count: i32,
+    pub is_truncated: bool,
+    pub attachment_truncation: Vec<AttachmentTruncation>,
+    pub pinned: bool,
+    pub citations: Vec<Citation>,
+    pub timed_out: bool,
+    pub generation_metadata: Option<GenerationMetadata>,
+    pub reasoning: Option<String>,
+    pub assistant_id: Option<i64>,
     pub attachment_list: Vec<MessageAttachment>,
     pub regenerate: Vec<MessageDetail>,
+    // 从其他对话引用的消息的来源，其他消息为空
+    pub quote: Option<MessageQuote>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MessageAttachment {
     pub id: i64,
-    pub message_id: i64,
+    // 读取对话中的附件时为引用它的消息，刚上传还没有发送的附件为 None
+    pub message_id: Option<i64>,
     pub attachment_type: AttachmentType,
     pub attachment_url: Option<String>,
     pub attachment_content: Option<String>,
     pub attachment_hash: Option<String>,
     pub use_vector: bool,
     pub token_count: Option<i32>,
+    // 模型不支持视觉时通过 OCR 从图片中识别出的文字，识别一次后缓存
+    pub ocr_text: Option<String>,
+    // 文本附件检测到的原始编码，例如 GBK、Shift_JIS，读取时已经转换成 UTF-8
+    pub encoding: Option<String>,
+}
+
+/// 回答的生成信息，用于在每条回答旁边展示模型、参数和耗时
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct GenerationMetadata {
+    // 提供商名称和类型
+    pub provider: Option<String>,
+    pub api_type: Option<String>,
+    pub model_code: Option<String>,
+    pub temperature: Option<f64>,
+    pub stream: bool,
+    // 从开始请求到收到第一段内容、到生成结束的耗时
+    pub first_token_ms: Option<u64>,
+    pub latency_ms: Option<u64>,
+    // 提供商返回的结束原因，超时和停滞时分别为 timeout、stalled
+    pub finish_reason: Option<String>,
+    // 停滞后自动重试的次数
+    pub retries: u32,
+    // 请求的模型不可用时换用了备用模型，这里记录原来请求的模型，model_code 为实际回答的模型
+    pub fallback_from: Option<String>,
+    // 快捷操作的快速模式，以及从按下快捷键到收到第一段内容的耗时
+    #[serde(default)]
+    pub fast_mode: bool,
+    pub shortcut_first_token_ms: Option<u64>,
+    // 先起草再改进时草稿消息的 id
+    #[serde(default)]
+    pub draft_message_id: Option<i64>,
+    // 提供商返回的 token 用量，用于统计对话的费用，提供商没有返回时为空
+    #[serde(default)]
+    pub prompt_tokens: Option<u32>,
+    #[serde(default)]
+    pub completion_tokens: Option<u32>,
+}
+
+impl GenerationMetadata {
+    /// 记录实际回答的模型，没有指定模型时使用的默认模型不算换用备用模型，
+    /// 连续换用多个备用模型时 fallback_from 保留最初请求的模型
+    pub fn record_served_model(&mut self, model_code: &str) {
+        let requested = self
+            .model_code
+            .take()
+            .filter(|code| !code.is_empty() && code != model_code);
+        if self.fallback_from.is_none() {
+            self.fallback_from = requested;
+        }
+        self.model_code = Some(model_code.to_string());
+    }
+}
+
+/// 文本附件注入提示词时的截断记录
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AttachmentTruncation {
+    pub attachment_id: i64,
+    pub name: String,
+    // 原始内容和实际注入内容的字符数
+    pub original_length: usize,
+    pub kept_length: usize,
+    // head: 保留开头部分，rag: 按与提问的相关度挑选片段
+    pub strategy: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PromptHistory {
+    pub id: i64,
+    pub assistant_id: Option<i64>,
+    pub prompt: String,
+    pub created_time: DateTime<Utc>,
+}
+
+/// 助手调用内置工具的记录，用于在对话中展示工具的输出和事后审计
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ToolInvocation {
+    pub id: i64,
+    pub conversation_id: Option<i64>,
+    pub message_id: Option<i64>,
+    pub tool_name: String,
+    // 调用参数的 JSON
+    pub arguments: String,
+    pub result: String,
+    // success: 成功, error: 失败或者被用户拒绝
+    pub status: String,
+    pub duration_ms: i64,
+    pub created_time: DateTime<Utc>,
+}
+
+/// 删除和编辑消息的操作记录，用于撤销和重做
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MessageOperation {
+    pub id: i64,
+    pub conversation_id: i64,
+    // delete: 删除消息, edit: 编辑消息内容
+    pub operation_type: String,
+    pub message_ids: Vec<i64>,
+    // 编辑前的内容，撤销编辑后保存撤销前的内容用于重做
+    pub content: Option<String>,
+    pub undone: bool,
+    pub created_time: DateTime<Utc>,
 }
 
 pub trait Repository<T> {
@@ -96,19 +290,23 @@ pub trait Repository<T> {
     fn delete(&self, id: i64) -> Result<()>;
 }
 
-pub struct ConversationRepository {
-    conn: Connection,
+pub struct ConversationRepository<C = PooledConnection> {
+    conn: C,
 }
 
-impl ConversationRepository {
-    pub fn new(conn: Connection) -> Self {
+impl<C: Borrow<Connection>> ConversationRepository<C> {
+    pub fn new(conn: C) -> Self {
         ConversationRepository { conn }
     }
 
+    fn conn(&self) -> &Connection {
+        self.conn.borrow()
+    }
+
     pub fn list(&self, page: u32, per_page: u32) -> Result<Vec<Conversation>> {
         let offset = (page - 1) * per_page;
-        let mut stmt = self.conn.prepare(
-            "SELECT id, name, assistant_id, created_time
+        let mut stmt = self.conn().prepare_cached(
+            "SELECT id, name, assistant_id, created_time, is_private, summary
              FROM conversation
              ORDER BY created_time DESC
              LIMIT ?1 OFFSET ?2",
@@ -119,62 +317,241 @@ impl ConversationRepository {
                 name: row.get(1)?,
                 assistant_id: row.get(2)?,
                 created_time: row.get(3)?,
+                is_private: row.get(4)?,
+                summary: row.get(5)?,
             })
         })?;
         rows.collect()
     }
 
+    pub fn list_private_ids(&self) -> Result<Vec<i64>> {
+        let mut stmt = self
+            .conn()
+            .prepare_cached("SELECT id FROM conversation WHERE is_private = 1")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    pub fn update_private(&self, id: i64, is_private: bool) -> Result<()> {
+        self.conn().execute(
+            "UPDATE conversation SET is_private = ?1 WHERE id = ?2",
+            (is_private, id),
+        )?;
+        Ok(())
+    }
+
     pub fn update_assistant_id(
         &self,
         origin_assistant_id: i64,
         assistant_id: Option<i64>,
     ) -> Result<()> {
-        self.conn.execute(
+        self.conn().execute(
             "UPDATE conversation SET assistant_id = ?1 WHERE assistant_id = ?2",
             (&assistant_id, &origin_assistant_id),
         )?;
         Ok(())
     }
 
+    /// 无痕助手创建对话时记录下来，之后只清理记录过的无痕对话，不影响切换到无痕之前的对话
+    pub fn set_incognito(&self, id: i64) -> Result<()> {
+        self.conn().execute(
+            "UPDATE conversation SET is_incognito = 1 WHERE id = ?1",
+            [id],
+        )?;
+        Ok(())
+    }
+
+    pub fn list_incognito_ids(&self) -> Result<Vec<i64>> {
+        let mut stmt = self
+            .conn()
+            .prepare_cached("SELECT id FROM conversation WHERE is_incognito = 1")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    /// 助手的对话中最后一条消息早于 before 的对话，已删除的消息不算
+    pub fn list_ids_inactive_before(
+        &self,
+        assistant_id: i64,
+        before: DateTime<Utc>,
+    ) -> Result<Vec<i64>> {
+        let mut stmt = self.conn().prepare_cached(
+            "SELECT c.id FROM conversation c
+             WHERE c.assistant_id = ?1
+               AND COALESCE(
+                   (SELECT MAX(m.created_time) FROM message m
+                    WHERE m.conversation_id = c.id AND m.deleted_time IS NULL),
+                   c.created_time
+               ) < ?2",
+        )?;
+        let rows = stmt.query_map((assistant_id, before), |row| row.get(0))?;
+        rows.collect()
+    }
+
+    /// 删除对话以及对话中的消息和附件
+    pub fn delete_with_messages(&self, id: i64) -> Result<()> {
+        // 附件可能被其他对话的消息或者助手引用，去掉关联后只删除没有其他引用的附件
+        let attachment_ids = linked_attachment_ids(
+            self.conn(),
+            "SELECT id FROM message WHERE conversation_id = ?1",
+            id,
+        )?;
+        self.conn().execute(
+            "DELETE FROM message_attachment_link WHERE message_id IN (SELECT id FROM message WHERE conversation_id = ?1)",
+            [id],
+        )?;
+        delete_unreferenced_attachments(self.conn(), &attachment_ids)?;
+        self.conn().execute(
+            "DELETE FROM tool_invocation WHERE conversation_id = ?1",
+            [id],
+        )?;
+        self.conn().execute(
+            "DELETE FROM conversation_draft WHERE conversation_id = ?1",
+            [id],
+        )?;
+        self.conn().execute(
+            "DELETE FROM conversation_style WHERE conversation_id = ?1",
+            [id],
+        )?;
+        self.conn().execute(
+            "DELETE FROM message_translation WHERE conversation_id = ?1",
+            [id],
+        )?;
+        self.conn()
+            .execute("DELETE FROM message_quote WHERE conversation_id = ?1", [id])?;
+        self.conn().execute(
+            "DELETE FROM message_operation WHERE conversation_id = ?1",
+            [id],
+        )?;
+        self.conn()
+            .execute("DELETE FROM message WHERE conversation_id = ?1", [id])?;
+        self.conn()
+            .execute("DELETE FROM conversation WHERE id = ?1", [id])?;
+        Ok(())
+    }
+
+    /// 按最后活动时间倒序列出最近的对话，最后一条消息和未读状态在同一条查询中取出
+    pub fn list_recent(&self, limit: u32) -> Result<Vec<RecentConversation>> {
+        let mut stmt = self.conn().prepare_cached(
+            "SELECT c.id, c.name, c.assistant_id, c.is_private, c.summary, m.content, m.message_type,
+                    COALESCE(m.created_time, c.created_time) AS last_active_time,
+                    EXISTS (
+                        SELECT 1 FROM message r
+                        WHERE r.conversation_id = c.id
+                          AND r.deleted_time IS NULL
+                          AND r.message_type NOT IN ('user', 'system', 'draft', 'quote')
+                          AND r.created_time > COALESCE(c.last_read_time, c.created_time)
+                    ) AS unread
+             FROM conversation c
+             LEFT JOIN message m ON m.id = (
+                 SELECT id FROM message
+                 WHERE conversation_id = c.id AND deleted_time IS NULL AND message_type != 'draft'
+                 ORDER BY created_time DESC, id DESC
+                 LIMIT 1
+             )
+             ORDER BY last_active_time DESC, c.id DESC
+             LIMIT ?1",
+        )?;
+        let rows = stmt.query_map([limit], |row| {
+            Ok(RecentConversation {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                assistant_id: row.get(2)?,
+                is_private: row.get(3)?,
+                summary: row.get(4)?,
+                last_message: row.get(5)?,
+                last_message_type: row.get(6)?,
+                last_active_time: row.get(7)?,
+                unread: row.get(8)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// 记录对话的查看时间，之后生成的回答才算未读
+    pub fn update_last_read_time(&self, id: i64) -> Result<()> {
+        self.conn().execute(
+            "UPDATE conversation SET last_read_time = ?1 WHERE id = ?2",
+            (Utc::now(), id),
+        )?;
+        Ok(())
+    }
+
     pub fn update_name(&self, conversation: &Conversation) -> Result<()> {
-        self.conn.execute(
+        self.conn().execute(
             "UPDATE conversation SET name = ?1 WHERE id = ?2",
             (&conversation.name, &conversation.id),
         )?;
         Ok(())
     }
+
+    /// 名称为空或者还是默认名称、并且有提问的对话，按创建时间倒序，私密对话不会发给模型总结，不包含在内
+    pub fn list_untitled_ids(&self, default_name: &str) -> Result<Vec<i64>> {
+        let mut stmt = self.conn().prepare_cached(
+            "SELECT c.id FROM conversation c
+             WHERE (TRIM(c.name) = '' OR c.name = ?1)
+               AND c.is_private = 0
+               AND EXISTS (
+                   SELECT 1 FROM message m
+                   WHERE m.conversation_id = c.id
+                     AND m.message_type = 'user'
+                     AND m.deleted_time IS NULL
+               )
+             ORDER BY c.created_time DESC, c.id DESC",
+        )?;
+        let rows = stmt.query_map([default_name], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    pub fn update_name_and_summary(
+        &self,
+        id: i64,
+        name: &str,
+        summary: Option<&str>,
+    ) -> Result<()> {
+        self.conn().execute(
+            "UPDATE conversation SET name = ?1, summary = ?2 WHERE id = ?3",
+            (name, summary, id),
+        )?;
+        Ok(())
+    }
 }
 
-impl Repository<Conversation> for ConversationRepository {
+impl<C: Borrow<Connection>> Repository<Conversation> for ConversationRepository<C> {
     fn create(&self, conversation: &Conversation) -> Result<Conversation> {
-        self.conn.execute(
-            "INSERT INTO conversation (name, assistant_id, created_time) VALUES (?1, ?2, ?3)",
+        self.conn().execute(
+            "INSERT INTO conversation (name, assistant_id, created_time, is_private, summary) VALUES (?1, ?2, ?3, ?4, ?5)",
             (
                 &conversation.name,
                 &conversation.assistant_id,
                 &conversation.created_time,
+                &conversation.is_private,
+                &conversation.summary,
             ),
         )?;
-        let id = self.conn.last_insert_rowid();
+        let id = self.conn().last_insert_rowid();
         Ok(Conversation {
             id,
             name: conversation.name.clone(),
             assistant_id: conversation.assistant_id,
             created_time: conversation.created_time,
+            is_private: conversation.is_private,
+            summary: conversation.summary.clone(),
         })
     }
 
     fn read(&self, id: i64) -> Result<Option<Conversation>> {
-        self.conn
-            .query_row(
-                "SELECT id, name, assistant_id, created_time FROM conversation WHERE id = ?",
-                &[&id],
+        self.conn()
+            .prepare_cached("SELECT id, name, assistant_id, created_time, is_private, summary FROM conversation WHERE id = ?")?
+            .query_row(&[&id],
                 |row| {
                     Ok(Conversation {
                         id: row.get(0)?,
                         name: row.get(1)?,
                         assistant_id: row.get(2)?,
                         created_time: row.get(3)?,
+                        is_private: row.get(4)?,
+                        summary: row.get(5)?,
                     })
                 },
             )
@@ -182,7 +559,7 @@ impl Repository<Conversation> for ConversationRepository {
     }
 
     fn update(&self, conversation: &Conversation) -> Result<()> {
-        self.conn.execute(
+        self.conn().execute(
             "UPDATE conversation SET name = ?1, assistant_id = ?2 WHERE id = ?3",
             (
                 &conversation.name,
@@ -194,31 +571,70 @@ impl Repository<Conversation> for ConversationRepository {
     }
 
     fn delete(&self, id: i64) -> Result<()> {
-        self.conn
+        self.conn()
             .execute("DELETE FROM conversation WHERE id = ?", &[&id])?;
         Ok(())
     }
 }
 
-pub struct MessageRepository {
-    conn: Connection,
+// 加解密失败时转换成数据库错误，错误信息会原样返回给前端
+fn crypto_error(e: String) -> rusqlite::Error {
+    rusqlite::Error::ToSqlConversionFailure(e.into())
+}
+
+fn store_error(e: std::io::Error) -> rusqlite::Error {
+    rusqlite::Error::ToSqlConversionFailure(Box::new(e))
+}
+
+pub struct MessageRepository<C = PooledConnection> {
+    conn: C,
+    private: PrivateConversationState,
+    attachment_store: AttachmentStore,
 }
 
-impl MessageRepository {
-    pub fn new(conn: Connection) -> Self {
-        MessageRepository { conn }
+impl<C: Borrow<Connection>> MessageRepository<C> {
+    pub fn new(conn: C, private: PrivateConversationState) -> Self {
+        MessageRepository {
+            conn,
+            private,
+            attachment_store: AttachmentStore::default(),
+        }
+    }
+
+    fn conn(&self) -> &Connection {
+        self.conn.borrow()
+    }
+
+    /// 读取消息的附件时从 store 中读出保存在文件中的内容
+    pub fn with_attachment_store(mut self, attachment_store: AttachmentStore) -> Self {
+        self.attachment_store = attachment_store;
+        self
+    }
+
+    fn decrypt_message(&self, mut message: Message) -> Result<Message> {
+        message.content = self
+            .private
+            .decrypt(message.conversation_id, &message.content)
+            .map_err(crypto_error)?;
+        message.reasoning = message
+            .reasoning
+            .map(|reasoning| self.private.decrypt(message.conversation_id, &reasoning))
+            .transpose()
+            .map_err(crypto_error)?;
+        Ok(message)
     }
 
     pub fn list_by_conversation_id(
         &self,
         conversation_id: i64,
     ) -> Result<Vec<(Message, Option<MessageAttachment>)>> {
-        let mut stmt = self.conn.prepare("SELECT message.id, message.parent_id, message.conversation_id, message.message_type, message.content, message.llm_model_id, message.llm_model_name, message.created_time, message.start_time, message.finish_time, message.token_count, ma.attachment_type, ma.attachment_url, ma.attachment_content, ma.use_vector as attachment_use_vector, ma.token_count as attachment_token_count
+        let mut stmt = self.conn().prepare_cached("SELECT message.id, message.parent_id, message.conversation_id, message.message_type, message.content, message.llm_model_id, message.llm_model_name, message.created_time, message.start_time, message.finish_time, message.token_count, message.is_truncated, message.attachment_truncation, message.pinned, message.citations, message.timed_out, message.generation_metadata, message.reasoning, ma.attachment_type, ma.attachment_url, ma.attachment_content, ma.use_vector as attachment_use_vector, ma.token_count as attachment_token_count, ma.ocr_text as attachment_ocr_text, ma.id as attachment_id, message.assistant_id, ma.encoding as attachment_encoding
                                           FROM message
-                                          LEFT JOIN message_attachment ma on message.id = ma.message_id
-                                          WHERE conversation_id = ?1")?;
+                                          LEFT JOIN message_attachment_link link on message.id = link.message_id
+                                          LEFT JOIN message_attachment ma on ma.id = link.attachment_id
+                                          WHERE conversation_id = ?1 AND message.deleted_time IS NULL AND message.message_type != 'draft'")?;
         let rows = stmt.query_map(&[&conversation_id], |row| {
-            let attachment_type_int: Option<i64> = row.get(11).ok();
+            let attachment_type_int: Option<i64> = row.get(18).ok();
             let attachment_type = attachment_type_int
                 .map(AttachmentType::try_from)
                 .transpose()?;
@@ -234,61 +650,319 @@ impl MessageRepository {
                 start_time: row.get(8)?,
                 finish_time: row.get(9)?,
                 token_count: row.get(10)?,
+                is_truncated: row.get(11)?,
+                attachment_truncation: row.get(12)?,
+                pinned: row.get(13)?,
+                citations: row.get(14)?,
+                timed_out: row.get(15)?,
+                generation_metadata: row.get(16)?,
+                reasoning: row.get(17)?,
+                assistant_id: row.get(25)?,
             };
             let attachment = if attachment_type.is_some() {
                 Some(MessageAttachment {
-                    id: 0,
+                    id: row.get(24)?,
                     message_id: row.get(0)?,
                     attachment_type: attachment_type.unwrap(),
-                    attachment_url: row.get(12)?,
-                    attachment_content: row.get(13)?,
+                    attachment_url: row.get(19)?,
+                    attachment_content: row.get(20)?,
                     attachment_hash: None,
-                    use_vector: row.get(14)?,
-                    token_count: row.get(15)?,
+                    use_vector: row.get(21)?,
+                    token_count: row.get(22)?,
+                    ocr_text: row.get(23)?,
+                    encoding: row.get(26)?,
                 })
             } else {
                 None
             };
             Ok((message, attachment))
         })?;
-        rows.collect()
+        rows.map(|row| {
+            let (message, attachment) = row?;
+            let attachment = attachment
+                .map(|attachment| {
+                    decrypt_attachment(
+                        &self.private,
+                        &self.attachment_store,
+                        conversation_id,
+                        attachment,
+                    )
+                })
+                .transpose()?;
+            Ok((self.decrypt_message(message)?, attachment))
+        })
+        .collect()
     }
 
     pub fn update_start_time(&self, id: i64) -> Result<()> {
-        self.conn.execute(
+        self.conn().execute(
             "UPDATE message SET start_time = CURRENT_TIMESTAMP WHERE id = ?1",
             [&id],
         )?;
         Ok(())
     }
 
+    pub fn update_attachment_truncation(
+        &self,
+        id: i64,
+        attachment_truncation: Option<&str>,
+    ) -> Result<()> {
+        self.conn().execute(
+            "UPDATE message SET attachment_truncation = ?1 WHERE id = ?2",
+            (attachment_truncation, id),
+        )?;
+        Ok(())
+    }
+
+    pub fn update_citations(&self, id: i64, citations: Option<&str>) -> Result<()> {
+        self.conn().execute(
+            "UPDATE message SET citations = ?1 WHERE id = ?2",
+            (citations, id),
+        )?;
+        Ok(())
+    }
+
+    pub fn update_generation_metadata(&self, id: i64, metadata: Option<&str>) -> Result<()> {
+        self.conn().execute(
+            "UPDATE message SET generation_metadata = ?1 WHERE id = ?2",
+            (metadata, id),
+        )?;
+        Ok(())
+    }
+
+    pub fn update_pinned(&self, id: i64, pinned: bool) -> Result<()> {
+        self.conn()
+            .execute("UPDATE message SET pinned = ?1 WHERE id = ?2", (pinned, id))?;
+        Ok(())
+    }
+
+    pub fn update_timed_out(&self, id: i64) -> Result<()> {
+        self.conn()
+            .execute("UPDATE message SET timed_out = 1 WHERE id = ?1", [&id])?;
+        Ok(())
+    }
+
     pub fn update_finish_time(&self, id: i64) -> Result<()> {
-        self.conn.execute(
+        self.conn().execute(
             "UPDATE message SET finish_time = CURRENT_TIMESTAMP WHERE id = ?1",
             [&id],
         )?;
         Ok(())
     }
+
+    /// 软删除消息和它重新生成的版本，返回这次删除的消息 id；删除的消息保留一段时间，可以撤销
+    pub fn soft_delete(&self, id: i64) -> Result<Vec<i64>> {
+        let mut stmt = self.conn().prepare_cached(
+            "SELECT id FROM message WHERE (id = ?1 OR parent_id = ?1) AND deleted_time IS NULL ORDER BY id",
+        )?;
+        let ids = stmt
+            .query_map([id], |row| row.get(0))?
+            .collect::<Result<Vec<i64>>>()?;
+        self.update_deleted(&ids, true)?;
+        Ok(ids)
+    }
+
+    /// deleted 为 false 时恢复软删除的消息
+    pub fn update_deleted(&self, ids: &[i64], deleted: bool) -> Result<()> {
+        let deleted_time = deleted.then(Utc::now);
+        for id in ids {
+            self.conn().execute(
+                "UPDATE message SET deleted_time = ?1 WHERE id = ?2",
+                (&deleted_time, id),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// 对话中软删除的消息，对话设为私密或者取消私密时也需要重新保存
+    pub fn list_deleted_by_conversation_id(&self, conversation_id: i64) -> Result<Vec<Message>> {
+        let mut stmt = self.conn().prepare_cached(
+            "SELECT id FROM message WHERE conversation_id = ?1 AND deleted_time IS NOT NULL",
+        )?;
+        let ids = stmt
+            .query_map([conversation_id], |row| row.get(0))?
+            .collect::<Result<Vec<i64>>>()?;
+        ids.into_iter()
+            .filter_map(|id| self.read(id).transpose())
+            .collect()
+    }
+
+    /// 消息所在的对话，不读取也不解密消息内容，私密对话锁定时同样可以查询；消息或者对话已经删除时返回 None
+    pub fn conversation_id_of(&self, id: i64) -> Result<Option<i64>> {
+        self.conn()
+            .query_row(
+                "SELECT m.conversation_id FROM message m
+                 JOIN conversation c ON c.id = m.conversation_id
+                 WHERE m.id = ?1",
+                [id],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    /// 对话中没有删除的消息 id，按时间排序，不包含草稿；导出很长的对话时逐条读取消息，避免一次加载全部内容
+    pub fn list_ids_by_conversation_id(&self, conversation_id: i64) -> Result<Vec<i64>> {
+        let mut stmt = self.conn().prepare_cached(
+            "SELECT id FROM message
+             WHERE conversation_id = ?1 AND deleted_time IS NULL AND message_type != 'draft'
+             ORDER BY created_time, id",
+        )?;
+        let ids = stmt
+            .query_map([conversation_id], |row| row.get(0))?
+            .collect::<Result<Vec<i64>>>()?;
+        Ok(ids)
+    }
+
+    /// 对话中每条消息的类型和生成信息，按时间顺序，用于统计对话的 token 用量和费用；不包含删除的消息和草稿
+    pub fn list_generation_metadata_by_conversation_id(
+        &self,
+        conversation_id: i64,
+    ) -> Result<Vec<(i64, String, Option<String>)>> {
+        let mut stmt = self.conn().prepare_cached(
+            "SELECT id, message_type, generation_metadata FROM message
+             WHERE conversation_id = ?1 AND deleted_time IS NULL AND message_type != 'draft'
+             ORDER BY created_time, id",
+        )?;
+        let rows = stmt
+            .query_map([conversation_id], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(rows)
+    }
+
+    /// year 年每天的消息数量和 token 数量，按本地时间的日期分组，没有消息的日期不返回；不包含删除的消息和草稿
+    pub fn activity_calendar(&self, year: i32) -> Result<Vec<ActivityDay>> {
+        // 先按前后多一天的范围用索引筛选，再按本地时间的年份精确过滤
+        let start = format!("{:04}-12-31", year - 1);
+        let end = format!("{:04}-01-02", year + 1);
+        let mut stmt = self.conn().prepare_cached(
+            "SELECT date(created_time, 'localtime') AS day, COUNT(*), COALESCE(SUM(token_count), 0)
+             FROM message
+             WHERE created_time >= ?1 AND created_time < ?2
+               AND strftime('%Y', created_time, 'localtime') = ?3
+               AND deleted_time IS NULL AND message_type != 'draft'
+             GROUP BY day
+             ORDER BY day",
+        )?;
+        let days = stmt
+            .query_map((start, end, format!("{:04}", year)), |row| {
+                Ok(ActivityDay {
+                    date: row.get(0)?,
+                    message_count: row.get(1)?,
+                    token_count: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<ActivityDay>>>()?;
+        Ok(days)
+    }
+
+    /// 把没有结束时间的回答标记为已结束，返回这些消息。只在启动时还没有开始生成之前调用，
+    /// 这时没有结束时间也没有生成信息的回答都是上次异常退出时正在生成的
+    pub fn mark_incomplete_interrupted(
+        &self,
+        generation_metadata: &str,
+    ) -> Result<Vec<InterruptedMessage>> {
+        let mut stmt = self.conn().prepare_cached(
+            "SELECT id, conversation_id, content FROM message
+             WHERE finish_time IS NULL AND generation_metadata IS NULL AND deleted_time IS NULL
+               AND message_type NOT IN ('user', 'system', 'draft', 'quote')
+             ORDER BY id",
+        )?;
+        let messages = stmt
+            .query_map([], |row| {
+                Ok(InterruptedMessage {
+                    message_id: row.get(0)?,
+                    conversation_id: row.get(1)?,
+                    has_content: !row.get::<_, String>(2)?.trim().is_empty(),
+                })
+            })?
+            .collect::<Result<Vec<InterruptedMessage>>>()?;
+        for message in &messages {
+            self.conn().execute(
+                "UPDATE message SET finish_time = CURRENT_TIMESTAMP, generation_metadata = ?1 WHERE id = ?2",
+                (generation_metadata, message.message_id),
+            )?;
+        }
+        Ok(messages)
+    }
+
+    /// 之前的版本生成完成时不记录结束时间，升级时把这些回答的结束时间补为创建时间，
+    /// 避免启动时被 mark_incomplete_interrupted 当成上次异常退出时中断的回答
+    pub fn backfill_finish_time(&self) -> Result<usize> {
+        self.conn().execute(
+            "UPDATE message SET finish_time = created_time
+             WHERE finish_time IS NULL AND generation_metadata IS NULL
+               AND message_type NOT IN ('user', 'system', 'draft', 'quote')",
+            [],
+        )
+    }
+
+    /// 彻底删除 before 之前软删除的消息，以及只被这些消息引用的附件，返回删除的消息数量
+    pub fn purge_deleted_before(&self, before: DateTime<Utc>) -> Result<usize> {
+        let attachment_ids = linked_attachment_ids(
+            self.conn(),
+            "SELECT id FROM message WHERE deleted_time < ?1",
+            before,
+        )?;
+        self.conn().execute(
+            "DELETE FROM message_attachment_link WHERE message_id IN (SELECT id FROM message WHERE deleted_time < ?1)",
+            [before],
+        )?;
+        delete_unreferenced_attachments(self.conn(), &attachment_ids)?;
+        self.conn().execute(
+            "DELETE FROM tool_invocation WHERE message_id IN (SELECT id FROM message WHERE deleted_time < ?1)",
+            [before],
+        )?;
+        self.conn().execute(
+            "DELETE FROM message_translation WHERE message_id IN (SELECT id FROM message WHERE deleted_time < ?1)",
+            [before],
+        )?;
+        self.conn().execute(
+            "DELETE FROM message_quote WHERE message_id IN (SELECT id FROM message WHERE deleted_time < ?1)",
+            [before],
+        )?;
+        self.conn()
+            .execute("DELETE FROM message WHERE deleted_time < ?1", [before])
+    }
 }
 
-impl Repository<Message> for MessageRepository {
+impl<C: Borrow<Connection>> Repository<Message> for MessageRepository<C> {
     fn create(&self, message: &Message) -> Result<Message> {
-        self.conn.execute(
-            "INSERT INTO message (parent_id, conversation_id, message_type, content, llm_model_id, llm_model_name, created_time, start_time, finish_time, token_count) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
-            (
+        let content = self
+            .private
+            .encrypt(message.conversation_id, &message.content)
+            .map_err(crypto_error)?;
+        let reasoning = message
+            .reasoning
+            .as_deref()
+            .map(|reasoning| self.private.encrypt(message.conversation_id, reasoning))
+            .transpose()
+            .map_err(crypto_error)?;
+        self.conn().execute(
+            "INSERT INTO message (parent_id, conversation_id, message_type, content, llm_model_id, llm_model_name, created_time, start_time, finish_time, token_count, is_truncated, attachment_truncation, pinned, citations, timed_out, generation_metadata, reasoning, assistant_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)",
+            rusqlite::params![
                 &message.parent_id,
                 &message.conversation_id,
                 &message.message_type,
-                &message.content,
+                &content,
                 &message.llm_model_id,
                 &message.llm_model_name,
                 &message.created_time,
                 &message.start_time,
                 &message.finish_time,
                 &message.token_count,
-            ),
+                &message.is_truncated,
+                &message.attachment_truncation,
+                &message.pinned,
+                &message.citations,
+                &message.timed_out,
+                &message.generation_metadata,
+                &reasoning,
+                &message.assistant_id,
+            ],
         )?;
-        let id = self.conn.last_insert_rowid();
+        let id = self.conn().last_insert_rowid();
         Ok(Message {
             id,
             parent_id: message.parent_id,
@@ -301,12 +975,21 @@ impl Repository<Message> for MessageRepository {
             start_time: message.start_time,
             finish_time: message.finish_time,
             token_count: message.token_count,
+            is_truncated: message.is_truncated,
+            attachment_truncation: message.attachment_truncation.clone(),
+            pinned: message.pinned,
+            citations: message.citations.clone(),
+            timed_out: message.timed_out,
+            generation_metadata: message.generation_metadata.clone(),
+            reasoning: message.reasoning.clone(),
+            assistant_id: message.assistant_id,
         })
     }
 
     fn read(&self, id: i64) -> Result<Option<Message>> {
-        self.conn
-            .query_row("SELECT id, parent_id, conversation_id, message_type, content, llm_model_id, llm_model_name, created_time, start_time, finish_time, token_count FROM message WHERE id = ?", &[&id], |row| {
+        self.conn()
+            .prepare_cached("SELECT id, parent_id, conversation_id, message_type, content, llm_model_id, llm_model_name, created_time, start_time, finish_time, token_count, is_truncated, attachment_truncation, pinned, citations, timed_out, generation_metadata, reasoning, assistant_id FROM message WHERE id = ?")?
+            .query_row(&[&id], |row| {
                 Ok(Message {
                     id: row.get(0)?,
                     parent_id: row.get(1)?,
@@ -319,21 +1002,48 @@ impl Repository<Message> for MessageRepository {
                     start_time: row.get(8)?,
                     finish_time: row.get(9)?,
                     token_count: row.get(10)?,
+                    is_truncated: row.get(11)?,
+                    attachment_truncation: row.get(12)?,
+                    pinned: row.get(13)?,
+                    citations: row.get(14)?,
+                    timed_out: row.get(15)?,
+                    generation_metadata: row.get(16)?,
+                    reasoning: row.get(17)?,
+                    assistant_id: row.get(18)?,
                 })
             })
-            .optional()
+            .optional()?
+            .map(|message| self.decrypt_message(message))
+            .transpose()
     }
 
     fn update(&self, message: &Message) -> Result<()> {
-        self.conn.execute(
-            "UPDATE message SET conversation_id = ?1, message_type = ?2, content = ?3, llm_model_id = ?4, llm_model_name = ?5, token_count = ?6 WHERE id = ?7",
+        let content = self
+            .private
+            .encrypt(message.conversation_id, &message.content)
+            .map_err(crypto_error)?;
+        let reasoning = message
+            .reasoning
+            .as_deref()
+            .map(|reasoning| self.private.encrypt(message.conversation_id, reasoning))
+            .transpose()
+            .map_err(crypto_error)?;
+        self.conn().execute(
+            "UPDATE message SET conversation_id = ?1, message_type = ?2, content = ?3, llm_model_id = ?4, llm_model_name = ?5, token_count = ?6, is_truncated = ?7, attachment_truncation = ?8, pinned = ?9, citations = ?10, timed_out = ?11, generation_metadata = ?12, reasoning = ?13 WHERE id = ?14",
             (
                 &message.conversation_id,
                 &message.message_type,
-                &message.content,
+                &content,
                 &message.llm_model_id,
                 &message.llm_model_name,
                 &message.token_count,
+                &message.is_truncated,
+                &message.attachment_truncation,
+                &message.pinned,
+                &message.citations,
+                &message.timed_out,
+                &message.generation_metadata,
+                &reasoning,
                 &message.id,
             ),
         )?;
@@ -341,168 +1051,1349 @@ impl Repository<Message> for MessageRepository {
     }
 
     fn delete(&self, id: i64) -> Result<()> {
-        self.conn
+        let attachment_ids =
+            linked_attachment_ids(self.conn(), "SELECT id FROM message WHERE id = ?1", id)?;
+        self.conn().execute(
+            "DELETE FROM message_attachment_link WHERE message_id = ?",
+            [id],
+        )?;
+        delete_unreferenced_attachments(self.conn(), &attachment_ids)?;
+        self.conn()
+            .execute("DELETE FROM message_translation WHERE message_id = ?", [id])?;
+        self.conn()
+            .execute("DELETE FROM message_quote WHERE message_id = ?", [id])?;
+        self.conn()
             .execute("DELETE FROM message WHERE id = ?", &[&id])?;
         Ok(())
     }
 }
 
-pub struct MessageAttachmentRepository {
-    conn: Connection,
+// 被 message_query 选出的消息关联的附件，message_query 只有一个参数 ?1
+fn linked_attachment_ids(
+    conn: &Connection,
+    message_query: &str,
+    param: impl rusqlite::ToSql,
+) -> Result<Vec<i64>> {
+    conn.prepare_cached(&format!(
+        "SELECT DISTINCT attachment_id FROM message_attachment_link WHERE message_id IN ({})",
+        message_query
+    ))?
+    .query_map([param], |row| row.get(0))?
+    .collect()
 }
 
-impl MessageAttachmentRepository {
-    pub fn new(conn: Connection) -> Self {
-        MessageAttachmentRepository { conn }
+// 附件的引用包括关联的消息和助手的常驻上下文，删除消息、对话和附件时都经过这里，
+// 只删除最后一个引用已经去掉的附件，返回删除的附件数量
+fn delete_unreferenced_attachments(conn: &Connection, attachment_ids: &[i64]) -> Result<usize> {
+    let mut stmt = conn.prepare_cached(
+        "DELETE FROM message_attachment WHERE id = ?1
+           AND NOT EXISTS (SELECT 1 FROM message_attachment_link WHERE attachment_id = ?1)
+           AND NOT EXISTS (SELECT 1 FROM assistant_attachment_ref WHERE attachment_id = ?1)",
+    )?;
+    let mut deleted = 0;
+    for attachment_id in attachment_ids {
+        deleted += stmt.execute([attachment_id])?;
     }
+    Ok(deleted)
+}
 
-    pub fn list_by_id(&self, id_list: &Vec<i64>) -> Result<Vec<MessageAttachment>> {
-        let id_list_str: Vec<String> = id_list.iter().map(|id| id.to_string()).collect();
-        let id_list_str = id_list_str.join(",");
-        let query = format!(
-            "SELECT * FROM message_attachment WHERE id IN ({})",
-            id_list_str
-        );
-        let mut stmt = self.conn.prepare(&query)?;
-        let rows = stmt.query_map([], |row| {
-            let attachment_type_int: i64 = row.get(2)?;
-            let attachment_type = AttachmentType::try_from(attachment_type_int)?;
-            Ok(MessageAttachment {
-                id: row.get(0)?,
-                message_id: row.get(1)?,
-                attachment_type,
-                attachment_url: row.get(3)?,
-                attachment_content: row.get(4)?,
-                attachment_hash: None,
-                use_vector: row.get(5)?,
-                token_count: row.get(6)?,
-            })
-        })?;
-        rows.collect()
+// 内容保存在文件中的大附件先读出文件内容
+fn resolve_attachment(
+    store: &AttachmentStore,
+    mut attachment: MessageAttachment,
+) -> Result<MessageAttachment> {
+    attachment.attachment_content = attachment
+        .attachment_content
+        .map(|content| store.resolve(content))
+        .transpose()
+        .map_err(store_error)?;
+    Ok(attachment)
+}
+
+fn decrypt_attachment(
+    private: &PrivateConversationState,
+    store: &AttachmentStore,
+    conversation_id: i64,
+    attachment: MessageAttachment,
+) -> Result<MessageAttachment> {
+    let mut attachment = resolve_attachment(store, attachment)?;
+    let decrypt = |text: Option<String>| {
+        text.map(|text| private.decrypt(conversation_id, &text))
+            .transpose()
+            .map_err(crypto_error)
+    };
+    attachment.attachment_content = decrypt(attachment.attachment_content)?;
+    attachment.ocr_text = decrypt(attachment.ocr_text)?;
+    Ok(attachment)
+}
+
+/// 附件和消息是多对多的关系，通过 message_attachment_link 关联；相同内容的附件按哈希复用，
+/// 被多条消息引用时只保存一份。助手的常驻上下文引用记录在 assistant_attachment_ref 中，
+/// 消息和助手的引用都去掉后才删除附件本身。
+/// message_attachment 中的 message_id 字段已经不再使用，新附件写入 NULL
+pub struct MessageAttachmentRepository<C = PooledConnection> {
+    conn: C,
+    private: PrivateConversationState,
+    store: AttachmentStore,
+}
+
+fn attachment_from_row(
+    row: &rusqlite::Row,
+    message_id: Option<i64>,
+) -> rusqlite::Result<MessageAttachment> {
+    let attachment_type_int: i64 = row.get("attachment_type")?;
+    Ok(MessageAttachment {
+        id: row.get("id")?,
+        message_id,
+        attachment_type: AttachmentType::try_from(attachment_type_int)?,
+        attachment_url: row.get("attachment_url")?,
+        attachment_content: row.get("attachment_content")?,
+        attachment_hash: None,
+        use_vector: row.get("use_vector")?,
+        token_count: row.get("token_count")?,
+        ocr_text: row.get("ocr_text")?,
+        encoding: row.get("encoding")?,
+    })
+}
+
+impl<C: Borrow<Connection>> MessageAttachmentRepository<C> {
+    pub fn new(conn: C, private: PrivateConversationState) -> Self {
+        MessageAttachmentRepository {
+            conn,
+            private,
+            store: AttachmentStore::default(),
+        }
     }
 
-    pub fn read_by_attachment_hash(
-        &self,
-        attachment_hash: &str,
-    ) -> Result<Option<MessageAttachment>> {
-        self.conn
-            .query_row("SELECT id, message_id, attachment_type, attachment_url, attachment_content, use_vector, token_count FROM message_attachment WHERE attachment_hash = ?", &[&attachment_hash], |row| {
-                let attachment_type_int: i64 = row.get(2)?;
-                let attachment_type = AttachmentType::try_from(attachment_type_int)?;
-                Ok(MessageAttachment {
-                    id: row.get(0)?,
-                    message_id: row.get(1)?,
-                    attachment_type,
-                    attachment_url: row.get(3)?,
-                    attachment_content: row.get(4)?,
-                    attachment_hash: None,
-                    use_vector: row.get(5)?,
-                    token_count: row.get(6)?,
-                })
-            })
-            .optional()
+    fn conn(&self) -> &Connection {
+        self.conn.borrow()
     }
-}
 
-impl Repository<MessageAttachment> for MessageAttachmentRepository {
-    fn create(&self, attachment: &MessageAttachment) -> Result<MessageAttachment> {
-        self.conn.execute(
-            "INSERT INTO message_attachment (message_id, attachment_type, attachment_url, attachment_content, attachment_hash, use_vector, token_count) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            (&attachment.message_id, &(attachment.attachment_type as i64), &attachment.attachment_url, &attachment.attachment_content, &attachment.attachment_hash, &attachment.use_vector, &attachment.token_count),
-        )?;
-        let id = self.conn.last_insert_rowid();
-        Ok(MessageAttachment {
-            id,
-            message_id: attachment.message_id,
-            attachment_type: attachment.attachment_type,
-            attachment_url: attachment.attachment_url.clone(),
-            attachment_content: attachment.attachment_content.clone(),
-            attachment_hash: None,
-            use_vector: attachment.use_vector,
-            token_count: attachment.token_count,
-        })
+    /// 超过大小阈值的附件内容保存到 store 中，不设置时全部保存在数据库中
+    pub fn with_store(mut self, store: AttachmentStore) -> Self {
+        self.store = store;
+        self
     }
 
-    fn read(&self, id: i64) -> Result<Option<MessageAttachment>> {
-        self.conn
-            .query_row(
-                "SELECT * FROM message_attachment WHERE id = ?",
-                &[&id],
-                |row| {
-                    let attachment_type_int: i64 = row.get(2)?;
-                    let attachment_type = AttachmentType::try_from(attachment_type_int)?;
-                    Ok(MessageAttachment {
-                        id: row.get(0)?,
-                        message_id: row.get(1)?,
-                        attachment_type,
-                        attachment_url: row.get(3)?,
-                        attachment_content: row.get(4)?,
-                        attachment_hash: None,
-                        use_vector: row.get(5)?,
-                        token_count: row.get(6)?,
-                    })
-                },
-            )
+    fn conversation_id_of(&self, message_id: i64) -> Result<Option<i64>> {
+        self.conn()
+            .prepare_cached("SELECT conversation_id FROM message WHERE id = ?")?
+            .query_row([message_id], |row| row.get(0))
             .optional()
     }
 
-    fn update(&self, attachment: &MessageAttachment) -> Result<()> {
-        self.conn.execute(
-            "UPDATE message_attachment SET message_id = ?1 WHERE id = ?2",
-            (&attachment.message_id, &attachment.id),
-        )?;
-        Ok(())
+    // 附件通过引用它的消息关联到对话，还没有被消息引用的附件返回 None；
+    // 私密对话中的附件不会和其他对话共用，取任意一条引用即可
+    fn conversation_id_of_attachment(&self, attachment_id: i64) -> Result<Option<i64>> {
+        self.conn()
+            .prepare_cached(
+                "SELECT message.conversation_id FROM message_attachment_link link
+                 JOIN message ON message.id = link.message_id
+                 WHERE link.attachment_id = ? LIMIT 1",
+            )?
+            .query_row([attachment_id], |row| row.get(0))
+            .optional()
     }
 
-    fn delete(&self, id: i64) -> Result<()> {
-        self.conn
-            .execute("DELETE FROM message_attachment WHERE id = ?", &[&id])?;
-        Ok(())
+    fn is_private_conversation(&self, conversation_id: Option<i64>) -> bool {
+        conversation_id
+            .is_some_and(|id| self.private.is_private(id) || self.private.is_incognito(id))
     }
-}
 
-pub struct ConversationDatabase {
-    db_path: PathBuf,
-}
+    fn decrypt(&self, attachment: MessageAttachment) -> Result<MessageAttachment> {
+        match self.conversation_id_of_attachment(attachment.id)? {
+            Some(conversation_id) => {
+                decrypt_attachment(&self.private, &self.store, conversation_id, attachment)
+            }
+            None => resolve_attachment(&self.store, attachment),
+        }
+    }
 
-impl ConversationDatabase {
-    pub fn new(app_handle: &tauri::AppHandle) -> rusqlite::Result<Self> {
-        let db_path = get_db_path(app_handle, "conversation.db");
+    // 加密后再判断大小，私密对话的大附件保存的是加密后的内容
+    fn externalize(&self, content: Option<String>) -> Result<Option<String>> {
+        content
+            .map(|content| self.store.externalize(&content))
+            .transpose()
+            .map_err(store_error)
+    }
 
-        Ok(ConversationDatabase {
-            db_path: db_path.unwrap(),
-        })
+    fn encrypt(&self, attachment_id: i64, text: Option<&str>) -> Result<Option<String>> {
+        let Some(text) = text else {
+            return Ok(None);
+        };
+        match self.conversation_id_of_attachment(attachment_id)? {
+            Some(conversation_id) => self
+                .private
+                .encrypt(conversation_id, text)
+                .map(Some)
+                .map_err(crypto_error),
+            None => Ok(Some(text.to_string())),
+        }
     }
 
-    pub fn conversation_repo(&self) -> Result<ConversationRepository, AppError> {
-        let conn = Connection::open(self.db_path.clone()).map_err(AppError::from)?;
-        Ok(ConversationRepository::new(conn))
+    /// 引用附件的消息和助手的数量
+    pub fn reference_count(&self, attachment_id: i64) -> Result<i64> {
+        self.conn()
+            .prepare_cached(
+                "SELECT (SELECT COUNT(*) FROM message_attachment_link WHERE attachment_id = ?1)
+                      + (SELECT COUNT(*) FROM assistant_attachment_ref WHERE attachment_id = ?1)",
+            )?
+            .query_row([attachment_id], |row| row.get(0))
+    }
+
+    /// 助手把附件加入常驻上下文时记录引用，删除对话时不会删除助手还在使用的附件
+    pub fn add_assistant_reference(&self, assistant_id: i64, attachment_id: i64) -> Result<()> {
+        self.conn().execute(
+            "INSERT OR IGNORE INTO assistant_attachment_ref (assistant_id, attachment_id) VALUES (?1, ?2)",
+            (assistant_id, attachment_id),
+        )?;
+        Ok(())
+    }
+
+    /// 去掉助手对附件的引用，attachment_id 为 None 时去掉助手的所有引用；没有其他引用的附件一起删除
+    pub fn remove_assistant_reference(
+        &self,
+        assistant_id: i64,
+        attachment_id: Option<i64>,
+    ) -> Result<()> {
+        let attachment_ids = self
+            .conn()
+            .prepare_cached(
+                "SELECT attachment_id FROM assistant_attachment_ref
+                 WHERE assistant_id = ?1 AND (?2 IS NULL OR attachment_id = ?2)",
+            )?
+            .query_map((assistant_id, attachment_id), |row| row.get(0))?
+            .collect::<Result<Vec<i64>>>()?;
+        self.conn().execute(
+            "DELETE FROM assistant_attachment_ref WHERE assistant_id = ?1 AND (?2 IS NULL OR attachment_id = ?2)",
+            (assistant_id, attachment_id),
+        )?;
+        delete_unreferenced_attachments(self.conn(), &attachment_ids)?;
+        Ok(())
+    }
+
+    // 复制一份附件，返回新附件的 id，复制出的附件还没有被任何消息引用
+    fn copy(&self, attachment_id: i64) -> Result<i64> {
+        self.conn().execute(
+            "INSERT INTO message_attachment (attachment_type, attachment_url, attachment_content, attachment_hash, use_vector, token_count, ocr_text, encoding)
+             SELECT attachment_type, attachment_url, attachment_content, attachment_hash, use_vector, token_count, ocr_text, encoding FROM message_attachment WHERE id = ?",
+            [attachment_id],
+        )?;
+        Ok(self.conn().last_insert_rowid())
+    }
+
+    /// 把附件关联到消息，返回实际关联的附件 id
+    ///
+    /// 私密和无痕对话中的附件加密保存，已经被其他消息引用的附件先复制一份再关联，不影响其他对话
+    pub fn link(&self, attachment_id: i64, message_id: i64) -> Result<i64> {
+        let private = self.is_private_conversation(self.conversation_id_of(message_id)?);
+        let attachment_id = if private && self.reference_count(attachment_id)? > 0 {
+            self.copy(attachment_id)?
+        } else {
+            attachment_id
+        };
+        self.conn().execute(
+            "INSERT OR IGNORE INTO message_attachment_link (message_id, attachment_id) VALUES (?1, ?2)",
+            (message_id, attachment_id),
+        )?;
+        if private {
+            if let Some(attachment) = self.read(attachment_id)? {
+                self.update_content(&attachment)?;
+            }
+        }
+        Ok(attachment_id)
+    }
+
+    /// 对话设为私密前调用，和其他对话共用的附件复制一份给这个对话，加密时不影响其他对话
+    pub fn unshare_conversation(&self, conversation_id: i64) -> Result<()> {
+        let shared = self
+            .conn()
+            .prepare_cached(
+                "SELECT link.message_id, link.attachment_id FROM message_attachment_link link
+                 JOIN message ON message.id = link.message_id
+                 WHERE message.conversation_id = ?1 AND EXISTS (
+                     SELECT 1 FROM message_attachment_link other
+                     JOIN message other_message ON other_message.id = other.message_id
+                     WHERE other.attachment_id = link.attachment_id
+                       AND other_message.conversation_id != ?1
+                 )",
+            )?
+            .query_map([conversation_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(i64, i64)>>>()?;
+        // 同一个附件在对话中被多条消息引用时只复制一次
+        let mut copies: HashMap<i64, i64> = HashMap::new();
+        for (message_id, attachment_id) in shared {
+            let copy_id = match copies.get(&attachment_id) {
+                Some(copy_id) => *copy_id,
+                None => {
+                    let copy_id = self.copy(attachment_id)?;
+                    copies.insert(attachment_id, copy_id);
+                    copy_id
+                }
+            };
+            self.conn().execute(
+                "UPDATE message_attachment_link SET attachment_id = ?1 WHERE message_id = ?2 AND attachment_id = ?3",
+                (copy_id, message_id, attachment_id),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// 对话设为私密或者取消私密时，按当前状态重新保存内容
+    pub fn list_by_conversation_id(&self, conversation_id: i64) -> Result<Vec<MessageAttachment>> {
+        let mut stmt = self.conn().prepare_cached(
+            "SELECT link.message_id, ma.* FROM message_attachment_link link
+             JOIN message_attachment ma ON ma.id = link.attachment_id
+             JOIN message ON message.id = link.message_id
+             WHERE message.conversation_id = ?1",
+        )?;
+        let rows = stmt.query_map([conversation_id], |row| {
+            attachment_from_row(row, Some(row.get("message_id")?))
+        })?;
+        rows.map(|row| decrypt_attachment(&self.private, &self.store, conversation_id, row?))
+            .collect()
+    }
+
+    /// 对话中的附件和引用它的消息的创建时间，按时间倒序，attachment_type 为 None 时返回所有类型
+    pub fn list_by_conversation_and_type(
+        &self,
+        conversation_id: i64,
+        attachment_type: Option<AttachmentType>,
+    ) -> Result<Vec<(MessageAttachment, DateTime<Utc>)>> {
+        let mut stmt = self.conn().prepare_cached(
+            "SELECT link.message_id, ma.*, message.created_time FROM message_attachment_link link
+             JOIN message_attachment ma ON ma.id = link.attachment_id
+             JOIN message ON message.id = link.message_id
+             WHERE message.conversation_id = ?1 AND message.deleted_time IS NULL
+               AND (?2 IS NULL OR ma.attachment_type = ?2)
+             ORDER BY message.created_time DESC, ma.id DESC",
+        )?;
+        let rows = stmt.query_map(
+            (conversation_id, attachment_type.map(|t| t as i64)),
+            |row| {
+                Ok((
+                    attachment_from_row(row, Some(row.get("message_id")?))?,
+                    row.get("created_time")?,
+                ))
+            },
+        )?;
+        rows.map(|row| {
+            let (attachment, created_time) = row?;
+            Ok((
+                decrypt_attachment(&self.private, &self.store, conversation_id, attachment)?,
+                created_time,
+            ))
+        })
+        .collect()
+    }
+
+    pub fn update_content(&self, attachment: &MessageAttachment) -> Result<()> {
+        self.conn().execute(
+            "UPDATE message_attachment SET attachment_content = ?1, ocr_text = ?2 WHERE id = ?3",
+            (
+                self.externalize(
+                    self.encrypt(attachment.id, attachment.attachment_content.as_deref())?,
+                )?,
+                self.encrypt(attachment.id, attachment.ocr_text.as_deref())?,
+                &attachment.id,
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// 一条消息引用的附件，按上传顺序
+    pub fn list_by_message_id(
+        &self,
+        conversation_id: i64,
+        message_id: i64,
+    ) -> Result<Vec<MessageAttachment>> {
+        let mut stmt = self.conn().prepare_cached(
+            "SELECT link.message_id, ma.* FROM message_attachment_link link
+             JOIN message_attachment ma ON ma.id = link.attachment_id
+             WHERE link.message_id = ?1
+             ORDER BY ma.id",
+        )?;
+        let rows = stmt.query_map([message_id], |row| {
+            attachment_from_row(row, Some(message_id))
+        })?;
+        rows.map(|row| decrypt_attachment(&self.private, &self.store, conversation_id, row?))
+            .collect()
+    }
+
+    pub fn list_by_id(&self, id_list: &Vec<i64>) -> Result<Vec<MessageAttachment>> {
+        let id_list_str: Vec<String> = id_list.iter().map(|id| id.to_string()).collect();
+        let id_list_str = id_list_str.join(",");
+        let query = format!(
+            "SELECT * FROM message_attachment WHERE id IN ({})",
+            id_list_str
+        );
+        let mut stmt = self.conn().prepare(&query)?;
+        let rows = stmt.query_map([], |row| attachment_from_row(row, None))?;
+        rows.map(|row| self.decrypt(row?)).collect()
+    }
+
+    pub fn read_by_attachment_hash(
+        &self,
+        attachment_hash: &str,
+    ) -> Result<Option<MessageAttachment>> {
+        self.conn()
+            .prepare_cached("SELECT * FROM message_attachment WHERE attachment_hash = ? AND (attachment_content IS NULL OR (attachment_content NOT LIKE 'aipp:enc:%' AND attachment_content NOT LIKE 'aipp:file:enc:%'))")?
+            .query_row(&[&attachment_hash], |row| attachment_from_row(row, None))
+            .optional()?
+            .map(|attachment| resolve_attachment(&self.store, attachment))
+            .transpose()
+    }
+
+    /// 把数据库中超过大小阈值的附件内容移到文件中，升级时迁移已有的附件，返回移动的附件数量
+    pub fn externalize_large_contents(&self) -> Result<usize> {
+        let ids = self
+            .conn()
+            .prepare(
+                "SELECT id FROM message_attachment
+                 WHERE length(attachment_content) > ?1 AND attachment_content NOT LIKE 'aipp:file:%'",
+            )?
+            .query_map([EXTERNAL_THRESHOLD as i64], |row| row.get(0))?
+            .collect::<Result<Vec<i64>>>()?;
+        let mut moved = 0;
+        // 逐条读取内容，大附件很多时不会一次全部加载到内存中
+        for id in ids {
+            let content: Option<String> = self
+                .conn()
+                .query_row(
+                    "SELECT attachment_content FROM message_attachment WHERE id = ?",
+                    [id],
+                    |row| row.get(0),
+                )
+                .optional()?
+                .flatten();
+            let Some(content) = content else {
+                continue;
+            };
+            let value = self.store.externalize(&content).map_err(store_error)?;
+            if value != content {
+                self.conn().execute(
+                    "UPDATE message_attachment SET attachment_content = ?1 WHERE id = ?2",
+                    (&value, id),
+                )?;
+                moved += 1;
+            }
+        }
+        Ok(moved)
+    }
+
+    /// 删除已经没有附件引用的文件，附件随对话和消息删除后调用，返回删除的文件数量
+    pub fn remove_orphan_files(&self) -> Result<usize> {
+        let referenced = self
+            .conn()
+            .prepare(
+                "SELECT attachment_content FROM message_attachment WHERE attachment_content LIKE 'aipp:file:%'",
+            )?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .filter_map(|value| match value {
+                Ok(value) => reference_hash(&value).map(|hash| Ok(hash.to_string())),
+                Err(e) => Some(Err(e)),
+            })
+            .collect::<Result<std::collections::HashSet<String>>>()?;
+        self.store
+            .remove_unreferenced(&referenced)
+            .map_err(store_error)
+    }
+
+    pub fn update_ocr_text(&self, id: i64, ocr_text: &str) -> Result<()> {
+        self.conn().execute(
+            "UPDATE message_attachment SET ocr_text = ?1 WHERE id = ?2",
+            (self.encrypt(id, Some(ocr_text))?, &id),
+        )?;
+        Ok(())
+    }
+}
+
+impl<C: Borrow<Connection>> Repository<MessageAttachment> for MessageAttachmentRepository<C> {
+    fn create(&self, attachment: &MessageAttachment) -> Result<MessageAttachment> {
+        self.conn().execute(
+            "INSERT INTO message_attachment (attachment_type, attachment_url, attachment_content, attachment_hash, use_vector, token_count, ocr_text, encoding) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            (&(attachment.attachment_type as i64), &attachment.attachment_url, &self.externalize(attachment.attachment_content.clone())?, &attachment.attachment_hash, &attachment.use_vector, &attachment.token_count, &attachment.ocr_text, &attachment.encoding),
+        )?;
+        let mut id = self.conn().last_insert_rowid();
+        if let Some(message_id) = attachment.message_id {
+            id = self.link(id, message_id)?;
+        }
+        Ok(MessageAttachment {
+            id,
+            message_id: attachment.message_id,
+            attachment_type: attachment.attachment_type,
+            attachment_url: attachment.attachment_url.clone(),
+            attachment_content: attachment.attachment_content.clone(),
+            attachment_hash: None,
+            use_vector: attachment.use_vector,
+            token_count: attachment.token_count,
+            ocr_text: attachment.ocr_text.clone(),
+            encoding: attachment.encoding.clone(),
+        })
+    }
+
+    fn read(&self, id: i64) -> Result<Option<MessageAttachment>> {
+        self.conn()
+            .prepare_cached("SELECT * FROM message_attachment WHERE id = ?")?
+            .query_row(&[&id], |row| attachment_from_row(row, None))
+            .optional()?
+            .map(|attachment| self.decrypt(attachment))
+            .transpose()
+    }
+
+    // 附件的内容保存后不再修改，更新只用于关联到 message_id 对应的消息
+    fn update(&self, attachment: &MessageAttachment) -> Result<()> {
+        if let Some(message_id) = attachment.message_id {
+            self.link(attachment.id, message_id)?;
+        }
+        Ok(())
+    }
+
+    // 还被消息或者助手引用的附件不删除，引用都去掉之后才会删除
+    fn delete(&self, id: i64) -> Result<()> {
+        delete_unreferenced_attachments(self.conn(), &[id])?;
+        Ok(())
+    }
+}
+
+pub struct PromptHistoryRepository<C = PooledConnection> {
+    conn: C,
+}
+
+impl<C: Borrow<Connection>> PromptHistoryRepository<C> {
+    pub fn new(conn: C) -> Self {
+        PromptHistoryRepository { conn }
+    }
+
+    fn conn(&self) -> &Connection {
+        self.conn.borrow()
+    }
+
+    // 相同的提示词只保留最新的一条，方便上下键翻阅历史时不出现重复
+    pub fn add(&self, assistant_id: Option<i64>, prompt: &str) -> Result<PromptHistory> {
+        let created_time = Utc::now();
+        self.conn()
+            .execute("DELETE FROM prompt_history WHERE prompt = ?1", [prompt])?;
+        self.conn().execute(
+            "INSERT INTO prompt_history (assistant_id, prompt, created_time) VALUES (?1, ?2, ?3)",
+            (&assistant_id, prompt, &created_time),
+        )?;
+        Ok(PromptHistory {
+            id: self.conn().last_insert_rowid(),
+            assistant_id,
+            prompt: prompt.to_string(),
+            created_time,
+        })
+    }
+
+    pub fn list_recent(&self, limit: u32) -> Result<Vec<PromptHistory>> {
+        let mut stmt = self.conn().prepare_cached(
+            "SELECT id, assistant_id, prompt, created_time
+             FROM prompt_history
+             ORDER BY created_time DESC
+             LIMIT ?1",
+        )?;
+        let rows = stmt.query_map([limit], |row| {
+            Ok(PromptHistory {
+                id: row.get(0)?,
+                assistant_id: row.get(1)?,
+                prompt: row.get(2)?,
+                created_time: row.get(3)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    pub fn delete_before(&self, time: DateTime<Utc>) -> Result<usize> {
+        self.conn()
+            .execute("DELETE FROM prompt_history WHERE created_time < ?1", [time])
+    }
+}
+
+pub struct ToolInvocationRepository<C = PooledConnection> {
+    conn: C,
+    private: PrivateConversationState,
+}
+
+impl<C: Borrow<Connection>> ToolInvocationRepository<C> {
+    pub fn new(conn: C, private: PrivateConversationState) -> Self {
+        ToolInvocationRepository { conn, private }
+    }
+
+    fn conn(&self) -> &Connection {
+        self.conn.borrow()
+    }
+
+    // 私密对话中的工具调用参数和输出和消息一样加密保存
+    fn encrypt(&self, conversation_id: Option<i64>, text: &str) -> Result<String> {
+        match conversation_id {
+            Some(conversation_id) => self
+                .private
+                .encrypt(conversation_id, text)
+                .map_err(crypto_error),
+            None => Ok(text.to_string()),
+        }
+    }
+
+    pub fn add(&self, invocation: &ToolInvocation) -> Result<ToolInvocation> {
+        self.conn().execute(
+            "INSERT INTO tool_invocation (conversation_id, message_id, tool_name, arguments, result, status, duration_ms, created_time)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            (
+                &invocation.conversation_id,
+                &invocation.message_id,
+                &invocation.tool_name,
+                self.encrypt(invocation.conversation_id, &invocation.arguments)?,
+                self.encrypt(invocation.conversation_id, &invocation.result)?,
+                &invocation.status,
+                &invocation.duration_ms,
+                &invocation.created_time,
+            ),
+        )?;
+        Ok(ToolInvocation {
+            id: self.conn().last_insert_rowid(),
+            ..invocation.clone()
+        })
+    }
+
+    pub fn list_by_conversation_id(&self, conversation_id: i64) -> Result<Vec<ToolInvocation>> {
+        let mut stmt = self.conn().prepare_cached(
+            "SELECT id, conversation_id, message_id, tool_name, arguments, result, status, duration_ms, created_time
+             FROM tool_invocation WHERE conversation_id = ?1 ORDER BY id",
+        )?;
+        let rows = stmt.query_map([conversation_id], |row| {
+            Ok(ToolInvocation {
+                id: row.get(0)?,
+                conversation_id: row.get(1)?,
+                message_id: row.get(2)?,
+                tool_name: row.get(3)?,
+                arguments: row.get(4)?,
+                result: row.get(5)?,
+                status: row.get(6)?,
+                duration_ms: row.get(7)?,
+                created_time: row.get(8)?,
+            })
+        })?;
+        rows.map(|row| {
+            let mut invocation = row?;
+            invocation.arguments = self
+                .private
+                .decrypt(conversation_id, &invocation.arguments)
+                .map_err(crypto_error)?;
+            invocation.result = self
+                .private
+                .decrypt(conversation_id, &invocation.result)
+                .map_err(crypto_error)?;
+            Ok(invocation)
+        })
+        .collect()
+    }
+}
+
+/// 输入框中还没有发送的草稿，已有对话按对话 id 保存，新对话按助手保存
+pub struct DraftRepository<C = PooledConnection> {
+    conn: C,
+    private: PrivateConversationState,
+}
+
+pub fn draft_key(conversation_id: Option<i64>, assistant_id: Option<i64>) -> String {
+    match conversation_id {
+        Some(conversation_id) => conversation_id.to_string(),
+        None => format!("new:{}", assistant_id.unwrap_or(0)),
+    }
+}
+
+impl<C: Borrow<Connection>> DraftRepository<C> {
+    pub fn new(conn: C, private: PrivateConversationState) -> Self {
+        DraftRepository { conn, private }
+    }
+
+    fn conn(&self) -> &Connection {
+        self.conn.borrow()
+    }
+
+    pub fn save(
+        &self,
+        conversation_id: Option<i64>,
+        assistant_id: Option<i64>,
+        content: &str,
+    ) -> Result<()> {
+        let draft_key = draft_key(conversation_id, assistant_id);
+        if content.trim().is_empty() {
+            return self.delete(&draft_key);
+        }
+        // 草稿和消息一样，私密对话中加密保存
+        let content = match conversation_id {
+            Some(conversation_id) => self
+                .private
+                .encrypt(conversation_id, content)
+                .map_err(crypto_error)?,
+            None => content.to_string(),
+        };
+        self.conn().execute(
+            "INSERT INTO conversation_draft (draft_key, conversation_id, content, updated_time)
+             VALUES (?1, ?2, ?3, CURRENT_TIMESTAMP)
+             ON CONFLICT(draft_key) DO UPDATE SET content = excluded.content, updated_time = excluded.updated_time",
+            (&draft_key, &conversation_id, &content),
+        )?;
+        Ok(())
+    }
+
+    pub fn get(
+        &self,
+        conversation_id: Option<i64>,
+        assistant_id: Option<i64>,
+    ) -> Result<Option<String>> {
+        let draft_key = draft_key(conversation_id, assistant_id);
+        let mut stmt = self
+            .conn()
+            .prepare_cached("SELECT content FROM conversation_draft WHERE draft_key = ?1")?;
+        let mut rows = stmt.query_map([&draft_key], |row| row.get::<_, String>(0))?;
+        match (rows.next().transpose()?, conversation_id) {
+            (Some(content), Some(conversation_id)) => self
+                .private
+                .decrypt(conversation_id, &content)
+                .map(Some)
+                .map_err(crypto_error),
+            (content, _) => Ok(content),
+        }
+    }
+
+    pub fn delete(&self, draft_key: &str) -> Result<()> {
+        self.conn().execute(
+            "DELETE FROM conversation_draft WHERE draft_key = ?1",
+            [draft_key],
+        )?;
+        Ok(())
+    }
+}
+
+/// 对话中删除和编辑消息的操作记录，按时间顺序组成撤销栈，撤销过的操作组成重做栈；
+/// 新的操作会清空重做栈
+pub struct MessageOperationRepository<C = PooledConnection> {
+    conn: C,
+    private: PrivateConversationState,
+}
+
+impl<C: Borrow<Connection>> MessageOperationRepository<C> {
+    pub fn new(conn: C, private: PrivateConversationState) -> Self {
+        MessageOperationRepository { conn, private }
+    }
+
+    fn conn(&self) -> &Connection {
+        self.conn.borrow()
+    }
+
+    // 编辑前的内容和消息一样，私密对话中加密保存
+    fn encrypt(&self, conversation_id: i64, content: Option<&str>) -> Result<Option<String>> {
+        content
+            .map(|content| self.private.encrypt(conversation_id, content))
+            .transpose()
+            .map_err(crypto_error)
+    }
+
+    fn operation_from_row(&self, row: &rusqlite::Row) -> Result<MessageOperation> {
+        let conversation_id: i64 = row.get(1)?;
+        let message_ids: String = row.get(3)?;
+        let content: Option<String> = row.get(4)?;
+        Ok(MessageOperation {
+            id: row.get(0)?,
+            conversation_id,
+            operation_type: row.get(2)?,
+            message_ids: serde_json::from_str(&message_ids).unwrap_or_default(),
+            content: content
+                .map(|content| self.private.decrypt(conversation_id, &content))
+                .transpose()
+                .map_err(crypto_error)?,
+            undone: row.get(5)?,
+            created_time: row.get(6)?,
+        })
+    }
+
+    pub fn add(
+        &self,
+        conversation_id: i64,
+        operation_type: &str,
+        message_ids: &[i64],
+        content: Option<&str>,
+    ) -> Result<MessageOperation> {
+        let created_time = Utc::now();
+        self.conn().execute(
+            "DELETE FROM message_operation WHERE conversation_id = ?1 AND undone = 1",
+            [conversation_id],
+        )?;
+        self.conn().execute(
+            "INSERT INTO message_operation (conversation_id, operation_type, message_ids, content, created_time)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            (
+                &conversation_id,
+                operation_type,
+                serde_json::to_string(message_ids).unwrap_or_default(),
+                self.encrypt(conversation_id, content)?,
+                &created_time,
+            ),
+        )?;
+        Ok(MessageOperation {
+            id: self.conn().last_insert_rowid(),
+            conversation_id,
+            operation_type: operation_type.to_string(),
+            message_ids: message_ids.to_vec(),
+            content: content.map(|content| content.to_string()),
+            undone: false,
+            created_time,
+        })
+    }
+
+    /// 最近一次还没有撤销的操作
+    pub fn last_done(&self, conversation_id: i64) -> Result<Option<MessageOperation>> {
+        self.conn()
+            .prepare_cached(
+                "SELECT id, conversation_id, operation_type, message_ids, content, undone, created_time
+                 FROM message_operation WHERE conversation_id = ?1 AND undone = 0
+                 ORDER BY id DESC LIMIT 1",
+            )?
+            .query_row([conversation_id], |row| self.operation_from_row(row))
+            .optional()
+    }
+
+    /// 最早一次撤销的操作，也就是最后撤销的那一个
+    pub fn first_undone(&self, conversation_id: i64) -> Result<Option<MessageOperation>> {
+        self.conn()
+            .prepare_cached(
+                "SELECT id, conversation_id, operation_type, message_ids, content, undone, created_time
+                 FROM message_operation WHERE conversation_id = ?1 AND undone = 1
+                 ORDER BY id LIMIT 1",
+            )?
+            .query_row([conversation_id], |row| self.operation_from_row(row))
+            .optional()
+    }
+
+    pub fn list_by_conversation_id(&self, conversation_id: i64) -> Result<Vec<MessageOperation>> {
+        let mut stmt = self.conn().prepare_cached(
+            "SELECT id, conversation_id, operation_type, message_ids, content, undone, created_time
+             FROM message_operation WHERE conversation_id = ?1 ORDER BY id",
+        )?;
+        let rows = stmt.query_map([conversation_id], |row| self.operation_from_row(row))?;
+        rows.collect()
+    }
+
+    /// 撤销或者重做后更新状态，编辑操作同时保存被替换的内容
+    pub fn update(&self, operation: &MessageOperation) -> Result<()> {
+        self.conn().execute(
+            "UPDATE message_operation SET content = ?1, undone = ?2 WHERE id = ?3",
+            (
+                self.encrypt(operation.conversation_id, operation.content.as_deref())?,
+                &operation.undone,
+                &operation.id,
+            ),
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_before(&self, time: DateTime<Utc>) -> Result<usize> {
+        self.conn().execute(
+            "DELETE FROM message_operation WHERE created_time < ?1",
+            [time],
+        )
+    }
+}
+
+/// 对话级别的回答风格，生成时转换成说明拼接到 system 消息后面，不用修改助手就能随时调整回答的形式
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ResponseStyle {
+    // bullet 要点列表，prose 连贯的段落，code_only 只输出代码
+    pub format: Option<String>,
+    // 回答使用的语言，比如 中文、English
+    pub language: Option<String>,
+    // 回答的语气，比如 正式、轻松
+    pub tone: Option<String>,
+    // 回答的最大字数，为空或者 0 时不限制
+    pub max_length: Option<u32>,
+}
+
+impl ResponseStyle {
+    /// 转换成给模型的说明，没有设置任何偏好时返回 None
+    pub fn instruction(&self) -> Option<String> {
+        let not_empty = |value: &Option<String>| {
+            value
+                .as_deref()
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+        let mut lines = Vec::new();
+        match not_empty(&self.format).as_deref() {
+            Some("bullet") => lines.push("使用要点列表的形式回答，每个要点简洁明了。".to_string()),
+            Some("prose") => lines.push("使用连贯的段落回答，不要使用列表。".to_string()),
+            Some("code_only") => {
+                lines.push("只输出代码，不要添加任何解释或者代码以外的文字。".to_string())
+            }
+            _ => {}
+        }
+        if let Some(language) = not_empty(&self.language) {
+            lines.push(format!("使用{}回答。", language));
+        }
+        if let Some(tone) = not_empty(&self.tone) {
+            lines.push(format!("回答的语气：{}。", tone));
+        }
+        if let Some(max_length) = self.max_length.filter(|max_length| *max_length > 0) {
+            lines.push(format!("回答不超过 {} 字。", max_length));
+        }
+        if lines.is_empty() {
+            None
+        } else {
+            Some(lines.join("\n"))
+        }
+    }
+}
+
+/// 对话的回答风格，每个对话一条记录，没有设置时使用助手原本的风格
+pub struct ResponseStyleRepository<C = PooledConnection> {
+    conn: C,
+}
+
+impl<C: Borrow<Connection>> ResponseStyleRepository<C> {
+    pub fn new(conn: C) -> Self {
+        ResponseStyleRepository { conn }
+    }
+
+    fn conn(&self) -> &Connection {
+        self.conn.borrow()
+    }
+
+    pub fn get(&self, conversation_id: i64) -> Result<Option<ResponseStyle>> {
+        let style: Option<String> = self
+            .conn()
+            .prepare_cached("SELECT style FROM conversation_style WHERE conversation_id = ?1")?
+            .query_row([conversation_id], |row| row.get(0))
+            .optional()?;
+        Ok(style.and_then(|style| serde_json::from_str(&style).ok()))
+    }
+
+    pub fn save(&self, conversation_id: i64, style: &ResponseStyle) -> Result<()> {
+        if style.instruction().is_none() {
+            return self.delete(conversation_id);
+        }
+        let style = serde_json::to_string(style)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        self.conn().execute(
+            "INSERT INTO conversation_style (conversation_id, style, updated_time)
+             VALUES (?1, ?2, CURRENT_TIMESTAMP)
+             ON CONFLICT(conversation_id) DO UPDATE SET style = excluded.style, updated_time = excluded.updated_time",
+            (&conversation_id, &style),
+        )?;
+        Ok(())
+    }
+
+    pub fn delete(&self, conversation_id: i64) -> Result<()> {
+        self.conn().execute(
+            "DELETE FROM conversation_style WHERE conversation_id = ?1",
+            [conversation_id],
+        )?;
+        Ok(())
+    }
+}
+
+/// 用户的术语表，翻译时原文中出现的术语按指定的译法翻译
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GlossaryTerm {
+    pub id: i64,
+    pub source_term: String,
+    pub target_term: String,
+    // 只在翻译成这种语言时使用，为空时所有语言都使用
+    pub target_lang: Option<String>,
+}
+
+pub struct GlossaryRepository<C = PooledConnection> {
+    conn: C,
+}
+
+impl<C: Borrow<Connection>> GlossaryRepository<C> {
+    pub fn new(conn: C) -> Self {
+        GlossaryRepository { conn }
+    }
+
+    fn conn(&self) -> &Connection {
+        self.conn.borrow()
+    }
+
+    /// target_lang 不为空时只返回这种语言和所有语言通用的术语
+    pub fn list(&self, target_lang: Option<&str>) -> Result<Vec<GlossaryTerm>> {
+        let mut stmt = self.conn().prepare_cached(
+            "SELECT id, source_term, target_term, target_lang FROM glossary_term
+             WHERE ?1 IS NULL OR target_lang IS NULL OR target_lang = ?1 COLLATE NOCASE
+             ORDER BY id",
+        )?;
+        let rows = stmt.query_map([target_lang], |row| {
+            Ok(GlossaryTerm {
+                id: row.get(0)?,
+                source_term: row.get(1)?,
+                target_term: row.get(2)?,
+                target_lang: row.get(3)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// id 为 0 时新增术语，否则更新已有的术语
+    pub fn save(&self, term: &GlossaryTerm) -> Result<GlossaryTerm> {
+        let target_lang = term
+            .target_lang
+            .as_deref()
+            .map(str::trim)
+            .filter(|lang| !lang.is_empty());
+        let id = if term.id == 0 {
+            self.conn().execute(
+                "INSERT INTO glossary_term (source_term, target_term, target_lang) VALUES (?1, ?2, ?3)",
+                (term.source_term.trim(), term.target_term.trim(), target_lang),
+            )?;
+            self.conn().last_insert_rowid()
+        } else {
+            self.conn().execute(
+                "UPDATE glossary_term SET source_term = ?1, target_term = ?2, target_lang = ?3 WHERE id = ?4",
+                (term.source_term.trim(), term.target_term.trim(), target_lang, term.id),
+            )?;
+            term.id
+        };
+        Ok(GlossaryTerm {
+            id,
+            source_term: term.source_term.trim().to_string(),
+            target_term: term.target_term.trim().to_string(),
+            target_lang: target_lang.map(str::to_string),
+        })
+    }
+
+    pub fn delete(&self, id: i64) -> Result<()> {
+        self.conn()
+            .execute("DELETE FROM glossary_term WHERE id = ?1", [id])?;
+        Ok(())
+    }
+}
+
+/// 消息的译文，和原文分开保存，原文没有变化时再次查看直接使用，不用重新请求
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MessageTranslation {
+    pub message_id: i64,
+    pub target_lang: String,
+    pub content: String,
+    // 翻译时原文的哈希，原文被编辑或者重新生成后需要重新翻译；私密对话中和译文一样加密保存，
+    // 不能通过哈希比对出原文
+    pub source_hash: String,
+    pub created_time: DateTime<Utc>,
+}
+
+pub struct TranslationRepository<C = PooledConnection> {
+    conn: C,
+    private: PrivateConversationState,
+}
+
+impl<C: Borrow<Connection>> TranslationRepository<C> {
+    pub fn new(conn: C, private: PrivateConversationState) -> Self {
+        TranslationRepository { conn, private }
+    }
+
+    fn conn(&self) -> &Connection {
+        self.conn.borrow()
+    }
+
+    pub fn get(
+        &self,
+        conversation_id: i64,
+        message_id: i64,
+        target_lang: &str,
+    ) -> Result<Option<MessageTranslation>> {
+        let translation = self
+            .conn()
+            .prepare_cached(
+                "SELECT message_id, target_lang, content, source_hash, created_time FROM message_translation
+                 WHERE message_id = ?1 AND target_lang = ?2",
+            )?
+            .query_row((message_id, target_lang), |row| {
+                Ok(MessageTranslation {
+                    message_id: row.get(0)?,
+                    target_lang: row.get(1)?,
+                    content: row.get(2)?,
+                    source_hash: row.get(3)?,
+                    created_time: row.get(4)?,
+                })
+            })
+            .optional()?;
+        translation
+            .map(|mut translation| {
+                // 译文和消息一样，私密对话中加密保存
+                translation.content = self
+                    .private
+                    .decrypt(conversation_id, &translation.content)
+                    .map_err(crypto_error)?;
+                translation.source_hash = self
+                    .private
+                    .decrypt(conversation_id, &translation.source_hash)
+                    .map_err(crypto_error)?;
+                Ok(translation)
+            })
+            .transpose()
+    }
+
+    pub fn save(&self, conversation_id: i64, translation: &MessageTranslation) -> Result<()> {
+        let content = self
+            .private
+            .encrypt(conversation_id, &translation.content)
+            .map_err(crypto_error)?;
+        let source_hash = self
+            .private
+            .encrypt(conversation_id, &translation.source_hash)
+            .map_err(crypto_error)?;
+        self.conn().execute(
+            "INSERT INTO message_translation (message_id, conversation_id, target_lang, content, source_hash, created_time)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT(message_id, target_lang) DO UPDATE SET content = excluded.content, source_hash = excluded.source_hash, created_time = excluded.created_time",
+            (
+                translation.message_id,
+                conversation_id,
+                &translation.target_lang,
+                &content,
+                &source_hash,
+                &translation.created_time,
+            ),
+        )?;
+        Ok(())
+    }
+}
+
+/// 引用消息的来源，引用时记录下来，原对话之后被修改或者删除也不影响已经引用的内容
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MessageQuote {
+    pub message_id: i64,
+    pub source_conversation_id: i64,
+    pub source_conversation_name: String,
+    pub source_message_id: i64,
+    // 被引用的消息是提问还是回答
+    pub source_message_type: String,
+    pub source_created_time: DateTime<Utc>,
+}
+
+pub struct QuoteRepository<C = PooledConnection> {
+    conn: C,
+    private: PrivateConversationState,
+}
+
+impl<C: Borrow<Connection>> QuoteRepository<C> {
+    pub fn new(conn: C, private: PrivateConversationState) -> Self {
+        QuoteRepository { conn, private }
+    }
+
+    fn conn(&self) -> &Connection {
+        self.conn.borrow()
+    }
+
+    /// 来源对话的名称按引用所在的对话加密保存，私密对话和无痕对话中不留下明文
+    pub fn save(&self, conversation_id: i64, quote: &MessageQuote) -> Result<()> {
+        let source_conversation_name = self
+            .private
+            .encrypt(conversation_id, &quote.source_conversation_name)
+            .map_err(crypto_error)?;
+        self.conn().execute(
+            "INSERT OR REPLACE INTO message_quote (message_id, conversation_id, source_conversation_id, source_conversation_name, source_message_id, source_message_type, source_created_time)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            (
+                quote.message_id,
+                conversation_id,
+                quote.source_conversation_id,
+                &source_conversation_name,
+                quote.source_message_id,
+                &quote.source_message_type,
+                &quote.source_created_time,
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// 对话中引用消息的来源，key 为引用消息的 id
+    pub fn list_by_conversation_id(
+        &self,
+        conversation_id: i64,
+    ) -> Result<HashMap<i64, MessageQuote>> {
+        let mut stmt = self.conn().prepare_cached(
+            "SELECT message_id, source_conversation_id, source_conversation_name, source_message_id, source_message_type, source_created_time
+             FROM message_quote WHERE conversation_id = ?1",
+        )?;
+        let rows = stmt.query_map([conversation_id], |row| {
+            let source_conversation_name: String = row.get(2)?;
+            Ok(MessageQuote {
+                message_id: row.get(0)?,
+                source_conversation_id: row.get(1)?,
+                source_conversation_name: self
+                    .private
+                    .decrypt(conversation_id, &source_conversation_name)
+                    .map_err(crypto_error)?,
+                source_message_id: row.get(3)?,
+                source_message_type: row.get(4)?,
+                source_created_time: row.get(5)?,
+            })
+        })?;
+        rows.map(|row| row.map(|quote| (quote.message_id, quote)))
+            .collect()
+    }
+}
+
+pub struct ConversationDatabase {
+    db_path: PathBuf,
+    private: PrivateConversationState,
+    attachment_store: AttachmentStore,
+}
+
+impl ConversationDatabase {
+    pub fn new(app_handle: &AppHandle) -> rusqlite::Result<Self> {
+        let db_path = get_db_path(app_handle, "conversation.db");
+
+        // 启动时创建表和升级数据库的时候还没有私密对话状态，这时不会读写对话内容
+        let private = app_handle
+            .try_state::<PrivateConversationState>()
+            .map(|state| state.inner().clone())
+            .unwrap_or_default();
+
+        Ok(ConversationDatabase {
+            db_path: db_path.unwrap(),
+            private,
+            attachment_store: AttachmentStore::new(get_attachment_dir(app_handle)),
+        })
+    }
+
+    pub fn conversation_repo(&self) -> Result<ConversationRepository, AppError> {
+        let conn = self.get_connection().map_err(AppError::from)?;
+        Ok(ConversationRepository::new(conn))
     }
 
     pub fn message_repo(&self) -> Result<MessageRepository, AppError> {
-        let conn = Connection::open(self.db_path.clone()).map_err(AppError::from)?;
-        Ok(MessageRepository::new(conn))
+        let conn = self.get_connection().map_err(AppError::from)?;
+        Ok(MessageRepository::new(conn, self.private.clone())
+            .with_attachment_store(self.attachment_store.clone()))
     }
 
     pub fn attachment_repo(&self) -> Result<MessageAttachmentRepository, AppError> {
-        let conn = Connection::open(self.db_path.clone()).map_err(AppError::from)?;
-        Ok(MessageAttachmentRepository::new(conn))
+        let conn = self.get_connection().map_err(AppError::from)?;
+        Ok(MessageAttachmentRepository::new(conn, self.private.clone())
+            .with_store(self.attachment_store.clone()))
+    }
+
+    pub fn prompt_history_repo(&self) -> Result<PromptHistoryRepository, AppError> {
+        let conn = self.get_connection().map_err(AppError::from)?;
+        Ok(PromptHistoryRepository::new(conn))
+    }
+
+    pub fn tool_invocation_repo(&self) -> Result<ToolInvocationRepository, AppError> {
+        let conn = self.get_connection().map_err(AppError::from)?;
+        Ok(ToolInvocationRepository::new(conn, self.private.clone()))
+    }
+
+    pub fn draft_repo(&self) -> Result<DraftRepository, AppError> {
+        let conn = self.get_connection().map_err(AppError::from)?;
+        Ok(DraftRepository::new(conn, self.private.clone()))
+    }
+
+    pub fn operation_repo(&self) -> Result<MessageOperationRepository, AppError> {
+        let conn = self.get_connection().map_err(AppError::from)?;
+        Ok(MessageOperationRepository::new(conn, self.private.clone()))
+    }
+
+    pub fn style_repo(&self) -> Result<ResponseStyleRepository, AppError> {
+        let conn = self.get_connection().map_err(AppError::from)?;
+        Ok(ResponseStyleRepository::new(conn))
+    }
+
+    pub fn glossary_repo(&self) -> Result<GlossaryRepository, AppError> {
+        let conn = self.get_connection().map_err(AppError::from)?;
+        Ok(GlossaryRepository::new(conn))
+    }
+
+    pub fn translation_repo(&self) -> Result<TranslationRepository, AppError> {
+        let conn = self.get_connection().map_err(AppError::from)?;
+        Ok(TranslationRepository::new(conn, self.private.clone()))
+    }
+
+    pub fn quote_repo(&self) -> Result<QuoteRepository, AppError> {
+        let conn = self.get_connection().map_err(AppError::from)?;
+        Ok(QuoteRepository::new(conn, self.private.clone()))
+    }
+
+    /// 在一个事务中执行 f，f 中通过 ConversationTransaction 取得的仓库共用这个事务，
+    /// f 返回错误时全部回滚
+    pub fn transaction<T>(
+        &self,
+        f: impl FnOnce(&ConversationTransaction) -> Result<T, AppError>,
+    ) -> Result<T, AppError> {
+        let mut conn = self.get_connection().map_err(AppError::from)?;
+        let tx = conn.transaction().map_err(AppError::from)?;
+        let result = f(&ConversationTransaction {
+            conn: &tx,
+            db: self,
+        })?;
+        tx.commit().map_err(AppError::from)?;
+        Ok(result)
+    }
+
+    // 仓库里的查询都通过 prepare_cached 复用预编译的语句，生成过程中反复读写消息时不用每次重新解析 SQL
+    // 连接用完后放回连接池，语句缓存才能在多次仓库调用之间生效
+    pub fn get_connection(&self) -> rusqlite::Result<PooledConnection> {
+        PooledConnection::open(&self.db_path, STATEMENT_CACHE_CAPACITY)
     }
 
     pub fn create_tables(&self) -> rusqlite::Result<()> {
         let conn = Connection::open(self.db_path.clone()).unwrap();
+        create_schema(&conn)
+    }
+}
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS conversation (
+/// 事务中的仓库，只能在 ConversationDatabase::transaction 的闭包中使用
+pub struct ConversationTransaction<'a> {
+    conn: &'a Connection,
+    db: &'a ConversationDatabase,
+}
+
+impl<'a> ConversationTransaction<'a> {
+    pub fn conversation_repo(&self) -> ConversationRepository<&'a Connection> {
+        ConversationRepository::new(self.conn)
+    }
+
+    pub fn message_repo(&self) -> MessageRepository<&'a Connection> {
+        MessageRepository::new(self.conn, self.db.private.clone())
+            .with_attachment_store(self.db.attachment_store.clone())
+    }
+
+    pub fn attachment_repo(&self) -> MessageAttachmentRepository<&'a Connection> {
+        MessageAttachmentRepository::new(self.conn, self.db.private.clone())
+            .with_store(self.db.attachment_store.clone())
+    }
+
+    pub fn quote_repo(&self) -> QuoteRepository<&'a Connection> {
+        QuoteRepository::new(self.conn, self.db.private.clone())
+    }
+
+    pub fn draft_repo(&self) -> DraftRepository<&'a Connection> {
+        DraftRepository::new(self.conn, self.db.private.clone())
+    }
+
+    pub fn operation_repo(&self) -> MessageOperationRepository<&'a Connection> {
+        MessageOperationRepository::new(self.conn, self.db.private.clone())
+    }
+}
+
+fn create_schema(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS conversation (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
                 name TEXT NOT NULL,
                 assistant_id INTEGER,
-                created_time DATETIME DEFAULT CURRENT_TIMESTAMP
+                created_time DATETIME DEFAULT CURRENT_TIMESTAMP,
+                is_private BOOLEAN NOT NULL DEFAULT 0,
+                last_read_time DATETIME,
+                summary TEXT,
+                is_incognito BOOLEAN NOT NULL DEFAULT 0
             )",
-            [],
-        )?;
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS message (
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS message (
                 id              INTEGER
                 primary key autoincrement,
                 conversation_id INTEGER not null,
@@ -514,12 +2405,21 @@ impl ConversationDatabase {
                 parent_id       integer,
                 start_time      DATETIME,
                 finish_time     DATETIME,
-                llm_model_name  TEXT
+                llm_model_name  TEXT,
+                is_truncated    BOOLEAN default 0 not null,
+                attachment_truncation TEXT,
+                pinned          BOOLEAN default 0 not null,
+                citations       TEXT,
+                timed_out       BOOLEAN default 0 not null,
+                generation_metadata TEXT,
+                reasoning       TEXT,
+                deleted_time    DATETIME,
+                assistant_id    INTEGER
             )",
-            [],
-        )?;
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS message_attachment (
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS message_attachment (
                 id                 INTEGER
                 primary key autoincrement,
                 message_id         INTEGER,
@@ -528,11 +2428,902 @@ impl ConversationDatabase {
                 attachment_hash    TEXT,
                 attachment_content TEXT,
                 use_vector         BOOLEAN default 0 not null,
-                token_count        INTEGER
+                token_count        INTEGER,
+                ocr_text           TEXT,
+                encoding           TEXT
             )",
-            [],
-        )?;
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS message_attachment_link (
+                message_id    INTEGER not null,
+                attachment_id INTEGER not null,
+                primary key (message_id, attachment_id)
+            )",
+        [],
+    )?;
+    // 助手的常驻上下文引用的附件，助手保存在另一个数据库中，这里记录引用用于附件的引用计数
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS assistant_attachment_ref (
+                assistant_id  INTEGER not null,
+                attachment_id INTEGER not null,
+                primary key (assistant_id, attachment_id)
+            )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS prompt_history (
+                id           INTEGER PRIMARY KEY AUTOINCREMENT,
+                assistant_id INTEGER,
+                prompt       TEXT NOT NULL,
+                created_time DATETIME DEFAULT CURRENT_TIMESTAMP
+            )",
+        [],
+    )?;
 
-        Ok(())
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS tool_invocation (
+            id              INTEGER PRIMARY KEY AUTOINCREMENT,
+            conversation_id INTEGER,
+            message_id      INTEGER,
+            tool_name       TEXT NOT NULL,
+            arguments       TEXT NOT NULL,
+            result          TEXT NOT NULL,
+            status          TEXT NOT NULL,
+            duration_ms     INTEGER NOT NULL DEFAULT 0,
+            created_time    DATETIME DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS conversation_draft (
+            draft_key       TEXT PRIMARY KEY,
+            conversation_id INTEGER,
+            content         TEXT NOT NULL,
+            updated_time    DATETIME DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS message_operation (
+            id              INTEGER PRIMARY KEY AUTOINCREMENT,
+            conversation_id INTEGER NOT NULL,
+            operation_type  TEXT NOT NULL,
+            message_ids     TEXT NOT NULL,
+            content         TEXT,
+            undone          BOOLEAN NOT NULL DEFAULT 0,
+            created_time    DATETIME DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS conversation_style (
+            conversation_id INTEGER PRIMARY KEY,
+            style           TEXT NOT NULL,
+            updated_time    DATETIME DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS glossary_term (
+            id           INTEGER PRIMARY KEY AUTOINCREMENT,
+            source_term  TEXT NOT NULL,
+            target_term  TEXT NOT NULL,
+            target_lang  TEXT,
+            created_time DATETIME DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS message_translation (
+            message_id      INTEGER NOT NULL,
+            conversation_id INTEGER NOT NULL,
+            target_lang     TEXT NOT NULL,
+            content         TEXT NOT NULL,
+            source_hash     TEXT NOT NULL,
+            created_time    DATETIME DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (message_id, target_lang)
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS message_quote (
+            message_id               INTEGER PRIMARY KEY,
+            conversation_id          INTEGER NOT NULL,
+            source_conversation_id   INTEGER NOT NULL,
+            source_conversation_name TEXT NOT NULL,
+            source_message_id        INTEGER NOT NULL,
+            source_message_type      TEXT NOT NULL,
+            source_created_time      DATETIME NOT NULL
+        )",
+        [],
+    )?;
+
+    create_indexes(conn)
+}
+
+/// 按对话查消息、按消息查附件、按哈希查附件和按时间排序的列表查询使用的索引，
+/// 新建数据库时和升级到 0.0.15 时创建
+pub fn create_indexes(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_conversation_created_time ON conversation (created_time);
+         CREATE INDEX IF NOT EXISTS idx_conversation_assistant_id ON conversation (assistant_id);
+         CREATE INDEX IF NOT EXISTS idx_message_conversation_id ON message (conversation_id, created_time);
+         CREATE INDEX IF NOT EXISTS idx_message_parent_id ON message (parent_id);
+         CREATE INDEX IF NOT EXISTS idx_message_created_time ON message (created_time);
+         CREATE INDEX IF NOT EXISTS idx_message_attachment_message_id ON message_attachment (message_id);
+         CREATE INDEX IF NOT EXISTS idx_message_attachment_hash ON message_attachment (attachment_hash);
+         CREATE INDEX IF NOT EXISTS idx_message_attachment_link_attachment_id ON message_attachment_link (attachment_id);
+         CREATE INDEX IF NOT EXISTS idx_prompt_history_created_time ON prompt_history (created_time);
+         CREATE INDEX IF NOT EXISTS idx_tool_invocation_conversation_id ON tool_invocation (conversation_id);
+         CREATE INDEX IF NOT EXISTS idx_message_operation_conversation_id ON message_operation (conversation_id);",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn query_plan(conn: &Connection, sql: &str) -> String {
+        let mut stmt = conn
+            .prepare(&format!("EXPLAIN QUERY PLAN {}", sql))
+            .unwrap();
+        let rows = stmt.query_map([], |row| row.get::<_, String>(3)).unwrap();
+        rows.map(|row| row.unwrap()).collect::<Vec<_>>().join("; ")
+    }
+
+    #[test]
+    fn test_queries_use_indexes() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        let plan = query_plan(&conn, "SELECT * FROM message WHERE conversation_id = 1");
+        assert!(plan.contains("idx_message_conversation_id"), "{}", plan);
+        let plan = query_plan(
+            &conn,
+            "SELECT * FROM message_attachment WHERE attachment_hash = 'x'",
+        );
+        assert!(plan.contains("idx_message_attachment_hash"), "{}", plan);
+        let plan = query_plan(
+            &conn,
+            "SELECT * FROM conversation ORDER BY created_time DESC LIMIT 10",
+        );
+        assert!(plan.contains("idx_conversation_created_time"), "{}", plan);
+    }
+
+    fn seed(conn: &Connection, conversations: i64, messages_per_conversation: i64) {
+        let tx = conn.unchecked_transaction().unwrap();
+        for conversation_id in 1..=conversations {
+            tx.execute(
+                "INSERT INTO conversation (id, name, assistant_id, created_time) VALUES (?1, 'c', 1, datetime('now', ?2))",
+                (conversation_id, format!("-{} minutes", conversation_id)),
+            )
+            .unwrap();
+            for _ in 0..messages_per_conversation {
+                tx.execute(
+                    "INSERT INTO message (conversation_id, message_type, content) VALUES (?1, 'user', 'hello')",
+                    [conversation_id],
+                )
+                .unwrap();
+                let message_id = tx.last_insert_rowid();
+                tx.execute(
+                    "INSERT INTO message_attachment (attachment_type, attachment_hash) VALUES (1, ?1)",
+                    [format!("hash-{}", message_id)],
+                )
+                .unwrap();
+                tx.execute(
+                    "INSERT INTO message_attachment_link (message_id, attachment_id) VALUES (?1, ?2)",
+                    (message_id, tx.last_insert_rowid()),
+                )
+                .unwrap();
+            }
+        }
+        tx.commit().unwrap();
+    }
+
+    fn measure(label: &str, runs: u32, mut f: impl FnMut(u32)) -> Duration {
+        let started = Instant::now();
+        for run in 0..runs {
+            f(run);
+        }
+        let elapsed = started.elapsed();
+        println!("{:<40} {:>10.3?} / {} runs", label, elapsed, runs);
+        elapsed
+    }
+
+    // 对比有无索引时列表和查找的耗时：cargo test bench_conversation_queries -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_conversation_queries() {
+        let private = PrivateConversationState::default();
+        for indexed in [false, true] {
+            let conn = Connection::open_in_memory().unwrap();
+            conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+            create_schema(&conn).unwrap();
+            if !indexed {
+                let mut stmt = conn
+                    .prepare(
+                        "SELECT name FROM sqlite_master WHERE type = 'index' AND name LIKE 'idx_%'",
+                    )
+                    .unwrap();
+                let names: Vec<String> = stmt
+                    .query_map([], |row| row.get(0))
+                    .unwrap()
+                    .map(|row| row.unwrap())
+                    .collect();
+                for name in names {
+                    conn.execute_batch(&format!("DROP INDEX {}", name)).unwrap();
+                }
+            }
+            seed(&conn, 2000, 20);
+            println!("indexed: {}", indexed);
+
+            let repo = MessageRepository::new(conn, private.clone());
+            measure("list messages by conversation", 200, |run| {
+                repo.list_by_conversation_id(run as i64 % 2000 + 1).unwrap();
+            });
+            let repo = MessageAttachmentRepository::new(repo.conn, private.clone());
+            measure("read attachment by hash", 200, |run| {
+                repo.read_by_attachment_hash(&format!("hash-{}", run * 97 + 1))
+                    .unwrap();
+            });
+            let repo = ConversationRepository::new(repo.conn);
+            measure("list conversations page", 200, |run| {
+                repo.list(run % 50 + 1, 20).unwrap();
+            });
+        }
+    }
+
+    #[test]
+    fn test_shared_attachment_reference_count() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO conversation (id, name) VALUES (1, 'a'), (2, 'b');
+             INSERT INTO message (id, conversation_id, message_type, content) VALUES (1, 1, 'user', 'a'), (2, 2, 'user', 'b');",
+        )
+        .unwrap();
+        let repo = MessageAttachmentRepository::new(conn, PrivateConversationState::default());
+        let attachment = repo
+            .create(&MessageAttachment {
+                id: 0,
+                message_id: None,
+                attachment_type: AttachmentType::Text,
+                attachment_url: Some("notes.txt".to_string()),
+                attachment_content: Some("hello".to_string()),
+                attachment_hash: Some("hash".to_string()),
+                use_vector: false,
+                token_count: Some(0),
+                ocr_text: None,
+                encoding: None,
+            })
+            .unwrap();
+        assert_eq!(repo.reference_count(attachment.id).unwrap(), 0);
+        // 两个对话引用同一个附件
+        assert_eq!(repo.link(attachment.id, 1).unwrap(), attachment.id);
+        assert_eq!(repo.link(attachment.id, 2).unwrap(), attachment.id);
+        assert_eq!(repo.reference_count(attachment.id).unwrap(), 2);
+        assert_eq!(
+            repo.list_by_conversation_id(2).unwrap()[0].message_id,
+            Some(2)
+        );
+
+        let conversation_repo = ConversationRepository::new(repo.conn);
+        conversation_repo.delete_with_messages(1).unwrap();
+        let repo = MessageAttachmentRepository::new(
+            conversation_repo.conn,
+            PrivateConversationState::default(),
+        );
+        assert!(repo.read(attachment.id).unwrap().is_some());
+        assert_eq!(repo.reference_count(attachment.id).unwrap(), 1);
+
+        let conversation_repo = ConversationRepository::new(repo.conn);
+        conversation_repo.delete_with_messages(2).unwrap();
+        let repo = MessageAttachmentRepository::new(
+            conversation_repo.conn,
+            PrivateConversationState::default(),
+        );
+        assert!(repo.read(attachment.id).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_assistant_attachment_reference() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO conversation (id, name) VALUES (1, 'a');
+             INSERT INTO message (id, conversation_id, message_type, content) VALUES (1, 1, 'user', 'a');",
+        )
+        .unwrap();
+        let private = PrivateConversationState::default();
+        let repo = MessageAttachmentRepository::new(&conn, private.clone());
+        let attachment = repo
+            .create(&MessageAttachment {
+                id: 0,
+                message_id: Some(1),
+                attachment_type: AttachmentType::Text,
+                attachment_url: Some("guide.md".to_string()),
+                attachment_content: Some("风格指南".to_string()),
+                attachment_hash: Some("hash".to_string()),
+                use_vector: false,
+                token_count: Some(0),
+                ocr_text: None,
+                encoding: None,
+            })
+            .unwrap();
+        repo.add_assistant_reference(3, attachment.id).unwrap();
+        assert_eq!(repo.reference_count(attachment.id).unwrap(), 2);
+        // 还被引用的附件不能直接删除
+        repo.delete(attachment.id).unwrap();
+        assert!(repo.read(attachment.id).unwrap().is_some());
+
+        // 彻底删除消息之后，助手仍然在使用这个附件
+        MessageRepository::new(&conn, private.clone())
+            .delete(1)
+            .unwrap();
+        assert_eq!(repo.reference_count(attachment.id).unwrap(), 1);
+        assert!(repo.read(attachment.id).unwrap().is_some());
+
+        // 最后一个引用去掉之后附件被删除
+        repo.remove_assistant_reference(3, None).unwrap();
+        assert!(repo.read(attachment.id).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_list_by_conversation_and_type() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO conversation (id, name) VALUES (1, 'a'), (2, 'b');
+             INSERT INTO message (id, conversation_id, message_type, content, created_time) VALUES
+                 (1, 1, 'user', 'a', '2024-01-01 00:00:00'),
+                 (2, 1, 'user', 'b', '2024-01-02 00:00:00'),
+                 (3, 2, 'user', 'c', '2024-01-03 00:00:00');",
+        )
+        .unwrap();
+        let repo = MessageAttachmentRepository::new(conn, PrivateConversationState::default());
+        let create = |message_id: i64, attachment_type: AttachmentType, name: &str| {
+            repo.create(&MessageAttachment {
+                id: 0,
+                message_id: Some(message_id),
+                attachment_type,
+                attachment_url: Some(name.to_string()),
+                attachment_content: Some(name.to_string()),
+                attachment_hash: Some(name.to_string()),
+                use_vector: false,
+                token_count: None,
+                ocr_text: None,
+                encoding: None,
+            })
+            .unwrap()
+        };
+        let image = create(1, AttachmentType::Image, "a.png");
+        let text = create(2, AttachmentType::Text, "b.txt");
+        create(3, AttachmentType::Text, "c.txt");
+
+        // 只返回这个对话的附件，新消息的附件在前
+        let all = repo.list_by_conversation_and_type(1, None).unwrap();
+        assert_eq!(
+            all.iter()
+                .map(|(attachment, _)| (attachment.id, attachment.message_id))
+                .collect::<Vec<_>>(),
+            vec![(text.id, Some(2)), (image.id, Some(1))]
+        );
+        let images = repo
+            .list_by_conversation_and_type(1, Some(AttachmentType::Image))
+            .unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].0.id, image.id);
+    }
+
+    #[test]
+    fn test_external_attachment_content() {
+        let dir = std::env::temp_dir().join(format!("aipp_conversation_db_{}", std::process::id()));
+        let conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO conversation (id, name) VALUES (1, 'a');
+             INSERT INTO message (id, conversation_id, message_type, content) VALUES (1, 1, 'user', 'a');",
+        )
+        .unwrap();
+        let content = format!("data:image/png;base64,{}", "A".repeat(EXTERNAL_THRESHOLD));
+        // 升级前保存在数据库中的大附件
+        conn.execute(
+            "INSERT INTO message_attachment (id, attachment_type, attachment_content) VALUES (1, 1, ?1)",
+            [&content],
+        )
+        .unwrap();
+        let repo = MessageAttachmentRepository::new(conn, PrivateConversationState::default())
+            .with_store(AttachmentStore::new(Some(dir.clone())));
+        let attachment = repo
+            .create(&MessageAttachment {
+                id: 0,
+                message_id: Some(1),
+                attachment_type: AttachmentType::Image,
+                attachment_url: Some("cat.png".to_string()),
+                attachment_content: Some(content.clone()),
+                attachment_hash: Some("hash".to_string()),
+                use_vector: false,
+                token_count: None,
+                ocr_text: None,
+                encoding: None,
+            })
+            .unwrap();
+        assert_eq!(repo.externalize_large_contents().unwrap(), 1);
+        let stored: Vec<String> = repo
+            .conn()
+            .prepare("SELECT attachment_content FROM message_attachment ORDER BY id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        // 相同的内容引用同一个文件
+        assert!(stored[0].starts_with("aipp:file:"));
+        assert_eq!(stored[0], stored[1]);
+
+        assert_eq!(
+            repo.read(1).unwrap().unwrap().attachment_content,
+            Some(content.clone())
+        );
+        assert_eq!(
+            repo.list_by_message_id(1, 1).unwrap()[0].attachment_content,
+            Some(content.clone())
+        );
+        assert_eq!(
+            repo.read_by_attachment_hash("hash").unwrap().unwrap().id,
+            attachment.id
+        );
+        let message_repo = MessageRepository::new(repo.conn, PrivateConversationState::default())
+            .with_attachment_store(AttachmentStore::new(Some(dir.clone())));
+        let (_, attachment) = message_repo.list_by_conversation_id(1).unwrap().remove(0);
+        assert_eq!(attachment.unwrap().attachment_content, Some(content));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_activity_calendar() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO message (conversation_id, message_type, content, token_count, created_time) VALUES
+                 (1, 'user', 'q1', 10, '2026-03-10 12:00:00'),
+                 (1, 'assistant', 'a1', 200, '2026-03-10 12:00:05'),
+                 (2, 'user', 'q2', 15, '2026-03-20 12:00:00'),
+                 (2, 'draft', 'd2', 0, '2026-03-20 12:01:00'),
+                 (3, 'user', 'last year', 5, '2025-06-01 12:00:00');
+             INSERT INTO message (conversation_id, message_type, content, token_count, created_time, deleted_time) VALUES
+                 (2, 'assistant', 'deleted', 100, '2026-03-20 12:02:00', '2026-03-20 12:03:00');",
+        )
+        .unwrap();
+        let repo = MessageRepository::new(conn, PrivateConversationState::default());
+        let days = repo.activity_calendar(2026).unwrap();
+        assert_eq!(days.len(), 2);
+        assert!(days[0].date.starts_with("2026-03-"));
+        assert_eq!((days[0].message_count, days[0].token_count), (2, 210));
+        // 草稿和删除的消息不算
+        assert_eq!((days[1].message_count, days[1].token_count), (1, 15));
+        assert_eq!(repo.activity_calendar(2024).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_mark_incomplete_interrupted() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO message (conversation_id, message_type, content, created_time) VALUES
+                 (1, 'user', 'q1', '2026-01-01 00:00:00'),
+                 (1, 'assistant', '', '2026-01-01 00:00:01'),
+                 (2, 'assistant', 'partial', '2026-01-01 00:00:02');
+             INSERT INTO message (conversation_id, message_type, content, created_time, finish_time) VALUES
+                 (2, 'assistant', 'done', '2026-01-01 00:00:03', '2026-01-01 00:00:04');",
+        )
+        .unwrap();
+        let repo = MessageRepository::new(conn, PrivateConversationState::default());
+        let metadata = "{\"finish_reason\":\"interrupted\"}";
+        let messages = repo.mark_incomplete_interrupted(metadata).unwrap();
+        assert_eq!(
+            messages
+                .iter()
+                .map(|m| (m.message_id, m.has_content))
+                .collect::<Vec<_>>(),
+            vec![(2, false), (3, true)]
+        );
+        let message = repo.read(3).unwrap().unwrap();
+        assert!(message.finish_time.is_some());
+        assert_eq!(message.generation_metadata.as_deref(), Some(metadata));
+        // 已经标记过的消息不会重复返回
+        assert_eq!(repo.mark_incomplete_interrupted(metadata).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_backfill_finish_time() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO message (conversation_id, message_type, content, created_time) VALUES
+                 (1, 'user', 'q1', '2025-01-01 00:00:00'),
+                 (1, 'assistant', 'a1', '2025-01-01 00:00:01');",
+        )
+        .unwrap();
+        let repo = MessageRepository::new(conn, PrivateConversationState::default());
+        assert_eq!(repo.backfill_finish_time().unwrap(), 1);
+        let message = repo.read(2).unwrap().unwrap();
+        assert_eq!(message.finish_time, Some(message.created_time));
+        // 补过结束时间的历史回答不会被当成中断的回答
+        let metadata = "{\"finish_reason\":\"interrupted\"}";
+        assert_eq!(repo.mark_incomplete_interrupted(metadata).unwrap(), vec![]);
+        assert_eq!(repo.read(1).unwrap().unwrap().finish_time, None);
+    }
+
+    #[test]
+    fn test_list_recent() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO conversation (id, name, assistant_id, created_time) VALUES
+                 (1, 'a', 1, '2026-01-01 00:00:00'),
+                 (2, 'b', 2, '2026-01-02 00:00:00'),
+                 (3, 'c', 1, '2026-01-03 00:00:00');
+             INSERT INTO message (conversation_id, message_type, content, created_time) VALUES
+                 (1, 'user', 'q1', '2026-01-04 00:00:00'),
+                 (1, 'assistant', 'a1', '2026-01-04 00:00:01'),
+                 (2, 'user', 'q2', '2026-01-02 00:00:01'),
+                 (2, 'draft', 'd2', '2026-01-06 00:00:00');
+             INSERT INTO message (conversation_id, message_type, content, created_time, deleted_time) VALUES
+                 (2, 'assistant', 'deleted', '2026-01-05 00:00:00', '2026-01-05 00:00:01');",
+        )
+        .unwrap();
+        let repo = ConversationRepository::new(conn);
+        let recent = repo.list_recent(10).unwrap();
+        // 对话 1 有最新的消息排在最前，对话 3 没有消息按创建时间排序，删除的消息和草稿不算
+        assert_eq!(
+            recent.iter().map(|c| c.id).collect::<Vec<_>>(),
+            vec![1, 3, 2]
+        );
+        assert_eq!(recent[0].last_message.as_deref(), Some("a1"));
+        assert_eq!(recent[0].last_message_type.as_deref(), Some("assistant"));
+        assert!(recent[0].unread);
+        assert_eq!(recent[1].last_message, None);
+        assert!(!recent[1].unread);
+        assert_eq!(recent[2].last_message.as_deref(), Some("q2"));
+        assert!(!recent[2].unread);
+
+        repo.update_last_read_time(1).unwrap();
+        assert!(!repo.list_recent(1).unwrap()[0].unread);
+        assert_eq!(repo.list_recent(2).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_list_untitled_ids() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO conversation (id, name, created_time, is_private) VALUES
+                 (1, '新对话', '2026-01-01 00:00:00', 0),
+                 (2, ' ', '2026-01-02 00:00:00', 0),
+                 (3, '旅行计划', '2026-01-03 00:00:00', 0),
+                 (4, '新对话', '2026-01-04 00:00:00', 1),
+                 (5, '新对话', '2026-01-05 00:00:00', 0);
+             INSERT INTO message (conversation_id, message_type, content) VALUES
+                 (1, 'user', 'q1'), (2, 'user', 'q2'), (3, 'user', 'q3'), (4, 'user', 'q4');",
+        )
+        .unwrap();
+        let repo = ConversationRepository::new(conn);
+        // 已有名称、私密和没有提问的对话不需要补全
+        assert_eq!(repo.list_untitled_ids("新对话").unwrap(), vec![2, 1]);
+
+        repo.update_name_and_summary(1, "问候", Some("用户打招呼"))
+            .unwrap();
+        let conversation = repo.read(1).unwrap().unwrap();
+        assert_eq!(conversation.name, "问候");
+        assert_eq!(conversation.summary.as_deref(), Some("用户打招呼"));
+        assert_eq!(repo.list_untitled_ids("新对话").unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn test_retention_queries() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO conversation (id, name, assistant_id, created_time) VALUES
+                 (1, '旧对话', 1, '2026-01-01 00:00:00'),
+                 (2, '最近有删除的消息', 1, '2026-01-01 00:00:00'),
+                 (3, '最近活跃', 1, '2026-01-01 00:00:00'),
+                 (4, '其他助手', 2, '2026-01-01 00:00:00');
+             INSERT INTO message (conversation_id, message_type, content, created_time, deleted_time) VALUES
+                 (1, 'user', 'q1', '2026-01-02 00:00:00', NULL),
+                 (2, 'user', 'q2', '2026-01-02 00:00:00', NULL),
+                 (2, 'user', 'q3', '2026-03-01 00:00:00', '2026-03-02 00:00:00'),
+                 (3, 'user', 'q4', '2026-03-01 00:00:00', NULL);",
+        )
+        .unwrap();
+        let repo = ConversationRepository::new(conn);
+        let before = "2026-02-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        // 已删除的消息不算作活跃
+        assert_eq!(
+            repo.list_ids_inactive_before(1, before).unwrap(),
+            vec![1, 2]
+        );
+
+        assert!(repo.list_incognito_ids().unwrap().is_empty());
+        repo.set_incognito(3).unwrap();
+        assert_eq!(repo.list_incognito_ids().unwrap(), vec![3]);
+    }
+
+    #[test]
+    fn test_record_served_model() {
+        // 没有指定模型时使用提供商的默认模型
+        let mut metadata = GenerationMetadata {
+            model_code: Some(String::new()),
+            ..Default::default()
+        };
+        metadata.record_served_model("gpt-4o");
+        assert_eq!(metadata.fallback_from, None);
+        metadata.record_served_model("gpt-4o-mini");
+        metadata.record_served_model("gpt-3.5-turbo");
+        assert_eq!(metadata.fallback_from.as_deref(), Some("gpt-4o"));
+        assert_eq!(metadata.model_code.as_deref(), Some("gpt-3.5-turbo"));
+    }
+
+    #[test]
+    fn test_response_style() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        let repo = ResponseStyleRepository::new(conn);
+        let style = ResponseStyle {
+            format: Some("bullet".to_string()),
+            max_length: Some(200),
+            ..Default::default()
+        };
+        let instruction = style.instruction().unwrap();
+        assert!(instruction.contains("要点列表"));
+        assert!(instruction.contains("200"));
+
+        repo.save(1, &style).unwrap();
+        assert_eq!(repo.get(1).unwrap(), Some(style));
+        // 清空所有偏好后删除记录
+        repo.save(1, &ResponseStyle::default()).unwrap();
+        assert_eq!(repo.get(1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_draft_repository() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        let repo = DraftRepository::new(conn, PrivateConversationState::default());
+
+        // 新对话按助手保存，已有对话按对话保存，互不影响
+        repo.save(None, Some(1), "新对话的草稿").unwrap();
+        repo.save(Some(5), Some(1), "对话 5 的草稿").unwrap();
+        assert_eq!(
+            repo.get(None, Some(1)).unwrap().as_deref(),
+            Some("新对话的草稿")
+        );
+        assert_eq!(repo.get(None, Some(2)).unwrap(), None);
+        assert_eq!(
+            repo.get(Some(5), None).unwrap().as_deref(),
+            Some("对话 5 的草稿")
+        );
+
+        repo.save(Some(5), None, "修改后的草稿").unwrap();
+        assert_eq!(
+            repo.get(Some(5), None).unwrap().as_deref(),
+            Some("修改后的草稿")
+        );
+        // 内容为空时删除草稿
+        repo.save(Some(5), None, "  \n").unwrap();
+        assert_eq!(repo.get(Some(5), None).unwrap(), None);
+        repo.delete(&draft_key(None, Some(1))).unwrap();
+        assert_eq!(repo.get(None, Some(1)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_glossary_and_translation() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        let glossary_repo = GlossaryRepository::new(conn);
+        let term = glossary_repo
+            .save(&GlossaryTerm {
+                id: 0,
+                source_term: " crate ".to_string(),
+                target_term: "包".to_string(),
+                target_lang: Some("简体中文".to_string()),
+            })
+            .unwrap();
+        assert_eq!(term.source_term, "crate");
+        glossary_repo
+            .save(&GlossaryTerm {
+                id: 0,
+                source_term: "Rust".to_string(),
+                target_term: "Rust".to_string(),
+                target_lang: Some(" ".to_string()),
+            })
+            .unwrap();
+        // 没有指定语言的术语在所有语言中都使用
+        assert_eq!(glossary_repo.list(Some("简体中文")).unwrap().len(), 2);
+        assert_eq!(glossary_repo.list(Some("日本語")).unwrap().len(), 1);
+        assert_eq!(glossary_repo.list(None).unwrap().len(), 2);
+        glossary_repo.delete(term.id).unwrap();
+        assert_eq!(glossary_repo.list(None).unwrap().len(), 1);
+
+        let repo =
+            TranslationRepository::new(glossary_repo.conn, PrivateConversationState::default());
+        let mut translation = MessageTranslation {
+            message_id: 1,
+            target_lang: "English".to_string(),
+            content: "Hello".to_string(),
+            source_hash: "a".to_string(),
+            created_time: Utc::now(),
+        };
+        repo.save(1, &translation).unwrap();
+        translation.content = "Hi".to_string();
+        translation.source_hash = "b".to_string();
+        repo.save(1, &translation).unwrap();
+        assert_eq!(repo.get(1, 1, "English").unwrap(), Some(translation));
+        assert_eq!(repo.get(1, 1, "日本語").unwrap(), None);
+    }
+
+    #[test]
+    fn test_translation_private_and_deleted_with_message() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO message (id, conversation_id, message_type, content, created_time) VALUES
+                 (1, 1, 'assistant', '你好', '2026-01-01 00:00:00');",
+        )
+        .unwrap();
+        let private = PrivateConversationState::default();
+        private.set_incognito(1).unwrap();
+        let repo = TranslationRepository::new(&conn, private.clone());
+        let translation = MessageTranslation {
+            message_id: 1,
+            target_lang: "English".to_string(),
+            content: "Hello".to_string(),
+            source_hash: "hash".to_string(),
+            created_time: Utc::now(),
+        };
+        repo.save(1, &translation).unwrap();
+        // 无痕对话中的原文哈希和译文一样加密保存
+        let (content, source_hash): (String, String) = conn
+            .query_row(
+                "SELECT content, source_hash FROM message_translation",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_ne!(content, "Hello");
+        assert_ne!(source_hash, "hash");
+        assert_eq!(repo.get(1, 1, "English").unwrap(), Some(translation));
+
+        // 彻底删除消息时译文一起删除
+        MessageRepository::new(&conn, private).delete(1).unwrap();
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM message_translation", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn test_quote_repository() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        let private = PrivateConversationState::default();
+        let repo = QuoteRepository::new(conn, private.clone());
+        let quote = MessageQuote {
+            message_id: 10,
+            source_conversation_id: 1,
+            source_conversation_name: "Rust 学习".to_string(),
+            source_message_id: 3,
+            source_message_type: "assistant".to_string(),
+            source_created_time: Utc::now(),
+        };
+        repo.save(2, &quote).unwrap();
+        let quotes = repo.list_by_conversation_id(2).unwrap();
+        assert_eq!(quotes.get(&10), Some(&quote));
+        assert!(repo.list_by_conversation_id(1).unwrap().is_empty());
+
+        // 无痕对话中的来源名称加密保存
+        private.set_incognito(3).unwrap();
+        repo.save(
+            3,
+            &MessageQuote {
+                message_id: 11,
+                ..quote.clone()
+            },
+        )
+        .unwrap();
+        let stored: String = repo
+            .conn()
+            .query_row(
+                "SELECT source_conversation_name FROM message_quote WHERE message_id = 11",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(crate::crypto::is_encrypted(&stored));
+        assert_eq!(
+            repo.list_by_conversation_id(3).unwrap()[&11].source_conversation_name,
+            "Rust 学习"
+        );
+
+        // 删除引用消息时一起删除来源
+        repo.conn()
+            .execute(
+                "INSERT INTO message (id, conversation_id, message_type, content) VALUES (10, 2, 'quote', 'q')",
+                [],
+            )
+            .unwrap();
+        let message_repo = MessageRepository::new(repo.conn, private);
+        message_repo.delete(10).unwrap();
+        let remaining: i64 = message_repo
+            .conn()
+            .query_row(
+                "SELECT COUNT(*) FROM message_quote WHERE message_id = 10",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(remaining, 0);
+    }
+
+    #[test]
+    fn test_soft_delete_and_operations() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO conversation (id, name) VALUES (1, 'a');
+             INSERT INTO message (id, conversation_id, message_type, content) VALUES (1, 1, 'user', 'q');
+             INSERT INTO message (id, conversation_id, message_type, content, parent_id) VALUES (2, 1, 'response', 'a', NULL), (3, 1, 'response', 'b', 2);
+             INSERT INTO message (id, conversation_id, message_type, content) VALUES (4, 1, 'draft', 'd');",
+        )
+        .unwrap();
+        let private = PrivateConversationState::default();
+        let message_repo = MessageRepository::new(conn, private.clone());
+        // 删除回答时重新生成的版本一起删除，草稿不在列表中，只能按 id 读取
+        assert_eq!(message_repo.soft_delete(2).unwrap(), vec![2, 3]);
+        assert_eq!(message_repo.list_by_conversation_id(1).unwrap().len(), 1);
+        assert!(message_repo.read(4).unwrap().is_some());
+        assert_eq!(
+            message_repo
+                .list_deleted_by_conversation_id(1)
+                .unwrap()
+                .len(),
+            2
+        );
+
+        let operation_repo = MessageOperationRepository::new(message_repo.conn, private.clone());
+        let delete = operation_repo.add(1, "delete", &[2, 3], None).unwrap();
+        let edit = operation_repo.add(1, "edit", &[1], Some("q")).unwrap();
+        assert_eq!(operation_repo.last_done(1).unwrap(), Some(edit.clone()));
+        operation_repo
+            .update(&MessageOperation {
+                undone: true,
+                ..edit.clone()
+            })
+            .unwrap();
+        assert_eq!(operation_repo.last_done(1).unwrap(), Some(delete));
+        assert_eq!(operation_repo.first_undone(1).unwrap().unwrap().id, edit.id);
+        // 新的操作清空重做栈
+        operation_repo.add(1, "edit", &[1], Some("q2")).unwrap();
+        assert_eq!(operation_repo.first_undone(1).unwrap(), None);
+
+        let message_repo = MessageRepository::new(operation_repo.conn, private);
+        message_repo.update_deleted(&[3], false).unwrap();
+        assert_eq!(message_repo.list_by_conversation_id(1).unwrap().len(), 2);
+        let purged = message_repo
+            .purge_deleted_before(Utc::now() + chrono::Duration::days(1))
+            .unwrap();
+        assert_eq!(purged, 1);
+        assert!(message_repo.read(2).unwrap().is_none());
+        assert!(message_repo.read(3).unwrap().is_some());
     }
 }
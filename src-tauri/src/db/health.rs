@@ -0,0 +1,529 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local, Utc};
+use rusqlite::Connection;
+use serde::Serialize;
+
+use super::{get_attachment_dir, get_db_path};
+use crate::AppHandle;
+
+// (数据库文件名, 从最早的版本起就存在的表)，这些表缺失说明数据库被破坏
+const DATABASES: &[(&str, &[&str])] = &[
+    ("system.db", &["system_config", "feature_config"]),
+    (
+        "llm.db",
+        &["llm_provider", "llm_model", "llm_provider_config"],
+    ),
+    (
+        "assistant.db",
+        &[
+            "assistant",
+            "assistant_model",
+            "assistant_prompt",
+            "assistant_model_config",
+        ],
+    ),
+    (
+        "conversation.db",
+        &["conversation", "message", "message_attachment"],
+    ),
+    ("plugin.db", &["Plugins"]),
+    (
+        "knowledge.db",
+        &["context_folder", "context_file", "context_chunk"],
+    ),
+];
+// 备份保存在数据目录的 db/backup 中，文件名为数据库文件名加上备份时间
+const BACKUP_DIR: &str = "backup";
+// 每个数据库轮换保留的备份数量，恢复时从最新的开始找第一个完好的备份
+const MAX_BACKUPS: usize = 3;
+// 附件目录中的文件按内容寻址，写入后不会修改，备份时只复制新增的文件
+const ATTACHMENT_BACKUP_DIR: &str = "attachments";
+const BACKUP_TIME_FORMAT: &str = "%Y%m%d%H%M%S%3f";
+// integrity_check 最多返回的问题条数
+const MAX_INTEGRITY_ERRORS: usize = 20;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DatabaseStatus {
+    Ok,
+    // 首次启动，还没有数据库文件
+    Created,
+    // 发现问题并且已经自动修复
+    Repaired,
+    // 无法修复，已经从备份恢复
+    Restored,
+    // 无法修复也没有可用的备份
+    Failed,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct DatabaseDiagnostic {
+    pub name: String,
+    pub status: DatabaseStatus,
+    // 检查中发现的问题
+    pub problems: Vec<String>,
+    // 自动执行的修复操作
+    pub actions: Vec<String>,
+}
+
+impl DatabaseDiagnostic {
+    fn new(name: &str, status: DatabaseStatus) -> Self {
+        DatabaseDiagnostic {
+            name: name.to_string(),
+            status,
+            problems: vec![],
+            actions: vec![],
+        }
+    }
+}
+
+/// 启动时数据库检查的结果
+#[derive(Serialize, Clone, Debug)]
+pub struct StartupDiagnostics {
+    // 所有数据库都可以正常使用，包括已经修复和恢复的
+    pub healthy: bool,
+    pub checked_time: DateTime<Utc>,
+    pub databases: Vec<DatabaseDiagnostic>,
+}
+
+impl StartupDiagnostics {
+    fn new(databases: Vec<DatabaseDiagnostic>) -> Self {
+        let mut diagnostics = StartupDiagnostics {
+            healthy: true,
+            checked_time: Utc::now(),
+            databases,
+        };
+        diagnostics.update_healthy();
+        diagnostics
+    }
+
+    /// 检查之后建表或升级失败时记录到对应的数据库
+    pub fn record_failure(&mut self, name: &str, problem: String) {
+        println!("database {} failed: {}", name, problem);
+        match self.databases.iter_mut().find(|d| d.name == name) {
+            Some(diagnostic) => {
+                diagnostic.status = DatabaseStatus::Failed;
+                diagnostic.problems.push(problem);
+            }
+            None => {
+                let mut diagnostic = DatabaseDiagnostic::new(name, DatabaseStatus::Failed);
+                diagnostic.problems.push(problem);
+                self.databases.push(diagnostic);
+            }
+        }
+        self.update_healthy();
+    }
+
+    fn update_healthy(&mut self) {
+        self.healthy = self
+            .databases
+            .iter()
+            .all(|d| d.status != DatabaseStatus::Failed);
+    }
+}
+
+/// 启动检查的结果，通过 get_startup_diagnostics 命令返回给界面
+pub struct StartupDiagnosticsState(pub StartupDiagnostics);
+
+/// 打开各个数据库之前调用：检查完整性和必需的表，尝试自动修复，修复失败时从最近的备份恢复
+pub fn run_startup_check(app_handle: &AppHandle) -> StartupDiagnostics {
+    let databases = DATABASES
+        .iter()
+        .map(|(name, tables)| match get_db_path(app_handle, name) {
+            Ok(path) => {
+                let diagnostic = check_database(&path, name, tables);
+                // 对话数据库恢复到备份时的状态，备份之后被清理掉的附件文件也要找回来
+                if diagnostic.status == DatabaseStatus::Restored {
+                    if let Some(dir) = get_attachment_dir(app_handle) {
+                        if let Err(e) = restore_attachments(&path, &dir) {
+                            println!("restore attachments error: {:?}", e);
+                        }
+                    }
+                }
+                diagnostic
+            }
+            Err(e) => {
+                let mut diagnostic = DatabaseDiagnostic::new(name, DatabaseStatus::Failed);
+                diagnostic.problems.push(format!("无法创建数据目录: {}", e));
+                diagnostic
+            }
+        })
+        .collect();
+    let diagnostics = StartupDiagnostics::new(databases);
+    println!("startup diagnostics: {:?}", diagnostics);
+    diagnostics
+}
+
+/// 建表和升级完成后在后台为检查通过的数据库添加备份，并同步附件目录中新增的文件。
+/// 本次启动修复、恢复或者失败的数据库不备份，避免修复后的数据挤掉之前完好的备份
+pub fn backup_databases(app_handle: &AppHandle, diagnostics: &StartupDiagnostics) {
+    let paths: Vec<PathBuf> = diagnostics
+        .databases
+        .iter()
+        .filter(|d| matches!(d.status, DatabaseStatus::Ok | DatabaseStatus::Created))
+        .filter_map(|d| get_db_path(app_handle, &d.name).ok())
+        .collect();
+    let attachment_dir = get_attachment_dir(app_handle);
+    tauri::async_runtime::spawn_blocking(move || {
+        for path in &paths {
+            if let Err(e) = backup_database(path) {
+                println!("backup database {:?} error: {:?}", path, e);
+            }
+        }
+        let conversation_db = paths.iter().find(|path| {
+            path.file_name()
+                .is_some_and(|name| name == "conversation.db")
+        });
+        if let (Some(path), Some(dir)) = (conversation_db, attachment_dir) {
+            if let Err(e) = backup_attachments(path, &dir) {
+                println!("backup attachments error: {:?}", e);
+            }
+        }
+    });
+}
+
+pub fn check_database(path: &Path, name: &str, expected_tables: &[&str]) -> DatabaseDiagnostic {
+    if !path.exists() {
+        return DatabaseDiagnostic::new(name, DatabaseStatus::Created);
+    }
+    let mut diagnostic = DatabaseDiagnostic::new(name, DatabaseStatus::Ok);
+    let integrity = match integrity_errors(path) {
+        Ok(errors) => errors,
+        Err(e) => vec![format!("无法读取数据库: {}", e)],
+    };
+    let missing = missing_tables(path, expected_tables).unwrap_or_default();
+    if integrity.is_empty() && missing.is_empty() {
+        return diagnostic;
+    }
+    diagnostic.problems.extend(integrity.iter().cloned());
+    if !missing.is_empty() {
+        diagnostic
+            .problems
+            .push(format!("缺少数据表: {}", missing.join(", ")));
+        // 重新创建的表是空的，有完好的备份时优先从备份恢复，找不到时再重新建表
+        if find_backup(path, expected_tables).is_some() {
+            restore_from_backup(path, expected_tables, &mut diagnostic);
+            if diagnostic.status == DatabaseStatus::Restored {
+                return diagnostic;
+            }
+        }
+    }
+
+    // 索引损坏可以通过重建索引修复，缺少的表会在随后建表时重新创建
+    if !integrity.is_empty() {
+        match Connection::open(path).and_then(|conn| conn.execute_batch("REINDEX;")) {
+            Ok(_) => diagnostic.actions.push("重建索引".to_string()),
+            Err(e) => println!("reindex {} error: {:?}", name, e),
+        }
+    }
+    if integrity_errors(path).is_ok_and(|errors| errors.is_empty()) {
+        if !missing.is_empty() {
+            diagnostic.actions.push("重新创建缺少的数据表".to_string());
+        }
+        diagnostic.status = DatabaseStatus::Repaired;
+        return diagnostic;
+    }
+
+    restore_from_backup(path, expected_tables, &mut diagnostic);
+    diagnostic
+}
+
+fn integrity_errors(path: &Path) -> rusqlite::Result<Vec<String>> {
+    let conn = Connection::open(path)?;
+    let mut stmt = conn.prepare(&format!("PRAGMA integrity_check({})", MAX_INTEGRITY_ERRORS))?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    let errors = rows.collect::<rusqlite::Result<Vec<String>>>()?;
+    Ok(errors.into_iter().filter(|row| row != "ok").collect())
+}
+
+fn missing_tables(path: &Path, expected_tables: &[&str]) -> rusqlite::Result<Vec<String>> {
+    let conn = Connection::open(path)?;
+    let mut stmt = conn.prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?")?;
+    let mut missing = vec![];
+    for table in expected_tables {
+        if !stmt.exists([table])? {
+            missing.push(table.to_string());
+        }
+    }
+    Ok(missing)
+}
+
+fn backup_dir(path: &Path) -> Option<PathBuf> {
+    Some(path.parent()?.join(BACKUP_DIR))
+}
+
+/// 数据库的所有备份，按备份时间从新到旧排列；
+/// 之前版本只保留一份、直接以数据库文件名命名的备份排在最后
+fn list_backups(path: &Path) -> Vec<PathBuf> {
+    let (Some(dir), Some(file_name)) = (backup_dir(path), path.file_name()) else {
+        return vec![];
+    };
+    let file_name = file_name.to_string_lossy().to_string();
+    let prefix = format!("{}.", file_name);
+    let mut backups: Vec<(String, PathBuf)> = fs::read_dir(&dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let time = name.strip_prefix(&prefix)?;
+            (!time.is_empty() && time.bytes().all(|b| b.is_ascii_digit()))
+                .then(|| (time.to_string(), entry.path()))
+        })
+        .collect();
+    backups.sort_by(|a, b| b.0.cmp(&a.0));
+    let mut backups: Vec<PathBuf> = backups.into_iter().map(|(_, path)| path).collect();
+    let legacy = dir.join(&file_name);
+    if legacy.exists() {
+        backups.push(legacy);
+    }
+    backups
+}
+
+// 最新的一个完整性检查通过、并且包含必需的表的备份
+fn find_backup(path: &Path, expected_tables: &[&str]) -> Option<PathBuf> {
+    list_backups(path).into_iter().find(|backup| {
+        integrity_errors(backup).is_ok_and(|errors| errors.is_empty())
+            && missing_tables(backup, expected_tables).is_ok_and(|missing| missing.is_empty())
+    })
+}
+
+// 用 VACUUM INTO 生成一致的副本，先写到临时文件，完成后再改名，超出数量的旧备份删除
+fn backup_database(path: &Path) -> Result<(), String> {
+    let dir = backup_dir(path).ok_or("无效的数据库路径")?;
+    let file_name = path.file_name().ok_or("无效的数据库路径")?;
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let backup = dir.join(format!(
+        "{}.{}",
+        file_name.to_string_lossy(),
+        Local::now().format(BACKUP_TIME_FORMAT)
+    ));
+    let temp = backup.with_extension("tmp");
+    let _ = fs::remove_file(&temp);
+    let conn = Connection::open(path).map_err(|e| e.to_string())?;
+    conn.execute("VACUUM INTO ?1", [temp.to_string_lossy()])
+        .map_err(|e| e.to_string())?;
+    fs::rename(&temp, &backup).map_err(|e| e.to_string())?;
+    for old in list_backups(path).into_iter().skip(MAX_BACKUPS) {
+        let _ = fs::remove_file(old);
+    }
+    Ok(())
+}
+
+// 把附件目录中备份里还没有的文件复制到备份目录，文件内容由哈希决定，已有的不需要再复制
+fn backup_attachments(path: &Path, attachment_dir: &Path) -> Result<usize, String> {
+    let backup = backup_dir(path)
+        .ok_or("无效的数据库路径")?
+        .join(ATTACHMENT_BACKUP_DIR);
+    copy_missing_files(attachment_dir, &backup).map_err(|e| e.to_string())
+}
+
+// 从备份恢复对话数据库之后，把备份中有而附件目录中缺少的文件复制回去
+fn restore_attachments(path: &Path, attachment_dir: &Path) -> Result<usize, String> {
+    let backup = backup_dir(path)
+        .ok_or("无效的数据库路径")?
+        .join(ATTACHMENT_BACKUP_DIR);
+    copy_missing_files(&backup, attachment_dir).map_err(|e| e.to_string())
+}
+
+// 按 from 中的目录结构复制 to 中不存在的文件，返回复制的文件数量
+fn copy_missing_files(from: &Path, to: &Path) -> std::io::Result<usize> {
+    if !from.exists() {
+        return Ok(0);
+    }
+    let mut copied = 0;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copied += copy_missing_files(&entry.path(), &target)?;
+        } else if !target.exists() && target.extension().map_or(true, |ext| ext != "tmp") {
+            fs::create_dir_all(to)?;
+            // 先复制到临时文件再改名，中途退出时不会留下不完整的文件
+            let temp = target.with_extension("tmp");
+            fs::copy(entry.path(), &temp)?;
+            fs::rename(&temp, &target)?;
+            copied += 1;
+        }
+    }
+    Ok(copied)
+}
+
+fn restore_from_backup(path: &Path, expected_tables: &[&str], diagnostic: &mut DatabaseDiagnostic) {
+    diagnostic.status = DatabaseStatus::Failed;
+    if list_backups(path).is_empty() {
+        diagnostic
+            .problems
+            .push("自动修复失败，并且没有可用的备份".to_string());
+        return;
+    }
+    let Some(backup) = find_backup(path, expected_tables) else {
+        diagnostic
+            .problems
+            .push("自动修复失败，备份也已经损坏".to_string());
+        return;
+    };
+
+    // 损坏的文件改名保留，方便之后手动找回数据
+    let suffix = Local::now().format("%Y%m%d%H%M%S");
+    let corrupted = path.with_extension(format!("db.corrupted-{}", suffix));
+    if let Err(e) = fs::rename(path, &corrupted) {
+        diagnostic
+            .problems
+            .push(format!("无法移走损坏的数据库文件: {}", e));
+        return;
+    }
+    // 残留的回滚日志属于损坏的文件，不能应用到恢复的备份上
+    let journal = PathBuf::from(format!("{}-journal", path.to_string_lossy()));
+    if journal.exists() {
+        let _ = fs::rename(
+            &journal,
+            PathBuf::from(format!("{}-journal", corrupted.to_string_lossy())),
+        );
+    }
+    if let Err(e) = fs::copy(&backup, path) {
+        diagnostic.problems.push(format!("从备份恢复失败: {}", e));
+        return;
+    }
+    let backup_time = fs::metadata(&backup)
+        .and_then(|metadata| metadata.modified())
+        .map(|time| {
+            DateTime::<Local>::from(time)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_default();
+    diagnostic.actions.push(format!(
+        "已从 {} 的备份恢复，损坏的文件保存为 {}",
+        backup_time,
+        corrupted
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    ));
+    diagnostic.status = DatabaseStatus::Restored;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_db(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aipp_health_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("test.db")
+    }
+
+    fn create_db(path: &Path, tables: &[&str]) {
+        let conn = Connection::open(path).unwrap();
+        for table in tables {
+            conn.execute_batch(&format!("CREATE TABLE {} (id INTEGER PRIMARY KEY);", table))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_healthy_and_missing_table() {
+        let path = temp_db("missing");
+        assert_eq!(
+            check_database(&path, "test.db", &["a"]).status,
+            DatabaseStatus::Created
+        );
+        create_db(&path, &["a"]);
+        assert_eq!(
+            check_database(&path, "test.db", &["a"]).status,
+            DatabaseStatus::Ok
+        );
+
+        let diagnostic = check_database(&path, "test.db", &["a", "b"]);
+        assert_eq!(diagnostic.status, DatabaseStatus::Repaired);
+        assert_eq!(diagnostic.problems, vec!["缺少数据表: b".to_string()]);
+    }
+
+    #[test]
+    fn test_missing_table_restored_from_backup() {
+        let path = temp_db("missing_restore");
+        create_db(&path, &["a", "b"]);
+        backup_database(&path).unwrap();
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch("DROP TABLE b;")
+            .unwrap();
+
+        // 有完好的备份时不重新创建空表，而是从备份恢复
+        let diagnostic = check_database(&path, "test.db", &["a", "b"]);
+        assert_eq!(diagnostic.status, DatabaseStatus::Restored);
+        assert!(missing_tables(&path, &["a", "b"]).unwrap().is_empty());
+        assert_eq!(list_backups(&path).len(), 1);
+    }
+
+    #[test]
+    fn test_backup_rotation() {
+        let path = temp_db("rotation");
+        create_db(&path, &["a"]);
+        // 之前版本留下的备份当作最旧的一份
+        fs::create_dir_all(backup_dir(&path).unwrap()).unwrap();
+        fs::copy(&path, backup_dir(&path).unwrap().join("test.db")).unwrap();
+        for _ in 0..MAX_BACKUPS + 1 {
+            backup_database(&path).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        let backups = list_backups(&path);
+        assert_eq!(backups.len(), MAX_BACKUPS);
+        assert!(backups
+            .iter()
+            .all(|backup| backup.file_name().unwrap() != "test.db"));
+
+        // 最新的备份损坏时使用下一个完好的备份
+        fs::write(&backups[0], b"garbage").unwrap();
+        assert_eq!(find_backup(&path, &["a"]), Some(backups[1].clone()));
+    }
+
+    #[test]
+    fn test_copy_missing_attachment_files() {
+        let path = temp_db("attachments");
+        let attachment_dir = path.parent().unwrap().join("attachments");
+        fs::create_dir_all(attachment_dir.join("ab")).unwrap();
+        fs::write(attachment_dir.join("ab").join("abcd"), "content").unwrap();
+        fs::write(attachment_dir.join("ab").join("abef.tmp"), "partial").unwrap();
+        assert_eq!(backup_attachments(&path, &attachment_dir).unwrap(), 1);
+        assert_eq!(backup_attachments(&path, &attachment_dir).unwrap(), 0);
+
+        fs::remove_dir_all(&attachment_dir).unwrap();
+        assert_eq!(restore_attachments(&path, &attachment_dir).unwrap(), 1);
+        assert_eq!(
+            fs::read_to_string(attachment_dir.join("ab").join("abcd")).unwrap(),
+            "content"
+        );
+    }
+
+    #[test]
+    fn test_restore_corrupted_database_from_backup() {
+        let path = temp_db("restore");
+        create_db(&path, &["a"]);
+        backup_database(&path).unwrap();
+
+        fs::write(
+            &path,
+            b"this is not a sqlite database, just some garbage bytes",
+        )
+        .unwrap();
+        let diagnostic = check_database(&path, "test.db", &["a"]);
+        assert_eq!(diagnostic.status, DatabaseStatus::Restored);
+        assert!(missing_tables(&path, &["a"]).unwrap().is_empty());
+
+        // 没有备份时报告失败，不会删除原来的文件
+        fs::remove_dir_all(backup_dir(&path).unwrap()).unwrap();
+        fs::write(
+            &path,
+            b"this is not a sqlite database, just some garbage bytes",
+        )
+        .unwrap();
+        let diagnostic = check_database(&path, "test.db", &["a"]);
+        assert_eq!(diagnostic.status, DatabaseStatus::Failed);
+        assert!(path.exists());
+    }
+}
@@ -0,0 +1,262 @@
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+
+use super::get_db_path;
+use crate::AppHandle;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ContextFolder {
+    pub id: i64,
+    pub path: String,
+    // pending: 等待索引, indexing: 索引中, ready: 已完成, error: 索引失败
+    pub status: String,
+    pub file_count: i64,
+    pub chunk_count: i64,
+    pub error_message: Option<String>,
+    pub last_indexed_time: Option<String>,
+    pub created_time: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ContextChunk {
+    pub id: i64,
+    pub folder_id: i64,
+    pub file_path: String,
+    pub chunk_index: i64,
+    pub content: String,
+    pub embedding: Vec<f32>,
+}
+
+pub struct KnowledgeDatabase {
+    pub conn: Connection,
+}
+
+impl KnowledgeDatabase {
+    pub fn new(app_handle: &AppHandle) -> Result<Self> {
+        let db_path = get_db_path(app_handle, "knowledge.db");
+        let conn = Connection::open(db_path.unwrap())?;
+        Ok(KnowledgeDatabase { conn })
+    }
+
+    pub fn create_tables(&self) -> Result<()> {
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS context_folder (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                path TEXT NOT NULL UNIQUE,
+                status TEXT NOT NULL DEFAULT 'pending',
+                file_count INTEGER NOT NULL DEFAULT 0,
+                chunk_count INTEGER NOT NULL DEFAULT 0,
+                error_message TEXT,
+                last_indexed_time DATETIME,
+                created_time DATETIME DEFAULT CURRENT_TIMESTAMP
+            );",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS context_file (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                folder_id INTEGER NOT NULL,
+                file_path TEXT NOT NULL,
+                modified_time INTEGER NOT NULL,
+                file_size INTEGER NOT NULL,
+                UNIQUE(folder_id, file_path)
+            );",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS context_chunk (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                folder_id INTEGER NOT NULL,
+                file_path TEXT NOT NULL,
+                chunk_index INTEGER NOT NULL,
+                content TEXT NOT NULL,
+                embedding BLOB NOT NULL
+            );",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_context_chunk_folder_file ON context_chunk (folder_id, file_path);",
+            [],
+        )?;
+        Ok(())
+    }
+
+    pub fn add_context_folder(&self, path: &str) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO context_folder (path) VALUES (?)",
+            params![path],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn delete_context_folder(&self, id: i64) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM context_chunk WHERE folder_id = ?", params![id])?;
+        self.conn
+            .execute("DELETE FROM context_file WHERE folder_id = ?", params![id])?;
+        self.conn
+            .execute("DELETE FROM context_folder WHERE id = ?", params![id])?;
+        Ok(())
+    }
+
+    pub fn get_context_folder(&self, id: i64) -> Result<Option<ContextFolder>> {
+        self.conn
+            .query_row(
+                "SELECT id, path, status, file_count, chunk_count, error_message, last_indexed_time, created_time FROM context_folder WHERE id = ?",
+                params![id],
+                |row| {
+                    Ok(ContextFolder {
+                        id: row.get(0)?,
+                        path: row.get(1)?,
+                        status: row.get(2)?,
+                        file_count: row.get(3)?,
+                        chunk_count: row.get(4)?,
+                        error_message: row.get(5)?,
+                        last_indexed_time: row.get(6)?,
+                        created_time: row.get(7)?,
+                    })
+                },
+            )
+            .optional()
+    }
+
+    pub fn get_context_folders(&self) -> Result<Vec<ContextFolder>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, path, status, file_count, chunk_count, error_message, last_indexed_time, created_time FROM context_folder",
+        )?;
+        let folders = stmt
+            .query_map([], |row| {
+                Ok(ContextFolder {
+                    id: row.get(0)?,
+                    path: row.get(1)?,
+                    status: row.get(2)?,
+                    file_count: row.get(3)?,
+                    chunk_count: row.get(4)?,
+                    error_message: row.get(5)?,
+                    last_indexed_time: row.get(6)?,
+                    created_time: row.get(7)?,
+                })
+            })?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(folders)
+    }
+
+    pub fn update_context_folder_status(
+        &self,
+        id: i64,
+        status: &str,
+        error_message: Option<&str>,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE context_folder SET status = ?, error_message = ? WHERE id = ?",
+            params![status, error_message, id],
+        )?;
+        Ok(())
+    }
+
+    // 索引完成后统计文件数和分块数，并记录完成时间
+    pub fn finish_context_folder_index(&self, id: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE context_folder SET status = 'ready', error_message = NULL,
+                file_count = (SELECT COUNT(*) FROM context_file WHERE folder_id = ?1),
+                chunk_count = (SELECT COUNT(*) FROM context_chunk WHERE folder_id = ?1),
+                last_indexed_time = CURRENT_TIMESTAMP
+             WHERE id = ?1",
+            params![id],
+        )?;
+        Ok(())
+    }
+
+    // 返回 文件路径 -> (修改时间, 文件大小)，用于判断文件是否需要重新索引
+    pub fn get_context_files(&self, folder_id: i64) -> Result<Vec<(String, i64, i64)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT file_path, modified_time, file_size FROM context_file WHERE folder_id = ?",
+        )?;
+        let files = stmt
+            .query_map(params![folder_id], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(files)
+    }
+
+    // 替换一个文件的所有分块，在事务中执行避免检索到一半的数据
+    pub fn replace_file_chunks(
+        &mut self,
+        folder_id: i64,
+        file_path: &str,
+        modified_time: i64,
+        file_size: i64,
+        chunks: &Vec<(String, Vec<f32>)>,
+    ) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "DELETE FROM context_chunk WHERE folder_id = ? AND file_path = ?",
+            params![folder_id, file_path],
+        )?;
+        for (index, (content, embedding)) in chunks.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO context_chunk (folder_id, file_path, chunk_index, content, embedding) VALUES (?, ?, ?, ?, ?)",
+                params![
+                    folder_id,
+                    file_path,
+                    index as i64,
+                    content,
+                    embedding_to_bytes(embedding)
+                ],
+            )?;
+        }
+        tx.execute(
+            "INSERT INTO context_file (folder_id, file_path, modified_time, file_size) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(folder_id, file_path) DO UPDATE SET modified_time = ?3, file_size = ?4",
+            params![folder_id, file_path, modified_time, file_size],
+        )?;
+        tx.commit()
+    }
+
+    pub fn delete_file_chunks(&self, folder_id: i64, file_path: &str) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM context_chunk WHERE folder_id = ? AND file_path = ?",
+            params![folder_id, file_path],
+        )?;
+        self.conn.execute(
+            "DELETE FROM context_file WHERE folder_id = ? AND file_path = ?",
+            params![folder_id, file_path],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_chunks_by_folder_ids(&self, folder_ids: &Vec<i64>) -> Result<Vec<ContextChunk>> {
+        let id_list_str: Vec<String> = folder_ids.iter().map(|id| id.to_string()).collect();
+        let query = format!(
+            "SELECT id, folder_id, file_path, chunk_index, content, embedding FROM context_chunk WHERE folder_id IN ({})",
+            id_list_str.join(",")
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let chunks = stmt
+            .query_map([], |row| {
+                let embedding: Vec<u8> = row.get(5)?;
+                Ok(ContextChunk {
+                    id: row.get(0)?,
+                    folder_id: row.get(1)?,
+                    file_path: row.get(2)?,
+                    chunk_index: row.get(3)?,
+                    content: row.get(4)?,
+                    embedding: embedding_from_bytes(&embedding),
+                })
+            })?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(chunks)
+    }
+}
+
+fn embedding_to_bytes(embedding: &Vec<f32>) -> Vec<u8> {
+    embedding.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+fn embedding_from_bytes(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
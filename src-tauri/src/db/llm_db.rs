@@ -1,6 +1,7 @@
 use rusqlite::{params, Connection};
 
 use super::get_db_path;
+use crate::AppHandle;
 
 #[derive(Debug)]
 pub struct LLMProvider {
@@ -32,6 +33,8 @@ pub struct LLMModel {
     pub vision_support: bool,
     pub audio_support: bool,
     pub video_support: bool,
+    // 隐藏的模型不出现在模型选择列表中
+    pub is_enabled: bool,
 }
 
 #[derive(Debug)]
@@ -41,12 +44,30 @@ pub struct ModelDetail {
     pub configs: Vec<LLMProviderConfig>,
 }
 
+// llm_model_from_row 按这个顺序读取
+const LLM_MODEL_COLUMNS: &str = "id, name, llm_provider_id, code, description, vision_support, \
+     audio_support, video_support, is_enabled";
+
+fn llm_model_from_row(row: &rusqlite::Row) -> rusqlite::Result<LLMModel> {
+    Ok(LLMModel {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        llm_provider_id: row.get(2)?,
+        code: row.get(3)?,
+        description: row.get(4)?,
+        vision_support: row.get(5)?,
+        audio_support: row.get(6)?,
+        video_support: row.get(7)?,
+        is_enabled: row.get(8)?,
+    })
+}
+
 pub struct LLMDatabase {
     pub conn: Connection,
 }
 
 impl LLMDatabase {
-    pub fn new(app_handle: &tauri::AppHandle) -> rusqlite::Result<Self> {
+    pub fn new(app_handle: &AppHandle) -> rusqlite::Result<Self> {
         let db_path = get_db_path(app_handle, "llm.db");
         let conn = Connection::open(db_path.unwrap())?;
         Ok(LLMDatabase { conn })
@@ -75,6 +96,7 @@ impl LLMDatabase {
                     vision_support BOOLEAN NOT NULL DEFAULT 0,
                     audio_support BOOLEAN NOT NULL DEFAULT 0,
                     video_support BOOLEAN NOT NULL DEFAULT 0,
+                    is_enabled BOOLEAN NOT NULL DEFAULT 1,
                     created_time DATETIME DEFAULT CURRENT_TIMESTAMP,
                     FOREIGN KEY (llm_provider_id) REFERENCES llm_provider(id)
                 );",
@@ -266,23 +288,12 @@ impl LLMDatabase {
         Ok(result)
     }
 
-    pub fn get_llm_models(
-        &self,
-        provider_id: String,
-    ) -> rusqlite::Result<Vec<(i64, String, i64, String, String, bool, bool, bool)>> {
-        let mut stmt = self.conn.prepare("SELECT id, name, llm_provider_id, code, description, vision_support, audio_support, video_support FROM llm_model WHERE llm_provider_id = ?")?;
-        let llm_models = stmt.query_map([provider_id], |row| {
-            Ok((
-                row.get(0)?,
-                row.get(1)?,
-                row.get(2)?,
-                row.get(3)?,
-                row.get(4)?,
-                row.get(5)?,
-                row.get(6)?,
-                row.get(7)?,
-            ))
-        })?;
+    pub fn get_llm_models(&self, provider_id: String) -> rusqlite::Result<Vec<LLMModel>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM llm_model WHERE llm_provider_id = ?",
+            LLM_MODEL_COLUMNS
+        ))?;
+        let llm_models = stmt.query_map([provider_id], llm_model_from_row)?;
 
         let mut result = Vec::new();
         for llm_model in llm_models {
@@ -296,20 +307,12 @@ impl LLMDatabase {
         provider_id: &i64,
         model_code: &String,
     ) -> rusqlite::Result<ModelDetail> {
-        let mut stmt = self.conn.prepare("SELECT id, name, llm_provider_id, code, description, vision_support, audio_support, video_support FROM llm_model WHERE llm_provider_id = ? AND code = ?")?;
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM llm_model WHERE llm_provider_id = ? AND code = ?",
+            LLM_MODEL_COLUMNS
+        ))?;
         let model = stmt
-            .query_map([&provider_id.to_string(), model_code], |row| {
-                Ok(LLMModel {
-                    id: row.get(0)?,
-                    name: row.get(1)?,
-                    llm_provider_id: row.get(2)?,
-                    code: row.get(3)?,
-                    description: row.get(4)?,
-                    vision_support: row.get(5)?,
-                    audio_support: row.get(6)?,
-                    video_support: row.get(7)?,
-                })
-            })?
+            .query_map([&provider_id.to_string(), model_code], llm_model_from_row)?
             .next()
             .transpose()?;
 
@@ -330,20 +333,12 @@ impl LLMDatabase {
     }
 
     pub fn get_llm_model_detail_by_id(&self, id: &i64) -> rusqlite::Result<ModelDetail> {
-        let mut stmt = self.conn.prepare("SELECT id, name, llm_provider_id, code, description, vision_support, audio_support, video_support FROM llm_model WHERE id = ?")?;
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {} FROM llm_model WHERE id = ?",
+            LLM_MODEL_COLUMNS
+        ))?;
         let model = stmt
-            .query_map([id], |row| {
-                Ok(LLMModel {
-                    id: row.get(0)?,
-                    name: row.get(1)?,
-                    llm_provider_id: row.get(2)?,
-                    code: row.get(3)?,
-                    description: row.get(4)?,
-                    vision_support: row.get(5)?,
-                    audio_support: row.get(6)?,
-                    video_support: row.get(7)?,
-                })
-            })?
+            .query_map([id], llm_model_from_row)?
             .next()
             .transpose()?;
 
@@ -363,6 +358,38 @@ impl LLMDatabase {
         })
     }
 
+    /// 批量启用或隐藏模型，隐藏的模型不会出现在模型选择列表中
+    pub fn set_llm_models_enabled(&self, ids: &[i64], enabled: bool) -> rusqlite::Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let placeholders = vec!["?"; ids.len()].join(", ");
+        let sql = format!(
+            "UPDATE llm_model SET is_enabled = ? WHERE id IN ({})",
+            placeholders
+        );
+        let mut values: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(enabled)];
+        for id in ids {
+            values.push(Box::new(*id));
+        }
+        self.conn
+            .execute(&sql, rusqlite::params_from_iter(values.iter()))?;
+        Ok(())
+    }
+
+    pub fn update_llm_model_enabled_by_code(
+        &self,
+        provider_id: i64,
+        code: &str,
+        enabled: bool,
+    ) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "UPDATE llm_model SET is_enabled = ? WHERE llm_provider_id = ? AND code = ?",
+            params![enabled, provider_id, code],
+        )?;
+        Ok(())
+    }
+
     pub fn delete_llm_model(&self, provider_id: i64, code: String) -> rusqlite::Result<()> {
         self.conn.execute(
             "DELETE FROM llm_model WHERE llm_provider_id = ? AND code = ?",
@@ -379,7 +406,11 @@ impl LLMDatabase {
         Ok(())
     }
 
-    pub fn get_models_for_select(&self) -> Result<Vec<(String, String, i64, i64)>, String> {
+    // include_disabled 为 false 时不返回隐藏的模型，显示名称等场景需要包括隐藏的模型
+    pub fn get_models_for_select(
+        &self,
+        include_disabled: bool,
+    ) -> Result<Vec<(String, String, i64, i64)>, String> {
         let mut stmt = match self.conn.prepare(
             "
             SELECT
@@ -391,14 +422,14 @@ impl LLMDatabase {
                 llm_model m
             JOIN
                 llm_provider p ON m.llm_provider_id = p.id
-            WHERE p.is_enabled = 1
+            WHERE p.is_enabled = 1 AND (m.is_enabled = 1 OR ?)
         ",
         ) {
             Ok(stmt) => stmt,
             Err(e) => return Err(e.to_string()), // Convert rusqlite::Error to String
         };
 
-        let models = match stmt.query_map([], |row| {
+        let models = match stmt.query_map([include_disabled], |row| {
             Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
         }) {
             Ok(models) => models,
@@ -428,6 +459,57 @@ impl LLMDatabase {
             "INSERT INTO llm_provider (id, name, api_type, description, is_official) VALUES (20, 'Anthropic', 'anthropic', 'Anthropic API', 1);",
             [],
         )?;
+        self.init_chinese_llm_provider()?;
+
+        Ok(())
+    }
+
+    /// 通义千问和智谱 GLM 提供商以及常用模型，已有数据库在升级时补充
+    ///
+    /// 用户添加的提供商可能已经占用了固定的 id，这里使用自增 id
+    pub fn init_chinese_llm_provider(&self) -> rusqlite::Result<()> {
+        let providers = [
+            (
+                "通义千问",
+                "dashscope",
+                "阿里云百炼 DashScope API",
+                vec![
+                    ("通义千问-Max", "qwen-max", false),
+                    ("通义千问-Plus", "qwen-plus", false),
+                    ("通义千问-Turbo", "qwen-turbo", false),
+                    ("通义千问VL-Max", "qwen-vl-max", true),
+                ],
+            ),
+            (
+                "智谱 AI",
+                "zhipu",
+                "智谱 AI 开放平台 API",
+                vec![
+                    ("GLM-4-Plus", "glm-4-plus", false),
+                    ("GLM-4-Air", "glm-4-air", false),
+                    ("GLM-4-Flash", "glm-4-flash", false),
+                    ("GLM-4V-Plus", "glm-4v-plus", true),
+                ],
+            ),
+        ];
+        for (name, api_type, description, models) in providers {
+            self.conn.execute(
+                "INSERT INTO llm_provider (name, api_type, description, is_official) VALUES (?, ?, ?, 1)",
+                params![name, api_type, description],
+            )?;
+            let llm_provider_id = self.conn.last_insert_rowid();
+            for (model_name, code, vision_support) in models {
+                self.add_llm_model(
+                    model_name,
+                    llm_provider_id,
+                    code,
+                    model_name,
+                    vision_support,
+                    false,
+                    false,
+                )?;
+            }
+        }
 
         Ok(())
     }
@@ -441,3 +523,50 @@ impl LLMDatabase {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_llm_models_enabled() {
+        let db = LLMDatabase {
+            conn: Connection::open_in_memory().unwrap(),
+        };
+        db.create_tables().unwrap();
+        db.add_llm_provider("OpenRouter", "openai_api", "", false, true)
+            .unwrap();
+        for code in ["gpt-4o", "gpt-4o-mini", "o3"] {
+            db.add_llm_model(code, 1, code, "", false, false, false)
+                .unwrap();
+        }
+        let models = db.get_llm_models("1".to_string()).unwrap();
+        assert!(models.iter().all(|model| model.is_enabled));
+
+        let hidden = models
+            .iter()
+            .filter(|model| model.code != "gpt-4o")
+            .map(|model| model.id)
+            .collect::<Vec<_>>();
+        db.set_llm_models_enabled(&hidden, false).unwrap();
+        let codes = |include_disabled| {
+            db.get_models_for_select(include_disabled)
+                .unwrap()
+                .into_iter()
+                .map(|(_, code, _, _)| code)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(codes(false), vec!["gpt-4o"]);
+        assert_eq!(codes(true).len(), 3);
+
+        db.update_llm_model_enabled_by_code(1, "o3", true).unwrap();
+        let detail = db.get_llm_model_detail(&1, &"o3".to_string()).unwrap();
+        assert!(detail.model.is_enabled);
+        assert!(
+            !db.get_llm_model_detail_by_id(&hidden[0])
+                .unwrap()
+                .model
+                .is_enabled
+        );
+    }
+}
@@ -1,30 +1,64 @@
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::time::Duration;
 
+use assistant_config_schema::{
+    assistant_config_model, migrate_config_value, ASSISTANT_CONFIG_SCHEMA_VERSION,
+    SCHEMA_VERSION_CONFIG_KEY,
+};
 use assistant_db::AssistantDatabase;
 use conversation_db::ConversationDatabase;
 use llm_db::LLMDatabase;
-use rusqlite::params;
+use rusqlite::{params, Connection};
 use semver::Version;
 use system_db::SystemDatabase;
 use tauri::Manager;
 
+use crate::AppHandle;
+
+pub mod assistant_config_schema;
 pub mod assistant_db;
+pub mod attachment_store;
+pub mod connection_pool;
 pub mod conversation_db;
+pub mod health;
+pub mod knowledge_db;
 pub mod llm_db;
 pub mod plugin_db;
 pub mod system_db;
 
-const CURRENT_VERSION: &str = "0.0.2";
+const CURRENT_VERSION: &str = "0.0.29";
+
+// 后台整理数据库时等待其他连接释放锁的时间
+const VACUUM_BUSY_TIMEOUT: Duration = Duration::from_secs(30);
 
-fn get_db_path(app_handle: &tauri::AppHandle, db_name: &str) -> Result<PathBuf, String> {
+fn get_db_path(app_handle: &AppHandle, db_name: &str) -> Result<PathBuf, String> {
+    // 测试应用使用内存数据库，见 test_harness
+    #[cfg(test)]
+    if let Some(databases) = app_handle.try_state::<crate::test_harness::MemoryDatabases>() {
+        return Ok(databases.path(db_name));
+    }
     let app_dir = app_handle.path().app_data_dir().unwrap();
     let db_path = app_dir.join("db");
     std::fs::create_dir_all(&db_path).map_err(|e| e.to_string())?;
     Ok(db_path.join(db_name))
 }
 
+// 大附件内容保存的目录，测试应用使用内存数据库时不启用，内容全部保存在数据库中
+fn get_attachment_dir(app_handle: &AppHandle) -> Option<PathBuf> {
+    #[cfg(test)]
+    if app_handle
+        .try_state::<crate::test_harness::MemoryDatabases>()
+        .is_some()
+    {
+        return None;
+    }
+    let app_dir = app_handle.path().app_data_dir().ok()?;
+    Some(app_dir.join("attachments"))
+}
+
 pub fn database_upgrade(
-    app_handle: &tauri::AppHandle,
+    app_handle: &AppHandle,
     system_db: SystemDatabase,
     llm_db: LLMDatabase,
     assistant_db: AssistantDatabase,
@@ -61,9 +95,38 @@ pub fn database_upgrade(
                         &LLMDatabase,
                         &AssistantDatabase,
                         &ConversationDatabase,
-                        &tauri::AppHandle,
+                        &AppHandle,
                     ) -> Result<(), String>,
-                )> = vec![("0.0.2", special_logic_0_0_2)];
+                )> = vec![
+                    ("0.0.2", special_logic_0_0_2),
+                    ("0.0.3", special_logic_0_0_3),
+                    ("0.0.4", special_logic_0_0_4),
+                    ("0.0.5", special_logic_0_0_5),
+                    ("0.0.6", special_logic_0_0_6),
+                    ("0.0.7", special_logic_0_0_7),
+                    ("0.0.8", special_logic_0_0_8),
+                    ("0.0.9", special_logic_0_0_9),
+                    ("0.0.10", special_logic_0_0_10),
+                    ("0.0.11", special_logic_0_0_11),
+                    ("0.0.12", special_logic_0_0_12),
+                    ("0.0.13", special_logic_0_0_13),
+                    ("0.0.14", special_logic_0_0_14),
+                    ("0.0.15", special_logic_0_0_15),
+                    ("0.0.16", special_logic_0_0_16),
+                    ("0.0.17", special_logic_0_0_17),
+                    ("0.0.18", special_logic_0_0_18),
+                    ("0.0.19", special_logic_0_0_19),
+                    ("0.0.20", special_logic_0_0_20),
+                    ("0.0.21", special_logic_0_0_21),
+                    ("0.0.22", special_logic_0_0_22),
+                    ("0.0.23", special_logic_0_0_23),
+                    ("0.0.24", special_logic_0_0_24),
+                    ("0.0.25", special_logic_0_0_25),
+                    ("0.0.26", special_logic_0_0_26),
+                    ("0.0.27", special_logic_0_0_27),
+                    ("0.0.28", special_logic_0_0_28),
+                    ("0.0.29", special_logic_0_0_29),
+                ];
 
                 for (version_str, logic) in special_versions.iter() {
                     let version = Version::parse(version_str).unwrap();
@@ -103,7 +166,7 @@ fn special_logic_0_0_2(
     llm_db: &LLMDatabase,
     assistant_db: &AssistantDatabase,
     _conversation_db: &ConversationDatabase,
-    _app_handle: &tauri::AppHandle,
+    _app_handle: &AppHandle,
 ) -> Result<(), String> {
     println!("special_logic_0_0_2");
     // 开始事务
@@ -192,3 +255,596 @@ fn special_logic_0_0_2(
     println!("special_logic_0_0_2 done");
     Ok(())
 }
+
+fn special_logic_0_0_3(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_3");
+    let conn = conversation_db
+        .get_connection()
+        .map_err(|e| format!("打开对话数据库失败: {}", e.to_string()))?;
+
+    conn.execute(
+        "ALTER TABLE message ADD COLUMN is_truncated BOOLEAN NOT NULL DEFAULT 0;",
+        [],
+    )
+    .map_err(|e| format!("添加字段is_truncated失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_3 done");
+    Ok(())
+}
+
+fn special_logic_0_0_4(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_4");
+    let conn = conversation_db
+        .get_connection()
+        .map_err(|e| format!("打开对话数据库失败: {}", e.to_string()))?;
+
+    conn.execute(
+        "ALTER TABLE message ADD COLUMN attachment_truncation TEXT;",
+        [],
+    )
+    .map_err(|e| format!("添加字段attachment_truncation失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_4 done");
+    Ok(())
+}
+
+fn special_logic_0_0_5(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_5");
+    let conn = conversation_db
+        .get_connection()
+        .map_err(|e| format!("打开对话数据库失败: {}", e.to_string()))?;
+
+    conn.execute(
+        "ALTER TABLE message ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT 0;",
+        [],
+    )
+    .map_err(|e| format!("添加字段pinned失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_5 done");
+    Ok(())
+}
+
+fn special_logic_0_0_6(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_6");
+    let conn = conversation_db
+        .get_connection()
+        .map_err(|e| format!("打开对话数据库失败: {}", e.to_string()))?;
+
+    conn.execute("ALTER TABLE message ADD COLUMN citations TEXT;", [])
+        .map_err(|e| format!("添加字段citations失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_6 done");
+    Ok(())
+}
+
+fn special_logic_0_0_7(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_7");
+    let conn = conversation_db
+        .get_connection()
+        .map_err(|e| format!("打开对话数据库失败: {}", e.to_string()))?;
+
+    conn.execute(
+        "ALTER TABLE message_attachment ADD COLUMN ocr_text TEXT;",
+        [],
+    )
+    .map_err(|e| format!("添加字段ocr_text失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_7 done");
+    Ok(())
+}
+
+fn special_logic_0_0_8(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_8");
+    let conn = conversation_db
+        .get_connection()
+        .map_err(|e| format!("打开对话数据库失败: {}", e.to_string()))?;
+
+    conn.execute(
+        "ALTER TABLE conversation ADD COLUMN is_private BOOLEAN NOT NULL DEFAULT 0;",
+        [],
+    )
+    .map_err(|e| format!("添加字段is_private失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_8 done");
+    Ok(())
+}
+
+fn special_logic_0_0_9(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    assistant_db: &AssistantDatabase,
+    _conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_9");
+    for sql in [
+        "ALTER TABLE assistant ADD COLUMN category TEXT;",
+        "ALTER TABLE assistant ADD COLUMN is_favorite BOOLEAN NOT NULL DEFAULT 0;",
+        "ALTER TABLE assistant ADD COLUMN use_count INTEGER NOT NULL DEFAULT 0;",
+        "ALTER TABLE assistant ADD COLUMN last_used_time DATETIME;",
+    ] {
+        assistant_db
+            .conn
+            .execute(sql, [])
+            .map_err(|e| format!("助手表添加字段失败: {}", e.to_string()))?;
+    }
+    println!("special_logic_0_0_9 done");
+    Ok(())
+}
+
+fn special_logic_0_0_10(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_10");
+    let conn = conversation_db
+        .get_connection()
+        .map_err(|e| format!("打开对话数据库失败: {}", e.to_string()))?;
+
+    conn.execute(
+        "ALTER TABLE message ADD COLUMN timed_out BOOLEAN NOT NULL DEFAULT 0;",
+        [],
+    )
+    .map_err(|e| format!("添加字段timed_out失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_10 done");
+    Ok(())
+}
+
+fn special_logic_0_0_11(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_11");
+    let conn = conversation_db
+        .get_connection()
+        .map_err(|e| format!("打开对话数据库失败: {}", e.to_string()))?;
+
+    conn.execute(
+        "ALTER TABLE message ADD COLUMN generation_metadata TEXT;",
+        [],
+    )
+    .map_err(|e| format!("添加字段generation_metadata失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_11 done");
+    Ok(())
+}
+
+fn special_logic_0_0_12(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    assistant_db: &AssistantDatabase,
+    _conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_12");
+    assistant_db
+        .conn
+        .execute("ALTER TABLE assistant ADD COLUMN active_preset TEXT;", [])
+        .map_err(|e| format!("添加字段active_preset失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_12 done");
+    Ok(())
+}
+
+fn special_logic_0_0_13(
+    _system_db: &SystemDatabase,
+    llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    _conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_13");
+    llm_db
+        .conn
+        .execute(
+            "ALTER TABLE llm_model ADD COLUMN is_enabled BOOLEAN NOT NULL DEFAULT 1;",
+            [],
+        )
+        .map_err(|e| format!("添加字段is_enabled失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_13 done");
+    Ok(())
+}
+
+fn special_logic_0_0_14(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_14");
+    let conn = conversation_db
+        .get_connection()
+        .map_err(|e| format!("打开对话数据库失败: {}", e.to_string()))?;
+
+    conn.execute("ALTER TABLE message ADD COLUMN reasoning TEXT;", [])
+        .map_err(|e| format!("添加字段reasoning失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_14 done");
+    Ok(())
+}
+
+fn special_logic_0_0_15(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_15");
+    let conn = conversation_db
+        .get_connection()
+        .map_err(|e| format!("打开对话数据库失败: {}", e.to_string()))?;
+    conversation_db::create_indexes(&conn)
+        .map_err(|e| format!("创建对话数据库索引失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_15 done");
+    Ok(())
+}
+
+fn special_logic_0_0_16(
+    _system_db: &SystemDatabase,
+    llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    _conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_16");
+    llm_db
+        .init_chinese_llm_provider()
+        .map_err(|e| format!("添加通义千问和智谱提供商失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_16 done");
+    Ok(())
+}
+
+fn special_logic_0_0_17(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_17");
+    let conn = conversation_db
+        .get_connection()
+        .map_err(|e| format!("打开对话数据库失败: {}", e.to_string()))?;
+
+    // 附件和消息的关联改为保存在 message_attachment_link 中，还没有发送的附件不再用 -1 表示
+    conn.execute_batch(
+        "INSERT OR IGNORE INTO message_attachment_link (message_id, attachment_id)
+             SELECT message_id, id FROM message_attachment WHERE message_id > 0;
+         UPDATE message_attachment SET message_id = NULL;",
+    )
+    .map_err(|e| format!("迁移附件关联失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_17 done");
+    Ok(())
+}
+
+fn special_logic_0_0_18(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_18");
+    let conn = conversation_db
+        .get_connection()
+        .map_err(|e| format!("打开对话数据库失败: {}", e.to_string()))?;
+
+    conn.execute("ALTER TABLE message ADD COLUMN deleted_time DATETIME;", [])
+        .map_err(|e| format!("添加字段deleted_time失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_18 done");
+    Ok(())
+}
+
+fn special_logic_0_0_19(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_19");
+    let conn = conversation_db
+        .get_connection()
+        .map_err(|e| format!("打开对话数据库失败: {}", e.to_string()))?;
+
+    conn.execute(
+        "ALTER TABLE conversation ADD COLUMN last_read_time DATETIME;",
+        [],
+    )
+    .map_err(|e| format!("添加字段last_read_time失败: {}", e.to_string()))?;
+    // 升级之前的对话都当作已读
+    conn.execute(
+        "UPDATE conversation SET last_read_time = CURRENT_TIMESTAMP;",
+        [],
+    )
+    .map_err(|e| format!("初始化字段last_read_time失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_19 done");
+    Ok(())
+}
+
+fn special_logic_0_0_20(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_20");
+    let conn = conversation_db
+        .get_connection()
+        .map_err(|e| format!("打开对话数据库失败: {}", e.to_string()))?;
+
+    conn.execute("ALTER TABLE conversation ADD COLUMN summary TEXT;", [])
+        .map_err(|e| format!("添加字段summary失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_20 done");
+    Ok(())
+}
+
+fn special_logic_0_0_21(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_21");
+    let conn = conversation_db
+        .get_connection()
+        .map_err(|e| format!("打开对话数据库失败: {}", e.to_string()))?;
+
+    conn.execute("ALTER TABLE message ADD COLUMN assistant_id INTEGER;", [])
+        .map_err(|e| format!("添加字段assistant_id失败: {}", e.to_string()))?;
+    // 升级之前的回答都来自对话的助手
+    conn.execute(
+        "UPDATE message SET assistant_id = (SELECT assistant_id FROM conversation WHERE conversation.id = message.conversation_id) WHERE message_type = 'assistant';",
+        [],
+    )
+    .map_err(|e| format!("初始化字段assistant_id失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_21 done");
+    Ok(())
+}
+
+fn special_logic_0_0_22(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    conversation_db: &ConversationDatabase,
+    app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_22");
+    // 已有的大附件移到附件目录中，数据库里只保留引用
+    let moved = conversation_db
+        .attachment_repo()
+        .map_err(|e| format!("打开对话数据库失败: {}", e.to_string()))?
+        .externalize_large_contents()
+        .map_err(|e| format!("迁移附件内容失败: {}", e.to_string()))?;
+    println!("externalized attachments: {}", moved);
+    if moved > 0 {
+        // 移走的内容占用的空间在整理数据库之后才会释放，整理大数据库需要较长时间，
+        // 放到后台进行，不阻塞启动。整理需要独占数据库，等待其他连接的读写完成
+        let db_path = get_db_path(app_handle, "conversation.db")?;
+        tauri::async_runtime::spawn_blocking(move || {
+            let result = Connection::open(&db_path).and_then(|conn| {
+                conn.busy_timeout(VACUUM_BUSY_TIMEOUT)?;
+                conn.execute_batch("VACUUM;")
+            });
+            match result {
+                Ok(()) => println!("vacuum conversation database done"),
+                Err(e) => println!("vacuum conversation database error: {:?}", e),
+            }
+        });
+    }
+    println!("special_logic_0_0_22 done");
+    Ok(())
+}
+
+fn special_logic_0_0_23(
+    system_db: &SystemDatabase,
+    llm_db: &LLMDatabase,
+    assistant_db: &AssistantDatabase,
+    _conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_23");
+    // 数据库由更新的版本写入过时，参数可能使用了新的规则，不做整理
+    let schema_version = system_db
+        .get_config(SCHEMA_VERSION_CONFIG_KEY)
+        .ok()
+        .and_then(|value| value.parse::<u32>().ok())
+        .unwrap_or(0);
+    if schema_version >= ASSISTANT_CONFIG_SCHEMA_VERSION {
+        return Ok(());
+    }
+    let configs = assistant_db
+        .list_all_assistant_model_configs()
+        .map_err(|e| format!("读取助手参数失败: {}", e.to_string()))?;
+    let mut updated = 0;
+    // 数值参数按助手的模型检查范围，同一个助手的模型只查询一次
+    let mut config_models: HashMap<i64, Option<(String, String)>> = HashMap::new();
+    for config in configs {
+        let config_model = config_models.entry(config.assistant_id).or_insert_with(|| {
+            let models = assistant_db
+                .get_assistant_model(config.assistant_id)
+                .unwrap_or_default();
+            assistant_config_model(llm_db, &models)
+        });
+        let value = config.value.clone().unwrap_or_default();
+        let (new_value, new_value_type) = migrate_config_value(
+            &config.name,
+            &value,
+            &config.value_type,
+            config_model
+                .as_ref()
+                .map(|(api_type, model_code)| (api_type.as_str(), model_code.as_str())),
+        );
+        if new_value != value || new_value_type != config.value_type {
+            assistant_db
+                .update_assistant_model_config_value(config.id, &new_value, &new_value_type)
+                .map_err(|e| format!("整理助手参数失败: {}", e.to_string()))?;
+            updated += 1;
+        }
+    }
+    println!("normalized assistant model configs: {}", updated);
+    let version = ASSISTANT_CONFIG_SCHEMA_VERSION.to_string();
+    let result = if schema_version == 0 {
+        system_db.add_system_config(SCHEMA_VERSION_CONFIG_KEY, &version)
+    } else {
+        system_db.update_system_config(SCHEMA_VERSION_CONFIG_KEY, &version)
+    };
+    result.map_err(|e| format!("保存助手参数版本失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_23 done");
+    Ok(())
+}
+
+fn special_logic_0_0_24(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_24");
+    let conn = conversation_db
+        .get_connection()
+        .map_err(|e| format!("打开对话数据库失败: {}", e.to_string()))?;
+
+    conn.execute(
+        "ALTER TABLE message_attachment ADD COLUMN encoding TEXT;",
+        [],
+    )
+    .map_err(|e| format!("添加字段encoding失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_24 done");
+    Ok(())
+}
+
+fn special_logic_0_0_25(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_25");
+    let conn = conversation_db
+        .get_connection()
+        .map_err(|e| format!("打开对话数据库失败: {}", e.to_string()))?;
+
+    // 之前的版本没有记录哪些对话是无痕对话，升级前的对话都按普通对话保留
+    conn.execute(
+        "ALTER TABLE conversation ADD COLUMN is_incognito BOOLEAN NOT NULL DEFAULT 0;",
+        [],
+    )
+    .map_err(|e| format!("添加字段is_incognito失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_25 done");
+    Ok(())
+}
+
+fn special_logic_0_0_26(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    assistant_db: &AssistantDatabase,
+    conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_26");
+    // 助手常驻上下文引用的附件之前不计入引用计数，补齐之后删除消息时不会误删这些附件
+    let references = assistant_db
+        .list_all_assistant_attachments()
+        .map_err(|e| format!("读取助手附件失败: {}", e.to_string()))?;
+    let attachment_repo = conversation_db
+        .attachment_repo()
+        .map_err(|e| format!("打开对话数据库失败: {}", e.to_string()))?;
+    for (assistant_id, attachment_id) in references {
+        attachment_repo
+            .add_assistant_reference(assistant_id, attachment_id)
+            .map_err(|e| format!("补齐附件引用失败: {}", e.to_string()))?;
+    }
+    println!("special_logic_0_0_26 done");
+    Ok(())
+}
+
+fn special_logic_0_0_27(
+    system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    _conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_27");
+    // 之前的版本没有选中文字模板，升级后询问窗口同样带上选中的文字
+    system_db
+        .add_selection_context_template()
+        .map_err(|e| format!("添加选中文字模板失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_27 done");
+    Ok(())
+}
+
+fn special_logic_0_0_28(
+    _system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_28");
+    // 之前的版本没有记录回答的结束时间，补上之后启动时不会把历史回答当成中断的回答
+    let updated = conversation_db
+        .message_repo()
+        .map_err(|e| format!("打开对话数据库失败: {}", e.to_string()))?
+        .backfill_finish_time()
+        .map_err(|e| format!("补充回答结束时间失败: {}", e.to_string()))?;
+    println!("backfilled finish time: {}", updated);
+    println!("special_logic_0_0_28 done");
+    Ok(())
+}
+
+fn special_logic_0_0_29(
+    system_db: &SystemDatabase,
+    _llm_db: &LLMDatabase,
+    _assistant_db: &AssistantDatabase,
+    _conversation_db: &ConversationDatabase,
+    _app_handle: &AppHandle,
+) -> Result<(), String> {
+    println!("special_logic_0_0_29");
+    // 之前的版本没有模型价格，升级后常用模型的回答同样能统计费用
+    system_db
+        .add_default_model_prices()
+        .map_err(|e| format!("添加默认模型价格失败: {}", e.to_string()))?;
+    println!("special_logic_0_0_29 done");
+    Ok(())
+}
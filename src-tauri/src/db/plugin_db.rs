@@ -1,5 +1,6 @@
 use super::get_db_path;
 use crate::errors::AppError;
+use crate::AppHandle;
 use chrono::prelude::*;
 use rusqlite::{Connection, OptionalExtension, Result};
 use serde::{Deserialize, Serialize};
@@ -426,7 +427,7 @@ pub struct PluginDatabase {
 }
 
 impl PluginDatabase {
-    pub fn new(app_handle: &tauri::AppHandle) -> rusqlite::Result<Self> {
+    pub fn new(app_handle: &AppHandle) -> rusqlite::Result<Self> {
         let db_path = get_db_path(app_handle, "plugin.db");
 
         Ok(PluginDatabase {
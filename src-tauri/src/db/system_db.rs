@@ -2,6 +2,9 @@ use rusqlite::{params, Connection, OptionalExtension, Result};
 use serde::{Deserialize, Serialize};
 
 use super::get_db_path;
+use crate::conversation_cost::{ModelPrice, DEFAULT_MODEL_PRICES, MODEL_PRICE_FEATURE};
+use crate::selection::{DEFAULT_BLOCKED_APPS, DEFAULT_CONTEXT_TEMPLATE};
+use crate::AppHandle;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FeatureConfig {
@@ -13,12 +16,24 @@ pub struct FeatureConfig {
     pub description: Option<String>,
 }
 
+/// model_price 功能配置中一个模型的价格，key 为模型代码
+pub fn model_price_config(model_code: &str, price: &ModelPrice) -> FeatureConfig {
+    FeatureConfig {
+        id: None,
+        feature_code: MODEL_PRICE_FEATURE.to_string(),
+        key: model_code.to_string(),
+        value: price.to_config_value(),
+        data_type: "string".to_string(),
+        description: Some("模型每百万 token 的价格，格式为 输入价格,输出价格".to_string()),
+    }
+}
+
 pub struct SystemDatabase {
     pub conn: Connection,
 }
 
 impl SystemDatabase {
-    pub fn new(app_handle: &tauri::AppHandle) -> Result<Self> {
+    pub fn new(app_handle: &AppHandle) -> Result<Self> {
         let db_path = get_db_path(app_handle, "system.db");
         let conn = Connection::open(db_path.unwrap())?;
         Ok(SystemDatabase { conn })
@@ -115,6 +130,63 @@ impl SystemDatabase {
         Ok(())
     }
 
+    /// 已有相同 feature_code 和 key 的配置时更新，没有时新增
+    pub fn upsert_feature_config(&self, config: &FeatureConfig) -> Result<()> {
+        match self.get_feature_config(&config.feature_code, &config.key)? {
+            Some(_) => self.update_feature_config(config),
+            None => self.add_feature_config(config),
+        }
+    }
+
+    /// 添加默认的选中文字模板，已经有这项配置时保持用户的设置不变，升级时也会调用
+    pub fn add_selection_context_template(&self) -> Result<()> {
+        if self
+            .get_feature_config("selection", "context_template")?
+            .is_some()
+        {
+            return Ok(());
+        }
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "selection".to_string(),
+            key: "context_template".to_string(),
+            value: DEFAULT_CONTEXT_TEMPLATE.to_string(),
+            data_type: "string".to_string(),
+            description: Some(
+                "询问窗口中添加到系统提示词的选中文字说明，{{app_name}} 为来源应用，{{selected_text}} 为选中的文字，为空时不添加"
+                    .to_string(),
+            ),
+        })
+    }
+
+    /// 添加常用模型的默认价格，已经有价格的模型保持用户的设置不变，升级时也会调用
+    pub fn add_default_model_prices(&self) -> Result<()> {
+        for (model_code, input, output) in DEFAULT_MODEL_PRICES {
+            if self
+                .get_feature_config(MODEL_PRICE_FEATURE, model_code)?
+                .is_some()
+            {
+                continue;
+            }
+            self.add_feature_config(&model_price_config(
+                model_code,
+                &ModelPrice {
+                    input: *input,
+                    output: *output,
+                },
+            ))?;
+        }
+        Ok(())
+    }
+
+    pub fn delete_feature_config(&self, feature_code: &str, key: &str) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM feature_config WHERE feature_code = ?1 AND key = ?2",
+            params![feature_code, key],
+        )?;
+        Ok(())
+    }
+
     pub fn delete_feature_config_by_feature_code(&self, feature_code: &str) -> Result<()> {
         self.conn.execute(
             "DELETE FROM feature_config WHERE feature_code = ?1",
@@ -207,6 +279,497 @@ impl SystemDatabase {
             data_type: "string".to_string(),
             description: Some("对话总结使用长度".to_string()),
         })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "prompt_history".to_string(),
+            key: "retention_days".to_string(),
+            value: "90".to_string(),
+            data_type: "string".to_string(),
+            description: Some("提示词历史保留天数，0 表示永久保留".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "generation".to_string(),
+            key: "stall_timeout".to_string(),
+            value: "60".to_string(),
+            data_type: "string".to_string(),
+            description: Some("超过多少秒没有收到新内容视为生成停滞".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "generation".to_string(),
+            key: "stall_auto_retry".to_string(),
+            value: "false".to_string(),
+            data_type: "string".to_string(),
+            description: Some("生成停滞时是否自动重试".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "generation".to_string(),
+            key: "stall_max_retries".to_string(),
+            value: "1".to_string(),
+            data_type: "string".to_string(),
+            description: Some("生成停滞时最多自动重试的次数".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "generation".to_string(),
+            key: "stream_max_resumes".to_string(),
+            value: "2".to_string(),
+            data_type: "string".to_string(),
+            description: Some("流式响应中断后最多自动续写的次数，0 表示不续写".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "session".to_string(),
+            key: "restore_on_startup".to_string(),
+            value: "false".to_string(),
+            data_type: "string".to_string(),
+            description: Some("启动时重新打开上次退出时的窗口和窗口中的对话".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "reasoning".to_string(),
+            key: "tags".to_string(),
+            value: "think,thinking".to_string(),
+            data_type: "string".to_string(),
+            description: Some(
+                "推理模型在回答中输出思考过程使用的标签，逗号分隔，标签内的内容和回答分开显示和保存"
+                    .to_string(),
+            ),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "webhook".to_string(),
+            key: "url".to_string(),
+            value: "".to_string(),
+            data_type: "string".to_string(),
+            description: Some(
+                "耗时较长的生成或后台任务结束时 POST 通知的地址，为空表示不通知".to_string(),
+            ),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "webhook".to_string(),
+            key: "payload_template".to_string(),
+            value: "".to_string(),
+            data_type: "string".to_string(),
+            description: Some(
+                "通知的请求体模板，可以使用 {{event}}、{{title}}、{{message}}、{{conversation_id}}、{{duration_secs}}，为空时发送事件 JSON"
+                    .to_string(),
+            ),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "webhook".to_string(),
+            key: "min_duration_secs".to_string(),
+            value: "30".to_string(),
+            data_type: "string".to_string(),
+            description: Some("耗时超过这个秒数才发送通知".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "webhook".to_string(),
+            key: "max_retries".to_string(),
+            value: "3".to_string(),
+            data_type: "string".to_string(),
+            description: Some("通知发送失败后的重试次数".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "audit_log".to_string(),
+            key: "enabled".to_string(),
+            value: "false".to_string(),
+            data_type: "string".to_string(),
+            description: Some(
+                "合规模式，把所有提问、回答和工具调用追加到数据目录的 audit_log.jsonl，记录之间用 hash 串联"
+                    .to_string(),
+            ),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "tools".to_string(),
+            key: "read_file_roots".to_string(),
+            value: "".to_string(),
+            data_type: "string".to_string(),
+            description: Some(
+                "read_file 工具可以直接读取的目录，每行一个，其他路径每次读取前需要确认"
+                    .to_string(),
+            ),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "tools".to_string(),
+            key: "read_file_max_bytes".to_string(),
+            value: "65536".to_string(),
+            data_type: "string".to_string(),
+            description: Some("read_file 工具每次最多读取的字节数，超出的部分截断".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "tools".to_string(),
+            key: "run_shell_enabled".to_string(),
+            value: "false".to_string(),
+            data_type: "string".to_string(),
+            description: Some(
+                "允许助手通过 run_shell 工具执行命令，每条命令执行前仍需确认".to_string(),
+            ),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "tools".to_string(),
+            key: "run_shell_timeout_secs".to_string(),
+            value: "30".to_string(),
+            data_type: "string".to_string(),
+            description: Some("run_shell 工具执行命令的超时时间，超时后结束命令".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "tools".to_string(),
+            key: "run_shell_cwd".to_string(),
+            value: "".to_string(),
+            data_type: "string".to_string(),
+            description: Some("run_shell 工具默认的工作目录，为空时使用用户目录".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "tools".to_string(),
+            key: "run_shell_allowed".to_string(),
+            value: "ls,cat,head,tail,wc,grep,find,pwd,echo,git".to_string(),
+            data_type: "string".to_string(),
+            description: Some(
+                "run_shell 工具可以执行的程序名称，逗号分隔，不在列表中的程序直接拒绝".to_string(),
+            ),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "attachment".to_string(),
+            key: "max_text_length".to_string(),
+            value: "20000".to_string(),
+            data_type: "string".to_string(),
+            description: Some("文本附件注入提示词的最大字符数".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "attachment".to_string(),
+            key: "truncate_strategy".to_string(),
+            value: "head".to_string(),
+            data_type: "string".to_string(),
+            description: Some(
+                "文本附件超长时的处理方式，head 保留开头，rag 挑选相关片段".to_string(),
+            ),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "injection_guard".to_string(),
+            key: "policy".to_string(),
+            value: "wrap".to_string(),
+            data_type: "string".to_string(),
+            description: Some(
+                "网页、文件和工具输出疑似提示注入时的处理方式，off 不检查，\
+                 sanitize 移除可疑内容，wrap 加分隔标记并提醒模型，block 拦截"
+                    .to_string(),
+            ),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "context".to_string(),
+            key: "max_length".to_string(),
+            value: "0".to_string(),
+            data_type: "string".to_string(),
+            description: Some(
+                "对话上下文的最大字符数，0 表示不限制，置顶的消息总是保留".to_string(),
+            ),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "selection".to_string(),
+            key: "fallback".to_string(),
+            value: "auto".to_string(),
+            data_type: "string".to_string(),
+            description: Some(
+                "无法直接获取选中文字时的处理方式，auto 回退到模拟复制，copy 总是模拟复制，off 不回退"
+                    .to_string(),
+            ),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "selection".to_string(),
+            key: "blocked_apps".to_string(),
+            value: DEFAULT_BLOCKED_APPS.to_string(),
+            data_type: "string".to_string(),
+            description: Some(
+                "划词快捷键不读取选中文字的应用，逗号分隔的进程名称，title: 开头的项匹配窗口标题"
+                    .to_string(),
+            ),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "selection".to_string(),
+            key: "blocked_action".to_string(),
+            value: "ignore".to_string(),
+            data_type: "string".to_string(),
+            description: Some(
+                "焦点在屏蔽的应用中时的处理方式，ignore 忽略快捷键，clipboard 只读取剪贴板，密码管理器总是忽略"
+                    .to_string(),
+            ),
+        })?;
+        self.add_selection_context_template()?;
+        self.add_default_model_prices()?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "quick_action".to_string(),
+            key: "allow_type_output".to_string(),
+            value: "false".to_string(),
+            data_type: "string".to_string(),
+            description: Some("是否允许快捷操作通过模拟键盘把结果输入到其他应用".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "quick_action".to_string(),
+            key: "type_interval".to_string(),
+            value: "10".to_string(),
+            data_type: "string".to_string(),
+            description: Some("模拟键盘输入时每个字符之间的间隔（毫秒）".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "fast_mode".to_string(),
+            key: "provider_id".to_string(),
+            value: "".to_string(),
+            data_type: "string".to_string(),
+            description: Some("快速模式使用的提供商，为空时使用助手的提供商".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "fast_mode".to_string(),
+            key: "model_code".to_string(),
+            value: "".to_string(),
+            data_type: "string".to_string(),
+            description: Some("划词弹窗等快捷操作使用的小模型，为空时使用助手的模型".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "fast_mode".to_string(),
+            key: "prewarm".to_string(),
+            value: "true".to_string(),
+            data_type: "string".to_string(),
+            description: Some("按下划词快捷键时是否提前和提供商建立连接".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "offline_mode".to_string(),
+            key: "provider_id".to_string(),
+            value: "".to_string(),
+            data_type: "string".to_string(),
+            description: Some("离线模式下换用的本地提供商，需要是地址指向本机的提供商".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "offline_mode".to_string(),
+            key: "model_code".to_string(),
+            value: "".to_string(),
+            data_type: "string".to_string(),
+            description: Some(
+                "离线模式下换用的本地模型，为空时不换用，请求云端提供商前提示用户".to_string(),
+            ),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "draft_refine".to_string(),
+            key: "enabled".to_string(),
+            value: "false".to_string(),
+            data_type: "string".to_string(),
+            description: Some("是否先用草稿模型回答，再由助手的模型改进草稿".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "draft_refine".to_string(),
+            key: "provider_id".to_string(),
+            value: "".to_string(),
+            data_type: "string".to_string(),
+            description: Some("起草使用的提供商".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "draft_refine".to_string(),
+            key: "model_code".to_string(),
+            value: "".to_string(),
+            data_type: "string".to_string(),
+            description: Some("起草使用的便宜模型，为空时不起草".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "draft_refine".to_string(),
+            key: "mode".to_string(),
+            value: "refine".to_string(),
+            data_type: "string".to_string(),
+            description: Some(
+                "改进方式：refine 直接改进，critique 先指出草稿的问题再修改".to_string(),
+            ),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "translation".to_string(),
+            key: "assistant_id".to_string(),
+            value: "".to_string(),
+            data_type: "string".to_string(),
+            description: Some("翻译消息使用的助手，为空时不能翻译".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "ocr".to_string(),
+            key: "engine".to_string(),
+            value: "tesseract".to_string(),
+            data_type: "string".to_string(),
+            description: Some(
+                "模型不支持视觉时识别图片文字的方式：tesseract、http 或 off".to_string(),
+            ),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "ocr".to_string(),
+            key: "language".to_string(),
+            value: "chi_sim+eng".to_string(),
+            data_type: "string".to_string(),
+            description: Some("OCR 识别的语言".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "ocr".to_string(),
+            key: "tesseract_path".to_string(),
+            value: "tesseract".to_string(),
+            data_type: "string".to_string(),
+            description: Some("tesseract 命令的路径".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "ocr".to_string(),
+            key: "endpoint".to_string(),
+            value: "".to_string(),
+            data_type: "string".to_string(),
+            description: Some("云端 OCR 服务的地址，engine 为 http 时使用".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "ocr".to_string(),
+            key: "api_key".to_string(),
+            value: "".to_string(),
+            data_type: "string".to_string(),
+            description: Some("云端 OCR 服务的 API Key".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "voice".to_string(),
+            key: "engine".to_string(),
+            value: "http".to_string(),
+            data_type: "string".to_string(),
+            description: Some("语音输入的识别方式：http（兼容 OpenAI 的 Whisper 接口）或 command（本地 whisper.cpp）".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "voice".to_string(),
+            key: "endpoint".to_string(),
+            value: "https://api.openai.com/v1/audio/transcriptions".to_string(),
+            data_type: "string".to_string(),
+            description: Some("语音识别接口的地址，engine 为 http 时使用".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "voice".to_string(),
+            key: "api_key".to_string(),
+            value: "".to_string(),
+            data_type: "string".to_string(),
+            description: Some("语音识别接口的 API Key".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "voice".to_string(),
+            key: "model".to_string(),
+            value: "whisper-1".to_string(),
+            data_type: "string".to_string(),
+            description: Some("语音识别使用的模型".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "voice".to_string(),
+            key: "language".to_string(),
+            value: "".to_string(),
+            data_type: "string".to_string(),
+            description: Some("语音的语言，例如 zh，为空时自动识别".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "voice".to_string(),
+            key: "command".to_string(),
+            value: "whisper-cli".to_string(),
+            data_type: "string".to_string(),
+            description: Some("whisper.cpp 命令的路径，engine 为 command 时使用".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "voice".to_string(),
+            key: "model_path".to_string(),
+            value: "".to_string(),
+            data_type: "string".to_string(),
+            description: Some("whisper.cpp 的模型文件路径".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "voice".to_string(),
+            key: "partial_interval".to_string(),
+            value: "3".to_string(),
+            data_type: "string".to_string(),
+            description: Some("录音时每隔多少秒识别一次中间结果，0 表示只在停止时识别".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "voice".to_string(),
+            key: "max_duration".to_string(),
+            value: "120".to_string(),
+            data_type: "string".to_string(),
+            description: Some("单次录音的最长时间，单位秒".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "attachment_policy".to_string(),
+            key: "blocked_extensions".to_string(),
+            value: "".to_string(),
+            data_type: "string".to_string(),
+            description: Some("禁止添加为附件的文件扩展名，逗号分隔，例如 exe,bat".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "attachment_policy".to_string(),
+            key: "max_size_mb".to_string(),
+            value: "0".to_string(),
+            data_type: "string".to_string(),
+            description: Some("单个附件的最大大小（MB），0 表示不限制".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "attachment_policy".to_string(),
+            key: "scanner_path".to_string(),
+            value: "".to_string(),
+            data_type: "string".to_string(),
+            description: Some("添加附件前调用的扫描程序，例如 clamdscan，为空时不扫描；退出码 0 表示通过，1 表示发现问题".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "attachment_policy".to_string(),
+            key: "scanner_args".to_string(),
+            value: "-".to_string(),
+            data_type: "string".to_string(),
+            description: Some("扫描程序的参数，空格分隔，文件内容通过标准输入传入".to_string()),
+        })?;
+        self.add_feature_config(&FeatureConfig {
+            id: None,
+            feature_code: "attachment_policy".to_string(),
+            key: "scanner_timeout_secs".to_string(),
+            value: "60".to_string(),
+            data_type: "string".to_string(),
+            description: Some("扫描程序的超时时间，超时按未通过处理".to_string()),
+        })?;
         Ok(())
     }
 }
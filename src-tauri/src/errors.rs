@@ -3,6 +3,9 @@ use std::error::Error as StdError;
 use serde::Serialize;
 use thiserror::Error;
 
+use crate::db::assistant_config_schema::ConfigValidationErrors;
+use crate::knowledge::attachment_policy::AttachmentRejection;
+
 #[derive(Error, Debug, Serialize)]
 pub enum AppError {
     #[error("数据库错误: {0}")]
@@ -32,8 +35,35 @@ pub enum AppError {
     #[error("未进行配置: {0}")]
     NoConfigError(String),
 
+    #[error("工具调用错误: {0}")]
+    ToolError(String),
+
     #[error("Anyhow错误: {0}")]
     Anyhow(String),
+
+    #[error("当前处于离线模式，不能请求云端提供商 {0}")]
+    OfflineBlocked(String),
+
+    #[error("附件未通过检查: {0}")]
+    AttachmentRejected(AttachmentRejection),
+
+    #[error("对话 {0} 正在生成回答，请等待生成结束或者停止生成后再操作")]
+    ConversationBusy(i64),
+
+    #[error("助手参数不正确: {0}")]
+    InvalidAssistantConfig(ConfigValidationErrors),
+}
+
+impl From<AttachmentRejection> for AppError {
+    fn from(err: AttachmentRejection) -> Self {
+        AppError::AttachmentRejected(err)
+    }
+}
+
+impl From<ConfigValidationErrors> for AppError {
+    fn from(err: ConfigValidationErrors) -> Self {
+        AppError::InvalidAssistantConfig(err)
+    }
 }
 
 impl From<rusqlite::Error> for AppError {
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use tauri::Manager;
+
+use crate::api::llm::Role;
+use crate::db::conversation_db::Message;
+use crate::AppHandle;
+use crate::NameCacheState;
+
+/// 所有助手的 id 和名称，用来识别提问中的 @助手名称 和标注其他助手的发言
+pub async fn assistant_names(app_handle: &AppHandle) -> HashMap<i64, String> {
+    match app_handle.try_state::<NameCacheState>() {
+        Some(state) => state.assistant_names.lock().await.clone(),
+        None => HashMap::new(),
+    }
+}
+
+/// 提问中第一个 @助手名称 对应的助手，决定群聊中由谁回答；名称互相包含时优先匹配最长的名称
+///
+/// 名称后面必须是空白、标点或者提问的结尾，@Coder2 不会匹配到名称为 Coder 的助手
+pub fn mentioned_assistant(prompt: &str, assistant_names: &HashMap<i64, String>) -> Option<i64> {
+    let mut names = assistant_names
+        .iter()
+        .map(|(id, name)| (*id, name.trim()))
+        .filter(|(_, name)| !name.is_empty())
+        .collect::<Vec<_>>();
+    names.sort_by(|a, b| {
+        b.1.chars()
+            .count()
+            .cmp(&a.1.chars().count())
+            .then(a.0.cmp(&b.0))
+    });
+    prompt.match_indices(['@', '＠']).find_map(|(index, at)| {
+        let rest = &prompt[index + at.len()..];
+        names
+            .iter()
+            .find(|(_, name)| {
+                rest.strip_prefix(name).is_some_and(|after| {
+                    !matches!(after.chars().next(), Some(c) if c.is_alphanumeric() || c == '_')
+                })
+            })
+            .map(|(id, _)| *id)
+    })
+}
+
+/// 其他助手在群聊中的发言，发给模型时以 [名称] 开头，让回答的助手分清是谁说的；
+/// 自己的发言、提问和已经删除的助手的发言返回 None，保持原样
+pub fn speaker_content(
+    message: &Message,
+    current_assistant_id: i64,
+    assistant_names: &HashMap<i64, String>,
+) -> Option<String> {
+    if message.role() != Some(Role::Assistant) {
+        return None;
+    }
+    let assistant_id = message
+        .assistant_id
+        .filter(|assistant_id| *assistant_id != current_assistant_id)?;
+    let name = assistant_names.get(&assistant_id)?;
+    Some(format!("[{}] {}", name, message.content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names() -> HashMap<i64, String> {
+        HashMap::from([
+            (1, "翻译".to_string()),
+            (2, "翻译助手".to_string()),
+            (3, "Coder".to_string()),
+        ])
+    }
+
+    #[test]
+    fn test_mentioned_assistant() {
+        let names = names();
+        assert_eq!(mentioned_assistant("@Coder 写一个排序", &names), Some(3));
+        assert_eq!(
+            mentioned_assistant("请 ＠翻译助手 翻译一下", &names),
+            Some(2)
+        );
+        assert_eq!(mentioned_assistant("@翻译 hello", &names), Some(1));
+        // 第一个能识别的 @ 决定回答的助手
+        assert_eq!(
+            mentioned_assistant("发邮件到 a@b.com，@Coder 再看看", &names),
+            Some(3)
+        );
+        assert_eq!(mentioned_assistant("没有提到助手", &names), None);
+        // 名称后面紧跟文字时不是提到这个助手
+        assert_eq!(mentioned_assistant("@Coder2 写一个排序", &names), None);
+        assert_eq!(mentioned_assistant("@翻译一下这段", &names), None);
+        assert_eq!(mentioned_assistant("@Coder，写一个排序", &names), Some(3));
+    }
+
+    #[test]
+    fn test_speaker_content() {
+        let names = names();
+        let message = Message {
+            id: 1,
+            parent_id: None,
+            conversation_id: 1,
+            message_type: "assistant".to_string(),
+            content: "def sort(): ...".to_string(),
+            llm_model_id: None,
+            llm_model_name: None,
+            created_time: chrono::Utc::now(),
+            start_time: None,
+            finish_time: None,
+            token_count: 0,
+            is_truncated: false,
+            attachment_truncation: None,
+            pinned: false,
+            citations: None,
+            timed_out: false,
+            generation_metadata: None,
+            reasoning: None,
+            assistant_id: Some(3),
+        };
+        assert_eq!(
+            speaker_content(&message, 1, &names).as_deref(),
+            Some("[Coder] def sort(): ...")
+        );
+        assert_eq!(speaker_content(&message, 3, &names), None);
+        let question = Message {
+            message_type: "user".to_string(),
+            ..message
+        };
+        assert_eq!(speaker_content(&question, 1, &names), None);
+    }
+}
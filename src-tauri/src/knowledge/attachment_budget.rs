@@ -0,0 +1,187 @@
+use crate::db::conversation_db::{AttachmentTruncation, MessageAttachment};
+
+use super::embedding::{cosine_similarity, embed};
+use super::indexer::split_text;
+
+pub const DEFAULT_MAX_ATTACHMENT_TEXT_LENGTH: usize = 20000;
+const CHUNK_SIZE: usize = 800;
+const CHUNK_OVERLAP: usize = 0;
+const TRUNCATED_MARKER: &str = "\n...[内容过长，已截断]";
+const OMITTED_MARKER: &str = "\n...\n";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TruncateStrategy {
+    // 保留开头的内容
+    Head,
+    // 按与提问的相关度挑选片段，保持原文顺序
+    Rag,
+}
+
+impl TruncateStrategy {
+    pub fn from_config(value: &str) -> Self {
+        match value {
+            "rag" => TruncateStrategy::Rag,
+            _ => TruncateStrategy::Head,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            TruncateStrategy::Head => "head",
+            TruncateStrategy::Rag => "rag",
+        }
+    }
+}
+
+/// 把文本附件控制在 max_length 个字符以内，返回每个附件的名称和注入内容，以及发生截断的附件
+///
+/// 预算按附件平均分配，短附件用不完的额度留给后面的长附件
+pub fn fit_attachments(
+    attachments: &[&MessageAttachment],
+    query: &str,
+    max_length: usize,
+    strategy: TruncateStrategy,
+) -> (Vec<(String, String)>, Vec<AttachmentTruncation>) {
+    let mut order: Vec<usize> = (0..attachments.len()).collect();
+    order.sort_by_key(|index| content_length(attachments[*index]));
+
+    let mut contents = vec![String::new(); attachments.len()];
+    let mut truncations = Vec::new();
+    let mut remaining = max_length;
+    for (position, index) in order.iter().enumerate() {
+        let attachment = attachments[*index];
+        let content = attachment.attachment_content.clone().unwrap_or_default();
+        let length = content.chars().count();
+        let budget = remaining / (order.len() - position);
+
+        if length <= budget {
+            remaining -= length;
+            contents[*index] = content;
+            continue;
+        }
+
+        let kept = match strategy {
+            TruncateStrategy::Rag if !query.trim().is_empty() => {
+                select_relevant(&content, query, budget)
+            }
+            _ => truncate_head(&content, budget),
+        };
+        let kept_length = kept.chars().count();
+        remaining = remaining.saturating_sub(kept_length);
+        truncations.push(AttachmentTruncation {
+            attachment_id: attachment.id,
+            name: attachment_name(attachment),
+            original_length: length,
+            kept_length,
+            strategy: strategy.as_str().to_string(),
+        });
+        contents[*index] = kept;
+    }
+
+    let result = attachments
+        .iter()
+        .zip(contents)
+        .map(|(attachment, content)| (attachment_name(attachment), content))
+        .collect();
+    (result, truncations)
+}
+
+fn attachment_name(attachment: &MessageAttachment) -> String {
+    attachment.attachment_url.clone().unwrap_or_default()
+}
+
+fn content_length(attachment: &MessageAttachment) -> usize {
+    attachment
+        .attachment_content
+        .as_ref()
+        .map(|content| content.chars().count())
+        .unwrap_or(0)
+}
+
+fn truncate_head(content: &str, budget: usize) -> String {
+    let marker_length = TRUNCATED_MARKER.chars().count();
+    // 额度放不下截断标记时只保留开头，保证结果不超过额度
+    if budget <= marker_length {
+        return content.chars().take(budget).collect();
+    }
+    let keep = budget - marker_length;
+    let head: String = content.chars().take(keep).collect();
+    // 尽量在换行处断开，避免截断半句话
+    let head = match head.rfind('\n') {
+        Some(pos) if pos > head.len() / 2 => head[..pos].to_string(),
+        _ => head,
+    };
+    format!("{}{}", head, TRUNCATED_MARKER)
+}
+
+fn select_relevant(content: &str, query: &str, budget: usize) -> String {
+    let chunks = split_text(content, CHUNK_SIZE, CHUNK_OVERLAP);
+    let query_embedding = embed(query);
+    let mut scored = chunks
+        .iter()
+        .enumerate()
+        .map(|(index, chunk)| (index, cosine_similarity(&query_embedding, &embed(chunk))))
+        .collect::<Vec<_>>();
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let marker_length = OMITTED_MARKER.chars().count();
+    let mut selected = Vec::new();
+    let mut used = 0;
+    for (index, _) in scored {
+        let length = chunks[index].chars().count() + marker_length;
+        if used + length > budget {
+            continue;
+        }
+        used += length;
+        selected.push(index);
+    }
+    if selected.is_empty() {
+        return truncate_head(content, budget);
+    }
+
+    selected.sort();
+    selected
+        .into_iter()
+        .map(|index| chunks[index].as_str())
+        .collect::<Vec<&str>>()
+        .join(OMITTED_MARKER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::conversation_db::AttachmentType;
+
+    fn attachment(id: i64, content: String) -> MessageAttachment {
+        MessageAttachment {
+            id,
+            message_id: Some(1),
+            attachment_type: AttachmentType::Text,
+            attachment_url: Some(format!("{}.txt", id)),
+            attachment_content: Some(content),
+            attachment_hash: None,
+            use_vector: false,
+            token_count: None,
+            ocr_text: None,
+            encoding: None,
+        }
+    }
+
+    #[test]
+    fn test_fit_attachments_within_budget() {
+        let attachments = (1..=4)
+            .map(|id| attachment(id, "内容".repeat(100)))
+            .collect::<Vec<_>>();
+        let refs = attachments.iter().collect::<Vec<_>>();
+        // 每个附件分到的额度比截断标记还短
+        for max_length in [0, 10, 30, 150] {
+            let (contents, truncations) =
+                fit_attachments(&refs, "", max_length, TruncateStrategy::Head);
+            let total: usize = contents.iter().map(|(_, c)| c.chars().count()).sum();
+            assert!(total <= max_length, "{} > {}", total, max_length);
+            assert!(truncations
+                .iter()
+                .all(|t| t.kept_length <= t.original_length));
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use futures::future::BoxFuture;
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+
+use crate::db::system_db::FeatureConfig;
+
+const DEFAULT_SCANNER_TIMEOUT_SECS: u64 = 60;
+
+/// 附件没有通过检查的原因，作为 AppError::AttachmentRejected 返回给前端
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum AttachmentRejection {
+    // 扩展名在禁止列表中，extension 为小写、不带点的扩展名
+    BlockedExtension { extension: String },
+    // size 和 max_size 的单位都是字节
+    TooLarge { size: u64, max_size: u64 },
+    // 扫描程序报告发现问题，detail 为扫描程序的输出
+    Infected { detail: String },
+    // 扫描程序无法运行、超时或者返回了无法识别的结果，按未通过处理
+    ScanFailed { detail: String },
+}
+
+impl fmt::Display for AttachmentRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttachmentRejection::BlockedExtension { extension } => {
+                write!(f, "不允许添加 .{} 类型的文件", extension)
+            }
+            AttachmentRejection::TooLarge { size, max_size } => {
+                write!(f, "文件大小 {} 字节超过了上限 {} 字节", size, max_size)
+            }
+            AttachmentRejection::Infected { detail } => write!(f, "扫描发现问题: {}", detail),
+            AttachmentRejection::ScanFailed { detail } => write!(f, "扫描失败: {}", detail),
+        }
+    }
+}
+
+/// 扫描附件内容的程序，返回 Ok(None) 表示没有发现问题，Ok(Some(detail)) 表示发现问题
+pub trait AttachmentScanner: Send + Sync {
+    fn scan(&self, name: String, content: Vec<u8>) -> BoxFuture<'static, Result<Option<String>>>;
+}
+
+/// 调用本地的扫描程序，文件内容通过 stdin 传入
+///
+/// 按 clamscan 的约定处理退出码：0 表示没有发现问题，1 表示发现问题，其他为扫描出错
+pub struct CommandScanner {
+    command: String,
+    args: Vec<String>,
+    timeout: Duration,
+}
+
+impl AttachmentScanner for CommandScanner {
+    fn scan(&self, name: String, content: Vec<u8>) -> BoxFuture<'static, Result<Option<String>>> {
+        let command = self.command.clone();
+        let args = self.args.clone();
+        let timeout = self.timeout;
+        Box::pin(async move {
+            let mut child = Command::new(&command)
+                .args(&args)
+                .env("AIPP_ATTACHMENT_NAME", &name)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .map_err(|e| anyhow!("启动扫描程序 {} 失败: {}", command, e))?;
+            let mut stdin = child
+                .stdin
+                .take()
+                .ok_or_else(|| anyhow!("无法写入扫描程序的标准输入"))?;
+            let (Some(mut stdout), Some(mut stderr)) = (child.stdout.take(), child.stderr.take())
+            else {
+                bail!("无法读取扫描程序的输出");
+            };
+
+            // 写入内容的同时读取输出，扫描程序边读边输出时不会因为管道写满而互相等待
+            let interaction = async {
+                let write = async move {
+                    let result = stdin.write_all(&content).await;
+                    drop(stdin);
+                    result
+                };
+                let mut output = Vec::new();
+                let mut error = Vec::new();
+                let (written, read_output, read_error) = tokio::join!(
+                    write,
+                    stdout.read_to_end(&mut output),
+                    stderr.read_to_end(&mut error)
+                );
+                // 扫描程序没有读完内容就退出时以退出码为准
+                if let Err(e) = written {
+                    if e.kind() != std::io::ErrorKind::BrokenPipe {
+                        return Err(e);
+                    }
+                }
+                read_output?;
+                read_error?;
+                let status = child.wait().await?;
+                Ok::<_, std::io::Error>((status, output, error))
+            };
+            let (status, output, error) = match tokio::time::timeout(timeout, interaction).await {
+                Ok(result) => result?,
+                Err(_) => {
+                    // kill_on_drop 只在 Child 被丢弃时发送信号，超时后立即结束并回收扫描程序
+                    let _ = child.kill().await;
+                    bail!("扫描超过 {} 秒没有完成", timeout.as_secs());
+                }
+            };
+            let stdout = String::from_utf8_lossy(&output).trim().to_string();
+            match status.code() {
+                Some(0) => Ok(None),
+                Some(1) => Ok(Some(stdout)),
+                code => bail!(
+                    "扫描程序退出码 {:?}: {}",
+                    code,
+                    String::from_utf8_lossy(&error).trim()
+                ),
+            }
+        })
+    }
+}
+
+/// 添加附件前的检查策略，保存在 attachment_policy 功能配置中，默认不做任何限制
+///
+/// 依次检查扩展名禁止列表、文件大小上限，最后调用扫描程序检查内容
+pub struct AttachmentPolicy {
+    blocked_extensions: HashSet<String>,
+    max_size: Option<u64>,
+    scanner: Option<Box<dyn AttachmentScanner>>,
+}
+
+impl AttachmentPolicy {
+    pub fn from_config_map(
+        config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+    ) -> Self {
+        let config = config_feature_map.get("attachment_policy");
+        let get_value = |key: &str| {
+            config
+                .and_then(|config| config.get(key))
+                .map(|config| config.value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+        // 逗号分隔，带不带点、大小写都可以，例如 "exe, .BAT"
+        let blocked_extensions = get_value("blocked_extensions")
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(|extension| extension.trim().trim_start_matches('.').to_lowercase())
+                    .filter(|extension| !extension.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let max_size = get_value("max_size_mb")
+            .and_then(|value| value.parse::<f64>().ok())
+            .filter(|mb| *mb > 0.0)
+            .map(|mb| (mb * 1024.0 * 1024.0) as u64);
+        let scanner = get_value("scanner_path").map(|command| {
+            let scanner: Box<dyn AttachmentScanner> = Box::new(CommandScanner {
+                command,
+                // 参数以空格分隔，默认的 "-" 让 clamscan、clamdscan 从 stdin 读取
+                args: get_value("scanner_args")
+                    .unwrap_or("-".to_string())
+                    .split_whitespace()
+                    .map(|arg| arg.to_string())
+                    .collect(),
+                timeout: Duration::from_secs(
+                    get_value("scanner_timeout_secs")
+                        .and_then(|value| value.parse().ok())
+                        .unwrap_or(DEFAULT_SCANNER_TIMEOUT_SECS),
+                ),
+            });
+            scanner
+        });
+        AttachmentPolicy {
+            blocked_extensions,
+            max_size,
+            scanner,
+        }
+    }
+
+    /// 检查文件名和大小，不需要读取文件内容，在读取文件之前调用
+    pub fn check_file(&self, name: &str, size: u64) -> Result<(), AttachmentRejection> {
+        let extension = Path::new(name)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        if let Some(extension) = extension {
+            if self.blocked_extensions.contains(&extension) {
+                return Err(AttachmentRejection::BlockedExtension { extension });
+            }
+        }
+        match self.max_size {
+            Some(max_size) if size > max_size => {
+                Err(AttachmentRejection::TooLarge { size, max_size })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn has_scanner(&self) -> bool {
+        self.scanner.is_some()
+    }
+
+    /// 调用扫描程序检查文件内容，没有配置扫描程序时直接通过
+    pub async fn scan(&self, name: &str, content: Vec<u8>) -> Result<(), AttachmentRejection> {
+        let Some(scanner) = &self.scanner else {
+            return Ok(());
+        };
+        match scanner.scan(name.to_string(), content).await {
+            Ok(None) => Ok(()),
+            Ok(Some(detail)) => Err(AttachmentRejection::Infected { detail }),
+            Err(e) => Err(AttachmentRejection::ScanFailed {
+                detail: e.to_string(),
+            }),
+        }
+    }
+
+    /// 检查已经在内存中的附件内容
+    pub async fn check(&self, name: &str, content: Vec<u8>) -> Result<(), AttachmentRejection> {
+        self.check_file(name, content.len() as u64)?;
+        self.scan(name, content).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_map(values: &[(&str, &str)]) -> HashMap<String, HashMap<String, FeatureConfig>> {
+        let config = values
+            .iter()
+            .map(|(key, value)| {
+                (
+                    key.to_string(),
+                    FeatureConfig {
+                        id: None,
+                        feature_code: "attachment_policy".to_string(),
+                        key: key.to_string(),
+                        value: value.to_string(),
+                        data_type: "string".to_string(),
+                        description: None,
+                    },
+                )
+            })
+            .collect();
+        HashMap::from([("attachment_policy".to_string(), config)])
+    }
+
+    struct RejectAll;
+
+    impl AttachmentScanner for RejectAll {
+        fn scan(&self, name: String, _: Vec<u8>) -> BoxFuture<'static, Result<Option<String>>> {
+            Box::pin(async move { Ok(Some(format!("{}: Eicar-Signature FOUND", name))) })
+        }
+    }
+
+    #[test]
+    fn test_check_file() {
+        let policy = AttachmentPolicy::from_config_map(&config_map(&[
+            ("blocked_extensions", "exe, .BAT"),
+            ("max_size_mb", "1"),
+        ]));
+        assert_eq!(
+            policy.check_file("setup.EXE", 10),
+            Err(AttachmentRejection::BlockedExtension {
+                extension: "exe".to_string()
+            })
+        );
+        assert!(policy.check_file("run.bat", 10).is_err());
+        assert_eq!(
+            policy.check_file("notes.md", 2 * 1024 * 1024),
+            Err(AttachmentRejection::TooLarge {
+                size: 2 * 1024 * 1024,
+                max_size: 1024 * 1024
+            })
+        );
+        assert_eq!(policy.check_file("notes.md", 1024), Ok(()));
+        assert_eq!(policy.check_file("Makefile", 1024), Ok(()));
+    }
+
+    #[test]
+    fn test_default_policy_allows_everything() {
+        let policy = AttachmentPolicy::from_config_map(&HashMap::new());
+        assert_eq!(policy.check_file("setup.exe", u64::MAX), Ok(()));
+        assert!(policy.scanner.is_none());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_command_scanner() {
+        // 内容超过管道缓冲区，cat 边读边输出，先写完再读输出时会互相等待
+        let scanner = CommandScanner {
+            command: "cat".to_string(),
+            args: Vec::new(),
+            timeout: Duration::from_secs(10),
+        };
+        let content = vec![b'a'; 1024 * 1024];
+        assert_eq!(
+            scanner.scan("a.txt".to_string(), content).await.unwrap(),
+            None
+        );
+
+        let scanner = CommandScanner {
+            command: "sleep".to_string(),
+            args: vec!["30".to_string()],
+            timeout: Duration::from_millis(200),
+        };
+        let start = std::time::Instant::now();
+        let error = scanner
+            .scan("a.txt".to_string(), b"hello".to_vec())
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("没有完成"));
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn test_scanner_rejection() {
+        let policy = AttachmentPolicy {
+            blocked_extensions: HashSet::new(),
+            max_size: None,
+            scanner: Some(Box::new(RejectAll)),
+        };
+        assert_eq!(
+            policy.check("a.txt", b"hello".to_vec()).await,
+            Err(AttachmentRejection::Infected {
+                detail: "a.txt: Eicar-Signature FOUND".to_string()
+            })
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use super::indexer::RetrievedChunk;
+
+/// 回答中引用的知识库分块，index 对应回答里的 [n] 标记
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Citation {
+    pub index: usize,
+    pub chunk_id: i64,
+    pub folder_id: i64,
+    pub file_path: String,
+    pub chunk_index: i64,
+}
+
+pub const CITATION_INSTRUCTION: &str =
+    "回答时如果用到了某条内容，请在对应句子末尾用 [编号] 标注来源，例如 [1]，多个来源写成 [1][2]，没有用到的内容不要标注。";
+
+/// 按检索结果的顺序从 1 开始编号，编号写入提示词中的 knowledge 标签
+pub fn number_chunks(chunks: &[RetrievedChunk]) -> Vec<Citation> {
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(index, retrieved)| Citation {
+            index: index + 1,
+            chunk_id: retrieved.chunk.id,
+            folder_id: retrieved.chunk.folder_id,
+            file_path: retrieved.chunk.file_path.clone(),
+            chunk_index: retrieved.chunk.chunk_index,
+        })
+        .collect()
+}
+
+/// 从完成的回答中解析 [n] 和 [n, m] 形式的引用标记，返回实际被引用的来源，按编号排序并去重
+///
+/// 超出来源编号范围的标记会被忽略，避免把模型编造的编号当成引用；代码块、行内代码和紧跟在
+/// 字母数字后面的标记（例如 arr[1]）是下标而不是引用
+pub fn parse_citations(content: &str, sources: &[Citation]) -> Vec<Citation> {
+    let code = Regex::new(r"(?s)```.*?(?:```|$)|`[^`\n]*`").unwrap();
+    let content = code.replace_all(content, " ");
+    let re = Regex::new(r"\[(\d+(?:\s*[,，]\s*\d+)*)\]").unwrap();
+    let mut indexes = re
+        .captures_iter(&content)
+        .filter(|caps| {
+            let start = caps.get(0).map_or(0, |m| m.start());
+            !content[..start]
+                .chars()
+                .next_back()
+                .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+        .flat_map(|caps| {
+            caps[1]
+                .split([',', '，'])
+                .filter_map(|index| index.trim().parse::<usize>().ok())
+                .collect::<Vec<usize>>()
+        })
+        .collect::<Vec<usize>>();
+    indexes.sort();
+    indexes.dedup();
+
+    indexes
+        .into_iter()
+        .filter_map(|index| sources.iter().find(|source| source.index == index))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources(count: usize) -> Vec<Citation> {
+        (1..=count)
+            .map(|index| Citation {
+                index,
+                chunk_id: index as i64 * 10,
+                folder_id: 1,
+                file_path: format!("/docs/{}.md", index),
+                chunk_index: 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_citations() {
+        let content = "Rust 的所有权在编译期检查[2]。借用规则保证内存安全 [1][2]。";
+        let citations = parse_citations(content, &sources(3));
+        assert_eq!(
+            citations.iter().map(|c| c.index).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(citations[1].chunk_id, 20);
+    }
+
+    #[test]
+    fn test_parse_grouped_citations() {
+        let citations = parse_citations("结论来自多篇文档 [1, 3]，也见[2，3]", &sources(3));
+        assert_eq!(
+            citations.iter().map(|c| c.index).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn test_ignore_unknown_markers() {
+        let content = "数组下标 arr[0] 和不存在的来源 [5] 不算引用，[链接](url) 也不算";
+        assert!(parse_citations(content, &sources(3)).is_empty());
+    }
+
+    #[test]
+    fn test_ignore_code_subscripts() {
+        let content =
+            "取第二个元素 arr[1]，`list[2]` 也是代码\n```rust\nlet x = v[3];\n```\n结论见[2]";
+        let citations = parse_citations(content, &sources(3));
+        assert_eq!(
+            citations.iter().map(|c| c.index).collect::<Vec<_>>(),
+            vec![2]
+        );
+    }
+}
@@ -0,0 +1,71 @@
+// 本地的特征哈希向量，不依赖外部模型服务，分词规则：英文和数字按单词切分，中日韩文字按相邻两个字切分
+pub const EMBEDDING_DIMENSION: usize = 512;
+
+pub fn embed(text: &str) -> Vec<f32> {
+    let mut vector = vec![0f32; EMBEDDING_DIMENSION];
+    for token in tokenize(text) {
+        let hash = fnv1a(token.as_bytes());
+        let index = (hash % EMBEDDING_DIMENSION as u64) as usize;
+        // 用哈希的另一位决定符号，减少哈希冲突带来的偏差
+        let sign = if (hash >> 32) & 1 == 0 { 1.0 } else { -1.0 };
+        vector[index] += sign;
+    }
+
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|v| *v /= norm);
+    }
+    vector
+}
+
+// 两个向量都已经归一化，点积即为余弦相似度
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b.iter()).map(|(x, y)| x * y).sum()
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut last_cjk: Option<char> = None;
+
+    for c in text.chars() {
+        if is_cjk(c) {
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+            if let Some(last) = last_cjk {
+                tokens.push(format!("{}{}", last, c));
+            } else {
+                tokens.push(c.to_string());
+            }
+            last_cjk = Some(c);
+        } else if c.is_alphanumeric() || c == '_' {
+            last_cjk = None;
+            word.extend(c.to_lowercase());
+        } else {
+            last_cjk = None;
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x4E00..=0x9FFF | 0x3400..=0x4DBF | 0x3040..=0x30FF | 0xAC00..=0xD7AF)
+}
+
+// 向量会持久化到数据库，必须使用跨版本稳定的哈希算法，不能用 DefaultHasher
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
@@ -0,0 +1,167 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::api::llm::ChatMessage;
+use crate::db::conversation_db::{AttachmentType, MessageAttachment};
+
+/// 超过模型单次请求图片上限而没有发送的图片
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct OmittedImage {
+    pub attachment_id: i64,
+    pub message_id: Option<i64>,
+    pub name: String,
+}
+
+/// 把消息列表中的图片控制在 max_images 张以内，返回没有发送的图片
+///
+/// 置顶消息中的图片优先保留，其余从最新的消息往前挑选；没有发送的图片从附件中移除，
+/// 并在所属消息后面用文字说明，让模型知道用户附带过这些图片
+pub fn select_images(
+    message_list: &mut [ChatMessage],
+    max_images: usize,
+    pinned_message_ids: &HashSet<i64>,
+) -> Vec<OmittedImage> {
+    // (消息下标, 图片在消息中的位置, 是否置顶)，置顶的在前，同一优先级中越新的越靠前；
+    // 同一个附件可能被多条消息引用，按位置区分
+    let mut candidates: Vec<(usize, usize, bool)> = message_list
+        .iter()
+        .enumerate()
+        .rev()
+        .flat_map(|(message_index, message)| {
+            let images = message.images().collect::<Vec<_>>();
+            images
+                .into_iter()
+                .enumerate()
+                .rev()
+                .map(move |(image_index, a)| {
+                    (
+                        message_index,
+                        image_index,
+                        a.message_id
+                            .is_some_and(|id| pinned_message_ids.contains(&id)),
+                    )
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    candidates.sort_by_key(|(_, _, pinned)| !pinned);
+    if candidates.len() <= max_images {
+        return vec![];
+    }
+
+    let mut omitted_by_message = vec![HashSet::new(); message_list.len()];
+    for (message_index, image_index, _) in candidates.into_iter().skip(max_images) {
+        omitted_by_message[message_index].insert(image_index);
+    }
+
+    let mut omitted = Vec::new();
+    for (message, image_indexes) in message_list.iter_mut().zip(omitted_by_message) {
+        if image_indexes.is_empty() {
+            continue;
+        }
+        let mut image_index = 0;
+        let removed = message.remove_attachments(|a| {
+            if a.attachment_type != AttachmentType::Image {
+                return false;
+            }
+            image_index += 1;
+            image_indexes.contains(&(image_index - 1))
+        });
+        let names = removed.iter().map(image_name).collect::<Vec<_>>();
+        message.push_text(&format!(
+            "\n[用户附带了 {} 张图片（{}），但由于当前模型单次请求最多支持 {} 张图片，这些图片没有发送]",
+            names.len(),
+            names.join(", "),
+            max_images
+        ));
+        omitted.extend(
+            removed
+                .into_iter()
+                .zip(names)
+                .map(|(attachment, name)| OmittedImage {
+                    attachment_id: attachment.id,
+                    message_id: attachment.message_id,
+                    name,
+                }),
+        );
+    }
+    omitted.sort_by_key(|image| (image.message_id, image.attachment_id));
+    omitted
+}
+
+fn image_name(attachment: &MessageAttachment) -> String {
+    attachment
+        .attachment_url
+        .as_deref()
+        .and_then(|url| Path::new(url).file_name())
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "未命名图片".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::llm::Role;
+
+    fn image(id: i64, message_id: i64) -> MessageAttachment {
+        MessageAttachment {
+            id,
+            message_id: Some(message_id),
+            attachment_type: AttachmentType::Image,
+            attachment_url: Some(format!("/images/{}.png", id)),
+            attachment_content: Some("data:image/png;base64,".to_string()),
+            attachment_hash: None,
+            use_vector: false,
+            token_count: None,
+            ocr_text: None,
+            encoding: None,
+        }
+    }
+
+    fn messages() -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::with_attachments(Role::User, "第一张", vec![image(1, 10)]),
+            ChatMessage::new(Role::Assistant, "好的"),
+            ChatMessage::with_attachments(Role::User, "再看两张", vec![image(2, 12), image(3, 12)]),
+        ]
+    }
+
+    fn image_ids(message_list: &[ChatMessage]) -> Vec<i64> {
+        message_list
+            .iter()
+            .flat_map(|message| message.attachments().map(|a| a.id))
+            .collect()
+    }
+
+    #[test]
+    fn test_keep_recent_images() {
+        let mut message_list = messages();
+        let omitted = select_images(&mut message_list, 2, &HashSet::new());
+        assert_eq!(image_ids(&message_list), vec![2, 3]);
+        assert_eq!(
+            omitted.iter().map(|i| i.attachment_id).collect::<Vec<_>>(),
+            vec![1]
+        );
+        assert!(message_list[0].text().contains("1.png"));
+    }
+
+    #[test]
+    fn test_keep_pinned_images() {
+        let mut message_list = messages();
+        let omitted = select_images(&mut message_list, 2, &HashSet::from([10]));
+        assert_eq!(image_ids(&message_list), vec![1, 3]);
+        assert_eq!(
+            omitted.iter().map(|i| i.attachment_id).collect::<Vec<_>>(),
+            vec![2]
+        );
+    }
+
+    #[test]
+    fn test_within_limit() {
+        let mut message_list = messages();
+        assert!(select_images(&mut message_list, 3, &HashSet::new()).is_empty());
+        assert_eq!(message_list[0].text(), "第一张");
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::UNIX_EPOCH;
+
+use mime_guess::from_path;
+use serde::Serialize;
+use tauri::Emitter;
+
+use crate::db::knowledge_db::{ContextChunk, KnowledgeDatabase};
+use crate::errors::AppError;
+use crate::AppHandle;
+
+use super::embedding::{cosine_similarity, embed};
+
+// 单个文件超过这个大小不进行索引，避免日志、数据文件占满知识库
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+const CHUNK_SIZE: usize = 800;
+const CHUNK_OVERLAP: usize = 100;
+// 每索引多少个文件发送一次进度事件
+const PROGRESS_EVENT_INTERVAL: usize = 20;
+
+const IGNORED_DIRS: &[&str] = &[
+    "node_modules",
+    "target",
+    "dist",
+    "build",
+    "__pycache__",
+    "venv",
+];
+
+const TEXT_EXTENSIONS: &[&str] = &[
+    "txt", "md", "markdown", "rst", "json", "yaml", "yml", "toml", "csv", "html", "htm", "xml",
+    "rs", "py", "js", "ts", "tsx", "jsx", "java", "go", "c", "h", "cpp", "hpp", "cs", "rb", "php",
+    "swift", "kt", "sql", "sh", "vue", "css", "scss", "ini", "cfg", "conf",
+];
+
+static INDEXING_FOLDERS: OnceLock<Mutex<HashSet<i64>>> = OnceLock::new();
+
+#[derive(Debug, Serialize, Clone)]
+pub struct IndexStatusEvent {
+    pub folder_id: i64,
+    pub status: String,
+    pub indexed_files: usize,
+    pub total_files: usize,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct RetrievedChunk {
+    pub chunk: ContextChunk,
+    pub score: f32,
+}
+
+// 同一个文件夹同时只允许一个索引任务，离开作用域时自动释放
+struct IndexingGuard(i64);
+
+impl IndexingGuard {
+    fn acquire(folder_id: i64) -> Option<IndexingGuard> {
+        let mut indexing = INDEXING_FOLDERS
+            .get_or_init(|| Mutex::new(HashSet::new()))
+            .lock()
+            .unwrap();
+        if indexing.insert(folder_id) {
+            Some(IndexingGuard(folder_id))
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for IndexingGuard {
+    fn drop(&mut self) {
+        if let Some(indexing) = INDEXING_FOLDERS.get() {
+            indexing.lock().unwrap().remove(&self.0);
+        }
+    }
+}
+
+/// 索引上下文文件夹，full 为 false 时只处理新增、修改和删除的文件
+///
+/// 这是一个阻塞操作，需要在 spawn_blocking 中调用
+pub fn index_folder(app_handle: &AppHandle, folder_id: i64, full: bool) -> Result<(), AppError> {
+    let _guard = match IndexingGuard::acquire(folder_id) {
+        Some(guard) => guard,
+        None => {
+            println!("context folder {} is indexing, skip", folder_id);
+            return Ok(());
+        }
+    };
+
+    let mut db = KnowledgeDatabase::new(app_handle)?;
+    let folder = db
+        .get_context_folder(folder_id)?
+        .ok_or(AppError::DatabaseError("未找到上下文文件夹".to_string()))?;
+
+    let root = PathBuf::from(&folder.path);
+    if !root.is_dir() {
+        let message = "文件夹不存在或无法访问".to_string();
+        db.update_context_folder_status(folder_id, "error", Some(&message))?;
+        emit_status(app_handle, folder_id, "error", 0, 0, Some(message));
+        return Ok(());
+    }
+
+    let mut files = Vec::new();
+    collect_files(&root, &mut files);
+    let known_files: HashMap<String, (i64, i64)> = db
+        .get_context_files(folder_id)?
+        .into_iter()
+        .map(|(path, modified_time, file_size)| (path, (modified_time, file_size)))
+        .collect();
+
+    let current_paths: HashSet<String> = files.iter().map(|(path, _, _)| path.clone()).collect();
+    let removed_files: Vec<&String> = known_files
+        .keys()
+        .filter(|path| !current_paths.contains(*path))
+        .collect();
+    let changed_files: Vec<&(String, i64, i64)> = files
+        .iter()
+        .filter(|(path, modified_time, file_size)| {
+            full || known_files.get(path) != Some(&(*modified_time, *file_size))
+        })
+        .collect();
+
+    if !full && removed_files.is_empty() && changed_files.is_empty() {
+        return Ok(());
+    }
+
+    db.update_context_folder_status(folder_id, "indexing", None)?;
+    emit_status(
+        app_handle,
+        folder_id,
+        "indexing",
+        0,
+        changed_files.len(),
+        None,
+    );
+
+    for path in removed_files {
+        db.delete_file_chunks(folder_id, path)?;
+    }
+
+    for (index, (path, modified_time, file_size)) in changed_files.iter().enumerate() {
+        // 二进制或非 UTF-8 的文件直接跳过，但仍然记录下来避免每次都重新尝试
+        let content = fs::read_to_string(path).unwrap_or_default();
+        let chunks = split_text(&content, CHUNK_SIZE, CHUNK_OVERLAP)
+            .into_iter()
+            .map(|chunk| {
+                let embedding = embed(&chunk);
+                (chunk, embedding)
+            })
+            .collect::<Vec<_>>();
+        if let Err(e) = db.replace_file_chunks(folder_id, path, *modified_time, *file_size, &chunks)
+        {
+            let message = format!("索引文件 {} 失败: {}", path, e);
+            db.update_context_folder_status(folder_id, "error", Some(&message))?;
+            emit_status(
+                app_handle,
+                folder_id,
+                "error",
+                index,
+                changed_files.len(),
+                Some(message),
+            );
+            return Err(AppError::from(e));
+        }
+
+        if (index + 1) % PROGRESS_EVENT_INTERVAL == 0 {
+            emit_status(
+                app_handle,
+                folder_id,
+                "indexing",
+                index + 1,
+                changed_files.len(),
+                None,
+            );
+        }
+    }
+
+    db.finish_context_folder_index(folder_id)?;
+    emit_status(
+        app_handle,
+        folder_id,
+        "ready",
+        changed_files.len(),
+        changed_files.len(),
+        None,
+    );
+    Ok(())
+}
+
+/// 从指定的文件夹中检索和问题最相关的分块
+pub fn retrieve(
+    app_handle: &AppHandle,
+    folder_ids: &Vec<i64>,
+    query: &str,
+    top_k: usize,
+) -> Result<Vec<RetrievedChunk>, AppError> {
+    if folder_ids.is_empty() || query.trim().is_empty() {
+        return Ok(vec![]);
+    }
+    let db = KnowledgeDatabase::new(app_handle)?;
+    let query_embedding = embed(query);
+
+    let mut result = db
+        .get_chunks_by_folder_ids(folder_ids)?
+        .into_iter()
+        .map(|chunk| {
+            let score = cosine_similarity(&query_embedding, &chunk.embedding);
+            RetrievedChunk { chunk, score }
+        })
+        .filter(|retrieved| retrieved.score > 0.0)
+        .collect::<Vec<_>>();
+    result.sort_by(|a, b| b.score.total_cmp(&a.score));
+    result.truncate(top_k);
+    Ok(result)
+}
+
+/// 按字符切分文本，尽量在换行处断开，相邻分块之间保留 overlap 个字符的重叠
+pub fn split_text(text: &str, chunk_size: usize, overlap: usize) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        let mut end = (start + chunk_size).min(chars.len());
+        if end < chars.len() {
+            let search_start = start + chunk_size / 2;
+            if let Some(pos) = chars[search_start..end].iter().rposition(|c| *c == '\n') {
+                end = search_start + pos + 1;
+            }
+        }
+
+        let chunk: String = chars[start..end].iter().collect();
+        if !chunk.trim().is_empty() {
+            chunks.push(chunk);
+        }
+        if end >= chars.len() {
+            break;
+        }
+        start = end.saturating_sub(overlap).max(start + 1);
+    }
+    chunks
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<(String, i64, i64)>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            println!("read dir {:?} error: {}", dir, e);
+            return;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if file_name.starts_with('.') {
+            continue;
+        }
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+
+        if metadata.is_dir() {
+            if !IGNORED_DIRS.contains(&file_name.as_str()) {
+                collect_files(&path, files);
+            }
+        } else if metadata.is_file() && metadata.len() <= MAX_FILE_SIZE && is_text_file(&path) {
+            let modified_time = metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0);
+            files.push((
+                path.to_string_lossy().to_string(),
+                modified_time,
+                metadata.len() as i64,
+            ));
+        }
+    }
+}
+
+fn is_text_file(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    TEXT_EXTENSIONS.contains(&extension.as_str())
+        || from_path(path)
+            .first()
+            .map(|mime| mime.type_() == "text")
+            .unwrap_or(false)
+}
+
+fn emit_status(
+    app_handle: &AppHandle,
+    folder_id: i64,
+    status: &str,
+    indexed_files: usize,
+    total_files: usize,
+    message: Option<String>,
+) {
+    let _ = app_handle.emit(
+        "context_folder_index_status",
+        IndexStatusEvent {
+            folder_id,
+            status: status.to_string(),
+            indexed_files,
+            total_files,
+            message,
+        },
+    );
+}
@@ -0,0 +1,10 @@
+pub mod attachment_budget;
+pub mod attachment_policy;
+pub mod citation;
+pub mod embedding;
+pub mod image_budget;
+pub mod indexer;
+pub mod injection_guard;
+pub mod ocr;
+pub mod text_encoding;
+pub mod watcher;
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::future::BoxFuture;
+use reqwest::Client;
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::knowledge::injection_guard::escape_attribute;
+
+use crate::api::llm::{client_builder, is_local_endpoint, send_request};
+use crate::db::system_db::FeatureConfig;
+
+const DEFAULT_TESSERACT_LANGUAGE: &str = "chi_sim+eng";
+// 单张图片的识别时间上限，和云端 OCR 服务的请求超时一致
+const TESSERACT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 从图片中识别文字，本地 tesseract 和云端 OCR 服务都实现这个 trait
+pub trait OcrEngine: Send + Sync {
+    fn recognize(&self, image: Vec<u8>) -> BoxFuture<'static, Result<String>>;
+}
+
+/// 调用本地安装的 tesseract 命令行，图片通过 stdin 传入，识别结果从 stdout 读取
+pub struct TesseractOcr {
+    command: String,
+    language: String,
+}
+
+impl OcrEngine for TesseractOcr {
+    fn recognize(&self, image: Vec<u8>) -> BoxFuture<'static, Result<String>> {
+        let command = self.command.clone();
+        let language = self.language.clone();
+        Box::pin(async move {
+            let mut child = Command::new(&command)
+                .args(["stdin", "stdout", "-l", &language])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .map_err(|e| anyhow!("启动 {} 失败，请确认已经安装 tesseract: {}", command, e))?;
+            let mut stdin = child
+                .stdin
+                .take()
+                .ok_or_else(|| anyhow!("无法写入 tesseract 的标准输入"))?;
+            // 超时后 child 被丢弃，kill_on_drop 会结束卡住的 tesseract 进程
+            let output = tokio::time::timeout(TESSERACT_TIMEOUT, async move {
+                stdin.write_all(&image).await?;
+                drop(stdin);
+                child.wait_with_output().await
+            })
+            .await
+            .map_err(|_| anyhow!("tesseract 识别超时"))??;
+            if !output.status.success() {
+                bail!(
+                    "tesseract 识别失败: {}",
+                    String::from_utf8_lossy(&output.stderr)
+                );
+            }
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        })
+    }
+}
+
+/// 云端 OCR 服务，向 endpoint 发送 `{"image": base64, "language": ..}`，从返回 JSON 的 text 字段读取结果
+pub struct HttpOcr {
+    client: Client,
+    endpoint: String,
+    api_key: Option<String>,
+    language: String,
+}
+
+impl OcrEngine for HttpOcr {
+    fn recognize(&self, image: Vec<u8>) -> BoxFuture<'static, Result<String>> {
+        let client = self.client.clone();
+        let endpoint = self.endpoint.clone();
+        let api_key = self.api_key.clone();
+        let language = self.language.clone();
+        Box::pin(async move {
+            let mut request = client.post(&endpoint).json(&json!({
+                "image": STANDARD.encode(&image),
+                "language": language,
+            }));
+            if let Some(api_key) = api_key {
+                request = request.bearer_auth(api_key);
+            }
+            let response = send_request(request).await?.error_for_status()?;
+            let value = response.json::<serde_json::Value>().await?;
+            value["text"]
+                .as_str()
+                .map(|text| text.trim().to_string())
+                .ok_or_else(|| anyhow!("OCR 服务返回的结果中没有 text 字段"))
+        })
+    }
+}
+
+/// 根据 ocr 的 engine 配置创建识别引擎，off 或者配置不完整时返回 None；
+/// 离线模式下云端 OCR 服务的地址不是本机时同样返回 None
+pub fn get_ocr_engine(
+    config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+    offline: bool,
+) -> Option<Box<dyn OcrEngine>> {
+    let config = config_feature_map.get("ocr");
+    let get_value = |key: &str| {
+        config
+            .and_then(|config| config.get(key))
+            .map(|config| config.value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    let language = get_value("language").unwrap_or(DEFAULT_TESSERACT_LANGUAGE.to_string());
+    match get_value("engine").as_deref().unwrap_or("tesseract") {
+        "tesseract" => Some(Box::new(TesseractOcr {
+            command: get_value("tesseract_path").unwrap_or("tesseract".to_string()),
+            language,
+        })),
+        "http" => {
+            let endpoint = get_value("endpoint")?;
+            if offline && !is_local_endpoint(&endpoint) {
+                println!("offline mode, skip ocr service {}", endpoint);
+                return None;
+            }
+            Some(Box::new(HttpOcr {
+                client: client_builder()
+                    .timeout(Duration::from_secs(60))
+                    .build()
+                    .map_err(|e| println!("create ocr client error: {}", e))
+                    .ok()?,
+                endpoint,
+                api_key: get_value("api_key"),
+                language,
+            }))
+        }
+        _ => None,
+    }
+}
+
+/// 解析图片附件保存的 data URL（data:image/png;base64,...），返回图片的原始字节
+pub fn decode_image_content(content: &str) -> Result<Vec<u8>> {
+    let base64_str = match content.split_once(";base64,") {
+        Some((_, data)) => data,
+        None => content,
+    };
+    Ok(STANDARD.decode(base64_str.trim())?)
+}
+
+/// OCR 文字注入提示词时的格式，注明内容来自 OCR 识别，提醒模型可能存在识别错误
+pub fn format_ocr_context(name: &str, text: &str) -> String {
+    format!(
+        r#"<ocr name="{}" note="以下文字由 OCR 从图片中识别得到，可能存在识别错误">{}</ocr>"#,
+        escape_attribute(name),
+        text
+    )
+}
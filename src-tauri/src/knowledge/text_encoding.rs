@@ -0,0 +1,56 @@
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8};
+
+/// 把文本文件的原始字节转换成 UTF-8，返回转换后的内容和检测到的编码名称，例如 UTF-8、GBK、Shift_JIS
+///
+/// 有 BOM 时按 BOM 的编码读取，合法的 UTF-8 直接使用，其他情况由 chardetng 按内容猜测编码，
+/// 无法识别的字节替换为 U+FFFD，不会因为编码问题读取失败
+pub fn decode_text(bytes: &[u8]) -> (String, &'static str) {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(bytes) {
+        let (content, _) = encoding.decode_without_bom_handling(&bytes[bom_length..]);
+        return (content.into_owned(), encoding.name());
+    }
+    if let Ok(content) = std::str::from_utf8(bytes) {
+        return (content.to_string(), UTF_8.name());
+    }
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    let encoding = detector.guess(None, true);
+    let (content, _) = encoding.decode_without_bom_handling(bytes);
+    (content.into_owned(), encoding.name())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{GBK, SHIFT_JIS, UTF_16LE, WINDOWS_1252};
+
+    #[test]
+    fn test_decode_text() {
+        assert_eq!(
+            decode_text("你好，world".as_bytes()),
+            ("你好，world".to_string(), "UTF-8")
+        );
+        assert_eq!(
+            decode_text(b"\xEF\xBB\xBFhello"),
+            ("hello".to_string(), "UTF-8")
+        );
+
+        let text = "这是一个用于测试编码检测的中文文本文件，保存时使用了简体中文系统默认的编码。";
+        let (bytes, _, _) = GBK.encode(text);
+        assert_eq!(decode_text(&bytes), (text.to_string(), "GBK"));
+
+        let text = "これは文字コードの検出をテストするための日本語のテキストファイルです。";
+        let (bytes, _, _) = SHIFT_JIS.encode(text);
+        assert_eq!(decode_text(&bytes).0, text);
+
+        let text = "Café crème brûlée, a naïve façade in the château.";
+        let (bytes, _, _) = WINDOWS_1252.encode(text);
+        assert_eq!(decode_text(&bytes).0, text);
+
+        // UTF-16 文件通过 BOM 识别
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend("hi".encode_utf16().flat_map(|unit| unit.to_le_bytes()));
+        assert_eq!(decode_text(&bytes), ("hi".to_string(), UTF_16LE.name()));
+    }
+}
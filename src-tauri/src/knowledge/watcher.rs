@@ -0,0 +1,137 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+
+use crate::db::knowledge_db::KnowledgeDatabase;
+use crate::AppHandle;
+
+use super::indexer::index_folder;
+
+// 文件变化后等待这么久没有新的变化再更新索引，编辑器保存文件时往往连续写入多次
+const DEBOUNCE: Duration = Duration::from_secs(2);
+// 重新读取上下文文件夹列表的间隔，新增和删除的文件夹在这之后开始或者停止监听
+const FOLDER_SYNC_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 监听所有上下文文件夹中的文件变化，变化停止一段时间后增量更新对应文件夹的索引
+///
+/// 开始监听一个文件夹时先增量更新一次，应用没有运行期间的修改也会更新到索引中
+pub fn start_context_folder_watcher(app_handle: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let (tx, mut rx) = mpsc::unbounded_channel::<notify::Result<Event>>();
+        let mut watcher = match notify::recommended_watcher(move |event| {
+            let _ = tx.send(event);
+        }) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                println!("create context folder watcher error: {}", e);
+                return;
+            }
+        };
+        let mut watched: HashMap<i64, PathBuf> = HashMap::new();
+        let mut pending: HashSet<i64> = HashSet::new();
+        let mut sync = tokio::time::interval(FOLDER_SYNC_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = sync.tick() => {
+                    pending.extend(sync_watched_folders(&app_handle, &mut watcher, &mut watched));
+                }
+                Some(event) = rx.recv() => match event {
+                    Ok(event) if !event.kind.is_access() => {
+                        pending.extend(changed_folders(&watched, &event.paths));
+                    }
+                    Ok(_) => {}
+                    Err(e) => println!("context folder watcher error: {}", e),
+                },
+                // 每次收到新的变化都会重新开始计时
+                _ = tokio::time::sleep(DEBOUNCE), if !pending.is_empty() => {
+                    for folder_id in pending.drain().collect::<Vec<_>>() {
+                        let app_handle_clone = app_handle.clone();
+                        let result = tauri::async_runtime::spawn_blocking(move || {
+                            index_folder(&app_handle_clone, folder_id, false)
+                        })
+                        .await;
+                        if let Ok(Err(e)) = result {
+                            println!("context folder {} index error: {:?}", folder_id, e);
+                        }
+                    }
+                }
+            }
+        }
+    });
+}
+
+// 按数据库中的上下文文件夹更新监听列表，返回新开始监听的文件夹
+fn sync_watched_folders(
+    app_handle: &AppHandle,
+    watcher: &mut RecommendedWatcher,
+    watched: &mut HashMap<i64, PathBuf>,
+) -> Vec<i64> {
+    let folders = match KnowledgeDatabase::new(app_handle).and_then(|db| db.get_context_folders()) {
+        Ok(folders) => folders,
+        Err(e) => {
+            println!("context folder watcher load folders error: {}", e);
+            return vec![];
+        }
+    };
+    let folder_ids: HashSet<i64> = folders.iter().map(|folder| folder.id).collect();
+    watched.retain(|folder_id, path| {
+        if folder_ids.contains(folder_id) {
+            return true;
+        }
+        let _ = watcher.unwatch(path);
+        false
+    });
+
+    let mut added = vec![];
+    for folder in folders {
+        if watched.contains_key(&folder.id) {
+            continue;
+        }
+        let path = PathBuf::from(&folder.path);
+        match watcher.watch(&path, RecursiveMode::Recursive) {
+            Ok(()) => {
+                watched.insert(folder.id, path);
+                added.push(folder.id);
+            }
+            // 文件夹不存在或者没有权限时下次同步再试
+            Err(e) => println!("watch context folder {} error: {}", folder.path, e),
+        }
+    }
+    added
+}
+
+// 发生变化的路径所在的上下文文件夹
+fn changed_folders(watched: &HashMap<i64, PathBuf>, paths: &[PathBuf]) -> Vec<i64> {
+    watched
+        .iter()
+        .filter(|(_, folder)| paths.iter().any(|path| path.starts_with(folder)))
+        .map(|(folder_id, _)| *folder_id)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changed_folders() {
+        let watched = HashMap::from([
+            (1, PathBuf::from("/home/user/notes")),
+            (2, PathBuf::from("/home/user/code")),
+        ]);
+        let mut folder_ids = changed_folders(
+            &watched,
+            &[
+                PathBuf::from("/home/user/notes/a.md"),
+                PathBuf::from("/home/user/code/src/main.rs"),
+            ],
+        );
+        folder_ids.sort();
+        assert_eq!(folder_ids, vec![1, 2]);
+        // 按路径组件比较，notes2 不属于 notes
+        assert!(changed_folders(&watched, &[PathBuf::from("/home/user/notes2/a.md")]).is_empty());
+    }
+}
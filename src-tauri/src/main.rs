@@ -3,59 +3,156 @@
     windows_subsystem = "windows"
 )]
 
+mod active_window;
 mod api;
 mod artifacts;
+mod audit_log;
+mod conversation_cost;
+mod conversation_export;
+mod crypto;
 mod db;
 mod errors;
+mod group_chat;
+mod knowledge;
 mod plugin;
+mod quote;
+mod retention;
+mod selection;
+mod shortcut;
+mod shutdown;
 mod state;
 mod template_engine;
+#[cfg(test)]
+mod test_harness;
+mod title_backfill;
+mod tokenizer;
+mod tools;
+mod topic_split;
+mod translation;
+mod tray;
+mod voice;
+mod webhook;
 mod window;
+mod window_placement;
 
-use crate::api::ai_api::{ask_ai, cancel_ai, regenerate_ai};
+use crate::api::ai_api::{
+    ask_ai, cancel_ai, continue_generation, get_interrupted_messages, preload_assistant_model,
+    preload_model, preview_request, prewarm_fast_mode, recover_interrupted_messages, regenerate_ai,
+    regenerate_with_model, replay_generation, resume_incomplete_message, InterruptedMessagesState,
+};
 use crate::api::artifacts_api::run_artifacts;
 use crate::api::assistant_api::{
-    add_assistant, copy_assistant, delete_assistant, get_assistant, get_assistant_field_value,
-    get_assistants, save_assistant,
+    add_assistant, add_assistant_context_item, add_assistant_skill, clone_assistant,
+    copy_assistant, delete_assistant, delete_assistant_example, delete_assistant_preset,
+    delete_skill, favorite_assistant, get_assistant, get_assistant_config_schema,
+    get_assistant_context_items, get_assistant_field_value, get_assistants,
+    list_assistant_examples, list_assistant_presets, list_assistant_skills, list_assistants,
+    list_skills, remove_assistant_context_item, remove_assistant_skill, reorder_assistant_examples,
+    save_assistant, save_assistant_example, save_assistant_preset, save_skill, set_active_preset,
+    set_assistant_category, set_assistant_skill_enabled,
+};
+use crate::api::attachment_api::{
+    add_attachment, add_attachment_from_clipboard, list_conversation_attachments,
+    open_attachment_with_default_app,
 };
-use crate::api::attachment_api::{add_attachment, open_attachment_with_default_app};
+use crate::api::config_api::{export_app_config, import_app_config};
 use crate::api::conversation_api::{
-    delete_conversation, get_conversation_with_messages, list_conversations, update_conversation,
+    backfill_conversation_titles, cancel_conversation_title_backfill, delete_conversation,
+    delete_glossary_term, delete_message, export_conversation, get_activity_calendar,
+    get_conversation_cost, get_conversation_style, get_conversation_with_messages, get_draft,
+    get_viewer_conversation, list_conversations, list_glossary_terms, lock_conversation,
+    mark_conversation_read, merge_conversations, pin_message, quote_message, recent_conversations,
+    redo_last_operation, save_draft, save_glossary_term, search_prompt_history,
+    set_conversation_private, set_conversation_style, set_model_price, suggest_split,
+    translate_message, undo_last_operation, unlock_conversation, update_conversation,
+    update_message_content,
+};
+use crate::api::experiment_api::{get_experiment_stats, run_experiment, vote_experiment};
+use crate::api::knowledge_api::{
+    add_context_folder, list_context_folders, reindex_folder, remove_context_folder,
 };
 use crate::api::llm_api::{
-    add_llm_model, add_llm_provider, delete_llm_model, delete_llm_provider, fetch_model_list,
-    get_llm_models, get_llm_provider_config, get_llm_providers, get_models_for_select,
-    update_llm_provider, update_llm_provider_config,
+    add_egress_host, add_llm_model, add_llm_provider, clear_response_cache, delete_llm_model,
+    delete_llm_provider, estimate_tokens, fetch_model_list, get_concise_mode, get_egress_allowlist,
+    get_generation_debug, get_llm_models, get_llm_provider_config, get_llm_providers,
+    get_model_param_schema, get_models_for_select, get_offline_mode, get_response_cache_stats,
+    load_egress_allowlist, set_concise_mode, set_egress_allowlist, set_generation_debug,
+    set_models_enabled, set_offline_mode, update_llm_provider, update_llm_provider_config,
+    validate_provider_config,
+};
+use crate::api::quick_action_api::{
+    apply_quick_action_output, delete_quick_action, list_quick_actions, save_quick_action,
 };
 use crate::api::system_api::{
-    get_all_feature_config, get_bang_list, get_selected_text_api, open_data_folder,
-    save_feature_config,
+    get_all_feature_config, get_bang_list, get_selected_text_api, get_startup_diagnostics,
+    get_theme, is_busy, open_data_folder, quit_app, reregister_shortcuts, save_feature_config,
+    set_theme, test_webhook,
 };
+use crate::api::tool_api::{call_tool, list_tool_invocations, list_tools};
+use crate::api::voice_api::{start_voice_input, stop_voice_input, VoiceInputState};
+use crate::audit_log::{export_audit_log, verify_audit_log, verify_exported_audit_log};
 use crate::db::assistant_db::AssistantDatabase;
+use crate::db::knowledge_db::KnowledgeDatabase;
 use crate::db::llm_db::LLMDatabase;
 use crate::db::system_db::SystemDatabase;
 use crate::window::{
-    create_ask_window, open_chat_ui_window, open_config_window, open_plugin_window,
+    apply_privacy_mode, create_ask_window, delete_named_window_session,
+    delete_window_placement_profile, get_privacy_mode, get_window_conversation, is_command_allowed,
+    is_privacy_mode, list_named_window_sessions, list_window_placement_profiles,
+    open_chat_ui_window, open_config_window, open_plugin_window, open_viewer_window,
+    restore_named_window_session, restore_window_sessions, save_named_window_session,
+    save_window_placement_profile, set_privacy_mode, set_window_conversation, PrivacyModeMenuState,
 };
+use active_window::active_window;
 use chrono::Local;
 use db::conversation_db::ConversationDatabase;
 use db::database_upgrade;
+use db::health::{
+    backup_databases, run_startup_check, StartupDiagnostics, StartupDiagnosticsState,
+};
 use db::plugin_db::PluginDatabase;
 use db::system_db::FeatureConfig;
-use get_selected_text::get_selected_text;
+use knowledge::watcher::start_context_folder_watcher;
+use selection::{
+    read_clipboard_text, read_selected_text, BlockedAction, SelectionBlocklist, SelectionFallback,
+    DEFAULT_BLOCKED_APPS,
+};
 use serde::{Deserialize, Serialize};
+use state::audit_log::AuditLogState;
 use state::message_token::MessageTokenManager;
+use state::model_preload::ModelPreloadState;
+use state::private_conversation::PrivateConversationState;
+use state::request_dedup::RequestDedupState;
+use state::response_cache::ResponseCacheState;
+use state::tokenizer::TokenizerState;
+use state::window_focus::WindowFocusState;
+use state::window_session::WindowSessionState;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Instant;
 use tauri::Emitter;
 use tauri::{
-    menu::{MenuBuilder, MenuItemBuilder},
-    Manager, RunEvent,
+    menu::{CheckMenuItemBuilder, MenuBuilder, MenuItemBuilder},
+    Manager, RunEvent, WindowEvent,
 };
 use tokio::sync::Mutex as TokioMutex;
+use tray::{cancel_all_generations, watch_generations, GenerationMenuState};
+
+// 命令和状态使用的运行时，测试时换成 tauri 的模拟运行时，不需要窗口系统也能通过 invoke 层调用命令
+#[cfg(not(test))]
+type AppRuntime = tauri::Wry;
+#[cfg(test)]
+type AppRuntime = tauri::test::MockRuntime;
+type AppHandle = tauri::AppHandle<AppRuntime>;
+type Window = tauri::Window<AppRuntime>;
+type WebviewWindow = tauri::WebviewWindow<AppRuntime>;
 
 struct AppState {
     selected_text: TokioMutex<String>,
+    // 按下划词快捷键时焦点所在的应用名称，取不到时为空
+    selected_app: TokioMutex<String>,
+    // 最近一次按下划词快捷键的时间，用于统计快速模式从按键到第一段内容的耗时
+    shortcut_pressed_at: TokioMutex<Option<Instant>>,
 }
 
 #[derive(Clone)]
@@ -94,8 +191,18 @@ fn query_accessibility_permissions() -> bool {
 }
 
 #[tauri::command]
-async fn get_selected() -> Result<String, String> {
-    let result = get_selected_text().unwrap_or_default();
+async fn get_selected(
+    feature_config_state: tauri::State<'_, FeatureConfigState>,
+) -> Result<String, String> {
+    let fallback = {
+        let config_feature_map = feature_config_state.config_feature_map.lock().await;
+        SelectionFallback::from_feature_config(&config_feature_map)
+    };
+    // 模拟复制时会等待剪贴板更新，不能阻塞异步运行时
+    let result = tauri::async_runtime::spawn_blocking(move || read_selected_text(fallback))
+        .await
+        .map_err(|e| e.to_string())?
+        .unwrap_or_default();
     println!("{:?}", result);
     Ok(result)
 }
@@ -116,7 +223,7 @@ async fn get_config(state: tauri::State<'_, AppState>) -> Result<Config, String>
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let app = tauri::Builder::default()
+    let app = manage_state(tauri::Builder::<AppRuntime>::new())
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_opener::init())
@@ -128,7 +235,17 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             // 系统托盘菜单和图标初始化
             let quit = MenuItemBuilder::with_id("quit", "退出").build(app)?;
             let show = MenuItemBuilder::with_id("show", "显示").build(app)?;
-            let tray_menu = MenuBuilder::new(app).items(&[&show, &quit]).build()?;
+            let privacy_mode = CheckMenuItemBuilder::with_id("privacy_mode", "隐私模式")
+                .checked(is_privacy_mode(app_handle))
+                .build(app)?;
+            let cancel_all = MenuItemBuilder::with_id("cancel_all", "取消所有生成")
+                .enabled(false)
+                .build(app)?;
+            let tray_menu = MenuBuilder::new(app)
+                .items(&[&show, &privacy_mode, &cancel_all, &quit])
+                .build()?;
+            app.manage(PrivacyModeMenuState(privacy_mode));
+            app.manage(GenerationMenuState(cancel_all));
 
             let tray = app.tray_by_id("aipp").unwrap();
             tray.set_menu(Some(tray_menu))?;
@@ -139,9 +256,19 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 "show" => {
                     handle_open_ask_window(&app);
                 }
+                "privacy_mode" => {
+                    if let Err(e) = apply_privacy_mode(app, !is_privacy_mode(app)) {
+                        println!("set privacy mode error: {}", e);
+                    }
+                }
+                "cancel_all" => {
+                    tauri::async_runtime::spawn(cancel_all_generations(app.clone()));
+                }
                 _ => {}
             });
             let _ = tray.set_show_menu_on_left_click(true);
+            // 生成进行中时托盘显示进度，生成全部结束后恢复
+            tauri::async_runtime::spawn(watch_generations(app_handle.clone()));
 
             if !query_accessibility_permissions() {
                 println!("Please grant accessibility permissions to the app");
@@ -149,11 +276,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 // 注册全局快捷键
                 #[cfg(desktop)]
                 {
-                    use tauri_plugin_global_shortcut::{Code, Modifiers, Shortcut, ShortcutState};
-                    let ctrl_shift_i_shortcut =
-                        Shortcut::new(Some(Modifiers::CONTROL | Modifiers::SHIFT), Code::KeyI);
-                    let ctrl_shift_o_shortcut =
-                        Shortcut::new(Some(Modifiers::CONTROL | Modifiers::SHIFT), Code::KeyO);
+                    use tauri_plugin_global_shortcut::ShortcutState;
+                    let ctrl_shift_i_shortcut = shortcut::selection_shortcut(app_handle);
+                    let ctrl_shift_o_shortcut = shortcut::ask_window_shortcut(app_handle);
 
                     app.handle().plugin(
                         tauri_plugin_global_shortcut::Builder::new()
@@ -164,40 +289,33 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                     match event.state() {
                                         ShortcutState::Pressed => {
                                             println!("CmdOrCtrl+Shift+I Pressed!");
+                                            if let Some(app_state) = _app.try_state::<AppState>() {
+                                                *app_state.shortcut_pressed_at.blocking_lock() =
+                                                    Some(Instant::now());
+                                            }
+                                            // 松开快捷键之前就开始和快速模式的提供商建立连接，划词弹窗使用默认助手
+                                            tauri::async_runtime::spawn(prewarm_fast_mode(
+                                                _app.clone(),
+                                                1,
+                                            ));
                                         }
                                         ShortcutState::Released => {
                                             println!(
                                                 "CmdOrCtrl+Shift+I pressed at time : {}",
                                                 &Local::now().to_string()
                                             );
-                                            match get_selected_text() {
-                                                Ok(selected_text) => {
-                                                    println!(
-                                                        "Selected text: {}, at time: {}",
-                                                        selected_text.clone(),
-                                                        &Local::now().to_string()
-                                                    );
-                                                    let _ = _app.emit(
-                                                        "get_selected_text_event",
-                                                        selected_text.clone(),
-                                                    );
-                                                    let app_state = _app.try_state::<AppState>();
-                                                    *app_state
-                                                        .unwrap()
-                                                        .selected_text
-                                                        .blocking_lock() = selected_text;
-                                                }
-                                                Err(e) => {
-                                                    println!("Error getting selected text: {}", e);
-                                                }
-                                            }
-                                            handle_open_ask_window(_app);
+                                            handle_selection_shortcut(_app);
                                         }
                                     }
                                 } else if shortcut == &ctrl_shift_o_shortcut {
                                     match event.state() {
                                         ShortcutState::Pressed => {
                                             println!("CmdOrCtrl+Shift+O Pressed!");
+                                            // 询问窗口使用本地模型时提前加载，第一次提问不用等待
+                                            tauri::async_runtime::spawn(preload_assistant_model(
+                                                _app.clone(),
+                                                1,
+                                            ));
                                         }
                                         ShortcutState::Released => {
                                             println!(
@@ -211,88 +329,58 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                             })
                             .build(),
                     )?;
+                    shortcut::start_shortcut_watcher(app_handle.clone());
                 }
             }
 
-            let system_db = SystemDatabase::new(&app_handle)?;
-            let llm_db = LLMDatabase::new(&app_handle)?;
-            let assistant_db = AssistantDatabase::new(&app_handle)?;
-            let conversation_db = ConversationDatabase::new(&app_handle)?;
-            let plugin_db = PluginDatabase::new(&app_handle)?;
-            system_db.create_tables()?;
-            llm_db.create_tables()?;
-            assistant_db.create_tables()?;
-            conversation_db.create_tables()?;
-            plugin_db.create_tables()?;
-
-            let _ = database_upgrade(
-                &app_handle,
-                system_db,
-                llm_db,
-                assistant_db,
-                conversation_db,
-            );
+            // 打开数据库之前先检查完整性，损坏时自动修复或从备份恢复
+            let mut startup_diagnostics = run_startup_check(&app_handle);
+            init_databases(&app_handle, &mut startup_diagnostics)?;
+            // 数据库检查和修复之后、任何提供商请求之前读取出站允许列表
+            load_egress_allowlist(&app_handle);
+            backup_databases(&app_handle, &startup_diagnostics);
+            app.manage(StartupDiagnosticsState(startup_diagnostics));
+            manage_database_state(&app_handle)?;
+            // 还没有开始任何生成，没有结束时间的回答都是上次异常退出时中断的
+            let interrupted_messages = recover_interrupted_messages(&app_handle);
+            app.manage(InterruptedMessagesState(interrupted_messages));
 
-            app.manage(initialize_state(&app_handle));
-            app.manage(initialize_name_cache_state(&app_handle));
+            start_context_folder_watcher(app_handle.clone());
+            retention::start_retention_cleanup(app_handle.clone());
 
             if app.get_webview_window("main").is_none() {
                 create_ask_window(&app_handle)
             }
+            restore_window_sessions(&app_handle);
 
             Ok(())
         })
-        .manage(AppState {
-            selected_text: TokioMutex::new(String::new()),
-        })
-        .manage(MessageTokenManager::new())
-        .invoke_handler(tauri::generate_handler![
-            ask_ai,
-            regenerate_ai,
-            cancel_ai,
-            get_selected,
-            open_config_window,
-            open_chat_ui_window,
-            open_plugin_window,
-            save_config,
-            get_config,
-            get_all_feature_config,
-            save_feature_config,
-            open_data_folder,
-            get_llm_providers,
-            update_llm_provider,
-            add_llm_provider,
-            delete_llm_provider,
-            get_llm_provider_config,
-            update_llm_provider_config,
-            get_llm_models,
-            fetch_model_list,
-            get_models_for_select,
-            add_llm_model,
-            delete_llm_model,
-            add_attachment,
-            open_attachment_with_default_app,
-            get_assistants,
-            get_assistant,
-            get_assistant_field_value,
-            save_assistant,
-            add_assistant,
-            delete_assistant,
-            copy_assistant,
-            list_conversations,
-            get_conversation_with_messages,
-            delete_conversation,
-            update_conversation,
-            run_artifacts,
-            get_bang_list,
-            get_selected_text_api
-        ])
+        .invoke_handler(invoke_handler())
         .build(tauri::generate_context!())
         .expect("error while running tauri application");
 
-    app.run(|_, e| match e {
-        RunEvent::ExitRequested { api, .. } => {
-            api.prevent_exit();
+    app.run(|app_handle, e| match e {
+        // 关闭最后一个窗口时 code 为 None，程序继续留在托盘中；主动退出时先完成清理再退出
+        RunEvent::ExitRequested { code, api, .. } => {
+            if !shutdown::is_shutdown_complete() {
+                api.prevent_exit();
+                if code.is_some() {
+                    let app_handle = app_handle.clone();
+                    tauri::async_runtime::spawn(async move {
+                        shutdown::request_shutdown(app_handle, false).await;
+                    });
+                }
+            }
+        }
+        // 记录获得焦点的窗口，生成回答时后台窗口降低发送内容的频率
+        RunEvent::WindowEvent {
+            label,
+            event: WindowEvent::Focused(focused),
+            ..
+        } => {
+            app_handle
+                .state::<WindowFocusState>()
+                .set_focused(&label, focused);
         }
         _ => {}
     });
@@ -300,7 +388,302 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn handle_open_ask_window(app_handle: &tauri::AppHandle) {
+// 不依赖数据库的状态，测试中构建应用时也使用同样的状态
+fn manage_state(builder: tauri::Builder<AppRuntime>) -> tauri::Builder<AppRuntime> {
+    builder
+        .manage(AppState {
+            selected_text: TokioMutex::new(String::new()),
+            selected_app: TokioMutex::new(String::new()),
+            shortcut_pressed_at: TokioMutex::new(None),
+        })
+        .manage(MessageTokenManager::new())
+        .manage(VoiceInputState::default())
+        .manage(WindowSessionState::default())
+        .manage(WindowFocusState::default())
+        .manage(ModelPreloadState::default())
+        .manage(AuditLogState::default())
+        .manage(TokenizerState::default())
+        .manage(ResponseCacheState::default())
+        .manage(RequestDedupState::<api::ai_api::AiResponse>::default())
+}
+
+fn invoke_handler() -> impl Fn(tauri::ipc::Invoke<AppRuntime>) -> bool + Send + Sync + 'static {
+    let handler = tauri::generate_handler![
+        ask_ai,
+        regenerate_ai,
+        regenerate_with_model,
+        preview_request,
+        continue_generation,
+        resume_incomplete_message,
+        replay_generation,
+        preload_model,
+        get_interrupted_messages,
+        cancel_ai,
+        get_selected,
+        open_config_window,
+        open_viewer_window,
+        open_chat_ui_window,
+        open_plugin_window,
+        save_config,
+        get_config,
+        get_all_feature_config,
+        save_feature_config,
+        open_data_folder,
+        get_startup_diagnostics,
+        get_privacy_mode,
+        set_privacy_mode,
+        get_llm_providers,
+        update_llm_provider,
+        add_llm_provider,
+        delete_llm_provider,
+        get_llm_provider_config,
+        update_llm_provider_config,
+        get_llm_models,
+        fetch_model_list,
+        validate_provider_config,
+        get_models_for_select,
+        get_model_param_schema,
+        add_llm_model,
+        delete_llm_model,
+        add_attachment,
+        open_attachment_with_default_app,
+        get_assistants,
+        get_assistant,
+        get_assistant_field_value,
+        save_assistant,
+        get_assistant_config_schema,
+        add_assistant,
+        delete_assistant,
+        copy_assistant,
+        list_conversations,
+        recent_conversations,
+        mark_conversation_read,
+        backfill_conversation_titles,
+        cancel_conversation_title_backfill,
+        suggest_split,
+        translate_message,
+        export_conversation,
+        get_conversation_cost,
+        set_model_price,
+        list_glossary_terms,
+        save_glossary_term,
+        delete_glossary_term,
+        get_conversation_with_messages,
+        get_viewer_conversation,
+        delete_conversation,
+        update_conversation,
+        search_prompt_history,
+        add_context_folder,
+        list_context_folders,
+        remove_context_folder,
+        reindex_folder,
+        run_artifacts,
+        get_bang_list,
+        get_selected_text_api,
+        reregister_shortcuts,
+        get_theme,
+        set_theme,
+        export_app_config,
+        import_app_config,
+        pin_message,
+        delete_message,
+        update_message_content,
+        undo_last_operation,
+        redo_last_operation,
+        clone_assistant,
+        run_experiment,
+        vote_experiment,
+        get_experiment_stats,
+        merge_conversations,
+        quote_message,
+        apply_quick_action_output,
+        list_quick_actions,
+        save_quick_action,
+        delete_quick_action,
+        get_assistant_context_items,
+        add_assistant_context_item,
+        remove_assistant_context_item,
+        is_busy,
+        quit_app,
+        set_conversation_private,
+        lock_conversation,
+        unlock_conversation,
+        list_assistants,
+        favorite_assistant,
+        set_assistant_category,
+        start_voice_input,
+        stop_voice_input,
+        add_attachment_from_clipboard,
+        list_conversation_attachments,
+        list_assistant_presets,
+        save_assistant_preset,
+        delete_assistant_preset,
+        set_active_preset,
+        list_skills,
+        save_skill,
+        delete_skill,
+        list_assistant_skills,
+        add_assistant_skill,
+        remove_assistant_skill,
+        set_assistant_skill_enabled,
+        list_assistant_examples,
+        save_assistant_example,
+        delete_assistant_example,
+        reorder_assistant_examples,
+        set_models_enabled,
+        set_window_conversation,
+        get_window_conversation,
+        list_named_window_sessions,
+        save_named_window_session,
+        restore_named_window_session,
+        delete_named_window_session,
+        list_window_placement_profiles,
+        save_window_placement_profile,
+        delete_window_placement_profile,
+        estimate_tokens,
+        clear_response_cache,
+        get_response_cache_stats,
+        get_offline_mode,
+        set_offline_mode,
+        get_concise_mode,
+        set_concise_mode,
+        get_generation_debug,
+        set_generation_debug,
+        get_egress_allowlist,
+        set_egress_allowlist,
+        add_egress_host,
+        test_webhook,
+        verify_audit_log,
+        verify_exported_audit_log,
+        export_audit_log,
+        list_tools,
+        call_tool,
+        list_tool_invocations,
+        save_draft,
+        get_draft,
+        get_conversation_style,
+        get_activity_calendar,
+        set_conversation_style
+    ];
+    move |invoke| {
+        // 只读查看窗口只能调用读取对话的命令，其他命令在这里拒绝
+        let label = invoke.message.webview_ref().label().to_string();
+        if !is_command_allowed(&label, invoke.message.command()) {
+            let error = format!("{} 窗口不能调用 {}", label, invoke.message.command());
+            invoke.resolver.reject(error);
+            return true;
+        }
+        handler(invoke)
+    }
+}
+
+// 创建数据表并升级数据库，创建失败的数据库记录到启动诊断中
+fn init_databases(
+    app_handle: &AppHandle,
+    startup_diagnostics: &mut StartupDiagnostics,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let system_db = SystemDatabase::new(app_handle)?;
+    let llm_db = LLMDatabase::new(app_handle)?;
+    let assistant_db = AssistantDatabase::new(app_handle)?;
+    let conversation_db = ConversationDatabase::new(app_handle)?;
+    let plugin_db = PluginDatabase::new(app_handle)?;
+    let knowledge_db = KnowledgeDatabase::new(app_handle)?;
+    let create_results = [
+        ("system.db", system_db.create_tables()),
+        ("llm.db", llm_db.create_tables()),
+        ("assistant.db", assistant_db.create_tables()),
+        ("conversation.db", conversation_db.create_tables()),
+        ("plugin.db", plugin_db.create_tables()),
+        ("knowledge.db", knowledge_db.create_tables()),
+    ];
+    for (name, result) in create_results {
+        if let Err(e) = result {
+            startup_diagnostics.record_failure(name, format!("创建数据表失败: {}", e));
+        }
+    }
+
+    let _ = database_upgrade(app_handle, system_db, llm_db, assistant_db, conversation_db);
+    Ok(())
+}
+
+// 从数据库加载的功能配置、名称缓存和私密对话状态
+fn manage_database_state(app_handle: &AppHandle) -> Result<(), Box<dyn std::error::Error>> {
+    app_handle.manage(initialize_state(app_handle));
+    app_handle.manage(initialize_name_cache_state(app_handle));
+    let private_ids = ConversationDatabase::new(app_handle)?
+        .conversation_repo()?
+        .list_private_ids()?;
+    app_handle.manage(PrivateConversationState::new(private_ids));
+    Ok(())
+}
+
+// 划词快捷键松开后读取选中文字并打开询问窗口，焦点在屏蔽的应用中时按配置忽略或者只读取剪贴板
+//
+// 模拟复制需要等待剪贴板更新，放到后台线程中执行，不阻塞快捷键的处理
+fn handle_selection_shortcut(app_handle: &AppHandle) {
+    let app_handle = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        let (fallback, blocklist) = match app_handle.try_state::<FeatureConfigState>() {
+            Some(state) => {
+                let config_feature_map = state.config_feature_map.lock().await;
+                (
+                    SelectionFallback::from_feature_config(&config_feature_map),
+                    SelectionBlocklist::from_feature_config(&config_feature_map),
+                )
+            }
+            None => (
+                SelectionFallback::Auto,
+                SelectionBlocklist::from_config(DEFAULT_BLOCKED_APPS, ""),
+            ),
+        };
+        let read = tauri::async_runtime::spawn_blocking(move || {
+            let window = active_window();
+            let blocked_action = window.as_ref().and_then(|window| {
+                let action = blocklist.check(window);
+                if action.is_some() {
+                    println!("selection shortcut in blocked app: {:?}", window);
+                }
+                action
+            });
+            let result = match blocked_action {
+                Some(BlockedAction::Ignore) => return None,
+                Some(BlockedAction::ClipboardOnly) => read_clipboard_text(),
+                None => read_selected_text(fallback),
+            };
+            Some((window, result))
+        })
+        .await;
+        let (window, result) = match read {
+            Ok(Some(read)) => read,
+            Ok(None) => return,
+            Err(e) => {
+                println!("read selected text task error: {}", e);
+                return;
+            }
+        };
+        match result {
+            Ok(selected_text) => {
+                println!(
+                    "Selected text: {}, at time: {}",
+                    selected_text.clone(),
+                    &Local::now().to_string()
+                );
+                let _ = app_handle.emit("get_selected_text_event", selected_text.clone());
+                if let Some(app_state) = app_handle.try_state::<AppState>() {
+                    *app_state.selected_text.lock().await = selected_text;
+                    *app_state.selected_app.lock().await =
+                        window.map(|window| window.app_name).unwrap_or_default();
+                }
+            }
+            Err(e) => {
+                println!("Error getting selected text: {}", e);
+            }
+        }
+        handle_open_ask_window(&app_handle);
+    });
+}
+
+fn handle_open_ask_window(app_handle: &AppHandle) {
     let ask_window = app_handle.get_webview_window("ask");
     let chat_ui_window = app_handle.get_webview_window("chat_ui");
 
@@ -326,7 +709,7 @@ fn handle_open_ask_window(app_handle: &tauri::AppHandle) {
     }
 }
 
-fn initialize_state(app_handle: &tauri::AppHandle) -> FeatureConfigState {
+fn initialize_state(app_handle: &AppHandle) -> FeatureConfigState {
     let db = SystemDatabase::new(app_handle).expect("Failed to connect to database");
     let configs = db
         .get_all_feature_config()
@@ -346,7 +729,7 @@ fn initialize_state(app_handle: &tauri::AppHandle) -> FeatureConfigState {
     }
 }
 
-fn initialize_name_cache_state(app_handle: &tauri::AppHandle) -> NameCacheState {
+fn initialize_name_cache_state(app_handle: &AppHandle) -> NameCacheState {
     let assistant_db = AssistantDatabase::new(app_handle).expect("Failed to connect to database");
     let assistants = assistant_db
         .get_assistants()
@@ -358,7 +741,7 @@ fn initialize_name_cache_state(app_handle: &tauri::AppHandle) -> NameCacheState
 
     let llm_db = LLMDatabase::new(app_handle).expect("Failed to connect to database");
     let models = llm_db
-        .get_models_for_select()
+        .get_models_for_select(true)
         .expect("Failed to load models");
     let mut model_names = HashMap::new();
     for model in models.clone().into_iter() {
@@ -0,0 +1,65 @@
+use crate::api::llm::Role;
+use crate::db::conversation_db::MessageQuote;
+
+/// 引用消息发给模型时的内容：先说明来自哪个对话的提问还是回答，再把原文放在引用块中，
+/// 让模型分清这是其他对话中的内容，而不是当前用户的提问
+pub fn quoted_content(content: &str, quote: Option<&MessageQuote>) -> String {
+    let source = match quote {
+        Some(quote) => format!(
+            "以下内容引用自对话「{}」中{}（{}）",
+            quote.source_conversation_name,
+            source_label(&quote.source_message_type),
+            quote
+                .source_created_time
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M")
+        ),
+        // 来源记录丢失时仍然按引用处理
+        None => "以下内容引用自其他对话".to_string(),
+    };
+    let block = content
+        .lines()
+        .map(|line| {
+            if line.is_empty() {
+                ">".to_string()
+            } else {
+                format!("> {}", line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("[{}]\n{}", source, block)
+}
+
+fn source_label(message_type: &str) -> &'static str {
+    match message_type.parse::<Role>() {
+        Ok(Role::User) => "用户的提问",
+        Ok(Role::Assistant) => "助手的回答",
+        _ => "的消息",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quoted_content() {
+        let quote = MessageQuote {
+            message_id: 10,
+            source_conversation_id: 1,
+            source_conversation_name: "Rust 学习".to_string(),
+            source_message_id: 3,
+            source_message_type: "assistant".to_string(),
+            source_created_time: chrono::Utc::now(),
+        };
+        let content = quoted_content("第一行\n\n第二行", Some(&quote));
+        assert!(content.starts_with("[以下内容引用自对话「Rust 学习」中助手的回答（"));
+        assert!(content.ends_with("]\n> 第一行\n>\n> 第二行"));
+
+        assert_eq!(
+            quoted_content("hello", None),
+            "[以下内容引用自其他对话]\n> hello"
+        );
+    }
+}
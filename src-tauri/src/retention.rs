@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use tauri::{Emitter, Manager};
+
+use crate::api::llm_api::remove_orphan_recordings;
+use crate::db::assistant_db::{AssistantDatabase, AssistantModelConfig};
+use crate::db::conversation_db::ConversationDatabase;
+use crate::errors::AppError;
+use crate::state::private_conversation::PrivateConversationState;
+use crate::AppHandle;
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// 启动时等窗口和状态初始化完成后再清理
+const STARTUP_DELAY: Duration = Duration::from_secs(10);
+// 删除的消息和操作记录保留的天数，在这之前可以撤销
+pub const TOMBSTONE_DAYS: i64 = 7;
+
+/// 助手的对话保留规则，保存在助手的 retention_days 和 incognito 配置中
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RetentionPolicy {
+    // 超过这个天数没有新消息的对话自动删除，没有配置或者为 0 表示永久保留
+    pub retention_days: Option<i64>,
+    // 无痕助手的对话内容不会保存下来，退出时删除
+    pub incognito: bool,
+}
+
+impl RetentionPolicy {
+    pub fn from_model_configs(configs: &[AssistantModelConfig]) -> Self {
+        let get_value = |name: &str| {
+            configs
+                .iter()
+                .find(|config| config.name == name)
+                .and_then(|config| config.value.as_deref())
+                .map(|value| value.trim())
+        };
+        RetentionPolicy {
+            retention_days: get_value("retention_days")
+                .and_then(|value| value.parse::<i64>().ok())
+                .filter(|days| *days > 0),
+            incognito: get_value("incognito") == Some("true"),
+        }
+    }
+
+    pub fn of_assistant(app_handle: &AppHandle, assistant_id: i64) -> Self {
+        AssistantDatabase::new(app_handle)
+            .and_then(|db| db.get_assistant_model_configs(assistant_id))
+            .map(|configs| Self::from_model_configs(&configs))
+            .unwrap_or_default()
+    }
+}
+
+/// 定时按助手的保留规则删除过期的对话，删除后发送 conversations_expired 事件通知界面刷新列表
+///
+/// 无痕助手在之前运行中留下的对话也会在这里删除，同时彻底删除超过 TOMBSTONE_DAYS 天的已删除消息
+pub fn start_retention_cleanup(app_handle: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(STARTUP_DELAY).await;
+        loop {
+            let app_handle_clone = app_handle.clone();
+            let result = tauri::async_runtime::spawn_blocking(move || {
+                if let Err(e) = purge_message_tombstones(&app_handle_clone) {
+                    println!("purge message tombstones error: {:?}", e);
+                }
+                cleanup_expired_conversations(&app_handle_clone)
+            })
+            .await;
+            match result {
+                Ok(Ok(ids)) if !ids.is_empty() => {
+                    println!("retention cleanup deleted conversations: {:?}", ids);
+                    let _ = app_handle.emit("conversations_expired", ids);
+                }
+                Ok(Err(e)) => println!("retention cleanup error: {:?}", e),
+                _ => {}
+            }
+            tokio::time::sleep(CLEANUP_INTERVAL).await;
+        }
+    });
+}
+
+pub(crate) fn cleanup_expired_conversations(app_handle: &AppHandle) -> Result<Vec<i64>, AppError> {
+    let assistant_db = AssistantDatabase::new(app_handle)?;
+    let db = ConversationDatabase::new(app_handle)?;
+    let conversation_repo = db.conversation_repo()?;
+    // 本次运行中的无痕对话还在使用，退出时再删除
+    let active_ids = app_handle
+        .try_state::<PrivateConversationState>()
+        .map(|state| state.incognito_ids())
+        .unwrap_or_default();
+
+    // 只删除创建时记录为无痕的对话，助手切换到无痕之前的对话按保留天数处理
+    let mut expired = conversation_repo.list_incognito_ids()?;
+    for assistant in assistant_db.get_assistants()? {
+        let policy = RetentionPolicy::from_model_configs(
+            &assistant_db.get_assistant_model_configs(assistant.id)?,
+        );
+        let Some(days) = policy.retention_days else {
+            continue;
+        };
+        let before = chrono::Utc::now() - chrono::Duration::days(days);
+        expired.extend(conversation_repo.list_ids_inactive_before(assistant.id, before)?);
+    }
+    expired.sort();
+    expired.dedup();
+    expired.retain(|id| !active_ids.contains(id));
+
+    // 每个对话的消息、附件和关联记录在一个事务中删除，中途出错不会留下一半的对话
+    let mut deleted = Vec::new();
+    for id in expired {
+        db.transaction(|tx| Ok(tx.conversation_repo().delete_with_messages(id)?))?;
+        deleted.push(id);
+    }
+    if !deleted.is_empty() {
+        remove_orphan_recordings(app_handle)?;
+    }
+    Ok(deleted)
+}
+
+fn purge_message_tombstones(app_handle: &AppHandle) -> Result<(), AppError> {
+    let db = ConversationDatabase::new(app_handle)?;
+    let before = chrono::Utc::now() - chrono::Duration::days(TOMBSTONE_DAYS);
+    let purged = db.message_repo()?.purge_deleted_before(before)?;
+    db.operation_repo()?.delete_before(before)?;
+    if purged > 0 {
+        println!("purged deleted messages: {}", purged);
+    }
+    // 删除对话和消息时附件随之删除，保存在文件中的内容在这里清理
+    let removed = db.attachment_repo()?.remove_orphan_files()?;
+    if removed > 0 {
+        println!("removed attachment files: {}", removed);
+    }
+    let removed = remove_orphan_recordings(app_handle)?;
+    if removed > 0 {
+        println!("removed generation recordings: {}", removed);
+    }
+    Ok(())
+}
+
+/// 退出时删除本次运行中的无痕对话
+pub fn delete_incognito_conversations(app_handle: &AppHandle) {
+    let ids = app_handle
+        .try_state::<PrivateConversationState>()
+        .map(|state| state.incognito_ids())
+        .unwrap_or_default();
+    if ids.is_empty() {
+        return;
+    }
+    let result = ConversationDatabase::new(app_handle)
+        .map_err(AppError::from)
+        .and_then(|db| {
+            db.transaction(|tx| {
+                let repo = tx.conversation_repo();
+                for id in ids.iter() {
+                    repo.delete_with_messages(*id)?;
+                }
+                Ok(())
+            })
+        });
+    if let Err(e) = result.and_then(|_| remove_orphan_recordings(app_handle)) {
+        println!("delete incognito conversations error: {:?}", e);
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+use arboard::{Clipboard, ImageData};
+use enigo::{Direction, Enigo, Key, Keyboard, Settings};
+use get_selected_text::get_selected_text;
+use regex::Regex;
+
+use crate::active_window::{normalize_process_name, ActiveWindow};
+use crate::db::system_db::FeatureConfig;
+
+// 模拟复制后轮询剪贴板的次数和间隔，部分应用响应 Ctrl+C 比较慢
+const COPY_POLL_TIMES: u32 = 10;
+const COPY_POLL_INTERVAL: Duration = Duration::from_millis(30);
+/// 默认的选中文字模板，保存在 selection 功能配置的 context_template 中
+pub const DEFAULT_CONTEXT_TEMPLATE: &str =
+    "用户在 {{app_name}} 中选中了以下文字：\n\n{{selected_text}}";
+// 取不到来源应用名称时在选中文字模板中使用的名称
+const UNKNOWN_APP_NAME: &str = "其他应用";
+// 密码管理器不管是否在 blocked_apps 中都忽略快捷键，blocked_action 为 clipboard 时也不读取剪贴板，
+// 剪贴板中很可能是刚复制的密码
+const PASSWORD_MANAGER_APPS: &[&str] = &[
+    "1password",
+    "bitwarden",
+    "keepass",
+    "keepassxc",
+    "lastpass",
+    "dashlane",
+    "enpass",
+    "keychain access",
+];
+// 没有配置 blocked_apps 时默认屏蔽的密码管理器和输入法进程，模拟复制会干扰它们的输入框和候选窗口
+pub const DEFAULT_BLOCKED_APPS: &str = "1password,bitwarden,keepass,keepassxc,lastpass,dashlane,enpass,keychain access,textinputhost,ctfmon,fcitx,fcitx5,ibus-ui-gtk3";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SelectionFallback {
+    // 只使用 get_selected_text
+    Off,
+    // get_selected_text 失败或者没有取到内容时，模拟复制再读取剪贴板
+    Auto,
+    // 总是模拟复制
+    Copy,
+}
+
+impl SelectionFallback {
+    pub fn from_config(value: &str) -> Self {
+        match value {
+            "off" => SelectionFallback::Off,
+            "copy" => SelectionFallback::Copy,
+            _ => SelectionFallback::Auto,
+        }
+    }
+
+    /// 读取 selection 的 fallback 配置，默认 auto
+    pub fn from_feature_config(
+        config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+    ) -> Self {
+        config_feature_map
+            .get("selection")
+            .and_then(|config| config.get("fallback"))
+            .map(|config| SelectionFallback::from_config(&config.value))
+            .unwrap_or(SelectionFallback::Auto)
+    }
+}
+
+/// 焦点在屏蔽的应用中时划词快捷键的处理方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockedAction {
+    // 忽略这次快捷键，不读取选中文字也不打开询问窗口
+    Ignore,
+    // 不读取选区也不模拟复制，直接使用剪贴板中已有的文字
+    ClipboardOnly,
+}
+
+/// 划词快捷键的应用屏蔽列表，保存在 selection 功能配置的 blocked_apps 和 blocked_action 中
+///
+/// blocked_apps 用逗号或换行分隔，每一项是进程名称或应用名称，或者以 title: 开头、匹配窗口标题中包含的文字，
+/// 都不区分大小写；PASSWORD_MANAGER_APPS 中的密码管理器不在列表中也总是忽略
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectionBlocklist {
+    process_names: Vec<String>,
+    title_keywords: Vec<String>,
+    action: BlockedAction,
+}
+
+impl SelectionBlocklist {
+    pub fn from_config(blocked_apps: &str, action: &str) -> Self {
+        let mut process_names = Vec::new();
+        let mut title_keywords = Vec::new();
+        for item in blocked_apps.split([',', '\n']) {
+            let item = item.trim();
+            match item.strip_prefix("title:") {
+                Some(keyword) if !keyword.trim().is_empty() => {
+                    title_keywords.push(keyword.trim().to_lowercase())
+                }
+                Some(_) => {}
+                None if !item.is_empty() => process_names.push(normalize_process_name(item)),
+                None => {}
+            }
+        }
+        SelectionBlocklist {
+            process_names,
+            title_keywords,
+            action: match action {
+                "clipboard" => BlockedAction::ClipboardOnly,
+                _ => BlockedAction::Ignore,
+            },
+        }
+    }
+
+    /// 读取 selection 的 blocked_apps 和 blocked_action 配置，没有配置 blocked_apps 时使用 DEFAULT_BLOCKED_APPS
+    pub fn from_feature_config(
+        config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+    ) -> Self {
+        let config = config_feature_map.get("selection");
+        let get_value = |key: &str| {
+            config
+                .and_then(|config| config.get(key))
+                .map(|config| config.value.trim().to_string())
+        };
+        SelectionBlocklist::from_config(
+            &get_value("blocked_apps").unwrap_or_else(|| DEFAULT_BLOCKED_APPS.to_string()),
+            &get_value("blocked_action").unwrap_or_default(),
+        )
+    }
+
+    /// 焦点窗口在屏蔽列表中时返回处理方式，进程名称和应用名称任意一个在列表中都算匹配
+    pub fn check(&self, window: &ActiveWindow) -> Option<BlockedAction> {
+        let names = [
+            window.process_name.clone(),
+            normalize_process_name(&window.app_name),
+        ]
+        .into_iter()
+        .filter(|name| !name.is_empty())
+        .collect::<Vec<String>>();
+        if names
+            .iter()
+            .any(|name| PASSWORD_MANAGER_APPS.contains(&name.as_str()))
+        {
+            return Some(BlockedAction::Ignore);
+        }
+        let title = window.title.to_lowercase();
+        let blocked = names.iter().any(|name| self.process_names.contains(name))
+            || self
+                .title_keywords
+                .iter()
+                .any(|keyword| title.contains(keyword.as_str()));
+        blocked.then_some(self.action)
+    }
+}
+
+/// 询问窗口中带上选中文字的系统提示词模板，保存在 selection 功能配置的 context_template 中
+///
+/// {{selected_text}} 替换为选中的文字，{{app_name}} 替换为按下划词快捷键时焦点所在的应用，模板为空时不添加
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SelectionContextTemplate {
+    template: String,
+}
+
+impl SelectionContextTemplate {
+    pub fn from_feature_config(
+        config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+    ) -> Self {
+        SelectionContextTemplate {
+            template: config_feature_map
+                .get("selection")
+                .and_then(|config| config.get("context_template"))
+                .map(|config| config.value.trim().to_string())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// 按模板生成选中文字的说明，模板或者选中的文字为空时返回 None
+    pub fn render(&self, selected_text: &str, app_name: &str) -> Option<String> {
+        if self.template.is_empty() || selected_text.trim().is_empty() {
+            return None;
+        }
+        let app_name = match app_name.trim() {
+            "" => UNKNOWN_APP_NAME,
+            name => name,
+        };
+        Some(
+            self.template
+                .replace("{{app_name}}", app_name)
+                .replace("{{selected_text}}", selected_text.trim()),
+        )
+    }
+}
+
+/// 提示词中是否通过 !s 或者 !selected_text 引用了选中的文字
+pub fn references_selected_text(prompt: &str) -> bool {
+    static SELECTED_TEXT_COMMAND: OnceLock<Regex> = OnceLock::new();
+    SELECTED_TEXT_COMMAND
+        .get_or_init(|| Regex::new(r"[!！](s|selected_text)\b").unwrap())
+        .is_match(prompt)
+}
+
+/// 读取剪贴板中的文字，不读取选区也不模拟按键
+pub fn read_clipboard_text() -> Result<String, String> {
+    Clipboard::new()
+        .and_then(|mut clipboard| clipboard.get_text())
+        .map_err(|e| e.to_string())
+}
+
+/// 获取当前选中的文字，在 Windows 和 Linux 上很多应用不支持直接读取选区，按配置回退到模拟复制
+///
+/// 模拟复制会短暂占用剪贴板，读取完成后恢复原来的内容
+pub fn read_selected_text(fallback: SelectionFallback) -> Result<String, String> {
+    if fallback == SelectionFallback::Copy {
+        return copy_selected_text();
+    }
+    match get_selected_text() {
+        Ok(text) if !text.trim().is_empty() || fallback == SelectionFallback::Off => Ok(text),
+        Ok(_) => copy_selected_text(),
+        Err(e) if fallback == SelectionFallback::Off => Err(e.to_string()),
+        Err(e) => {
+            println!("get selected text error: {}, fallback to simulated copy", e);
+            copy_selected_text()
+        }
+    }
+}
+
+// 模拟复制前剪贴板中的内容，arboard 能读取的格式都保存下来
+struct SavedClipboard {
+    text: Option<String>,
+    html: Option<String>,
+    image: Option<ImageData<'static>>,
+    files: Option<Vec<PathBuf>>,
+}
+
+impl SavedClipboard {
+    fn save(clipboard: &mut Clipboard) -> Self {
+        SavedClipboard {
+            text: clipboard.get_text().ok(),
+            html: clipboard.get().html().ok(),
+            image: clipboard.get_image().ok(),
+            files: clipboard
+                .get()
+                .file_list()
+                .ok()
+                .filter(|files| !files.is_empty()),
+        }
+    }
+
+    // 写入一种格式会替换剪贴板中的所有内容，按文件、图片、HTML、文字的顺序恢复信息最完整的一种，
+    // HTML 同时写入纯文字，粘贴到只接受文字的应用时不受影响
+    fn restore(self, clipboard: &mut Clipboard) {
+        let result = if let Some(files) = self.files {
+            clipboard.set().file_list(&files)
+        } else if let Some(image) = self.image {
+            clipboard.set_image(image)
+        } else if let Some(html) = self.html {
+            clipboard.set_html(html, self.text)
+        } else if let Some(text) = self.text {
+            clipboard.set_text(text)
+        } else {
+            clipboard.clear()
+        };
+        if let Err(e) = result {
+            println!("restore clipboard error: {}", e);
+        }
+    }
+}
+
+fn copy_selected_text() -> Result<String, String> {
+    let mut clipboard = Clipboard::new().map_err(|e| e.to_string())?;
+    let saved = SavedClipboard::save(&mut clipboard);
+    // 先清空剪贴板，否则没有选中文字时会读到原来的剪贴板内容
+    let _ = clipboard.clear();
+
+    let copy_result = simulate_copy();
+    let mut text = String::new();
+    if copy_result.is_ok() {
+        for _ in 0..COPY_POLL_TIMES {
+            thread::sleep(COPY_POLL_INTERVAL);
+            if let Ok(copied) = clipboard.get_text() {
+                if !copied.is_empty() {
+                    text = copied;
+                    break;
+                }
+            }
+        }
+    }
+
+    saved.restore(&mut clipboard);
+    copy_result.map(|_| text)
+}
+
+fn simulate_copy() -> Result<(), String> {
+    let mut enigo = Enigo::new(&Settings::default()).map_err(|e| e.to_string())?;
+    #[cfg(target_os = "macos")]
+    let modifier = Key::Meta;
+    #[cfg(not(target_os = "macos"))]
+    let modifier = Key::Control;
+
+    // 快捷键里的 Shift 可能还没有松开，会变成 Ctrl+Shift+C
+    let _ = enigo.key(Key::Shift, Direction::Release);
+    enigo
+        .key(modifier, Direction::Press)
+        .map_err(|e| e.to_string())?;
+    let result = enigo.key(Key::Unicode('c'), Direction::Click);
+    enigo
+        .key(modifier, Direction::Release)
+        .map_err(|e| e.to_string())?;
+    result.map_err(|e| e.to_string())
+}
+
+/// 模拟键盘把文字逐字输入到当前焦点所在的应用，每个字符之间间隔 interval，
+/// 输入过快时部分应用会丢字
+///
+/// 这是一个阻塞操作，需要在 spawn_blocking 中调用
+pub fn type_text(text: &str, interval: Duration) -> Result<(), String> {
+    let mut enigo = Enigo::new(&Settings::default()).map_err(|e| e.to_string())?;
+    for c in text.chars() {
+        enigo.text(&c.to_string()).map_err(|e| e.to_string())?;
+        thread::sleep(interval);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(process_name: &str, title: &str) -> ActiveWindow {
+        ActiveWindow {
+            process_name: process_name.to_string(),
+            app_name: String::new(),
+            title: title.to_string(),
+        }
+    }
+
+    fn app_window(process_name: &str, app_name: &str) -> ActiveWindow {
+        ActiveWindow {
+            app_name: app_name.to_string(),
+            ..window(process_name, "")
+        }
+    }
+
+    #[test]
+    fn test_selection_context_template() {
+        let config = |value: &str| {
+            HashMap::from([(
+                "selection".to_string(),
+                HashMap::from([(
+                    "context_template".to_string(),
+                    FeatureConfig {
+                        id: None,
+                        feature_code: "selection".to_string(),
+                        key: "context_template".to_string(),
+                        value: value.to_string(),
+                        data_type: "string".to_string(),
+                        description: None,
+                    },
+                )]),
+            )])
+        };
+        let template = SelectionContextTemplate::from_feature_config(&config(
+            "用户在 {{app_name}} 中选中了：{{selected_text}}",
+        ));
+        assert_eq!(
+            template.render(" fn main() {} \n", "Visual Studio Code"),
+            Some("用户在 Visual Studio Code 中选中了：fn main() {}".to_string())
+        );
+        assert_eq!(
+            template.render("hello", ""),
+            Some("用户在 其他应用 中选中了：hello".to_string())
+        );
+        assert_eq!(template.render("  ", "Chrome"), None);
+        assert_eq!(
+            SelectionContextTemplate::from_feature_config(&config("")).render("hello", "Chrome"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_references_selected_text() {
+        assert!(references_selected_text("翻译 !s"));
+        assert!(references_selected_text("解释：！selected_text"));
+        assert!(!references_selected_text("!sub_start(10|!web(url))"));
+        assert!(!references_selected_text("没有引用"));
+    }
+
+    #[test]
+    fn test_selection_blocklist() {
+        let blocklist = SelectionBlocklist::from_config("KeePassXC.exe, title:密码\nfcitx5", "");
+        assert_eq!(
+            blocklist.check(&window("keepassxc", "Passwords.kdbx")),
+            Some(BlockedAction::Ignore)
+        );
+        assert_eq!(
+            blocklist.check(&window("chrome", "修改密码 - 设置")),
+            Some(BlockedAction::Ignore)
+        );
+        assert_eq!(
+            blocklist.check(&window("fcitx5", "")),
+            Some(BlockedAction::Ignore)
+        );
+        assert_eq!(blocklist.check(&window("code", "main.rs")), None);
+
+        let blocklist = SelectionBlocklist::from_config("1password,code", "clipboard");
+        assert_eq!(
+            blocklist.check(&window("code", "")),
+            Some(BlockedAction::ClipboardOnly)
+        );
+        // 密码管理器总是忽略，不读取剪贴板
+        assert_eq!(
+            blocklist.check(&window("1password", "")),
+            Some(BlockedAction::Ignore)
+        );
+        // 可执行文件名称和应用名称不同时按应用名称匹配
+        assert_eq!(
+            blocklist.check(&app_window("electron", "Code")),
+            Some(BlockedAction::ClipboardOnly)
+        );
+
+        // 配置为空时只忽略密码管理器，没有配置时使用默认列表
+        let blocklist = SelectionBlocklist::from_config("", "");
+        assert_eq!(blocklist.check(&window("fcitx5", "")), None);
+        assert_eq!(
+            blocklist.check(&app_window("bitwarden-desktop", "Bitwarden")),
+            Some(BlockedAction::Ignore)
+        );
+        let blocklist = SelectionBlocklist::from_feature_config(&HashMap::new());
+        assert_eq!(
+            blocklist.check(&window("bitwarden", "")),
+            Some(BlockedAction::Ignore)
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+
+use tauri::{Emitter, Manager};
+use tauri_plugin_global_shortcut::{Code, GlobalShortcut, Modifiers, Shortcut};
+
+use crate::db::system_db::SystemDatabase;
+use crate::{AppHandle, AppRuntime};
+
+// 检查快捷键是否还有效的间隔
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+// 两次检查之间的实际时间比间隔多出这么多时，认为系统经历了睡眠和唤醒
+const RESUME_TOLERANCE: Duration = Duration::from_secs(30);
+
+/// system_config 中保存自定义快捷键的键，值的格式为 ctrl+shift+KeyI
+pub const SELECTION_SHORTCUT_KEY: &str = "selection_shortcut";
+pub const ASK_WINDOW_SHORTCUT_KEY: &str = "ask_window_shortcut";
+
+// 快捷键的处理函数在启动时注册，修改后需要重启应用才会生效，所以只在第一次使用时读取
+static SHORTCUTS: OnceLock<(Shortcut, Shortcut)> = OnceLock::new();
+
+/// 划词快捷键，松开后读取选中文字并打开询问窗口
+pub fn selection_shortcut(app_handle: &AppHandle) -> Shortcut {
+    configured_shortcuts(app_handle).0
+}
+
+/// 打开询问窗口的快捷键
+pub fn ask_window_shortcut(app_handle: &AppHandle) -> Shortcut {
+    configured_shortcuts(app_handle).1
+}
+
+fn configured_shortcuts(app_handle: &AppHandle) -> (Shortcut, Shortcut) {
+    *SHORTCUTS.get_or_init(|| {
+        let db = SystemDatabase::new(app_handle).ok();
+        let read = |key: &str, default: Shortcut| {
+            db.as_ref()
+                .and_then(|db| db.get_config(key).ok())
+                .and_then(|value| parse_shortcut(&value).ok())
+                .unwrap_or(default)
+        };
+        (
+            read(
+                SELECTION_SHORTCUT_KEY,
+                Shortcut::new(Some(Modifiers::CONTROL | Modifiers::SHIFT), Code::KeyI),
+            ),
+            read(
+                ASK_WINDOW_SHORTCUT_KEY,
+                Shortcut::new(Some(Modifiers::CONTROL | Modifiers::SHIFT), Code::KeyO),
+            ),
+        )
+    })
+}
+
+/// 解析配置中的快捷键，必须带有修饰键，避免占用普通按键
+pub fn parse_shortcut(value: &str) -> Result<Shortcut, String> {
+    let shortcut = value
+        .trim()
+        .parse::<Shortcut>()
+        .map_err(|e| format!("无效的快捷键 {}: {}", value, e))?;
+    if shortcut.mods.is_empty() {
+        return Err(format!("快捷键 {} 需要包含修饰键", value));
+    }
+    Ok(shortcut)
+}
+
+/// 取消并重新注册所有全局快捷键，快捷键的处理函数在插件中设置，重新注册后保持不变
+///
+/// 没有辅助功能权限时启动时不会注册快捷键，这时返回错误
+pub fn reregister_shortcuts(app_handle: &AppHandle) -> Result<(), String> {
+    let Some(global_shortcut) = app_handle.try_state::<GlobalShortcut<AppRuntime>>() else {
+        return Err("全局快捷键没有启用，请检查辅助功能权限".to_string());
+    };
+    global_shortcut
+        .unregister_all()
+        .map_err(|e| e.to_string())?;
+    global_shortcut
+        .register_multiple([
+            selection_shortcut(app_handle),
+            ask_window_shortcut(app_handle),
+        ])
+        .map_err(|e| e.to_string())?;
+    println!("global shortcuts reregistered");
+    let _ = app_handle.emit("shortcuts_reregistered", ());
+    Ok(())
+}
+
+/// 部分平台睡眠唤醒后全局快捷键会失效，定时检查，唤醒后或者快捷键不再处于注册状态时重新注册
+pub fn start_shortcut_watcher(app_handle: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut last_check = SystemTime::now();
+        loop {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+            let now = SystemTime::now();
+            let resumed = is_resumed(now.duration_since(last_check).unwrap_or_default());
+            last_check = now;
+            if resumed {
+                println!("system resumed, reregister global shortcuts");
+            } else if all_registered(&app_handle) {
+                continue;
+            }
+            if let Err(e) = reregister_shortcuts(&app_handle) {
+                println!("reregister global shortcuts error: {}", e);
+            }
+        }
+    });
+}
+
+// 睡眠期间定时器不走，唤醒后墙上时间已经过去很久
+fn is_resumed(wall_elapsed: Duration) -> bool {
+    wall_elapsed > CHECK_INTERVAL + RESUME_TOLERANCE
+}
+
+fn all_registered(app_handle: &AppHandle) -> bool {
+    match app_handle.try_state::<GlobalShortcut<AppRuntime>>() {
+        Some(global_shortcut) => {
+            global_shortcut.is_registered(selection_shortcut(app_handle))
+                && global_shortcut.is_registered(ask_window_shortcut(app_handle))
+        }
+        // 没有启用全局快捷键时不需要重新注册
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_resumed() {
+        assert!(!is_resumed(CHECK_INTERVAL));
+        assert!(!is_resumed(CHECK_INTERVAL + Duration::from_secs(5)));
+        assert!(is_resumed(Duration::from_secs(60 * 60)));
+    }
+
+    #[test]
+    fn test_parse_shortcut() {
+        let shortcut = parse_shortcut("ctrl+shift+KeyI").unwrap();
+        assert_eq!(
+            shortcut,
+            Shortcut::new(Some(Modifiers::CONTROL | Modifiers::SHIFT), Code::KeyI)
+        );
+        assert!(parse_shortcut("KeyI").is_err());
+        assert!(parse_shortcut("ctrl+shift+").is_err());
+    }
+}
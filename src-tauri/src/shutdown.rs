@@ -0,0 +1,76 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use tauri::{Emitter, Manager};
+
+use crate::retention::delete_incognito_conversations;
+use crate::state::message_token::MessageTokenManager;
+use crate::window::{save_window_geometry, save_window_sessions};
+use crate::AppHandle;
+
+// 取消生成后等待接收端把已经生成的内容写入数据库的最长时间
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(3);
+const FLUSH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// 清理完成后再次调用 exit 时不再拦截
+static SHUTDOWN_COMPLETE: AtomicBool = AtomicBool::new(false);
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+// 有生成正在进行时第一次退出只提醒用户，再次退出才真正退出
+static BUSY_WARNED: AtomicBool = AtomicBool::new(false);
+
+pub fn is_shutdown_complete() -> bool {
+    SHUTDOWN_COMPLETE.load(Ordering::SeqCst)
+}
+
+/// 处理退出请求，有生成正在进行且 force 为 false 时发送 exit_blocked 事件提醒用户并返回 false
+pub async fn request_shutdown(app_handle: AppHandle, force: bool) -> bool {
+    let running = app_handle
+        .state::<MessageTokenManager>()
+        .running_count()
+        .await;
+    if running == 0 {
+        BUSY_WARNED.store(false, Ordering::SeqCst);
+    }
+    if running > 0 && !force && !BUSY_WARNED.swap(true, Ordering::SeqCst) {
+        println!("{} generations still running, exit blocked", running);
+        let _ = app_handle.emit("exit_blocked", running);
+        if let Some(window) = app_handle.get_webview_window("ask") {
+            let _ = window.show();
+            let _ = window.set_focus();
+        }
+        return false;
+    }
+    if SHUTTING_DOWN.swap(true, Ordering::SeqCst) {
+        return true;
+    }
+    graceful_shutdown(&app_handle).await;
+    SHUTDOWN_COMPLETE.store(true, Ordering::SeqCst);
+    app_handle.exit(0);
+    true
+}
+
+// 取消所有生成并等待内容落库，删除无痕对话，保存打开的窗口和位置，注销全局快捷键
+// 数据库连接都是按次打开的，生成的接收端结束后就不会再有连接持有未提交的写入
+async fn graceful_shutdown(app_handle: &AppHandle) {
+    println!("graceful shutdown start");
+    let message_token_manager = app_handle.state::<MessageTokenManager>();
+    message_token_manager.cancel_all().await;
+    let mut waited = Duration::ZERO;
+    while message_token_manager.running_count().await > 0 && waited < FLUSH_TIMEOUT {
+        tokio::time::sleep(FLUSH_POLL_INTERVAL).await;
+        waited += FLUSH_POLL_INTERVAL;
+    }
+    delete_incognito_conversations(app_handle);
+
+    save_window_sessions(app_handle);
+    save_window_geometry(app_handle);
+
+    #[cfg(desktop)]
+    {
+        use tauri_plugin_global_shortcut::GlobalShortcutExt;
+        if let Err(e) = app_handle.global_shortcut().unregister_all() {
+            println!("unregister global shortcuts error: {:?}", e);
+        }
+    }
+    println!("graceful shutdown done");
+}
@@ -0,0 +1,22 @@
+use std::sync::Mutex;
+
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+/// 等待写入审计日志的一条记录
+pub struct AuditRecord {
+    pub kind: String,
+    pub conversation_id: Option<i64>,
+    pub message_id: Option<i64>,
+    pub content: Value,
+}
+
+/// 审计日志的写入队列和链尾：tail 为下一条记录的序号和上一条记录的 hash，为 None 时在下次写入前从文件读取
+///
+/// 所有记录都通过 sender 交给同一个后台任务按调用顺序逐条写入，多个生成任务同时记录时链不会交错
+#[derive(Default)]
+pub struct AuditLogState {
+    pub tail: Mutex<Option<(u64, String)>>,
+    // 第一次记录时启动写入任务
+    pub sender: Mutex<Option<mpsc::UnboundedSender<AuditRecord>>>,
+}
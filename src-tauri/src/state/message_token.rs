@@ -1,16 +1,56 @@
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex as StdMutex};
 use tokio::sync::Mutex;
 use tokio_util::sync::CancellationToken;
 
 pub struct MessageTokenManager {
     tokens: Arc<Mutex<HashMap<i64, CancellationToken>>>,
+    // 每个对话一把锁，同一个对话的生成依次进行，不同对话之间可以并行
+    // 命令中需要同步检查对话是否正在生成，使用标准库的锁，持有期间不会等待
+    conversation_locks: StdMutex<HashMap<i64, Arc<Mutex<()>>>>,
 }
 
 impl MessageTokenManager {
     pub fn new() -> Self {
         Self {
             tokens: Arc::new(Mutex::new(HashMap::new())),
+            conversation_locks: StdMutex::new(HashMap::new()),
+        }
+    }
+
+    /// 获取对话的生成锁，生成开始前加锁，结束后释放
+    pub async fn conversation_lock(&self, conversation_id: i64) -> Arc<Mutex<()>> {
+        let mut locks = self.locks();
+        // 只剩这里持有的锁说明对应的对话已经没有生成在进行或者排队
+        locks.retain(|id, lock| *id == conversation_id || Arc::strong_count(lock) > 1);
+        locks.entry(conversation_id).or_default().clone()
+    }
+
+    /// 对话是否有生成在进行或者排队，这时不能删除对话、编辑消息等
+    pub fn is_conversation_busy(&self, conversation_id: i64) -> bool {
+        self.locks()
+            .get(&conversation_id)
+            .is_some_and(|lock| Arc::strong_count(lock) > 1)
+    }
+
+    /// 生成结束后归还对话的生成锁，对话上已经没有其他生成时返回对话 id
+    pub fn release_conversation_lock(&self, conversation_lock: Arc<Mutex<()>>) -> Option<i64> {
+        let locks = self.locks();
+        let conversation_id = locks
+            .iter()
+            .find(|(_, lock)| Arc::ptr_eq(lock, &conversation_lock))
+            .map(|(id, _)| *id)?;
+        drop(conversation_lock);
+        locks
+            .get(&conversation_id)
+            .filter(|lock| Arc::strong_count(lock) == 1)
+            .map(|_| conversation_id)
+    }
+
+    fn locks(&self) -> std::sync::MutexGuard<'_, HashMap<i64, Arc<Mutex<()>>>> {
+        match self.conversation_locks.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
         }
     }
 
@@ -38,7 +78,41 @@ impl MessageTokenManager {
         map.remove(&message_id);
     }
 
+    /// 正在生成的消息数量，生成结束时令牌会被移除
+    pub async fn running_count(&self) -> usize {
+        self.tokens.lock().await.len()
+    }
+
+    /// 取消所有正在进行的生成，令牌保留到接收端保存完内容后再移除
+    pub async fn cancel_all(&self) {
+        for token in self.tokens.lock().await.values() {
+            token.cancel();
+        }
+    }
+
     pub fn get_tokens(&self) -> Arc<Mutex<HashMap<i64, CancellationToken>>> {
         Arc::clone(&self.tokens)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_conversation_busy() {
+        let manager = MessageTokenManager::new();
+        assert!(!manager.is_conversation_busy(1));
+
+        let first = manager.conversation_lock(1).await;
+        let second = manager.conversation_lock(1).await;
+        assert!(manager.is_conversation_busy(1));
+        assert!(!manager.is_conversation_busy(2));
+
+        // 还有排队的生成时不算空闲
+        assert_eq!(manager.release_conversation_lock(first), None);
+        assert!(manager.is_conversation_busy(1));
+        assert_eq!(manager.release_conversation_lock(second), Some(1));
+        assert!(!manager.is_conversation_busy(1));
+    }
+}
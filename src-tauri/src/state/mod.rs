@@ -1 +1,9 @@
+pub mod audit_log;
 pub mod message_token;
+pub mod model_preload;
+pub mod private_conversation;
+pub mod request_dedup;
+pub mod response_cache;
+pub mod tokenizer;
+pub mod window_focus;
+pub mod window_session;
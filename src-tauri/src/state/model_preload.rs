@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// 预加载成功后这么久以内不再重复发送预加载请求，比 Ollama 默认保留模型的 5 分钟短
+pub const PRELOAD_REFRESH_INTERVAL: Duration = Duration::from_secs(4 * 60);
+
+enum PreloadEntry {
+    Loading,
+    Loaded(Instant),
+}
+
+/// 本地模型的预加载状态，按提供商和模型代码区分，频繁打开询问窗口或者按快捷键时不重复加载同一个模型
+#[derive(Default)]
+pub struct ModelPreloadState {
+    entries: Mutex<HashMap<(i64, String), PreloadEntry>>,
+}
+
+impl ModelPreloadState {
+    /// 返回是否需要发送预加载请求，需要时标记为正在加载；正在加载或者刚加载过时返回 false
+    pub fn begin(&self, provider_id: i64, model_code: &str) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        let key = (provider_id, model_code.to_string());
+        match entries.get(&key) {
+            Some(PreloadEntry::Loading) => false,
+            Some(PreloadEntry::Loaded(at)) if at.elapsed() < PRELOAD_REFRESH_INTERVAL => false,
+            _ => {
+                entries.insert(key, PreloadEntry::Loading);
+                true
+            }
+        }
+    }
+
+    /// 预加载结束，失败时清除记录，下次可以重试
+    pub fn finish(&self, provider_id: i64, model_code: &str, loaded: bool) {
+        let mut entries = self.entries.lock().unwrap();
+        let key = (provider_id, model_code.to_string());
+        if loaded {
+            entries.insert(key, PreloadEntry::Loaded(Instant::now()));
+        } else {
+            entries.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_preload_state() {
+        let state = ModelPreloadState::default();
+        assert!(state.begin(1, "llama3"));
+        // 正在加载时不重复请求，其他模型不受影响
+        assert!(!state.begin(1, "llama3"));
+        assert!(state.begin(2, "llama3"));
+
+        state.finish(1, "llama3", true);
+        assert!(!state.begin(1, "llama3"));
+
+        state.finish(2, "llama3", false);
+        assert!(state.begin(2, "llama3"));
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use crate::crypto::{self, KEY_LEN};
+use crate::AppHandle;
+
+const KEYCHAIN_ACCOUNT: &str = "conversation_key";
+
+#[derive(Default)]
+struct PrivateConversationInner {
+    // 密钥只在第一次解锁时从系统钥匙串读取，之后保存在内存中
+    key: Option<[u8; KEY_LEN]>,
+    private_ids: HashSet<i64>,
+    unlocked_ids: HashSet<i64>,
+    // 无痕助手的对话用本次运行随机生成的密钥加密，退出后内容无法恢复
+    session_key: Option<[u8; KEY_LEN]>,
+    incognito_ids: HashSet<i64>,
+}
+
+/// 私密对话的密钥和锁定状态，私密对话的消息和附件内容加密保存，只有解锁后才能读写
+#[derive(Clone, Default)]
+pub struct PrivateConversationState {
+    inner: Arc<Mutex<PrivateConversationInner>>,
+}
+
+impl PrivateConversationState {
+    pub fn new(private_ids: Vec<i64>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(PrivateConversationInner {
+                private_ids: private_ids.into_iter().collect(),
+                ..Default::default()
+            })),
+        }
+    }
+
+    pub fn is_private(&self, conversation_id: i64) -> bool {
+        self.inner
+            .lock()
+            .unwrap()
+            .private_ids
+            .contains(&conversation_id)
+    }
+
+    /// 私密对话未解锁时返回 true，普通对话总是返回 false
+    pub fn is_locked(&self, conversation_id: i64) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner.private_ids.contains(&conversation_id)
+            && !inner.unlocked_ids.contains(&conversation_id)
+    }
+
+    /// 读取密钥并解锁对话，还没有任何私密对话并且钥匙串中没有密钥时会生成一个，
+    /// 需要新密钥的对话要在解锁之后再调用 set_private
+    pub fn unlock(&self, app_handle: &AppHandle, conversation_id: i64) -> Result<(), String> {
+        let (has_key, can_create) = {
+            let inner = self.inner.lock().unwrap();
+            (inner.key.is_some(), inner.private_ids.is_empty())
+        };
+        if !has_key {
+            // 访问钥匙串可能会弹出系统授权，不持有锁
+            let key = crypto::load_or_create_key(app_handle, KEYCHAIN_ACCOUNT, can_create)?;
+            self.inner.lock().unwrap().key = Some(key);
+        }
+        self.inner
+            .lock()
+            .unwrap()
+            .unlocked_ids
+            .insert(conversation_id);
+        Ok(())
+    }
+
+    pub fn lock(&self, conversation_id: i64) {
+        self.inner
+            .lock()
+            .unwrap()
+            .unlocked_ids
+            .remove(&conversation_id);
+    }
+
+    pub fn set_private(&self, conversation_id: i64, private: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        if private {
+            inner.private_ids.insert(conversation_id);
+        } else {
+            inner.private_ids.remove(&conversation_id);
+            inner.unlocked_ids.remove(&conversation_id);
+        }
+    }
+
+    /// 把对话标记为无痕对话，之后保存的内容使用只在内存中的密钥加密
+    pub fn set_incognito(&self, conversation_id: i64) -> Result<(), String> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.session_key.is_none() {
+            inner.session_key = Some(crypto::generate_key()?);
+        }
+        inner.incognito_ids.insert(conversation_id);
+        Ok(())
+    }
+
+    pub fn is_incognito(&self, conversation_id: i64) -> bool {
+        self.inner
+            .lock()
+            .unwrap()
+            .incognito_ids
+            .contains(&conversation_id)
+    }
+
+    /// 本次运行中创建的无痕对话
+    pub fn incognito_ids(&self) -> Vec<i64> {
+        self.inner
+            .lock()
+            .unwrap()
+            .incognito_ids
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    /// 保存前调用，私密对话的内容加密后返回，普通对话原样返回
+    pub fn encrypt(&self, conversation_id: i64, text: &str) -> Result<String, String> {
+        let inner = self.inner.lock().unwrap();
+        if inner.incognito_ids.contains(&conversation_id) && !crypto::is_encrypted(text) {
+            if let Some(key) = &inner.session_key {
+                return crypto::encrypt_text(key, text);
+            }
+        }
+        if !inner.private_ids.contains(&conversation_id) || crypto::is_encrypted(text) {
+            return Ok(text.to_string());
+        }
+        match (&inner.key, inner.unlocked_ids.contains(&conversation_id)) {
+            (Some(key), true) => crypto::encrypt_text(key, text),
+            _ => Err("对话已锁定，请先解锁".to_string()),
+        }
+    }
+
+    /// 读取后调用，加密的内容需要对话已经解锁
+    pub fn decrypt(&self, conversation_id: i64, text: &str) -> Result<String, String> {
+        if !crypto::is_encrypted(text) {
+            return Ok(text.to_string());
+        }
+        let inner = self.inner.lock().unwrap();
+        if inner.incognito_ids.contains(&conversation_id) {
+            if let Some(key) = &inner.session_key {
+                return crypto::decrypt_text(key, text);
+            }
+        }
+        match (&inner.key, inner.unlocked_ids.contains(&conversation_id)) {
+            (Some(key), true) => crypto::decrypt_text(key, text),
+            _ => Err("对话已锁定，请先解锁".to_string()),
+        }
+    }
+}
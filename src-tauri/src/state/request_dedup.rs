@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use tokio::sync::{watch, Mutex};
+
+// 这个时间内相同的提问只发起一次生成
+const DEDUP_WINDOW: Duration = Duration::from_secs(3);
+
+/// 判断两次提问是否相同：同一个对话（新对话按助手区分）、相同的模型、相同的附件，
+/// 提问内容去掉首尾空白并把连续的空白合并后相同
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DedupKey {
+    conversation: String,
+    prompt: String,
+    model: String,
+    attachments: Vec<i64>,
+}
+
+impl DedupKey {
+    pub fn new(
+        conversation_id: &str,
+        assistant_id: i64,
+        prompt: &str,
+        model: &str,
+        attachments: Option<&[i64]>,
+    ) -> Self {
+        let conversation = if conversation_id.is_empty() {
+            format!("new:{}", assistant_id)
+        } else {
+            conversation_id.to_string()
+        };
+        let mut attachments = attachments.map(|ids| ids.to_vec()).unwrap_or_default();
+        attachments.sort_unstable();
+        DedupKey {
+            conversation,
+            prompt: prompt.split_whitespace().collect::<Vec<_>>().join(" "),
+            model: model.to_string(),
+            attachments,
+        }
+    }
+}
+
+struct RecentRequest<T> {
+    started: Instant,
+    // 第一次请求完成初始化后发送结果，重复的请求等待这个结果
+    result: watch::Receiver<Option<T>>,
+}
+
+pub enum DedupClaim<T> {
+    // 相同的请求正在进行，直接使用它的结果
+    Duplicate(T),
+    // 第一次请求，初始化完成后通过 complete 通知重复的请求
+    First(watch::Sender<Option<T>>),
+    // 相同的请求失败了，按新请求处理但不登记
+    Retry,
+}
+
+/// 连续按发送快捷键时相同的提问会同时发出，这里把短时间内的重复提问合并成一次生成
+pub struct RequestDedupState<T> {
+    recent: Mutex<HashMap<DedupKey, RecentRequest<T>>>,
+}
+
+impl<T> Default for RequestDedupState<T> {
+    fn default() -> Self {
+        RequestDedupState {
+            recent: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T: Clone> RequestDedupState<T> {
+    pub async fn claim(&self, key: DedupKey) -> DedupClaim<T> {
+        let mut result = {
+            let mut recent = self.recent.lock().await;
+            recent.retain(|_, request| request.started.elapsed() < DEDUP_WINDOW);
+            match recent.get(&key) {
+                Some(request) => request.result.clone(),
+                None => {
+                    let (tx, rx) = watch::channel(None);
+                    recent.insert(
+                        key,
+                        RecentRequest {
+                            started: Instant::now(),
+                            result: rx,
+                        },
+                    );
+                    return DedupClaim::First(tx);
+                }
+            }
+        };
+        // 第一次请求出错时 sender 被丢弃，wait_for 返回错误
+        let value = match result.wait_for(|value| value.is_some()).await {
+            Ok(value) => value.clone(),
+            Err(_) => None,
+        };
+        value.map_or(DedupClaim::Retry, DedupClaim::Duplicate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(prompt: &str) -> DedupKey {
+        DedupKey::new("1", 1, prompt, "gpt-4o", Some(&[2, 1]))
+    }
+
+    #[test]
+    fn test_dedup_key_normalizes_prompt() {
+        assert_eq!(key("  你好\n 世界 "), key("你好 世界"));
+        assert_eq!(
+            DedupKey::new("1", 1, "你好", "gpt-4o", Some(&[1, 2])),
+            key("你好")
+        );
+        assert_ne!(
+            key("你好"),
+            DedupKey::new("1", 1, "你好", "gpt-4o-mini", Some(&[1, 2]))
+        );
+        // 新对话按助手区分
+        assert_ne!(
+            DedupKey::new("", 1, "你好", "gpt-4o", None),
+            DedupKey::new("", 2, "你好", "gpt-4o", None)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_reuses_first_result() {
+        let state = RequestDedupState::<i64>::default();
+        let DedupClaim::First(sender) = state.claim(key("你好")).await else {
+            panic!("first claim should start a request");
+        };
+        let waiting = state.claim(key("你好"));
+        sender.send(Some(42)).unwrap();
+        assert!(matches!(waiting.await, DedupClaim::Duplicate(42)));
+        // 不同的提问不受影响
+        assert!(matches!(
+            state.claim(key("再见")).await,
+            DedupClaim::First(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_failed_first_request_retries() {
+        let state = RequestDedupState::<i64>::default();
+        let DedupClaim::First(sender) = state.claim(key("你好")).await else {
+            panic!("first claim should start a request");
+        };
+        drop(sender);
+        assert!(matches!(state.claim(key("你好")).await, DedupClaim::Retry));
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::api::llm::{ChatMessage, ContentPart};
+use crate::db::assistant_db::AssistantModelConfig;
+use crate::db::system_db::FeatureConfig;
+
+// 缓存的回答数量上限，超过后淘汰最早写入的
+const MAX_ENTRIES: usize = 200;
+const DEFAULT_TTL_MINUTES: u64 = 60;
+
+/// 回答缓存的配置，保存在 response_cache 功能配置中，默认关闭
+///
+/// 开启后 temperature 为 0 的提问，以及多模型对比、对比实验中设置了 use_response_cache 的提问，
+/// 在 ttl_minutes 内遇到相同的模型、消息和参数时直接使用之前的回答
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResponseCacheConfig {
+    pub enabled: bool,
+    pub ttl: Duration,
+}
+
+impl ResponseCacheConfig {
+    pub fn from_config_map(
+        config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+    ) -> Self {
+        let config = config_feature_map.get("response_cache");
+        let get_value = |key: &str| {
+            config
+                .and_then(|config| config.get(key))
+                .map(|config| config.value.trim().to_string())
+                .unwrap_or_default()
+        };
+        let ttl_minutes = get_value("ttl_minutes")
+            .parse::<u64>()
+            .ok()
+            .filter(|minutes| *minutes > 0)
+            .unwrap_or(DEFAULT_TTL_MINUTES);
+        ResponseCacheConfig {
+            enabled: get_value("enabled") == "true",
+            ttl: Duration::from_secs(ttl_minutes * 60),
+        }
+    }
+}
+
+/// 缓存的一次完整回答
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedResponse {
+    pub content: String,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct ResponseCacheStats {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+}
+
+/// 计算缓存的键：提供商、模型、整理后的消息和模型参数的哈希
+///
+/// 消息统一换行符并去掉每行末尾和整体首尾的空白，附件按内容参与计算；参数按名称排序，和配置的顺序无关
+pub fn response_cache_key(
+    provider_id: i64,
+    model_code: &str,
+    messages: &[ChatMessage],
+    model_config: &[AssistantModelConfig],
+) -> String {
+    let mut hasher = Sha256::new();
+    // 每一段前面加上长度，避免不同的拼接方式得到相同的输入
+    let mut update = |bytes: &[u8]| {
+        hasher.update((bytes.len() as u64).to_le_bytes());
+        hasher.update(bytes);
+    };
+    update(provider_id.to_string().as_bytes());
+    update(model_code.as_bytes());
+    for message in messages {
+        update(message.role.as_str().as_bytes());
+        for part in &message.content {
+            match part {
+                ContentPart::Text(text) => update(normalize_text(text).as_bytes()),
+                ContentPart::Blocks(blocks) => update(blocks.to_string().as_bytes()),
+                ContentPart::Attachment(attachment) => {
+                    update(&[attachment.attachment_type as u8]);
+                    update(
+                        attachment
+                            .attachment_content
+                            .as_deref()
+                            .unwrap_or_default()
+                            .as_bytes(),
+                    );
+                }
+            }
+        }
+    }
+    let mut params = model_config
+        .iter()
+        .map(|config| (config.name.as_str(), config.value.as_deref().unwrap_or("")))
+        .collect::<Vec<_>>();
+    params.sort();
+    for (name, value) in params {
+        update(name.as_bytes());
+        update(value.trim().as_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
+
+fn normalize_text(text: &str) -> String {
+    text.replace("\r\n", "\n")
+        .lines()
+        .map(|line| line.trim_end())
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+#[derive(Default)]
+struct CacheInner {
+    entries: HashMap<String, (Instant, CachedResponse)>,
+    hits: u64,
+    misses: u64,
+}
+
+/// 内存中的回答缓存，重启后清空
+#[derive(Default)]
+pub struct ResponseCacheState {
+    inner: Mutex<CacheInner>,
+}
+
+impl ResponseCacheState {
+    pub fn get(&self, key: &str, ttl: Duration) -> Option<CachedResponse> {
+        self.get_at(key, ttl, Instant::now())
+    }
+
+    pub fn insert(&self, key: String, response: CachedResponse) {
+        self.insert_at(key, response, Instant::now());
+    }
+
+    /// 清空缓存和命中统计，返回清除的回答数量
+    pub fn clear(&self) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let count = inner.entries.len();
+        *inner = CacheInner::default();
+        count
+    }
+
+    pub fn stats(&self) -> ResponseCacheStats {
+        let inner = self.inner.lock().unwrap();
+        let total = inner.hits + inner.misses;
+        ResponseCacheStats {
+            entries: inner.entries.len(),
+            hits: inner.hits,
+            misses: inner.misses,
+            hit_rate: if total == 0 {
+                0.0
+            } else {
+                inner.hits as f64 / total as f64
+            },
+        }
+    }
+
+    fn get_at(&self, key: &str, ttl: Duration, now: Instant) -> Option<CachedResponse> {
+        let mut inner = self.inner.lock().unwrap();
+        let cached = match inner.entries.get(key) {
+            Some((created, response)) if now.duration_since(*created) < ttl => {
+                Some(response.clone())
+            }
+            Some(_) => {
+                inner.entries.remove(key);
+                None
+            }
+            None => None,
+        };
+        match cached {
+            Some(_) => inner.hits += 1,
+            None => inner.misses += 1,
+        }
+        cached
+    }
+
+    fn insert_at(&self, key: String, response: CachedResponse, now: Instant) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.entries.len() >= MAX_ENTRIES && !inner.entries.contains_key(&key) {
+            let oldest = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, (created, _))| *created)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                inner.entries.remove(&oldest);
+            }
+        }
+        inner.entries.insert(key, (now, response));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::llm::Role;
+
+    fn config(name: &str, value: &str) -> AssistantModelConfig {
+        AssistantModelConfig {
+            id: 0,
+            assistant_id: 1,
+            assistant_model_id: 1,
+            name: name.to_string(),
+            value: Some(value.to_string()),
+            value_type: "string".to_string(),
+        }
+    }
+
+    fn response(content: &str) -> CachedResponse {
+        CachedResponse {
+            content: content.to_string(),
+            finish_reason: Some("stop".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let messages = vec![
+            ChatMessage::new(Role::System, "你是一个助手"),
+            ChatMessage::new(Role::User, "你好\r\n"),
+        ];
+        let params = vec![config("temperature", "0"), config("top_p", "1")];
+        let key = response_cache_key(1, "gpt-4o", &messages, &params);
+        // 换行符、末尾空白和参数顺序不影响
+        let same = response_cache_key(
+            1,
+            "gpt-4o",
+            &[
+                ChatMessage::new(Role::System, "你是一个助手 "),
+                ChatMessage::new(Role::User, "你好"),
+            ],
+            &[config("top_p", "1"), config("temperature", "0")],
+        );
+        assert_eq!(key, same);
+        assert_ne!(key, response_cache_key(2, "gpt-4o", &messages, &params));
+        assert_ne!(
+            key,
+            response_cache_key(1, "gpt-4o", &messages, &[config("temperature", "0.5")])
+        );
+        assert_ne!(
+            key,
+            response_cache_key(
+                1,
+                "gpt-4o",
+                &[ChatMessage::new(Role::User, "你好")],
+                &params
+            )
+        );
+    }
+
+    #[test]
+    fn test_ttl_and_stats() {
+        let cache = ResponseCacheState::default();
+        let now = Instant::now();
+        let ttl = Duration::from_secs(60);
+        assert_eq!(cache.get_at("a", ttl, now), None);
+        cache.insert_at("a".to_string(), response("答案"), now);
+        assert_eq!(
+            cache.get_at("a", ttl, now + Duration::from_secs(30)),
+            Some(response("答案"))
+        );
+        assert_eq!(cache.get_at("a", ttl, now + Duration::from_secs(61)), None);
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (0, 1, 2));
+
+        cache.insert_at("b".to_string(), response("b"), now);
+        assert_eq!(cache.clear(), 1);
+        assert_eq!(cache.stats(), ResponseCacheStats::default());
+    }
+
+    #[test]
+    fn test_evict_oldest() {
+        let cache = ResponseCacheState::default();
+        let now = Instant::now();
+        for i in 0..=MAX_ENTRIES {
+            cache.insert_at(
+                i.to_string(),
+                response("x"),
+                now + Duration::from_millis(i as u64),
+            );
+        }
+        let ttl = Duration::from_secs(60);
+        assert_eq!(cache.stats().entries, MAX_ENTRIES);
+        assert_eq!(cache.get_at("0", ttl, now), None);
+        assert!(cache.get_at("1", ttl, now).is_some());
+    }
+}
@@ -0,0 +1,22 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::tokenizer::{create_tokenizer, tokenizer_family, Tokenizer};
+
+/// 缓存各个模型家族的分词器实例，输入框每次输入都会估算 token 数量，避免重复创建
+#[derive(Default)]
+pub struct TokenizerState {
+    tokenizers: Mutex<HashMap<&'static str, Arc<dyn Tokenizer>>>,
+}
+
+impl TokenizerState {
+    pub fn get(&self, model_code: &str) -> Arc<dyn Tokenizer> {
+        let family = tokenizer_family(model_code);
+        self.tokenizers
+            .lock()
+            .unwrap()
+            .entry(family)
+            .or_insert_with(|| Arc::new(create_tokenizer(family)))
+            .clone()
+    }
+}
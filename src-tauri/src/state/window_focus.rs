@@ -0,0 +1,68 @@
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+// 前台窗口的生成内容发送间隔，和 StreamBatcher 默认的间隔一致
+pub const FOCUSED_FLUSH_INTERVAL: Duration = Duration::from_millis(50);
+// 后台窗口的生成合并更多内容再发送，减少 IPC 事件数量
+pub const BACKGROUND_FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// 当前获得焦点的窗口，生成回答时前台窗口的内容更频繁地发送，后台窗口降低发送频率
+#[derive(Default)]
+pub struct WindowFocusState {
+    focused: Mutex<Option<String>>,
+}
+
+impl WindowFocusState {
+    fn focused(&self) -> MutexGuard<'_, Option<String>> {
+        match self.focused.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    pub fn set_focused(&self, label: &str, focused: bool) {
+        let mut current = self.focused();
+        if focused {
+            *current = Some(label.to_string());
+        } else if current.as_deref() == Some(label) {
+            // 焦点切换时新窗口的 Focused(true) 可能先于旧窗口的 Focused(false) 到达
+            *current = None;
+        }
+    }
+
+    pub fn is_focused(&self, label: &str) -> bool {
+        self.focused().as_deref() == Some(label)
+    }
+
+    /// 窗口中生成内容的发送间隔，每收到一段内容时重新读取，窗口切到前台后马上恢复正常频率
+    pub fn flush_interval(&self, label: &str) -> Duration {
+        if self.is_focused(label) {
+            FOCUSED_FLUSH_INTERVAL
+        } else {
+            BACKGROUND_FLUSH_INTERVAL
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_focus() {
+        let state = WindowFocusState::default();
+        assert_eq!(state.flush_interval("chat_ui"), BACKGROUND_FLUSH_INTERVAL);
+
+        state.set_focused("chat_ui", true);
+        assert_eq!(state.flush_interval("chat_ui"), FOCUSED_FLUSH_INTERVAL);
+
+        // 新窗口先获得焦点，旧窗口失去焦点的事件不影响新窗口
+        state.set_focused("ask", true);
+        state.set_focused("chat_ui", false);
+        assert!(state.is_focused("ask"));
+        assert!(!state.is_focused("chat_ui"));
+
+        state.set_focused("ask", false);
+        assert!(!state.is_focused("ask"));
+    }
+}
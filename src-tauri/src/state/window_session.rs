@@ -0,0 +1,22 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// 各个窗口当前显示的对话，前端切换对话时上报，退出时保存下来用于下次启动时恢复
+#[derive(Default)]
+pub struct WindowSessionState {
+    conversations: Mutex<HashMap<String, i64>>,
+}
+
+impl WindowSessionState {
+    pub fn set(&self, label: &str, conversation_id: Option<i64>) {
+        let mut conversations = self.conversations.lock().unwrap();
+        match conversation_id {
+            Some(id) => conversations.insert(label.to_string(), id),
+            None => conversations.remove(label),
+        };
+    }
+
+    pub fn get(&self, label: &str) -> Option<i64> {
+        self.conversations.lock().unwrap().get(label).copied()
+    }
+}
@@ -3,10 +3,12 @@ use futures::future::BoxFuture;
 use futures::FutureExt;
 use htmd;
 use regex::Regex;
-use reqwest;
 use serde::Serialize;
 use std::collections::HashMap;
 
+use crate::api::llm::{client_builder, is_local_endpoint, send_request};
+use crate::knowledge::injection_guard::InjectionGuard;
+
 // 定义命令处理函数类型
 type CommandFn = fn(TemplateEngine, String, HashMap<String, String>) -> BoxFuture<'static, String>;
 
@@ -67,22 +69,32 @@ fn selected_text(
 }
 
 // 新增获取网页内容的函数
-fn web(_: TemplateEngine, url: String, _: HashMap<String, String>) -> BoxFuture<'static, String> {
+fn web(
+    engine: TemplateEngine,
+    url: String,
+    _: HashMap<String, String>,
+) -> BoxFuture<'static, String> {
     async move {
         // 移除url中前后的括号
         let url = url.trim_start_matches('(').trim_end_matches(')');
+        if !engine.allows_url(url) {
+            return format!("离线模式下不获取网页 {}", url);
+        }
 
-        let client = reqwest::Client::builder()
-            .danger_accept_invalid_certs(true)
-            .build()
-            .unwrap();
+        let client = match client_builder().danger_accept_invalid_certs(true).build() {
+            Ok(client) => client,
+            Err(err) => return err.to_string(),
+        };
 
-        match client.get(url).send().await {
+        match send_request(client.get(url)).await {
             Ok(response) => {
                 let html = response
                     .text()
                     .await
                     .unwrap_or_else(|_| "Failed to get web content".to_string());
+                let html = engine
+                    .injection_guard
+                    .apply(&format!("网页 {}", url), &html);
                 format!("\n<bangweb url=\"{}\">\n{}\n</bangweb>", url, html)
             }
             Err(err) => err.to_string(),
@@ -93,22 +105,30 @@ fn web(_: TemplateEngine, url: String, _: HashMap<String, String>) -> BoxFuture<
 
 // 新增获取网页内容并转换为 Markdown 的函数
 fn web_to_markdown(
-    _: TemplateEngine,
+    engine: TemplateEngine,
     url: String,
     _: HashMap<String, String>,
 ) -> BoxFuture<'static, String> {
     async move {
         // 移除url中前后的括号
         let url = url.trim_start_matches('(').trim_end_matches(')');
+        if !engine.allows_url(url) {
+            return format!("离线模式下不获取网页 {}", url);
+        }
 
-        let client = reqwest::Client::new();
-        match client.get(url).send().await {
+        let client = match client_builder().build() {
+            Ok(client) => client,
+            Err(err) => return err.to_string(),
+        };
+        match send_request(client.get(url)).await {
             Ok(response) => {
                 let html = response.text().await.unwrap_or_default();
+                let markdown = engine
+                    .injection_guard
+                    .apply(&format!("网页 {}", url), &htmd::convert(&html).unwrap());
                 format!(
                     "\n<bangwebtomarkdown url=\"{}\">\n{}\n</bangwebtomarkdown>",
-                    url,
-                    htmd::convert(&html).unwrap()
+                    url, markdown
                 )
             }
             Err(_) => "".to_string(),
@@ -121,6 +141,10 @@ fn web_to_markdown(
 #[derive(Clone)]
 pub struct TemplateEngine {
     commands: HashMap<String, Bang>,
+    // 网页内容加入提示词前的提示注入检查
+    injection_guard: InjectionGuard,
+    // 离线模式下网页命令只获取本机地址的内容
+    offline: bool,
 }
 
 #[derive(Clone)]
@@ -260,7 +284,27 @@ impl TemplateEngine {
             },
         );
 
-        TemplateEngine { commands }
+        TemplateEngine {
+            commands,
+            injection_guard: InjectionGuard::default(),
+            offline: false,
+        }
+    }
+
+    // 设置网页内容的提示注入检查策略
+    pub fn with_injection_guard(mut self, injection_guard: InjectionGuard) -> Self {
+        self.injection_guard = injection_guard;
+        self
+    }
+
+    // 设置是否处于离线模式
+    pub fn with_offline_mode(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    fn allows_url(&self, url: &str) -> bool {
+        !self.offline || is_local_endpoint(url)
     }
 
     // 注册命令
@@ -193,3 +193,17 @@ async fn test_web_to_markdown_command() {
 
     mock.assert();
 }
+
+#[tokio::test]
+async fn test_web_command_offline() {
+    // 离线模式下不请求本机以外的地址
+    let engine = TemplateEngine::new().with_offline_mode(true);
+    let result = engine
+        .parse("!web(https://example.com)", &HashMap::new())
+        .await;
+    assert_eq!(result, "离线模式下不获取网页 https://example.com");
+    let result = engine
+        .parse("!wm(https://example.com)", &HashMap::new())
+        .await;
+    assert_eq!(result, "离线模式下不获取网页 https://example.com");
+}
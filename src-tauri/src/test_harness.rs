@@ -0,0 +1,1370 @@
+//! 测试用的应用：使用 tauri 的模拟运行时和内存数据库，像前端一样通过 invoke 层调用命令，
+//! 命令的参数名称、参数和返回值的 JSON 格式发生变化时测试会失败
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rusqlite::Connection;
+use serde_json::{json, Value};
+use tauri::ipc::{CallbackFn, InvokeBody};
+use tauri::test::{get_ipc_response, mock_builder, mock_context, noop_assets, INVOKE_KEY};
+use tauri::webview::InvokeRequest;
+use tauri::{App, Manager, WebviewUrl, WebviewWindowBuilder};
+
+use crate::db::health::{run_startup_check, StartupDiagnosticsState};
+use crate::{
+    init_databases, invoke_handler, manage_database_state, manage_state, AppHandle, AppRuntime,
+    WebviewWindow,
+};
+
+// 等待后台生成完成的最长时间
+const WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+const WAIT_INTERVAL: Duration = Duration::from_millis(50);
+
+static NEXT_APP_ID: AtomicU64 = AtomicU64::new(1);
+
+/// 每个测试应用自己的一组内存数据库，get_db_path 在测试中返回这里的地址
+///
+/// 使用 SQLite 的 memdb，以 / 开头的名称在同一个进程的连接之间共享，最后一个连接关闭时数据库被释放，
+/// 所以为每个数据库保留一个连接直到测试应用销毁
+pub struct MemoryDatabases {
+    app_id: u64,
+    connections: Mutex<HashMap<String, Connection>>,
+    temp_dirs: Mutex<Vec<PathBuf>>,
+}
+
+impl MemoryDatabases {
+    fn new() -> Self {
+        MemoryDatabases {
+            app_id: NEXT_APP_ID.fetch_add(1, Ordering::SeqCst),
+            connections: Mutex::new(HashMap::new()),
+            temp_dirs: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// 测试应用自己的临时目录，代替应用数据目录中的 name 目录，测试应用销毁时删除
+    pub fn temp_dir(&self, name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "aipp-test-{}-{}-{}",
+            std::process::id(),
+            self.app_id,
+            name
+        ));
+        let mut temp_dirs = self.temp_dirs.lock().unwrap();
+        if !temp_dirs.contains(&dir) {
+            temp_dirs.push(dir.clone());
+        }
+        dir
+    }
+
+    pub fn path(&self, db_name: &str) -> PathBuf {
+        let path = format!("file:/aipp-test-{}-{}?vfs=memdb", self.app_id, db_name);
+        let mut connections = self.connections.lock().unwrap();
+        if !connections.contains_key(db_name) {
+            match Connection::open(&path) {
+                Ok(conn) => {
+                    connections.insert(db_name.to_string(), conn);
+                }
+                Err(e) => println!("open memory database {} error: {:?}", db_name, e),
+            }
+        }
+        PathBuf::from(path)
+    }
+}
+
+/// 构建方式和 main 相同的应用，状态、命令和数据库初始化都使用 main 中的函数
+pub struct TestApp {
+    app: App<AppRuntime>,
+    webview: WebviewWindow,
+    // 写入临时目录的录制文件，测试应用销毁时删除
+    fixtures: Mutex<Vec<PathBuf>>,
+}
+
+impl TestApp {
+    pub fn new() -> Self {
+        let app = manage_state(mock_builder())
+            .manage(MemoryDatabases::new())
+            .invoke_handler(invoke_handler())
+            .build(mock_context(noop_assets()))
+            .expect("failed to build test app");
+        let app_handle = app.handle();
+        let mut startup_diagnostics = run_startup_check(app_handle);
+        init_databases(app_handle, &mut startup_diagnostics).expect("failed to init databases");
+        assert!(startup_diagnostics.healthy);
+        app.manage(StartupDiagnosticsState(startup_diagnostics));
+        manage_database_state(app_handle).expect("failed to load state from databases");
+        // 询问窗口会按助手配置写入剪贴板，命令从对话窗口调用
+        let webview = WebviewWindowBuilder::new(&app, "chat_ui", WebviewUrl::default())
+            .build()
+            .expect("failed to create test window");
+        TestApp {
+            app,
+            webview,
+            fixtures: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// 通过 invoke 层调用命令，args 和前端传入的一样，参数名称为 camelCase；命令返回错误时为 Err
+    pub fn invoke(&self, cmd: &str, args: Value) -> Result<Value, Value> {
+        invoke_in_webview(&self.webview, cmd, args)
+    }
+
+    /// 从 label 对应的窗口调用命令，窗口不存在时先创建，用于检查按窗口限制的命令
+    pub fn invoke_in(&self, label: &str, cmd: &str, args: Value) -> Result<Value, Value> {
+        let webview = self.app.get_webview_window(label).unwrap_or_else(|| {
+            WebviewWindowBuilder::new(&self.app, label, WebviewUrl::default())
+                .build()
+                .expect("failed to create test window")
+        });
+        invoke_in_webview(&webview, cmd, args)
+    }
+
+    /// 后台任务等没有对应命令的功能直接使用应用句柄调用
+    pub fn app_handle(&self) -> &AppHandle {
+        self.app.handle()
+    }
+
+    pub fn invoke_ok(&self, cmd: &str, args: Value) -> Value {
+        self.invoke(cmd, args)
+            .unwrap_or_else(|e| panic!("{} failed: {}", cmd, e))
+    }
+
+    /// 添加回放 reply 的 mock 提供商，设为默认助手和生成标题使用的模型，返回提供商的 id
+    ///
+    /// 和用户在设置界面中的操作一样通过命令完成，同时检查了提供商、模型、助手和功能配置相关的命令
+    pub fn add_mock_model(&self, reply: &str) -> i64 {
+        self.invoke_ok(
+            "add_llm_provider",
+            json!({ "name": "Mock", "apiType": "mock" }),
+        );
+        let providers = self.invoke_ok("get_llm_providers", json!({}));
+        let provider_id = providers
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|provider| provider["api_type"] == "mock")
+            .and_then(|provider| provider["id"].as_i64())
+            .expect("mock provider not found");
+        self.invoke_ok(
+            "update_llm_provider",
+            json!({
+                "id": provider_id,
+                "name": "Mock",
+                "apiType": "mock",
+                "description": "",
+                "isEnabled": true,
+            }),
+        );
+        self.invoke_ok(
+            "update_llm_provider_config",
+            json!({
+                "llmProviderId": provider_id,
+                "name": "fixture_path",
+                "value": self.write_fixture(reply),
+            }),
+        );
+        self.invoke_ok(
+            "add_llm_model",
+            json!({ "llmProviderId": provider_id, "code": "mock" }),
+        );
+
+        let mut assistant = self.invoke_ok("get_assistant", json!({ "assistantId": 1 }));
+        let model_id = assistant["model"][0]["id"].as_i64().unwrap_or(0);
+        assistant["model"] = json!([{
+            "id": model_id,
+            "assistant_id": 1,
+            "provider_id": provider_id,
+            "model_code": "mock",
+            "alias": "",
+        }]);
+        self.invoke_ok("save_assistant", json!({ "assistantDetail": assistant }));
+        self.invoke_ok(
+            "save_feature_config",
+            json!({
+                "featureCode": "conversation_summary",
+                "config": {
+                    "provider_id": provider_id.to_string(),
+                    "model_code": "mock",
+                    "prompt": "请总结对话",
+                    "summary_length": "100",
+                },
+            }),
+        );
+        provider_id
+    }
+
+    // 把回答写成 OpenAI 格式的流式录制文件，返回文件路径
+    fn write_fixture(&self, reply: &str) -> String {
+        let events = [
+            json!({ "choices": [{ "delta": { "content": reply } }] }),
+            json!({ "choices": [{ "delta": {}, "finish_reason": "stop" }] }),
+        ];
+        self.write_events_fixture("reply", &events)
+    }
+
+    /// 把 OpenAI 格式的流式事件写成录制文件，name 用来区分同一个测试应用的多个文件，返回文件路径
+    ///
+    /// 文件在测试应用销毁时删除
+    pub fn write_events_fixture(&self, name: &str, events: &[Value]) -> String {
+        // 每个事件是一个数据块，mock 提供商的 error_after 可以在任意事件之后注入错误
+        let chunks = events
+            .iter()
+            .map(|event| format!("data: {}\n\n", event))
+            .chain(["data: [DONE]\n\n".to_string()])
+            .map(|data| format!("{}\n", json!({ "delay_ms": 0, "data": data })))
+            .collect::<String>();
+        let path = std::env::temp_dir().join(format!(
+            "aipp_harness_{}_{}_{}.jsonl",
+            std::process::id(),
+            self.memory_id(),
+            name
+        ));
+        std::fs::write(&path, chunks).unwrap();
+        self.fixtures.lock().unwrap().push(path.clone());
+        path.to_string_lossy().to_string()
+    }
+
+    fn memory_id(&self) -> u64 {
+        self.app.state::<MemoryDatabases>().app_id
+    }
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        if let Ok(fixtures) = self.fixtures.lock() {
+            for path in fixtures.iter() {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+        if let Ok(temp_dirs) = self.app.state::<MemoryDatabases>().temp_dirs.lock() {
+            for dir in temp_dirs.iter() {
+                let _ = std::fs::remove_dir_all(dir);
+            }
+        }
+    }
+}
+
+fn invoke_in_webview(webview: &WebviewWindow, cmd: &str, args: Value) -> Result<Value, Value> {
+    let request = InvokeRequest {
+        cmd: cmd.to_string(),
+        callback: CallbackFn(0),
+        error: CallbackFn(1),
+        url: "http://tauri.localhost".parse().unwrap(),
+        body: InvokeBody::Json(args),
+        headers: Default::default(),
+        invoke_key: INVOKE_KEY.to_string(),
+    };
+    get_ipc_response(webview, request).map(|body| {
+        body.deserialize::<Value>()
+            .unwrap_or_else(|e| panic!("{} returned invalid json: {:?}", cmd, e))
+    })
+}
+
+/// 每隔一段时间检查一次，直到 check 返回 Some，超过 WAIT_TIMEOUT 时失败
+pub fn wait_for<T>(what: &str, mut check: impl FnMut() -> Option<T>) -> T {
+    let started = Instant::now();
+    loop {
+        if let Some(value) = check() {
+            return value;
+        }
+        assert!(
+            started.elapsed() < WAIT_TIMEOUT,
+            "timed out waiting for {}",
+            what
+        );
+        std::thread::sleep(WAIT_INTERVAL);
+    }
+}
+
+/// 简单的线性同余生成器，随机测试用固定的种子，保证每次运行生成的数据一致
+pub struct Lcg(pub u64);
+
+impl Lcg {
+    /// 0 到 max 之间（不含 max）的下一个数
+    pub fn next(&mut self, max: usize) -> usize {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((self.0 >> 33) as usize) % max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::message_token::MessageTokenManager;
+
+    #[test]
+    fn test_config_commands() {
+        let app = TestApp::new();
+        app.invoke_ok(
+            "save_feature_config",
+            json!({
+                "featureCode": "display",
+                "config": { "theme": "dark" },
+            }),
+        );
+        let configs = app.invoke_ok("get_all_feature_config", json!({}));
+        let theme = configs
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|config| config["feature_code"] == "display" && config["key"] == "theme")
+            .expect("saved config not returned");
+        assert_eq!(theme["value"], "dark");
+
+        assert_eq!(app.invoke_ok("get_offline_mode", json!({})), json!(false));
+        app.invoke_ok("set_offline_mode", json!({ "enabled": true }));
+        assert_eq!(app.invoke_ok("get_offline_mode", json!({})), json!(true));
+
+        let diagnostics = app.invoke_ok("get_startup_diagnostics", json!({}));
+        assert_eq!(diagnostics["healthy"], json!(true));
+        // 缺少参数时 invoke 层直接返回错误
+        assert!(app.invoke("set_offline_mode", json!({})).is_err());
+    }
+
+    #[test]
+    fn test_model_price_commands() {
+        let app = TestApp::new();
+        let model_price = |model_code: &str| {
+            app.invoke_ok("get_all_feature_config", json!({}))
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|config| {
+                    config["feature_code"] == "model_price" && config["key"] == model_code
+                })
+                .map(|config| config["value"].clone())
+        };
+        // 初始化时带有常用模型的价格
+        assert_eq!(model_price("gpt-4o"), Some(json!("2.5,10")));
+
+        app.invoke_ok(
+            "set_model_price",
+            json!({ "modelCode": "gpt-4o", "inputPrice": 2.0, "outputPrice": 8.0 }),
+        );
+        assert_eq!(model_price("gpt-4o"), Some(json!("2,8")));
+        assert!(app
+            .invoke(
+                "set_model_price",
+                json!({ "modelCode": "gpt-4o", "inputPrice": -1.0, "outputPrice": 8.0 }),
+            )
+            .is_err());
+        app.invoke_ok("set_model_price", json!({ "modelCode": "gpt-4o" }));
+        assert_eq!(model_price("gpt-4o"), None);
+    }
+
+    #[test]
+    fn test_copy_assistant() {
+        let app = TestApp::new();
+        app.invoke_ok(
+            "save_assistant_example",
+            json!({
+                "example": {
+                    "id": 0,
+                    "assistant_id": 1,
+                    "user_content": "你好",
+                    "assistant_content": "你好！",
+                    "is_enabled": true,
+                },
+            }),
+        );
+        // 复制助手和克隆助手一样带上回答风格示例
+        let copied = app.invoke_ok("copy_assistant", json!({ "assistantId": 1 }));
+        let examples = app.invoke_ok(
+            "list_assistant_examples",
+            json!({ "assistantId": copied["assistant"]["id"] }),
+        );
+        assert_eq!(examples.as_array().unwrap().len(), 1);
+        assert_eq!(examples[0]["user_content"], "你好");
+    }
+
+    #[test]
+    fn test_attachment_commands() {
+        let app = TestApp::new();
+        let result = app.invoke_ok(
+            "add_attachment",
+            json!({
+                "fileContent": "hello",
+                "fileName": "notes.txt",
+                "attachmentType": 2,
+            }),
+        );
+        assert!(result["attachment_id"].as_i64().unwrap() > 0);
+
+        app.invoke_ok(
+            "save_feature_config",
+            json!({
+                "featureCode": "attachment_policy",
+                "config": { "blocked_extensions": "exe" },
+            }),
+        );
+        let rejected = app
+            .invoke(
+                "add_attachment",
+                json!({
+                    "fileContent": "MZ",
+                    "fileName": "setup.exe",
+                    "attachmentType": 2,
+                }),
+            )
+            .unwrap_err();
+        assert_eq!(
+            rejected,
+            json!({ "AttachmentRejected": { "reason": "blocked_extension", "extension": "exe" } })
+        );
+        assert!(app.invoke("add_attachment", json!({})).is_err());
+    }
+
+    #[test]
+    fn test_assistant_preset_commands() {
+        let app = TestApp::new();
+        let save = |params: Value| {
+            app.invoke(
+                "save_assistant_preset",
+                json!({ "assistantId": 1, "name": "custom", "params": params }),
+            )
+        };
+        assert!(save(json!({ "temperature": "3" })).is_err());
+        assert!(save(json!({ "seed": "42" })).is_err());
+        save(json!({ "temperature": "0.5", "top_p": "0.9" })).unwrap();
+
+        let presets = app.invoke_ok("list_assistant_presets", json!({ "assistantId": 1 }));
+        let custom = presets
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|preset| preset["name"] == "custom")
+            .expect("saved preset not returned");
+        assert_eq!(custom["params"]["temperature"], "0.5");
+        app.invoke_ok(
+            "set_active_preset",
+            json!({ "assistantId": 1, "preset": "custom" }),
+        );
+        assert!(app
+            .invoke(
+                "set_active_preset",
+                json!({ "assistantId": 1, "preset": "not-exist" }),
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_named_window_sessions() {
+        let app = TestApp::new();
+        app.invoke_ok("set_window_conversation", json!({ "conversationId": 5 }));
+        assert_eq!(
+            app.invoke_ok("get_window_conversation", json!({})),
+            json!(5)
+        );
+
+        let session = app.invoke_ok("save_named_window_session", json!({ "name": "写作" }));
+        assert_eq!(session["windows"][0]["label"], "chat_ui");
+        assert_eq!(session["windows"][0]["conversation_id"], json!(5));
+        assert!(app
+            .invoke("save_named_window_session", json!({ "name": " " }))
+            .is_err());
+
+        // 切换到命名会话时窗口回到保存时显示的对话
+        app.invoke_ok("set_window_conversation", json!({ "conversationId": 7 }));
+        app.invoke_ok("restore_named_window_session", json!({ "name": "写作" }));
+        assert_eq!(
+            app.invoke_ok("get_window_conversation", json!({})),
+            json!(5)
+        );
+
+        let sessions = app.invoke_ok("list_named_window_sessions", json!({}));
+        assert_eq!(sessions.as_array().unwrap().len(), 1);
+        app.invoke_ok("delete_named_window_session", json!({ "name": "写作" }));
+        assert!(app
+            .invoke("restore_named_window_session", json!({ "name": "写作" }))
+            .is_err());
+    }
+
+    #[test]
+    fn test_ask_ai_with_mock_provider() {
+        let app = TestApp::new();
+        app.add_mock_model("你好，我是模拟模型");
+
+        let response = app.invoke_ok(
+            "ask_ai",
+            json!({
+                "request": {
+                    "conversation_id": "",
+                    "assistant_id": 1,
+                    "prompt": "你好",
+                    "model": null,
+                    "temperature": null,
+                    "top_p": null,
+                    "max_tokens": null,
+                    "stream": null,
+                    "attachment_list": null,
+                    "fast_mode": null,
+                    "use_response_cache": null,
+                    "allow_online": null,
+                },
+            }),
+        );
+        let conversation_id = response["conversation_id"].as_i64().unwrap();
+        let message_id = response["add_message_id"].as_i64().unwrap();
+        assert!(response["request_prompt_result_with_context"]
+            .as_str()
+            .unwrap()
+            .contains("你好"));
+
+        let reply = wait_for("mock reply", || {
+            let result = app.invoke_ok(
+                "get_conversation_with_messages",
+                json!({ "conversationId": conversation_id }),
+            );
+            result[1]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|message| message["id"] == message_id && message["content"] != "")
+                .cloned()
+        });
+        assert_eq!(reply["content"], "你好，我是模拟模型");
+        // 新对话用 conversation_summary 配置的模型生成标题
+        wait_for("conversation title", || {
+            let recent = app.invoke_ok("recent_conversations", json!({ "limit": 1 }));
+            (recent[0]["name"] == "你好，我是模拟模型").then_some(())
+        });
+    }
+
+    #[test]
+    fn test_ask_ai_with_draft_refine() {
+        let app = TestApp::new();
+        let provider_id = app.add_mock_model("改进后的回答");
+        app.invoke_ok(
+            "add_llm_model",
+            json!({ "llmProviderId": provider_id, "code": "mock-draft" }),
+        );
+        app.invoke_ok(
+            "save_feature_config",
+            json!({
+                "featureCode": "draft_refine",
+                "config": {
+                    "enabled": "true",
+                    "provider_id": provider_id.to_string(),
+                    "model_code": "mock-draft",
+                    "mode": "critique",
+                },
+            }),
+        );
+
+        let response = app.invoke_ok(
+            "ask_ai",
+            json!({
+                "request": {
+                    "conversation_id": "",
+                    "assistant_id": 1,
+                    "prompt": "你好",
+                },
+            }),
+        );
+        let conversation_id = response["conversation_id"].as_i64().unwrap();
+        let message_id = response["add_message_id"].as_i64().unwrap();
+        let draft_message_id = response["draft_message_id"].as_i64().unwrap();
+        let messages = wait_for("refined reply", || {
+            let result = app.invoke_ok(
+                "get_conversation_with_messages",
+                json!({ "conversationId": conversation_id }),
+            );
+            let messages = result[1].as_array().unwrap().clone();
+            messages
+                .iter()
+                .any(|message| message["id"] == message_id && message["content"] != "")
+                .then_some(messages)
+        });
+        // 草稿不出现在消息列表中，最终的回答记录了参考的草稿
+        assert!(messages
+            .iter()
+            .all(|message| message["id"] != draft_message_id));
+        let reply = messages
+            .iter()
+            .find(|message| message["id"] == message_id)
+            .unwrap();
+        assert_eq!(reply["content"], "改进后的回答");
+        assert_eq!(
+            reply["generation_metadata"]["draft_message_id"],
+            json!(draft_message_id)
+        );
+    }
+
+    // 在消息列表和重新生成的分支中查找消息
+    fn find_message(messages: &Value, message_id: i64) -> Option<Value> {
+        messages.as_array()?.iter().find_map(|message| {
+            if message["id"] == message_id {
+                Some(message.clone())
+            } else {
+                find_message(&message["regenerate"], message_id)
+            }
+        })
+    }
+
+    fn wait_for_reply(app: &TestApp, conversation_id: i64, message_id: i64) -> Value {
+        wait_for("reply", || {
+            let result = app.invoke_ok(
+                "get_conversation_with_messages",
+                json!({ "conversationId": conversation_id }),
+            );
+            find_message(&result[1], message_id).filter(|message| message["content"] != "")
+        })
+    }
+
+    fn ask(app: &TestApp, prompt: &str) -> (i64, i64) {
+        let response = app.invoke_ok(
+            "ask_ai",
+            json!({
+                "request": {
+                    "conversation_id": "",
+                    "assistant_id": 1,
+                    "prompt": prompt,
+                },
+            }),
+        );
+        (
+            response["conversation_id"].as_i64().unwrap(),
+            response["add_message_id"].as_i64().unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_regenerate_with_model() {
+        let app = TestApp::new();
+        let provider_id = app.add_mock_model("模拟回答");
+        app.invoke_ok(
+            "add_llm_model",
+            json!({ "llmProviderId": provider_id, "code": "mock-b" }),
+        );
+        let (conversation_id, message_id) = ask(&app, "你好");
+        wait_for_reply(&app, conversation_id, message_id);
+
+        let response = app.invoke_ok(
+            "regenerate_with_model",
+            json!({
+                "messageId": message_id,
+                "modelCode": "mock-b",
+                "llmProviderId": provider_id,
+            }),
+        );
+        let regenerated_id = response["add_message_id"].as_i64().unwrap();
+        assert_ne!(regenerated_id, message_id);
+        let regenerated = wait_for_reply(&app, conversation_id, regenerated_id);
+        // 作为原来回答的分支保存，记录实际使用的模型
+        assert_eq!(regenerated["parent_id"], json!(message_id));
+        assert_eq!(regenerated["generation_metadata"]["model_code"], "mock-b");
+        assert!(app
+            .invoke(
+                "regenerate_with_model",
+                json!({ "messageId": message_id, "modelCode": "not-exist" }),
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_stream_interrupted() {
+        let app = TestApp::new();
+        let provider_id = app.add_mock_model("已经生成的内容");
+        // 第一个事件之后连接出错，模拟提供商不支持续写，保留已生成的内容并标记为中断
+        app.invoke_ok(
+            "update_llm_provider_config",
+            json!({ "llmProviderId": provider_id, "name": "error_after", "value": "1" }),
+        );
+        let (conversation_id, message_id) = ask(&app, "你好");
+        let reply = wait_for("interrupted reply", || {
+            let result = app.invoke_ok(
+                "get_conversation_with_messages",
+                json!({ "conversationId": conversation_id }),
+            );
+            find_message(&result[1], message_id)
+                .filter(|message| !message["generation_metadata"]["finish_reason"].is_null())
+        });
+        assert_eq!(reply["content"], "已经生成的内容");
+        assert_eq!(reply["generation_metadata"]["finish_reason"], "interrupted");
+    }
+
+    #[test]
+    fn test_retention_cleanup() {
+        let app = TestApp::new();
+        app.add_mock_model("模拟回答");
+        let (conversation_id, message_id) = ask(&app, "你好");
+        wait_for_reply(&app, conversation_id, message_id);
+
+        // 助手切换到无痕之后，之前的普通对话不会被清理
+        let mut assistant = app.invoke_ok("get_assistant", json!({ "assistantId": 1 }));
+        assistant["model_configs"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!({
+                "id": 0,
+                "assistant_id": 1,
+                "assistant_model_id": -1,
+                "name": "incognito",
+                "value": "true",
+                "value_type": "boolean",
+            }));
+        app.invoke_ok("save_assistant", json!({ "assistantDetail": assistant }));
+        let (incognito_id, incognito_message_id) = ask(&app, "无痕提问");
+        wait_for_reply(&app, incognito_id, incognito_message_id);
+
+        // 本次运行中的无痕对话还在使用，退出时才删除
+        let deleted = crate::retention::cleanup_expired_conversations(app.app_handle()).unwrap();
+        assert!(deleted.is_empty());
+        for id in [conversation_id, incognito_id] {
+            assert!(app
+                .invoke(
+                    "get_conversation_with_messages",
+                    json!({ "conversationId": id })
+                )
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn test_suggest_split_skips_incognito() {
+        let app = TestApp::new();
+        app.add_mock_model("模拟回答");
+        app.invoke_ok(
+            "save_feature_config",
+            json!({ "featureCode": "topic_split", "config": { "mode": "suggest" } }),
+        );
+        let (conversation_id, message_id) = ask(&app, "你好");
+        wait_for_reply(&app, conversation_id, message_id);
+        let suggestion = app.invoke_ok(
+            "suggest_split",
+            json!({ "conversationId": conversation_id }),
+        );
+        assert!(suggestion["message_id"].is_i64());
+
+        // 无痕对话的内容只用内存中的密钥加密，不读取内容做检查
+        let mut assistant = app.invoke_ok("get_assistant", json!({ "assistantId": 1 }));
+        assistant["model_configs"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!({
+                "id": 0,
+                "assistant_id": 1,
+                "assistant_model_id": -1,
+                "name": "incognito",
+                "value": "true",
+                "value_type": "boolean",
+            }));
+        app.invoke_ok("save_assistant", json!({ "assistantDetail": assistant }));
+        let (incognito_id, incognito_message_id) = ask(&app, "无痕提问");
+        wait_for_reply(&app, incognito_id, incognito_message_id);
+        let suggestion = app.invoke_ok("suggest_split", json!({ "conversationId": incognito_id }));
+        assert_eq!(suggestion["message_id"], Value::Null);
+    }
+
+    #[test]
+    fn test_mention_incognito_assistant_refused() {
+        let app = TestApp::new();
+        app.add_mock_model("模拟回答");
+        let mut assistant = app.invoke_ok(
+            "add_assistant",
+            json!({ "name": "无痕", "description": "", "assistantType": 0 }),
+        );
+        let assistant_id = assistant["assistant"]["id"].as_i64().unwrap();
+        assistant["model_configs"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!({
+                "id": 0,
+                "assistant_id": assistant_id,
+                "assistant_model_id": -1,
+                "name": "incognito",
+                "value": "true",
+                "value_type": "boolean",
+            }));
+        app.invoke_ok("save_assistant", json!({ "assistantDetail": assistant }));
+
+        // 无痕助手的回答不能写入普通对话
+        let result = app.invoke(
+            "ask_ai",
+            json!({
+                "request": {
+                    "conversation_id": "",
+                    "assistant_id": 1,
+                    "prompt": "@无痕 你好",
+                },
+            }),
+        );
+        assert!(result.is_err());
+        // 名称后面紧跟文字时不是 @ 这个助手，由默认助手回答
+        let (conversation_id, message_id) = ask(&app, "@无痕迹 你好");
+        wait_for_reply(&app, conversation_id, message_id);
+    }
+
+    #[test]
+    fn test_conversation_busy_guards() {
+        let app = TestApp::new();
+        app.add_mock_model("模拟回答");
+        let (conversation_id, message_id) = ask(&app, "你好");
+        wait_for_reply(&app, conversation_id, message_id);
+        let (other_id, other_message_id) = ask(&app, "另一个对话");
+        wait_for_reply(&app, other_id, other_message_id);
+
+        // 和生成任务一样持有对话的生成锁，对话处于正在生成的状态
+        let token_manager = app.app_handle().state::<MessageTokenManager>();
+        let lock = tauri::async_runtime::block_on(token_manager.conversation_lock(conversation_id));
+        let busy = json!({ "ConversationBusy": conversation_id });
+        assert_eq!(
+            app.invoke(
+                "delete_conversation",
+                json!({ "conversationId": conversation_id })
+            ),
+            Err(busy.clone())
+        );
+        assert_eq!(
+            app.invoke(
+                "update_message_content",
+                json!({ "messageId": message_id, "content": "修改" })
+            ),
+            Err(busy.clone())
+        );
+        assert_eq!(
+            app.invoke(
+                "merge_conversations",
+                json!({
+                    "conversationIds": [other_id, conversation_id],
+                    "strategy": "concatenate",
+                })
+            ),
+            Err(busy.clone())
+        );
+        // 重新生成会切换到新的分支
+        assert_eq!(
+            app.invoke("regenerate_ai", json!({ "messageId": message_id })),
+            Err(busy)
+        );
+        // 其他对话不受影响
+        app.invoke_ok(
+            "update_message_content",
+            json!({ "messageId": other_message_id, "content": "修改" }),
+        );
+
+        token_manager.release_conversation_lock(lock);
+        app.invoke_ok(
+            "merge_conversations",
+            json!({
+                "conversationIds": [other_id, conversation_id],
+                "strategy": "concatenate",
+            }),
+        );
+        app.invoke_ok(
+            "delete_conversation",
+            json!({ "conversationId": conversation_id }),
+        );
+    }
+
+    #[test]
+    fn test_model_fallback() {
+        let app = TestApp::new();
+        let provider_id = app.add_mock_model("备用模型的回答");
+        app.invoke_ok(
+            "add_llm_model",
+            json!({ "llmProviderId": provider_id, "code": "mock-b" }),
+        );
+        for (name, value) in [
+            ("unavailable_models", "mock"),
+            ("fallback_models", "mock-b"),
+        ] {
+            app.invoke_ok(
+                "update_llm_provider_config",
+                json!({ "llmProviderId": provider_id, "name": name, "value": value }),
+            );
+        }
+        let (conversation_id, message_id) = ask(&app, "你好");
+        let reply = wait_for_reply(&app, conversation_id, message_id);
+        assert_eq!(reply["content"], "备用模型的回答");
+        assert_eq!(reply["generation_metadata"]["model_code"], "mock-b");
+        assert_eq!(reply["generation_metadata"]["fallback_from"], "mock");
+    }
+
+    #[test]
+    fn test_ask_ai_fast_mode() {
+        let app = TestApp::new();
+        let provider_id = app.add_mock_model("快速回答");
+        app.invoke_ok(
+            "add_llm_model",
+            json!({ "llmProviderId": provider_id, "code": "mock-fast" }),
+        );
+        app.invoke_ok(
+            "save_feature_config",
+            json!({
+                "featureCode": "fast_mode",
+                "config": {
+                    "provider_id": provider_id.to_string(),
+                    "model_code": "mock-fast",
+                    "prewarm": "false",
+                },
+            }),
+        );
+        let attachment = app.invoke_ok(
+            "add_attachment",
+            json!({
+                "fileContent": "附件中的内容",
+                "fileName": "notes.txt",
+                "attachmentType": 2,
+            }),
+        );
+
+        let response = app.invoke_ok(
+            "ask_ai",
+            json!({
+                "request": {
+                    "conversation_id": "",
+                    "assistant_id": 1,
+                    "prompt": "翻译这段话",
+                    "attachment_list": [attachment["attachment_id"]],
+                    "fast_mode": true,
+                },
+            }),
+        );
+        // 快速模式不处理附件，换用 fast_mode 配置的模型
+        assert!(!response["request_prompt_result_with_context"]
+            .as_str()
+            .unwrap()
+            .contains("附件中的内容"));
+        let conversation_id = response["conversation_id"].as_i64().unwrap();
+        let message_id = response["add_message_id"].as_i64().unwrap();
+        let reply = wait_for_reply(&app, conversation_id, message_id);
+        assert_eq!(reply["content"], "快速回答");
+        assert_eq!(reply["generation_metadata"]["model_code"], "mock-fast");
+        assert_eq!(reply["generation_metadata"]["fast_mode"], json!(true));
+    }
+
+    #[test]
+    fn test_quick_action_commands() {
+        let app = TestApp::new();
+        app.add_mock_model("模拟回答");
+        let quick_action = json!({
+            "id": 0,
+            "name": "翻译",
+            "assistant_id": 1,
+            "clipboard_mode": "off",
+            "clipboard_only": true,
+        });
+        let quick_action_id =
+            app.invoke_ok("save_quick_action", json!({ "quickAction": quick_action }));
+        assert!(app
+            .invoke(
+                "save_quick_action",
+                json!({ "quickAction": { "id": 0, "name": "翻译", "assistant_id": 99 } }),
+            )
+            .is_err());
+
+        // 不写入剪贴板的快捷操作忽略 clipboard_only，回答照常生成
+        let response = app.invoke_ok(
+            "ask_ai",
+            json!({
+                "request": {
+                    "conversation_id": "",
+                    "assistant_id": 1,
+                    "prompt": "hello",
+                    "quick_action_id": quick_action_id,
+                },
+            }),
+        );
+        let reply = wait_for_reply(
+            &app,
+            response["conversation_id"].as_i64().unwrap(),
+            response["add_message_id"].as_i64().unwrap(),
+        );
+        assert_eq!(reply["content"], "模拟回答");
+
+        // 快捷操作随配置导出，导入时映射到同名的助手
+        let path = std::env::temp_dir().join(format!(
+            "aipp_harness_{}_{}_config.json",
+            std::process::id(),
+            app.memory_id()
+        ));
+        let path = path.to_string_lossy().to_string();
+        app.invoke_ok("export_app_config", json!({ "path": path }));
+        app.invoke_ok("delete_quick_action", json!({ "id": quick_action_id }));
+        assert_eq!(app.invoke_ok("list_quick_actions", json!({})), json!([]));
+        let result = app.invoke_ok("import_app_config", json!({ "path": path }));
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(result["quick_actions"], json!(1));
+        let quick_actions = app.invoke_ok("list_quick_actions", json!({}));
+        assert_eq!(quick_actions[0]["name"], "翻译");
+        assert_eq!(quick_actions[0]["assistant_id"], json!(1));
+        assert_eq!(quick_actions[0]["clipboard_only"], json!(true));
+    }
+
+    #[test]
+    fn test_response_style_counts_in_context_window() {
+        let app = TestApp::new();
+        app.add_mock_model("模拟回答");
+        let (conversation_id, message_id) = ask(&app, "你好");
+        wait_for_reply(&app, conversation_id, message_id);
+        let preview = || {
+            app.invoke_ok(
+                "preview_request",
+                json!({ "conversationId": conversation_id, "prompt": "继续" }),
+            )
+        };
+        let length = preview()["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|message| message["content"].as_str().unwrap().chars().count())
+            .sum::<usize>();
+        app.invoke_ok(
+            "save_feature_config",
+            json!({
+                "featureCode": "context",
+                "config": { "max_length": length.to_string() },
+            }),
+        );
+        assert_eq!(preview()["dropped_messages"], json!(0));
+
+        // 回答风格的说明计入上下文长度，超出时丢弃最早的历史消息
+        let tone = "正式".repeat(50);
+        app.invoke_ok(
+            "set_conversation_style",
+            json!({
+                "conversationId": conversation_id,
+                "style": { "format": null, "language": null, "tone": tone, "max_length": null },
+            }),
+        );
+        let preview = preview();
+        assert!(preview["system_prompt"].as_str().unwrap().contains(&tone));
+        assert!(preview["dropped_messages"].as_u64().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_preview_request_modes() {
+        let app = TestApp::new();
+        let provider_id = app.add_mock_model("模拟回答");
+        app.invoke_ok(
+            "add_llm_model",
+            json!({ "llmProviderId": provider_id, "code": "mock-fast" }),
+        );
+        app.invoke_ok(
+            "save_feature_config",
+            json!({
+                "featureCode": "fast_mode",
+                "config": {
+                    "provider_id": provider_id.to_string(),
+                    "model_code": "mock-fast",
+                    "prewarm": "false",
+                },
+            }),
+        );
+        let attachment = app.invoke_ok(
+            "add_attachment",
+            json!({
+                "fileContent": "常驻的参考资料",
+                "fileName": "guide.md",
+                "attachmentType": 2,
+            }),
+        );
+        app.invoke_ok(
+            "add_assistant_context_item",
+            json!({
+                "assistantId": 1,
+                "attachmentId": attachment["attachment_id"],
+                "useRetrieval": false,
+            }),
+        );
+        let (conversation_id, message_id) = ask(&app, "你好");
+        wait_for_reply(&app, conversation_id, message_id);
+
+        // 预览和提问一样拼接助手的常驻上下文，快速模式换用 fast_mode 配置的模型
+        let preview = app.invoke_ok(
+            "preview_request",
+            json!({ "conversationId": conversation_id, "prompt": "继续", "fastMode": true }),
+        );
+        assert_eq!(preview["model_code"], "mock-fast");
+        assert!(preview["system_prompt"]
+            .as_str()
+            .unwrap()
+            .contains("常驻的参考资料"));
+
+        // 离线模式下预览云端的模型和提问一样被拦截，确认后可以继续
+        app.invoke_ok(
+            "add_llm_provider",
+            json!({ "name": "Cloud", "apiType": "openai" }),
+        );
+        let cloud_id = app
+            .invoke_ok("get_llm_providers", json!({}))
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|provider| provider["name"] == "Cloud")
+            .and_then(|provider| provider["id"].as_i64())
+            .unwrap();
+        app.invoke_ok(
+            "update_llm_provider",
+            json!({
+                "id": cloud_id,
+                "name": "Cloud",
+                "apiType": "openai",
+                "description": "",
+                "isEnabled": true,
+            }),
+        );
+        app.invoke_ok(
+            "add_llm_model",
+            json!({ "llmProviderId": cloud_id, "code": "cloud-model" }),
+        );
+        app.invoke_ok("set_offline_mode", json!({ "enabled": true }));
+        let args = json!({ "conversationId": conversation_id, "modelCode": "cloud-model" });
+        let blocked = app.invoke("preview_request", args.clone()).unwrap_err();
+        assert_eq!(blocked, json!({ "OfflineBlocked": "Cloud" }));
+        let mut args = args;
+        args["allowOnline"] = json!(true);
+        assert_eq!(
+            app.invoke_ok("preview_request", args)["model_code"],
+            "cloud-model"
+        );
+    }
+
+    #[test]
+    fn test_ask_ai_dedup() {
+        let app = TestApp::new();
+        app.add_mock_model("模拟回答");
+        let (conversation_id, message_id) = ask(&app, "你好");
+        // 连续按发送时相同的提问返回正在进行的生成，只是空白不同也算相同
+        let (duplicate_conversation_id, duplicate_message_id) = ask(&app, "  你好 ");
+        assert_eq!(duplicate_conversation_id, conversation_id);
+        assert_eq!(duplicate_message_id, message_id);
+        let (other_conversation_id, other_message_id) = ask(&app, "你是谁");
+        assert_ne!(other_conversation_id, conversation_id);
+        assert_ne!(other_message_id, message_id);
+        wait_for_reply(&app, conversation_id, message_id);
+    }
+
+    #[test]
+    fn test_ask_ai_with_tool_call() {
+        let app = TestApp::new();
+        let provider_id = app.add_mock_model("1+1 等于 2");
+        // 第一次请求时模型调用 calculate，带着工具结果的第二次请求回放最终的回答
+        let tool_call_fixture = app.write_events_fixture(
+            "tool_call",
+            &[
+                json!({ "choices": [{ "delta": { "tool_calls": [{
+                    "index": 0,
+                    "id": "call_1",
+                    "function": { "name": "calculate", "arguments": "{\"expression\":" },
+                }] } }] }),
+                json!({ "choices": [{ "delta": { "tool_calls": [{
+                    "index": 0,
+                    "function": { "arguments": "\"1+1\"}" },
+                }] } }] }),
+                json!({ "choices": [{ "delta": {}, "finish_reason": "tool_calls" }] }),
+            ],
+        );
+        app.invoke_ok(
+            "update_llm_provider_config",
+            json!({
+                "llmProviderId": provider_id,
+                "name": "tool_call_fixture_path",
+                "value": tool_call_fixture,
+            }),
+        );
+        let mut assistant = app.invoke_ok("get_assistant", json!({ "assistantId": 1 }));
+        assistant["model_configs"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!({
+                "id": 0,
+                "assistant_id": 1,
+                "assistant_model_id": -1,
+                "name": "tools",
+                "value": "calculate",
+                "value_type": "string",
+            }));
+        app.invoke_ok("save_assistant", json!({ "assistantDetail": assistant }));
+
+        let (conversation_id, message_id) = ask(&app, "1+1 等于几");
+        let reply = wait_for_reply(&app, conversation_id, message_id);
+        assert_eq!(reply["content"], "1+1 等于 2");
+        let invocations = app.invoke_ok(
+            "list_tool_invocations",
+            json!({ "conversationId": conversation_id }),
+        );
+        assert_eq!(invocations[0]["tool_name"], "calculate");
+        assert_eq!(invocations[0]["message_id"], json!(message_id));
+        assert_eq!(invocations[0]["result"], "1+1 = 2");
+    }
+
+    #[test]
+    fn test_draft_commands() {
+        let app = TestApp::new();
+        app.add_mock_model("模拟回答");
+        app.invoke_ok(
+            "save_draft",
+            json!({ "conversationId": null, "assistantId": 1, "content": "还没写完的问题" }),
+        );
+        let draft = app.invoke_ok(
+            "get_draft",
+            json!({ "conversationId": null, "assistantId": 1 }),
+        );
+        assert_eq!(draft, "还没写完的问题");
+
+        // 发送之后新对话的草稿被删除，已有对话的草稿单独保存
+        let (conversation_id, message_id) = ask(&app, "还没写完的问题");
+        wait_for_reply(&app, conversation_id, message_id);
+        let draft = app.invoke_ok(
+            "get_draft",
+            json!({ "conversationId": null, "assistantId": 1 }),
+        );
+        assert!(draft.is_null());
+        app.invoke_ok(
+            "save_draft",
+            json!({ "conversationId": conversation_id, "assistantId": 1, "content": "追问" }),
+        );
+        let draft = app.invoke_ok(
+            "get_draft",
+            json!({ "conversationId": conversation_id, "assistantId": 1 }),
+        );
+        assert_eq!(draft, "追问");
+    }
+
+    #[test]
+    fn test_viewer_window_read_only() {
+        let app = TestApp::new();
+        app.add_mock_model("你好");
+        let (conversation_id, message_id) = ask(&app, "hello");
+        wait_for_reply(&app, conversation_id, message_id);
+
+        let args = json!({ "conversationId": conversation_id });
+        let full = app.invoke_ok("get_conversation_with_messages", args.clone());
+        assert!(full[1]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|message| message["message_type"] == "system"));
+        // 查看窗口中没有提示词、模型和生成参数
+        let viewer = app
+            .invoke_in("viewer", "get_viewer_conversation", args.clone())
+            .unwrap();
+        let messages = viewer[1].as_array().unwrap();
+        assert_eq!(messages.len(), 2);
+        for message in messages {
+            assert_ne!(message["message_type"], "system");
+            assert!(message["llm_model_id"].is_null());
+            assert!(message["generation_metadata"].is_null());
+        }
+        assert_eq!(
+            app.invoke_in("viewer", "get_window_conversation", json!({})),
+            Ok(Value::Null)
+        );
+
+        // 查看窗口不能发送消息、修改数据或者读取配置
+        for (cmd, args) in [
+            (
+                "ask_ai",
+                json!({ "request": { "conversation_id": conversation_id.to_string(), "assistant_id": 1, "prompt": "hi" } }),
+            ),
+            ("delete_conversation", args.clone()),
+            ("get_conversation_with_messages", args.clone()),
+            ("get_llm_providers", json!({})),
+        ] {
+            let error = app.invoke_in("viewer", cmd, args).unwrap_err();
+            assert!(error.as_str().unwrap().contains(cmd), "{}", error);
+        }
+        assert_eq!(
+            app.invoke_ok("get_conversation_with_messages", args)[1],
+            full[1]
+        );
+    }
+
+    #[test]
+    fn test_privacy_mode_commands() {
+        use tauri::Listener;
+
+        let app = TestApp::new();
+        assert_eq!(app.invoke_ok("get_privacy_mode", json!({})), false);
+
+        let changed = std::sync::Arc::new(Mutex::new(vec![]));
+        let events = changed.clone();
+        app.app_handle()
+            .listen("privacy_mode_changed", move |event| {
+                events.lock().unwrap().push(event.payload().to_string());
+            });
+        app.invoke_ok("set_privacy_mode", json!({ "enabled": true }));
+        assert_eq!(app.invoke_ok("get_privacy_mode", json!({})), true);
+        app.invoke_ok("set_privacy_mode", json!({ "enabled": false }));
+        assert_eq!(app.invoke_ok("get_privacy_mode", json!({})), false);
+        assert_eq!(*changed.lock().unwrap(), vec!["true", "false"]);
+    }
+    #[test]
+    fn test_replay_generation() {
+        use tauri::Listener;
+
+        let app = TestApp::new();
+        app.add_mock_model("模拟回答");
+        let (conversation_id, message_id) = ask(&app, "你好");
+        wait_for_reply(&app, conversation_id, message_id);
+        // 没有录制数据时不能回放
+        assert!(app
+            .invoke("replay_generation", json!({ "messageId": message_id }))
+            .is_err());
+
+        // DeepSeek 格式的录制，思考过程在 reasoning_content 中
+        let path =
+            crate::api::llm_api::generation_recording_path(app.app_handle(), message_id).unwrap();
+        let chunks = [
+            json!({ "choices": [{ "delta": { "reasoning_content": "先想一想" } }] }),
+            json!({ "choices": [{ "delta": { "content": "回放的回答" }, "finish_reason": "stop" }] }),
+        ]
+        .iter()
+        .map(|event| {
+            let data = format!("data: {}\n\n", event);
+            format!("{}\n", json!({ "delay_ms": 0, "data": data }))
+        })
+        .collect::<String>();
+        std::fs::write(&path, chunks).unwrap();
+
+        let events = std::sync::Arc::new(Mutex::new(vec![]));
+        for name in [
+            "message_reasoning".to_string(),
+            format!("message_{}", message_id),
+        ] {
+            let events = events.clone();
+            app.app_handle().listen(name.clone(), move |event| {
+                events
+                    .lock()
+                    .unwrap()
+                    .push((name.clone(), event.payload().to_string()));
+            });
+        }
+        assert!(app
+            .invoke(
+                "replay_generation",
+                json!({ "messageId": message_id, "speed": 0 })
+            )
+            .is_err());
+        app.invoke_ok(
+            "replay_generation",
+            json!({ "messageId": message_id, "speed": 100.0 }),
+        );
+        {
+            let events = events.lock().unwrap();
+            // 思考过程单独发送，回答中不包含思考过程
+            assert!(
+                events
+                    .iter()
+                    .any(|(name, payload)| name == "message_reasoning"
+                        && payload.contains("先想一想"))
+            );
+            assert_eq!(events.last().unwrap().1, json!("回放的回答").to_string());
+        }
+
+        // 录制文件是明文，随对话一起删除
+        app.invoke_ok(
+            "delete_conversation",
+            json!({ "conversationId": conversation_id }),
+        );
+        assert!(!path.exists());
+    }
+}
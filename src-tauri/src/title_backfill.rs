@@ -0,0 +1,308 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+use tauri::{Emitter, Manager};
+use tokio_util::sync::CancellationToken;
+
+use crate::api::llm::{get_provider, is_local_provider, ChatMessage, ModelProvider, Role};
+use crate::api::llm_api::is_offline_mode;
+use crate::db::assistant_db::AssistantModelConfig;
+use crate::db::conversation_db::{ConversationDatabase, Repository, DEFAULT_CONVERSATION_NAME};
+use crate::db::llm_db::LLMDatabase;
+use crate::errors::AppError;
+use crate::AppHandle;
+use crate::FeatureConfigState;
+
+pub const DEFAULT_BATCH_SIZE: usize = 10;
+// 两次请求之间的间隔，补全大量历史对话时避免触发提供商的限流
+const REQUEST_INTERVAL: Duration = Duration::from_secs(2);
+// 每批之间额外等待的时间
+const BATCH_INTERVAL: Duration = Duration::from_secs(30);
+// 发给模型的提问和回答各自的最大字符数
+const MAX_CONTEXT_CHARS: usize = 1000;
+const MAX_TITLE_CHARS: usize = 50;
+const MAX_SUMMARY_CHARS: usize = 120;
+const INSTRUCTION: &str =
+    "请用两行回答：第一行是上述对话的标题，不需要包含标点符号；第二行用一句话总结对话的内容";
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// 补全标题任务的进度，每处理完一个对话通过 title_backfill_progress 事件发送
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct TitleBackfillProgress {
+    pub total: usize,
+    pub processed: usize,
+    pub updated: usize,
+    pub failed: usize,
+    // 全部处理完或者被取消后为 true
+    pub done: bool,
+}
+
+/// 在后台为还是默认名称的历史对话生成标题和一句话总结，返回需要处理的对话数量
+///
+/// 使用 conversation_summary 功能配置的模型，按 batch_size 分批处理，请求之间和每批之间都会等待一段时间；
+/// 处理完一个对话后发送 title_change 事件，同一时间只会有一个任务在运行
+pub async fn start_title_backfill(
+    app_handle: AppHandle,
+    batch_size: Option<usize>,
+) -> Result<usize, AppError> {
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        return Err(AppError::UnknownError(
+            "补全标题的任务正在进行中".to_string(),
+        ));
+    }
+    let prepared = prepare(&app_handle).await;
+    let (model, prompt, ids) = match prepared {
+        Ok(prepared) if !prepared.2.is_empty() => prepared,
+        result => {
+            RUNNING.store(false, Ordering::SeqCst);
+            return result.map(|_| 0);
+        }
+    };
+    CANCELLED.store(false, Ordering::SeqCst);
+    let total = ids.len();
+    let batch_size = batch_size
+        .filter(|size| *size > 0)
+        .unwrap_or(DEFAULT_BATCH_SIZE);
+    tauri::async_runtime::spawn(async move {
+        run_backfill(&app_handle, &model, &prompt, &ids, batch_size).await;
+        RUNNING.store(false, Ordering::SeqCst);
+    });
+    Ok(total)
+}
+
+/// 停止正在进行的补全任务，正在等待的请求完成后结束
+pub fn cancel_title_backfill() {
+    if RUNNING.load(Ordering::SeqCst) {
+        CANCELLED.store(true, Ordering::SeqCst);
+    }
+}
+
+// 总结使用的提供商和模型
+struct SummaryModel {
+    provider: Arc<dyn ModelProvider>,
+    model_code: String,
+    // 本地提供商在任务进行中切换到离线模式后可以继续使用
+    local: bool,
+}
+
+// 读取总结使用的模型和需要补全的对话
+async fn prepare(app_handle: &AppHandle) -> Result<(SummaryModel, String, Vec<i64>), AppError> {
+    let feature_config_state = app_handle.state::<FeatureConfigState>();
+    let config_feature_map = feature_config_state.config_feature_map.lock().await;
+    let config = config_feature_map
+        .get("conversation_summary")
+        .ok_or(AppError::NoConfigError("conversation_summary".to_string()))?;
+    let get_value = |key: &str| {
+        config
+            .get(key)
+            .map(|config| config.value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .ok_or(AppError::NoConfigError(key.to_string()))
+    };
+    let provider_id = get_value("provider_id")?.parse::<i64>()?;
+    let model_code = get_value("model_code")?;
+    let prompt = get_value("prompt").unwrap_or_default();
+    drop(config_feature_map);
+
+    let model_detail =
+        LLMDatabase::new(app_handle)?.get_llm_model_detail(&provider_id, &model_code)?;
+    let local = is_local_provider(&model_detail.provider, &model_detail.configs);
+    if is_offline_mode(app_handle) && !local {
+        return Err(AppError::OfflineBlocked(model_detail.provider.name));
+    }
+    let ids = ConversationDatabase::new(app_handle)?
+        .conversation_repo()?
+        .list_untitled_ids(DEFAULT_CONVERSATION_NAME)?;
+    let model = SummaryModel {
+        provider: get_provider(model_detail.provider, model_detail.configs)?,
+        model_code: model_detail.model.code,
+        local,
+    };
+    Ok((model, prompt, ids))
+}
+
+async fn run_backfill(
+    app_handle: &AppHandle,
+    model: &SummaryModel,
+    prompt: &str,
+    ids: &[i64],
+    batch_size: usize,
+) {
+    let mut progress = TitleBackfillProgress {
+        total: ids.len(),
+        ..Default::default()
+    };
+    for (index, id) in ids.iter().enumerate() {
+        if index > 0 {
+            let interval = if index % batch_size == 0 {
+                BATCH_INTERVAL
+            } else {
+                REQUEST_INTERVAL
+            };
+            tokio::time::sleep(interval).await;
+        }
+        if CANCELLED.load(Ordering::SeqCst) {
+            println!("title backfill cancelled after {} conversations", index);
+            break;
+        }
+        if !model.local && is_offline_mode(app_handle) {
+            println!(
+                "offline mode, stop title backfill after {} conversations",
+                index
+            );
+            break;
+        }
+        match backfill_conversation(app_handle, model, prompt, *id).await {
+            Ok(Some(name)) => {
+                progress.updated += 1;
+                let _ = app_handle.emit("title_change", (*id, name));
+            }
+            Ok(None) => {}
+            Err(e) => {
+                println!("backfill title of conversation {} error: {:?}", id, e);
+                progress.failed += 1;
+            }
+        }
+        progress.processed += 1;
+        let _ = app_handle.emit("title_backfill_progress", &progress);
+    }
+    progress.done = true;
+    let _ = app_handle.emit("title_backfill_progress", &progress);
+}
+
+// 生成并保存对话的标题和总结，返回新的标题；任务开始后对话被删除、改名或者设为私密时跳过，返回 None
+async fn backfill_conversation(
+    app_handle: &AppHandle,
+    model: &SummaryModel,
+    prompt: &str,
+    conversation_id: i64,
+) -> Result<Option<String>, AppError> {
+    let db = ConversationDatabase::new(app_handle)?;
+    let conversation_repo = db.conversation_repo()?;
+    let Some(conversation) = conversation_repo.read(conversation_id)? else {
+        return Ok(None);
+    };
+    let untitled =
+        conversation.name.trim().is_empty() || conversation.name == DEFAULT_CONVERSATION_NAME;
+    if !untitled || conversation.is_private {
+        return Ok(None);
+    }
+
+    let mut messages = db
+        .message_repo()?
+        .list_by_conversation_id(conversation_id)?
+        .into_iter()
+        .map(|(message, _)| message)
+        .collect::<Vec<_>>();
+    messages.sort_by_key(|message| (message.created_time, message.id));
+    let user_prompt = messages
+        .iter()
+        .find(|message| message.role() == Some(Role::User))
+        .map(|message| message.content.clone())
+        .unwrap_or_default();
+    let reply = messages
+        .iter()
+        .find(|message| message.role() == Some(Role::Assistant))
+        .map(|message| message.content.clone());
+    let context = conversation_context(&user_prompt, reply.as_deref());
+
+    let response = model
+        .provider
+        .chat(
+            -1,
+            vec![
+                ChatMessage::new(Role::System, prompt),
+                ChatMessage::new(Role::User, context),
+            ],
+            vec![AssistantModelConfig {
+                id: 0,
+                assistant_id: 0,
+                assistant_model_id: 0,
+                name: "model".to_string(),
+                value: Some(model.model_code.clone()),
+                value_type: "string".to_string(),
+            }],
+            CancellationToken::new(),
+        )
+        .await?;
+    let (name, summary) = parse_title_and_summary(&response.content)
+        .ok_or(AppError::ProviderError("模型没有返回标题".to_string()))?;
+    conversation_repo.update_name_and_summary(conversation_id, &name, summary.as_deref())?;
+    Ok(Some(name))
+}
+
+fn conversation_context(user_prompt: &str, reply: Option<&str>) -> String {
+    let truncate = |text: &str| text.chars().take(MAX_CONTEXT_CHARS).collect::<String>();
+    match reply {
+        Some(reply) => format!(
+            "# user\n {} \n\n#assistant\n {} \n\n{}",
+            truncate(user_prompt),
+            truncate(reply),
+            INSTRUCTION
+        ),
+        None => format!("# user\n {} \n\n{}", truncate(user_prompt), INSTRUCTION),
+    }
+}
+
+// 第一行非空内容为标题，第二行为总结，去掉模型可能加上的“标题：”等前缀、引号和 markdown 标记
+fn parse_title_and_summary(content: &str) -> Option<(String, Option<String>)> {
+    let mut lines = content
+        .lines()
+        .map(clean_line)
+        .filter(|line| !line.is_empty());
+    let title = lines.next()?.chars().take(MAX_TITLE_CHARS).collect();
+    let summary = lines
+        .next()
+        .map(|line| line.chars().take(MAX_SUMMARY_CHARS).collect());
+    Some((title, summary))
+}
+
+fn clean_line(line: &str) -> String {
+    let line = line.trim().trim_start_matches(['#', '*', '-', ' ']);
+    // 只去掉后面跟着冒号的前缀，“总结报告”这样的标题保持不变
+    let line = ["标题", "总结", "摘要", "Title", "Summary"]
+        .iter()
+        .find_map(|prefix| {
+            let rest = line.strip_prefix(prefix)?.trim_start();
+            rest.strip_prefix([':', '：'])
+        })
+        .unwrap_or(line);
+    line.trim_matches(|c: char| c.is_whitespace() || "*\"'“”「」《》".contains(c))
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_title_and_summary() {
+        assert_eq!(
+            parse_title_and_summary("标题：周末旅行计划\n\n总结：用户询问杭州两日游的行程安排。"),
+            Some((
+                "周末旅行计划".to_string(),
+                Some("用户询问杭州两日游的行程安排。".to_string())
+            ))
+        );
+        assert_eq!(
+            parse_title_and_summary("**“Rust 生命周期”**\n- 解释了借用检查的规则"),
+            Some((
+                "Rust 生命周期".to_string(),
+                Some("解释了借用检查的规则".to_string())
+            ))
+        );
+        assert_eq!(
+            parse_title_and_summary("只有标题"),
+            Some(("只有标题".to_string(), None))
+        );
+        assert_eq!(
+            parse_title_and_summary("总结报告撰写"),
+            Some(("总结报告撰写".to_string(), None))
+        );
+        assert_eq!(parse_title_and_summary(" \n\n"), None);
+    }
+}
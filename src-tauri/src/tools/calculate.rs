@@ -0,0 +1,600 @@
+use futures::future::BoxFuture;
+use serde_json::{json, Value};
+
+use super::{invalid_arguments, Tool, ToolDefinition};
+use crate::errors::AppError;
+use crate::AppHandle;
+
+// 表达式的最大长度和括号、函数调用的最大嵌套层数，避免递归过深
+const MAX_EXPRESSION_LENGTH: usize = 1000;
+const MAX_DEPTH: usize = 64;
+// 结果保留的有效数字位数，去掉 0.1 + 0.2 这样的浮点误差
+const SIGNIFICANT_DIGITS: i32 = 12;
+
+/// 在本地计算数学表达式并换算单位，模型不需要自己心算，结果是确定的，不访问网络
+pub struct CalculateTool;
+
+impl Tool for CalculateTool {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: "calculate".to_string(),
+            description: "计算数学表达式，支持 + - * / % ^、括号、sqrt abs ln log exp sin cos tan floor ceil round min max 等函数和 pi、e 常量；同时提供 from_unit 和 to_unit 时把结果从一个单位换算到另一个单位，支持长度、重量、时间、面积、体积、速度、数据大小和温度".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "expression": {"type": "string", "description": "数学表达式，例如 (3 + 4) * 2 ^ 3"},
+                    "from_unit": {"type": "string", "description": "表达式结果的单位，例如 km、lb、°F、GB"},
+                    "to_unit": {"type": "string", "description": "换算到的单位"}
+                },
+                "required": ["expression"]
+            }),
+        }
+    }
+
+    fn call(
+        &self,
+        _app_handle: AppHandle,
+        arguments: Value,
+    ) -> BoxFuture<'static, Result<String, AppError>> {
+        Box::pin(async move {
+            let expression = arguments["expression"]
+                .as_str()
+                .filter(|expression| !expression.trim().is_empty())
+                .ok_or_else(|| invalid_arguments("缺少 expression"))?;
+            let unit = |key: &str| {
+                arguments[key]
+                    .as_str()
+                    .map(|unit| unit.trim())
+                    .filter(|unit| !unit.is_empty())
+            };
+            let value = evaluate(expression).map_err(AppError::ToolError)?;
+            match (unit("from_unit"), unit("to_unit")) {
+                (Some(from), Some(to)) => {
+                    let converted = convert(value, from, to).map_err(AppError::ToolError)?;
+                    Ok(format!(
+                        "{} {} = {} {}",
+                        format_number(value),
+                        from,
+                        format_number(converted),
+                        to
+                    ))
+                }
+                (None, None) => Ok(format!("{} = {}", expression.trim(), format_number(value))),
+                _ => Err(invalid_arguments("from_unit 和 to_unit 需要同时提供")),
+            }
+        })
+    }
+}
+
+/// 计算表达式，错误信息直接返回给模型
+pub fn evaluate(expression: &str) -> Result<f64, String> {
+    if expression.len() > MAX_EXPRESSION_LENGTH {
+        return Err(format!("表达式超过 {} 个字符", MAX_EXPRESSION_LENGTH));
+    }
+    let mut parser = Parser {
+        tokens: tokenize(expression)?,
+        position: 0,
+        depth: 0,
+    };
+    let value = parser.expression()?;
+    if let Some(token) = parser.peek() {
+        return Err(format!("无法识别的内容: {:?}", token));
+    }
+    if !value.is_finite() {
+        return Err("结果不是有限的数字".to_string());
+    }
+    Ok(value)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(char),
+    LParen,
+    RParen,
+    Comma,
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = expression.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '0'..='9' | '.' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                // 科学计数法，例如 1.5e3、2E-4
+                if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                    let mut j = i + 1;
+                    if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+                        j += 1;
+                    }
+                    if j < chars.len() && chars[j].is_ascii_digit() {
+                        i = j;
+                        while i < chars.len() && chars[i].is_ascii_digit() {
+                            i += 1;
+                        }
+                    }
+                }
+                let text: String = chars[start..i].iter().collect();
+                // 千位分隔符以外的写法都按数字解析
+                let number = text
+                    .parse::<f64>()
+                    .map_err(|_| format!("无法识别的数字: {}", text))?;
+                tokens.push(Token::Number(number));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                tokens.push(Token::Ident(
+                    chars[start..i].iter().collect::<String>().to_lowercase(),
+                ));
+            }
+            '+' | '-' | '*' | '/' | '%' | '^' => {
+                // ** 和 ^ 一样表示乘方
+                if c == '*' && chars.get(i + 1) == Some(&'*') {
+                    tokens.push(Token::Op('^'));
+                    i += 2;
+                } else {
+                    tokens.push(Token::Op(c));
+                    i += 1;
+                }
+            }
+            '×' => {
+                tokens.push(Token::Op('*'));
+                i += 1;
+            }
+            '÷' => {
+                tokens.push(Token::Op('/'));
+                i += 1;
+            }
+            '(' => {
+                tokens.push(Token::LParen);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                i += 1;
+            }
+            ',' => {
+                tokens.push(Token::Comma);
+                i += 1;
+            }
+            _ => return Err(format!("无法识别的字符: {}", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+// 优先级从低到高：加减、乘除取余、正负号、乘方（右结合）、函数和括号
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(format!("需要 {:?}，实际是 {:?}", expected, token)),
+            None => Err(format!("表达式不完整，缺少 {:?}", expected)),
+        }
+    }
+
+    fn expression(&mut self) -> Result<f64, String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err("表达式嵌套层数过多".to_string());
+        }
+        let mut value = self.term()?;
+        while let Some(Token::Op(op @ ('+' | '-'))) = self.peek().cloned() {
+            self.position += 1;
+            let rhs = self.term()?;
+            value = if op == '+' { value + rhs } else { value - rhs };
+        }
+        self.depth -= 1;
+        Ok(value)
+    }
+
+    fn term(&mut self) -> Result<f64, String> {
+        let mut value = self.unary()?;
+        while let Some(Token::Op(op @ ('*' | '/' | '%'))) = self.peek().cloned() {
+            self.position += 1;
+            let rhs = self.unary()?;
+            if op != '*' && rhs == 0.0 {
+                return Err("除数不能为 0".to_string());
+            }
+            value = match op {
+                '*' => value * rhs,
+                '/' => value / rhs,
+                _ => value % rhs,
+            };
+        }
+        Ok(value)
+    }
+
+    // -2 ^ 2 按数学习惯等于 -4
+    fn unary(&mut self) -> Result<f64, String> {
+        match self.peek() {
+            Some(Token::Op('-')) => {
+                self.position += 1;
+                Ok(-self.unary()?)
+            }
+            Some(Token::Op('+')) => {
+                self.position += 1;
+                self.unary()
+            }
+            _ => self.power(),
+        }
+    }
+
+    fn power(&mut self) -> Result<f64, String> {
+        let base = self.primary()?;
+        if let Some(Token::Op('^')) = self.peek() {
+            self.position += 1;
+            let exponent = self.unary()?;
+            return Ok(base.powf(exponent));
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<f64, String> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(number),
+            Some(Token::LParen) => {
+                let value = self.expression()?;
+                self.expect(Token::RParen)?;
+                Ok(value)
+            }
+            Some(Token::Ident(name)) => {
+                if self.peek() == Some(&Token::LParen) {
+                    self.position += 1;
+                    let mut args = vec![self.expression()?];
+                    while self.peek() == Some(&Token::Comma) {
+                        self.position += 1;
+                        args.push(self.expression()?);
+                    }
+                    self.expect(Token::RParen)?;
+                    call_function(&name, &args)
+                } else {
+                    match name.as_str() {
+                        "pi" | "π" => Ok(std::f64::consts::PI),
+                        "e" => Ok(std::f64::consts::E),
+                        _ => Err(format!("未知的常量: {}", name)),
+                    }
+                }
+            }
+            Some(token) => Err(format!("无法识别的内容: {:?}", token)),
+            None => Err("表达式不完整".to_string()),
+        }
+    }
+}
+
+fn call_function(name: &str, args: &[f64]) -> Result<f64, String> {
+    let single = || match args {
+        [value] => Ok(*value),
+        _ => Err(format!("{} 需要 1 个参数", name)),
+    };
+    let value = match name {
+        "sqrt" => {
+            let value = single()?;
+            if value < 0.0 {
+                return Err("不能对负数开平方".to_string());
+            }
+            value.sqrt()
+        }
+        "abs" => single()?.abs(),
+        "ln" | "log" | "log10" | "log2" => {
+            let value = single()?;
+            if value <= 0.0 {
+                return Err(format!("{} 的参数需要大于 0", name));
+            }
+            match name {
+                "ln" => value.ln(),
+                "log2" => value.log2(),
+                _ => value.log10(),
+            }
+        }
+        "exp" => single()?.exp(),
+        "sin" => single()?.sin(),
+        "cos" => single()?.cos(),
+        "tan" => single()?.tan(),
+        "floor" => single()?.floor(),
+        "ceil" => single()?.ceil(),
+        "round" => match args {
+            [value] => value.round(),
+            // round(x, n) 保留 n 位小数
+            [value, digits] => {
+                let factor = 10f64.powi(*digits as i32);
+                (value * factor).round() / factor
+            }
+            _ => return Err("round 需要 1 或 2 个参数".to_string()),
+        },
+        "min" => args.iter().cloned().fold(f64::INFINITY, f64::min),
+        "max" => args.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+        _ => return Err(format!("未知的函数: {}", name)),
+    };
+    Ok(value)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Dimension {
+    Length,
+    Mass,
+    Time,
+    Area,
+    Volume,
+    Speed,
+    Data,
+    Temperature,
+}
+
+// 换算到基本单位：基本单位的值 = 值 * factor + offset，只有温度有 offset
+struct Unit {
+    names: &'static [&'static str],
+    dimension: Dimension,
+    factor: f64,
+    offset: f64,
+}
+
+const fn unit(names: &'static [&'static str], dimension: Dimension, factor: f64) -> Unit {
+    Unit {
+        names,
+        dimension,
+        factor,
+        offset: 0.0,
+    }
+}
+
+const UNITS: &[Unit] = &[
+    unit(&["m", "meter", "meters", "米"], Dimension::Length, 1.0),
+    unit(
+        &["km", "kilometer", "kilometers", "公里", "千米"],
+        Dimension::Length,
+        1000.0,
+    ),
+    unit(
+        &["cm", "centimeter", "centimeters", "厘米"],
+        Dimension::Length,
+        0.01,
+    ),
+    unit(
+        &["mm", "millimeter", "millimeters", "毫米"],
+        Dimension::Length,
+        0.001,
+    ),
+    unit(
+        &["mi", "mile", "miles", "英里"],
+        Dimension::Length,
+        1609.344,
+    ),
+    unit(&["yd", "yard", "yards", "码"], Dimension::Length, 0.9144),
+    unit(&["ft", "foot", "feet", "英尺"], Dimension::Length, 0.3048),
+    unit(&["in", "inch", "inches", "英寸"], Dimension::Length, 0.0254),
+    unit(&["nmi", "nautical mile", "海里"], Dimension::Length, 1852.0),
+    unit(
+        &["kg", "kilogram", "kilograms", "千克", "公斤"],
+        Dimension::Mass,
+        1.0,
+    ),
+    unit(&["g", "gram", "grams", "克"], Dimension::Mass, 0.001),
+    unit(
+        &["mg", "milligram", "milligrams", "毫克"],
+        Dimension::Mass,
+        1e-6,
+    ),
+    unit(&["t", "tonne", "tonnes", "吨"], Dimension::Mass, 1000.0),
+    unit(
+        &["lb", "lbs", "pound", "pounds", "磅"],
+        Dimension::Mass,
+        0.45359237,
+    ),
+    unit(
+        &["oz", "ounce", "ounces", "盎司"],
+        Dimension::Mass,
+        0.028349523125,
+    ),
+    unit(&["斤"], Dimension::Mass, 0.5),
+    unit(
+        &["s", "sec", "second", "seconds", "秒"],
+        Dimension::Time,
+        1.0,
+    ),
+    unit(
+        &["ms", "millisecond", "milliseconds", "毫秒"],
+        Dimension::Time,
+        0.001,
+    ),
+    unit(&["min", "minute", "minutes", "分钟"], Dimension::Time, 60.0),
+    unit(
+        &["h", "hr", "hour", "hours", "小时"],
+        Dimension::Time,
+        3600.0,
+    ),
+    unit(&["d", "day", "days", "天"], Dimension::Time, 86400.0),
+    unit(&["week", "weeks", "周"], Dimension::Time, 604800.0),
+    unit(&["m2", "m²", "平方米"], Dimension::Area, 1.0),
+    unit(&["km2", "km²", "平方公里"], Dimension::Area, 1e6),
+    unit(&["cm2", "cm²", "平方厘米"], Dimension::Area, 1e-4),
+    unit(&["ha", "hectare", "hectares", "公顷"], Dimension::Area, 1e4),
+    unit(&["acre", "acres", "英亩"], Dimension::Area, 4046.8564224),
+    unit(
+        &["ft2", "ft²", "sqft", "平方英尺"],
+        Dimension::Area,
+        0.09290304,
+    ),
+    unit(&["亩"], Dimension::Area, 10000.0 / 15.0),
+    unit(
+        &["l", "liter", "liters", "litre", "升"],
+        Dimension::Volume,
+        1.0,
+    ),
+    unit(
+        &["ml", "milliliter", "milliliters", "毫升"],
+        Dimension::Volume,
+        0.001,
+    ),
+    unit(&["m3", "m³", "立方米"], Dimension::Volume, 1000.0),
+    unit(
+        &["gal", "gallon", "gallons", "加仑"],
+        Dimension::Volume,
+        3.785411784,
+    ),
+    unit(
+        &["floz", "fl oz", "fluid ounce"],
+        Dimension::Volume,
+        0.0295735295625,
+    ),
+    unit(&["m/s", "mps"], Dimension::Speed, 1.0),
+    unit(&["km/h", "kmh", "kph"], Dimension::Speed, 1000.0 / 3600.0),
+    unit(&["mph", "mi/h"], Dimension::Speed, 0.44704),
+    unit(
+        &["kn", "knot", "knots", "节"],
+        Dimension::Speed,
+        1852.0 / 3600.0,
+    ),
+    unit(&["b", "byte", "bytes", "字节"], Dimension::Data, 1.0),
+    unit(&["kb"], Dimension::Data, 1e3),
+    unit(&["mb"], Dimension::Data, 1e6),
+    unit(&["gb"], Dimension::Data, 1e9),
+    unit(&["tb"], Dimension::Data, 1e12),
+    unit(&["kib"], Dimension::Data, 1024.0),
+    unit(&["mib"], Dimension::Data, 1048576.0),
+    unit(&["gib"], Dimension::Data, 1073741824.0),
+    unit(&["tib"], Dimension::Data, 1099511627776.0),
+    unit(&["k", "kelvin", "开尔文"], Dimension::Temperature, 1.0),
+    Unit {
+        names: &["c", "°c", "℃", "celsius", "摄氏度"],
+        dimension: Dimension::Temperature,
+        factor: 1.0,
+        offset: 273.15,
+    },
+    Unit {
+        names: &["f", "°f", "℉", "fahrenheit", "华氏度"],
+        dimension: Dimension::Temperature,
+        factor: 5.0 / 9.0,
+        offset: 273.15 - 32.0 * 5.0 / 9.0,
+    },
+];
+
+fn find_unit(name: &str) -> Result<&'static Unit, String> {
+    let name = name.trim().to_lowercase();
+    UNITS
+        .iter()
+        .find(|unit| unit.names.contains(&name.as_str()))
+        .ok_or_else(|| format!("不支持的单位: {}", name))
+}
+
+/// 把 value 从 from 单位换算到 to 单位，两个单位需要是同一种量
+pub fn convert(value: f64, from: &str, to: &str) -> Result<f64, String> {
+    let from_unit = find_unit(from)?;
+    let to_unit = find_unit(to)?;
+    if from_unit.dimension != to_unit.dimension {
+        return Err(format!("{} 和 {} 不是同一种量，不能换算", from, to));
+    }
+    let base = value * from_unit.factor + from_unit.offset;
+    Ok((base - to_unit.offset) / to_unit.factor)
+}
+
+/// 按有效数字格式化结果，整数不带小数点，很大或很小的数使用科学计数法
+pub fn format_number(value: f64) -> String {
+    if value == 0.0 {
+        return "0".to_string();
+    }
+    let magnitude = value.abs().log10().floor() as i32;
+    if !(-6..15).contains(&magnitude) {
+        return format!("{:e}", value);
+    }
+    let decimals = (SIGNIFICANT_DIGITS - 1 - magnitude).max(0) as usize;
+    let text = format!("{:.*}", decimals, value);
+    let text = if text.contains('.') {
+        text.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        &text
+    };
+    match text {
+        "-0" => "0".to_string(),
+        text => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(evaluate("2 + 3 * 4").unwrap(), 14.0);
+        assert_eq!(evaluate("(2 + 3) * 4").unwrap(), 20.0);
+        // 乘方右结合，负号优先级低于乘方
+        assert_eq!(evaluate("2 ^ 3 ^ 2").unwrap(), 512.0);
+        assert_eq!(evaluate("-2 ^ 2").unwrap(), -4.0);
+        assert_eq!(evaluate("2 ** -1").unwrap(), 0.5);
+        assert_eq!(evaluate("10 % 3").unwrap(), 1.0);
+        assert_eq!(format_number(evaluate("1.5e3 + 2E-1").unwrap()), "1500.2");
+        assert_eq!(evaluate("max(1, 7, 3) - min(4, 2)").unwrap(), 5.0);
+        assert_eq!(evaluate("round(2 * pi, 2)").unwrap(), 6.28);
+        assert_eq!(evaluate("sqrt(16) × 3 ÷ 2").unwrap(), 6.0);
+    }
+
+    #[test]
+    fn test_evaluate_errors() {
+        assert!(evaluate("1 / 0").unwrap_err().contains("除数"));
+        assert!(evaluate("5 % 0").is_err());
+        assert!(evaluate("sqrt(-1)").is_err());
+        assert!(evaluate("(1 + 2").unwrap_err().contains("不完整"));
+        assert!(evaluate("1 + 2)").is_err());
+        assert!(evaluate("foo(1)").unwrap_err().contains("未知的函数"));
+        assert!(evaluate("2 $ 3").is_err());
+        assert!(evaluate("").is_err());
+        assert!(evaluate("10 ^ 400").is_err());
+        assert!(evaluate(&"(".repeat(200)).unwrap_err().contains("嵌套"));
+    }
+
+    #[test]
+    fn test_convert() {
+        assert_eq!(
+            format_number(convert(5.0, "km", "mi").unwrap()),
+            "3.10685596119"
+        );
+        assert_eq!(format_number(convert(100.0, "°C", "F").unwrap()), "212");
+        assert_eq!(format_number(convert(-40.0, "f", "c").unwrap()), "-40");
+        assert_eq!(
+            format_number(convert(1.0, "GiB", "MB").unwrap()),
+            "1073.741824"
+        );
+        assert_eq!(format_number(convert(2.0, "斤", "kg").unwrap()), "1");
+        assert!(convert(1.0, "kg", "m")
+            .unwrap_err()
+            .contains("不是同一种量"));
+        assert!(convert(1.0, "parsec", "m").is_err());
+    }
+
+    #[test]
+    fn test_format_number() {
+        assert_eq!(format_number(0.1 + 0.2), "0.3");
+        assert_eq!(format_number(14.0), "14");
+        assert_eq!(format_number(-0.5), "-0.5");
+        assert_eq!(format_number(1e20), "1e20");
+        assert_eq!(format_number(1.0 / 3.0), "0.333333333333");
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use serde::Serialize;
+use serde_json::Value;
+use tauri_plugin_dialog::{DialogExt, MessageDialogButtons, MessageDialogKind};
+use tokio::sync::oneshot;
+
+use crate::db::system_db::FeatureConfig;
+use crate::errors::AppError;
+use crate::AppHandle;
+
+mod calculate;
+mod read_file;
+mod run_shell;
+
+pub use calculate::CalculateTool;
+pub use read_file::ReadFileTool;
+pub use run_shell::RunShellTool;
+
+/// 工具的名称、说明和参数的 JSON Schema，和 OpenAI、Anthropic 的工具定义格式一致
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    pub parameters: Value,
+}
+
+/// 助手可以调用的内置工具，arguments 为模型按 parameters 生成的参数，返回给模型的文本结果
+pub trait Tool: Send + Sync {
+    fn definition(&self) -> ToolDefinition;
+
+    fn call(
+        &self,
+        app_handle: AppHandle,
+        arguments: Value,
+    ) -> BoxFuture<'static, Result<String, AppError>>;
+}
+
+pub fn builtin_tools() -> Vec<Arc<dyn Tool>> {
+    vec![
+        Arc::new(ReadFileTool),
+        Arc::new(RunShellTool),
+        Arc::new(CalculateTool),
+    ]
+}
+
+pub fn find_tool(name: &str) -> Option<Arc<dyn Tool>> {
+    builtin_tools()
+        .into_iter()
+        .find(|tool| tool.definition().name == name)
+}
+
+/// 工具功能配置 tools 中的值，没有配置时返回空字符串
+pub fn tool_config_value(
+    config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+    key: &str,
+) -> String {
+    config_feature_map
+        .get("tools")
+        .and_then(|config| config.get(key))
+        .map(|config| config.value.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// 弹出系统对话框请用户确认这次调用，用户取消或者关闭对话框时返回 false
+pub async fn confirm_tool_call(app_handle: &AppHandle, title: &str, message: &str) -> bool {
+    let (tx, rx) = oneshot::channel();
+    app_handle
+        .dialog()
+        .message(message)
+        .title(title)
+        .kind(MessageDialogKind::Warning)
+        .buttons(MessageDialogButtons::OkCancelCustom(
+            "允许".to_string(),
+            "拒绝".to_string(),
+        ))
+        .show(move |approved| {
+            let _ = tx.send(approved);
+        });
+    rx.await.unwrap_or(false)
+}
+
+pub fn invalid_arguments(message: &str) -> AppError {
+    AppError::ToolError(format!("参数错误，{}", message))
+}
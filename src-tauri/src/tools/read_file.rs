@@ -0,0 +1,242 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use futures::future::BoxFuture;
+use regex::Regex;
+use serde_json::{json, Value};
+use tauri::Manager;
+
+use super::{confirm_tool_call, invalid_arguments, tool_config_value, Tool, ToolDefinition};
+use crate::errors::AppError;
+use crate::AppHandle;
+use crate::FeatureConfigState;
+
+const DEFAULT_MAX_BYTES: u64 = 64 * 1024;
+// 列出目录时最多返回的条目数和递归的深度
+const MAX_LIST_ENTRIES: usize = 200;
+const MAX_LIST_DEPTH: usize = 8;
+
+/// 读取本地文件或者列出目录，授权目录保存在 tools 的 read_file_roots 配置中，
+/// 授权目录以外的路径每次调用都需要用户确认
+pub struct ReadFileTool;
+
+impl Tool for ReadFileTool {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: "read_file".to_string(),
+            description: "读取本地文本文件的内容；path 是目录时列出目录中的文件，可以用 pattern 按 glob 过滤，例如 **/*.md".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "path": {"type": "string", "description": "文件或目录的绝对路径"},
+                    "pattern": {"type": "string", "description": "列出目录时使用的 glob，相对于 path"}
+                },
+                "required": ["path"]
+            }),
+        }
+    }
+
+    fn call(
+        &self,
+        app_handle: AppHandle,
+        arguments: Value,
+    ) -> BoxFuture<'static, Result<String, AppError>> {
+        Box::pin(async move {
+            let path = arguments["path"]
+                .as_str()
+                .filter(|path| !path.trim().is_empty())
+                .ok_or_else(|| invalid_arguments("缺少 path"))?;
+            let pattern = arguments["pattern"].as_str().map(|s| s.to_string());
+            let path = fs::canonicalize(path.trim())
+                .map_err(|e| AppError::ToolError(format!("无法访问 {}: {}", path, e)))?;
+
+            let (roots, max_bytes) = match app_handle.try_state::<FeatureConfigState>() {
+                Some(state) => {
+                    let config_feature_map = state.config_feature_map.lock().await;
+                    (
+                        parse_roots(&tool_config_value(&config_feature_map, "read_file_roots")),
+                        tool_config_value(&config_feature_map, "read_file_max_bytes")
+                            .parse()
+                            .unwrap_or(DEFAULT_MAX_BYTES),
+                    )
+                }
+                None => (Vec::new(), DEFAULT_MAX_BYTES),
+            };
+            if !is_within_roots(&path, &roots) {
+                let message = format!(
+                    "助手请求读取授权目录以外的路径：\n{}\n\n可以在设置中把常用目录加入授权目录，之后不再询问。",
+                    path.display()
+                );
+                if !confirm_tool_call(&app_handle, "读取本地文件", &message).await {
+                    return Err(AppError::ToolError(format!(
+                        "用户拒绝读取 {}",
+                        path.display()
+                    )));
+                }
+            }
+
+            tauri::async_runtime::spawn_blocking(move || {
+                if path.is_dir() {
+                    list_dir(&path, pattern.as_deref())
+                } else {
+                    read_text(&path, max_bytes)
+                }
+            })
+            .await
+            .map_err(|e| AppError::ToolError(e.to_string()))?
+        })
+    }
+}
+
+/// 授权目录配置每行一个，也可以用分号分隔，不存在的目录忽略
+fn parse_roots(value: &str) -> Vec<PathBuf> {
+    value
+        .split(|c| c == '\n' || c == ';')
+        .map(|root| root.trim())
+        .filter(|root| !root.is_empty())
+        .filter_map(|root| fs::canonicalize(root).ok())
+        .collect()
+}
+
+// path 和 roots 都应该是规范化之后的路径，避免通过 .. 或符号链接跳出授权目录
+fn is_within_roots(path: &Path, roots: &[PathBuf]) -> bool {
+    roots.iter().any(|root| path.starts_with(root))
+}
+
+fn read_text(path: &Path, max_bytes: u64) -> Result<String, AppError> {
+    let file_size = fs::metadata(path)?.len();
+    let mut buffer = Vec::new();
+    File::open(path)?.take(max_bytes).read_to_end(&mut buffer)?;
+    if buffer.contains(&0) {
+        return Err(AppError::ToolError(format!(
+            "{} 不是文本文件",
+            path.display()
+        )));
+    }
+    let mut content = String::from_utf8_lossy(&buffer).to_string();
+    if file_size > max_bytes {
+        content.push_str(&format!(
+            "\n\n[文件共 {} 字节，只读取了前 {} 字节]",
+            file_size, max_bytes
+        ));
+    }
+    Ok(content)
+}
+
+fn list_dir(dir: &Path, pattern: Option<&str>) -> Result<String, AppError> {
+    let matcher = pattern
+        .filter(|pattern| !pattern.trim().is_empty())
+        .map(|pattern| glob_to_regex(pattern.trim()));
+    let mut entries = Vec::new();
+    let mut truncated = false;
+    collect_entries(dir, dir, matcher.as_ref(), 0, &mut entries, &mut truncated);
+    if entries.is_empty() {
+        return Ok(format!("{} 中没有匹配的文件", dir.display()));
+    }
+    let mut result = entries.join("\n");
+    if truncated {
+        result.push_str(&format!("\n\n[只列出了前 {} 项]", MAX_LIST_ENTRIES));
+    }
+    Ok(result)
+}
+
+// 没有 pattern 时只列出当前目录，有 pattern 时递归匹配相对路径
+fn collect_entries(
+    root: &Path,
+    dir: &Path,
+    matcher: Option<&Regex>,
+    depth: usize,
+    entries: &mut Vec<String>,
+    truncated: &mut bool,
+) {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut children: Vec<_> = read_dir.flatten().collect();
+    children.sort_by_key(|entry| entry.file_name());
+    for entry in children {
+        if entries.len() >= MAX_LIST_ENTRIES {
+            *truncated = true;
+            return;
+        }
+        let path = entry.path();
+        let relative = path
+            .strip_prefix(root)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .replace('\\', "/");
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        match matcher {
+            None => entries.push(if metadata.is_dir() {
+                format!("{}/", relative)
+            } else {
+                format!("{}\t{}", relative, metadata.len())
+            }),
+            Some(matcher) => {
+                if metadata.is_file() && matcher.is_match(&relative) {
+                    entries.push(format!("{}\t{}", relative, metadata.len()));
+                }
+                if metadata.is_dir() && depth < MAX_LIST_DEPTH {
+                    collect_entries(root, &path, Some(matcher), depth + 1, entries, truncated);
+                }
+            }
+        }
+    }
+}
+
+/// 把 glob 转成正则：* 匹配文件名中的任意字符，** 匹配任意层目录，? 匹配一个字符
+fn glob_to_regex(pattern: &str) -> Regex {
+    let mut regex = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_to_regex() {
+        let matcher = glob_to_regex("**/*.md");
+        assert!(matcher.is_match("README.md"));
+        assert!(matcher.is_match("docs/guide/intro.md"));
+        assert!(!matcher.is_match("src/main.rs"));
+
+        let matcher = glob_to_regex("src/*.rs");
+        assert!(matcher.is_match("src/main.rs"));
+        assert!(!matcher.is_match("src/api/ai_api.rs"));
+        assert!(glob_to_regex("a?.txt").is_match("ab.txt"));
+        assert!(!glob_to_regex("a?.txt").is_match("a.txt"));
+    }
+
+    #[test]
+    fn test_is_within_roots() {
+        let roots = vec![PathBuf::from("/home/user/notes")];
+        assert!(is_within_roots(Path::new("/home/user/notes/a.md"), &roots));
+        assert!(!is_within_roots(
+            Path::new("/home/user/notes-private/a.md"),
+            &roots
+        ));
+        assert!(!is_within_roots(Path::new("/etc/passwd"), &[]));
+    }
+}
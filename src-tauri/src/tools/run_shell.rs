@@ -0,0 +1,292 @@
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use serde_json::{json, Value};
+use tauri::Manager;
+use tokio::process::Command;
+
+use super::{confirm_tool_call, invalid_arguments, tool_config_value, Tool, ToolDefinition};
+use crate::errors::AppError;
+use crate::AppHandle;
+use crate::FeatureConfigState;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+// stdout 和 stderr 各自最多保留的字符数，避免把大量输出塞进对话
+const MAX_OUTPUT_CHARS: usize = 16 * 1024;
+// 传给命令的环境变量，其他环境变量（例如 API Key）不会传下去
+const ALLOWED_ENV: [&str; 6] = ["PATH", "HOME", "USERPROFILE", "LANG", "SYSTEMROOT", "TEMP"];
+
+/// 执行允许列表中的程序，需要在 tools 的 run_shell_enabled 配置中开启，每条命令执行前都需要用户确认
+///
+/// 程序和参数分开传入，直接启动程序而不经过 shell，参数中的管道、重定向和命令替换都只是普通文字
+pub struct RunShellTool;
+
+struct ShellConfig {
+    enabled: bool,
+    timeout: Duration,
+    working_dir: Option<PathBuf>,
+    allowed: Vec<String>,
+}
+
+impl Tool for RunShellTool {
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: "run_shell".to_string(),
+            description: "在用户的电脑上执行允许列表中的程序并返回退出码、stdout 和 stderr，不经过 shell，每条命令都需要用户确认".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "program": {"type": "string", "description": "要执行的程序名称，例如 git"},
+                    "args": {"type": "array", "items": {"type": "string"}, "description": "传给程序的参数，每个参数一项"},
+                    "cwd": {"type": "string", "description": "执行命令的目录，不填时使用配置的工作目录"}
+                },
+                "required": ["program"]
+            }),
+        }
+    }
+
+    fn call(
+        &self,
+        app_handle: AppHandle,
+        arguments: Value,
+    ) -> BoxFuture<'static, Result<String, AppError>> {
+        Box::pin(async move {
+            let (program, args) = parse_argv(&arguments)?;
+            let config = shell_config(&app_handle).await;
+            if !config.enabled {
+                return Err(AppError::ToolError(
+                    "run_shell 工具没有开启，请在设置中开启后再使用".to_string(),
+                ));
+            }
+            check_allowed(&program, &config.allowed)?;
+            let command = display_command(&program, &args);
+            let working_dir = arguments["cwd"]
+                .as_str()
+                .filter(|cwd| !cwd.trim().is_empty())
+                .map(PathBuf::from)
+                .or(config.working_dir)
+                .or_else(|| app_handle.path().home_dir().ok());
+
+            let message = format!(
+                "助手请求执行以下命令：\n\n{}\n\n目录：{}",
+                command,
+                working_dir
+                    .as_ref()
+                    .map(|dir| dir.display().to_string())
+                    .unwrap_or_default()
+            );
+            if !confirm_tool_call(&app_handle, "执行命令", &message).await {
+                return Err(AppError::ToolError(format!("用户拒绝执行 {}", command)));
+            }
+            run_command(&program, &args, working_dir, config.timeout).await
+        })
+    }
+}
+
+async fn shell_config(app_handle: &AppHandle) -> ShellConfig {
+    let Some(state) = app_handle.try_state::<FeatureConfigState>() else {
+        return ShellConfig {
+            enabled: false,
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            working_dir: None,
+            allowed: Vec::new(),
+        };
+    };
+    let config_feature_map = state.config_feature_map.lock().await;
+    let get_value = |key: &str| tool_config_value(&config_feature_map, key);
+    ShellConfig {
+        enabled: get_value("run_shell_enabled") == "true",
+        timeout: Duration::from_secs(
+            get_value("run_shell_timeout_secs")
+                .parse()
+                .unwrap_or(DEFAULT_TIMEOUT_SECS),
+        ),
+        working_dir: Some(get_value("run_shell_cwd"))
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from),
+        allowed: get_value("run_shell_allowed")
+            .split(',')
+            .map(|allowed| allowed.trim().to_string())
+            .filter(|allowed| !allowed.is_empty())
+            .collect(),
+    }
+}
+
+// 模型传入的程序和参数，参数必须是字符串数组
+fn parse_argv(arguments: &Value) -> Result<(String, Vec<String>), AppError> {
+    let program = arguments["program"]
+        .as_str()
+        .map(|program| program.trim().to_string())
+        .filter(|program| !program.is_empty())
+        .ok_or_else(|| invalid_arguments("缺少 program"))?;
+    let args = match &arguments["args"] {
+        Value::Null => Vec::new(),
+        Value::Array(args) => args
+            .iter()
+            .map(|arg| arg.as_str().map(|arg| arg.to_string()))
+            .collect::<Option<Vec<String>>>()
+            .ok_or_else(|| invalid_arguments("args 必须是字符串数组"))?,
+        _ => return Err(invalid_arguments("args 必须是字符串数组")),
+    };
+    Ok((program, args))
+}
+
+// 只接受允许列表中的程序名称，带路径的程序可能绕过允许列表，直接拒绝
+fn check_allowed(program: &str, allowed: &[String]) -> Result<(), AppError> {
+    if program.contains(['/', '\\']) {
+        return Err(AppError::ToolError(format!(
+            "程序 {} 不能包含路径，只能使用允许列表中的程序名称",
+            program
+        )));
+    }
+    if !allowed.iter().any(|allowed| allowed == program) {
+        return Err(AppError::ToolError(format!(
+            "程序 {} 不在允许列表中，可以在设置中把它加入 run_shell 允许执行的程序",
+            program
+        )));
+    }
+    Ok(())
+}
+
+// 确认框和输出中显示的命令，包含空白或引号的参数加上引号
+fn display_command(program: &str, args: &[String]) -> String {
+    std::iter::once(program.to_string())
+        .chain(args.iter().map(|arg| {
+            if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'')
+            {
+                format!("{:?}", arg)
+            } else {
+                arg.clone()
+            }
+        }))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+async fn run_command(
+    program: &str,
+    args: &[String],
+    working_dir: Option<PathBuf>,
+    timeout: Duration,
+) -> Result<String, AppError> {
+    let mut process = Command::new(program);
+    process
+        .args(args)
+        .env_clear()
+        .envs(ALLOWED_ENV.iter().filter_map(|key| {
+            std::env::var(key)
+                .ok()
+                .map(|value| (key.to_string(), value))
+        }))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // 超时后 future 被丢弃时结束子进程
+        .kill_on_drop(true);
+    if let Some(working_dir) = working_dir {
+        process.current_dir(working_dir);
+    }
+
+    let output = match tokio::time::timeout(timeout, process.output()).await {
+        Ok(output) => output.map_err(|e| AppError::ToolError(format!("执行命令失败: {}", e)))?,
+        Err(_) => {
+            return Err(AppError::ToolError(format!(
+                "命令执行超过 {} 秒，已经结束",
+                timeout.as_secs()
+            )))
+        }
+    };
+    Ok(format_output(
+        &display_command(program, args),
+        output.status.code(),
+        &String::from_utf8_lossy(&output.stdout),
+        &String::from_utf8_lossy(&output.stderr),
+    ))
+}
+
+/// 返回给模型和显示在对话中的输出，stdout 和 stderr 分开，超出长度的部分截断
+fn format_output(command: &str, exit_code: Option<i32>, stdout: &str, stderr: &str) -> String {
+    let truncate = |text: &str| {
+        let text = text.trim_end();
+        match text.char_indices().nth(MAX_OUTPUT_CHARS) {
+            Some((index, _)) => format!("{}\n[输出过长，已截断]", &text[..index]),
+            None => text.to_string(),
+        }
+    };
+    let exit_code = match exit_code {
+        Some(code) => code.to_string(),
+        None => "无（被信号结束）".to_string(),
+    };
+    let mut result = format!("$ {}\nexit code: {}", command, exit_code);
+    for (name, text) in [("stdout", stdout), ("stderr", stderr)] {
+        if !text.trim().is_empty() {
+            result.push_str(&format!("\n\n{}:\n{}", name, truncate(text)));
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_output() {
+        let output = format_output("ls", Some(0), "a.txt\nb.txt\n", "");
+        assert_eq!(output, "$ ls\nexit code: 0\n\nstdout:\na.txt\nb.txt");
+
+        let long = "好".repeat(MAX_OUTPUT_CHARS + 10);
+        let output = format_output("cat", Some(1), "", &long);
+        assert!(output.ends_with("[输出过长，已截断]"));
+        assert!(output.contains("exit code: 1\n\nstderr:\n"));
+    }
+
+    #[test]
+    fn test_parse_argv() {
+        let (program, args) =
+            parse_argv(&json!({ "program": " git ", "args": ["log", "-n", "1"] })).unwrap();
+        assert_eq!(program, "git");
+        assert_eq!(args, vec!["log", "-n", "1"]);
+        assert!(parse_argv(&json!({ "program": "ls" }))
+            .unwrap()
+            .1
+            .is_empty());
+        assert!(parse_argv(&json!({ "program": "ls", "args": "-la" })).is_err());
+        assert!(parse_argv(&json!({ "program": "ls", "args": [1] })).is_err());
+        assert!(parse_argv(&json!({ "command": "ls" })).is_err());
+    }
+
+    #[test]
+    fn test_check_allowed() {
+        let allowed = vec!["ls".to_string(), "git".to_string()];
+        assert!(check_allowed("git", &allowed).is_ok());
+        assert!(check_allowed("rm", &allowed).is_err());
+        assert!(check_allowed("/bin/ls", &allowed).is_err());
+        assert!(check_allowed("..\\ls", &allowed).is_err());
+        assert!(check_allowed("ls", &[]).is_err());
+    }
+
+    #[test]
+    fn test_display_command() {
+        let args = vec![
+            "a b".to_string(),
+            "; rm -rf /".to_string(),
+            "-l".to_string(),
+        ];
+        assert_eq!(display_command("ls", &args), "ls \"a b\" \"; rm -rf /\" -l");
+    }
+
+    #[tokio::test]
+    async fn test_shell_syntax_is_not_interpreted() {
+        if cfg!(target_os = "windows") {
+            return;
+        }
+        let args = vec!["hello; echo injected".to_string(), "$(id)".to_string()];
+        let output = run_command("echo", &args, None, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert!(output.contains("stdout:\nhello; echo injected $(id)"));
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+use tauri::Manager;
+
+use crate::api::llm::Role;
+use crate::db::conversation_db::{
+    Conversation, ConversationDatabase, Message, Repository, DEFAULT_CONVERSATION_NAME,
+};
+use crate::db::system_db::FeatureConfig;
+use crate::errors::AppError;
+use crate::knowledge::embedding::{cosine_similarity, embed};
+use crate::state::private_conversation::PrivateConversationState;
+use crate::AppHandle;
+
+const DEFAULT_THRESHOLD: f32 = 0.1;
+const DEFAULT_RECENT_TURNS: usize = 3;
+// 之前至少有这么多轮提问才判断，对话刚开始时还没有形成话题
+const MIN_TURNS: usize = 2;
+// “继续”“为什么”这样的短追问和上文几乎没有相同的词，不参与判断
+const MIN_PROMPT_CHARS: usize = 10;
+// 比较时每条消息最多取的字符数，避免一条很长的回答掩盖其他几轮的话题
+const MAX_MESSAGE_CHARS: usize = 500;
+const MAX_SUMMARY_CHARS: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TopicSplitMode {
+    #[default]
+    Off,
+    // 只通过 suggest_split 给出建议，由用户决定是否开始新对话
+    Suggest,
+    // 提问时发现换了话题直接在新对话中回答
+    Auto,
+}
+
+/// 话题切换检测的配置，保存在 topic_split 功能配置中，默认关闭
+///
+/// 把新的提问和最近 recent_turns 轮对话分别转换成向量，相似度低于 threshold 时认为换了话题
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicSplitConfig {
+    pub mode: TopicSplitMode,
+    pub threshold: f32,
+    pub recent_turns: usize,
+}
+
+impl TopicSplitConfig {
+    pub fn from_config_map(
+        config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+    ) -> Self {
+        let config = config_feature_map.get("topic_split");
+        let get_value = |key: &str| {
+            config
+                .and_then(|config| config.get(key))
+                .map(|config| config.value.trim().to_string())
+                .unwrap_or_default()
+        };
+        let mode = match get_value("mode").as_str() {
+            "suggest" => TopicSplitMode::Suggest,
+            "auto" => TopicSplitMode::Auto,
+            _ => TopicSplitMode::Off,
+        };
+        TopicSplitConfig {
+            mode,
+            threshold: get_value("threshold")
+                .parse::<f32>()
+                .ok()
+                .filter(|threshold| (0.0..=1.0).contains(threshold))
+                .unwrap_or(DEFAULT_THRESHOLD),
+            recent_turns: get_value("recent_turns")
+                .parse::<usize>()
+                .ok()
+                .filter(|turns| *turns > 0)
+                .unwrap_or(DEFAULT_RECENT_TURNS),
+        }
+    }
+}
+
+/// suggest_split 的结果
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct SplitSuggestion {
+    pub should_split: bool,
+    // 最后一次提问和之前几轮对话的相似度，未开启、对话太短或者提问太短时为 None
+    pub similarity: Option<f32>,
+    // 换了话题的那条提问
+    pub message_id: Option<i64>,
+    // 开始新对话时可以带过去的上一个对话的简要内容
+    pub summary: Option<String>,
+}
+
+/// 检查对话的最后一次提问是否换了话题，私密对话和无痕对话不做检查
+pub fn suggest_split(
+    app_handle: &AppHandle,
+    conversation_id: i64,
+    config: &TopicSplitConfig,
+) -> Result<SplitSuggestion, AppError> {
+    if config.mode == TopicSplitMode::Off {
+        return Ok(SplitSuggestion::default());
+    }
+    let Some((conversation, messages)) = load_conversation(app_handle, conversation_id)? else {
+        return Ok(SplitSuggestion::default());
+    };
+    let Some(index) = messages
+        .iter()
+        .rposition(|message| message.role() == Some(Role::User))
+    else {
+        return Ok(SplitSuggestion::default());
+    };
+    let history = &messages[..index];
+    let similarity = topic_similarity(history, &messages[index].content, config.recent_turns);
+    let should_split = similarity.is_some_and(|similarity| similarity < config.threshold);
+    Ok(SplitSuggestion {
+        should_split,
+        similarity,
+        message_id: Some(messages[index].id),
+        summary: should_split.then(|| carry_over_summary(&conversation, history)),
+    })
+}
+
+/// auto 模式下在已有对话中提问前调用，新的提问换了话题时返回要带到新对话中的简要内容
+pub fn auto_split(
+    app_handle: &AppHandle,
+    conversation_id: i64,
+    prompt: &str,
+    config: &TopicSplitConfig,
+) -> Result<Option<String>, AppError> {
+    if config.mode != TopicSplitMode::Auto {
+        return Ok(None);
+    }
+    let Some((conversation, messages)) = load_conversation(app_handle, conversation_id)? else {
+        return Ok(None);
+    };
+    let shifted = topic_similarity(&messages, prompt, config.recent_turns)
+        .is_some_and(|similarity| similarity < config.threshold);
+    Ok(shifted.then(|| carry_over_summary(&conversation, &messages)))
+}
+
+// 对话和按时间排序的提问、回答，私密对话和无痕对话的内容是加密的，返回 None
+fn load_conversation(
+    app_handle: &AppHandle,
+    conversation_id: i64,
+) -> Result<Option<(Conversation, Vec<Message>)>, AppError> {
+    let db = ConversationDatabase::new(app_handle)?;
+    let Some(conversation) = db.conversation_repo()?.read(conversation_id)? else {
+        return Ok(None);
+    };
+    let incognito = app_handle
+        .try_state::<PrivateConversationState>()
+        .is_some_and(|state| state.is_incognito(conversation_id));
+    if conversation.is_private || incognito {
+        return Ok(None);
+    }
+    let mut seen = HashSet::new();
+    let mut messages = db
+        .message_repo()?
+        .list_by_conversation_id(conversation_id)?
+        .into_iter()
+        .map(|(message, _)| message)
+        .filter(|message| matches!(message.role(), Some(Role::User | Role::Assistant)))
+        .filter(|message| seen.insert(message.id))
+        .collect::<Vec<_>>();
+    messages.sort_by_key(|message| (message.created_time, message.id));
+    Ok(Some((conversation, messages)))
+}
+
+// 提问和最近 recent_turns 轮对话的相似度，history 中的轮数不够或者提问太短时返回 None
+fn topic_similarity(history: &[Message], prompt: &str, recent_turns: usize) -> Option<f32> {
+    if prompt.trim().chars().count() < MIN_PROMPT_CHARS {
+        return None;
+    }
+    let user_indexes = history
+        .iter()
+        .enumerate()
+        .filter(|(_, message)| message.role() == Some(Role::User))
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    if user_indexes.len() < MIN_TURNS {
+        return None;
+    }
+    let start = user_indexes[user_indexes.len().saturating_sub(recent_turns)];
+    let recent = history[start..]
+        .iter()
+        .map(|message| truncate(&message.content, MAX_MESSAGE_CHARS))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let prompt_embedding = embed(prompt);
+    if prompt_embedding.iter().all(|value| *value == 0.0) {
+        return None;
+    }
+    Some(cosine_similarity(&prompt_embedding, &embed(&recent)))
+}
+
+// 优先使用补全标题时生成的一句话总结，没有时用标题和最近几次提问
+fn carry_over_summary(conversation: &Conversation, history: &[Message]) -> String {
+    let summary = conversation
+        .summary
+        .as_deref()
+        .map(|summary| summary.trim())
+        .filter(|summary| !summary.is_empty())
+        .map(|summary| summary.to_string())
+        .unwrap_or_else(|| {
+            let prompts = history
+                .iter()
+                .rev()
+                .filter(|message| message.role() == Some(Role::User))
+                .take(DEFAULT_RECENT_TURNS)
+                .map(|message| message.content.trim().replace('\n', " "))
+                .collect::<Vec<_>>();
+            prompts.into_iter().rev().collect::<Vec<_>>().join("；")
+        });
+    let name = conversation.name.trim();
+    let summary = truncate(&summary, MAX_SUMMARY_CHARS);
+    if name.is_empty() || name == DEFAULT_CONVERSATION_NAME {
+        format!("用户在上一个对话中讨论了：{}", summary)
+    } else {
+        format!("用户在上一个对话“{}”中讨论了：{}", name, summary)
+    }
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    text.chars().take(max_chars).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: i64, message_type: &str, content: &str) -> Message {
+        Message {
+            id,
+            parent_id: None,
+            conversation_id: 1,
+            message_type: message_type.to_string(),
+            content: content.to_string(),
+            llm_model_id: None,
+            llm_model_name: None,
+            created_time: chrono::Utc::now(),
+            start_time: None,
+            finish_time: None,
+            token_count: 0,
+            is_truncated: false,
+            attachment_truncation: None,
+            pinned: false,
+            citations: None,
+            timed_out: false,
+            generation_metadata: None,
+            reasoning: None,
+            assistant_id: None,
+        }
+    }
+
+    fn history() -> Vec<Message> {
+        vec![
+            message(1, "user", "Rust 的所有权和借用规则是什么"),
+            message(
+                2,
+                "assistant",
+                "Rust 中每个值都有一个所有者，借用分为共享借用和可变借用",
+            ),
+            message(3, "user", "Rust 的可变借用为什么不能同时存在多个"),
+            message(
+                4,
+                "assistant",
+                "同时存在多个可变借用会导致数据竞争，借用检查器会拒绝",
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_topic_similarity() {
+        let history = history();
+        let same_topic =
+            topic_similarity(&history, "Rust 的借用检查器怎么处理生命周期", 3).unwrap();
+        let new_topic = topic_similarity(&history, "周末去杭州旅游有哪些推荐的景点", 3).unwrap();
+        assert!(same_topic > new_topic);
+        assert!(new_topic < DEFAULT_THRESHOLD);
+        assert!(same_topic >= DEFAULT_THRESHOLD);
+
+        // 短追问和只有一轮的对话不判断
+        assert_eq!(topic_similarity(&history, "为什么？", 3), None);
+        assert_eq!(
+            topic_similarity(&history[..2], "周末去杭州旅游有哪些推荐的景点", 3),
+            None
+        );
+    }
+
+    #[test]
+    fn test_carry_over_summary() {
+        let mut conversation = Conversation {
+            id: 1,
+            name: DEFAULT_CONVERSATION_NAME.to_string(),
+            assistant_id: Some(1),
+            created_time: chrono::Utc::now(),
+            is_private: false,
+            summary: None,
+        };
+        assert_eq!(
+            carry_over_summary(&conversation, &history()),
+            "用户在上一个对话中讨论了：Rust 的所有权和借用规则是什么；Rust 的可变借用为什么不能同时存在多个"
+        );
+        conversation.name = "Rust 借用".to_string();
+        conversation.summary = Some("解释了 Rust 的借用规则".to_string());
+        assert_eq!(
+            carry_over_summary(&conversation, &history()),
+            "用户在上一个对话“Rust 借用”中讨论了：解释了 Rust 的借用规则"
+        );
+    }
+
+    #[test]
+    fn test_config_from_map() {
+        assert_eq!(
+            TopicSplitConfig::from_config_map(&HashMap::new()).mode,
+            TopicSplitMode::Off
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+
+use sha2::{Digest, Sha256};
+use tokio_util::sync::CancellationToken;
+
+use crate::api::assistant_api::get_assistant;
+use crate::api::llm::{
+    get_provider, is_local_provider, parse_reasoning_tags, split_reasoning, ChatMessage, Role,
+    DEFAULT_REASONING_TAGS, RESPONSE_LENGTH_CONFIG,
+};
+use crate::api::llm_api::is_offline_mode;
+use crate::db::assistant_db::AssistantModelConfig;
+use crate::db::conversation_db::{ConversationDatabase, GlossaryTerm, Message, MessageTranslation};
+use crate::db::llm_db::LLMDatabase;
+use crate::db::system_db::FeatureConfig;
+use crate::errors::AppError;
+use crate::AppHandle;
+
+const DEFAULT_INSTRUCTION: &str =
+    "你是一名专业的翻译，请把用户发送的内容翻译成目标语言，保留原有的 markdown 格式和代码块，只输出译文";
+
+/// 翻译消息的配置，保存在 translation 功能配置中
+///
+/// assistant_id 为翻译使用的助手，使用它的模型、参数和提示词，为空时无法翻译
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TranslationConfig {
+    pub assistant_id: Option<i64>,
+}
+
+impl TranslationConfig {
+    pub fn from_config_map(
+        config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+    ) -> Self {
+        TranslationConfig {
+            assistant_id: config_feature_map
+                .get("translation")
+                .and_then(|config| config.get("assistant_id"))
+                .and_then(|config| config.value.trim().parse::<i64>().ok()),
+        }
+    }
+}
+
+/// 把消息翻译成 target_lang，原文没有变化时直接返回保存的译文，不会再次请求模型
+pub async fn translate_message(
+    app_handle: &AppHandle,
+    config: &TranslationConfig,
+    message: &Message,
+    target_lang: &str,
+) -> Result<MessageTranslation, AppError> {
+    let target_lang = target_lang.trim();
+    if target_lang.is_empty() {
+        return Err(AppError::UnknownError("没有指定目标语言".to_string()));
+    }
+    let db = ConversationDatabase::new(app_handle)?;
+    let translation_repo = db.translation_repo()?;
+    let hash = source_hash(&message.content);
+    if let Some(translation) =
+        translation_repo.get(message.conversation_id, message.id, target_lang)?
+    {
+        if translation.source_hash == hash {
+            return Ok(translation);
+        }
+    }
+
+    let assistant_id = config
+        .assistant_id
+        .ok_or(AppError::NoConfigError("translation".to_string()))?;
+    let assistant_detail =
+        get_assistant(app_handle.clone(), assistant_id).map_err(AppError::UnknownError)?;
+    let model = assistant_detail
+        .model
+        .first()
+        .ok_or(AppError::NoConfigError("translation".to_string()))?;
+    let model_detail = LLMDatabase::new(app_handle)?
+        .get_llm_model_detail(&model.provider_id, &model.model_code)?;
+    if is_offline_mode(app_handle)
+        && !is_local_provider(&model_detail.provider, &model_detail.configs)
+    {
+        return Err(AppError::OfflineBlocked(model_detail.provider.name));
+    }
+
+    let glossary = db.glossary_repo()?.list(Some(target_lang))?;
+    let instruction = assistant_detail
+        .prompts
+        .first()
+        .map(|prompt| prompt.prompt.trim())
+        .filter(|prompt| !prompt.is_empty())
+        .unwrap_or(DEFAULT_INSTRUCTION);
+    let mut model_configs = assistant_detail
+        .model_configs
+        .into_iter()
+        // 回答长度预设只用于对话，不限制译文的长度
+        .filter(|config| {
+            config.name != "model"
+                && config.name != "stream"
+                && config.name != RESPONSE_LENGTH_CONFIG
+        })
+        .collect::<Vec<_>>();
+    model_configs.push(AssistantModelConfig {
+        id: 0,
+        assistant_id,
+        assistant_model_id: 0,
+        name: "model".to_string(),
+        value: Some(model_detail.model.code.clone()),
+        value_type: "string".to_string(),
+    });
+    let response = get_provider(model_detail.provider, model_detail.configs)?
+        .chat(
+            -1,
+            vec![
+                ChatMessage::new(Role::System, instruction),
+                ChatMessage::new(
+                    Role::User,
+                    translation_prompt(&message.content, target_lang, &glossary),
+                ),
+            ],
+            model_configs,
+            CancellationToken::new(),
+        )
+        .await?;
+    // 推理模型的思考过程不保存到译文中
+    let content = split_reasoning(
+        &response.content,
+        &parse_reasoning_tags(DEFAULT_REASONING_TAGS),
+        true,
+    )
+    .answer
+    .trim()
+    .to_string();
+    if content.is_empty() {
+        return Err(AppError::ProviderError("模型没有返回译文".to_string()));
+    }
+
+    let translation = MessageTranslation {
+        message_id: message.id,
+        target_lang: target_lang.to_string(),
+        content,
+        source_hash: hash,
+        created_time: chrono::Utc::now(),
+    };
+    translation_repo.save(message.conversation_id, &translation)?;
+    Ok(translation)
+}
+
+// 只把原文中出现的术语放进提示词，术语表很长时不会占用太多上下文
+fn translation_prompt(content: &str, target_lang: &str, glossary: &[GlossaryTerm]) -> String {
+    let lower_content = content.to_lowercase();
+    let terms = glossary
+        .iter()
+        .filter(|term| !term.source_term.trim().is_empty())
+        .filter(|term| lower_content.contains(&term.source_term.trim().to_lowercase()))
+        .map(|term| {
+            format!(
+                "- {} → {}",
+                term.source_term.trim(),
+                term.target_term.trim()
+            )
+        })
+        .collect::<Vec<_>>();
+    if terms.is_empty() {
+        format!("目标语言：{}\n\n{}", target_lang, content)
+    } else {
+        format!(
+            "目标语言：{}\n\n以下术语请使用指定的译法：\n{}\n\n{}",
+            target_lang,
+            terms.join("\n"),
+            content
+        )
+    }
+}
+
+fn source_hash(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(source_term: &str, target_term: &str) -> GlossaryTerm {
+        GlossaryTerm {
+            id: 0,
+            source_term: source_term.to_string(),
+            target_term: target_term.to_string(),
+            target_lang: None,
+        }
+    }
+
+    #[test]
+    fn test_translation_prompt() {
+        let glossary = vec![term("Borrow Checker", "借用检查器"), term("crate", "包")];
+        assert_eq!(
+            translation_prompt("The borrow checker rejects it.", "简体中文", &glossary),
+            "目标语言：简体中文\n\n以下术语请使用指定的译法：\n- Borrow Checker → 借用检查器\n\nThe borrow checker rejects it."
+        );
+        assert_eq!(
+            translation_prompt("Hello", "日本語", &glossary),
+            "目标语言：日本語\n\nHello"
+        );
+    }
+
+    #[test]
+    fn test_source_hash() {
+        assert_eq!(source_hash("hello"), source_hash("hello"));
+        assert_ne!(source_hash("hello"), source_hash("hello!"));
+    }
+}
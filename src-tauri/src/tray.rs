@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+use image::{Rgba, RgbaImage};
+use tauri::image::Image;
+use tauri::menu::MenuItem;
+use tauri::Manager;
+
+use crate::state::message_token::MessageTokenManager;
+use crate::{AppHandle, AppRuntime};
+
+const TRAY_ID: &str = "aipp";
+// 检查生成数量的间隔，生成的开始和结束分散在提问、重新生成、取消等多个地方，统一轮询令牌数量
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const TRAY_ICON_PNG: &[u8] = include_bytes!("../icons/32x32.png");
+// 生成中在图标右下角画的圆点
+const BUSY_DOT_COLOR: Rgba<u8> = Rgba([255, 149, 0, 255]);
+
+/// 托盘菜单中的“取消所有生成”，没有生成时禁用，生成中显示正在生成的数量
+pub struct GenerationMenuState(pub MenuItem<AppRuntime>);
+
+/// 在后台跟踪正在进行的生成数量，更新托盘图标、提示文字和取消菜单，生成全部结束后恢复空闲状态
+pub async fn watch_generations(app_handle: AppHandle) {
+    let icons = tray_icons();
+    let mut last_count = 0;
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let Some(message_token_manager) = app_handle.try_state::<MessageTokenManager>() else {
+            continue;
+        };
+        let count = message_token_manager.running_count().await;
+        if count == last_count {
+            continue;
+        }
+        last_count = count;
+        if let Err(e) = update_tray(&app_handle, count, icons.as_ref()) {
+            println!("update tray error: {:?}", e);
+        }
+    }
+}
+
+/// 托盘菜单的“取消所有生成”，和退出时一样只取消令牌，已经生成的内容由接收端保存
+pub async fn cancel_all_generations(app_handle: AppHandle) {
+    if let Some(message_token_manager) = app_handle.try_state::<MessageTokenManager>() {
+        message_token_manager.cancel_all().await;
+    }
+}
+
+fn update_tray(
+    app_handle: &AppHandle,
+    count: usize,
+    icons: Option<&(Image<'static>, Image<'static>)>,
+) -> tauri::Result<()> {
+    let busy = count > 0;
+    if let Some(menu_state) = app_handle.try_state::<GenerationMenuState>() {
+        menu_state.0.set_enabled(busy)?;
+        menu_state.0.set_text(if busy {
+            format!("取消所有生成（{}）", count)
+        } else {
+            "取消所有生成".to_string()
+        })?;
+    }
+    let Some(tray) = app_handle.tray_by_id(TRAY_ID) else {
+        return Ok(());
+    };
+    tray.set_tooltip(Some(if busy {
+        format!("AIPP - 正在生成 {} 条回答", count)
+    } else {
+        "AIPP".to_string()
+    }))?;
+    // macOS 的托盘图标是模板图标，只显示轮廓，改为在图标旁边显示文字
+    if cfg!(target_os = "macos") {
+        tray.set_title(busy.then(|| format!("生成中 {}", count)))?;
+    } else if let Some((idle, busy_icon)) = icons {
+        let icon = if busy { busy_icon } else { idle };
+        tray.set_icon(Some(icon.clone()))?;
+    }
+    Ok(())
+}
+
+// (空闲图标, 生成中图标)，生成中图标在右下角加一个圆点
+fn tray_icons() -> Option<(Image<'static>, Image<'static>)> {
+    let idle = match image::load_from_memory(TRAY_ICON_PNG) {
+        Ok(image) => image.to_rgba8(),
+        Err(e) => {
+            println!("load tray icon error: {:?}", e);
+            return None;
+        }
+    };
+    let busy = with_busy_dot(&idle);
+    let to_image = |image: RgbaImage| {
+        let (width, height) = image.dimensions();
+        Image::new_owned(image.into_raw(), width, height)
+    };
+    Some((to_image(idle), to_image(busy)))
+}
+
+fn with_busy_dot(icon: &RgbaImage) -> RgbaImage {
+    let mut busy = icon.clone();
+    let (width, height) = busy.dimensions();
+    let radius = width.min(height) as f32 / 5.0;
+    let center_x = width as f32 - radius - 1.0;
+    let center_y = height as f32 - radius - 1.0;
+    for (x, y, pixel) in busy.enumerate_pixels_mut() {
+        let dx = x as f32 + 0.5 - center_x;
+        let dy = y as f32 + 0.5 - center_y;
+        if dx * dx + dy * dy <= radius * radius {
+            *pixel = BUSY_DOT_COLOR;
+        }
+    }
+    busy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_busy_dot() {
+        let icon = RgbaImage::from_pixel(32, 32, Rgba([0, 0, 0, 0]));
+        let busy = with_busy_dot(&icon);
+        assert_eq!(busy.dimensions(), (32, 32));
+        assert_eq!(*busy.get_pixel(26, 26), BUSY_DOT_COLOR);
+        assert_eq!(*busy.get_pixel(2, 2), Rgba([0, 0, 0, 0]));
+        // 原来的图标保持不变
+        assert_eq!(*icon.get_pixel(26, 26), Rgba([0, 0, 0, 0]));
+    }
+}
@@ -0,0 +1,477 @@
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{SampleFormat, SizedSample};
+use futures::future::BoxFuture;
+use reqwest::{multipart, Client};
+
+use crate::api::llm::{client_builder, is_local_endpoint, send_request};
+use crate::db::system_db::FeatureConfig;
+
+const DEFAULT_WHISPER_ENDPOINT: &str = "https://api.openai.com/v1/audio/transcriptions";
+const DEFAULT_WHISPER_MODEL: &str = "whisper-1";
+
+/// 把录音转成文字，兼容 OpenAI 的 Whisper 接口和本地 whisper.cpp 命令行都实现这个 trait
+pub trait SpeechToText: Send + Sync {
+    // audio 为 16 位单声道 WAV
+    fn transcribe(&self, audio: Vec<u8>) -> BoxFuture<'static, Result<String>>;
+}
+
+/// OpenAI 格式的 /audio/transcriptions 接口，通过 multipart 上传 WAV 文件
+pub struct WhisperApi {
+    client: Client,
+    endpoint: String,
+    api_key: Option<String>,
+    model: String,
+    language: Option<String>,
+}
+
+impl SpeechToText for WhisperApi {
+    fn transcribe(&self, audio: Vec<u8>) -> BoxFuture<'static, Result<String>> {
+        let client = self.client.clone();
+        let endpoint = self.endpoint.clone();
+        let api_key = self.api_key.clone();
+        let model = self.model.clone();
+        let language = self.language.clone();
+        Box::pin(async move {
+            let file = multipart::Part::bytes(audio)
+                .file_name("voice.wav")
+                .mime_str("audio/wav")?;
+            let mut form = multipart::Form::new()
+                .part("file", file)
+                .text("model", model)
+                .text("response_format", "json");
+            if let Some(language) = language {
+                form = form.text("language", language);
+            }
+            let mut request = client.post(&endpoint).multipart(form);
+            if let Some(api_key) = api_key {
+                request = request.bearer_auth(api_key);
+            }
+            let response = send_request(request).await?.error_for_status()?;
+            let value = response.json::<serde_json::Value>().await?;
+            value["text"]
+                .as_str()
+                .map(|text| text.trim().to_string())
+                .ok_or_else(|| anyhow!("语音识别服务返回的结果中没有 text 字段"))
+        })
+    }
+}
+
+/// 调用本地的 whisper.cpp 命令行，录音先写入临时文件，识别结果从 stdout 读取
+pub struct WhisperCommand {
+    command: String,
+    model_path: String,
+    language: Option<String>,
+}
+
+impl SpeechToText for WhisperCommand {
+    fn transcribe(&self, audio: Vec<u8>) -> BoxFuture<'static, Result<String>> {
+        let command = self.command.clone();
+        let model_path = self.model_path.clone();
+        let language = self.language.clone();
+        Box::pin(async move {
+            let path = std::env::temp_dir().join(format!(
+                "aipp_voice_{}_{}.wav",
+                std::process::id(),
+                chrono::Utc::now().timestamp_millis()
+            ));
+            tokio::fs::write(&path, &audio).await?;
+            let mut args = vec![
+                "-m".to_string(),
+                model_path,
+                "-f".to_string(),
+                path.to_string_lossy().to_string(),
+                "--no-timestamps".to_string(),
+            ];
+            if let Some(language) = language {
+                args.push("-l".to_string());
+                args.push(language);
+            }
+            let output = tokio::process::Command::new(&command)
+                .args(&args)
+                .kill_on_drop(true)
+                .output()
+                .await;
+            let _ = tokio::fs::remove_file(&path).await;
+            let output = output
+                .map_err(|e| anyhow!("启动 {} 失败，请确认已经安装 whisper.cpp: {}", command, e))?;
+            if !output.status.success() {
+                bail!(
+                    "whisper 识别失败: {}",
+                    String::from_utf8_lossy(&output.stderr)
+                );
+            }
+            Ok(String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(|line| line.trim())
+                .filter(|line| !line.is_empty())
+                .collect::<Vec<_>>()
+                .join(" "))
+        })
+    }
+}
+
+/// 根据 voice 的 engine 配置创建识别引擎，配置不完整时返回错误说明；
+/// 离线模式下只能使用本地命令或者地址指向本机的识别服务
+pub fn get_speech_engine(
+    config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+    offline: bool,
+) -> Result<Box<dyn SpeechToText>> {
+    let config = config_feature_map.get("voice");
+    let get_value = |key: &str| {
+        config
+            .and_then(|config| config.get(key))
+            .map(|config| config.value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    let language = get_value("language");
+    match get_value("engine").as_deref().unwrap_or("http") {
+        "http" => {
+            let endpoint = get_value("endpoint").unwrap_or(DEFAULT_WHISPER_ENDPOINT.to_string());
+            if offline && !is_local_endpoint(&endpoint) {
+                bail!("离线模式下不能使用云端语音识别服务 {}", endpoint);
+            }
+            Ok(Box::new(WhisperApi {
+                client: client_builder().timeout(Duration::from_secs(120)).build()?,
+                endpoint,
+                api_key: get_value("api_key"),
+                model: get_value("model").unwrap_or(DEFAULT_WHISPER_MODEL.to_string()),
+                language,
+            }))
+        }
+        "command" => Ok(Box::new(WhisperCommand {
+            command: get_value("command").unwrap_or("whisper-cli".to_string()),
+            model_path: get_value("model_path")
+                .ok_or_else(|| anyhow!("请先在设置中配置 whisper 模型文件的路径"))?,
+            language,
+        })),
+        engine => bail!("不支持的语音识别方式: {}", engine),
+    }
+}
+
+/// 正在进行的录音，采样数据在录音线程中不断追加
+///
+/// cpal 的 Stream 在部分平台上不能跨线程移动，所以由单独的线程持有，停止时通知线程退出
+pub struct Recording {
+    samples: Arc<Mutex<Vec<f32>>>,
+    channels: u16,
+    sample_rate: u32,
+    stop_tx: mpsc::Sender<()>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl Recording {
+    /// 打开默认的麦克风开始录音，max_duration 后自动停止采集
+    pub fn start(max_duration: Duration) -> Result<Self, String> {
+        let samples = Arc::new(Mutex::new(Vec::new()));
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let (ready_tx, ready_rx) = mpsc::channel::<Result<(u16, u32), String>>();
+
+        let thread_samples = samples.clone();
+        let handle = thread::spawn(move || {
+            let stream = match build_input_stream(thread_samples) {
+                Ok((stream, channels, sample_rate)) => {
+                    let _ = ready_tx.send(Ok((channels, sample_rate)));
+                    stream
+                }
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e));
+                    return;
+                }
+            };
+            let _ = stop_rx.recv_timeout(max_duration);
+            drop(stream);
+        });
+
+        let (channels, sample_rate) = ready_rx
+            .recv()
+            .map_err(|_| "录音线程异常退出".to_string())??;
+        Ok(Recording {
+            samples,
+            channels,
+            sample_rate,
+            stop_tx,
+            handle: Some(handle),
+        })
+    }
+
+    /// 当前已经录到的音频，用于识别中间结果
+    pub fn snapshot_wav(&self) -> Vec<u8> {
+        let samples = self.samples.lock().unwrap().clone();
+        encode_wav(&samples, self.channels, self.sample_rate)
+    }
+
+    pub fn duration(&self) -> Duration {
+        let len = self.samples.lock().unwrap().len() as u64;
+        Duration::from_millis(len * 1000 / (self.sample_rate as u64 * self.channels as u64).max(1))
+    }
+
+    /// 停止录音并返回完整的 WAV
+    pub fn stop(mut self) -> Vec<u8> {
+        let _ = self.stop_tx.send(());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        self.snapshot_wav()
+    }
+}
+
+fn build_input_stream(samples: Arc<Mutex<Vec<f32>>>) -> Result<(cpal::Stream, u16, u32), String> {
+    let device = cpal::default_host()
+        .default_input_device()
+        .ok_or_else(|| "没有找到可用的麦克风".to_string())?;
+    let supported_config = device.default_input_config().map_err(|e| e.to_string())?;
+    let sample_format = supported_config.sample_format();
+    let config = supported_config.config();
+    let channels = config.channels;
+    let sample_rate = config.sample_rate.0;
+
+    let stream = match sample_format {
+        SampleFormat::F32 => build_stream::<f32>(&device, &config, samples, |s| s),
+        SampleFormat::I16 => {
+            build_stream::<i16>(&device, &config, samples, |s| s as f32 / i16::MAX as f32)
+        }
+        SampleFormat::U16 => build_stream::<u16>(&device, &config, samples, |s| {
+            (s as f32 - 32768.0) / 32768.0
+        }),
+        format => return Err(format!("不支持的麦克风采样格式: {:?}", format)),
+    }?;
+    stream.play().map_err(|e| e.to_string())?;
+    Ok((stream, channels, sample_rate))
+}
+
+fn build_stream<T: SizedSample>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    samples: Arc<Mutex<Vec<f32>>>,
+    convert: fn(T) -> f32,
+) -> Result<cpal::Stream, String> {
+    device
+        .build_input_stream::<T, _, _>(
+            config,
+            move |data: &[T], _: &cpal::InputCallbackInfo| {
+                samples
+                    .lock()
+                    .unwrap()
+                    .extend(data.iter().map(|s| convert(*s)));
+            },
+            |e| println!("voice input stream error: {}", e),
+            None,
+        )
+        .map_err(|e| e.to_string())
+}
+
+/// 把交错的多声道采样混成单声道，编码为 16 位 PCM 的 WAV
+pub fn encode_wav(samples: &[f32], channels: u16, sample_rate: u32) -> Vec<u8> {
+    let channels = channels.max(1) as usize;
+    let mono: Vec<i16> = samples
+        .chunks(channels)
+        .map(|frame| {
+            let value = frame.iter().sum::<f32>() / frame.len() as f32;
+            (value.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+        })
+        .collect();
+
+    let data_len = (mono.len() * 2) as u32;
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    // PCM，单声道
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in mono {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+// 句末标点，英文的标点后面需要跟空白才算句子结束
+const SENTENCE_TERMINATORS: [char; 8] = ['。', '！', '？', '；', '…', '!', '?', '.'];
+// 句末标点后面的引号和括号属于同一句
+const CLOSING_MARKS: [char; 9] = ['"', '\'', '”', '’', ')', '）', '」', '』', '】'];
+
+/// 切分出来的一句话，index 从 0 开始递增，用于前端朗读时同步高亮
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sentence {
+    pub index: usize,
+    pub text: String,
+}
+
+/// 从流式生成的内容中切分出完整的句子，朗读时每得到一句就可以开始合成，不需要等生成结束
+#[derive(Default)]
+pub struct SentenceSplitter {
+    // 已经切分出去的内容
+    consumed: String,
+    index: usize,
+}
+
+impl SentenceSplitter {
+    /// content 为目前为止生成的完整内容，返回新出现的完整句子
+    ///
+    /// 重试时内容会重新生成，还没超过已切分的部分时不返回句子，和之前的内容不一致时从第 0 句重新开始
+    pub fn push(&mut self, content: &str) -> Vec<Sentence> {
+        if !content.starts_with(self.consumed.as_str()) {
+            if self.consumed.starts_with(content) {
+                return vec![];
+            }
+            self.consumed.clear();
+            self.index = 0;
+        }
+        let mut sentences = Vec::new();
+        loop {
+            let rest = &content[self.consumed.len()..];
+            let Some(end) = find_sentence_end(rest) else {
+                break;
+            };
+            if let Some(sentence) = self.next_sentence(&rest[..end]) {
+                sentences.push(sentence);
+            }
+            self.consumed.push_str(&rest[..end]);
+        }
+        sentences
+    }
+
+    /// 生成结束时把剩下的内容作为最后一句
+    pub fn finish(&mut self, content: &str) -> Vec<Sentence> {
+        let mut sentences = self.push(content);
+        if let Some(rest) = content.get(self.consumed.len()..) {
+            if let Some(sentence) = self.next_sentence(rest) {
+                sentences.push(sentence);
+            }
+            self.consumed = content.to_string();
+        }
+        sentences
+    }
+
+    fn next_sentence(&mut self, text: &str) -> Option<Sentence> {
+        let text = text.trim();
+        if text.is_empty() {
+            return None;
+        }
+        let sentence = Sentence {
+            index: self.index,
+            text: text.to_string(),
+        };
+        self.index += 1;
+        Some(sentence)
+    }
+}
+
+// 返回第一个句子结束的位置，句末标点之后还没有内容时无法确定句子是否结束，返回 None 等待后续的内容
+fn find_sentence_end(text: &str) -> Option<usize> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i].1;
+        if c != '\n' && !SENTENCE_TERMINATORS.contains(&c) {
+            i += 1;
+            continue;
+        }
+        let mut j = i + 1;
+        while j < chars.len()
+            && (SENTENCE_TERMINATORS.contains(&chars[j].1) || CLOSING_MARKS.contains(&chars[j].1))
+        {
+            j += 1;
+        }
+        let Some(&(end, next)) = chars.get(j) else {
+            return None;
+        };
+        // 避免切开小数、网址和英文缩写
+        if c.is_ascii() && c != '\n' && !next.is_whitespace() {
+            i = j;
+            continue;
+        }
+        return Some(end);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(sentences: Vec<Sentence>) -> Vec<String> {
+        sentences.into_iter().map(|s| s.text).collect()
+    }
+
+    #[test]
+    fn test_sentence_splitter_stream() {
+        let mut splitter = SentenceSplitter::default();
+        assert!(splitter.push("你好").is_empty());
+        assert!(splitter.push("你好。").is_empty());
+        assert_eq!(texts(splitter.push("你好。今天")), vec!["你好。"]);
+        assert_eq!(
+            texts(splitter.push("你好。今天“不错！”Pi is 3.14. Done")),
+            vec!["今天“不错！”", "Pi is 3.14."]
+        );
+        let last = splitter.finish("你好。今天“不错！”Pi is 3.14. Done");
+        assert_eq!(
+            last,
+            vec![Sentence {
+                index: 3,
+                text: "Done".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_sentence_splitter_regenerated() {
+        let mut splitter = SentenceSplitter::default();
+        assert_eq!(texts(splitter.push("第一句。第二")), vec!["第一句。"]);
+        // 重试时重新输出已经切分过的内容
+        assert!(splitter.push("第一").is_empty());
+        assert_eq!(texts(splitter.push("第一句。第二句。\n")), vec!["第二句。"]);
+        let sentences = splitter.push("另一个回答。好");
+        assert_eq!(sentences[0].index, 0);
+        assert_eq!(sentences[0].text, "另一个回答。");
+    }
+
+    #[test]
+    fn test_speech_engine_offline() {
+        let config = |endpoint: &str| {
+            let mut voice = HashMap::new();
+            voice.insert(
+                "endpoint".to_string(),
+                FeatureConfig {
+                    id: None,
+                    feature_code: "voice".to_string(),
+                    key: "endpoint".to_string(),
+                    value: endpoint.to_string(),
+                    data_type: "string".to_string(),
+                    description: None,
+                },
+            );
+            HashMap::from([("voice".to_string(), voice)])
+        };
+        // 离线模式下默认的云端识别服务不可用，本机的识别服务可以使用
+        assert!(get_speech_engine(&HashMap::new(), true).is_err());
+        assert!(get_speech_engine(&HashMap::new(), false).is_ok());
+        assert!(get_speech_engine(&config("http://127.0.0.1:8080/inference"), true).is_ok());
+    }
+
+    #[test]
+    fn test_encode_wav_downmix() {
+        let wav = encode_wav(&[1.0, 0.0, -1.0, -1.0], 2, 16000);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(&wav[8..12], b"WAVE");
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 16000);
+        assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()), 4);
+        assert_eq!(wav.len(), 48);
+        assert_eq!(i16::from_le_bytes([wav[44], wav[45]]), i16::MAX / 2);
+        assert_eq!(i16::from_le_bytes([wav[46], wav[47]]), -i16::MAX);
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::Serialize;
+use tauri::Manager;
+
+use crate::api::llm::{client_builder, send_request};
+use crate::api::llm_api::check_offline_endpoint;
+use crate::db::conversation_db::{ConversationDatabase, Message, Repository};
+use crate::db::system_db::FeatureConfig;
+use crate::errors::AppError;
+use crate::state::private_conversation::PrivateConversationState;
+use crate::AppHandle;
+use crate::FeatureConfigState;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// 重试前等待的时间，每次翻倍
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// webhook 通知的配置，保存在 webhook 功能配置中，url 为空表示不发送
+#[derive(Debug, Clone, Default)]
+pub struct WebhookConfig {
+    pub url: String,
+    // 请求体模板，{{event}} 等占位符替换为事件字段，为空时直接发送事件的 JSON
+    pub payload_template: String,
+    // 耗时超过这个秒数的生成和后台任务才通知
+    pub min_duration_secs: u64,
+    pub max_retries: u32,
+}
+
+impl WebhookConfig {
+    pub fn from_config_map(
+        config_feature_map: &HashMap<String, HashMap<String, FeatureConfig>>,
+    ) -> Self {
+        let config = config_feature_map.get("webhook");
+        let get_value = |key: &str| {
+            config
+                .and_then(|config| config.get(key))
+                .map(|config| config.value.trim().to_string())
+                .unwrap_or_default()
+        };
+        WebhookConfig {
+            url: get_value("url"),
+            payload_template: get_value("payload_template"),
+            min_duration_secs: get_value("min_duration_secs").parse().unwrap_or(30),
+            max_retries: get_value("max_retries").parse().unwrap_or(3),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.url.is_empty()
+    }
+}
+
+/// 发送给 webhook 的事件
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookEvent {
+    // generation_finished、index_finished、test
+    pub event: String,
+    pub title: String,
+    pub message: String,
+    pub conversation_id: Option<i64>,
+    pub duration_secs: u64,
+}
+
+/// 按模板生成请求体，占位符的值按 JSON 字符串转义，方便写 {"text": "{{title}}"} 这样的模板
+pub fn render_payload(template: &str, event: &WebhookEvent) -> String {
+    if template.trim().is_empty() {
+        return serde_json::to_string(event).unwrap_or_default();
+    }
+    let escape = |value: &str| {
+        let quoted = serde_json::to_string(value).unwrap_or_default();
+        quoted[1..quoted.len() - 1].to_string()
+    };
+    let conversation_id = event
+        .conversation_id
+        .map(|id| id.to_string())
+        .unwrap_or_default();
+    template
+        .replace("{{event}}", &escape(&event.event))
+        .replace("{{title}}", &escape(&event.title))
+        .replace("{{message}}", &escape(&event.message))
+        .replace("{{conversation_id}}", &conversation_id)
+        .replace("{{duration_secs}}", &event.duration_secs.to_string())
+}
+
+/// 发送通知，失败时按配置的次数重试，返回最后一次的 HTTP 状态码
+pub async fn send_webhook(config: &WebhookConfig, event: &WebhookEvent) -> Result<u16, String> {
+    let payload = render_payload(&config.payload_template, event);
+    // 模板渲染出来不是 JSON 时按纯文本发送，例如 ntfy 直接把请求体作为通知内容
+    let content_type = if serde_json::from_str::<serde_json::Value>(&payload).is_ok() {
+        "application/json"
+    } else {
+        "text/plain; charset=utf-8"
+    };
+    let client = client_builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let mut delay = RETRY_DELAY;
+    let mut attempt = 0;
+    loop {
+        let request = client
+            .post(&config.url)
+            .header("Content-Type", content_type)
+            .body(payload.clone());
+        let result = send_request(request).await;
+        let error = match result {
+            Ok(response) if response.status().is_success() => {
+                return Ok(response.status().as_u16())
+            }
+            // 4xx 一般是地址或者请求体写错了，重试也不会成功
+            Ok(response) if response.status().is_client_error() => {
+                return Err(format!("webhook 返回 {}", response.status()))
+            }
+            Ok(response) => format!("webhook 返回 {}", response.status()),
+            Err(e) => e.to_string(),
+        };
+        if attempt >= config.max_retries {
+            return Err(error);
+        }
+        attempt += 1;
+        println!("webhook error: {}, retry {} in {:?}", error, attempt, delay);
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+    }
+}
+
+/// 耗时超过配置的阈值时在后台发送通知，不影响调用方
+pub fn notify(app_handle: &AppHandle, event: WebhookEvent) {
+    let app_handle = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        let Some(state) = app_handle.try_state::<FeatureConfigState>() else {
+            return;
+        };
+        let config = WebhookConfig::from_config_map(&*state.config_feature_map.lock().await);
+        if !config.is_enabled() || event.duration_secs < config.min_duration_secs {
+            return;
+        }
+        if let Err(e) = check_offline_endpoint(&app_handle, &config.url) {
+            println!("skip webhook {}: {}", event.event, e);
+            return;
+        }
+        if let Err(e) = send_webhook(&config, &event).await {
+            println!("send webhook {} error: {}", event.event, e);
+        }
+    });
+}
+
+/// 回答生成结束时通知，私密和无痕对话不发送标题
+pub fn notify_generation_finished(
+    app_handle: &AppHandle,
+    message_id: i64,
+    duration_secs: u64,
+    finish_reason: Option<&str>,
+) {
+    let result = (|| -> Result<(Message, String), AppError> {
+        let conversation_db = ConversationDatabase::new(app_handle)?;
+        let message = conversation_db
+            .message_repo()?
+            .read(message_id)?
+            .ok_or(AppError::DatabaseError("未找到消息".to_string()))?;
+        let conversation = conversation_db
+            .conversation_repo()?
+            .read(message.conversation_id)?;
+        let incognito = app_handle
+            .try_state::<PrivateConversationState>()
+            .map(|state| state.is_incognito(message.conversation_id))
+            .unwrap_or(false);
+        let title = match conversation {
+            Some(conversation) if !conversation.is_private && !incognito => conversation.name,
+            _ => "私密对话".to_string(),
+        };
+        Ok((message, title))
+    })();
+    let (message, title) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            println!("webhook read message {} error: {:?}", message_id, e);
+            return;
+        }
+    };
+    let status = match finish_reason {
+        Some("timeout") => "生成超时".to_string(),
+        Some("stalled") => "生成停滞".to_string(),
+        _ => "回答已生成".to_string(),
+    };
+    notify(
+        app_handle,
+        WebhookEvent {
+            event: "generation_finished".to_string(),
+            title,
+            message: format!(
+                "{}，模型 {}，耗时 {} 秒",
+                status,
+                message.llm_model_name.unwrap_or_default(),
+                duration_secs
+            ),
+            conversation_id: Some(message.conversation_id),
+            duration_secs,
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_payload() {
+        let event = WebhookEvent {
+            event: "generation_finished".to_string(),
+            title: "写一首\"诗\"".to_string(),
+            message: "回答已生成".to_string(),
+            conversation_id: Some(3),
+            duration_secs: 42,
+        };
+        let payload = render_payload(
+            r#"{"text": "{{title}}：{{message}} ({{duration_secs}}s)"}"#,
+            &event,
+        );
+        let value: serde_json::Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(value["text"], "写一首\"诗\"：回答已生成 (42s)");
+
+        let value: serde_json::Value = serde_json::from_str(&render_payload("", &event)).unwrap();
+        assert_eq!(value["conversation_id"], 3);
+    }
+}
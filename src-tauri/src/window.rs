@@ -1,17 +1,519 @@
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use tauri::menu::CheckMenuItem;
 use tauri::Emitter;
 use tauri::Listener;
-use tauri::{AppHandle, Manager, Url, WebviewUrl, WebviewWindowBuilder, WindowEvent};
+use tauri::{
+    Manager, PhysicalPosition, PhysicalSize, Theme, Url, WebviewUrl, WebviewWindowBuilder,
+    WindowEvent,
+};
+
+use crate::api::llm::{client_builder, send_request};
+use crate::db::system_db::SystemDatabase;
+use crate::state::private_conversation::PrivateConversationState;
+use crate::state::window_session::WindowSessionState;
+use crate::window_placement::{
+    delete_profile, load_profiles, monitor_signature, select_profile, upsert_profile, MonitorInfo,
+    PlacementProfile, WindowPlacement,
+};
+use crate::{AppHandle, AppRuntime, FeatureConfigState, WebviewWindow, Window};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ThemeChangedPayload {
+    // 用户设置的主题偏好: system, light, dark
+    pub preference: String,
+    // 实际生效的主题: light, dark
+    pub theme: String,
+}
+
+/// 读取 system_config 中保存的主题偏好，未设置时跟随系统
+pub fn get_theme_preference(app: &AppHandle) -> String {
+    SystemDatabase::new(app)
+        .and_then(|db| db.get_config("theme"))
+        .ok()
+        .filter(|theme| matches!(theme.as_str(), "light" | "dark"))
+        .unwrap_or_else(|| "system".to_string())
+}
+
+// 创建窗口时使用的主题，None 表示跟随系统，同时决定 Windows 上标题栏的颜色
+pub fn preferred_theme(app: &AppHandle) -> Option<Theme> {
+    match get_theme_preference(app).as_str() {
+        "light" => Some(Theme::Light),
+        "dark" => Some(Theme::Dark),
+        _ => None,
+    }
+}
+
+pub fn theme_name(theme: Theme) -> String {
+    match theme {
+        Theme::Dark => "dark".to_string(),
+        _ => "light".to_string(),
+    }
+}
+
+// 跟随系统时，把系统外观的变化转发给窗口内的页面
+fn handle_theme_changed(window: &WebviewWindow, theme: Theme) {
+    let preference = get_theme_preference(window.app_handle());
+    if preference == "system" {
+        let _ = window.emit_to(
+            window.label(),
+            "theme_changed",
+            ThemeChangedPayload {
+                preference,
+                theme: theme_name(theme),
+            },
+        );
+    }
+}
+
+// 退出时保存位置和大小的窗口，询问窗口固定大小并且居中显示，不需要保存
+const GEOMETRY_WINDOWS: [&str; 3] = ["config", "chat_ui", "plugin"];
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct WindowGeometry {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    maximized: bool,
+}
+
+fn geometry_key(label: &str) -> String {
+    format!("window_geometry_{}", label)
+}
+
+/// 把打开过的窗口的位置和大小保存到 system_config，下次创建窗口时恢复
+pub fn save_window_geometry(app: &AppHandle) {
+    let Ok(db) = SystemDatabase::new(app) else {
+        return;
+    };
+    for label in GEOMETRY_WINDOWS {
+        let Some(window) = app.get_webview_window(label) else {
+            continue;
+        };
+        let (Ok(position), Ok(size)) = (window.outer_position(), window.inner_size()) else {
+            continue;
+        };
+        let geometry = WindowGeometry {
+            x: position.x,
+            y: position.y,
+            width: size.width,
+            height: size.height,
+            maximized: window.is_maximized().unwrap_or(false),
+        };
+        let Ok(value) = serde_json::to_string(&geometry) else {
+            continue;
+        };
+        if let Err(e) = set_system_config(&db, &geometry_key(label), &value) {
+            println!("save window {} geometry error: {:?}", label, e);
+        }
+    }
+}
+
+pub fn set_system_config(db: &SystemDatabase, key: &str, value: &str) -> rusqlite::Result<()> {
+    match db.get_config(key) {
+        Ok(existing) if existing.is_empty() => db.add_system_config(key, value),
+        Ok(_) => db.update_system_config(key, value),
+        Err(e) => Err(e),
+    }
+}
+
+const PRIVACY_MODE_CONFIG_KEY: &str = "privacy_mode";
+
+/// 托盘菜单中的隐私模式勾选项，通过命令切换时同步勾选状态
+pub struct PrivacyModeMenuState(pub CheckMenuItem<AppRuntime>);
+
+/// 读取 system_config 中保存的隐私模式开关，默认关闭
+pub fn is_privacy_mode(app: &AppHandle) -> bool {
+    SystemDatabase::new(app)
+        .and_then(|db| db.get_config(PRIVACY_MODE_CONFIG_KEY))
+        .is_ok_and(|value| value == "true")
+}
+
+/// 开启隐私模式后询问窗口不会出现在屏幕共享和截图中，
+/// Windows 上对应 SetWindowDisplayAffinity，macOS 上对应 NSWindow 的 sharingType，其他平台不生效
+pub fn apply_privacy_mode(app: &AppHandle, enabled: bool) -> Result<(), String> {
+    let db = SystemDatabase::new(app).map_err(|e| e.to_string())?;
+    set_system_config(&db, PRIVACY_MODE_CONFIG_KEY, &enabled.to_string())
+        .map_err(|e| e.to_string())?;
+
+    if let Some(window) = app.get_webview_window("ask") {
+        window
+            .set_content_protected(enabled)
+            .map_err(|e| e.to_string())?;
+    }
+    if let Some(menu_state) = app.try_state::<PrivacyModeMenuState>() {
+        let _ = menu_state.0.set_checked(enabled);
+    }
+    app.emit("privacy_mode_changed", enabled)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_privacy_mode(app_handle: AppHandle) -> bool {
+    is_privacy_mode(&app_handle)
+}
+
+#[tauri::command]
+pub fn set_privacy_mode(app_handle: AppHandle, enabled: bool) -> Result<(), String> {
+    apply_privacy_mode(&app_handle, enabled)
+}
+
+// 退出时保存会话的窗口，下次启动时按 session 的 restore_on_startup 配置重新打开并显示原来的对话
+const SESSION_WINDOWS: [&str; 2] = ["ask", "chat_ui"];
+// 只读查看窗口，退出时不保存
+const VIEWER_WINDOW: &str = "viewer";
+// 只读查看窗口可以调用的命令，只能读取要显示的对话
+const VIEWER_COMMANDS: [&str; 3] = [
+    "get_window_conversation",
+    "get_viewer_conversation",
+    "get_privacy_mode",
+];
+const SESSION_CONFIG_KEY: &str = "window_sessions";
+// 用户命名保存的会话，值为 NamedWindowSession 列表的 JSON
+const NAMED_SESSIONS_CONFIG_KEY: &str = "named_window_sessions";
+
+/// 一个窗口和窗口中显示的对话
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WindowSession {
+    pub label: String,
+    pub conversation_id: Option<i64>,
+    pub visible: bool,
+}
+
+/// 命名保存的一组窗口，例如“写作”“翻译”，可以随时切换过去
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct NamedWindowSession {
+    pub name: String,
+    pub windows: Vec<WindowSession>,
+}
+
+// 当前打开的会话窗口，无痕对话退出时会被删除，不保存
+fn current_window_sessions(app: &AppHandle) -> Vec<WindowSession> {
+    let session_state = app.state::<WindowSessionState>();
+    let private_state = app.try_state::<PrivateConversationState>();
+    SESSION_WINDOWS
+        .iter()
+        .filter_map(|label| {
+            let window = app.get_webview_window(label)?;
+            let conversation_id = session_state.get(label).filter(|id| {
+                !private_state
+                    .as_ref()
+                    .is_some_and(|state| state.is_incognito(*id))
+            });
+            Some(WindowSession {
+                label: label.to_string(),
+                conversation_id,
+                visible: window.is_visible().unwrap_or(false),
+            })
+        })
+        .collect()
+}
+
+// 记录每个窗口要显示的对话并打开可见的窗口，已经打开的窗口通过 window_conversation 事件切换对话
+fn open_window_sessions(app: &AppHandle, sessions: &[WindowSession]) {
+    let session_state = app.state::<WindowSessionState>();
+    for session in sessions {
+        if !SESSION_WINDOWS.contains(&session.label.as_str()) {
+            continue;
+        }
+        session_state.set(&session.label, session.conversation_id);
+        match app.get_webview_window(&session.label) {
+            Some(window) => {
+                let _ = window.emit("window_conversation", session.conversation_id);
+                if session.visible {
+                    let _ = window.show();
+                }
+            }
+            None if !session.visible => {}
+            None => match session.label.as_str() {
+                "ask" => create_ask_window(app),
+                _ => create_chat_ui_window(app),
+            },
+        }
+    }
+}
+
+/// 保存打开的窗口和窗口中显示的对话
+pub fn save_window_sessions(app: &AppHandle) {
+    let Ok(db) = SystemDatabase::new(app) else {
+        return;
+    };
+    let Ok(value) = serde_json::to_string(&current_window_sessions(app)) else {
+        return;
+    };
+    if let Err(e) = set_system_config(&db, SESSION_CONFIG_KEY, &value) {
+        println!("save window sessions error: {:?}", e);
+    }
+}
+
+/// 启动时恢复上次退出时打开的窗口，窗口中的页面通过 get_window_conversation 获取要显示的对话
+pub fn restore_window_sessions(app: &AppHandle) {
+    let enabled = app
+        .try_state::<FeatureConfigState>()
+        .and_then(|state| {
+            state
+                .config_feature_map
+                .blocking_lock()
+                .get("session")
+                .and_then(|config| config.get("restore_on_startup"))
+                .map(|config| config.value == "true")
+        })
+        .unwrap_or(false);
+    if !enabled {
+        return;
+    }
+    let sessions = SystemDatabase::new(app)
+        .and_then(|db| db.get_config(SESSION_CONFIG_KEY))
+        .ok()
+        .and_then(|value| serde_json::from_str::<Vec<WindowSession>>(&value).ok())
+        .unwrap_or_default();
+    open_window_sessions(app, &sessions);
+}
+
+fn load_named_sessions(db: &SystemDatabase) -> Vec<NamedWindowSession> {
+    db.get_config(NAMED_SESSIONS_CONFIG_KEY)
+        .ok()
+        .and_then(|value| serde_json::from_str(&value).ok())
+        .unwrap_or_default()
+}
+
+fn save_named_sessions(db: &SystemDatabase, sessions: &[NamedWindowSession]) -> Result<(), String> {
+    let value = serde_json::to_string(sessions).map_err(|e| e.to_string())?;
+    set_system_config(db, NAMED_SESSIONS_CONFIG_KEY, &value).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn list_named_window_sessions(
+    app_handle: AppHandle,
+) -> Result<Vec<NamedWindowSession>, String> {
+    let db = SystemDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    Ok(load_named_sessions(&db))
+}
+
+/// 把当前打开的窗口和窗口中显示的对话保存为命名的会话，同名的会话会被替换
+#[tauri::command]
+pub fn save_named_window_session(
+    app_handle: AppHandle,
+    name: String,
+) -> Result<NamedWindowSession, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("会话名称不能为空".to_string());
+    }
+    let session = NamedWindowSession {
+        name: name.to_string(),
+        windows: current_window_sessions(&app_handle),
+    };
+    let db = SystemDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    let mut sessions = load_named_sessions(&db);
+    sessions.retain(|existing| existing.name != session.name);
+    sessions.push(session.clone());
+    save_named_sessions(&db, &sessions)?;
+    Ok(session)
+}
+
+/// 打开命名会话中的窗口并切换到保存时显示的对话
+#[tauri::command]
+pub fn restore_named_window_session(app_handle: AppHandle, name: String) -> Result<(), String> {
+    let db = SystemDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    let session = load_named_sessions(&db)
+        .into_iter()
+        .find(|session| session.name == name)
+        .ok_or_else(|| format!("未找到会话 {}", name))?;
+    open_window_sessions(&app_handle, &session.windows);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn delete_named_window_session(app_handle: AppHandle, name: String) -> Result<(), String> {
+    let db = SystemDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    let mut sessions = load_named_sessions(&db);
+    let count = sessions.len();
+    sessions.retain(|session| session.name != name);
+    if sessions.len() == count {
+        return Err(format!("未找到会话 {}", name));
+    }
+    save_named_sessions(&db, &sessions)
+}
+
+/// 窗口切换对话时调用，记录窗口当前显示的对话
+#[tauri::command]
+pub fn set_window_conversation(
+    window: Window,
+    session_state: tauri::State<'_, WindowSessionState>,
+    conversation_id: Option<i64>,
+) {
+    session_state.set(window.label(), conversation_id);
+}
+
+/// 窗口打开时调用，返回上次退出时这个窗口显示的对话
+#[tauri::command]
+pub fn get_window_conversation(
+    window: Window,
+    session_state: tauri::State<'_, WindowSessionState>,
+) -> Option<i64> {
+    session_state.get(window.label())
+}
+
+// 可以保存到布局方案中的窗口
+const PLACEMENT_WINDOWS: [&str; 4] = ["ask", "config", "chat_ui", "plugin"];
+
+fn current_monitors(app: &AppHandle) -> Vec<MonitorInfo> {
+    app.available_monitors()
+        .unwrap_or_default()
+        .iter()
+        .map(|monitor| MonitorInfo {
+            name: monitor.name().cloned().unwrap_or_default(),
+            x: monitor.position().x,
+            y: monitor.position().y,
+            width: monitor.size().width,
+            height: monitor.size().height,
+        })
+        .collect()
+}
+
+// 按当前的显示器布局选择布局方案并应用到窗口，返回 false 表示没有匹配的方案或者方案中没有这个窗口
+fn apply_placement_profile(window: &WebviewWindow) -> bool {
+    let Ok(db) = SystemDatabase::new(window.app_handle()) else {
+        return false;
+    };
+    let profiles = load_profiles(&db);
+    if profiles.is_empty() {
+        return false;
+    }
+    let monitors = current_monitors(window.app_handle());
+    let Some(profile) = select_profile(&profiles, &monitor_signature(&monitors)) else {
+        return false;
+    };
+    let Some(placement) = profile.window(window.label()) else {
+        return false;
+    };
+    let Some((x, y)) = profile.resolve_position(placement, &monitors) else {
+        return false;
+    };
+    let _ = window.set_size(PhysicalSize::new(placement.width, placement.height));
+    let _ = window.set_position(PhysicalPosition::new(x, y));
+    if placement.maximized {
+        let _ = window.maximize();
+    }
+    println!(
+        "window {} placed by profile {}",
+        window.label(),
+        profile.name
+    );
+    true
+}
+
+/// 窗口布局方案列表，active 为和当前显示器布局匹配、创建窗口时会使用的方案
+#[derive(Serialize, Clone, Debug)]
+pub struct WindowPlacementProfiles {
+    pub profiles: Vec<PlacementProfile>,
+    pub active: Option<String>,
+}
+
+#[tauri::command]
+pub fn list_window_placement_profiles(
+    app_handle: AppHandle,
+) -> Result<WindowPlacementProfiles, String> {
+    let db = SystemDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    let profiles = load_profiles(&db);
+    let active = select_profile(
+        &profiles,
+        &monitor_signature(&current_monitors(&app_handle)),
+    )
+    .map(|profile| profile.name.clone());
+    Ok(WindowPlacementProfiles { profiles, active })
+}
+
+/// 把当前打开的窗口的位置和大小连同当前的显示器布局保存为命名的方案，同名的方案会被替换；
+/// 之后在相同的显示器布局下创建窗口时自动使用这个方案
+#[tauri::command]
+pub fn save_window_placement_profile(
+    app_handle: AppHandle,
+    name: String,
+) -> Result<PlacementProfile, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("方案名称不能为空".to_string());
+    }
+    let windows: Vec<WindowPlacement> = PLACEMENT_WINDOWS
+        .iter()
+        .filter_map(|label| {
+            let window = app_handle.get_webview_window(label)?;
+            let position = window.outer_position().ok()?;
+            let size = window.inner_size().ok()?;
+            Some(WindowPlacement {
+                label: label.to_string(),
+                monitor: window
+                    .current_monitor()
+                    .ok()
+                    .flatten()
+                    .and_then(|monitor| monitor.name().cloned()),
+                x: position.x,
+                y: position.y,
+                width: size.width,
+                height: size.height,
+                maximized: window.is_maximized().unwrap_or(false),
+            })
+        })
+        .collect();
+    if windows.is_empty() {
+        return Err("没有打开的窗口".to_string());
+    }
+    let monitors = current_monitors(&app_handle);
+    let profile = PlacementProfile {
+        name: name.to_string(),
+        monitor_signature: monitor_signature(&monitors),
+        monitors,
+        windows,
+    };
+    let db = SystemDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    upsert_profile(&db, profile.clone()).map_err(|e| e.to_string())?;
+    Ok(profile)
+}
+
+#[tauri::command]
+pub fn delete_window_placement_profile(app_handle: AppHandle, name: String) -> Result<(), String> {
+    let db = SystemDatabase::new(&app_handle).map_err(|e| e.to_string())?;
+    if !delete_profile(&db, &name).map_err(|e| e.to_string())? {
+        return Err(format!("未找到布局方案 {}", name));
+    }
+    Ok(())
+}
+
+// 恢复窗口的位置和大小，优先使用和当前显示器布局匹配的布局方案，其次使用上次退出时保存的位置和大小，
+// 返回 false 表示都没有
+fn restore_window_geometry(window: &WebviewWindow) -> bool {
+    if apply_placement_profile(window) {
+        return true;
+    }
+    let geometry = SystemDatabase::new(window.app_handle())
+        .and_then(|db| db.get_config(&geometry_key(window.label())))
+        .ok()
+        .and_then(|value| serde_json::from_str::<WindowGeometry>(&value).ok());
+    let Some(geometry) = geometry else {
+        return false;
+    };
+    if geometry.maximized {
+        let _ = window.maximize();
+    } else {
+        let _ = window.set_size(PhysicalSize::new(geometry.width, geometry.height));
+        let _ = window.set_position(PhysicalPosition::new(geometry.x, geometry.y));
+    }
+    true
+}
 
 pub fn create_ask_window(app: &AppHandle) {
     let window_builder =
         WebviewWindowBuilder::new(app, "ask", WebviewUrl::App("index.html".into()))
             .title("Aipp")
+            .theme(preferred_theme(app))
             .inner_size(800.0, 450.0)
             .fullscreen(false)
             .resizable(false)
             .decorations(false)
+            .content_protected(is_privacy_mode(app))
             .center();
 
     #[cfg(not(target_os = "macos"))]
@@ -20,11 +522,17 @@ pub fn create_ask_window(app: &AppHandle) {
     match window_builder.build() {
         Ok(window) => {
             let window_clone = window.clone();
-            window.on_window_event(move |event| {
-                if let WindowEvent::CloseRequested { .. } = event {
+            window.on_window_event(move |event| match event {
+                WindowEvent::CloseRequested { .. } => {
                     window_clone.hide().unwrap();
                 }
+                WindowEvent::ThemeChanged(theme) => {
+                    handle_theme_changed(&window_clone, *theme);
+                }
+                _ => {}
             });
+            // 询问窗口默认居中显示，有匹配的布局方案时使用方案中的位置
+            apply_placement_profile(&window);
         }
         Err(e) => eprintln!("Failed to build window: {}", e),
     }
@@ -34,6 +542,7 @@ pub fn create_config_window(app: &AppHandle) {
     let window_builder =
         WebviewWindowBuilder::new(app, "config", WebviewUrl::App("index.html".into()))
             .title("Aipp")
+            .theme(preferred_theme(app))
             .inner_size(1000.0, 800.0)
             .fullscreen(false)
             .resizable(true)
@@ -46,11 +555,16 @@ pub fn create_config_window(app: &AppHandle) {
     match window_builder.build() {
         Ok(window) => {
             let window_clone = window.clone();
-            window.on_window_event(move |event| {
-                if let WindowEvent::CloseRequested { .. } = event {
+            window.on_window_event(move |event| match event {
+                WindowEvent::CloseRequested { .. } => {
                     window_clone.hide().unwrap();
                 }
+                WindowEvent::ThemeChanged(theme) => {
+                    handle_theme_changed(&window_clone, *theme);
+                }
+                _ => {}
             });
+            restore_window_geometry(&window);
         }
         Err(e) => eprintln!("Failed to build window: {}", e),
     }
@@ -60,6 +574,7 @@ pub fn create_chat_ui_window(app: &AppHandle) {
     let window_builder =
         WebviewWindowBuilder::new(app, "chat_ui", WebviewUrl::App("index.html".into()))
             .title("Aipp")
+            .theme(preferred_theme(app))
             .inner_size(1000.0, 800.0)
             .fullscreen(false)
             .resizable(true)
@@ -73,12 +588,18 @@ pub fn create_chat_ui_window(app: &AppHandle) {
     match window_builder.build() {
         Ok(window) => {
             let window_clone = window.clone();
-            window.on_window_event(move |event| {
-                if let WindowEvent::CloseRequested { .. } = event {
+            window.on_window_event(move |event| match event {
+                WindowEvent::CloseRequested { .. } => {
                     window_clone.hide().unwrap();
                 }
+                WindowEvent::ThemeChanged(theme) => {
+                    handle_theme_changed(&window_clone, *theme);
+                }
+                _ => {}
             });
-            let _ = window.maximize();
+            if !restore_window_geometry(&window) {
+                let _ = window.maximize();
+            }
         }
         Err(e) => eprintln!("Failed to build window: {}", e),
     }
@@ -88,6 +609,7 @@ pub fn create_plugin_window(app: &AppHandle) {
     let window_builder =
         WebviewWindowBuilder::new(app, "plugin", WebviewUrl::App("index.html".into()))
             .title("Aipp")
+            .theme(preferred_theme(app))
             .inner_size(1000.0, 800.0)
             .fullscreen(false)
             .resizable(true)
@@ -100,16 +622,84 @@ pub fn create_plugin_window(app: &AppHandle) {
     match window_builder.build() {
         Ok(window) => {
             let window_clone = window.clone();
-            window.on_window_event(move |event| {
-                if let WindowEvent::CloseRequested { .. } = event {
+            window.on_window_event(move |event| match event {
+                WindowEvent::CloseRequested { .. } => {
                     window_clone.hide().unwrap();
                 }
+                WindowEvent::ThemeChanged(theme) => {
+                    handle_theme_changed(&window_clone, *theme);
+                }
+                _ => {}
+            });
+            restore_window_geometry(&window);
+        }
+        Err(e) => eprintln!("Failed to build window: {}", e),
+    }
+}
+
+/// 只读查看对话的窗口，用于演示或者共享屏幕，页面通过 get_window_conversation 获取要显示的对话，
+/// 不显示输入框，通过 get_viewer_conversation 读取不包含提供商信息的消息
+pub fn create_viewer_window(app: &AppHandle) {
+    let window_builder =
+        WebviewWindowBuilder::new(app, VIEWER_WINDOW, WebviewUrl::App("index.html".into()))
+            .title("Aipp")
+            .theme(preferred_theme(app))
+            .inner_size(900.0, 800.0)
+            .fullscreen(false)
+            .resizable(true)
+            .decorations(true)
+            .disable_drag_drop_handler()
+            .center();
+
+    #[cfg(not(target_os = "macos"))]
+    let window_builder = window_builder.transparent(false);
+
+    match window_builder.build() {
+        Ok(window) => {
+            let window_clone = window.clone();
+            // 查看窗口关闭后直接销毁，不像主窗口一样隐藏
+            window.on_window_event(move |event| {
+                if let WindowEvent::ThemeChanged(theme) = event {
+                    handle_theme_changed(&window_clone, *theme);
+                }
             });
+            restore_window_geometry(&window);
         }
         Err(e) => eprintln!("Failed to build window: {}", e),
     }
 }
 
+/// 只读查看窗口不能发送消息、修改数据或者读取配置，只能调用 VIEWER_COMMANDS 中的命令
+pub fn is_command_allowed(window_label: &str, command: &str) -> bool {
+    window_label != VIEWER_WINDOW || VIEWER_COMMANDS.contains(&command)
+}
+
+/// 打开只读查看窗口显示对话，窗口已经打开时切换到这个对话
+#[tauri::command]
+pub async fn open_viewer_window(
+    app_handle: AppHandle,
+    session_state: tauri::State<'_, WindowSessionState>,
+    private_state: tauri::State<'_, PrivateConversationState>,
+    conversation_id: i64,
+) -> Result<(), String> {
+    if private_state.is_locked(conversation_id) {
+        return Err("对话已锁定，请先解锁".to_string());
+    }
+    session_state.set(VIEWER_WINDOW, Some(conversation_id));
+    match app_handle.get_webview_window(VIEWER_WINDOW) {
+        None => create_viewer_window(&app_handle),
+        Some(window) => {
+            let _ = window.emit("viewer_conversation", conversation_id);
+            if window.is_minimized().unwrap_or(false) {
+                let _ = window.unminimize();
+            }
+            let _ = window.show();
+            let _ = window.set_focus();
+        }
+    }
+    Ok(())
+}
+
 #[tauri::command]
 pub async fn open_config_window(app_handle: AppHandle) -> Result<(), String> {
     if app_handle.get_webview_window("config").is_none() {
@@ -178,6 +768,7 @@ pub async fn open_preview_html_window(app_handle: AppHandle, html: String) -> Re
         WebviewUrl::App("index.html".into()),
     )
     .title("Aipp")
+    .theme(preferred_theme(&app_handle))
     .inner_size(1000.0, 800.0)
     .fullscreen(false)
     .resizable(true)
@@ -190,10 +781,14 @@ pub async fn open_preview_html_window(app_handle: AppHandle, html: String) -> Re
     match window_builder.build() {
         Ok(window) => {
             let window_clone = window.clone();
-            window.on_window_event(move |event| {
-                if let WindowEvent::CloseRequested { .. } = event {
+            window.on_window_event(move |event| match event {
+                WindowEvent::CloseRequested { .. } => {
                     window_clone.hide().unwrap();
                 }
+                WindowEvent::ThemeChanged(theme) => {
+                    handle_theme_changed(&window_clone, *theme);
+                }
+                _ => {}
             });
 
             let window = app_handle.get_webview_window("preview_html").unwrap();
@@ -230,8 +825,8 @@ async fn open_preview_window(
 
     let file_content = html.clone();
 
-    let client = reqwest::Client::new();
-    let response = client
+    let client = client_builder().build().map_err(|e| e.to_string())?;
+    let request = client
         .post(format!(
             "http://preview.teafakedomain.com:{}/api/saveFile",
             port
@@ -239,9 +834,8 @@ async fn open_preview_window(
         .json(&serde_json::json!({
             "fileName": file_name,
             "fileContent": file_content
-        }))
-        .send()
-        .await;
+        }));
+    let response = send_request(request).await;
 
     if let Ok(response) = response {
         if response.status().is_success() {
@@ -254,6 +848,7 @@ async fn open_preview_window(
             let window_builder =
                 WebviewWindowBuilder::new(&app_handle, window_id, WebviewUrl::External(url))
                     .title("Aipp")
+                    .theme(preferred_theme(&app_handle))
                     .inner_size(1000.0, 800.0)
                     .fullscreen(false)
                     .resizable(true)
@@ -266,10 +861,14 @@ async fn open_preview_window(
             match window_builder.build() {
                 Ok(window) => {
                     let window_clone = window.clone();
-                    window.on_window_event(move |event| {
-                        if let WindowEvent::CloseRequested { .. } = event {
+                    window.on_window_event(move |event| match event {
+                        WindowEvent::CloseRequested { .. } => {
                             window_clone.hide().unwrap();
                         }
+                        WindowEvent::ThemeChanged(theme) => {
+                            handle_theme_changed(&window_clone, *theme);
+                        }
+                        _ => {}
                     });
 
                     let window = app_handle.get_webview_window(window_id).unwrap();
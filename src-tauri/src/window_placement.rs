@@ -0,0 +1,258 @@
+use serde::{Deserialize, Serialize};
+
+use crate::db::system_db::SystemDatabase;
+use crate::window::set_system_config;
+
+// system_config 中保存窗口布局方案的 key，值为 PlacementProfile 列表的 JSON
+const PROFILES_CONFIG_KEY: &str = "window_placement_profiles";
+
+/// 一个显示器的名称、位置和分辨率，都是物理像素
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MonitorInfo {
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// 显示器布局的标识，显示器的名称、分辨率和位置都相同时标识相同，和显示器的枚举顺序无关
+pub fn monitor_signature(monitors: &[MonitorInfo]) -> String {
+    let mut parts: Vec<String> = monitors
+        .iter()
+        .map(|m| format!("{}:{}x{}@{},{}", m.name, m.width, m.height, m.x, m.y))
+        .collect();
+    parts.sort();
+    parts.join(";")
+}
+
+// 只比较显示器的名称和分辨率，显示器的排列方式改变时仍然可以匹配到方案
+fn monitor_set(signature: &str) -> Vec<&str> {
+    let mut monitors: Vec<&str> = signature
+        .split(';')
+        .map(|part| part.split_once('@').map_or(part, |(monitor, _)| monitor))
+        .collect();
+    monitors.sort();
+    monitors
+}
+
+/// 一个窗口在方案中的位置和大小
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WindowPlacement {
+    pub label: String,
+    // 保存时窗口所在的显示器
+    pub monitor: Option<String>,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    #[serde(default)]
+    pub maximized: bool,
+}
+
+/// 命名的窗口布局方案，例如“笔记本”“外接显示器”，创建窗口时按当前的显示器布局自动选择
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PlacementProfile {
+    pub name: String,
+    pub monitor_signature: String,
+    pub monitors: Vec<MonitorInfo>,
+    pub windows: Vec<WindowPlacement>,
+}
+
+impl PlacementProfile {
+    pub fn window(&self, label: &str) -> Option<&WindowPlacement> {
+        self.windows.iter().find(|window| window.label == label)
+    }
+
+    /// 窗口在当前显示器布局下的位置：显示器的排列改变后按保存时所在显示器的新位置平移，
+    /// 保存时所在的显示器已经不存在或者窗口和所有显示器都不相交时返回 None，避免窗口出现在屏幕外
+    pub fn resolve_position(
+        &self,
+        placement: &WindowPlacement,
+        monitors: &[MonitorInfo],
+    ) -> Option<(i32, i32)> {
+        let (mut x, mut y) = (placement.x, placement.y);
+        if let Some(name) = &placement.monitor {
+            let current = monitors.iter().find(|monitor| &monitor.name == name)?;
+            if let Some(saved) = self.monitors.iter().find(|monitor| &monitor.name == name) {
+                x += current.x - saved.x;
+                y += current.y - saved.y;
+            }
+        }
+        let visible = monitors.iter().any(|monitor| {
+            x < monitor.x + monitor.width as i32
+                && x + placement.width as i32 > monitor.x
+                && y < monitor.y + monitor.height as i32
+                && y + placement.height as i32 > monitor.y
+        });
+        visible.then_some((x, y))
+    }
+}
+
+/// 选择和当前显示器布局匹配的方案：优先选择布局完全相同的，其次选择显示器相同只是排列不同的，
+/// 有多个匹配时使用最近保存的
+pub fn select_profile<'a>(
+    profiles: &'a [PlacementProfile],
+    signature: &str,
+) -> Option<&'a PlacementProfile> {
+    profiles
+        .iter()
+        .rev()
+        .find(|profile| profile.monitor_signature == signature)
+        .or_else(|| {
+            let monitors = monitor_set(signature);
+            profiles
+                .iter()
+                .rev()
+                .find(|profile| monitor_set(&profile.monitor_signature) == monitors)
+        })
+}
+
+pub fn load_profiles(db: &SystemDatabase) -> Vec<PlacementProfile> {
+    db.get_config(PROFILES_CONFIG_KEY)
+        .ok()
+        .and_then(|value| serde_json::from_str(&value).ok())
+        .unwrap_or_default()
+}
+
+fn save_profiles(db: &SystemDatabase, profiles: &[PlacementProfile]) -> rusqlite::Result<()> {
+    let value = serde_json::to_string(profiles).unwrap_or_else(|_| "[]".to_string());
+    set_system_config(db, PROFILES_CONFIG_KEY, &value)
+}
+
+/// 保存方案，同名的方案会被替换，最近保存的方案放在最后
+pub fn upsert_profile(db: &SystemDatabase, profile: PlacementProfile) -> rusqlite::Result<()> {
+    let mut profiles = load_profiles(db);
+    profiles.retain(|existing| existing.name != profile.name);
+    profiles.push(profile);
+    save_profiles(db, &profiles)
+}
+
+/// 删除方案，返回是否存在这个方案
+pub fn delete_profile(db: &SystemDatabase, name: &str) -> rusqlite::Result<bool> {
+    let mut profiles = load_profiles(db);
+    let count = profiles.len();
+    profiles.retain(|profile| profile.name != name);
+    if profiles.len() == count {
+        return Ok(false);
+    }
+    save_profiles(db, &profiles)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(name: &str, x: i32, width: u32) -> MonitorInfo {
+        MonitorInfo {
+            name: name.to_string(),
+            x,
+            y: 0,
+            width,
+            height: 1080,
+        }
+    }
+
+    fn profile(name: &str, monitors: Vec<MonitorInfo>) -> PlacementProfile {
+        PlacementProfile {
+            name: name.to_string(),
+            monitor_signature: monitor_signature(&monitors),
+            monitors,
+            windows: vec![],
+        }
+    }
+
+    #[test]
+    fn test_monitor_signature() {
+        let laptop = monitor("Built-in", 0, 1920);
+        let external = monitor("DELL U2720Q", 1920, 3840);
+        assert_eq!(
+            monitor_signature(&[laptop.clone(), external.clone()]),
+            monitor_signature(&[external, laptop])
+        );
+        assert_eq!(
+            monitor_signature(&[monitor("Built-in", 0, 1920)]),
+            "Built-in:1920x1080@0,0"
+        );
+    }
+
+    #[test]
+    fn test_select_profile() {
+        let laptop = profile("笔记本", vec![monitor("Built-in", 0, 1920)]);
+        let docked = profile(
+            "外接显示器",
+            vec![monitor("Built-in", 0, 1920), monitor("DELL", 1920, 3840)],
+        );
+        let profiles = vec![laptop, docked];
+
+        let current = monitor_signature(&[monitor("Built-in", 0, 1920)]);
+        assert_eq!(select_profile(&profiles, &current).unwrap().name, "笔记本");
+
+        // 外接显示器换到了左边，仍然匹配外接显示器的方案
+        let current =
+            monitor_signature(&[monitor("DELL", -3840, 3840), monitor("Built-in", 0, 1920)]);
+        assert_eq!(
+            select_profile(&profiles, &current).unwrap().name,
+            "外接显示器"
+        );
+
+        let current = monitor_signature(&[monitor("Built-in", 0, 2560)]);
+        assert!(select_profile(&profiles, &current).is_none());
+    }
+
+    #[test]
+    fn test_resolve_position() {
+        let mut docked = profile(
+            "外接显示器",
+            vec![monitor("Built-in", 0, 1920), monitor("DELL", 1920, 3840)],
+        );
+        let placement = WindowPlacement {
+            label: "chat_ui".to_string(),
+            monitor: Some("DELL".to_string()),
+            x: 2020,
+            y: 100,
+            width: 800,
+            height: 600,
+            maximized: false,
+        };
+        docked.windows.push(placement.clone());
+
+        let current = vec![monitor("Built-in", 0, 1920), monitor("DELL", 1920, 3840)];
+        assert_eq!(
+            docked.resolve_position(&placement, &current),
+            Some((2020, 100))
+        );
+        // 外接显示器换到了左边，窗口跟着显示器移动
+        let current = vec![monitor("DELL", -3840, 3840), monitor("Built-in", 0, 1920)];
+        assert_eq!(
+            docked.resolve_position(&placement, &current),
+            Some((-3740, 100))
+        );
+        // 保存时所在的显示器不存在
+        let current = vec![monitor("Built-in", 0, 1920)];
+        assert_eq!(docked.resolve_position(&placement, &current), None);
+        // 没有记录显示器的窗口在所有显示器之外
+        let outside = WindowPlacement {
+            monitor: None,
+            x: 5000,
+            ..placement
+        };
+        assert_eq!(docked.resolve_position(&outside, &current), None);
+    }
+
+    #[test]
+    fn test_select_latest_profile() {
+        let monitors = vec![monitor("Built-in", 0, 1920)];
+        let profiles = vec![
+            profile("旧方案", monitors.clone()),
+            profile("新方案", monitors.clone()),
+        ];
+        assert_eq!(
+            select_profile(&profiles, &monitor_signature(&monitors))
+                .unwrap()
+                .name,
+            "新方案"
+        );
+    }
+}
@@ -21,6 +21,7 @@ import { writeText } from "@tauri-apps/plugin-clipboard-manager";
 import CodeBlock from "./components/CodeBlock";
 import useFileManagement from "./hooks/useFileManagement";
 import InputArea from "./components/conversation/InputArea";
+import useDraft from "./hooks/useDraft";
 const appWindow = getCurrentWebviewWindow();
 
 interface AiResponse {
@@ -48,8 +49,39 @@ function AskWindow() {
     const [copySuccess, setCopySuccess] = useState<boolean>(false);
     const [selectedText, setSelectedText] = useState<string>("");
 
+    useDraft({ assistantId: 1 }, query, setQuery);
+
     let unsubscribe: Promise<() => void> | null = null;
 
+    // 显示窗口上次的回答，启动时恢复会话或者切换到命名会话时使用
+    const showConversation = (conversationId: number | null) => {
+        if (!conversationId) {
+            startNewConversation();
+            return;
+        }
+        invoke<[any, any[]]>("get_conversation_with_messages", {
+            conversationId,
+        }).then(([, messages]) => {
+            const reply = [...messages]
+                .reverse()
+                .find((message) => message.message_type === "assistant");
+            if (reply) {
+                setMessageId(reply.id);
+                setResponse(reply.content);
+            }
+        });
+    };
+
+    useEffect(() => {
+        invoke<number | null>("get_window_conversation").then(showConversation);
+        const unlisten = listen<number | null>("window_conversation", (event) =>
+            showConversation(event.payload),
+        );
+        return () => {
+            unlisten.then((f) => f());
+        };
+    }, []);
+
     useEffect(() => {
         invoke<string>("get_selected_text_api").then((text) => {
             console.log("get_selected_text_api", text);
@@ -75,9 +107,14 @@ function AskWindow() {
                     conversation_id: "",
                     assistant_id: 1,
                     attachment_list: fileInfoList?.map((i) => i.id),
+                    // 没有附件时走快速模式，尽快显示第一段内容
+                    fast_mode: !fileInfoList || fileInfoList.length === 0,
                 },
             }).then((res) => {
                 setMessageId(res.add_message_id);
+                invoke("set_window_conversation", {
+                    conversationId: res.conversation_id,
+                });
 
                 console.log("ask ai response", res);
                 if (unsubscribe) {
@@ -163,6 +200,7 @@ function AskWindow() {
         setResponse("");
         setMessageId(-1);
         setAiIsResponsing(false);
+        invoke("set_window_conversation", { conversationId: null });
     };
 
     const { fileInfoList, handleChooseFile, handleDeleteFile, handlePaste } =
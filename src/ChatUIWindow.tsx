@@ -6,13 +6,41 @@ import ConversationUI from "./components/ConversationUI";
 
 import "./styles/ChatUIWindow.css";
 import { appDataDir } from "@tauri-apps/api/path";
-import { convertFileSrc } from "@tauri-apps/api/core";
+import { convertFileSrc, invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
 
 function ChatUIWindow() {
     const [pluginList, setPluginList] = useState<any[]>([]);
 
     const [selectedConversation, setSelectedConversation] = useState<string>("");
 
+    // 恢复上次退出时显示的对话，切换对话时记录下来，恢复完成之前不记录，避免覆盖要恢复的对话
+    const [sessionRestored, setSessionRestored] = useState<boolean>(false);
+    useEffect(() => {
+        invoke<number | null>("get_window_conversation").then((conversationId) => {
+            if (conversationId) {
+                setSelectedConversation(conversationId.toString());
+            }
+            setSessionRestored(true);
+        });
+        // 切换到命名会话时由后端通知要显示的对话
+        const unlisten = listen<number | null>("window_conversation", (event) => {
+            setSelectedConversation(event.payload ? event.payload.toString() : "");
+        });
+        return () => {
+            unlisten.then((f) => f());
+        };
+    }, []);
+
+    useEffect(() => {
+        if (!sessionRestored) {
+            return;
+        }
+        invoke("set_window_conversation", {
+            conversationId: selectedConversation ? parseInt(selectedConversation) : null,
+        });
+    }, [selectedConversation, sessionRestored]);
+
     useEffect(() => {
         const pluginLoadList = [
             {
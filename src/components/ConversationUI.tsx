@@ -15,6 +15,7 @@ import InputArea from "./conversation/InputArea";
 import FormDialog from "./FormDialog";
 import useConversationManager from "../hooks/useConversationManager";
 import useFileManagement from "@/hooks/useFileManagement";
+import useDraft from "@/hooks/useDraft";
 
 interface AssistantListItem {
     id: number;
@@ -659,6 +660,13 @@ function ConversationUI({
     }, 200);
 
     const [selectedAssistant, setSelectedAssistant] = useState(-1);
+    useDraft(
+        conversationId
+            ? { conversationId: +conversationId }
+            : { assistantId: selectedAssistant },
+        inputText,
+        setInputText,
+    );
 
     const handleArtifact = useCallback((lang: string, inputStr: string) => {
         invoke("run_artifacts", { lang, inputStr })
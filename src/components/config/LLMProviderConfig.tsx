@@ -54,6 +54,9 @@ const LLMProviderConfig: React.FC = () => {
         { value: 'ollama', label: 'Ollama API' },
         { value: 'anthropic', label: 'Anthropic API' },
         { value: 'cohere', label: 'Cohere API' },
+        { value: 'huggingface', label: 'Hugging Face / TGI' },
+        { value: 'dashscope', label: '通义千问 DashScope' },
+        { value: 'zhipu', label: '智谱 AI' },
     ]
 
     const openNewProviderDialog = useCallback(() => {
@@ -38,6 +38,13 @@ const LLMProviderConfigForm: React.FC<LLMProviderConfigFormProps> = ({ id, index
     const defaultValues = useMemo(() => ({
         endpoint: '',
         api_key: '',
+        default_model: '',
+        fallback_models: '',
+        auth_scheme: '',
+        auth_header: '',
+        custom_headers: '',
+        organization: '',
+        project: '',
     }), []);
 
     const form = useForm({
@@ -125,6 +132,51 @@ const LLMProviderConfigForm: React.FC<LLMProviderConfigFormProps> = ({ id, index
             label: 'API Key',
             value: '',
         },
+        default_model: {
+            type: 'input' as const,
+            label: '默认模型',
+            value: '',
+        },
+        fallback_models: {
+            type: 'input' as const,
+            label: '备用模型（逗号分隔，模型不可用时依次尝试）',
+            value: '',
+        },
+        auth_scheme: {
+            type: 'select' as const,
+            label: '鉴权方式（不选择时按API类型的默认方式）',
+            value: '',
+            options: [
+                { value: 'bearer', label: 'Authorization: Bearer' },
+                { value: 'api_key', label: 'api-key' },
+                { value: 'x_api_key', label: 'x-api-key' },
+                { value: 'custom', label: '自定义请求头' },
+                { value: 'none', label: '不鉴权' },
+            ],
+        },
+        auth_header: {
+            type: 'input' as const,
+            label: '自定义鉴权请求头名称（鉴权方式为自定义请求头时使用）',
+            value: '',
+        },
+        custom_headers: {
+            type: 'textarea' as const,
+            label: '自定义请求头（JSON 对象，同名时覆盖鉴权请求头）',
+            value: '',
+        },
+        // 组织和项目请求头只有 OpenAI 类型的提供商会发送
+        ...(apiType === 'openai_api' ? {
+            organization: {
+                type: 'input' as const,
+                label: 'Organization ID（可选，用量记到指定组织）',
+                value: '',
+            },
+            project: {
+                type: 'input' as const,
+                label: 'Project ID（可选，用量记到指定项目）',
+                value: '',
+            },
+        } : {}),
         fetchModelList: {
             type: 'button' as const,
             label: '',
@@ -137,7 +189,7 @@ const LLMProviderConfigForm: React.FC<LLMProviderConfigFormProps> = ({ id, index
             value: '',
             customRender: tagInputRender,
         },
-    }), [fetchModelList, tagInputRender]);
+    }), [apiType, fetchModelList, tagInputRender]);
 
     const extraButtons = useMemo(() => (
         <Switch checked={enabled} onCheckedChange={() => onToggleEnabled(index)} />
@@ -0,0 +1,52 @@
+import { useEffect, useMemo, useRef } from "react";
+import { invoke } from "@tauri-apps/api/core";
+import { debounce } from "lodash";
+
+// 输入停止这么久之后保存草稿
+const SAVE_DRAFT_DELAY = 500;
+
+interface DraftKey {
+    conversationId?: number;
+    assistantId?: number;
+}
+
+// 自动保存输入框中还没有发送的内容，切换对话或者重新打开窗口时恢复，发送后由后端删除
+const useDraft = (
+    key: DraftKey,
+    inputText: string,
+    setInputText: (text: string) => void,
+) => {
+    // 草稿加载完成之前不保存，避免把上一个对话的输入保存到新的对话
+    const loadedKey = useRef<string | null>(null);
+    const keyString = `${key.conversationId ?? ""}:${key.assistantId ?? ""}`;
+
+    const saveDraft = useMemo(
+        () =>
+            debounce((draftKey: DraftKey, content: string) => {
+                invoke("save_draft", { ...draftKey, content }).catch((e) =>
+                    console.error("save draft error", e),
+                );
+            }, SAVE_DRAFT_DELAY),
+        [],
+    );
+
+    useEffect(() => {
+        saveDraft.flush();
+        loadedKey.current = null;
+        invoke<string | null>("get_draft", key).then((draft) => {
+            setInputText(draft ?? "");
+            loadedKey.current = keyString;
+        });
+    }, [keyString]);
+
+    useEffect(() => {
+        if (loadedKey.current !== keyString) {
+            return;
+        }
+        saveDraft(key, inputText);
+    }, [inputText]);
+
+    useEffect(() => () => saveDraft.flush(), []);
+};
+
+export default useDraft;